@@ -12,11 +12,15 @@ fn main() {
   );
 
   driver
-    .create_input(input_config1, |event| println!("all      >> {:?}", event))
+    .create_input(input_config1, |event| {
+      println!("all      >> {:016} {}", event.timestamp, event.message)
+    })
     .unwrap();
 
   driver
-    .create_input(input_config2, |event| println!("novation >> {:?}", event))
+    .create_input(input_config2, |event| {
+      println!("novation >> {:016} {}", event.timestamp, event.message)
+    })
     .unwrap();
 
   print_endpoints(&driver);
@@ -49,11 +53,14 @@ fn print_endpoints(driver: &midi::drivers::Driver) {
         format!(" ({})", source.connected_inputs.join(", "))
       })
       .unwrap_or_default();
-    println!("  [{:08x}] {} {}", source.id, source.name, input_names);
+    println!(
+      "  [{:08x}] {} {}",
+      source.id, source.display_name, input_names
+    );
   }
   println!("Destinations:");
   for destination in driver.destinations() {
-    println!("  [{:08x}] {}", destination.id, destination.name);
+    println!("  [{:08x}] {}", destination.id, destination.display_name);
   }
   println!("===================================================================================");
 }
@@ -0,0 +1,14 @@
+#![no_main]
+
+use kiro_midi::conformance;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  let words = data
+    .chunks_exact(4)
+    .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    .collect::<Vec<_>>();
+  if let Err(violation) = conformance::check_decode(&words) {
+    panic!("{}", violation);
+  }
+});
@@ -0,0 +1,10 @@
+#![no_main]
+
+use kiro_midi::conformance;
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+  if let Err(violation) = conformance::check_midi1_parse(data) {
+    panic!("{}", violation);
+  }
+});
@@ -0,0 +1,16 @@
+#![no_main]
+
+use kiro_midi::conformance::{self, Rng};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u64, u8)| {
+  let (seed, len) = input;
+  let mut rng = Rng::new(seed);
+  let group = rng.below(16) as u8;
+  let messages = (0..len)
+    .map(|_| conformance::random_midi1_message(&mut rng, group))
+    .collect::<Vec<_>>();
+  if let Err(violation) = conformance::check_midi1_round_trip(group, &messages) {
+    panic!("Seed {}: {}", seed, violation);
+  }
+});
@@ -0,0 +1,12 @@
+#![no_main]
+
+use kiro_midi::conformance::{self, Rng};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: (u64, u8)| {
+  let (seed, len) = input;
+  let messages = conformance::random_stream(&mut Rng::new(seed), len as usize);
+  if let Err(violation) = conformance::check_round_trip(&messages) {
+    panic!("Seed {}: {}", seed, violation);
+  }
+});
@@ -0,0 +1,525 @@
+//! Arpeggiators, playing the notes of the chords held one after the other.
+//!
+//! The arpeggiator is a `Processor`, so it can go between an input and its handler, or before an output
+//! in a `Chain`. It takes the notes received (from any channel) and plays them, through the channel and
+//! the group of the last note received, as MIDI 1.0 notes. The rest of messages pass on.
+//!
+//! It steps with its own clock at a fixed tempo, in which case it needs to be advanced periodically
+//! (see `Processor::advance`), or with the MIDI clock received along with the notes.
+
+use crate::endpoints::EndpointId;
+use crate::event::{Event, TimestampNanos};
+use crate::processor::Processor;
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::system::System;
+use crate::protocol::messages::{Message, MessageType};
+use crate::transport::TICKS_PER_BEAT;
+
+const NANOS_PER_MINUTE: f64 = 60_000_000_000.0;
+
+pub const DEFAULT_TEMPO: f64 = 120.0;
+
+pub const MAX_OCTAVES: u8 = 4;
+
+/// The order to play the notes held in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpMode {
+  Up,
+  Down,
+  /// Up and then down, without repeating the highest and the lowest notes
+  UpDown,
+  Random,
+}
+
+/// Where the steps of the arpeggiator come from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArpClock {
+  /// Its own clock, at a tempo in beats per minute
+  Internal(f64),
+  /// The MIDI clock received, stepping only while the transport runs
+  External,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Destination {
+  endpoint: EndpointId,
+  group: u8,
+  channel: u8,
+}
+
+/// Plays the notes held one after the other, see the module docs.
+///
+/// The memory for the notes is allocated upfront, so it can be used from real-time threads.
+pub struct Arpeggiator {
+  mode: ArpMode,
+  octaves: u8,
+  gate: f64,
+  steps_per_beat: u8,
+  clock: ArpClock,
+  latch: bool,
+  /// Notes held, sorted, with their velocities
+  held: Vec<(u8, u8)>,
+  /// Keys still pressed, which can be less than the notes held when latching them
+  pressed: u8,
+  destination: Destination,
+  step: u64,
+  /// When the next step is due, with the internal clock
+  next_step: Option<TimestampNanos>,
+  /// Timing clocks received since the start, and the last interval between them, with the external clock
+  clocks: u64,
+  last_clock: Option<TimestampNanos>,
+  clock_interval: Option<TimestampNanos>,
+  running: bool,
+  /// The note playing, with its note off and when it is due, if known
+  playing: Option<(Message, Option<TimestampNanos>)>,
+  random: u32,
+}
+
+impl Arpeggiator {
+  /// Plays the sixteenths up through one octave, at 120 BPM, with half of every step sounding.
+  pub fn new() -> Self {
+    Self {
+      mode: ArpMode::Up,
+      octaves: 1,
+      gate: 0.5,
+      steps_per_beat: 4,
+      clock: ArpClock::Internal(DEFAULT_TEMPO),
+      latch: false,
+      held: Vec::with_capacity(128),
+      pressed: 0,
+      destination: Destination {
+        endpoint: 0,
+        group: 0,
+        channel: 0,
+      },
+      step: 0,
+      next_step: None,
+      clocks: 0,
+      last_clock: None,
+      clock_interval: None,
+      running: false,
+      playing: None,
+      random: 0x2545_f491,
+    }
+  }
+
+  #[must_use]
+  pub fn with_mode(mut self, mode: ArpMode) -> Self {
+    self.mode = mode;
+    self
+  }
+
+  /// Plays the notes held in `octaves` octaves, from 1 to `MAX_OCTAVES`, going up from the notes held.
+  #[must_use]
+  pub fn with_octaves(mut self, octaves: u8) -> Self {
+    self.octaves = octaves.clamp(1, MAX_OCTAVES);
+    self
+  }
+
+  /// Sets how much of every step the notes sound, from 0 to 1.
+  #[must_use]
+  pub fn with_gate(mut self, gate: f32) -> Self {
+    self.gate = gate.clamp(0.0, 1.0) as f64;
+    self
+  }
+
+  /// Sets the steps per beat, such as 4 for sixteenths or 3 for eighth triplets,
+  /// rounded down to one dividing the 24 clocks of a beat.
+  #[must_use]
+  pub fn with_steps_per_beat(mut self, steps_per_beat: u8) -> Self {
+    self.steps_per_beat = (1..=steps_per_beat.clamp(1, TICKS_PER_BEAT as u8))
+      .rev()
+      .find(|steps| TICKS_PER_BEAT % *steps as u64 == 0)
+      .unwrap_or(1);
+    self
+  }
+
+  #[must_use]
+  pub fn with_clock(mut self, clock: ArpClock) -> Self {
+    self.clock = match clock {
+      ArpClock::Internal(tempo) => ArpClock::Internal(tempo.max(1.0)),
+      ArpClock::External => ArpClock::External,
+    };
+    self
+  }
+
+  /// Keeps playing the notes after releasing them, until the next chord.
+  #[must_use]
+  pub fn with_latch(mut self, latch: bool) -> Self {
+    self.latch = latch;
+    self
+  }
+
+  fn note_on(&mut self, event: &Event, channel: u8, note: u8, velocity: u8) {
+    if self.pressed == 0 && self.latch {
+      self.held.clear();
+    }
+    self.pressed = self.pressed.saturating_add(1);
+    if let Err(index) = self.held.binary_search_by_key(&note, |(held, _)| *held) {
+      if self.held.len() < self.held.capacity() {
+        self.held.insert(index, (note, velocity));
+      }
+    }
+    self.destination = Destination {
+      endpoint: event.endpoint,
+      group: event.message.group,
+      channel,
+    };
+    if self.next_step.is_none() {
+      self.next_step = Some(event.timestamp);
+      self.step = 0;
+    }
+  }
+
+  fn note_off(&mut self, note: u8) {
+    self.pressed = self.pressed.saturating_sub(1);
+    if !self.latch {
+      self.held.retain(|(held, _)| *held != note);
+    }
+  }
+
+  fn timing_clock(&mut self, timestamp: TimestampNanos, output: &mut dyn FnMut(Event)) {
+    if let Some(last_clock) = self.last_clock {
+      self.clock_interval = Some(timestamp.saturating_sub(last_clock));
+    }
+    self.last_clock = Some(timestamp);
+    if self.running {
+      let clocks_per_step = TICKS_PER_BEAT / self.steps_per_beat as u64;
+      if self.clocks % clocks_per_step == 0 {
+        let step_interval = self
+          .clock_interval
+          .map(|interval| interval * clocks_per_step);
+        self.play_step(timestamp, step_interval, output);
+      }
+      self.clocks += 1;
+    }
+  }
+
+  fn step_interval(&self) -> Option<TimestampNanos> {
+    match self.clock {
+      ArpClock::Internal(tempo) => {
+        Some((NANOS_PER_MINUTE / (tempo * self.steps_per_beat as f64)) as TimestampNanos)
+      }
+      ArpClock::External => None,
+    }
+  }
+
+  fn play_step(
+    &mut self,
+    timestamp: TimestampNanos,
+    step_interval: Option<TimestampNanos>,
+    output: &mut dyn FnMut(Event),
+  ) {
+    self.release(timestamp, output);
+    let (note, velocity) = match self.step_note() {
+      Some(step_note) => step_note,
+      None => return,
+    };
+    self.step += 1;
+
+    let message = |message| Message {
+      group: self.destination.group,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: self.destination.channel,
+        message,
+      }),
+    };
+    output(Event {
+      timestamp,
+      endpoint: self.destination.endpoint,
+      message: message(ChannelVoice1Message::NoteOn { note, velocity }),
+    });
+    let note_off = message(ChannelVoice1Message::NoteOff { note, velocity: 0 });
+    let due =
+      step_interval.map(|interval| timestamp + (interval as f64 * self.gate) as TimestampNanos);
+    self.playing = Some((note_off, due));
+  }
+
+  /// The note of the current step, with its velocity.
+  fn step_note(&mut self) -> Option<(u8, u8)> {
+    let held = self.held.len() as u64;
+    let length = held * self.octaves as u64;
+    if length == 0 {
+      return None;
+    }
+    let index = match self.mode {
+      ArpMode::Up => self.step % length,
+      ArpMode::Down => length - 1 - self.step % length,
+      ArpMode::UpDown if length > 1 => {
+        let period = 2 * length - 2;
+        let position = self.step % period;
+        if position < length {
+          position
+        } else {
+          period - position
+        }
+      }
+      ArpMode::UpDown => 0,
+      ArpMode::Random => {
+        // Xorshift, good enough to pick the notes
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 17;
+        self.random ^= self.random << 5;
+        self.random as u64 % length
+      }
+    };
+    let (note, velocity) = self.held[(index % held) as usize];
+    let note = note as u64 + 12 * (index / held);
+    (note <= 127).then(|| (note as u8, velocity))
+  }
+
+  /// Sends the note off of the note playing, at `timestamp` unless it was due before.
+  fn release(&mut self, timestamp: TimestampNanos, output: &mut dyn FnMut(Event)) {
+    if let Some((note_off, due)) = self.playing.take() {
+      output(Event {
+        timestamp: due.map_or(timestamp, |due| due.min(timestamp)),
+        endpoint: self.destination.endpoint,
+        message: note_off,
+      });
+    }
+  }
+}
+
+impl Default for Arpeggiator {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Processor for Arpeggiator {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    self.advance(event.timestamp, output);
+
+    let note = match event.message.mtype {
+      MessageType::ChannelVoice1(ChannelVoice1 { channel, message }) => match message {
+        ChannelVoice1Message::NoteOn { note, velocity } if velocity > 0 => {
+          Some((channel, note, Some(velocity)))
+        }
+        ChannelVoice1Message::NoteOn { note, .. } | ChannelVoice1Message::NoteOff { note, .. } => {
+          Some((channel, note, None))
+        }
+        _ => None,
+      },
+      MessageType::ChannelVoice(ChannelVoice { channel, message }) => match message {
+        ChanelVoiceMessage::NoteOn { note, velocity, .. } => {
+          Some((channel, note, Some(((velocity >> 9) as u8).max(1))))
+        }
+        ChanelVoiceMessage::NoteOff { note, .. } => Some((channel, note, None)),
+        _ => None,
+      },
+      _ => None,
+    };
+
+    match note {
+      Some((channel, note, Some(velocity))) => {
+        self.note_on(&event, channel, note & 0x7f, velocity);
+        self.advance(event.timestamp, output);
+      }
+      Some((_, note, None)) => {
+        self.note_off(note & 0x7f);
+        if self.held.is_empty() {
+          self.next_step = None;
+          self.release(event.timestamp, output);
+        }
+      }
+      None => {
+        if self.clock == ArpClock::External {
+          match event.message.mtype {
+            MessageType::System(System::TimingClock) => self.timing_clock(event.timestamp, output),
+            MessageType::System(System::Start) => {
+              self.running = true;
+              self.clocks = 0;
+              self.step = 0;
+            }
+            MessageType::System(System::Continue) => self.running = true,
+            MessageType::System(System::Stop) => {
+              self.running = false;
+              self.release(event.timestamp, output);
+            }
+            _ => {}
+          }
+        }
+        output(event);
+      }
+    }
+  }
+
+  fn advance(&mut self, now: TimestampNanos, output: &mut dyn FnMut(Event)) {
+    if let Some((_, Some(due))) = self.playing {
+      if due <= now {
+        self.release(due, output);
+      }
+    }
+    if let Some(step_interval) = self.step_interval() {
+      while let Some(next_step) = self.next_step.filter(|next_step| *next_step <= now) {
+        self.play_step(next_step, Some(step_interval), output);
+        self.next_step = Some(next_step + step_interval);
+        if let Some((_, Some(due))) = self.playing {
+          if due <= now {
+            self.release(due, output);
+          }
+        }
+      }
+    }
+  }
+
+  fn reset(&mut self) {
+    self.held.clear();
+    self.pressed = 0;
+    self.step = 0;
+    self.next_step = None;
+    self.clocks = 0;
+    self.last_clock = None;
+    self.clock_interval = None;
+    self.running = false;
+    self.playing = None;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MS: TimestampNanos = 1_000_000;
+
+  fn event(timestamp: TimestampNanos, mtype: MessageType) -> Event {
+    Event {
+      timestamp,
+      endpoint: 1,
+      message: Message { group: 0, mtype },
+    }
+  }
+
+  fn note(timestamp: TimestampNanos, note: u8, velocity: u8) -> Event {
+    event(
+      timestamp,
+      MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message: if velocity > 0 {
+          ChannelVoice1Message::NoteOn { note, velocity }
+        } else {
+          ChannelVoice1Message::NoteOff { note, velocity }
+        },
+      }),
+    )
+  }
+
+  /// The timestamps and notes played, with 0 as the velocity of the note offs.
+  fn played(events: &[Event]) -> Vec<(TimestampNanos, u8, u8)> {
+    events
+      .iter()
+      .filter_map(|event| match event.message.mtype {
+        MessageType::ChannelVoice1(ChannelVoice1 {
+          message: ChannelVoice1Message::NoteOn { note, velocity },
+          ..
+        }) => Some((event.timestamp / MS, note, velocity)),
+        MessageType::ChannelVoice1(ChannelVoice1 {
+          message: ChannelVoice1Message::NoteOff { note, .. },
+          ..
+        }) => Some((event.timestamp / MS, note, 0)),
+        _ => None,
+      })
+      .collect()
+  }
+
+  #[test]
+  fn play_up_with_the_internal_clock() {
+    let mut arpeggiator = Arpeggiator::new().with_octaves(2);
+    let mut events = Vec::new();
+
+    arpeggiator.process(note(0, 60, 100), &mut |event| events.push(event));
+    arpeggiator.process(note(0, 64, 90), &mut |event| events.push(event));
+    arpeggiator.advance(400 * MS, &mut |event| events.push(event));
+    arpeggiator.process(note(450 * MS, 60, 0), &mut |event| events.push(event));
+    arpeggiator.process(note(460 * MS, 64, 0), &mut |event| events.push(event));
+    arpeggiator.advance(1000 * MS, &mut |event| events.push(event));
+
+    assert_eq!(
+      played(&events),
+      vec![
+        (0, 60, 100),
+        (62, 60, 0),
+        (125, 64, 90),
+        (187, 64, 0),
+        (250, 72, 100),
+        (312, 72, 0),
+        (375, 76, 90),
+        (437, 76, 0),
+      ]
+    );
+  }
+
+  #[test]
+  fn step_through_the_modes() {
+    let notes = |mode: ArpMode| {
+      let mut arpeggiator = Arpeggiator::new().with_mode(mode).with_gate(1.0);
+      let mut events = Vec::new();
+      for note_on in [note(0, 64, 100), note(0, 60, 100), note(0, 67, 100)] {
+        arpeggiator.process(note_on, &mut |_| {});
+      }
+      arpeggiator.reset();
+      for note_on in [note(0, 64, 100), note(0, 60, 100), note(0, 67, 100)] {
+        arpeggiator.process(note_on, &mut |event| events.push(event));
+      }
+      arpeggiator.advance(125 * 7 * MS, &mut |event| events.push(event));
+      played(&events)
+        .into_iter()
+        .filter(|(_, _, velocity)| *velocity > 0)
+        .map(|(_, note, _)| note)
+        .collect::<Vec<_>>()
+    };
+
+    assert_eq!(notes(ArpMode::Up)[1..7], [64, 67, 60, 64, 67, 60]);
+    assert_eq!(notes(ArpMode::Down)[1..7], [64, 60, 67, 64, 60, 67]);
+    assert_eq!(notes(ArpMode::UpDown)[1..7], [64, 67, 64, 60, 64, 67]);
+    assert!(notes(ArpMode::Random)
+      .iter()
+      .all(|note| [60, 64, 67].contains(note)));
+  }
+
+  #[test]
+  fn follow_the_external_clock() {
+    let mut arpeggiator = Arpeggiator::new()
+      .with_clock(ArpClock::External)
+      .with_steps_per_beat(2)
+      .with_latch(true);
+    let mut events = Vec::new();
+    let clock = |timestamp| event(timestamp, MessageType::System(System::TimingClock));
+
+    arpeggiator.process(note(0, 60, 100), &mut |event| events.push(event));
+    arpeggiator.process(note(0, 67, 100), &mut |event| events.push(event));
+    arpeggiator.process(note(5 * MS, 60, 0), &mut |event| events.push(event));
+    arpeggiator.process(note(5 * MS, 67, 0), &mut |event| events.push(event));
+    arpeggiator.process(clock(0), &mut |event| events.push(event));
+    assert!(played(&events).is_empty());
+
+    arpeggiator.process(
+      event(15 * MS, MessageType::System(System::Start)),
+      &mut |event| events.push(event),
+    );
+    for tick in 0..25 {
+      arpeggiator.process(clock(20 * MS + tick * 20 * MS), &mut |event| {
+        events.push(event)
+      });
+    }
+
+    assert_eq!(
+      played(&events),
+      vec![
+        (20, 60, 100),
+        (140, 60, 0),
+        (260, 67, 100),
+        (380, 67, 0),
+        (500, 60, 100)
+      ]
+    );
+    assert_eq!(
+      events
+        .iter()
+        .filter(|event| event.message.mtype == MessageType::System(System::TimingClock))
+        .count(),
+      26
+    );
+  }
+}
@@ -0,0 +1,307 @@
+//! MIDI beat clock.
+//!
+//! The sender transmits 24 timing clock messages per beat while its transport is running,
+//! and controls the transport with start, stop and continue. The song position pointer
+//! moves the transport in sixteenths, to resume from there with continue.
+
+use crate::event::{Event, TimestampNanos};
+use crate::input_handler::InputHandler;
+use crate::protocol::messages::system::System;
+use crate::protocol::messages::{Message, MessageType};
+use crate::transport::{Transport, TransportState, TICKS_PER_BEAT};
+
+const DEFAULT_BEATS_PER_BAR: u8 = 4;
+
+/// How much every new interval between clocks weights in the average
+const SMOOTHING: f64 = 1.0 / 16.0;
+
+/// Intervals between clocks further than this factor from the average are taken as a tempo change
+const TEMPO_CHANGE_FACTOR: f64 = 1.5;
+
+/// Longest interval between clocks (20 BPM) before the tempo is considered unknown
+const MAX_CLOCK_INTERVAL: TimestampNanos = 125_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ClockEvent {
+  Start,
+  Stop,
+  Continue,
+  /// The transport moved, with the position in clocks
+  Position {
+    clocks: u64,
+  },
+  /// A beat started, counting from 0 at the start
+  Beat {
+    beat: u64,
+    /// Beat within the bar
+    beat_in_bar: u8,
+    /// In beats per minute, when known
+    tempo: Option<f64>,
+  },
+  /// A bar started, counting from 0 at the start
+  Bar {
+    bar: u64,
+  },
+}
+
+/// Follows the MIDI clock and transport of a source.
+///
+/// The tempo is estimated from the intervals between the clocks, averaged to smooth the jitter,
+/// even while the transport is stopped. Sudden changes of the interval restart the estimation,
+/// so the tempo follows them without the delay of the average.
+pub struct ClockFollower {
+  beats_per_bar: u8,
+  transport: TransportState,
+  last_clock: Option<TimestampNanos>,
+  /// Average interval between clocks, in nanoseconds
+  interval: Option<f64>,
+}
+
+impl ClockFollower {
+  pub fn new() -> Self {
+    Self {
+      beats_per_bar: DEFAULT_BEATS_PER_BAR,
+      transport: TransportState::new(),
+      last_clock: None,
+      interval: None,
+    }
+  }
+
+  #[must_use]
+  pub fn with_beats_per_bar(mut self, beats_per_bar: u8) -> Self {
+    self.beats_per_bar = beats_per_bar.max(1);
+    self
+  }
+
+  pub fn transport(&self) -> &TransportState {
+    &self.transport
+  }
+
+  /// In beats per minute, when enough clocks arrived to estimate it.
+  pub fn tempo(&self) -> Option<f64> {
+    self
+      .interval
+      .map(|interval| 60_000_000_000.0 / (interval * TICKS_PER_BEAT as f64))
+  }
+
+  /// Handles a message received at `timestamp`, calling `f` with the resulting events.
+  pub fn process<F>(&mut self, timestamp: TimestampNanos, message: &Message, mut f: F)
+  where
+    F: FnMut(ClockEvent),
+  {
+    if let MessageType::System(System::TimingClock) = message.mtype {
+      return self.clock(timestamp, &mut f);
+    }
+    match self.transport.process(message) {
+      Some(Transport::Start) => f(ClockEvent::Start),
+      Some(Transport::Stop) => f(ClockEvent::Stop),
+      Some(Transport::Continue) => f(ClockEvent::Continue),
+      Some(Transport::SongPosition { .. }) => f(ClockEvent::Position {
+        clocks: self.transport.ticks(),
+      }),
+      None => {}
+    }
+  }
+
+  /// Wraps a callback receiving clock events into a handler for an input.
+  ///
+  /// The input should be connected only to the source to follow,
+  /// as the clocks of several sources would be mixed up.
+  pub fn handler<F>(mut self, mut f: F) -> InputHandler
+  where
+    F: FnMut(TimestampNanos, ClockEvent) + Send + 'static,
+  {
+    InputHandler::from(move |event: Event| {
+      self.process(event.timestamp, &event.message, |clock_event| {
+        f(event.timestamp, clock_event)
+      })
+    })
+  }
+
+  pub fn reset(&mut self) {
+    self.transport.reset();
+    self.last_clock = None;
+    self.interval = None;
+  }
+
+  fn clock<F>(&mut self, timestamp: TimestampNanos, f: &mut F)
+  where
+    F: FnMut(ClockEvent),
+  {
+    if let Some(last_clock) = self.last_clock {
+      let elapsed = timestamp.saturating_sub(last_clock);
+      if elapsed > MAX_CLOCK_INTERVAL {
+        self.interval = None;
+      } else {
+        let elapsed = elapsed as f64;
+        self.interval = Some(match self.interval {
+          Some(interval)
+            if elapsed < interval * TEMPO_CHANGE_FACTOR
+              && elapsed * TEMPO_CHANGE_FACTOR > interval =>
+          {
+            interval + (elapsed - interval) * SMOOTHING
+          }
+          _ => elapsed,
+        });
+      }
+    }
+    self.last_clock = Some(timestamp);
+
+    let clocks = match self.transport.tick() {
+      Some(clocks) => clocks,
+      None => return,
+    };
+    if clocks % TICKS_PER_BEAT == 0 {
+      let beat = clocks / TICKS_PER_BEAT;
+      let beats_per_bar = self.beats_per_bar as u64;
+      let beat_in_bar = (beat % beats_per_bar) as u8;
+      if beat_in_bar == 0 {
+        f(ClockEvent::Bar {
+          bar: beat / beats_per_bar,
+        });
+      }
+      f(ClockEvent::Beat {
+        beat,
+        beat_in_bar,
+        tempo: self.tempo(),
+      });
+    }
+  }
+}
+
+impl Default for ClockFollower {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// At 120 BPM
+  const CLOCK_INTERVAL: TimestampNanos = 20_833_333;
+
+  fn message(system: System) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::System(system),
+    }
+  }
+
+  fn process(
+    follower: &mut ClockFollower,
+    timestamp: TimestampNanos,
+    system: System,
+  ) -> Vec<ClockEvent> {
+    let mut events = Vec::new();
+    follower.process(timestamp, &message(system), |event| events.push(event));
+    events
+  }
+
+  #[test]
+  fn tempo_with_jitter() {
+    let mut follower = ClockFollower::new();
+    let jitter = [0, 2_000_000, -1_000_000, 1_500_000, -2_000_000, 0];
+    for i in 0..96 {
+      let timestamp = i * CLOCK_INTERVAL as i64 + jitter[i as usize % jitter.len()];
+      process(
+        &mut follower,
+        timestamp as TimestampNanos,
+        System::TimingClock,
+      );
+    }
+
+    let tempo = follower.tempo().unwrap();
+    assert!((tempo - 120.0).abs() < 2.0, "{}", tempo);
+  }
+
+  #[test]
+  fn tempo_change() {
+    let mut follower = ClockFollower::new();
+    let mut timestamp = 0;
+    for _ in 0..48 {
+      timestamp += CLOCK_INTERVAL;
+      process(&mut follower, timestamp, System::TimingClock);
+    }
+    for _ in 0..2 {
+      timestamp += 2 * CLOCK_INTERVAL;
+      process(&mut follower, timestamp, System::TimingClock);
+    }
+
+    let tempo = follower.tempo().unwrap();
+    assert!((tempo - 60.0).abs() < 0.1, "{}", tempo);
+
+    process(
+      &mut follower,
+      timestamp + MAX_CLOCK_INTERVAL + 1,
+      System::TimingClock,
+    );
+    assert_eq!(follower.tempo(), None);
+  }
+
+  #[test]
+  fn beats_and_bars() {
+    let mut follower = ClockFollower::new().with_beats_per_bar(3);
+    assert_eq!(
+      process(&mut follower, 0, System::Start),
+      vec![ClockEvent::Start]
+    );
+
+    let mut events = Vec::new();
+    for i in 0..TICKS_PER_BEAT * 4 {
+      events.extend(process(
+        &mut follower,
+        i * CLOCK_INTERVAL,
+        System::TimingClock,
+      ));
+    }
+
+    let beats = events
+      .iter()
+      .filter_map(|event| match event {
+        ClockEvent::Beat {
+          beat, beat_in_bar, ..
+        } => Some((*beat, *beat_in_bar)),
+        _ => None,
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(beats, vec![(0, 0), (1, 1), (2, 2), (3, 0)]);
+    let bars = events
+      .iter()
+      .filter(|event| matches!(event, ClockEvent::Bar { .. }))
+      .count();
+    assert_eq!(bars, 2);
+    assert_eq!(events[0], ClockEvent::Bar { bar: 0 });
+  }
+
+  #[test]
+  fn song_position_and_continue() {
+    let mut follower = ClockFollower::new();
+    process(&mut follower, 0, System::TimingClock);
+    assert!(process(&mut follower, CLOCK_INTERVAL, System::TimingClock).is_empty());
+
+    assert_eq!(
+      process(&mut follower, 0, System::SongPositionPointer(16)),
+      vec![ClockEvent::Position { clocks: 96 }]
+    );
+    process(&mut follower, 0, System::Continue);
+    let events = process(&mut follower, 2 * CLOCK_INTERVAL, System::TimingClock);
+
+    assert!(matches!(
+      events.as_slice(),
+      [
+        ClockEvent::Bar { bar: 1 },
+        ClockEvent::Beat {
+          beat: 4,
+          beat_in_bar: 0,
+          tempo: Some(_)
+        }
+      ]
+    ));
+
+    process(&mut follower, 0, System::Stop);
+    assert!(!follower.transport().is_running());
+    assert_eq!(follower.transport().ticks(), 97);
+  }
+}
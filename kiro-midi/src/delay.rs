@@ -0,0 +1,364 @@
+//! MIDI delays, repeating the notes received a number of times after them.
+//!
+//! The `Delay` is a `Processor` passing on every event as it comes, followed by the echoes of the notes,
+//! timestamped for later. They should go to an `Output` scheduling them, which are those from an
+//! `OutputQueue` or from the drivers with timestamped APIs (the rest send them right away).
+//!
+//! The time between the echoes is fixed or a number of beats, at the tempo of the MIDI clock received
+//! along with the notes or at the tempo set when there is none.
+
+use std::time::Duration;
+
+use crate::clock::ClockFollower;
+use crate::event::{Event, TimestampNanos};
+use crate::processor::Processor;
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::{Message, MessageType};
+
+const NANOS_PER_MINUTE: f64 = 60_000_000_000.0;
+
+pub const DEFAULT_TEMPO: f64 = 120.0;
+
+pub const MAX_REPEATS: u8 = 32;
+
+/// Time between the echoes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DelayTime {
+  Fixed(Duration),
+  /// Fraction of beats, such as 0.75 for a dotted eighth
+  Beats(f64),
+}
+
+/// Repeats the notes received, see the module docs.
+pub struct Delay {
+  time: DelayTime,
+  tempo: f64,
+  clock: ClockFollower,
+  repeats: u8,
+  feedback: f32,
+  transpose: i8,
+}
+
+impl Delay {
+  /// Repeats the notes 3 times, every `time`, with the velocity halved every time.
+  pub fn new(time: DelayTime) -> Self {
+    Self {
+      time,
+      tempo: DEFAULT_TEMPO,
+      clock: ClockFollower::new(),
+      repeats: 3,
+      feedback: 0.5,
+      transpose: 0,
+    }
+  }
+
+  /// Sets the number of echoes for every note, up to `MAX_REPEATS`.
+  #[must_use]
+  pub fn with_repeats(mut self, repeats: u8) -> Self {
+    self.repeats = repeats.min(MAX_REPEATS);
+    self
+  }
+
+  /// Sets how much of the velocity is left from one echo to the next, from 0 to 1.
+  ///
+  /// The echoes of MIDI 1.0 notes keep a velocity of 1 at least, so they don't turn into note offs.
+  #[must_use]
+  pub fn with_feedback(mut self, feedback: f32) -> Self {
+    self.feedback = feedback.clamp(0.0, 1.0);
+    self
+  }
+
+  /// Shifts every echo by `semitones` from the previous one, dropping those out of the range of the notes.
+  #[must_use]
+  pub fn with_transpose(mut self, semitones: i8) -> Self {
+    self.transpose = semitones;
+    self
+  }
+
+  /// Sets the tempo for the times in beats, in beats per minute, while the tempo of the MIDI clock is unknown.
+  #[must_use]
+  pub fn with_tempo(mut self, tempo: f64) -> Self {
+    self.tempo = tempo.max(1.0);
+    self
+  }
+
+  /// Time between the echoes, in nanoseconds.
+  fn interval(&self) -> TimestampNanos {
+    match self.time {
+      DelayTime::Fixed(duration) => duration.as_nanos() as TimestampNanos,
+      DelayTime::Beats(beats) => {
+        let tempo = self.clock.tempo().unwrap_or(self.tempo);
+        (beats.max(0.0) * NANOS_PER_MINUTE / tempo) as TimestampNanos
+      }
+    }
+  }
+
+  /// The note and the velocity of the echo `repeat` (counting from 1), if the note is still in range.
+  fn echo<V>(
+    &self,
+    note: u8,
+    velocity: V,
+    repeat: u8,
+    scale: impl Fn(V, f32) -> V,
+  ) -> Option<(u8, V)> {
+    let note = note as i32 + self.transpose as i32 * repeat as i32;
+    let gain = self.feedback.powi(repeat as i32);
+    (0..=127)
+      .contains(&note)
+      .then(|| (note as u8, scale(velocity, gain)))
+  }
+}
+
+impl Processor for Delay {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    if let DelayTime::Beats(_) = self.time {
+      self.clock.process(event.timestamp, &event.message, |_| {});
+    }
+
+    let interval = self.interval();
+    let Event {
+      timestamp,
+      endpoint,
+      message: original,
+    } = event;
+    output(event);
+
+    for repeat in 1..=self.repeats {
+      let echo = match original.mtype {
+        MessageType::ChannelVoice1(ChannelVoice1 { channel, message }) => match message {
+          ChannelVoice1Message::NoteOn { note, velocity } if velocity > 0 => self
+            .echo(note, velocity, repeat, |velocity, gain| {
+              ((velocity as f32 * gain).round() as u8).max(1)
+            })
+            .map(|(note, velocity)| ChannelVoice1Message::NoteOn { note, velocity }),
+          ChannelVoice1Message::NoteOn { note, velocity }
+          | ChannelVoice1Message::NoteOff { note, velocity } => self
+            .echo(note, velocity, repeat, |velocity, _| velocity)
+            .map(|(note, velocity)| ChannelVoice1Message::NoteOff { note, velocity }),
+          _ => None,
+        }
+        .map(|message| MessageType::ChannelVoice1(ChannelVoice1 { channel, message })),
+        MessageType::ChannelVoice(ChannelVoice { channel, message }) => match message {
+          ChanelVoiceMessage::NoteOn {
+            note,
+            velocity,
+            attr_type,
+            attr_data,
+          } => self
+            .echo(note, velocity, repeat, |velocity, gain| {
+              (velocity as f32 * gain).round() as u16
+            })
+            .map(|(note, velocity)| ChanelVoiceMessage::NoteOn {
+              note,
+              velocity,
+              attr_type,
+              attr_data,
+            }),
+          ChanelVoiceMessage::NoteOff {
+            note,
+            velocity,
+            attr_type,
+            attr_data,
+          } => self
+            .echo(note, velocity, repeat, |velocity, _| velocity)
+            .map(|(note, velocity)| ChanelVoiceMessage::NoteOff {
+              note,
+              velocity,
+              attr_type,
+              attr_data,
+            }),
+          _ => None,
+        }
+        .map(|message| MessageType::ChannelVoice(ChannelVoice { channel, message })),
+        _ => None,
+      };
+
+      if let Some(mtype) = echo {
+        output(Event {
+          timestamp: timestamp + interval * repeat as TimestampNanos,
+          endpoint,
+          message: Message { mtype, ..original },
+        });
+      }
+    }
+  }
+
+  fn reset(&mut self) {
+    self.clock.reset();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::output_queue::OutputQueue;
+  use crate::processor::Chain;
+  use crate::protocol::messages::system::System;
+
+  const MS: TimestampNanos = 1_000_000;
+
+  fn event(timestamp: TimestampNanos, mtype: MessageType) -> Event {
+    Event {
+      timestamp,
+      endpoint: 1,
+      message: Message { group: 0, mtype },
+    }
+  }
+
+  fn note(timestamp: TimestampNanos, message: ChannelVoice1Message) -> Event {
+    event(
+      timestamp,
+      MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 2,
+        message,
+      }),
+    )
+  }
+
+  #[test]
+  fn repeat_notes_with_decay_and_transpose() {
+    let mut delay = Delay::new(DelayTime::Fixed(Duration::from_millis(100)))
+      .with_repeats(3)
+      .with_feedback(0.5)
+      .with_transpose(12);
+    let mut events = Vec::new();
+
+    delay.process(
+      note(
+        10 * MS,
+        ChannelVoice1Message::NoteOn {
+          note: 100,
+          velocity: 100,
+        },
+      ),
+      &mut |event| events.push(event),
+    );
+    delay.process(
+      note(
+        50 * MS,
+        ChannelVoice1Message::NoteOff {
+          note: 100,
+          velocity: 0,
+        },
+      ),
+      &mut |event| events.push(event),
+    );
+    delay.process(
+      note(60 * MS, ChannelVoice1Message::PitchBend { data: 0x2000 }),
+      &mut |event| events.push(event),
+    );
+
+    assert_eq!(
+      events,
+      vec![
+        note(
+          10 * MS,
+          ChannelVoice1Message::NoteOn {
+            note: 100,
+            velocity: 100
+          }
+        ),
+        note(
+          110 * MS,
+          ChannelVoice1Message::NoteOn {
+            note: 112,
+            velocity: 50
+          }
+        ),
+        note(
+          210 * MS,
+          ChannelVoice1Message::NoteOn {
+            note: 124,
+            velocity: 25
+          }
+        ),
+        note(
+          50 * MS,
+          ChannelVoice1Message::NoteOff {
+            note: 100,
+            velocity: 0
+          }
+        ),
+        note(
+          150 * MS,
+          ChannelVoice1Message::NoteOff {
+            note: 112,
+            velocity: 0
+          }
+        ),
+        note(
+          250 * MS,
+          ChannelVoice1Message::NoteOff {
+            note: 124,
+            velocity: 0
+          }
+        ),
+        note(60 * MS, ChannelVoice1Message::PitchBend { data: 0x2000 }),
+      ]
+    );
+  }
+
+  #[test]
+  fn follow_the_tempo_of_the_clock() {
+    let mut delay = Delay::new(DelayTime::Beats(0.5))
+      .with_repeats(1)
+      .with_tempo(60.0);
+    let mut timestamps = Vec::new();
+    let note_on = |timestamp| {
+      note(
+        timestamp,
+        ChannelVoice1Message::NoteOn {
+          note: 60,
+          velocity: 100,
+        },
+      )
+    };
+
+    delay.process(note_on(0), &mut |event| timestamps.push(event.timestamp));
+    // 24 clocks per beat at 125 BPM
+    for tick in 0..48 {
+      delay.process(
+        event(tick * 20 * MS, MessageType::System(System::TimingClock)),
+        &mut |_| {},
+      );
+    }
+    delay.process(note_on(1000 * MS), &mut |event| {
+      timestamps.push(event.timestamp)
+    });
+
+    assert_eq!(timestamps, vec![0, 500 * MS, 1000 * MS, 1240 * MS]);
+  }
+
+  #[test]
+  fn schedule_echoes_through_an_output_queue() {
+    let queue = OutputQueue::new();
+    let mut chain = Chain::new()
+      .with(Delay::new(DelayTime::Fixed(Duration::from_millis(250))).with_repeats(2))
+      .with(queue.output("delay"));
+
+    chain.process(
+      note(
+        1000,
+        ChannelVoice1Message::NoteOn {
+          note: 60,
+          velocity: 100,
+        },
+      ),
+      &mut |_| {},
+    );
+
+    let mut scheduled = Vec::new();
+    queue.drain(TimestampNanos::MAX, |timestamp, ump| {
+      scheduled.push((timestamp, ump.to_vec()))
+    });
+    assert_eq!(
+      scheduled,
+      vec![
+        (1000, vec![0x2092_3c64]),
+        (1000 + 250 * MS, vec![0x2092_3c32]),
+        (1000 + 500 * MS, vec![0x2092_3c19]),
+      ]
+    );
+  }
+}
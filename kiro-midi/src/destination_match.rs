@@ -0,0 +1,241 @@
+use regex::Regex;
+
+use crate::endpoints::DestinationId;
+
+/// Regexes are serialized as their pattern, and compiled again when deserialized.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+  feature = "serde",
+  serde(
+    try_from = "SerializedDestinationMatch",
+    into = "SerializedDestinationMatch"
+  )
+)]
+#[derive(Debug, Clone)]
+pub enum DestinationMatch {
+  Id(DestinationId),
+  Name(String),
+  Regex(Regex),
+}
+
+impl DestinationMatch {
+  pub fn regex(regex: &str) -> Result<Self, regex::Error> {
+    Regex::new(regex).map(Self::Regex)
+  }
+
+  /// Names and regexes match either the name or the display name of the destination.
+  pub(crate) fn matches(
+    &self,
+    destination_id: DestinationId,
+    destination_name: &str,
+    display_name: &str,
+  ) -> bool {
+    match self {
+      Self::Id(id) => destination_id == *id,
+      Self::Name(name) => destination_name == name.as_str() || display_name == name.as_str(),
+      Self::Regex(regex) => regex.is_match(destination_name) || regex.is_match(display_name),
+    }
+  }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+enum SerializedDestinationMatch {
+  Id(DestinationId),
+  Name(String),
+  Regex(String),
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SerializedDestinationMatch> for DestinationMatch {
+  type Error = regex::Error;
+
+  fn try_from(destination_match: SerializedDestinationMatch) -> Result<Self, Self::Error> {
+    match destination_match {
+      SerializedDestinationMatch::Id(id) => Ok(Self::Id(id)),
+      SerializedDestinationMatch::Name(name) => Ok(Self::Name(name)),
+      SerializedDestinationMatch::Regex(regex) => Self::regex(&regex),
+    }
+  }
+}
+
+#[cfg(feature = "serde")]
+impl From<DestinationMatch> for SerializedDestinationMatch {
+  fn from(destination_match: DestinationMatch) -> Self {
+    match destination_match {
+      DestinationMatch::Id(id) => Self::Id(id),
+      DestinationMatch::Name(name) => Self::Name(name),
+      DestinationMatch::Regex(regex) => Self::Regex(regex.as_str().to_string()),
+    }
+  }
+}
+
+impl From<DestinationId> for DestinationMatch {
+  fn from(destination_id: DestinationId) -> Self {
+    Self::Id(destination_id)
+  }
+}
+
+impl From<&str> for DestinationMatch {
+  fn from(name: &str) -> Self {
+    Self::Name(name.to_string())
+  }
+}
+
+/// Group and channel that all the messages are rewritten to when sent to a destination
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DestinationRemap {
+  group: Option<u8>,
+  channel: Option<u8>,
+}
+
+impl DestinationRemap {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Sends all the messages to the group, starting from 1.
+  #[must_use]
+  pub fn with_group(mut self, group: u8) -> Self {
+    if group > 0 && group <= 16 {
+      self.group = Some(group - 1);
+    }
+    self
+  }
+
+  /// Sends all the channel voice messages to the channel, starting from 1.
+  #[must_use]
+  pub fn with_channel(mut self, channel: u8) -> Self {
+    if channel > 0 && channel <= 16 {
+      self.channel = Some(channel - 1);
+    }
+    self
+  }
+
+  pub fn is_identity(&self) -> bool {
+    self.group.is_none() && self.channel.is_none()
+  }
+
+  /// Rewrites the first word of a UMP packet.
+  pub(crate) fn apply(&self, word: u32) -> u32 {
+    let mut word = word;
+    if let Some(group) = self.group {
+      word = (word & 0xf0ff_ffff) | ((group as u32) << 24);
+    }
+    if let Some(channel) = self.channel {
+      // Only the MIDI 1.0 and MIDI 2.0 channel voice messages have a channel
+      if matches!(word >> 28, 0x2 | 0x4) {
+        word = (word & 0xfff0_ffff) | ((channel as u32) << 16);
+      }
+    }
+    word
+  }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct DestinationMatches(Vec<(DestinationMatch, DestinationRemap)>);
+
+impl DestinationMatches {
+  pub fn new(matches: Vec<(DestinationMatch, DestinationRemap)>) -> Self {
+    Self(matches)
+  }
+
+  #[must_use]
+  pub fn with_destination<M>(mut self, destination_match: M) -> Self
+  where
+    M: Into<DestinationMatch>,
+  {
+    self.add_destination(destination_match.into());
+    self
+  }
+
+  #[must_use]
+  pub fn with_remapped_destination<M>(
+    mut self,
+    destination_match: M,
+    remap: DestinationRemap,
+  ) -> Self
+  where
+    M: Into<DestinationMatch>,
+  {
+    self.add_remapped_destination(destination_match, remap);
+    self
+  }
+
+  pub fn add_destination<M>(&mut self, destination_match: M)
+  where
+    M: Into<DestinationMatch>,
+  {
+    self.add_remapped_destination(destination_match, DestinationRemap::default());
+  }
+
+  pub fn add_remapped_destination<M>(&mut self, destination_match: M, remap: DestinationRemap)
+  where
+    M: Into<DestinationMatch>,
+  {
+    self.0.push((destination_match.into(), remap));
+  }
+
+  pub(crate) fn iter(&self) -> impl Iterator<Item = &(DestinationMatch, DestinationRemap)> {
+    self.0.iter()
+  }
+
+  pub(crate) fn iter_mut(
+    &mut self,
+  ) -> impl Iterator<Item = &mut (DestinationMatch, DestinationRemap)> {
+    self.0.iter_mut()
+  }
+
+  pub fn matches(&self, id: DestinationId, name: &str, display_name: &str) -> bool {
+    self.match_remap(id, name, display_name).is_some()
+  }
+
+  /// Returns the remap of the first match for the destination, if any.
+  pub fn match_remap(
+    &self,
+    id: DestinationId,
+    name: &str,
+    display_name: &str,
+  ) -> Option<DestinationRemap> {
+    self.0.iter().find_map(|(destination_match, remap)| {
+      destination_match
+        .matches(id, name, display_name)
+        .then(|| *remap)
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn first_match_remap() {
+    let remap = DestinationRemap::new().with_channel(5);
+    let destinations = DestinationMatches::default()
+      .with_remapped_destination("Synth B", remap)
+      .with_destination(DestinationMatch::regex("Synth.*").unwrap());
+
+    assert_eq!(
+      destinations.match_remap(1, "Synth B", "Synth B"),
+      Some(remap)
+    );
+    assert_eq!(
+      destinations.match_remap(2, "Synth A", "Synth A"),
+      Some(DestinationRemap::default())
+    );
+    assert!(!destinations.matches(3, "Drums", "Drums"));
+  }
+
+  #[test]
+  fn remap_group_and_channel() {
+    let remap = DestinationRemap::new().with_group(2).with_channel(5);
+
+    assert_eq!(remap.apply(0x2090_3c64), 0x2194_3c64);
+    assert_eq!(remap.apply(0x409f_3c00), 0x4194_3c00);
+    assert_eq!(remap.apply(0x10f8_0000), 0x11f8_0000);
+    assert_eq!(DestinationRemap::new().apply(0x2090_3c64), 0x2090_3c64);
+  }
+}
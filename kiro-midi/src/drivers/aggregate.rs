@@ -0,0 +1,807 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::destination_match::{DestinationMatch, DestinationMatches};
+use crate::drivers::thru::Thru;
+use crate::drivers::{self, Capabilities, Driver, DriverSpec};
+use crate::endpoints::{DestinationId, DestinationInfo, EndpointId, SourceId, SourceInfo};
+use crate::event::{self, Event, TimestampNanos};
+use crate::filter::Filter;
+use crate::input_config::InputConfig;
+use crate::input_handler::InputHandler;
+use crate::input_info::InputInfo;
+use crate::midi_ci::negotiation::MidiProtocol;
+use crate::midi_ci::profile::ProfileId;
+use crate::midi_ci::property::PropertyResponse;
+use crate::midi_ci::{CiAddress, CiDevice};
+use crate::output::{Output, OutputSink};
+use crate::output_config::OutputConfig;
+use crate::output_connection::{OutputConnection, OutputConnectionHandler};
+use crate::protocol::duplicates::DuplicateSuppression;
+use crate::protocol::messages::Message;
+use crate::source_match::{SourceMatch, SourceMatches};
+use crate::transform::Transform;
+
+const INDEX_SHIFT: u32 = 56;
+const LOCAL_ID_MASK: EndpointId = (1 << INDEX_SHIFT) - 1;
+
+/// Combines several drivers behind a single one.
+///
+/// The endpoints of every driver are namespaced by replacing the top 8 bits of their ids
+/// with the index of the driver, and the inputs are created in all of them, so the same
+/// `InputConfig` can match sources from different backends. The same happens with the outputs,
+/// but only in the drivers supporting them.
+pub struct AggregateDriver {
+  drivers: Vec<Driver>,
+  inputs: Mutex<HashMap<String, InputConfig>>,
+  outputs: Mutex<HashMap<String, (OutputConfig, Output)>>,
+  thrus: Mutex<HashMap<String, Arc<Mutex<Vec<Thru>>>>>,
+}
+
+impl DriverSpec for AggregateDriver {
+  fn create_input<H>(&mut self, config: InputConfig, handler: H) -> Result<String, drivers::Error>
+  where
+    H: Into<InputHandler>,
+  {
+    if self.inputs.lock().contains_key(config.name.as_str()) {
+      return Err(drivers::Error::InputAlreadyExists(config));
+    }
+
+    let handler = Arc::new(Mutex::new(handler.into()));
+    let thrus = Arc::new(Mutex::new(Vec::<Thru>::new()));
+    let duplicates =
+      Arc::new(Mutex::new(config.duplicate_window.map(|window| {
+        DuplicateSuppression::new(window.as_nanos() as TimestampNanos)
+      })));
+    for index in 0..self.drivers.len() {
+      let driver = &mut self.drivers[index];
+      let handler = handler.clone();
+      let thrus = thrus.clone();
+      let duplicates = duplicates.clone();
+      let driver_config = InputConfig {
+        name: config.name.clone(),
+        sources: Self::local_sources(index, &config.sources, driver),
+        jitter_reduction: config.jitter_reduction,
+        assemble_parameters: config.assemble_parameters,
+        pair_controllers: config.pair_controllers,
+        controller_rate_limit: config.controller_rate_limit,
+        // Suppressed here instead, as the duplicates can come from different drivers
+        duplicate_window: None,
+        merge_sources: config.merge_sources,
+      };
+      // Converted here, as the drivers could be aggregates too, and a new closure type
+      // per level would instantiate create_input endlessly
+      let driver_handler = InputHandler::from(move |mut event: Event| {
+        // By arrival time, as the shm and proxy drivers keep the timestamps of other processes
+        if let Some(duplicates) = duplicates.lock().as_mut() {
+          if !duplicates.process(&event.message, event::now()) {
+            return;
+          }
+        }
+        event.endpoint = Self::namespaced_id(index, event.endpoint);
+        for thru in thrus.lock().iter() {
+          thru.send(&event);
+        }
+        handler.lock().call(event)
+      });
+      if let Err(error) = driver.create_input(driver_config, driver_handler) {
+        for driver in self.drivers[..index].iter() {
+          let _ = driver.remove_input(config.name.as_str());
+        }
+        return Err(error);
+      }
+    }
+
+    let name = config.name.clone();
+    self.inputs.lock().insert(name.clone(), config);
+    self.thrus.lock().insert(name.clone(), thrus);
+    Ok(name)
+  }
+
+  fn sources(&self) -> Vec<SourceInfo> {
+    self
+      .drivers
+      .iter()
+      .enumerate()
+      .flat_map(|(index, driver)| {
+        driver.sources().into_iter().map(move |mut source| {
+          source.id = Self::namespaced_id(index, source.id);
+          source
+        })
+      })
+      .collect()
+  }
+
+  fn destinations(&self) -> Vec<DestinationInfo> {
+    self
+      .drivers
+      .iter()
+      .enumerate()
+      .flat_map(|(index, driver)| {
+        driver
+          .destinations()
+          .into_iter()
+          .map(move |mut destination| {
+            destination.id = Self::namespaced_id(index, destination.id);
+            destination
+          })
+      })
+      .collect()
+  }
+
+  fn inputs(&self) -> Vec<InputInfo> {
+    let mut connected_sources = HashMap::<String, Vec<EndpointId>>::new();
+    for (index, driver) in self.drivers.iter().enumerate() {
+      for info in driver.inputs() {
+        connected_sources.entry(info.name).or_default().extend(
+          info
+            .connected_sources
+            .into_iter()
+            .map(|source_id| Self::namespaced_id(index, source_id)),
+        );
+      }
+    }
+
+    self
+      .inputs
+      .lock()
+      .values()
+      .map(|config| InputInfo {
+        name: config.name.clone(),
+        sources: config.sources.clone(),
+        connected_sources: connected_sources
+          .remove(config.name.as_str())
+          .unwrap_or_default(),
+      })
+      .collect()
+  }
+
+  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
+    self.inputs.lock().get(name).cloned()
+  }
+
+  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+    let mut inputs = self.inputs.lock();
+    let config = inputs
+      .get_mut(name)
+      .ok_or_else(|| drivers::Error::InputNotFound(name.to_string()))?;
+
+    for (index, driver) in self.drivers.iter().enumerate() {
+      driver.set_input_sources(name, Self::local_sources(index, &sources, driver))?;
+    }
+
+    config.sources = sources;
+    Ok(())
+  }
+
+  fn remove_input(&self, name: &str) -> Result<(), drivers::Error> {
+    self
+      .inputs
+      .lock()
+      .remove(name)
+      .ok_or_else(|| drivers::Error::InputNotFound(name.to_string()))?;
+    self.thrus.lock().remove(name);
+    for driver in self.drivers.iter() {
+      driver.remove_input(name)?;
+    }
+    Ok(())
+  }
+
+  /// Data related capabilities are only reported when all the drivers have them,
+  /// while output and hotplug are reported when any of them has it.
+  fn capabilities(&self) -> Capabilities {
+    let capabilities = self
+      .drivers
+      .iter()
+      .map(|driver| driver.capabilities())
+      .collect::<Vec<Capabilities>>();
+
+    Capabilities {
+      output: capabilities.iter().any(|capabilities| capabilities.output),
+      ump_native: capabilities
+        .iter()
+        .all(|capabilities| capabilities.ump_native),
+      frame_timestamps: capabilities
+        .iter()
+        .all(|capabilities| capabilities.frame_timestamps),
+      source_merging: capabilities
+        .iter()
+        .all(|capabilities| capabilities.source_merging),
+      hotplug: capabilities.iter().any(|capabilities| capabilities.hotplug),
+      ..Capabilities::default()
+    }
+  }
+
+  fn create_output(&mut self, config: OutputConfig) -> Result<Output, drivers::Error> {
+    if self.outputs.lock().contains_key(config.name.as_str()) {
+      return Err(drivers::Error::OutputAlreadyExists(Box::new(config)));
+    }
+
+    let mut outputs = Vec::new();
+    for (index, driver) in self.drivers.iter_mut().enumerate() {
+      // Filtered and transformed once at the aggregate level
+      let driver_config = OutputConfig {
+        name: config.name.clone(),
+        destinations: Self::local_destinations(index, &config.destinations, driver),
+        ..OutputConfig::new(config.name.as_str())
+      };
+      match driver.create_output(driver_config) {
+        Ok(output) => outputs.push((index, output)),
+        Err(drivers::Error::OutputsNotSupported) => {}
+        Err(error) => return Err(error),
+      }
+    }
+
+    if outputs.is_empty() {
+      return Err(drivers::Error::OutputsNotSupported);
+    }
+
+    let name = config.name.clone();
+    let output = Output::new(name.clone(), Arc::new(AggregateSink { outputs }))
+      .with_processing(config.filter, config.transforms.clone());
+    self.outputs.lock().insert(name, (config, output.clone()));
+    Ok(output)
+  }
+
+  fn set_output_destinations(
+    &self,
+    name: &str,
+    destinations: DestinationMatches,
+  ) -> Result<(), drivers::Error> {
+    let mut outputs = self.outputs.lock();
+    let (config, _) = outputs
+      .get_mut(name)
+      .ok_or_else(|| drivers::Error::OutputNotFound(name.to_string()))?;
+
+    for (index, driver) in self.drivers.iter().enumerate() {
+      let local_destinations = Self::local_destinations(index, &destinations, driver);
+      match driver.set_output_destinations(name, local_destinations) {
+        Ok(()) | Err(drivers::Error::OutputsNotSupported) => {}
+        Err(error) => return Err(error),
+      }
+    }
+
+    config.destinations = destinations;
+    Ok(())
+  }
+  /// The ids of the destinations are namespaced as the ones from `destinations()`.
+  fn set_output_connection_handler(&mut self, handler: OutputConnectionHandler) {
+    let handler = Arc::new(Mutex::new(handler));
+    for (index, driver) in self.drivers.iter_mut().enumerate() {
+      let handler = handler.clone();
+      driver.set_output_connection_handler(Box::new(move |connection| {
+        let connection = match connection {
+          OutputConnection::Connected {
+            output,
+            destination,
+          } => OutputConnection::Connected {
+            output,
+            destination: Self::namespaced_id(index, destination),
+          },
+          OutputConnection::Disconnected {
+            output,
+            destination,
+          } => OutputConnection::Disconnected {
+            output,
+            destination: Self::namespaced_id(index, destination),
+          },
+        };
+        (handler.lock())(connection)
+      }));
+    }
+  }
+
+  fn set_broadcast_filter(&mut self, filter: Filter) {
+    for driver in self.drivers.iter_mut() {
+      driver.set_broadcast_filter(filter);
+    }
+  }
+
+  fn broadcast(&self, message: Message) -> Result<(), drivers::Error> {
+    let mut supported = false;
+    for driver in self.drivers.iter() {
+      match driver.broadcast(message) {
+        Ok(()) => supported = true,
+        Err(drivers::Error::OutputsNotSupported) => {}
+        Err(error) => return Err(error),
+      }
+    }
+    if supported {
+      Ok(())
+    } else {
+      Err(drivers::Error::OutputsNotSupported)
+    }
+  }
+
+  fn panic_all(&self) {
+    for driver in self.drivers.iter() {
+      driver.panic_all();
+    }
+  }
+
+  fn identify_sources(&self) -> Result<(), drivers::Error> {
+    let mut supported = false;
+    for driver in self.drivers.iter() {
+      match driver.identify_sources() {
+        Ok(()) => supported = true,
+        Err(drivers::Error::IdentityNotSupported) => {}
+        Err(error) => return Err(error),
+      }
+    }
+    if supported {
+      Ok(())
+    } else {
+      Err(drivers::Error::IdentityNotSupported)
+    }
+  }
+
+  fn discover_ci_devices(&self) -> Result<(), drivers::Error> {
+    let mut supported = false;
+    for driver in self.drivers.iter() {
+      match driver.discover_ci_devices() {
+        Ok(()) => supported = true,
+        Err(drivers::Error::MidiCiNotSupported) => {}
+        Err(error) => return Err(error),
+      }
+    }
+    if supported {
+      Ok(())
+    } else {
+      Err(drivers::Error::MidiCiNotSupported)
+    }
+  }
+
+  fn ci_devices(&self) -> Vec<CiDevice> {
+    self
+      .drivers
+      .iter()
+      .enumerate()
+      .flat_map(|(index, driver)| {
+        driver.ci_devices().into_iter().map(move |mut device| {
+          device.source = Self::namespaced_id(index, device.source);
+          device
+        })
+      })
+      .collect()
+  }
+
+  fn ci_profile_inquiry(&self, source: SourceId, address: CiAddress) -> Result<(), drivers::Error> {
+    let (driver, local_source) = self.local_ci_source(source)?;
+    driver.ci_profile_inquiry(local_source, address)
+  }
+
+  fn set_ci_profile(
+    &self,
+    source: SourceId,
+    address: CiAddress,
+    profile: ProfileId,
+    enabled: bool,
+  ) -> Result<(), drivers::Error> {
+    let (driver, local_source) = self.local_ci_source(source)?;
+    driver.set_ci_profile(local_source, address, profile, enabled)
+  }
+
+  fn negotiate_ci_protocol(
+    &self,
+    source: SourceId,
+    protocol: MidiProtocol,
+  ) -> Result<(), drivers::Error> {
+    let (driver, local_source) = self.local_ci_source(source)?;
+    driver.negotiate_ci_protocol(local_source, protocol)
+  }
+
+  fn get_ci_property(
+    &self,
+    source: SourceId,
+    resource: &str,
+  ) -> Result<PropertyResponse, drivers::Error> {
+    let (driver, local_source) = self.local_ci_source(source)?;
+    driver.get_ci_property(local_source, resource)
+  }
+
+  fn set_ci_property(
+    &self,
+    source: SourceId,
+    resource: &str,
+    data: &[u8],
+  ) -> Result<PropertyResponse, drivers::Error> {
+    let (driver, local_source) = self.local_ci_source(source)?;
+    driver.set_ci_property(local_source, resource, data)
+  }
+
+  /// The thru is created at the aggregate level, so the events from any driver
+  /// can be sent to the outputs of the others.
+  fn create_thru(
+    &mut self,
+    input: &str,
+    output: &str,
+    transform: Transform,
+  ) -> Result<(), drivers::Error> {
+    let output = self
+      .outputs
+      .lock()
+      .get(output)
+      .map(|(_, output)| output.clone())
+      .ok_or_else(|| drivers::Error::OutputNotFound(output.to_string()))?;
+
+    self
+      .thrus
+      .lock()
+      .get(input)
+      .ok_or_else(|| drivers::Error::InputNotFound(input.to_string()))?
+      .lock()
+      .push(Thru::new(output, transform));
+
+    Ok(())
+  }
+}
+
+impl AggregateDriver {
+  /// Combines the drivers, which are indexed in the same order for namespacing their endpoints.
+  pub fn new(drivers: Vec<Driver>) -> Self {
+    assert!(
+      drivers.len() <= (1 << (EndpointId::BITS - INDEX_SHIFT)),
+      "Too many drivers to aggregate"
+    );
+
+    Self {
+      drivers,
+      inputs: Mutex::new(HashMap::new()),
+      outputs: Mutex::new(HashMap::new()),
+      thrus: Mutex::new(HashMap::new()),
+    }
+  }
+
+  /// The id of an endpoint of the driver at `index` as exposed by the aggregate.
+  pub fn namespaced_id(index: usize, id: EndpointId) -> EndpointId {
+    ((index as EndpointId) << INDEX_SHIFT) | (id & LOCAL_ID_MASK)
+  }
+
+  /// The index of the driver that an aggregated endpoint id belongs to.
+  pub fn driver_index(id: EndpointId) -> usize {
+    (id >> INDEX_SHIFT) as usize
+  }
+
+  /// The driver and local id of the source of a MIDI-CI device.
+  fn local_ci_source(&self, source: SourceId) -> Result<(&Driver, SourceId), drivers::Error> {
+    let index = Self::driver_index(source);
+    let driver = self
+      .drivers
+      .get(index)
+      .ok_or(drivers::Error::CiDeviceNotFound(source))?;
+    driver
+      .ci_devices()
+      .into_iter()
+      .map(|device| device.source)
+      .find(|local_source| Self::namespaced_id(index, *local_source) == source)
+      .map(|local_source| (driver, local_source))
+      .ok_or(drivers::Error::CiDeviceNotFound(source))
+  }
+
+  /// Translates the sources for the driver at `index`.
+  ///
+  /// The matches by id that belong to other drivers are discarded, and the rest are
+  /// translated back to the ids of the driver.
+  fn local_sources(index: usize, sources: &SourceMatches, driver: &Driver) -> SourceMatches {
+    let mut local_sources = SourceMatches::default();
+    for (source_match, filter, transform) in sources.iter() {
+      match source_match {
+        SourceMatch::Id(id) if Self::driver_index(*id) != index => {}
+        SourceMatch::Id(id) => {
+          // The namespace drops the top bits of the local ids, so they are recovered from the sources
+          let local_id = driver
+            .sources()
+            .into_iter()
+            .map(|source| source.id)
+            .find(|local_id| Self::namespaced_id(index, *local_id) == *id)
+            .unwrap_or(*id & LOCAL_ID_MASK);
+          local_sources.add_source_with_transform(local_id, filter.clone(), *transform);
+        }
+        source_match => {
+          local_sources.add_source_with_transform(source_match.clone(), filter.clone(), *transform)
+        }
+      }
+    }
+    local_sources
+  }
+
+  /// Translates the destinations for the driver at `index`, in the same way as `local_sources`.
+  fn local_destinations(
+    index: usize,
+    destinations: &DestinationMatches,
+    driver: &Driver,
+  ) -> DestinationMatches {
+    let mut local_destinations = DestinationMatches::default();
+    for (destination_match, remap) in destinations.iter() {
+      match destination_match {
+        DestinationMatch::Id(id) if Self::driver_index(*id) != index => {}
+        DestinationMatch::Id(id) => {
+          let local_id = driver
+            .destinations()
+            .into_iter()
+            .map(|destination| destination.id)
+            .find(|local_id| Self::namespaced_id(index, *local_id) == *id)
+            .unwrap_or(*id & LOCAL_ID_MASK);
+          local_destinations.add_remapped_destination(local_id, *remap);
+        }
+        destination_match => {
+          local_destinations.add_remapped_destination(destination_match.clone(), *remap)
+        }
+      }
+    }
+    local_destinations
+  }
+}
+
+/// Sends to the outputs created in every driver
+struct AggregateSink {
+  outputs: Vec<(usize, Output)>,
+}
+
+impl OutputSink for AggregateSink {
+  fn send(&self, timestamp: TimestampNanos, ump: &[u32]) {
+    for (_, output) in self.outputs.iter() {
+      output.send_ump(timestamp, ump);
+    }
+  }
+
+  fn connected_destinations(&self) -> Vec<DestinationId> {
+    self
+      .outputs
+      .iter()
+      .flat_map(|(index, output)| {
+        output
+          .connected_destinations()
+          .into_iter()
+          .map(move |destination_id| AggregateDriver::namespaced_id(*index, destination_id))
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drivers::{LoopbackDriver, MockDriver};
+  use crate::filter::Filter;
+  use crate::protocol::messages::channel_voice1::ChannelVoice1;
+  use crate::protocol::messages::{Message, MessageType};
+
+  #[test]
+  fn namespaced_ids() {
+    let id = AggregateDriver::namespaced_id(3, 0xffff_0000_1234_5678);
+
+    assert_eq!(id, 0x03ff_0000_1234_5678);
+    assert_eq!(AggregateDriver::driver_index(id), 3);
+  }
+
+  #[test]
+  fn endpoints_from_all_drivers() {
+    let mut mock = MockDriver::new("mock");
+    let keys = mock.add_source("Keys");
+    let mut loopback = LoopbackDriver::new("loopback");
+    let port = loopback.add_port("sequencer").unwrap();
+
+    let driver = AggregateDriver::new(vec![mock.into(), loopback.into()]);
+
+    let sources = driver
+      .sources()
+      .into_iter()
+      .map(|source| (source.id, source.name))
+      .collect::<Vec<_>>();
+    assert_eq!(
+      sources,
+      vec![
+        (AggregateDriver::namespaced_id(0, keys), "Keys".to_string()),
+        (
+          AggregateDriver::namespaced_id(1, port),
+          "sequencer".to_string()
+        ),
+      ]
+    );
+    assert_eq!(driver.destinations().len(), 1);
+    assert_eq!(
+      driver.destinations()[0].id,
+      AggregateDriver::namespaced_id(1, port)
+    );
+  }
+
+  #[test]
+  fn capabilities_from_all_drivers() {
+    let mock = MockDriver::new("mock");
+    let loopback = LoopbackDriver::new("loopback");
+
+    let driver = AggregateDriver::new(vec![mock.into(), loopback.into()]);
+
+    assert_eq!(
+      driver.capabilities(),
+      Capabilities {
+        output: true,
+        ump_native: true,
+        hotplug: true,
+        ..Capabilities::default()
+      }
+    );
+  }
+
+  #[test]
+  fn output_to_destinations_across_drivers() {
+    let mut mock1 = MockDriver::new("mock1");
+    let synth1 = mock1.add_destination("Synth");
+    let mut mock2 = MockDriver::new("mock2");
+    let synth2 = mock2.add_destination("Synth");
+    mock2.add_destination("Drums");
+
+    let mut driver = AggregateDriver::new(vec![mock1.into(), mock2.into()]);
+    let output = driver
+      .create_output(OutputConfig::new("synths").with_destination("Synth"))
+      .unwrap();
+
+    assert_eq!(
+      output.connected_destinations(),
+      vec![
+        AggregateDriver::namespaced_id(0, synth1),
+        AggregateDriver::namespaced_id(1, synth2)
+      ]
+    );
+    assert!(matches!(
+      driver.create_output(OutputConfig::new("synths")),
+      Err(drivers::Error::OutputAlreadyExists(_))
+    ));
+  }
+
+  #[test]
+  fn thru_across_drivers() {
+    let mut loopback1 = LoopbackDriver::new("loopback1");
+    let keys = loopback1.add_port("keys").unwrap();
+    let sender = loopback1.sender(keys).unwrap();
+    let mut loopback2 = LoopbackDriver::new("loopback2");
+    loopback2.add_port("synth").unwrap();
+
+    let mut driver = AggregateDriver::new(vec![loopback1.into(), loopback2.into()]);
+    driver
+      .create_input(
+        InputConfig::new("keys").with_source("keys", Filter::default()),
+        |_| {},
+      )
+      .unwrap();
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_clone = events.clone();
+    driver
+      .create_input(
+        InputConfig::new("synth").with_source("synth", Filter::default()),
+        move |event: Event| events_clone.lock().push(event.message),
+      )
+      .unwrap();
+    driver
+      .create_output(OutputConfig::new("synth").with_destination("synth"))
+      .unwrap();
+    driver
+      .create_thru("keys", "synth", Transform::new().with_channel(1, 2))
+      .unwrap();
+
+    sender.send(0, &[0x2090_3c64]);
+
+    assert!(matches!(
+      events.lock().as_slice(),
+      [Message {
+        mtype: MessageType::ChannelVoice1(ChannelVoice1 { channel: 1, .. }),
+        ..
+      }]
+    ));
+    assert!(matches!(
+      driver.create_thru("keys", "unknown", Transform::default()),
+      Err(drivers::Error::OutputNotFound(_))
+    ));
+  }
+
+  #[test]
+  fn input_matching_sources_across_drivers() {
+    let mut loopback1 = LoopbackDriver::new("loopback1");
+    let port1 = loopback1.add_port("sequencer").unwrap();
+    let sender1 = loopback1.sender(port1).unwrap();
+    let mut loopback2 = LoopbackDriver::new("loopback2");
+    let port2 = loopback2.add_port("sequencer").unwrap();
+    let sender2 = loopback2.sender(port2).unwrap();
+
+    let mut driver = AggregateDriver::new(vec![loopback1.into(), loopback2.into()]);
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_clone = events.clone();
+    let config = InputConfig::new("all").with_source("sequencer", Filter::default());
+    driver
+      .create_input(config, move |event: Event| {
+        events_clone.lock().push(event.endpoint)
+      })
+      .unwrap();
+
+    sender1.send(0, &[0x2090_3c64]);
+    sender2.send(0, &[0x2090_3c64]);
+
+    assert_eq!(
+      events.lock().as_slice(),
+      &[
+        AggregateDriver::namespaced_id(0, port1),
+        AggregateDriver::namespaced_id(1, port2)
+      ]
+    );
+    let inputs = driver.inputs();
+    assert_eq!(inputs.len(), 1);
+    assert_eq!(inputs[0].connected_sources.len(), 2);
+  }
+
+  #[test]
+  fn input_removed_from_all_drivers_when_one_fails() {
+    let loopback1 = LoopbackDriver::new("loopback1");
+    let mut loopback2 = LoopbackDriver::new("loopback2");
+    loopback2
+      .create_input(InputConfig::new("input"), |_: Event| {})
+      .unwrap();
+
+    let mut driver = AggregateDriver::new(vec![loopback1.into(), loopback2.into()]);
+    let result = driver.create_input(InputConfig::new("input"), |_: Event| {});
+
+    assert!(matches!(result, Err(drivers::Error::InputAlreadyExists(_))));
+    assert!(driver.drivers[0].inputs().is_empty());
+    assert!(driver.inputs().is_empty());
+  }
+
+  #[test]
+  fn duplicates_across_drivers_with_other_clocks() {
+    let mut loopback1 = LoopbackDriver::new("loopback1");
+    let port1 = loopback1.add_port("keys").unwrap();
+    let sender1 = loopback1.sender(port1).unwrap();
+    let mut loopback2 = LoopbackDriver::new("loopback2");
+    let port2 = loopback2.add_port("keys").unwrap();
+    let sender2 = loopback2.sender(port2).unwrap();
+
+    let mut driver = AggregateDriver::new(vec![loopback1.into(), loopback2.into()]);
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_clone = events.clone();
+    let config = InputConfig::new("keys")
+      .with_source("keys", Filter::default())
+      .with_duplicate_suppression(std::time::Duration::from_secs(1));
+    driver
+      .create_input(config, move |event: Event| {
+        events_clone.lock().push(event.endpoint)
+      })
+      .unwrap();
+
+    sender1.send(0, &[0x2090_3c64]);
+    sender2.send(3_600_000_000_000, &[0x2090_3c64]);
+
+    assert_eq!(
+      events.lock().as_slice(),
+      &[AggregateDriver::namespaced_id(0, port1)]
+    );
+  }
+
+  #[test]
+  fn source_matches_by_namespaced_id() {
+    let mut loopback1 = LoopbackDriver::new("loopback1");
+    let port1 = loopback1.add_port("sequencer").unwrap();
+    let sender1 = loopback1.sender(port1).unwrap();
+    let mut loopback2 = LoopbackDriver::new("loopback2");
+    let port2 = loopback2.add_port("sequencer").unwrap();
+    let sender2 = loopback2.sender(port2).unwrap();
+
+    let mut driver = AggregateDriver::new(vec![loopback1.into(), loopback2.into()]);
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_clone = events.clone();
+    driver
+      .create_input(InputConfig::new("input"), move |event: Event| {
+        events_clone.lock().push(event.endpoint)
+      })
+      .unwrap();
+
+    let source_id = AggregateDriver::namespaced_id(1, port2);
+    let sources = SourceMatches::default().with_source(source_id, Filter::default());
+    driver.set_input_sources("input", sources).unwrap();
+
+    sender1.send(0, &[0x2090_3c64]);
+    sender2.send(0, &[0x2090_3c64]);
+
+    assert_eq!(events.lock().as_slice(), &[source_id]);
+  }
+}
@@ -0,0 +1,278 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+
+use btleplug::api::{Central, CentralEvent, Manager as _, Peripheral as _, ScanFilter};
+use btleplug::platform::{Adapter, Manager, PeripheralId};
+use futures::StreamExt;
+use parking_lot::Mutex;
+use thiserror::Error;
+use tokio::runtime;
+use tokio::sync::oneshot;
+use uuid::Uuid;
+
+use crate::drivers;
+use crate::drivers::blemidi::packet::PacketDecoder;
+use crate::drivers::endpoints;
+use crate::drivers::inputs::Inputs;
+use crate::drivers::Capabilities;
+use crate::endpoints::{DestinationInfo, SourceId, SourceInfo};
+use crate::event;
+use crate::input_config::InputConfig;
+use crate::input_handler::InputHandler;
+use crate::input_info::InputInfo;
+use crate::source_match::SourceMatches;
+
+const MIDI_SERVICE_UUID: Uuid = Uuid::from_u128(0x03b80e5a_ede8_4b33_a751_6ce34ec4c700);
+const MIDI_CHARACTERISTIC_UUID: Uuid = Uuid::from_u128(0x7772e5db_3868_4112_a1a9_f2669d106bf3);
+
+type Endpoints = endpoints::Endpoints<PeripheralId, PeripheralId>;
+
+#[derive(Error, Debug)]
+pub enum BleMidiError {
+  #[error("Error creating the async runtime: {0}")]
+  Runtime(std::io::Error),
+
+  #[error("Bluetooth: {0}")]
+  Bluetooth(#[from] btleplug::Error),
+
+  #[error("No Bluetooth adapter found")]
+  AdapterNotFound,
+
+  #[error("The peripheral doesn't have the MIDI characteristic")]
+  CharacteristicNotFound,
+}
+
+pub struct BleMidiDriver {
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  shutdown: Option<oneshot::Sender<()>>,
+}
+
+impl drivers::DriverSpec for BleMidiDriver {
+  fn create_input<H>(&mut self, config: InputConfig, handler: H) -> Result<String, drivers::Error>
+  where
+    H: Into<InputHandler>,
+  {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .create(config, handler.into(), endpoints.connected_source_names())
+  }
+
+  fn sources(&self) -> Vec<SourceInfo> {
+    let endpoints = self.endpoints.lock();
+    self.inputs.lock().source_infos(&endpoints)
+  }
+
+  fn destinations(&self) -> Vec<DestinationInfo> {
+    self.endpoints.lock().destination_infos()
+  }
+
+  fn inputs(&self) -> Vec<InputInfo> {
+    self.inputs.lock().infos()
+  }
+
+  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
+    self.inputs.lock().config(name)
+  }
+
+  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .set_sources(name, sources, endpoints.connected_source_names())
+  }
+
+  fn remove_input(&self, name: &str) -> Result<(), drivers::Error> {
+    self.inputs.lock().remove(name)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      hotplug: true,
+      ..Capabilities::default()
+    }
+  }
+}
+
+impl BleMidiDriver {
+  /// Creates a driver that keeps scanning for BLE MIDI peripherals in a background thread,
+  /// connecting to them whenever they are advertised (which includes after they wake up from sleeping).
+  pub fn new(name: &str) -> Result<Self, drivers::Error> {
+    let runtime = runtime::Builder::new_current_thread()
+      .enable_all()
+      .build()
+      .map_err(BleMidiError::Runtime)?;
+
+    let central = runtime.block_on(Self::central())?;
+
+    let endpoints = Arc::new(Mutex::new(Endpoints::new()));
+    let inputs = Inputs::shared();
+    let scanner = Arc::new(Scanner {
+      central,
+      endpoints: endpoints.clone(),
+      inputs: inputs.clone(),
+      connecting: Mutex::new(HashSet::new()),
+    });
+
+    let (shutdown, shutdown_receiver) = oneshot::channel();
+    std::thread::Builder::new()
+      .name(format!("{}-blemidi", name))
+      .spawn(move || {
+        runtime.block_on(async move {
+          tokio::select! {
+            _ = scanner.run() => {}
+            _ = shutdown_receiver => {}
+          }
+        })
+      })
+      .map_err(BleMidiError::Runtime)?;
+
+    Ok(Self {
+      endpoints,
+      inputs,
+      shutdown: Some(shutdown),
+    })
+  }
+
+  async fn central() -> Result<Adapter, BleMidiError> {
+    let manager = Manager::new().await?;
+    let adapters = manager.adapters().await?;
+    adapters
+      .into_iter()
+      .next()
+      .ok_or(BleMidiError::AdapterNotFound)
+  }
+}
+
+impl Drop for BleMidiDriver {
+  fn drop(&mut self) {
+    if let Some(shutdown) = self.shutdown.take() {
+      shutdown.send(()).ok();
+    }
+  }
+}
+
+struct Scanner {
+  central: Adapter,
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  connecting: Mutex<HashSet<PeripheralId>>,
+}
+
+impl Scanner {
+  async fn run(self: Arc<Self>) -> Result<(), BleMidiError> {
+    let mut events = self.central.events().await?;
+    self
+      .central
+      .start_scan(ScanFilter {
+        services: vec![MIDI_SERVICE_UUID],
+      })
+      .await?;
+
+    while let Some(event) = events.next().await {
+      match event {
+        CentralEvent::DeviceDiscovered(peripheral_id)
+        | CentralEvent::DeviceUpdated(peripheral_id) => {
+          let scanner = self.clone();
+          tokio::spawn(async move { scanner.connect(peripheral_id).await.ok() });
+        }
+        CentralEvent::DeviceDisconnected(peripheral_id) => {
+          self.handle_peripheral_disconnected(Self::endpoint_id(&peripheral_id))
+        }
+        _ => {}
+      }
+    }
+
+    Ok(())
+  }
+
+  async fn connect(&self, peripheral_id: PeripheralId) -> Result<(), BleMidiError> {
+    let source_id = Self::endpoint_id(&peripheral_id);
+    let connected = self.endpoints.lock().get_source(source_id).is_some();
+    if connected || !self.connecting.lock().insert(peripheral_id.clone()) {
+      return Ok(());
+    }
+
+    let result = self.receive(&peripheral_id, source_id).await;
+
+    self.connecting.lock().remove(&peripheral_id);
+    self.handle_peripheral_disconnected(source_id);
+
+    result
+  }
+
+  /// Connects to the peripheral and dispatches its messages until it gets disconnected.
+  async fn receive(
+    &self,
+    peripheral_id: &PeripheralId,
+    source_id: SourceId,
+  ) -> Result<(), BleMidiError> {
+    let peripheral = self.central.peripheral(peripheral_id).await?;
+
+    let name = peripheral
+      .properties()
+      .await?
+      .and_then(|properties| properties.local_name)
+      .unwrap_or_else(|| format!("{:?}", peripheral_id));
+
+    if !peripheral.is_connected().await? {
+      peripheral.connect().await?;
+    }
+    peripheral.discover_services().await?;
+
+    let characteristic = peripheral
+      .characteristics()
+      .into_iter()
+      .find(|characteristic| characteristic.uuid == MIDI_CHARACTERISTIC_UUID)
+      .ok_or(BleMidiError::CharacteristicNotFound)?;
+
+    peripheral.subscribe(&characteristic).await?;
+    let mut notifications = peripheral.notifications().await?;
+
+    self.handle_peripheral_connected(peripheral_id, source_id, name);
+
+    let mut decoder = PacketDecoder::new();
+    while let Some(notification) = notifications.next().await {
+      if notification.uuid == MIDI_CHARACTERISTIC_UUID {
+        let received = event::now();
+        let mut inputs = self.inputs.lock();
+        decoder.decode(&notification.value, received, |timestamp, ump| {
+          inputs.dispatch(source_id, timestamp, ump.as_slice())
+        });
+        inputs.flush();
+      }
+    }
+
+    Ok(())
+  }
+
+  fn handle_peripheral_connected(
+    &self,
+    peripheral_id: &PeripheralId,
+    source_id: SourceId,
+    name: String,
+  ) {
+    let mut endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .connect_source(source_id, name.as_str(), name.as_str());
+    endpoints.add_source(source_id, name.clone(), peripheral_id.clone());
+    endpoints.add_destination(source_id, name, peripheral_id.clone());
+  }
+
+  fn handle_peripheral_disconnected(&self, source_id: SourceId) {
+    let mut endpoints = self.endpoints.lock();
+    if endpoints.remove_source_by_id(source_id).is_some() {
+      self.inputs.lock().disconnect_source(source_id);
+    }
+    endpoints.remove_destination_by_id(source_id);
+  }
+
+  fn endpoint_id(peripheral_id: &PeripheralId) -> SourceId {
+    endpoints::hashed_id(format!("{:?}", peripheral_id).as_str())
+  }
+}
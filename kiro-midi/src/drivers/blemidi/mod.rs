@@ -0,0 +1,4 @@
+mod driver;
+mod packet;
+
+pub use driver::{BleMidiDriver, BleMidiError};
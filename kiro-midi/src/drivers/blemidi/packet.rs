@@ -0,0 +1,154 @@
+use crate::event::TimestampNanos;
+use crate::protocol::encoder::Ump;
+use crate::protocol::midi1;
+
+const NANOS_PER_MILLI: u64 = 1_000_000;
+
+/// Decoder for the BLE MIDI packets.
+///
+/// Every packet starts with a header byte containing the 6 most significant bits
+/// of a 13 bits milliseconds timestamp, followed by MIDI 1.0 messages,
+/// where every status byte is preceded by a byte with the 7 least significant bits of the timestamp.
+/// Running status messages can omit the timestamp, and SysEx messages can span several packets.
+pub struct PacketDecoder {
+  parser: midi1::Parser,
+}
+
+impl PacketDecoder {
+  pub fn new() -> Self {
+    Self {
+      parser: midi1::Parser::new(0),
+    }
+  }
+
+  /// Decodes a packet, calling `f` with the timestamp and the UMP packet of every message.
+  ///
+  /// The clock of the peripheral is not synchronized with ours, so the first message of the packet
+  /// gets the time the packet was received, and the following ones are spaced according to their timestamps.
+  pub fn decode<F>(&mut self, packet: &[u8], received: TimestampNanos, mut f: F)
+  where
+    F: FnMut(TimestampNanos, Ump),
+  {
+    let (header, data) = match packet.split_first() {
+      Some((header, data)) if header & 0xc0 == 0x80 => (header, data),
+      _ => return,
+    };
+
+    let mut timestamp_high = (*header & 0x3f) as u16;
+    let mut last_timestamp_low = None;
+    let mut first_timestamp = None;
+    let mut timestamp = received;
+    let mut after_timestamp = false;
+
+    for byte in data.iter().cloned() {
+      if byte & 0x80 != 0 && !after_timestamp {
+        let timestamp_low = (byte & 0x7f) as u16;
+        if matches!(last_timestamp_low, Some(last) if timestamp_low < last) {
+          timestamp_high = (timestamp_high + 1) & 0x3f;
+        }
+        last_timestamp_low = Some(timestamp_low);
+
+        let timestamp_millis = (timestamp_high << 7) | timestamp_low;
+        let first_timestamp_millis = *first_timestamp.get_or_insert(timestamp_millis);
+        let elapsed_millis = timestamp_millis.wrapping_sub(first_timestamp_millis) & 0x1fff;
+        timestamp = received + elapsed_millis as u64 * NANOS_PER_MILLI;
+        after_timestamp = true;
+      } else {
+        after_timestamp = false;
+        self.parser.push(byte, |ump| f(timestamp, ump));
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn decode(decoder: &mut PacketDecoder, packet: &[u8]) -> Vec<(TimestampNanos, u32)> {
+    let mut messages = Vec::new();
+    decoder.decode(packet, 1_000_000_000, |timestamp, ump| {
+      messages.extend(ump.as_slice().iter().map(|word| (timestamp, *word)))
+    });
+    messages
+  }
+
+  #[test]
+  fn single_message() {
+    let mut decoder = PacketDecoder::new();
+
+    assert_eq!(
+      decode(&mut decoder, &[0x80, 0x80, 0x90, 0x3c, 0x64]),
+      vec![(1_000_000_000, 0x20903c64)]
+    );
+  }
+
+  #[test]
+  fn multiple_messages_with_timestamps() {
+    let mut decoder = PacketDecoder::new();
+
+    assert_eq!(
+      decode(
+        &mut decoder,
+        &[0x81, 0x82, 0x90, 0x3c, 0x64, 0x85, 0x80, 0x3c, 0x00]
+      ),
+      vec![(1_000_000_000, 0x20903c64), (1_003_000_000, 0x20803c00)]
+    );
+  }
+
+  #[test]
+  fn running_status_with_and_without_timestamp() {
+    let mut decoder = PacketDecoder::new();
+
+    assert_eq!(
+      decode(
+        &mut decoder,
+        &[0x80, 0x80, 0xb0, 0x07, 0x10, 0x07, 0x20, 0x82, 0x07, 0x30]
+      ),
+      vec![
+        (1_000_000_000, 0x20b00710),
+        (1_000_000_000, 0x20b00720),
+        (1_002_000_000, 0x20b00730)
+      ]
+    );
+  }
+
+  #[test]
+  fn timestamp_low_overflow() {
+    let mut decoder = PacketDecoder::new();
+
+    assert_eq!(
+      decode(&mut decoder, &[0x80, 0xfe, 0xf8, 0x81, 0xf8]),
+      vec![(1_000_000_000, 0x10f80000), (1_003_000_000, 0x10f80000)]
+    );
+  }
+
+  #[test]
+  fn sysex_spanning_packets() {
+    let mut decoder = PacketDecoder::new();
+
+    assert_eq!(
+      decode(&mut decoder, &[0x80, 0x80, 0xf0, 0x7e, 0x7f]),
+      vec![]
+    );
+    assert_eq!(
+      decode(&mut decoder, &[0x80, 0x06, 0x01, 0x81, 0xf7, 0x82, 0xf8]),
+      vec![
+        (1_001_000_000, 0x3004_7e7f),
+        (1_001_000_000, 0x0601_0000),
+        (1_002_000_000, 0x10f80000)
+      ]
+    );
+  }
+
+  #[test]
+  fn invalid_header_is_ignored() {
+    let mut decoder = PacketDecoder::new();
+
+    assert_eq!(
+      decode(&mut decoder, &[0x40, 0x80, 0x90, 0x3c, 0x64]),
+      vec![]
+    );
+    assert_eq!(decode(&mut decoder, &[]), vec![]);
+  }
+}
@@ -0,0 +1,17 @@
+/// What a driver supports, so applications can adapt to it rather than finding out through errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Capabilities {
+  /// Virtual sources and destinations can be created for other applications to connect to
+  pub virtual_endpoints: bool,
+  /// MIDI can be sent to destinations
+  pub output: bool,
+  /// The data is received as UMP from the backend, rather than translated from MIDI 1.0 bytes
+  pub ump_native: bool,
+  /// The timestamps are aligned with audio frames
+  pub frame_timestamps: bool,
+  /// Sources and destinations are added and removed while the driver is running
+  pub hotplug: bool,
+  /// The data of several sources is received at once, so `InputConfig::with_source_merging` sorts it.
+  /// The rest of drivers (CoreMIDI included) receive the data of every source as it comes, and ignore it.
+  pub source_merging: bool,
+}
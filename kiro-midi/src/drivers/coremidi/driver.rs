@@ -1,27 +1,127 @@
-use arc_swap::ArcSwap;
+use arc_swap::{ArcSwap, ArcSwapOption};
 use core_foundation_sys::base::OSStatus;
 use coremidi::{
-  Client, EventList, InputPortWithContext, Notification, NotifyCallback, Object, ObjectType,
-  Protocol, Source,
+  Client, Destination, EventBuffer, EventList, InputPortWithContext, Notification, NotifyCallback,
+  Object, ObjectType, OutputPort, Protocol, Source, VirtualDestination, VirtualSource,
 };
 use parking_lot::Mutex;
 use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use thiserror::Error;
 
+use crate::destination_match::DestinationMatches;
 use crate::drivers;
-use crate::drivers::coremidi::endpoints::Endpoints;
-use crate::drivers::coremidi::timestamp::coremidi_timestamp_to_nanos;
-use crate::endpoints::{DestinationInfo, EndpointId, SourceId, SourceInfo};
-use crate::event::Event;
+use crate::drivers::coremidi::timestamp::{
+  coremidi_timestamp_to_nanos, nanos_to_coremidi_timestamp,
+};
+use crate::drivers::endpoints;
+use crate::drivers::inputs::Inputs;
+use crate::drivers::outputs::{DestinationSender, Outputs};
+use crate::drivers::thru::Thru;
+use crate::drivers::Capabilities;
+use crate::endpoints::{DestinationId, DestinationInfo, EndpointId, SourceId, SourceInfo};
+use crate::event::TimestampNanos;
 use crate::filter::Filter;
+use crate::identity::{identity_request, Identities, ALL_DEVICES};
 use crate::input_config::InputConfig;
 use crate::input_handler::InputHandler;
 use crate::input_info::InputInfo;
-use crate::protocol::decoder::DecoderProtocol2;
+use crate::midi_ci::negotiation::MidiProtocol;
+use crate::midi_ci::profile::ProfileId;
+use crate::midi_ci::property::PropertyResponse;
+use crate::midi_ci::{CiAddress, CiDevice, DeviceInfo, MidiCi};
+use crate::output::Output;
+use crate::output_config::OutputConfig;
+use crate::output_connection::OutputConnectionHandler;
+use crate::protocol::encoder::{encode_message, encode_sysex7};
+use crate::protocol::messages::Message;
 use crate::source_match::SourceMatches;
+use crate::transform::Transform;
+
+type Endpoints = endpoints::Endpoints<InputSource, Destination>;
+
+/// The name of the source as context, to find the destination paired with it
+type SourcesPort = InputPortWithContext<(SourceId, String)>;
+
+/// Where the data of the sources goes: the inputs matching them, and the identities
+/// and MIDI-CI devices behind them.
+#[derive(Clone)]
+struct Receivers {
+  inputs: Arc<Mutex<Inputs>>,
+  identities: Arc<Mutex<Identities>>,
+  midi_ci: Arc<Mutex<MidiCi>>,
+  /// The devices reply through the sender, as the callbacks can't lock the endpoints nor the outputs
+  sender: CoreMidiSender,
+}
+
+impl Receivers {
+  fn receive(&self, source_id: SourceId, source_name: &str, events: &EventList) {
+    let mut inputs = self.inputs.lock();
+    for event in events.iter() {
+      let timestamp = coremidi_timestamp_to_nanos(event.timestamp());
+      inputs.dispatch(source_id, timestamp, event.data());
+    }
+    inputs.flush();
+    drop(inputs);
+
+    let destination = self.sender.paired_destination(source_name);
+    let mut identities = self.identities.lock();
+    let mut midi_ci = self.midi_ci.lock();
+    for event in events.iter() {
+      identities.receive(source_id, event.data());
+      midi_ci.receive(source_id, event.data(), |reply| {
+        if let Some(destination) = destination {
+          encode_sysex7(0, reply, |ump| {
+            self.sender.send(destination, 0, ump.as_slice())
+          });
+        }
+      });
+    }
+  }
+
+  /// Connects a source to the inputs matching it, and to the port receiving from all the sources.
+  fn connect(
+    &self,
+    port: &Mutex<Option<SourcesPort>>,
+    source_id: SourceId,
+    name: &str,
+    display_name: &str,
+    source: &InputSource,
+  ) {
+    self
+      .inputs
+      .lock()
+      .connect_source(source_id, name, display_name);
+    if let (InputSource::Physical(source), Some(port)) = (source, port.lock().as_mut()) {
+      port
+        .connect_source(source, (source_id, name.to_string()))
+        .ok();
+    }
+  }
 
-type InputName = String;
+  fn disconnect(
+    &self,
+    port: &Mutex<Option<SourcesPort>>,
+    source_id: SourceId,
+    source: &InputSource,
+  ) {
+    if let (InputSource::Physical(source), Some(port)) = (source, port.lock().as_mut()) {
+      port.disconnect_source(source).ok();
+    }
+    self.inputs.lock().disconnect_source(source_id);
+    self.identities.lock().remove_source(source_id);
+    self.midi_ci.lock().remove_source(source_id);
+  }
+}
+
+/// Sources that the inputs can connect to
+#[derive(PartialEq)]
+enum InputSource {
+  /// Connected to the input port of the driver
+  Physical(Source),
+  /// The virtual destinations created by this driver, which receive through their own callbacks
+  Virtual,
+}
 
 #[derive(Error, Debug)]
 pub enum CoreMidiError {
@@ -31,31 +131,37 @@ pub enum CoreMidiError {
   #[error("Error creating an input port: {0}")]
   PortCreate(OSStatus),
 
-  #[error("An input with this name already exists: {0:?}")]
-  InputAlreadyExists(InputConfig),
-
-  #[error("Input not found: {0}")]
-  InputNotFound(InputName),
+  #[error("Error creating the output port: {0}")]
+  OutputPortCreate(OSStatus),
 
   #[error("Source not found: {0}")]
   SourceNotFound(SourceId),
 
   #[error("Error connecting the source {2:08x} to the input {1}: {0}")]
-  ConnectSource(OSStatus, InputName, SourceId),
-}
+  ConnectSource(OSStatus, String, SourceId),
+
+  #[error("Error creating a virtual source: {0}")]
+  VirtualSourceCreate(OSStatus),
 
-struct Input {
-  name: InputName,
-  sources: SourceMatches,
-  connected: HashSet<SourceId>,
-  filters: Arc<ArcSwap<HashMap<SourceId, Filter>>>,
-  port: coremidi::InputPortWithContext<SourceId>,
+  #[error("Error creating a virtual destination: {0}")]
+  VirtualDestinationCreate(OSStatus),
+
+  #[error("A virtual destination with this name already exists: {0}")]
+  VirtualDestinationAlreadyExists(String),
 }
 
 pub struct CoreMidiDriver {
   client: Client,
   endpoints: Arc<Mutex<Endpoints>>,
-  inputs: Arc<Mutex<HashMap<String, Input>>>,
+  outputs: Arc<Mutex<Outputs>>,
+  /// Also used by the outputs, to send without locking them
+  sender: CoreMidiSender,
+  receivers: Receivers,
+  /// Connected to every source, whether an input matches it or not.
+  /// Created once there is a client, like the output port
+  port: Arc<Mutex<Option<SourcesPort>>>,
+  virtual_sources: Vec<VirtualSource>,
+  virtual_destinations: Vec<VirtualDestination>,
 }
 
 impl drivers::DriverSpec for CoreMidiDriver {
@@ -63,343 +169,642 @@ impl drivers::DriverSpec for CoreMidiDriver {
   where
     H: Into<InputHandler>,
   {
-    if self.inputs.lock().contains_key(config.name.as_str()) {
-      Err(CoreMidiError::InputAlreadyExists(config).into())
-    } else {
-      let InputConfig { name, sources } = config;
+    let endpoints = self.endpoints.lock();
+    self
+      .receivers
+      .inputs
+      .lock()
+      .create(config, handler.into(), endpoints.connected_source_names())
+  }
 
-      let filters = self
-        .endpoints
-        .lock()
-        .connected_sources()
-        .into_iter()
-        .filter_map(|connected_source| {
-          sources
-            .match_filter(connected_source.id, connected_source.name.as_str())
-            .map(|filter| (connected_source.id, filter))
-        })
-        .collect::<HashMap<SourceId, Filter>>();
+  fn sources(&self) -> Vec<SourceInfo> {
+    let endpoints = self.endpoints.lock();
+    let identities = self.receivers.identities.lock();
+    let mut sources = self.receivers.inputs.lock().source_infos(&endpoints);
+    for source in sources.iter_mut() {
+      source.identity = identities.identity(source.id);
+    }
+    sources
+  }
 
-      let filters = Arc::new(ArcSwap::new(Arc::new(filters)));
+  fn destinations(&self) -> Vec<DestinationInfo> {
+    self.endpoints.lock().destination_infos()
+  }
 
-      let mut port = self.create_input_port(name.clone(), handler.into(), filters.clone())?;
+  fn inputs(&self) -> Vec<InputInfo> {
+    self.receivers.inputs.lock().infos()
+  }
 
-      let endpoints = self.endpoints.lock();
+  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
+    self.receivers.inputs.lock().config(name)
+  }
 
-      let mut connected = HashSet::new();
+  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    self
+      .receivers
+      .inputs
+      .lock()
+      .set_sources(name, sources, endpoints.connected_source_names())
+  }
 
-      for source_id in filters.load().keys().cloned() {
-        if let Some(source) = endpoints.get_source(source_id) {
-          if let Ok(()) = port.connect_source(source, source_id) {
-            connected.insert(source_id);
-          }
-        }
-      }
+  fn remove_input(&self, name: &str) -> Result<(), drivers::Error> {
+    self.receivers.inputs.lock().remove(name)
+  }
 
-      let input = Input {
-        name: name.clone(),
-        sources,
-        connected,
-        filters,
-        port,
-      };
+  /// Creates a source that other applications can connect to.
+  ///
+  /// It will be listed with the rest of sources once CoreMIDI notifies about it.
+  fn create_virtual_source(&mut self, name: &str) -> Result<EndpointId, drivers::Error> {
+    let virtual_source = self
+      .client
+      .virtual_source(name)
+      .map_err(CoreMidiError::VirtualSourceCreate)?;
+    let id = virtual_source
+      .unique_id()
+      .map(|id| id as EndpointId)
+      .unwrap_or_default();
+    self.virtual_sources.push(virtual_source);
+    Ok(id)
+  }
 
-      self.inputs.lock().insert(name.clone(), input);
+  /// Creates a destination that other applications can send data to.
+  ///
+  /// The inputs see it as one more source, which id is derived from the name.
+  fn create_virtual_destination(&mut self, name: &str) -> Result<SourceId, drivers::Error> {
+    let source_id = endpoints::hashed_id(name);
+    let mut endpoints = self.endpoints.lock();
+    if endpoints.get_source(source_id).is_some() {
+      return Err(CoreMidiError::VirtualDestinationAlreadyExists(name.to_string()).into());
+    }
+
+    let receivers = self.receivers.clone();
+    let source_name = name.to_string();
+    let virtual_destination = self
+      .client
+      .virtual_destination_with_protocol(name, Protocol::Midi20, move |events: &EventList| {
+        receivers.receive(source_id, source_name.as_str(), events)
+      })
+      .map_err(CoreMidiError::VirtualDestinationCreate)?;
 
-      Ok(name)
+    endpoints.add_source(source_id, name.to_string(), InputSource::Virtual);
+    self
+      .receivers
+      .connect(&self.port, source_id, name, name, &InputSource::Virtual);
+    self.virtual_destinations.push(virtual_destination);
+
+    Ok(source_id)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      output: true,
+      virtual_endpoints: true,
+      ump_native: true,
+      hotplug: true,
+      // Every source comes in its own callback
+      source_merging: false,
+      ..Capabilities::default()
     }
   }
 
-  fn sources(&self) -> Vec<SourceInfo> {
-    let endpoints = self.endpoints.lock();
+  fn create_output(&mut self, config: OutputConfig) -> Result<Output, drivers::Error> {
+    let (output, pending_ranges) = {
+      let endpoints = self.endpoints.lock();
+      let mut outputs = self.outputs.lock();
+      let output = outputs.create(config, endpoints.connected_destination_names())?;
+      (output, outputs.take_pending_ranges())
+    };
+    pending_ranges.send();
+    Ok(output)
+  }
 
-    let mut source_inputs = HashMap::<SourceId, HashSet<String>>::new();
-    for input in self.inputs.lock().values() {
-      for source_id in input.connected.iter().cloned() {
-        let inputs = source_inputs.entry(source_id).or_default();
-        inputs.insert(input.name.clone());
-      }
-    }
+  fn set_output_destinations(
+    &self,
+    name: &str,
+    destinations: DestinationMatches,
+  ) -> Result<(), drivers::Error> {
+    let pending_ranges = {
+      let endpoints = self.endpoints.lock();
+      let mut outputs = self.outputs.lock();
+      outputs.set_destinations(name, destinations, endpoints.connected_destination_names())?;
+      outputs.take_pending_ranges()
+    };
+    pending_ranges.send();
+    Ok(())
+  }
 
-    endpoints
-      .connected_sources()
-      .into_iter()
-      .map(|connected_source| {
-        let inputs = source_inputs
-          .get(&connected_source.id)
-          .map(|inputs| inputs.iter().cloned().collect::<Vec<String>>())
-          .unwrap_or_default();
-        SourceInfo::new(connected_source.id, connected_source.name.clone(), inputs)
-      })
-      .collect()
+  fn set_output_connection_handler(&mut self, handler: OutputConnectionHandler) {
+    self.outputs.lock().set_connection_handler(handler);
   }
 
-  fn destinations(&self) -> Vec<DestinationInfo> {
-    self
+  fn set_broadcast_filter(&mut self, filter: Filter) {
+    self.outputs.lock().set_broadcast_filter(filter);
+  }
+
+  fn broadcast(&self, message: Message) -> Result<(), drivers::Error> {
+    if !self.outputs.lock().broadcast_filter().message(&message) {
+      return Ok(());
+    }
+    let ump = encode_message(&message);
+    let destinations = self
       .endpoints
       .lock()
       .connected_destinations()
       .into_iter()
-      .map(|connected_destination| {
-        DestinationInfo::new(connected_destination.id, connected_destination.name.clone())
-      })
-      .collect()
+      .map(|destination| destination.id)
+      .collect::<Vec<DestinationId>>();
+    for destination_id in destinations {
+      self.sender.send(destination_id, 0, ump.as_slice());
+    }
+    Ok(())
   }
 
-  fn inputs(&self) -> Vec<InputInfo> {
+  fn panic_all(&self) {
+    self.outputs.lock().panic_all();
+  }
+
+  fn create_thru(
+    &mut self,
+    input: &str,
+    output: &str,
+    transform: Transform,
+  ) -> Result<(), drivers::Error> {
+    let output = self.outputs.lock().get(output)?;
     self
+      .receivers
       .inputs
       .lock()
-      .values()
-      .map(|input| InputInfo {
-        name: input.name.clone(),
-        sources: input.sources.clone(),
-        connected_sources: input.connected.iter().cloned().collect(),
-      })
-      .collect()
+      .add_thru(input, Thru::new(output, transform))
   }
 
-  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
-    self.inputs.lock().get(name).map(|input| InputConfig {
-      name: input.name.clone(),
-      sources: input.sources.clone(),
-    })
+  fn identify_sources(&self) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    let destinations = endpoints
+      .connected_destinations()
+      .into_iter()
+      .map(|destination| destination.id);
+    self
+      .outputs
+      .lock()
+      .broadcast_sysex(&identity_request(ALL_DEVICES), destinations);
+    Ok(())
   }
 
-  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+  fn discover_ci_devices(&self) -> Result<(), drivers::Error> {
+    let discovery = self.receivers.midi_ci.lock().discovery();
     let endpoints = self.endpoints.lock();
-
-    let mut inputs = self.inputs.lock();
-
-    let input = inputs
-      .get_mut(name)
-      .ok_or_else(|| CoreMidiError::InputNotFound(name.to_string()))?;
-
-    let connected_sources = endpoints
-      .connected_sources()
+    let destinations = endpoints
+      .connected_destinations()
       .into_iter()
-      .filter_map(|connected_source| {
-        sources
-          .match_filter(connected_source.id, connected_source.name.as_str())
-          .map(|filter| (connected_source.id, filter, &connected_source.source))
-      })
-      .collect::<Vec<(SourceId, Filter, &Source)>>();
+      .map(|destination| destination.id);
+    self
+      .outputs
+      .lock()
+      .broadcast_sysex(&discovery, destinations);
+    Ok(())
+  }
+
+  fn ci_devices(&self) -> Vec<CiDevice> {
+    self.receivers.midi_ci.lock().devices()
+  }
 
-    let mut filters = HashMap::<SourceId, Filter>::with_capacity(connected_sources.len());
-    let mut disconnected = input.connected.clone();
+  fn ci_profile_inquiry(&self, source: SourceId, address: CiAddress) -> Result<(), drivers::Error> {
+    let request = self
+      .receivers
+      .midi_ci
+      .lock()
+      .profile_inquiry(source, address);
+    self.send_ci(source, request.map(|request| vec![request]))
+  }
 
-    for (source_id, filter, source) in connected_sources {
-      filters.insert(source_id, filter);
-      if !input.connected.contains(&source_id) {
-        if let Ok(()) = input.port.connect_source(source, source_id) {
-          input.connected.insert(source_id);
-        }
-      } else {
-        disconnected.remove(&source_id);
-      }
-    }
+  fn set_ci_profile(
+    &self,
+    source: SourceId,
+    address: CiAddress,
+    profile: ProfileId,
+    enabled: bool,
+  ) -> Result<(), drivers::Error> {
+    let request = self
+      .receivers
+      .midi_ci
+      .lock()
+      .set_profile(source, address, profile, enabled);
+    self.send_ci(source, request.map(|request| vec![request]))
+  }
 
-    for source_id in disconnected {
-      if let Some(source) = endpoints.get_source(source_id) {
-        input.port.disconnect_source(source).ok();
-      }
-    }
+  fn negotiate_ci_protocol(
+    &self,
+    source: SourceId,
+    protocol: MidiProtocol,
+  ) -> Result<(), drivers::Error> {
+    let request = self
+      .receivers
+      .midi_ci
+      .lock()
+      .negotiate_protocol(source, protocol);
+    self.send_ci(source, request.map(|request| vec![request]))
+  }
 
-    input.sources = sources;
-    input.filters.swap(Arc::new(filters));
+  fn get_ci_property(
+    &self,
+    source: SourceId,
+    resource: &str,
+  ) -> Result<PropertyResponse, drivers::Error> {
+    let request = self.receivers.midi_ci.lock().get_property(source, resource);
+    self.send_ci_property(source, request)
+  }
 
-    Ok(())
+  fn set_ci_property(
+    &self,
+    source: SourceId,
+    resource: &str,
+    data: &[u8],
+  ) -> Result<PropertyResponse, drivers::Error> {
+    let request = self
+      .receivers
+      .midi_ci
+      .lock()
+      .set_property(source, resource, data);
+    self.send_ci_property(source, request)
   }
 }
 
 impl CoreMidiDriver {
   pub fn new(name: &str) -> Result<Self, drivers::Error> {
     let endpoints = Arc::new(Mutex::new(Endpoints::new()));
-    let inputs = Arc::new(Mutex::new(HashMap::new()));
-    let callback = Self::notifications_callback(endpoints.clone(), inputs.clone());
+    // The ports can only be created once there is a client, which needs the outputs for the notifications
+    let output_port = Arc::new(ArcSwapOption::empty());
+    let port = Arc::new(Mutex::new(None));
+    let sender = CoreMidiSender {
+      destinations: Arc::new(ArcSwap::from_pointee(HashMap::new())),
+      port: output_port.clone(),
+    };
+    let outputs = Arc::new(Mutex::new(Outputs::new(sender.clone())));
+    let receivers = Receivers {
+      inputs: Inputs::shared(),
+      identities: Arc::new(Mutex::new(Identities::new())),
+      midi_ci: Arc::new(Mutex::new(MidiCi::new(DeviceInfo::default()))),
+      sender: sender.clone(),
+    };
+    let callback = Self::notifications_callback(
+      endpoints.clone(),
+      outputs.clone(),
+      receivers.clone(),
+      port.clone(),
+    );
     let client =
       Client::new_with_notifications(name, callback).map_err(CoreMidiError::ClientCreate)?;
+    output_port.store(Some(Arc::new(
+      client
+        .output_port(format!("{}-output", name).as_str())
+        .map_err(CoreMidiError::OutputPortCreate)?,
+    )));
+    *port.lock() = Some(Self::create_sources_port(&client, name, receivers.clone())?);
     Self::initialize_endpoints(endpoints.clone());
+    {
+      let endpoints = endpoints.lock();
+      sender.update_destinations(&endpoints);
+      for source in endpoints.connected_sources() {
+        receivers.connect(
+          &port,
+          source.id,
+          source.name.as_str(),
+          source.display_name.as_str(),
+          &source.source,
+        );
+      }
+    }
 
     Ok(Self {
       client,
       endpoints,
-      inputs,
+      outputs,
+      sender,
+      receivers,
+      port,
+      virtual_sources: Vec::new(),
+      virtual_destinations: Vec::new(),
     })
   }
 
-  fn create_input_port(
+  /// Sends the messages of a MIDI-CI request to the destination paired with the source of the device.
+  fn send_ci(
     &self,
-    name: String,
-    mut handler: InputHandler,
-    filters: Arc<ArcSwap<HashMap<SourceId, Filter>>>,
-  ) -> Result<InputPortWithContext<SourceId>, CoreMidiError> {
-    let default_filter = Filter::new();
-    let mut decoder = DecoderProtocol2::default();
-    self
-      .client
+    source_id: SourceId,
+    messages: Option<Vec<Vec<u8>>>,
+  ) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    match messages.zip(endpoints.paired_destination(source_id)) {
+      Some((messages, destination)) => {
+        let outputs = self.outputs.lock();
+        for message in messages {
+          outputs.broadcast_sysex(&message, Some(destination));
+        }
+        Ok(())
+      }
+      None => Err(drivers::Error::CiDeviceNotFound(source_id)),
+    }
+  }
+
+  fn send_ci_property(
+    &self,
+    source_id: SourceId,
+    request: Option<(Vec<Vec<u8>>, PropertyResponse)>,
+  ) -> Result<PropertyResponse, drivers::Error> {
+    let (messages, response) = request.ok_or(drivers::Error::CiDeviceNotFound(source_id))?;
+    self.send_ci(source_id, Some(messages))?;
+    Ok(response)
+  }
+
+  /// Creates the port receiving from all the sources, which the inputs are dispatched from.
+  fn create_sources_port(
+    client: &Client,
+    name: &str,
+    receivers: Receivers,
+  ) -> Result<SourcesPort, CoreMidiError> {
+    client
       .input_port_with_protocol(
-        name.clone().as_str(),
+        format!("{}-input", name).as_str(),
         Protocol::Midi20,
-        move |events, source_id: &mut SourceId| {
-          Self::handle_input(
-            name.as_str(),
-            &filters,
-            &default_filter,
-            &mut decoder,
-            &mut handler,
-            events,
-            *source_id,
-          );
+        move |events, (source_id, source_name): &mut (SourceId, String)| {
+          receivers.receive(*source_id, source_name.as_str(), events)
         },
       )
       .map_err(CoreMidiError::PortCreate)
   }
 
-  fn handle_input(
-    _name: &str,
-    filters: &ArcSwap<HashMap<SourceId, Filter>>,
-    default_filter: &Filter,
-    decoder: &mut DecoderProtocol2,
-    handler: &mut InputHandler,
-    events: &EventList,
-    source_id: SourceId,
-  ) {
-    let filters = filters.load();
-    let filter = filters.get(&source_id).unwrap_or(default_filter);
-    // println!("filter: {:#?}", filter);
-    // println!("\n==> [{}:{:08x}:{}] {:?}", name, source_id, source_id, events);
-
-    for event in events.iter() {
-      decoder.reset();
-      let timestamp = coremidi_timestamp_to_nanos(event.timestamp());
-      for word in event.data() {
-        if let Ok(Some(message)) = decoder.next(*word, filter) {
-          let event = Event {
-            timestamp,
-            endpoint: source_id,
-            message,
-          };
-          handler.call(event);
-        }
-      }
-    }
-  }
-
   fn notifications_callback(
     endpoints: Arc<Mutex<Endpoints>>,
-    mut inputs: Arc<Mutex<HashMap<InputName, Input>>>,
+    outputs: Arc<Mutex<Outputs>>,
+    receivers: Receivers,
+    port: Arc<Mutex<Option<SourcesPort>>>,
   ) -> NotifyCallback {
     NotifyCallback::by_ownership(move |notification: Notification| match notification {
       Notification::ObjectAdded(info) => match info.child_type {
-        ObjectType::Source => Self::handle_source_connected(&endpoints, &mut inputs, info.child),
-        ObjectType::Destination => Self::handle_destination_connected(&endpoints, info.child),
+        ObjectType::Source => {
+          Self::handle_source_connected(&endpoints, &receivers, &port, info.child)
+        }
+        ObjectType::Destination => {
+          Self::handle_destination_connected(&endpoints, &outputs, &receivers.sender, info.child)
+        }
         _ => {}
       },
       Notification::ObjectRemoved(info) => match info.child_type {
-        ObjectType::Source => Self::handle_source_disconnected(&endpoints, &mut inputs, info.child),
-        ObjectType::Destination => Self::handle_destination_disconnected(&endpoints, info.child),
+        ObjectType::Source => {
+          Self::handle_source_disconnected(&endpoints, &receivers, &port, info.child)
+        }
+        ObjectType::Destination => {
+          Self::handle_destination_disconnected(&endpoints, &outputs, &receivers.sender, info.child)
+        }
         _ => {}
       },
+      Notification::SetupChanged => {
+        Self::handle_setup_changed(&endpoints, &outputs, &receivers, &port)
+      }
       _ => {}
     })
   }
 
-  fn handle_source_connected(
+  /// Synchronises the endpoints with the ones currently available in the system.
+  ///
+  /// Adding or removing a device doesn't always notify about every endpoint of the device,
+  /// but the setup changed notification is always sent afterwards.
+  fn handle_setup_changed(
     endpoints: &Arc<Mutex<Endpoints>>,
-    inputs: &mut Arc<Mutex<HashMap<InputName, Input>>>,
-    object: Object,
+    outputs: &Mutex<Outputs>,
+    receivers: &Receivers,
+    port: &Mutex<Option<SourcesPort>>,
   ) {
-    if let Some((source_id, name)) = Self::object_info(&object) {
-      let mut endpoints = endpoints.lock();
-      endpoints.add_source(source_id, name.clone(), object.into());
-      if let Some(source) = endpoints.get_source(source_id) {
-        Self::connect_source(&mut inputs.lock(), source_id, name, source);
+    let mut endpoints = endpoints.lock();
+
+    let mut available_sources = HashSet::new();
+    for source in coremidi::Sources {
+      if let Some((source_id, name, display_name)) = Self::object_info(&source) {
+        available_sources.insert(source_id);
+        if endpoints.get_source(source_id).is_none() {
+          endpoints.add_source_with_display_name(
+            source_id,
+            name.clone(),
+            display_name.clone(),
+            InputSource::Physical(source),
+          );
+          if let Some(source) = endpoints.get_source(source_id) {
+            receivers.connect(
+              port,
+              source_id,
+              name.as_str(),
+              display_name.as_str(),
+              source,
+            );
+          }
+        }
       }
     }
-  }
 
-  fn connect_source(
-    inputs: &mut HashMap<InputName, Input>,
-    source_id: SourceId,
-    source_name: String,
-    source: &Source,
-  ) {
-    for input in inputs.values_mut() {
-      if !input.connected.contains(&source_id) {
-        if let Some(filter) = input.sources.match_filter(source_id, source_name.as_str()) {
-          let mut filters = input.filters.load().as_ref().clone();
-          filters.insert(source_id, filter);
-          input.filters.swap(Arc::new(filters));
-          input.port.connect_source(source, source_id).ok();
-          input.connected.insert(source_id);
-        }
+    let removed_sources = endpoints
+      .connected_sources()
+      .into_iter()
+      .filter(|connected_source| {
+        matches!(connected_source.source, InputSource::Physical(_))
+          && !available_sources.contains(&connected_source.id)
+      })
+      .map(|connected_source| connected_source.id)
+      .collect::<Vec<SourceId>>();
+
+    for source_id in removed_sources {
+      if let Some(connected_source) = endpoints.remove_source_by_id(source_id) {
+        receivers.disconnect(port, connected_source.id, &connected_source.source);
+      }
+    }
+
+    let mut outputs = outputs.lock();
+
+    let mut available_destinations = HashSet::new();
+    for destination in coremidi::Destinations {
+      if let Some((id, name, display_name)) = Self::object_info(&destination) {
+        available_destinations.insert(id);
+        endpoints.add_destination_with_display_name(
+          id,
+          name.clone(),
+          display_name.clone(),
+          destination,
+        );
+        outputs.connect_destination(id, name.as_str(), display_name.as_str());
       }
     }
+
+    let removed_destinations = endpoints
+      .connected_destinations()
+      .into_iter()
+      .filter(|connected_destination| !available_destinations.contains(&connected_destination.id))
+      .map(|connected_destination| connected_destination.id)
+      .collect::<Vec<DestinationId>>();
+
+    for destination_id in removed_destinations {
+      endpoints.remove_destination_by_id(destination_id);
+      outputs.disconnect_destination(destination_id);
+    }
+    // Before sending the pitch bend ranges, so the sender finds the new destinations
+    receivers.sender.update_destinations(&endpoints);
+
+    let pending_ranges = outputs.take_pending_ranges();
+    drop(outputs);
+    drop(endpoints);
+    pending_ranges.send();
   }
 
-  fn handle_source_disconnected(
+  fn handle_source_connected(
     endpoints: &Arc<Mutex<Endpoints>>,
-    inputs: &mut Arc<Mutex<HashMap<InputName, Input>>>,
+    receivers: &Receivers,
+    port: &Mutex<Option<SourcesPort>>,
     object: Object,
   ) {
-    if let Some(connected_source) = endpoints.lock().remove_source(object.into()) {
-      Self::disconnect_source(
-        &mut inputs.lock(),
-        connected_source.id,
-        connected_source.name,
-        connected_source.source,
+    if let Some((source_id, name, display_name)) = Self::object_info(&object) {
+      let mut endpoints = endpoints.lock();
+      endpoints.add_source_with_display_name(
+        source_id,
+        name.clone(),
+        display_name.clone(),
+        InputSource::Physical(object.into()),
       );
+      if let Some(source) = endpoints.get_source(source_id) {
+        receivers.connect(
+          port,
+          source_id,
+          name.as_str(),
+          display_name.as_str(),
+          source,
+        );
+      }
     }
   }
 
-  fn disconnect_source(
-    inputs: &mut HashMap<InputName, Input>,
-    source_id: SourceId,
-    source_name: String,
-    source: Source,
+  fn handle_source_disconnected(
+    endpoints: &Arc<Mutex<Endpoints>>,
+    receivers: &Receivers,
+    port: &Mutex<Option<SourcesPort>>,
+    object: Object,
   ) {
-    for input in inputs.values_mut() {
-      if input
-        .sources
-        .match_index(source_id, source_name.as_str())
-        .is_some()
-      {
-        let mut filters = input.filters.load().as_ref().clone();
-        filters.remove(&source_id);
-        input.filters.swap(Arc::new(filters));
-        input.port.disconnect_source(&source).ok();
-        input.connected.remove(&source_id);
-      }
+    let source = InputSource::Physical(object.into());
+    if let Some(connected_source) = endpoints.lock().remove_source(source) {
+      receivers.disconnect(port, connected_source.id, &connected_source.source);
     }
   }
 
-  fn handle_destination_connected(endpoints: &Arc<Mutex<Endpoints>>, object: Object) {
-    if let Some((id, name)) = Self::object_info(&object) {
-      endpoints.lock().add_destination(id, name, object.into());
+  fn handle_destination_connected(
+    endpoints: &Arc<Mutex<Endpoints>>,
+    outputs: &Mutex<Outputs>,
+    sender: &CoreMidiSender,
+    object: Object,
+  ) {
+    if let Some((id, name, display_name)) = Self::object_info(&object) {
+      let pending_ranges = {
+        let mut endpoints = endpoints.lock();
+        endpoints.add_destination_with_display_name(
+          id,
+          name.clone(),
+          display_name.clone(),
+          object.into(),
+        );
+        sender.update_destinations(&endpoints);
+        let mut outputs = outputs.lock();
+        outputs.connect_destination(id, name.as_str(), display_name.as_str());
+        outputs.take_pending_ranges()
+      };
+      pending_ranges.send();
     }
   }
 
-  fn handle_destination_disconnected(endpoints: &Arc<Mutex<Endpoints>>, object: Object) {
-    endpoints.lock().remove_destination(object.into());
+  fn handle_destination_disconnected(
+    endpoints: &Arc<Mutex<Endpoints>>,
+    outputs: &Mutex<Outputs>,
+    sender: &CoreMidiSender,
+    object: Object,
+  ) {
+    let mut endpoints = endpoints.lock();
+    if let Some(connected_destination) = endpoints.remove_destination(object.into()) {
+      sender.update_destinations(&endpoints);
+      outputs
+        .lock()
+        .disconnect_destination(connected_destination.id);
+    }
   }
 
-  fn object_info(object: &coremidi::Object) -> Option<(EndpointId, String)> {
+  /// Returns the id, name and display name of an object.
+  ///
+  /// The display name includes the name of the device, which is what users usually recognise.
+  fn object_info(object: &coremidi::Object) -> Option<(EndpointId, String, String)> {
     let maybe_id = object.unique_id().map(|id| id as u64);
-    let maybe_name = object.display_name();
-    maybe_id.zip(maybe_name)
+    let maybe_display_name = object.display_name();
+    maybe_id.zip(maybe_display_name).map(|(id, display_name)| {
+      let name = object.name().unwrap_or_else(|| display_name.clone());
+      (id, name, display_name)
+    })
   }
 
   fn initialize_endpoints(endpoints: Arc<Mutex<Endpoints>>) {
     let mut endpoints = endpoints.lock();
     for source in coremidi::Sources {
-      if let Some((id, name)) = Self::object_info(&source) {
-        endpoints.add_source(id, name, source);
+      if let Some((id, name, display_name)) = Self::object_info(&source) {
+        endpoints.add_source_with_display_name(
+          id,
+          name,
+          display_name,
+          InputSource::Physical(source),
+        );
       }
     }
     for destination in coremidi::Destinations {
-      if let Some((id, name)) = Self::object_info(&destination) {
-        endpoints.add_destination(id, name, destination);
+      if let Some((id, name, display_name)) = Self::object_info(&destination) {
+        endpoints.add_destination_with_display_name(id, name, display_name, destination);
+      }
+    }
+  }
+}
+
+/// Sends the data from the outputs through the output port of the driver
+#[derive(Clone)]
+struct CoreMidiSender {
+  /// A copy of the connected destinations and their names, so sending from the read callbacks
+  /// doesn't lock the endpoints
+  destinations: Arc<ArcSwap<HashMap<DestinationId, (String, Destination)>>>,
+  port: Arc<ArcSwapOption<OutputPort>>,
+}
+
+impl CoreMidiSender {
+  fn update_destinations(&self, endpoints: &Endpoints) {
+    let destinations = endpoints
+      .connected_destinations()
+      .into_iter()
+      .map(|connected| {
+        let destination = (connected.name.clone(), connected.destination.clone());
+        (connected.id, destination)
+      })
+      .collect::<HashMap<DestinationId, (String, Destination)>>();
+    self.destinations.store(Arc::new(destinations));
+  }
+
+  /// The destination with the same name as a source, like `Endpoints::paired_destination`.
+  fn paired_destination(&self, source_name: &str) -> Option<DestinationId> {
+    self
+      .destinations
+      .load()
+      .iter()
+      .find(|(_, (name, _))| name == source_name)
+      .map(|(id, _)| *id)
+  }
+}
+
+impl DestinationSender for CoreMidiSender {
+  /// The words are sent as an event list, with the timestamp converted into host time
+  /// so CoreMIDI schedules the events sent ahead of time.
+  fn send(&self, destination: DestinationId, timestamp: TimestampNanos, ump: &[u32]) {
+    if let Some(port) = self.port.load().as_ref() {
+      if let Some((_, destination)) = self.destinations.load().get(&destination) {
+        let events = EventBuffer::new(Protocol::Midi20)
+          .with_packet(nanos_to_coremidi_timestamp(timestamp), ump);
+        port.send(destination, &events).ok();
       }
     }
   }
@@ -1,5 +1,5 @@
 mod driver;
-mod endpoints;
 mod timestamp;
 
 pub use driver::{CoreMidiDriver, CoreMidiError};
+pub(crate) use timestamp::host_time_nanos;
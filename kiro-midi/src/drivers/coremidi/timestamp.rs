@@ -7,6 +7,20 @@ mod external {
   }
 }
 
+/// Converts nanoseconds into host time, keeping 0 as it means to send right away.
+pub fn nanos_to_coremidi_timestamp(nanos: u64) -> u64 {
+  if nanos == 0 {
+    0
+  } else {
+    unsafe { external::AudioConvertNanosToHostTime(nanos) }
+  }
+}
+
 pub fn coremidi_timestamp_to_nanos(timestamp: u64) -> u64 {
   unsafe { external::AudioConvertHostTimeToNanos(timestamp) }
 }
+
+/// Current host time, in nanoseconds.
+pub fn host_time_nanos() -> u64 {
+  unsafe { external::AudioConvertHostTimeToNanos(external::AudioGetCurrentHostTime()) }
+}
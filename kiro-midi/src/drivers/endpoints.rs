@@ -0,0 +1,249 @@
+use std::collections::hash_map;
+use std::collections::HashMap;
+
+use crate::endpoints::{DestinationId, DestinationInfo, EndpointId, SourceId};
+
+/// Generates a stable endpoint id (FNV-1a hash) for backends that identify their endpoints by strings.
+pub fn hashed_id(key: &str) -> EndpointId {
+  key.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+    (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+  })
+}
+
+pub struct ConnectedSource<S> {
+  pub id: SourceId,
+  pub name: String,
+  pub display_name: String,
+  pub source: S,
+}
+
+pub struct ConnectedDestination<D> {
+  pub id: DestinationId,
+  pub name: String,
+  pub display_name: String,
+  pub destination: D,
+}
+
+pub struct DisconnectedSource {
+  pub id: SourceId,
+  pub name: String,
+}
+
+pub struct DisconnectedDestination {
+  pub id: DestinationId,
+  pub name: String,
+}
+
+/// Keeps track of the sources and destinations known by a driver.
+///
+/// The type parameters are the backend specific handles for sources (`S`) and destinations (`D`).
+pub struct Endpoints<S, D> {
+  connected_sources: HashMap<SourceId, ConnectedSource<S>>,
+  connected_destinations: HashMap<DestinationId, ConnectedDestination<D>>,
+  disconnected_sources: HashMap<SourceId, DisconnectedSource>,
+  disconnected_destinations: HashMap<DestinationId, DisconnectedDestination>,
+}
+
+impl<S, D> Endpoints<S, D>
+where
+  S: PartialEq,
+  D: PartialEq,
+{
+  pub fn new() -> Self {
+    Self {
+      connected_sources: HashMap::new(),
+      connected_destinations: HashMap::new(),
+      disconnected_sources: HashMap::new(),
+      disconnected_destinations: HashMap::new(),
+    }
+  }
+
+  pub fn connected_sources(&self) -> Vec<&ConnectedSource<S>> {
+    let mut sources = self
+      .connected_sources
+      .values()
+      .collect::<Vec<&ConnectedSource<S>>>();
+    sources.sort_unstable_by(|source1, source2| source1.name.cmp(&source2.name));
+    sources
+  }
+
+  /// Id, name and display name of the connected sources, as required to match them against `SourceMatches`.
+  pub fn connected_source_names(&self) -> Vec<(SourceId, &str, &str)> {
+    self
+      .connected_sources
+      .values()
+      .map(|connected_source| {
+        (
+          connected_source.id,
+          connected_source.name.as_str(),
+          connected_source.display_name.as_str(),
+        )
+      })
+      .collect()
+  }
+
+  /// Id, name and display name of the connected destinations, as required to match them against `DestinationMatches`.
+  pub fn connected_destination_names(&self) -> Vec<(DestinationId, &str, &str)> {
+    self
+      .connected_destinations
+      .values()
+      .map(|connected_destination| {
+        (
+          connected_destination.id,
+          connected_destination.name.as_str(),
+          connected_destination.display_name.as_str(),
+        )
+      })
+      .collect()
+  }
+
+  pub fn connected_destinations(&self) -> Vec<&ConnectedDestination<D>> {
+    let mut destinations = self
+      .connected_destinations
+      .values()
+      .collect::<Vec<&ConnectedDestination<D>>>();
+    destinations
+      .sort_unstable_by(|destination1, destination2| destination1.name.cmp(&destination2.name));
+    destinations
+  }
+
+  /// The destination with the same name as a source, which is how the devices usually expose
+  /// both directions of a port, so the replies to a source can be sent to it.
+  pub fn paired_destination(&self, source_id: SourceId) -> Option<DestinationId> {
+    let source = self.connected_sources.get(&source_id)?;
+    self
+      .connected_destinations
+      .values()
+      .find(|destination| destination.name == source.name)
+      .map(|destination| destination.id)
+  }
+
+  pub fn destination_infos(&self) -> Vec<DestinationInfo> {
+    self
+      .connected_destinations()
+      .into_iter()
+      .map(|connected_destination| {
+        DestinationInfo::new(
+          connected_destination.id,
+          connected_destination.name.clone(),
+          connected_destination.display_name.clone(),
+        )
+      })
+      .collect()
+  }
+
+  pub fn add_source(&mut self, id: SourceId, name: String, source: S) {
+    let display_name = name.clone();
+    self.add_source_with_display_name(id, name, display_name, source)
+  }
+
+  pub fn add_source_with_display_name(
+    &mut self,
+    id: SourceId,
+    name: String,
+    display_name: String,
+    source: S,
+  ) {
+    if let hash_map::Entry::Vacant(connected_source) = self.connected_sources.entry(id) {
+      self.disconnected_sources.remove(&id);
+      connected_source.insert(ConnectedSource {
+        id,
+        name,
+        display_name,
+        source,
+      });
+    }
+  }
+
+  pub fn remove_source(&mut self, source: S) -> Option<ConnectedSource<S>> {
+    let maybe_id = self
+      .connected_sources
+      .iter()
+      .find_map(|(id, connected_source)| (connected_source.source == source).then(|| *id));
+
+    maybe_id.and_then(|id| self.remove_source_by_id(id))
+  }
+
+  pub fn remove_source_by_id(&mut self, source_id: SourceId) -> Option<ConnectedSource<S>> {
+    let maybe_connected_source = self.connected_sources.remove(&source_id);
+
+    maybe_connected_source.map(|connected_source| {
+      self.disconnected_sources.insert(
+        connected_source.id,
+        DisconnectedSource {
+          id: connected_source.id,
+          name: connected_source.name.clone(),
+        },
+      );
+
+      connected_source
+    })
+  }
+
+  pub fn get_source(&self, source_id: SourceId) -> Option<&S> {
+    self
+      .connected_sources
+      .get(&source_id)
+      .map(|connected_source| &connected_source.source)
+  }
+
+  pub fn add_destination(&mut self, id: DestinationId, name: String, destination: D) {
+    let display_name = name.clone();
+    self.add_destination_with_display_name(id, name, display_name, destination)
+  }
+
+  pub fn add_destination_with_display_name(
+    &mut self,
+    id: DestinationId,
+    name: String,
+    display_name: String,
+    destination: D,
+  ) {
+    if let hash_map::Entry::Vacant(connected_destination) = self.connected_destinations.entry(id) {
+      self.disconnected_destinations.remove(&id);
+      connected_destination.insert(ConnectedDestination {
+        id,
+        name,
+        display_name,
+        destination,
+      });
+    }
+  }
+
+  pub fn get_destination(&self, destination_id: DestinationId) -> Option<&D> {
+    self
+      .connected_destinations
+      .get(&destination_id)
+      .map(|connected_destination| &connected_destination.destination)
+  }
+
+  pub fn remove_destination(&mut self, destination: D) -> Option<ConnectedDestination<D>> {
+    let maybe_id = self
+      .connected_destinations
+      .iter()
+      .find_map(|(id, connected_destination)| {
+        (connected_destination.destination == destination).then(|| *id)
+      });
+
+    maybe_id.and_then(|id| self.remove_destination_by_id(id))
+  }
+
+  pub fn remove_destination_by_id(
+    &mut self,
+    destination_id: DestinationId,
+  ) -> Option<ConnectedDestination<D>> {
+    let maybe_connected_destination = self.connected_destinations.remove(&destination_id);
+
+    maybe_connected_destination.map(|connected_destination| {
+      self.disconnected_destinations.insert(
+        connected_destination.id,
+        DisconnectedDestination {
+          id: connected_destination.id,
+          name: connected_destination.name.clone(),
+        },
+      );
+
+      connected_destination
+    })
+  }
+}
@@ -0,0 +1,857 @@
+use std::collections::hash_map;
+use std::collections::HashMap;
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+
+use parking_lot::Mutex;
+
+use crate::drivers::endpoints::Endpoints;
+use crate::drivers::pending_timer::PendingTimer;
+use crate::drivers::thru::Thru;
+use crate::drivers::Error;
+use crate::endpoints::{SourceId, SourceInfo};
+use crate::event::{Event, TimestampNanos};
+use crate::filter::FilterExpr;
+use crate::input_config::InputConfig;
+use crate::input_handler::InputHandler;
+use crate::input_info::InputInfo;
+use crate::protocol::controllers::ControllerPairing;
+use crate::protocol::decoder::DecoderProtocol2;
+use crate::protocol::duplicates::DuplicateSuppression;
+use crate::protocol::jitter_reduction::JitterReduction;
+use crate::protocol::parameters::ParameterAssembler;
+use crate::protocol::rate_limiter::ControllerRateLimiter;
+use crate::source_match::SourceMatches;
+use crate::transform::{HeldNotes, Transform};
+
+type InputName = String;
+
+/// Events kept for every cycle when merging the sources, delivering them earlier if more arrive
+const MERGE_CAPACITY: usize = 1024;
+
+/// Inputs for the drivers that receive the data from the sources by themselves
+/// (rather than through per-input ports provided by the OS),
+/// so they need to decode, filter and dispatch it to the handlers.
+pub struct Inputs {
+  inputs: HashMap<InputName, Input>,
+  /// Delivers the values kept by the rate limiters when the sources stop sending,
+  /// started with the first input limiting them and shared by the rest
+  timer: Option<PendingTimer>,
+  /// For the timer, when the inputs are shared with the threads of the driver
+  shared: Weak<Mutex<Inputs>>,
+}
+
+struct Input {
+  name: InputName,
+  sources: SourceMatches,
+  jitter_reduction: bool,
+  assemble_parameters: bool,
+  pair_controllers: bool,
+  controller_rate_limit: Option<u32>,
+  duplicate_window: Option<Duration>,
+  connected: HashMap<SourceId, Connection>,
+  delivery: Delivery,
+}
+
+/// Where the events of all the sources go after processing them
+struct Delivery {
+  /// Shared by all the sources, as the duplicates come from different ones
+  duplicates: Option<DuplicateSuppression>,
+  /// Events waiting for the end of the cycle when merging the sources, with the order they arrived in
+  merged: Option<Vec<(usize, Event)>>,
+  handler: InputHandler,
+  thrus: Vec<Thru>,
+}
+
+struct Connection {
+  filter: FilterExpr,
+  transform: Transform,
+  held_notes: HeldNotes,
+  decoder: DecoderProtocol2,
+  jitter_reduction: JitterReduction,
+  parameters: ParameterAssembler,
+  controllers: ControllerPairing,
+  rate_limiter: Option<ControllerRateLimiter>,
+}
+
+impl Connection {
+  /// Delivers the values kept by the rate limiter that are due by `now`.
+  fn deliver_pending(&mut self, source_id: SourceId, now: TimestampNanos, delivery: &mut Delivery) {
+    if let Some(rate_limiter) = self.rate_limiter.as_mut() {
+      while let Some((message, timestamp)) = rate_limiter.next_pending(now) {
+        let event = Event {
+          timestamp,
+          endpoint: source_id,
+          message,
+        };
+        delivery.receive(event);
+      }
+    }
+  }
+}
+
+impl Input {
+  fn connect(&mut self, source_id: SourceId, source_name: &str, display_name: &str) {
+    if let hash_map::Entry::Vacant(entry) = self.connected.entry(source_id) {
+      if let Some((filter, transform)) =
+        self
+          .sources
+          .match_source(source_id, source_name, display_name)
+      {
+        entry.insert(Connection {
+          filter,
+          transform,
+          held_notes: HeldNotes::new(),
+          decoder: DecoderProtocol2::default(),
+          jitter_reduction: JitterReduction::new(),
+          parameters: ParameterAssembler::new(),
+          controllers: ControllerPairing::new(),
+          rate_limiter: self.controller_rate_limit.map(ControllerRateLimiter::new),
+        });
+      }
+    }
+  }
+
+  fn dispatch(&mut self, source_id: SourceId, timestamp: TimestampNanos, ump: &[u32]) {
+    if let Some(connection) = self.connected.get_mut(&source_id) {
+      connection.deliver_pending(source_id, timestamp, &mut self.delivery);
+      for word in ump.iter().cloned() {
+        if let Ok(Some(message)) = connection.decoder.next_matching(word, &connection.filter) {
+          let timestamp = if self.jitter_reduction {
+            connection.jitter_reduction.timestamp(&message, timestamp)
+          } else {
+            timestamp
+          };
+          let message = if self.assemble_parameters {
+            match connection.parameters.process(message) {
+              Some(message) => message,
+              None => continue,
+            }
+          } else {
+            message
+          };
+          let message = if self.pair_controllers {
+            connection.controllers.process(message)
+          } else {
+            message
+          };
+          // After the stages keeping state by channel, so remapping channels does not mix it up
+          for message in connection
+            .transform
+            .apply_held(message, &mut connection.held_notes)
+          {
+            let message = match connection.rate_limiter.as_mut() {
+              Some(rate_limiter) => match rate_limiter.process(message, timestamp) {
+                Some(message) => message,
+                None => continue,
+              },
+              None => message,
+            };
+            let event = Event {
+              timestamp,
+              endpoint: source_id,
+              message,
+            };
+            self.delivery.receive(event);
+          }
+        }
+      }
+    }
+  }
+}
+
+impl Delivery {
+  fn receive(&mut self, event: Event) {
+    match self.merged.as_mut() {
+      Some(merged) if merged.len() < merged.capacity() => merged.push((merged.len(), event)),
+      Some(_) => {
+        self.flush();
+        self.receive(event);
+      }
+      None => self.deliver(event),
+    }
+  }
+
+  /// Delivers the events merged so far, sorted by timestamp and then by arrival.
+  fn flush(&mut self) {
+    if let Some(mut merged) = self.merged.take() {
+      merged.sort_unstable_by_key(|(order, event)| (event.timestamp, *order));
+      for (_, event) in merged.drain(..) {
+        self.deliver(event);
+      }
+      self.merged = Some(merged);
+    }
+  }
+
+  fn deliver(&mut self, event: Event) {
+    if let Some(duplicates) = self.duplicates.as_mut() {
+      if !duplicates.process(&event.message, event.timestamp) {
+        return;
+      }
+    }
+    for thru in self.thrus.iter() {
+      thru.send(&event);
+    }
+    self.handler.call(event);
+  }
+}
+
+impl Inputs {
+  pub fn new() -> Self {
+    Self {
+      inputs: HashMap::new(),
+      timer: None,
+      shared: Weak::new(),
+    }
+  }
+
+  /// Creates the inputs for the drivers dispatching from their own threads,
+  /// which deliver the values kept by the rate limiters from a timer once they are due.
+  pub fn shared() -> Arc<Mutex<Self>> {
+    let inputs = Arc::new(Mutex::new(Self::new()));
+    inputs.lock().shared = Arc::downgrade(&inputs);
+    inputs
+  }
+
+  pub fn create<'a, S>(
+    &mut self,
+    config: InputConfig,
+    handler: InputHandler,
+    available_sources: S,
+  ) -> Result<String, Error>
+  where
+    S: IntoIterator<Item = (SourceId, &'a str, &'a str)>,
+  {
+    if self.inputs.contains_key(config.name.as_str()) {
+      Err(Error::InputAlreadyExists(config))
+    } else {
+      let InputConfig {
+        name,
+        sources,
+        jitter_reduction,
+        assemble_parameters,
+        pair_controllers,
+        controller_rate_limit,
+        duplicate_window,
+        merge_sources,
+      } = config;
+
+      let mut input = Input {
+        name: name.clone(),
+        sources,
+        jitter_reduction,
+        assemble_parameters,
+        pair_controllers,
+        controller_rate_limit,
+        duplicate_window,
+        connected: HashMap::new(),
+        delivery: Delivery {
+          duplicates: duplicate_window
+            .map(|window| DuplicateSuppression::new(window.as_nanos() as TimestampNanos)),
+          merged: merge_sources.then(|| Vec::with_capacity(MERGE_CAPACITY)),
+          handler,
+          thrus: Vec::new(),
+        },
+      };
+
+      for (source_id, source_name, display_name) in available_sources {
+        input.connect(source_id, source_name, display_name);
+      }
+
+      if controller_rate_limit.is_some() && self.timer.is_none() {
+        self.timer = self.spawn_timer();
+      }
+
+      self.inputs.insert(name.clone(), input);
+
+      Ok(name)
+    }
+  }
+
+  pub fn set_sources<'a, S>(
+    &mut self,
+    name: &str,
+    sources: SourceMatches,
+    available_sources: S,
+  ) -> Result<(), Error>
+  where
+    S: IntoIterator<Item = (SourceId, &'a str, &'a str)>,
+  {
+    let input = self
+      .inputs
+      .get_mut(name)
+      .ok_or_else(|| Error::InputNotFound(name.to_string()))?;
+
+    let mut connected = HashMap::with_capacity(input.connected.len());
+    for (source_id, source_name, display_name) in available_sources {
+      if let Some((filter, transform)) = sources.match_source(source_id, source_name, display_name)
+      {
+        let connection = match input.connected.remove(&source_id) {
+          Some(connection) => Connection {
+            filter,
+            transform,
+            ..connection
+          },
+          None => Connection {
+            filter,
+            transform,
+            held_notes: HeldNotes::new(),
+            decoder: DecoderProtocol2::default(),
+            jitter_reduction: JitterReduction::new(),
+            parameters: ParameterAssembler::new(),
+            controllers: ControllerPairing::new(),
+            rate_limiter: input.controller_rate_limit.map(ControllerRateLimiter::new),
+          },
+        };
+        connected.insert(source_id, connection);
+      }
+    }
+
+    input.sources = sources;
+    input.connected = connected;
+
+    Ok(())
+  }
+
+  pub fn remove(&mut self, name: &str) -> Result<(), Error> {
+    self
+      .inputs
+      .remove(name)
+      .map(|_| ())
+      .ok_or_else(|| Error::InputNotFound(name.to_string()))
+  }
+
+  pub fn add_thru(&mut self, name: &str, thru: Thru) -> Result<(), Error> {
+    let input = self
+      .inputs
+      .get_mut(name)
+      .ok_or_else(|| Error::InputNotFound(name.to_string()))?;
+    input.delivery.thrus.push(thru);
+    Ok(())
+  }
+
+  pub fn connect_source(&mut self, source_id: SourceId, source_name: &str, display_name: &str) {
+    for input in self.inputs.values_mut() {
+      input.connect(source_id, source_name, display_name);
+    }
+  }
+
+  pub fn disconnect_source(&mut self, source_id: SourceId) {
+    for input in self.inputs.values_mut() {
+      input.connected.remove(&source_id);
+    }
+  }
+
+  /// Decodes the UMP words received from a source, and sends the resulting events
+  /// to the handlers of the inputs connected to it.
+  ///
+  /// The inputs merging their sources keep the events until the next `flush`.
+  pub fn dispatch(&mut self, source_id: SourceId, timestamp: TimestampNanos, ump: &[u32]) {
+    for input in self.inputs.values_mut() {
+      input.dispatch(source_id, timestamp, ump);
+    }
+    if let Some(timer) = self.timer.as_ref() {
+      if let Some(deadline) = self.next_deadline() {
+        timer.schedule(deadline);
+      }
+    }
+  }
+
+  /// Delivers the values kept by the rate limiters that are due by `now`,
+  /// returning when the next ones are due.
+  pub fn deliver_pending(&mut self, now: TimestampNanos) -> Option<TimestampNanos> {
+    for input in self.inputs.values_mut() {
+      for (source_id, connection) in input.connected.iter_mut() {
+        connection.deliver_pending(*source_id, now, &mut input.delivery);
+      }
+      input.delivery.flush();
+    }
+    self.next_deadline()
+  }
+
+  /// When the next value kept by the rate limiters is due, if any.
+  pub fn next_deadline(&self) -> Option<TimestampNanos> {
+    self
+      .inputs
+      .values()
+      .flat_map(|input| input.connected.values())
+      .filter_map(|connection| connection.rate_limiter.as_ref()?.next_deadline())
+      .min()
+  }
+
+  fn spawn_timer(&self) -> Option<PendingTimer> {
+    let inputs = self.shared.clone();
+    // Not started for the inputs that are not shared, as nothing could deliver through them
+    inputs.upgrade()?;
+    PendingTimer::spawn("rate-limiter", move |now| {
+      inputs.upgrade()?.lock().deliver_pending(now)
+    })
+    .ok()
+  }
+
+  /// Delivers the events kept by the inputs merging their sources, sorted by timestamp,
+  /// which the drivers call at the end of every cycle reading the sources.
+  pub fn flush(&mut self) {
+    for input in self.inputs.values_mut() {
+      input.delivery.flush();
+    }
+  }
+
+  pub fn connected_inputs(&self, source_id: SourceId) -> Vec<String> {
+    self
+      .inputs
+      .values()
+      .filter(|input| input.connected.contains_key(&source_id))
+      .map(|input| input.name.clone())
+      .collect()
+  }
+
+  pub fn source_infos<S, D>(&self, endpoints: &Endpoints<S, D>) -> Vec<SourceInfo>
+  where
+    S: PartialEq,
+    D: PartialEq,
+  {
+    endpoints
+      .connected_sources()
+      .into_iter()
+      .map(|connected_source| {
+        SourceInfo::new(
+          connected_source.id,
+          connected_source.name.clone(),
+          connected_source.display_name.clone(),
+          self.connected_inputs(connected_source.id),
+        )
+      })
+      .collect()
+  }
+
+  pub fn infos(&self) -> Vec<InputInfo> {
+    self
+      .inputs
+      .values()
+      .map(|input| InputInfo {
+        name: input.name.clone(),
+        sources: input.sources.clone(),
+        connected_sources: input.connected.keys().cloned().collect(),
+      })
+      .collect()
+  }
+
+  pub fn config(&self, name: &str) -> Option<InputConfig> {
+    self.inputs.get(name).map(|input| InputConfig {
+      name: input.name.clone(),
+      sources: input.sources.clone(),
+      jitter_reduction: input.jitter_reduction,
+      assemble_parameters: input.assemble_parameters,
+      pair_controllers: input.pair_controllers,
+      controller_rate_limit: input.controller_rate_limit,
+      duplicate_window: input.duplicate_window,
+      merge_sources: input.delivery.merged.is_some(),
+    })
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, Mutex};
+
+  use super::*;
+  use crate::event;
+  use crate::filter::Filter;
+  use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+  use crate::protocol::messages::{Message, MessageType};
+  use crate::source_match::SourceMatch;
+
+  fn recorder() -> (Arc<Mutex<Vec<Event>>>, InputHandler) {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_clone = events.clone();
+    let handler = InputHandler::from(move |event: Event| events_clone.lock().unwrap().push(event));
+    (events, handler)
+  }
+
+  fn note_on(channel: u8) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::NoteOn {
+          note: 0x3c,
+          velocity: 0x64,
+        },
+      }),
+    }
+  }
+
+  #[test]
+  fn create_connects_matching_sources() {
+    let mut inputs = Inputs::new();
+    let (_, handler) = recorder();
+    let config = InputConfig::new("keys").with_source("Keys", Filter::default());
+
+    inputs
+      .create(
+        config,
+        handler,
+        vec![(1, "Keys", "Keys"), (2, "Pads", "Pads")],
+      )
+      .unwrap();
+
+    assert_eq!(inputs.connected_inputs(1), vec!["keys".to_string()]);
+    assert!(inputs.connected_inputs(2).is_empty());
+  }
+
+  #[test]
+  fn sources_match_by_display_name() {
+    let mut inputs = Inputs::new();
+    let (_, handler) = recorder();
+    let config = InputConfig::new("keys")
+      .with_source(SourceMatch::regex("^Keys .*").unwrap(), Filter::default());
+
+    let available_sources = vec![(1, "Port 1", "Keys Port 1"), (2, "Port 1", "Pads Port 1")];
+    inputs.create(config, handler, available_sources).unwrap();
+
+    assert_eq!(inputs.connected_inputs(1), vec!["keys".to_string()]);
+    assert!(inputs.connected_inputs(2).is_empty());
+  }
+
+  #[test]
+  fn create_existing_input_fails() {
+    let mut inputs = Inputs::new();
+    let (_, handler1) = recorder();
+    let (_, handler2) = recorder();
+
+    inputs
+      .create(InputConfig::new("keys"), handler1, vec![])
+      .unwrap();
+    let result = inputs.create(InputConfig::new("keys"), handler2, vec![]);
+
+    assert!(matches!(result, Err(Error::InputAlreadyExists(_))));
+  }
+
+  #[test]
+  fn dispatch_to_connected_inputs() {
+    let mut inputs = Inputs::new();
+    let (keys_events, keys_handler) = recorder();
+    let (pads_events, pads_handler) = recorder();
+    let keys_config = InputConfig::new("keys").with_source("Keys", Filter::default());
+    let pads_config =
+      InputConfig::new("pads").with_source("Pads", Filter::default().with_channels(1, &[10]));
+    let available_sources = vec![(1, "Keys", "Keys"), (2, "Pads", "Pads")];
+
+    inputs
+      .create(keys_config, keys_handler, available_sources.clone())
+      .unwrap();
+    inputs
+      .create(pads_config, pads_handler, available_sources)
+      .unwrap();
+
+    inputs.dispatch(1, 10, &[0x20903c64]);
+    inputs.dispatch(2, 20, &[0x20903c64, 0x20993c64]);
+
+    assert_eq!(
+      keys_events.lock().unwrap().as_slice(),
+      &[Event {
+        timestamp: 10,
+        endpoint: 1,
+        message: note_on(0),
+      }]
+    );
+    assert_eq!(
+      pads_events.lock().unwrap().as_slice(),
+      &[Event {
+        timestamp: 20,
+        endpoint: 2,
+        message: note_on(9),
+      }]
+    );
+  }
+
+  #[test]
+  fn transform_events_by_source() {
+    let mut inputs = Inputs::new();
+    let (events, handler) = recorder();
+    let config = InputConfig::new("keys")
+      .with_source_transform(
+        "Keys",
+        Filter::default(),
+        Transform::new().with_all_channels(1),
+      )
+      .with_source("Pads", Filter::default());
+    let available_sources = vec![(1, "Keys", "Keys"), (2, "Pads", "Pads")];
+    inputs.create(config, handler, available_sources).unwrap();
+
+    inputs.dispatch(1, 10, &[0x20993c64]);
+    inputs.dispatch(2, 20, &[0x20993c64]);
+
+    let messages = events
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|event| event.message)
+      .collect::<Vec<_>>();
+    assert_eq!(messages, vec![note_on(0), note_on(9)]);
+  }
+
+  #[test]
+  fn jitter_reduction_corrects_timestamps() {
+    let mut inputs = Inputs::new();
+    let (plain_events, plain_handler) = recorder();
+    let (corrected_events, corrected_handler) = recorder();
+    let plain_config = InputConfig::new("plain").with_source("Keys", Filter::default());
+    let corrected_config = InputConfig::new("corrected")
+      .with_source("Keys", Filter::default())
+      .with_jitter_reduction(true);
+    let available_sources = vec![(1, "Keys", "Keys")];
+
+    inputs
+      .create(plain_config, plain_handler, available_sources.clone())
+      .unwrap();
+    inputs
+      .create(corrected_config, corrected_handler, available_sources)
+      .unwrap();
+
+    // JR Clock at tick 0, then a note sent 1000 ticks (32ms) later but received 40ms later
+    inputs.dispatch(1, 1_000_000, &[0x0010_0000]);
+    inputs.dispatch(1, 41_000_000, &[0x0020_03e8, 0x20903c64]);
+
+    let timestamps = |events: &Mutex<Vec<Event>>| {
+      events
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|event| event.message == note_on(0))
+        .map(|event| event.timestamp)
+        .collect::<Vec<TimestampNanos>>()
+    };
+    assert_eq!(timestamps(&plain_events), vec![41_000_000]);
+    assert_eq!(timestamps(&corrected_events), vec![33_000_000]);
+    assert_eq!(
+      inputs
+        .config("corrected")
+        .map(|config| config.jitter_reduction),
+      Some(true)
+    );
+  }
+
+  #[test]
+  fn control_changes_are_rate_limited() {
+    let mut inputs = Inputs::new();
+    let (events, handler) = recorder();
+    let config = InputConfig::new("encoders")
+      .with_source("Encoders", Filter::default())
+      .with_controller_rate_limit(100);
+    inputs
+      .create(config, handler, vec![(1, "Encoders", "Encoders")])
+      .unwrap();
+
+    // Controller 1 turned every 2ms, with the last value delivered with the note 10ms after the first one
+    inputs.dispatch(1, 0, &[0x20b0_0140]);
+    inputs.dispatch(1, 2_000_000, &[0x20b0_0141]);
+    inputs.dispatch(1, 4_000_000, &[0x20b0_0142]);
+    inputs.dispatch(1, 20_000_000, &[0x20903c64]);
+
+    let events = events
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|event| (event.timestamp, event.message.mtype))
+      .collect::<Vec<_>>();
+    let control_change = |data: u8| {
+      MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message: ChannelVoice1Message::ControlChange { index: 1, data },
+      })
+    };
+    assert_eq!(
+      events,
+      vec![
+        (0, control_change(0x40)),
+        (10_000_000, control_change(0x42)),
+        (20_000_000, note_on(0).mtype),
+      ]
+    );
+  }
+
+  #[test]
+  fn pending_control_change_delivered_by_the_timer() {
+    let inputs = Inputs::shared();
+    let (events, handler) = recorder();
+    let config = InputConfig::new("encoders")
+      .with_source("Encoders", Filter::default())
+      .with_controller_rate_limit(100);
+    inputs
+      .lock()
+      .create(config, handler, vec![(1, "Encoders", "Encoders")])
+      .unwrap();
+
+    // Nothing else is sent after the second value
+    let now = event::now();
+    inputs.lock().dispatch(1, now, &[0x20b0_0140]);
+    inputs.lock().dispatch(1, now + 2_000_000, &[0x20b0_0141]);
+
+    let start = std::time::Instant::now();
+    while events.lock().unwrap().len() < 2 && start.elapsed() < Duration::from_secs(5) {
+      std::thread::sleep(Duration::from_millis(1));
+    }
+    let timestamps = events
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|event| event.timestamp - now)
+      .collect::<Vec<TimestampNanos>>();
+    assert_eq!(timestamps, vec![0, 10_000_000]);
+    assert_eq!(inputs.lock().next_deadline(), None);
+  }
+
+  #[test]
+  fn timer_shared_by_the_inputs() {
+    let inputs = Inputs::shared();
+    let (encoders_events, encoders_handler) = recorder();
+    let (faders_events, faders_handler) = recorder();
+    let available_sources = vec![(1, "Encoders", "Encoders"), (2, "Faders", "Faders")];
+    for (name, source, handler) in [
+      ("encoders", "Encoders", encoders_handler),
+      ("faders", "Faders", faders_handler),
+    ] {
+      let config = InputConfig::new(name)
+        .with_source(source, Filter::default())
+        .with_controller_rate_limit(100);
+      inputs
+        .lock()
+        .create(config, handler, available_sources.clone())
+        .unwrap();
+    }
+
+    let now = event::now();
+    for source_id in [1, 2] {
+      inputs.lock().dispatch(source_id, now, &[0x20b0_0140]);
+      inputs
+        .lock()
+        .dispatch(source_id, now + 2_000_000, &[0x20b0_0141]);
+    }
+
+    let start = std::time::Instant::now();
+    let delivered = || encoders_events.lock().unwrap().len() + faders_events.lock().unwrap().len();
+    while delivered() < 4 && start.elapsed() < Duration::from_secs(5) {
+      std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(encoders_events.lock().unwrap().len(), 2);
+    assert_eq!(faders_events.lock().unwrap().len(), 2);
+    assert_eq!(inputs.lock().next_deadline(), None);
+  }
+
+  #[test]
+  fn duplicates_are_suppressed() {
+    let mut inputs = Inputs::new();
+    let (events, handler) = recorder();
+    let config = InputConfig::new("keys")
+      .with_source("Keys", Filter::default())
+      .with_source("Keys (bridged)", Filter::default())
+      .with_duplicate_suppression(Duration::from_millis(2));
+    let available_sources = vec![(1, "Keys", "Keys"), (2, "Keys (bridged)", "Keys (bridged)")];
+    inputs.create(config, handler, available_sources).unwrap();
+
+    inputs.dispatch(1, 10_000_000, &[0x20903c64]);
+    inputs.dispatch(2, 10_500_000, &[0x20903c64]);
+    inputs.dispatch(1, 20_000_000, &[0x20903c64]);
+
+    let endpoints = events
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|event| event.endpoint)
+      .collect::<Vec<_>>();
+    assert_eq!(endpoints, vec![1, 1]);
+    assert_eq!(
+      inputs
+        .config("keys")
+        .and_then(|config| config.duplicate_window),
+      Some(Duration::from_millis(2))
+    );
+  }
+
+  #[test]
+  fn merged_sources_are_sorted_by_timestamp() {
+    let mut inputs = Inputs::new();
+    let (events, handler) = recorder();
+    let config = InputConfig::new("keys")
+      .with_source("Keys", Filter::default())
+      .with_source("Pads", Filter::default())
+      .with_source_merging(true);
+    let available_sources = vec![(1, "Keys", "Keys"), (2, "Pads", "Pads")];
+    inputs.create(config, handler, available_sources).unwrap();
+
+    inputs.dispatch(1, 10, &[0x20903c64]);
+    inputs.dispatch(1, 30, &[0x20903e64]);
+    inputs.dispatch(2, 20, &[0x20904064]);
+    inputs.dispatch(2, 30, &[0x20904164]);
+    assert!(events.lock().unwrap().is_empty());
+
+    inputs.flush();
+    let delivered = events
+      .lock()
+      .unwrap()
+      .iter()
+      .map(|event| (event.timestamp, event.endpoint))
+      .collect::<Vec<_>>();
+    assert_eq!(delivered, vec![(10, 1), (20, 2), (30, 1), (30, 2)]);
+    assert_eq!(
+      inputs.config("keys").map(|config| config.merge_sources),
+      Some(true)
+    );
+  }
+
+  #[test]
+  fn disconnected_sources_are_not_dispatched() {
+    let mut inputs = Inputs::new();
+    let (events, handler) = recorder();
+    let config = InputConfig::new("all").with_source(SourceMatch::Id(1), Filter::default());
+
+    inputs
+      .create(config, handler, vec![(1, "Keys", "Keys")])
+      .unwrap();
+    inputs.disconnect_source(1);
+    inputs.dispatch(1, 10, &[0x20903c64]);
+
+    assert!(events.lock().unwrap().is_empty());
+    assert!(inputs.connected_inputs(1).is_empty());
+  }
+
+  #[test]
+  fn set_sources_reconnects() {
+    let mut inputs = Inputs::new();
+    let (events, handler) = recorder();
+    let config = InputConfig::new("input").with_source("Keys", Filter::default());
+    let available_sources = vec![(1, "Keys", "Keys"), (2, "Pads", "Pads")];
+
+    inputs
+      .create(config, handler, available_sources.clone())
+      .unwrap();
+    inputs
+      .set_sources(
+        "input",
+        SourceMatches::default().with_source("Pads", Filter::default()),
+        available_sources,
+      )
+      .unwrap();
+
+    inputs.dispatch(1, 10, &[0x20903c64]);
+    inputs.dispatch(2, 20, &[0x20903c64]);
+
+    assert_eq!(events.lock().unwrap().len(), 1);
+    assert_eq!(events.lock().unwrap()[0].endpoint, 2);
+    assert_eq!(
+      inputs.config("input").map(|config| config.name),
+      Some("input".to_string())
+    );
+  }
+
+  #[test]
+  fn set_sources_for_unknown_input_fails() {
+    let mut inputs = Inputs::new();
+
+    let result = inputs.set_sources("unknown", SourceMatches::default(), vec![]);
+
+    assert!(matches!(result, Err(Error::InputNotFound(name)) if name == "unknown"));
+  }
+}
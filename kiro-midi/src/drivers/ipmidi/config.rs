@@ -0,0 +1,48 @@
+use std::net::Ipv4Addr;
+
+/// ipMIDI ports are numbered from 1, and each one uses its own UDP port starting from 21928.
+pub const MAX_PORTS: u16 = 20;
+
+#[derive(Debug, Clone)]
+pub struct IpMidiConfig {
+  /// Network interface where to join the multicast group
+  pub interface: Ipv4Addr,
+  /// Pairs of ipMIDI port and the UMP group (0 to 15) assigned to its messages
+  pub ports: Vec<(u16, u8)>,
+}
+
+impl IpMidiConfig {
+  pub fn new() -> Self {
+    Self {
+      interface: Ipv4Addr::UNSPECIFIED,
+      ports: Vec::new(),
+    }
+  }
+
+  #[must_use]
+  pub fn with_interface(mut self, interface: Ipv4Addr) -> Self {
+    self.interface = interface;
+    self
+  }
+
+  #[must_use]
+  pub fn with_port(mut self, port: u16, group: u8) -> Self {
+    self.ports.push((port, group));
+    self
+  }
+
+  /// Maps the first ipMIDI ports to consecutive UMP groups
+  #[must_use]
+  pub fn with_ports(mut self, num_ports: u16) -> Self {
+    for port in 1..=num_ports.min(16) {
+      self.ports.push((port, (port - 1) as u8));
+    }
+    self
+  }
+}
+
+impl Default for IpMidiConfig {
+  fn default() -> Self {
+    Self::new()
+  }
+}
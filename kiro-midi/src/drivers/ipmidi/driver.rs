@@ -0,0 +1,203 @@
+use std::io::ErrorKind;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use socket2::{Domain, Protocol, Socket, Type};
+use thiserror::Error;
+
+use crate::drivers;
+use crate::drivers::endpoints;
+use crate::drivers::inputs::Inputs;
+use crate::drivers::ipmidi::config::{IpMidiConfig, MAX_PORTS};
+use crate::drivers::Capabilities;
+use crate::endpoints::{DestinationInfo, EndpointId, SourceId, SourceInfo};
+use crate::event;
+use crate::input_config::InputConfig;
+use crate::input_handler::InputHandler;
+use crate::input_info::InputInfo;
+use crate::protocol::midi1;
+use crate::source_match::SourceMatches;
+
+const MULTICAST_ADDRESS: Ipv4Addr = Ipv4Addr::new(225, 0, 0, 37);
+const BASE_UDP_PORT: u16 = 21928;
+const MAX_DATAGRAM_SIZE: usize = 1500;
+const RECEIVE_TIMEOUT: Duration = Duration::from_millis(100);
+
+type Endpoints = endpoints::Endpoints<u16, u16>;
+
+#[derive(Error, Debug)]
+pub enum IpMidiError {
+  #[error("Invalid ipMIDI port {0}, it should be between 1 and {}", MAX_PORTS)]
+  InvalidPort(u16),
+
+  #[error("Invalid group {1} for the ipMIDI port {0}")]
+  InvalidGroup(u16, u8),
+
+  #[error("Error opening the socket for the ipMIDI port {0}: {1}")]
+  Socket(u16, std::io::Error),
+}
+
+pub struct IpMidiDriver {
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  running: Arc<AtomicBool>,
+  receivers: Vec<JoinHandle<()>>,
+}
+
+impl drivers::DriverSpec for IpMidiDriver {
+  fn create_input<H>(&mut self, config: InputConfig, handler: H) -> Result<String, drivers::Error>
+  where
+    H: Into<InputHandler>,
+  {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .create(config, handler.into(), endpoints.connected_source_names())
+  }
+
+  fn sources(&self) -> Vec<SourceInfo> {
+    let endpoints = self.endpoints.lock();
+    self.inputs.lock().source_infos(&endpoints)
+  }
+
+  fn destinations(&self) -> Vec<DestinationInfo> {
+    self.endpoints.lock().destination_infos()
+  }
+
+  fn inputs(&self) -> Vec<InputInfo> {
+    self.inputs.lock().infos()
+  }
+
+  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
+    self.inputs.lock().config(name)
+  }
+
+  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .set_sources(name, sources, endpoints.connected_source_names())
+  }
+
+  fn remove_input(&self, name: &str) -> Result<(), drivers::Error> {
+    self.inputs.lock().remove(name)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities::default()
+  }
+}
+
+impl IpMidiDriver {
+  /// Creates a driver that joins the ipMIDI multicast sessions for the configured ports.
+  ///
+  /// Every ipMIDI port shows up as both a source and a destination.
+  pub fn new(name: &str, config: IpMidiConfig) -> Result<Self, drivers::Error> {
+    let mut endpoints = Endpoints::new();
+    let inputs = Inputs::shared();
+    let running = Arc::new(AtomicBool::new(true));
+
+    let mut sockets = Vec::with_capacity(config.ports.len());
+    for (port, group) in config.ports.iter().cloned() {
+      if port == 0 || port > MAX_PORTS {
+        return Err(IpMidiError::InvalidPort(port).into());
+      }
+      if group > 0x0f {
+        return Err(IpMidiError::InvalidGroup(port, group).into());
+      }
+      let socket = Self::open_socket(port, config.interface)
+        .map_err(|error| IpMidiError::Socket(port, error))?;
+      sockets.push((port, group, socket));
+    }
+
+    let mut receivers = Vec::with_capacity(sockets.len());
+    for (port, group, socket) in sockets {
+      let endpoint_id = Self::endpoint_id(port);
+      let endpoint_name = format!("ipMIDI Port {}", port);
+      endpoints.add_source(endpoint_id, endpoint_name.clone(), port);
+      endpoints.add_destination(endpoint_id, endpoint_name, port);
+
+      let receiver = Receiver {
+        socket,
+        source_id: endpoint_id,
+        parser: midi1::Parser::new(group),
+        inputs: inputs.clone(),
+        running: running.clone(),
+      };
+
+      let join_handle = std::thread::Builder::new()
+        .name(format!("{}-ipmidi-{}", name, port))
+        .spawn(move || receiver.run())
+        .map_err(|error| IpMidiError::Socket(port, error))?;
+
+      receivers.push(join_handle);
+    }
+
+    Ok(Self {
+      endpoints: Arc::new(Mutex::new(endpoints)),
+      inputs,
+      running,
+      receivers,
+    })
+  }
+
+  fn open_socket(port: u16, interface: Ipv4Addr) -> std::io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    // Other applications in the same host might be using the same ipMIDI ports
+    socket.set_reuse_address(true)?;
+    let address = SocketAddr::from((Ipv4Addr::UNSPECIFIED, BASE_UDP_PORT + port - 1));
+    socket.bind(&address.into())?;
+    socket.join_multicast_v4(&MULTICAST_ADDRESS, &interface)?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_read_timeout(Some(RECEIVE_TIMEOUT))?;
+    Ok(socket.into())
+  }
+
+  fn endpoint_id(port: u16) -> EndpointId {
+    port as EndpointId
+  }
+}
+
+impl Drop for IpMidiDriver {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::Relaxed);
+    for receiver in self.receivers.drain(..) {
+      receiver.join().ok();
+    }
+  }
+}
+
+struct Receiver {
+  socket: UdpSocket,
+  source_id: SourceId,
+  parser: midi1::Parser,
+  inputs: Arc<Mutex<Inputs>>,
+  running: Arc<AtomicBool>,
+}
+
+impl Receiver {
+  fn run(mut self) {
+    let mut buffer = [0u8; MAX_DATAGRAM_SIZE];
+    while self.running.load(Ordering::Relaxed) {
+      match self.socket.recv(&mut buffer) {
+        Ok(len) => {
+          let timestamp = event::now();
+          let source_id = self.source_id;
+          let mut inputs = self.inputs.lock();
+          self.parser.parse(&buffer[..len], |ump| {
+            inputs.dispatch(source_id, timestamp, ump.as_slice())
+          });
+          inputs.flush();
+        }
+        Err(error) if matches!(error.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => {}
+        Err(_) => break,
+      }
+    }
+  }
+}
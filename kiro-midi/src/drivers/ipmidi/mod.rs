@@ -0,0 +1,5 @@
+mod config;
+mod driver;
+
+pub use config::IpMidiConfig;
+pub use driver::{IpMidiDriver, IpMidiError};
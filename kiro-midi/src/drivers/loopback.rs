@@ -0,0 +1,368 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use crate::destination_match::DestinationMatches;
+use crate::drivers;
+use crate::drivers::endpoints;
+use crate::drivers::inputs::Inputs;
+use crate::drivers::outputs::{DestinationSender, Outputs};
+use crate::drivers::Capabilities;
+use crate::endpoints::{DestinationId, DestinationInfo, SourceInfo};
+use crate::event::TimestampNanos;
+use crate::filter::Filter;
+use crate::input_config::InputConfig;
+use crate::input_handler::InputHandler;
+use crate::input_info::InputInfo;
+use crate::output::Output;
+use crate::output_config::OutputConfig;
+use crate::output_connection::OutputConnectionHandler;
+use crate::protocol::messages::Message;
+use crate::source_match::SourceMatches;
+
+type Endpoints = endpoints::Endpoints<(), ()>;
+
+#[derive(Error, Debug)]
+pub enum LoopbackError {
+  #[error("Loopback port already exists: {0}")]
+  PortAlreadyExists(String),
+
+  #[error("Loopback destination not found: {0:016x}")]
+  DestinationNotFound(DestinationId),
+}
+
+/// Pure software driver where the data sent to a destination is received from its paired source.
+///
+/// It allows to connect components in the same process (a sequencer with an instrument, for example)
+/// without going through the MIDI stack of the OS.
+pub struct LoopbackDriver {
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  outputs: Mutex<Outputs>,
+}
+
+impl drivers::DriverSpec for LoopbackDriver {
+  fn create_input<H>(&mut self, config: InputConfig, handler: H) -> Result<String, drivers::Error>
+  where
+    H: Into<InputHandler>,
+  {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .create(config, handler.into(), endpoints.connected_source_names())
+  }
+
+  fn sources(&self) -> Vec<SourceInfo> {
+    let endpoints = self.endpoints.lock();
+    self.inputs.lock().source_infos(&endpoints)
+  }
+
+  fn destinations(&self) -> Vec<DestinationInfo> {
+    self.endpoints.lock().destination_infos()
+  }
+
+  fn inputs(&self) -> Vec<InputInfo> {
+    self.inputs.lock().infos()
+  }
+
+  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
+    self.inputs.lock().config(name)
+  }
+
+  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .set_sources(name, sources, endpoints.connected_source_names())
+  }
+
+  fn remove_input(&self, name: &str) -> Result<(), drivers::Error> {
+    self.inputs.lock().remove(name)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      output: true,
+      ump_native: true,
+      hotplug: true,
+      ..Capabilities::default()
+    }
+  }
+
+  fn create_output(&mut self, config: OutputConfig) -> Result<Output, drivers::Error> {
+    let (output, pending_ranges) = {
+      let endpoints = self.endpoints.lock();
+      let mut outputs = self.outputs.lock();
+      let output = outputs.create(config, endpoints.connected_destination_names())?;
+      (output, outputs.take_pending_ranges())
+    };
+    pending_ranges.send();
+    Ok(output)
+  }
+
+  fn set_output_destinations(
+    &self,
+    name: &str,
+    destinations: DestinationMatches,
+  ) -> Result<(), drivers::Error> {
+    let pending_ranges = {
+      let endpoints = self.endpoints.lock();
+      let mut outputs = self.outputs.lock();
+      outputs.set_destinations(name, destinations, endpoints.connected_destination_names())?;
+      outputs.take_pending_ranges()
+    };
+    pending_ranges.send();
+    Ok(())
+  }
+  fn set_output_connection_handler(&mut self, handler: OutputConnectionHandler) {
+    self.outputs.lock().set_connection_handler(handler);
+  }
+
+  fn set_broadcast_filter(&mut self, filter: Filter) {
+    self.outputs.lock().set_broadcast_filter(filter);
+  }
+
+  fn broadcast(&self, message: Message) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    let destinations = endpoints
+      .connected_destinations()
+      .into_iter()
+      .map(|destination| destination.id);
+    self.outputs.lock().broadcast_message(message, destinations);
+    Ok(())
+  }
+
+  fn panic_all(&self) {
+    self.outputs.lock().panic_all();
+  }
+}
+
+impl LoopbackDriver {
+  pub fn new(_name: &str) -> Self {
+    let inputs = Inputs::shared();
+    let sender = InputsSender {
+      inputs: inputs.clone(),
+    };
+    Self {
+      endpoints: Arc::new(Mutex::new(Endpoints::new())),
+      inputs,
+      outputs: Mutex::new(Outputs::new(sender)),
+    }
+  }
+
+  /// Creates a pair of destination and source with the same name and id.
+  pub fn add_port(&mut self, name: &str) -> Result<DestinationId, drivers::Error> {
+    let id = endpoints::hashed_id(name);
+    let mut endpoints = self.endpoints.lock();
+    if endpoints.get_source(id).is_some() {
+      return Err(LoopbackError::PortAlreadyExists(name.to_string()).into());
+    }
+    endpoints.add_source(id, name.to_string(), ());
+    endpoints.add_destination(id, name.to_string(), ());
+    self.inputs.lock().connect_source(id, name, name);
+    let pending_ranges = {
+      let mut outputs = self.outputs.lock();
+      outputs.connect_destination(id, name, name);
+      outputs.take_pending_ranges()
+    };
+    drop(endpoints);
+    pending_ranges.send();
+    Ok(id)
+  }
+
+  pub fn remove_port(&mut self, id: DestinationId) -> Result<(), drivers::Error> {
+    let mut endpoints = self.endpoints.lock();
+    endpoints
+      .remove_destination_by_id(id)
+      .ok_or(LoopbackError::DestinationNotFound(id))?;
+    endpoints.remove_source_by_id(id);
+    self.inputs.lock().disconnect_source(id);
+    self.outputs.lock().disconnect_destination(id);
+    Ok(())
+  }
+
+  /// Sends UMP words to a destination, which are received by the inputs connected to its paired source.
+  pub fn send(
+    &self,
+    destination: DestinationId,
+    timestamp: TimestampNanos,
+    ump: &[u32],
+  ) -> Result<(), drivers::Error> {
+    self.sender(destination)?.send(timestamp, ump);
+    Ok(())
+  }
+
+  /// Returns a handle to send data to a destination from other threads.
+  pub fn sender(&self, destination: DestinationId) -> Result<LoopbackSender, drivers::Error> {
+    if self.endpoints.lock().get_source(destination).is_some() {
+      Ok(LoopbackSender {
+        destination,
+        inputs: self.inputs.clone(),
+      })
+    } else {
+      Err(LoopbackError::DestinationNotFound(destination).into())
+    }
+  }
+}
+
+/// Sends data to a loopback destination.
+///
+/// Once the port is removed, the data sent is just dropped.
+#[derive(Clone)]
+pub struct LoopbackSender {
+  destination: DestinationId,
+  inputs: Arc<Mutex<Inputs>>,
+}
+
+impl LoopbackSender {
+  pub fn destination(&self) -> DestinationId {
+    self.destination
+  }
+
+  pub fn send(&self, timestamp: TimestampNanos, ump: &[u32]) {
+    let mut inputs = self.inputs.lock();
+    inputs.dispatch(self.destination, timestamp, ump);
+    inputs.flush();
+  }
+}
+
+/// Sends the data from the outputs to the inputs connected to the paired sources
+struct InputsSender {
+  inputs: Arc<Mutex<Inputs>>,
+}
+
+impl DestinationSender for InputsSender {
+  fn send(&self, destination: DestinationId, timestamp: TimestampNanos, ump: &[u32]) {
+    let mut inputs = self.inputs.lock();
+    inputs.dispatch(destination, timestamp, ump);
+    inputs.flush();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::mpsc;
+
+  use super::*;
+  use crate::drivers::DriverSpec;
+  use crate::event::Event;
+  use crate::filter::Filter;
+  use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+  use crate::protocol::messages::{Message, MessageType};
+
+  fn receiver(driver: &mut LoopbackDriver, port: &str) -> mpsc::Receiver<Event> {
+    let (sender, receiver) = mpsc::channel();
+    let config = InputConfig::new(port).with_source(port, Filter::default());
+    driver
+      .create_input(config, move |event| sender.send(event).unwrap())
+      .unwrap();
+    receiver
+  }
+
+  #[test]
+  fn sent_data_is_received_from_the_paired_source() {
+    let mut driver = LoopbackDriver::new("test");
+    let sequencer = driver.add_port("sequencer").unwrap();
+    let other = driver.add_port("other").unwrap();
+    let events = receiver(&mut driver, "sequencer");
+
+    driver.send(sequencer, 10, &[0x2090_3c64]).unwrap();
+    driver.send(other, 20, &[0x2090_3d64]).unwrap();
+
+    let event = events.try_recv().unwrap();
+    assert_eq!(event.timestamp, 10);
+    assert_eq!(event.endpoint, sequencer);
+    assert!(events.try_recv().is_err());
+  }
+
+  #[test]
+  fn ports_are_both_sources_and_destinations() {
+    let mut driver = LoopbackDriver::new("test");
+    let port = driver.add_port("sequencer").unwrap();
+
+    let sources = driver.sources();
+    let destinations = driver.destinations();
+    assert_eq!(sources.len(), 1);
+    assert_eq!(destinations.len(), 1);
+    assert_eq!(sources[0].id, port);
+    assert_eq!(destinations[0].id, port);
+    assert_eq!(destinations[0].name, "sequencer");
+  }
+
+  #[test]
+  fn add_existing_port_fails() {
+    let mut driver = LoopbackDriver::new("test");
+    driver.add_port("sequencer").unwrap();
+
+    let result = driver.add_port("sequencer");
+
+    assert!(matches!(
+      result,
+      Err(drivers::Error::Loopback(LoopbackError::PortAlreadyExists(
+        _
+      )))
+    ));
+  }
+
+  #[test]
+  fn sender_from_another_thread() {
+    let mut driver = LoopbackDriver::new("test");
+    let port = driver.add_port("sequencer").unwrap();
+    let events = receiver(&mut driver, "sequencer");
+
+    let sender = driver.sender(port).unwrap();
+    std::thread::spawn(move || sender.send(0, &[0x2090_3c64]))
+      .join()
+      .unwrap();
+
+    assert!(events.try_recv().is_ok());
+  }
+
+  #[test]
+  fn outputs_send_to_the_paired_sources() {
+    let mut driver = LoopbackDriver::new("test");
+    let events = receiver(&mut driver, "sequencer");
+    let output = driver
+      .create_output(OutputConfig::new("out").with_destination("sequencer"))
+      .unwrap();
+    assert!(output.connected_destinations().is_empty());
+
+    let port = driver.add_port("sequencer").unwrap();
+    output.send(Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message: ChannelVoice1Message::NoteOn {
+          note: 0x3c,
+          velocity: 0x64,
+        },
+      }),
+    });
+
+    assert_eq!(output.connected_destinations(), vec![port]);
+    assert_eq!(events.try_recv().unwrap().endpoint, port);
+  }
+
+  #[test]
+  fn removed_port() {
+    let mut driver = LoopbackDriver::new("test");
+    let port = driver.add_port("sequencer").unwrap();
+    let events = receiver(&mut driver, "sequencer");
+    let sender = driver.sender(port).unwrap();
+
+    driver.remove_port(port).unwrap();
+    sender.send(0, &[0x2090_3c64]);
+
+    assert!(events.try_recv().is_err());
+    assert!(driver.sources().is_empty());
+    assert!(matches!(
+      driver.send(port, 0, &[0x2090_3c64]),
+      Err(drivers::Error::Loopback(
+        LoopbackError::DestinationNotFound(_)
+      ))
+    ));
+  }
+}
@@ -0,0 +1,278 @@
+use std::collections::{hash_map, HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use midir_crate::{Ignore, MidiInput, MidiInputConnection, MidiInputPort, MidiOutput};
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use crate::drivers;
+use crate::drivers::endpoints;
+use crate::drivers::inputs::Inputs;
+use crate::drivers::Capabilities;
+use crate::endpoints::{DestinationInfo, SourceId, SourceInfo};
+use crate::event::{self, TimestampNanos};
+use crate::input_config::InputConfig;
+use crate::input_handler::InputHandler;
+use crate::input_info::InputInfo;
+use crate::protocol::midi1;
+use crate::source_match::SourceMatches;
+
+const SCAN_INTERVAL: Duration = Duration::from_secs(1);
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+type Endpoints = endpoints::Endpoints<(), ()>;
+
+#[derive(Error, Debug)]
+pub enum MidirError {
+  #[error("Error initialising midir: {0}")]
+  Init(midir_crate::InitError),
+
+  #[error("Error creating the scanner thread: {0}")]
+  Thread(std::io::Error),
+}
+
+/// Fallback driver for the platforms without a native one, built on top of midir.
+///
+/// midir does not notify about the ports being added or removed, so they are scanned
+/// periodically, and every input port is connected while available. The bytes received
+/// are parsed as MIDI 1.0 into UMP words for group 0, and the ids are derived from the
+/// port names.
+pub struct MidirDriver {
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  running: Arc<AtomicBool>,
+  scanner: Option<JoinHandle<()>>,
+}
+
+impl drivers::DriverSpec for MidirDriver {
+  fn create_input<H>(&mut self, config: InputConfig, handler: H) -> Result<String, drivers::Error>
+  where
+    H: Into<InputHandler>,
+  {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .create(config, handler.into(), endpoints.connected_source_names())
+  }
+
+  fn sources(&self) -> Vec<SourceInfo> {
+    let endpoints = self.endpoints.lock();
+    self.inputs.lock().source_infos(&endpoints)
+  }
+
+  fn destinations(&self) -> Vec<DestinationInfo> {
+    self.endpoints.lock().destination_infos()
+  }
+
+  fn inputs(&self) -> Vec<InputInfo> {
+    self.inputs.lock().infos()
+  }
+
+  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
+    self.inputs.lock().config(name)
+  }
+
+  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .set_sources(name, sources, endpoints.connected_source_names())
+  }
+
+  fn remove_input(&self, name: &str) -> Result<(), drivers::Error> {
+    self.inputs.lock().remove(name)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      hotplug: true,
+      ..Capabilities::default()
+    }
+  }
+}
+
+impl MidirDriver {
+  pub fn new(name: &str) -> Result<Self, drivers::Error> {
+    // Fail early when the backend is not available
+    MidiInput::new(name).map_err(MidirError::Init)?;
+
+    let endpoints = Arc::new(Mutex::new(Endpoints::new()));
+    let inputs = Inputs::shared();
+    let running = Arc::new(AtomicBool::new(true));
+
+    let mut scanner = Scanner {
+      name: name.to_string(),
+      endpoints: endpoints.clone(),
+      inputs: inputs.clone(),
+      running: running.clone(),
+      connections: HashMap::new(),
+    };
+    scanner.scan();
+
+    let scanner = std::thread::Builder::new()
+      .name(format!("{}-midir", name))
+      .spawn(move || scanner.run())
+      .map_err(MidirError::Thread)?;
+
+    Ok(Self {
+      endpoints,
+      inputs,
+      running,
+      scanner: Some(scanner),
+    })
+  }
+}
+
+impl Drop for MidirDriver {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::Relaxed);
+    if let Some(scanner) = self.scanner.take() {
+      scanner.join().ok();
+    }
+  }
+}
+
+struct Scanner {
+  name: String,
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  running: Arc<AtomicBool>,
+  connections: HashMap<SourceId, MidiInputConnection<()>>,
+}
+
+impl Scanner {
+  fn run(mut self) {
+    let mut last_scan = Instant::now();
+    while self.running.load(Ordering::Relaxed) {
+      std::thread::sleep(POLL_INTERVAL);
+      if last_scan.elapsed() >= SCAN_INTERVAL {
+        last_scan = Instant::now();
+        self.scan();
+      }
+    }
+
+    for (_, connection) in self.connections.drain() {
+      connection.close();
+    }
+  }
+
+  fn scan(&mut self) {
+    if let Ok(midi_input) = MidiInput::new(self.name.as_str()) {
+      let ports = midi_input
+        .ports()
+        .into_iter()
+        .filter_map(|port| {
+          let name = midi_input.port_name(&port).ok()?;
+          Some((endpoints::hashed_id(name.as_str()), name, port))
+        })
+        .collect::<Vec<(SourceId, String, MidiInputPort)>>();
+      self.update_sources(ports);
+    }
+
+    if let Ok(midi_output) = MidiOutput::new(self.name.as_str()) {
+      let ports = midi_output
+        .ports()
+        .into_iter()
+        .filter_map(|port| {
+          let name = midi_output.port_name(&port).ok()?;
+          Some((endpoints::hashed_id(name.as_str()), name))
+        })
+        .collect::<Vec<(SourceId, String)>>();
+      self.update_destinations(ports);
+    }
+  }
+
+  fn update_sources(&mut self, ports: Vec<(SourceId, String, MidiInputPort)>) {
+    let available = ports
+      .iter()
+      .map(|(id, _, _)| *id)
+      .collect::<HashSet<SourceId>>();
+    let removed = self
+      .connections
+      .keys()
+      .filter(|id| !available.contains(id))
+      .cloned()
+      .collect::<Vec<SourceId>>();
+    for id in removed {
+      if let Some(connection) = self.connections.remove(&id) {
+        connection.close();
+      }
+      self.endpoints.lock().remove_source_by_id(id);
+      self.inputs.lock().disconnect_source(id);
+    }
+
+    for (id, name, port) in ports {
+      if let hash_map::Entry::Vacant(entry) = self.connections.entry(id) {
+        if let Some(connection) = Self::connect(&self.name, &self.inputs, id, name.as_str(), &port)
+        {
+          entry.insert(connection);
+          let mut endpoints = self.endpoints.lock();
+          self
+            .inputs
+            .lock()
+            .connect_source(id, name.as_str(), name.as_str());
+          endpoints.add_source(id, name, ());
+        }
+      }
+    }
+  }
+
+  fn update_destinations(&mut self, ports: Vec<(SourceId, String)>) {
+    let available = ports
+      .iter()
+      .map(|(id, _)| *id)
+      .collect::<HashSet<SourceId>>();
+    let mut endpoints = self.endpoints.lock();
+    let removed = endpoints
+      .connected_destinations()
+      .into_iter()
+      .map(|connected_destination| connected_destination.id)
+      .filter(|id| !available.contains(id))
+      .collect::<Vec<SourceId>>();
+    for id in removed {
+      endpoints.remove_destination_by_id(id);
+    }
+    for (id, name) in ports {
+      endpoints.add_destination(id, name, ());
+    }
+  }
+
+  /// Connects to an input port, using a new client, as midir consumes it for every connection.
+  fn connect(
+    client_name: &str,
+    inputs: &Arc<Mutex<Inputs>>,
+    source_id: SourceId,
+    name: &str,
+    port: &MidiInputPort,
+  ) -> Option<MidiInputConnection<()>> {
+    let mut midi_input = MidiInput::new(client_name).ok()?;
+    midi_input.ignore(Ignore::None);
+
+    let inputs = inputs.clone();
+    let mut parser = midi1::Parser::new(0);
+    // The timestamps of midir start with every connection, so they are moved to the shared clock
+    let mut offset = None;
+    midi_input
+      .connect(
+        port,
+        name,
+        move |timestamp_micros, bytes, _| {
+          let timestamp = timestamp_micros as TimestampNanos * 1000;
+          let offset = *offset.get_or_insert_with(|| event::now().saturating_sub(timestamp));
+          let timestamp = timestamp + offset;
+          let mut inputs = inputs.lock();
+          parser.parse(bytes, |ump| {
+            inputs.dispatch(source_id, timestamp, ump.as_slice())
+          });
+          inputs.flush();
+        },
+        (),
+      )
+      .ok()
+  }
+}
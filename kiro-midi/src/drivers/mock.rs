@@ -0,0 +1,923 @@
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::destination_match::DestinationMatches;
+use crate::drivers;
+use crate::drivers::endpoints;
+use crate::drivers::inputs::Inputs;
+use crate::drivers::outputs::{DestinationSender, Outputs};
+use crate::drivers::thru::Thru;
+use crate::drivers::Capabilities;
+use crate::endpoints::{DestinationId, DestinationInfo, SourceId, SourceInfo};
+use crate::event::{Event, TimestampNanos};
+use crate::filter::Filter;
+use crate::identity::{identity_request, Identities, ALL_DEVICES};
+use crate::input_config::InputConfig;
+use crate::input_handler::InputHandler;
+use crate::input_info::InputInfo;
+use crate::midi_ci::negotiation::MidiProtocol;
+use crate::midi_ci::profile::ProfileId;
+use crate::midi_ci::property::PropertyResponse;
+use crate::midi_ci::{CiAddress, CiDevice, DeviceInfo, MidiCi};
+use crate::output::Output;
+use crate::output_config::OutputConfig;
+use crate::output_connection::OutputConnectionHandler;
+use crate::protocol::messages::Message;
+use crate::protocol::midi1;
+use crate::source_match::SourceMatches;
+use crate::transform::Transform;
+
+type Endpoints = endpoints::Endpoints<(), ()>;
+
+/// An event delivered to the handler of an input
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeliveredEvent {
+  pub input: String,
+  pub event: Event,
+}
+
+/// UMP words sent by an output to a destination
+#[derive(Debug, Clone, PartialEq)]
+pub struct SentEvent {
+  pub destination: DestinationId,
+  pub timestamp: TimestampNanos,
+  pub ump: Vec<u32>,
+}
+
+/// In-memory driver to test the routing of inputs without any MIDI environment.
+///
+/// Sources and destinations are added and removed by hand, and the data pushed
+/// from a source is decoded, filtered and delivered to the connected inputs like
+/// any other driver would do. Every delivered event is also recorded, so tests can
+/// check what the handlers received, and the same for the data sent by the outputs.
+pub struct MockDriver {
+  endpoints: Endpoints,
+  inputs: Mutex<Inputs>,
+  outputs: Mutex<Outputs>,
+  midi_ci: Mutex<MidiCi>,
+  identities: Mutex<Identities>,
+  delivered: Arc<Mutex<Vec<DeliveredEvent>>>,
+  sent: Arc<Mutex<Vec<SentEvent>>>,
+}
+
+impl drivers::DriverSpec for MockDriver {
+  fn create_input<H>(&mut self, config: InputConfig, handler: H) -> Result<String, drivers::Error>
+  where
+    H: Into<InputHandler>,
+  {
+    let input = config.name.clone();
+    let delivered = self.delivered.clone();
+    let mut handler = handler.into();
+    let recorder = move |event: Event| {
+      delivered.lock().push(DeliveredEvent {
+        input: input.clone(),
+        event: event.clone(),
+      });
+      handler.call(event);
+    };
+
+    self.inputs.lock().create(
+      config,
+      recorder.into(),
+      self.endpoints.connected_source_names(),
+    )
+  }
+
+  fn sources(&self) -> Vec<SourceInfo> {
+    let identities = self.identities.lock();
+    let mut sources = self.inputs.lock().source_infos(&self.endpoints);
+    for source in sources.iter_mut() {
+      source.identity = identities.identity(source.id);
+    }
+    sources
+  }
+
+  fn destinations(&self) -> Vec<DestinationInfo> {
+    self.endpoints.destination_infos()
+  }
+
+  fn inputs(&self) -> Vec<InputInfo> {
+    self.inputs.lock().infos()
+  }
+
+  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
+    self.inputs.lock().config(name)
+  }
+
+  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+    self
+      .inputs
+      .lock()
+      .set_sources(name, sources, self.endpoints.connected_source_names())
+  }
+
+  fn remove_input(&self, name: &str) -> Result<(), drivers::Error> {
+    self.inputs.lock().remove(name)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      output: true,
+      ump_native: true,
+      hotplug: true,
+      ..Capabilities::default()
+    }
+  }
+
+  fn create_output(&mut self, config: OutputConfig) -> Result<Output, drivers::Error> {
+    let mut outputs = self.outputs.lock();
+    let output = outputs.create(config, self.endpoints.connected_destination_names())?;
+    let pending_ranges = outputs.take_pending_ranges();
+    drop(outputs);
+    pending_ranges.send();
+    Ok(output)
+  }
+
+  fn set_output_destinations(
+    &self,
+    name: &str,
+    destinations: DestinationMatches,
+  ) -> Result<(), drivers::Error> {
+    let mut outputs = self.outputs.lock();
+    outputs.set_destinations(
+      name,
+      destinations,
+      self.endpoints.connected_destination_names(),
+    )?;
+    let pending_ranges = outputs.take_pending_ranges();
+    drop(outputs);
+    pending_ranges.send();
+    Ok(())
+  }
+  fn set_output_connection_handler(&mut self, handler: OutputConnectionHandler) {
+    self.outputs.lock().set_connection_handler(handler);
+  }
+
+  fn set_broadcast_filter(&mut self, filter: Filter) {
+    self.outputs.lock().set_broadcast_filter(filter);
+  }
+
+  fn broadcast(&self, message: Message) -> Result<(), drivers::Error> {
+    let destinations = self
+      .endpoints
+      .connected_destinations()
+      .into_iter()
+      .map(|destination| destination.id);
+    self.outputs.lock().broadcast_message(message, destinations);
+    Ok(())
+  }
+
+  fn panic_all(&self) {
+    self.outputs.lock().panic_all();
+  }
+
+  fn create_thru(
+    &mut self,
+    input: &str,
+    output: &str,
+    transform: Transform,
+  ) -> Result<(), drivers::Error> {
+    let output = self.outputs.lock().get(output)?;
+    self
+      .inputs
+      .lock()
+      .add_thru(input, Thru::new(output, transform))
+  }
+
+  fn identify_sources(&self) -> Result<(), drivers::Error> {
+    let destinations = self
+      .endpoints
+      .connected_destinations()
+      .into_iter()
+      .map(|destination| destination.id);
+    self
+      .outputs
+      .lock()
+      .broadcast_sysex(&identity_request(ALL_DEVICES), destinations);
+    Ok(())
+  }
+
+  fn discover_ci_devices(&self) -> Result<(), drivers::Error> {
+    let destinations = self
+      .endpoints
+      .connected_destinations()
+      .into_iter()
+      .map(|destination| destination.id);
+    let discovery = self.midi_ci.lock().discovery();
+    self
+      .outputs
+      .lock()
+      .broadcast_sysex(&discovery, destinations);
+    Ok(())
+  }
+
+  fn ci_devices(&self) -> Vec<CiDevice> {
+    self.midi_ci.lock().devices()
+  }
+
+  fn ci_profile_inquiry(&self, source: SourceId, address: CiAddress) -> Result<(), drivers::Error> {
+    let request = self.midi_ci.lock().profile_inquiry(source, address);
+    self.send_ci(source, request.map(|request| vec![request]))
+  }
+
+  fn set_ci_profile(
+    &self,
+    source: SourceId,
+    address: CiAddress,
+    profile: ProfileId,
+    enabled: bool,
+  ) -> Result<(), drivers::Error> {
+    let request = self
+      .midi_ci
+      .lock()
+      .set_profile(source, address, profile, enabled);
+    self.send_ci(source, request.map(|request| vec![request]))
+  }
+
+  fn negotiate_ci_protocol(
+    &self,
+    source: SourceId,
+    protocol: MidiProtocol,
+  ) -> Result<(), drivers::Error> {
+    let request = self.midi_ci.lock().negotiate_protocol(source, protocol);
+    self.send_ci(source, request.map(|request| vec![request]))
+  }
+
+  fn get_ci_property(
+    &self,
+    source: SourceId,
+    resource: &str,
+  ) -> Result<PropertyResponse, drivers::Error> {
+    let request = self.midi_ci.lock().get_property(source, resource);
+    self.send_ci_property(source, request)
+  }
+
+  fn set_ci_property(
+    &self,
+    source: SourceId,
+    resource: &str,
+    data: &[u8],
+  ) -> Result<PropertyResponse, drivers::Error> {
+    let request = self.midi_ci.lock().set_property(source, resource, data);
+    self.send_ci_property(source, request)
+  }
+}
+
+impl MockDriver {
+  pub fn new(_name: &str) -> Self {
+    let sent = Arc::new(Mutex::new(Vec::new()));
+    Self {
+      endpoints: Endpoints::new(),
+      inputs: Mutex::new(Inputs::new()),
+      outputs: Mutex::new(Outputs::new(Recorder { sent: sent.clone() })),
+      midi_ci: Mutex::new(MidiCi::new(DeviceInfo::default())),
+      identities: Mutex::new(Identities::new()),
+      delivered: Arc::new(Mutex::new(Vec::new())),
+      sent,
+    }
+  }
+
+  /// Adds a source and connects it to the inputs matching it.
+  ///
+  /// The id is derived from the name, so the same name always gets the same id.
+  pub fn add_source(&mut self, name: &str) -> SourceId {
+    let source_id = endpoints::hashed_id(name);
+    self.inputs.lock().connect_source(source_id, name, name);
+    self.endpoints.add_source(source_id, name.to_string(), ());
+    source_id
+  }
+
+  pub fn remove_source(&mut self, source_id: SourceId) {
+    self.endpoints.remove_source_by_id(source_id);
+    self.inputs.lock().disconnect_source(source_id);
+    self.midi_ci.lock().remove_source(source_id);
+    self.identities.lock().remove_source(source_id);
+  }
+
+  /// Adds a destination and connects it to the outputs matching it.
+  pub fn add_destination(&mut self, name: &str) -> DestinationId {
+    let destination_id = endpoints::hashed_id(name);
+    self
+      .endpoints
+      .add_destination(destination_id, name.to_string(), ());
+    let pending_ranges = {
+      let mut outputs = self.outputs.lock();
+      outputs.connect_destination(destination_id, name, name);
+      outputs.take_pending_ranges()
+    };
+    pending_ranges.send();
+    destination_id
+  }
+
+  pub fn remove_destination(&mut self, destination_id: DestinationId) {
+    self.endpoints.remove_destination_by_id(destination_id);
+    self.outputs.lock().disconnect_destination(destination_id);
+  }
+
+  /// Pushes UMP words as if they were received from a source.
+  ///
+  /// The MIDI-CI messages are answered through the destination paired with the source.
+  pub fn push(&mut self, source_id: SourceId, timestamp: TimestampNanos, ump: &[u32]) {
+    {
+      let mut inputs = self.inputs.lock();
+      inputs.dispatch(source_id, timestamp, ump);
+      inputs.flush();
+    }
+    self.identities.lock().receive(source_id, ump);
+    self.receive_ci(source_id, ump);
+  }
+
+  /// Pushes MIDI 1.0 bytes as if they were received from a source, using group 0.
+  pub fn push_midi1(&mut self, source_id: SourceId, timestamp: TimestampNanos, bytes: &[u8]) {
+    let mut words = Vec::new();
+    midi1::Parser::new(0).parse(bytes, |ump| words.extend_from_slice(ump.as_slice()));
+    self.push(source_id, timestamp, &words);
+  }
+
+  /// Events delivered to the input handlers so far, in order.
+  pub fn delivered(&self) -> Vec<DeliveredEvent> {
+    self.delivered.lock().clone()
+  }
+
+  /// Returns the events delivered so far, and forgets about them.
+  pub fn take_delivered(&mut self) -> Vec<DeliveredEvent> {
+    std::mem::take(&mut *self.delivered.lock())
+  }
+
+  /// Data sent by the outputs so far, in order.
+  pub fn sent(&self) -> Vec<SentEvent> {
+    self.sent.lock().clone()
+  }
+
+  /// Returns the data sent so far, and forgets about it.
+  pub fn take_sent(&mut self) -> Vec<SentEvent> {
+    std::mem::take(&mut *self.sent.lock())
+  }
+
+  /// Sends the messages of a MIDI-CI request to the destination paired with the source of the device.
+  fn send_ci(
+    &self,
+    source_id: SourceId,
+    messages: Option<Vec<Vec<u8>>>,
+  ) -> Result<(), drivers::Error> {
+    let destination = self.endpoints.paired_destination(source_id);
+    match messages.zip(destination) {
+      Some((messages, destination)) => {
+        let outputs = self.outputs.lock();
+        for message in messages {
+          outputs.broadcast_sysex(&message, Some(destination));
+        }
+        Ok(())
+      }
+      None => Err(drivers::Error::CiDeviceNotFound(source_id)),
+    }
+  }
+
+  fn send_ci_property(
+    &self,
+    source_id: SourceId,
+    request: Option<(Vec<Vec<u8>>, PropertyResponse)>,
+  ) -> Result<PropertyResponse, drivers::Error> {
+    let (messages, response) = request.ok_or(drivers::Error::CiDeviceNotFound(source_id))?;
+    self.send_ci(source_id, Some(messages))?;
+    Ok(response)
+  }
+
+  fn receive_ci(&self, source_id: SourceId, ump: &[u32]) {
+    let destination = self.endpoints.paired_destination(source_id);
+    let outputs = self.outputs.lock();
+    self.midi_ci.lock().receive(source_id, ump, |reply| {
+      outputs.broadcast_sysex(reply, destination)
+    });
+  }
+}
+
+struct Recorder {
+  sent: Arc<Mutex<Vec<SentEvent>>>,
+}
+
+impl DestinationSender for Recorder {
+  fn send(&self, destination: DestinationId, timestamp: TimestampNanos, ump: &[u32]) {
+    self.sent.lock().push(SentEvent {
+      destination,
+      timestamp,
+      ump: ump.to_vec(),
+    });
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::atomic::{AtomicUsize, Ordering};
+
+  use super::*;
+  use crate::drivers::DriverSpec;
+  use crate::filter::{Filter, FilterExpr};
+  use crate::identity::IdentityReply;
+  use crate::midi_ci::profile::ProfileState;
+  use crate::protocol::encoder::encode_sysex7;
+  use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+  use crate::protocol::messages::{Message, MessageType};
+
+  fn note_on(channel: u8, note: u8) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::NoteOn {
+          note,
+          velocity: 0x64,
+        },
+      }),
+    }
+  }
+
+  #[test]
+  fn sources_and_destinations() {
+    let mut driver = MockDriver::new("test");
+    let keys = driver.add_source("Keys");
+    let pads = driver.add_source("Pads");
+    let synth = driver.add_destination("Synth");
+
+    driver
+      .create_input(
+        InputConfig::new("keys").with_source("Keys", Filter::default()),
+        |_| {},
+      )
+      .unwrap();
+    driver.remove_source(pads);
+
+    let sources = driver.sources();
+    assert_eq!(sources.len(), 1);
+    assert_eq!(sources[0].id, keys);
+    assert_eq!(sources[0].name, "Keys");
+    assert_eq!(sources[0].connected_inputs, vec!["keys".to_string()]);
+
+    let destinations = driver.destinations();
+    assert_eq!(destinations.len(), 1);
+    assert_eq!(destinations[0].id, synth);
+  }
+
+  #[test]
+  fn push_delivers_to_matching_inputs() {
+    let mut driver = MockDriver::new("test");
+    let keys = driver.add_source("Keys");
+    let pads = driver.add_source("Pads");
+
+    let calls = Arc::new(AtomicUsize::new(0));
+    let calls_clone = calls.clone();
+    driver
+      .create_input(
+        InputConfig::new("keys").with_source("Keys", Filter::default()),
+        move |_| {
+          calls_clone.fetch_add(1, Ordering::Relaxed);
+        },
+      )
+      .unwrap();
+
+    driver.push(keys, 10, &[0x2090_3c64]);
+    driver.push(pads, 20, &[0x2090_3d64]);
+
+    assert_eq!(calls.load(Ordering::Relaxed), 1);
+    assert_eq!(
+      driver.take_delivered(),
+      vec![DeliveredEvent {
+        input: "keys".to_string(),
+        event: Event {
+          timestamp: 10,
+          endpoint: keys,
+          message: note_on(0, 0x3c),
+        },
+      }]
+    );
+    assert!(driver.delivered().is_empty());
+  }
+
+  #[test]
+  fn push_midi1_with_running_status() {
+    let mut driver = MockDriver::new("test");
+    let keys = driver.add_source("Keys");
+    driver
+      .create_input(
+        InputConfig::new("all").with_all_sources(Filter::default()),
+        |_| {},
+      )
+      .unwrap();
+
+    driver.push_midi1(keys, 0, &[0x91, 0x3c, 0x64, 0x3e, 0x64]);
+
+    let messages = driver
+      .delivered()
+      .into_iter()
+      .map(|delivered| delivered.event.message)
+      .collect::<Vec<_>>();
+    assert_eq!(messages, vec![note_on(1, 0x3c), note_on(1, 0x3e)]);
+  }
+
+  #[test]
+  fn sources_added_later_are_connected() {
+    let mut driver = MockDriver::new("test");
+    driver
+      .create_input(
+        InputConfig::new("keys").with_source("Keys", Filter::default()),
+        |_| {},
+      )
+      .unwrap();
+
+    let keys = driver.add_source("Keys");
+    driver.push(keys, 0, &[0x2090_3c64]);
+    assert_eq!(driver.take_delivered().len(), 1);
+
+    driver.remove_source(keys);
+    driver.push(keys, 0, &[0x2090_3c64]);
+    assert!(driver.delivered().is_empty());
+  }
+
+  #[test]
+  fn set_input_sources_reconnects() {
+    let mut driver = MockDriver::new("test");
+    let keys = driver.add_source("Keys");
+    let pads = driver.add_source("Pads");
+    driver
+      .create_input(
+        InputConfig::new("input").with_source("Keys", Filter::default()),
+        |_| {},
+      )
+      .unwrap();
+
+    let sources = InputConfig::new("input")
+      .with_source("Pads", Filter::default())
+      .sources;
+    driver.set_input_sources("input", sources).unwrap();
+
+    driver.push(keys, 0, &[0x2090_3c64]);
+    driver.push(pads, 0, &[0x2090_3d64]);
+
+    let delivered = driver.delivered();
+    assert_eq!(delivered.len(), 1);
+    assert_eq!(delivered[0].event.endpoint, pads);
+  }
+
+  #[test]
+  fn update_source_matches() {
+    let mut driver = MockDriver::new("test");
+    let keys = driver.add_source("Keys");
+    let pads = driver.add_source("Pads");
+    driver
+      .create_input(
+        InputConfig::new("input").with_source("Keys", Filter::default()),
+        |_| {},
+      )
+      .unwrap();
+
+    driver
+      .update_filter("input", 0, Filter::new().with_mtypes(&[0x04]).into())
+      .unwrap();
+    driver.push(keys, 0, &[0x2090_3c64]);
+    assert!(driver.take_delivered().is_empty());
+
+    let index = driver
+      .add_source_match("input", "Pads".into(), Filter::default().into())
+      .unwrap();
+    assert_eq!(index, 1);
+    driver.push(pads, 0, &[0x2090_3d64]);
+    assert_eq!(driver.take_delivered().len(), 1);
+
+    driver.remove_source_match("input", 1).unwrap();
+    driver.push(pads, 0, &[0x2090_3d64]);
+    assert!(driver.take_delivered().is_empty());
+    assert!(matches!(
+      driver.remove_source_match("input", 1),
+      Err(drivers::Error::SourceMatchNotFound(name, 1)) if name == "input"
+    ));
+    assert!(matches!(
+      driver.update_filter("unknown", 0, FilterExpr::default()),
+      Err(drivers::Error::InputNotFound(_))
+    ));
+  }
+
+  #[test]
+  fn outputs_send_to_matching_destinations() {
+    let mut driver = MockDriver::new("test");
+    let synth = driver.add_destination("Synth");
+    driver.add_destination("Drums");
+    let output = driver
+      .create_output(OutputConfig::new("synth").with_destination("Synth"))
+      .unwrap();
+
+    output.send(note_on(0, 0x3c));
+    driver.remove_destination(synth);
+    output.send(note_on(0, 0x3e));
+
+    assert_eq!(
+      driver.take_sent(),
+      vec![SentEvent {
+        destination: synth,
+        timestamp: 0,
+        ump: vec![0x2090_3c64],
+      }]
+    );
+    assert!(driver.sent().is_empty());
+  }
+
+  #[test]
+  fn set_output_destinations_reconnects() {
+    let mut driver = MockDriver::new("test");
+    driver.add_destination("Synth");
+    let drums = driver.add_destination("Drums");
+    let output = driver
+      .create_output(OutputConfig::new("output").with_destination("Synth"))
+      .unwrap();
+
+    driver
+      .set_output_destinations(
+        "output",
+        DestinationMatches::default().with_destination("Drums"),
+      )
+      .unwrap();
+    output.send(note_on(0, 0x3c));
+
+    let sent = driver.sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].destination, drums);
+  }
+
+  #[test]
+  fn thru_sends_transformed_events() {
+    let mut driver = MockDriver::new("test");
+    let keys = driver.add_source("Keys");
+    let synth = driver.add_destination("Synth");
+    driver
+      .create_input(
+        InputConfig::new("keys").with_source("Keys", Filter::default()),
+        |_| {},
+      )
+      .unwrap();
+    driver
+      .create_output(OutputConfig::new("synth").with_destination("Synth"))
+      .unwrap();
+    driver
+      .create_thru("keys", "synth", Transform::new().with_channel(1, 10))
+      .unwrap();
+
+    driver.push(keys, 10, &[0x2090_3c64]);
+
+    assert_eq!(driver.delivered().len(), 1);
+    assert_eq!(
+      driver.sent(),
+      vec![SentEvent {
+        destination: synth,
+        timestamp: 0,
+        ump: vec![0x2099_3c64],
+      }]
+    );
+    assert!(matches!(
+      driver.create_thru("unknown", "synth", Transform::default()),
+      Err(drivers::Error::InputNotFound(_))
+    ));
+  }
+
+  #[test]
+  fn panic_all_outputs() {
+    let mut driver = MockDriver::new("test");
+    let synth = driver.add_destination("Synth");
+    driver
+      .create_output(
+        OutputConfig::new("synth")
+          .with_destination("Synth")
+          .with_filter(Filter::new().with_channels(1, &[1])),
+      )
+      .unwrap();
+
+    driver.panic_all();
+
+    let sent = driver.take_sent();
+    assert_eq!(sent.len(), 16 * 16 * 3);
+    assert!(sent.iter().all(|event| event.destination == synth));
+    assert_eq!(
+      sent[..3]
+        .iter()
+        .map(|event| event.ump.clone())
+        .collect::<Vec<_>>(),
+      vec![vec![0x20b0_7b00], vec![0x20b0_7800], vec![0x20b0_4000]]
+    );
+    assert_eq!(sent[47].ump, vec![0x20bf_4000]);
+    assert_eq!(sent[48].ump, vec![0x21b0_7b00]);
+    assert_eq!(sent[767].ump, vec![0x2fbf_4000]);
+  }
+
+  #[test]
+  fn panic_transformed() {
+    let mut driver = MockDriver::new("test");
+    driver.add_destination("Synth");
+    driver
+      .create_output(
+        OutputConfig::new("synth")
+          .with_destination("Synth")
+          .with_transform(Transform::new().with_group(1, 3)),
+      )
+      .unwrap();
+
+    driver.panic_all();
+
+    let sent = driver.take_sent();
+    assert_eq!(sent.len(), 16 * 16 * 3);
+    assert_eq!(sent[0].ump, vec![0x22b0_7b00]);
+    let group_3 = sent
+      .iter()
+      .filter(|event| event.ump[0] >> 24 == 0x22)
+      .count();
+    assert_eq!(group_3, 2 * 16 * 3);
+  }
+
+  #[test]
+  fn broadcast_to_all_destinations() {
+    let mut driver = MockDriver::new("test");
+    let synth = driver.add_destination("Synth");
+    let drums = driver.add_destination("Drums");
+
+    driver.broadcast(note_on(0, 0x3c)).unwrap();
+
+    let mut destinations = driver
+      .sent()
+      .into_iter()
+      .map(|event| event.destination)
+      .collect::<Vec<_>>();
+    destinations.sort_unstable();
+    let mut expected = vec![synth, drums];
+    expected.sort_unstable();
+    assert_eq!(destinations, expected);
+  }
+
+  #[test]
+  fn broadcast_filtered() {
+    let mut driver = MockDriver::new("test");
+    driver.add_destination("Synth");
+    driver.set_broadcast_filter(Filter::new().with_channels(1, &[2]));
+
+    driver.broadcast(note_on(0, 0x3c)).unwrap();
+    assert!(driver.sent().is_empty());
+
+    driver.broadcast(note_on(1, 0x3c)).unwrap();
+    assert_eq!(driver.sent().len(), 1);
+  }
+
+  #[test]
+  fn identify_sources() {
+    let mut driver = MockDriver::new("test");
+    let source = driver.add_source("Synth");
+    let destination = driver.add_destination("Synth");
+
+    driver.identify_sources().unwrap();
+    let sent = driver.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].destination, destination);
+    assert_eq!(driver.sources()[0].identity, None);
+
+    let info = DeviceInfo {
+      manufacturer: [0x43, 0x00, 0x00],
+      family: 0x41,
+      model: 0x0a,
+      version: [0, 1, 0, 0],
+    };
+    let reply = IdentityReply { device: 0x10, info };
+    let mut words = Vec::new();
+    encode_sysex7(0, &reply.to_sysex(), |ump| {
+      words.extend_from_slice(ump.as_slice())
+    });
+    driver.push(source, 0, &words);
+    assert_eq!(driver.sources()[0].identity, Some(info));
+
+    driver.remove_source(source);
+    let source = driver.add_source("Synth");
+    assert_eq!(driver.sources()[0].id, source);
+    assert_eq!(driver.sources()[0].identity, None);
+  }
+
+  #[test]
+  fn midi_ci_discovery() {
+    let mut driver = MockDriver::new("test");
+    let source = driver.add_source("Synth");
+    let destination = driver.add_destination("Synth");
+    driver.add_destination("Drums");
+
+    driver.discover_ci_devices().unwrap();
+    let sent = driver.take_sent();
+    assert_eq!(sent.len(), 2 * 5);
+
+    // Another driver plays the remote device answering to the discovery
+    let mut device = MockDriver::new("device");
+    let device_source = device.add_source("Host");
+    device.add_destination("Host");
+    for event in sent.iter().filter(|event| event.destination == destination) {
+      device.push(device_source, 0, &event.ump);
+    }
+    let replies = device.take_sent();
+    assert!(!replies.is_empty());
+    assert_eq!(device.ci_devices().len(), 1);
+
+    for event in replies {
+      driver.push(source, 0, &event.ump);
+    }
+    let devices = driver.ci_devices();
+    assert_eq!(devices.len(), 1);
+    assert_eq!(devices[0].source, source);
+    assert_eq!(devices[0].muid, device.midi_ci.lock().muid());
+    assert!(driver.sent().is_empty());
+  }
+
+  #[test]
+  fn midi_ci_profiles() {
+    let mut driver = MockDriver::new("test");
+    let source = driver.add_source("Synth");
+    let destination = driver.add_destination("Synth");
+    let profile = ProfileId([0x7e, 0x31, 0x00, 0x01, 0x01]);
+
+    assert!(matches!(
+      driver.ci_profile_inquiry(source, CiAddress::FunctionBlock),
+      Err(drivers::Error::CiDeviceNotFound(_))
+    ));
+
+    let device = MidiCi::new(DeviceInfo::default());
+    let mut ump = Vec::new();
+    encode_sysex7(0, &device.discovery(), |packet| {
+      ump.extend_from_slice(packet.as_slice())
+    });
+    driver.push(source, 0, &ump);
+    driver.take_sent();
+
+    driver
+      .set_ci_profile(source, CiAddress::Channel(0), profile, true)
+      .unwrap();
+    assert!(driver
+      .sent()
+      .iter()
+      .all(|event| event.destination == destination));
+    assert_eq!(
+      driver.ci_devices()[0].profiles[0].state,
+      ProfileState::Enabling
+    );
+  }
+
+  #[test]
+  fn midi_ci_protocol_negotiation() {
+    let mut driver = MockDriver::new("test");
+    let source = driver.add_source("Synth");
+    driver.add_destination("Synth");
+    let mut device = MockDriver::new("device");
+    let device_source = device.add_source("Host");
+    device.add_destination("Host");
+
+    driver.discover_ci_devices().unwrap();
+    driver
+      .negotiate_ci_protocol(source, MidiProtocol::Midi2)
+      .unwrap_err();
+    for event in driver.take_sent() {
+      device.push(device_source, 0, &event.ump);
+    }
+    for event in device.take_sent() {
+      driver.push(source, 0, &event.ump);
+    }
+
+    driver
+      .negotiate_ci_protocol(source, MidiProtocol::Midi2)
+      .unwrap();
+    while !driver.sent().is_empty() {
+      for event in driver.take_sent() {
+        device.push(device_source, 0, &event.ump);
+      }
+      for event in device.take_sent() {
+        driver.push(source, 0, &event.ump);
+      }
+    }
+    assert_eq!(driver.ci_devices()[0].protocol, MidiProtocol::Midi2);
+    assert_eq!(device.ci_devices()[0].protocol, MidiProtocol::Midi2);
+  }
+
+  #[test]
+  fn midi_ci_properties() {
+    let mut driver = MockDriver::new("test");
+    let source = driver.add_source("Synth");
+    driver.add_destination("Synth");
+
+    assert!(matches!(
+      driver.get_ci_property(source, "DeviceInfo"),
+      Err(drivers::Error::CiDeviceNotFound(_))
+    ));
+
+    let device = MidiCi::new(DeviceInfo::default());
+    let mut ump = Vec::new();
+    encode_sysex7(0, &device.discovery(), |packet| {
+      ump.extend_from_slice(packet.as_slice())
+    });
+    driver.push(source, 0, &ump);
+    driver.take_sent();
+
+    let response = driver
+      .set_ci_property(source, "ProgramList", b"[]")
+      .unwrap();
+    assert!(!driver.sent().is_empty());
+    assert!(response.try_take().is_none());
+  }
+}
@@ -1,22 +1,138 @@
-#[cfg(target_os = "macos")]
+mod aggregate;
+#[cfg(feature = "blemidi")]
+mod blemidi;
+mod capabilities;
+#[cfg(all(target_os = "macos", feature = "coremidi"))]
 mod coremidi;
+#[cfg(feature = "ipmidi")]
+mod ipmidi;
+mod loopback;
+#[cfg(feature = "midir")]
+mod midir;
+mod mock;
+#[cfg(feature = "proxy")]
+mod proxy;
+#[cfg(feature = "serial")]
+mod serial;
+#[cfg(feature = "shm")]
+mod shm;
+#[cfg(all(target_arch = "wasm32", feature = "webmidi"))]
+mod webmidi;
 
-#[cfg(target_os = "macos")]
+mod endpoints;
+mod inputs;
+mod outputs;
+mod pending_timer;
+mod thru;
+
+pub use crate::drivers::aggregate::AggregateDriver;
+#[cfg(feature = "blemidi")]
+pub use crate::drivers::blemidi::{BleMidiDriver, BleMidiError};
+pub use crate::drivers::capabilities::Capabilities;
+#[cfg(all(target_os = "macos", feature = "coremidi"))]
+pub(crate) use crate::drivers::coremidi::host_time_nanos;
+#[cfg(all(target_os = "macos", feature = "coremidi"))]
 use crate::drivers::coremidi::{CoreMidiDriver, CoreMidiError};
+#[cfg(feature = "ipmidi")]
+pub use crate::drivers::ipmidi::{IpMidiConfig, IpMidiDriver, IpMidiError};
+pub use crate::drivers::loopback::{LoopbackDriver, LoopbackError, LoopbackSender};
+#[cfg(feature = "midir")]
+pub use crate::drivers::midir::{MidirDriver, MidirError};
+pub use crate::drivers::mock::{DeliveredEvent, MockDriver, SentEvent};
+#[cfg(feature = "proxy")]
+pub use crate::drivers::proxy::{ProxyDriver, ProxyError, ProxyServer};
+#[cfg(feature = "serial")]
+pub use crate::drivers::serial::{SerialConfig, SerialDriver, SerialError};
+#[cfg(feature = "shm")]
+pub use crate::drivers::shm::{SharedMemoryConfig, SharedMemoryDriver, SharedMemoryError};
+#[cfg(all(target_arch = "wasm32", feature = "webmidi"))]
+use crate::drivers::webmidi::{WebMidiDriver, WebMidiError};
 
 use thiserror::Error;
 
 #[derive(Error, Debug)]
 pub enum Error {
-  #[cfg(target_os = "macos")]
+  #[error("An input with this name already exists: {0:?}")]
+  InputAlreadyExists(InputConfig),
+
+  #[error("Input not found: {0}")]
+  InputNotFound(String),
+
+  #[error("Source match {1} not found in the input: {0}")]
+  SourceMatchNotFound(String, usize),
+
+  #[error("Virtual endpoints are not supported by this driver")]
+  VirtualEndpointsNotSupported,
+
+  #[error("An output with this name already exists: {0:?}")]
+  OutputAlreadyExists(Box<OutputConfig>),
+
+  #[error("Output not found: {0}")]
+  OutputNotFound(String),
+
+  #[error("Outputs are not supported by this driver")]
+  OutputsNotSupported,
+
+  #[error("Thru connections are not supported by this driver")]
+  ThruNotSupported,
+
+  #[error("MIDI-CI is not supported by this driver")]
+  MidiCiNotSupported,
+
+  #[error("MIDI-CI device not found for the source: {0}")]
+  CiDeviceNotFound(SourceId),
+
+  #[error("Identifying the sources is not supported by this driver")]
+  IdentityNotSupported,
+
+  #[cfg(all(target_os = "macos", feature = "coremidi"))]
   #[error("CoreMidi: {0}")]
   CoreMidi(#[from] CoreMidiError),
+
+  #[cfg(all(target_arch = "wasm32", feature = "webmidi"))]
+  #[error("WebMidi: {0}")]
+  WebMidi(#[from] WebMidiError),
+
+  #[cfg(feature = "blemidi")]
+  #[error("BleMidi: {0}")]
+  BleMidi(#[from] BleMidiError),
+
+  #[cfg(feature = "ipmidi")]
+  #[error("IpMidi: {0}")]
+  IpMidi(#[from] IpMidiError),
+
+  #[error("Loopback: {0}")]
+  Loopback(#[from] LoopbackError),
+
+  #[cfg(feature = "midir")]
+  #[error("Midir: {0}")]
+  Midir(#[from] MidirError),
+
+  #[cfg(feature = "proxy")]
+  #[error("Proxy: {0}")]
+  Proxy(#[from] ProxyError),
+
+  #[cfg(feature = "serial")]
+  #[error("Serial: {0}")]
+  Serial(#[from] SerialError),
+
+  #[cfg(feature = "shm")]
+  #[error("SharedMemory: {0}")]
+  SharedMemory(#[from] SharedMemoryError),
 }
 
 use enum_dispatch::enum_dispatch;
 
-use crate::endpoints::{DestinationInfo, SourceInfo};
-use crate::{InputConfig, InputHandler, InputInfo, SourceMatches};
+use crate::endpoints::{DestinationInfo, EndpointId, SourceId, SourceInfo};
+use crate::midi_ci::negotiation::MidiProtocol;
+use crate::midi_ci::profile::ProfileId;
+use crate::midi_ci::property::PropertyResponse;
+use crate::midi_ci::{CiAddress, CiDevice};
+use crate::protocol::messages::Message;
+use crate::{
+  DestinationMatches, Filter, FilterExpr, InputConfig, InputHandler, InputInfo, Output,
+  OutputConfig, OutputConnectionHandler, SourceMatch, SourceMatches, Transform,
+};
 
 #[enum_dispatch(Driver)]
 pub trait DriverSpec {
@@ -28,15 +144,209 @@ pub trait DriverSpec {
   fn inputs(&self) -> Vec<InputInfo>;
   fn get_input_config(&self, name: &str) -> Option<InputConfig>;
   fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), Error>;
+  /// Removes an input, disconnecting its sources and dropping its handler.
+  fn remove_input(&self, name: &str) -> Result<(), Error>;
+  fn capabilities(&self) -> Capabilities;
+
+  /// Adds a source match to an input, after the ones it has, returning its index.
+  ///
+  /// Like with `set_input_sources`, only the sources changing their match are connected or disconnected,
+  /// and the rest keep their connections and the state of their decoders.
+  fn add_source_match(
+    &self,
+    name: &str,
+    source_match: SourceMatch,
+    filter: FilterExpr,
+  ) -> Result<usize, Error> {
+    let mut sources = self.input_sources(name)?;
+    sources.add_source(source_match, filter);
+    let index = sources.len() - 1;
+    self.set_input_sources(name, sources).map(|_| index)
+  }
+
+  /// Removes the source match at `index` from an input, disconnecting the sources not matching any other.
+  ///
+  /// The source matches after it move one position back.
+  fn remove_source_match(&self, name: &str, index: usize) -> Result<(), Error> {
+    let mut sources = self.input_sources(name)?;
+    sources
+      .remove(index)
+      .ok_or_else(|| Error::SourceMatchNotFound(name.to_string(), index))?;
+    self.set_input_sources(name, sources)
+  }
+
+  /// Replaces the filter of the source match at `index` of an input, keeping its sources connected.
+  fn update_filter(&self, name: &str, index: usize, filter: FilterExpr) -> Result<(), Error> {
+    let mut sources = self.input_sources(name)?;
+    if !sources.set_filter(index, filter) {
+      return Err(Error::SourceMatchNotFound(name.to_string(), index));
+    }
+    self.set_input_sources(name, sources)
+  }
+
+  /// The source matches of an input.
+  fn input_sources(&self, name: &str) -> Result<SourceMatches, Error> {
+    self
+      .get_input_config(name)
+      .map(|config| config.sources)
+      .ok_or_else(|| Error::InputNotFound(name.to_string()))
+  }
+
+  /// Creates a source advertised to other applications, returning its id.
+  fn create_virtual_source(&mut self, _name: &str) -> Result<EndpointId, Error> {
+    Err(Error::VirtualEndpointsNotSupported)
+  }
+
+  /// Creates a destination advertised to other applications, returning the id of the source
+  /// that the inputs can connect to in order to receive the data sent to it.
+  fn create_virtual_destination(&mut self, _name: &str) -> Result<SourceId, Error> {
+    Err(Error::VirtualEndpointsNotSupported)
+  }
+
+  /// Creates an output that sends to the destinations matching the config, connecting
+  /// and disconnecting them as they come and go.
+  fn create_output(&mut self, _config: OutputConfig) -> Result<Output, Error> {
+    Err(Error::OutputsNotSupported)
+  }
+
+  /// Changes the destinations of an output, reconnecting it to the ones matching them.
+  fn set_output_destinations(
+    &self,
+    _name: &str,
+    _destinations: DestinationMatches,
+  ) -> Result<(), Error> {
+    Err(Error::OutputsNotSupported)
+  }
+
+  /// Sets the handler called when the outputs of the driver connect or disconnect destinations.
+  fn set_output_connection_handler(&mut self, _handler: OutputConnectionHandler) {}
+
+  /// Sets the filter for the messages sent with `broadcast`, which accepts all of them by default.
+  fn set_broadcast_filter(&mut self, _filter: Filter) {}
+
+  /// Sends a message to all the destinations available, useful for clock, transport or resets.
+  ///
+  /// The messages rejected by the filter from `set_broadcast_filter` are not sent.
+  fn broadcast(&self, _message: Message) -> Result<(), Error> {
+    Err(Error::OutputsNotSupported)
+  }
+
+  /// Calls `Output::panic` for all the outputs created by the driver.
+  fn panic_all(&self) {}
+
+  /// Sends the events received by an input to an output, applying the transform to them.
+  ///
+  /// The events are sent from the thread receiving them, without going through the handler
+  /// of the input, so the output must not feed back into the same input.
+  fn create_thru(
+    &mut self,
+    _input: &str,
+    _output: &str,
+    _transform: Transform,
+  ) -> Result<(), Error> {
+    Err(Error::ThruNotSupported)
+  }
+
+  /// Sends an identity request to all the destinations.
+  ///
+  /// The devices answer through the source paired with the destination, and their identity
+  /// is available in the `SourceInfo` once the replies are received.
+  fn identify_sources(&self) -> Result<(), Error> {
+    Err(Error::IdentityNotSupported)
+  }
+
+  /// Sends a MIDI-CI discovery to all the destinations.
+  ///
+  /// The devices answer through the source paired with the destination, and can be found
+  /// with `ci_devices` once the replies are received.
+  fn discover_ci_devices(&self) -> Result<(), Error> {
+    Err(Error::MidiCiNotSupported)
+  }
+
+  /// The MIDI-CI devices found so far, which are the sources that support MIDI 2.0.
+  fn ci_devices(&self) -> Vec<CiDevice> {
+    Vec::new()
+  }
+
+  /// Asks the MIDI-CI device behind a source for its profiles,
+  /// which are available in the `CiDevice` once it replies.
+  fn ci_profile_inquiry(&self, _source: SourceId, _address: CiAddress) -> Result<(), Error> {
+    Err(Error::MidiCiNotSupported)
+  }
+
+  /// Asks the MIDI-CI device behind a source to enable or disable one of its profiles.
+  fn set_ci_profile(
+    &self,
+    _source: SourceId,
+    _address: CiAddress,
+    _profile: ProfileId,
+    _enabled: bool,
+  ) -> Result<(), Error> {
+    Err(Error::MidiCiNotSupported)
+  }
+
+  /// Negotiates the protocol to use with the MIDI-CI device behind a source,
+  /// which is available in the `CiDevice` once established.
+  ///
+  /// The negotiation messages are sent as MIDI-CI 1.1, as 1.2 deprecated them. The inputs keep decoding
+  /// the source as before, since their decoder takes both the MIDI 1.0 and the MIDI 2.0 channel voice packets.
+  fn negotiate_ci_protocol(&self, _source: SourceId, _protocol: MidiProtocol) -> Result<(), Error> {
+    Err(Error::MidiCiNotSupported)
+  }
+
+  /// Asks the MIDI-CI device behind a source for a resource through property exchange,
+  /// such as `DeviceInfo`, `ChannelList` or `ProgramList`.
+  ///
+  /// The response can be awaited, or waited for from a thread that can block.
+  fn get_ci_property(&self, _source: SourceId, _resource: &str) -> Result<PropertyResponse, Error> {
+    Err(Error::MidiCiNotSupported)
+  }
+
+  /// Sets a resource of the MIDI-CI device behind a source through property exchange,
+  /// sending the data in as many chunks as needed.
+  fn set_ci_property(
+    &self,
+    _source: SourceId,
+    _resource: &str,
+    _data: &[u8],
+  ) -> Result<PropertyResponse, Error> {
+    Err(Error::MidiCiNotSupported)
+  }
 }
 
 #[enum_dispatch]
 pub enum Driver {
-  #[cfg(target_os = "macos")]
+  AggregateDriver,
+  #[cfg(all(target_os = "macos", feature = "coremidi"))]
   CoreMidiDriver,
+  #[cfg(all(target_arch = "wasm32", feature = "webmidi"))]
+  WebMidiDriver,
+  #[cfg(feature = "blemidi")]
+  BleMidiDriver,
+  #[cfg(feature = "ipmidi")]
+  IpMidiDriver,
+  LoopbackDriver,
+  #[cfg(feature = "midir")]
+  MidirDriver,
+  MockDriver,
+  #[cfg(feature = "proxy")]
+  ProxyDriver,
+  #[cfg(feature = "serial")]
+  SerialDriver,
+  #[cfg(feature = "shm")]
+  SharedMemoryDriver,
 }
 
-#[cfg(target_os = "macos")]
+#[cfg(all(target_os = "macos", feature = "coremidi"))]
 pub fn create(name: &str) -> Result<Driver, Error> {
   CoreMidiDriver::new(name).map(Into::into)
 }
+
+/// Creates a driver for the Web MIDI API.
+///
+/// The browser might ask the user for permission to access the MIDI devices,
+/// that's why the creation needs to be asynchronous.
+#[cfg(all(target_arch = "wasm32", feature = "webmidi"))]
+pub async fn create(name: &str) -> Result<Driver, Error> {
+  WebMidiDriver::new(name).await.map(Into::into)
+}
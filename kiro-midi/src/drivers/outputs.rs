@@ -0,0 +1,712 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::destination_match::{DestinationMatches, DestinationRemap};
+use crate::drivers::Error;
+use crate::endpoints::DestinationId;
+use crate::event::TimestampNanos;
+use crate::filter::Filter;
+use crate::output::{Output, OutputSink};
+use crate::output_config::OutputConfig;
+use crate::output_connection::{OutputConnection, OutputConnectionHandler};
+use crate::protocol::encoder::{encode_message, encode_sysex7, MAX_UMP_WORDS};
+use crate::protocol::messages::Message;
+
+type OutputName = String;
+
+/// Sends UMP words to a destination of a driver
+pub trait DestinationSender: Send + Sync {
+  fn send(&self, destination: DestinationId, timestamp: TimestampNanos, ump: &[u32]);
+}
+
+/// Outputs for the drivers, which keeps track of the destinations connected to every output
+/// and fans out the data sent through them to a `DestinationSender`.
+///
+/// The pitch bend ranges for the destinations connected are not sent right away, but kept until
+/// the driver takes them with `take_pending_ranges`, as its sender might need the locks held meanwhile.
+pub struct Outputs {
+  outputs: HashMap<OutputName, (Arc<OutputState>, Output)>,
+  sender: Arc<dyn DestinationSender>,
+  connection_handler: Option<OutputConnectionHandler>,
+  pending_ranges: Vec<PendingRange>,
+  broadcast_filter: Filter,
+}
+
+type PendingRange = (Arc<OutputState>, Output, DestinationId);
+
+/// The pitch bend ranges left to send to the destinations connected to the outputs.
+#[must_use = "the pitch bend ranges are only sent with `PendingRanges::send`"]
+#[derive(Default)]
+pub struct PendingRanges(Vec<PendingRange>);
+
+impl PendingRanges {
+  /// Sends them to their destinations only, once the driver released the locks that its sender takes.
+  pub fn send(self) {
+    for (state, output, destination_id) in self.0 {
+      output.pitch_bend_range(&mut |message| {
+        state.send_to(destination_id, 0, encode_message(&message).as_slice())
+      });
+    }
+  }
+}
+
+type Connected = Vec<(DestinationId, DestinationRemap)>;
+
+struct OutputState {
+  destinations: Mutex<DestinationMatches>,
+  /// Replaced rather than modified, so sending only takes a reference to it
+  connected: Mutex<Arc<Connected>>,
+  sender: Arc<dyn DestinationSender>,
+}
+
+impl OutputState {
+  /// Returns whether the destination was connected.
+  fn connect(
+    &self,
+    destination_id: DestinationId,
+    destination_name: &str,
+    display_name: &str,
+  ) -> bool {
+    let mut connected = self.connected.lock();
+    if connected.iter().any(|(id, _)| *id == destination_id) {
+      return false;
+    }
+    let remap =
+      self
+        .destinations
+        .lock()
+        .match_remap(destination_id, destination_name, display_name);
+    if let Some(remap) = remap {
+      let mut updated = Connected::clone(&connected);
+      updated.push((destination_id, remap));
+      *connected = Arc::new(updated);
+    }
+    remap.is_some()
+  }
+
+  /// Returns whether the destination was disconnected.
+  fn disconnect(&self, destination_id: DestinationId) -> bool {
+    let mut connected = self.connected.lock();
+    if connected
+      .iter()
+      .all(|(connected_id, _)| *connected_id != destination_id)
+    {
+      return false;
+    }
+    let updated = connected
+      .iter()
+      .filter(|(connected_id, _)| *connected_id != destination_id)
+      .cloned()
+      .collect();
+    *connected = Arc::new(updated);
+    true
+  }
+
+  /// Sends the words to one of the destinations connected only.
+  fn send_to(&self, destination_id: DestinationId, timestamp: TimestampNanos, ump: &[u32]) {
+    let remap = self
+      .connected
+      .lock()
+      .iter()
+      .find(|(connected_id, _)| *connected_id == destination_id)
+      .map(|(_, remap)| *remap);
+    if let Some(remap) = remap {
+      self.send_remapped(destination_id, remap, timestamp, ump);
+    }
+  }
+
+  fn send_remapped(
+    &self,
+    destination_id: DestinationId,
+    remap: DestinationRemap,
+    timestamp: TimestampNanos,
+    ump: &[u32],
+  ) {
+    if remap.is_identity() || ump.is_empty() || ump.len() > MAX_UMP_WORDS {
+      self.sender.send(destination_id, timestamp, ump);
+    } else {
+      let mut words = [0; MAX_UMP_WORDS];
+      let words = &mut words[..ump.len()];
+      words.copy_from_slice(ump);
+      words[0] = remap.apply(words[0]);
+      self.sender.send(destination_id, timestamp, words);
+    }
+  }
+}
+
+impl OutputSink for OutputState {
+  fn send(&self, timestamp: TimestampNanos, ump: &[u32]) {
+    // Not locked while sending, as the destination might end up sending to this output again
+    let connected = self.connected.lock().clone();
+    for (destination_id, remap) in connected.iter() {
+      self.send_remapped(*destination_id, *remap, timestamp, ump);
+    }
+  }
+
+  fn connected_destinations(&self) -> Vec<DestinationId> {
+    self
+      .connected
+      .lock()
+      .iter()
+      .map(|(destination_id, _)| *destination_id)
+      .collect()
+  }
+}
+
+impl Outputs {
+  pub fn new<S>(sender: S) -> Self
+  where
+    S: DestinationSender + 'static,
+  {
+    Self {
+      outputs: HashMap::new(),
+      sender: Arc::new(sender),
+      connection_handler: None,
+      pending_ranges: Vec::new(),
+      broadcast_filter: Filter::default(),
+    }
+  }
+
+  pub fn set_connection_handler(&mut self, handler: OutputConnectionHandler) {
+    self.connection_handler = Some(handler);
+  }
+
+  pub fn create<'a, D>(
+    &mut self,
+    config: OutputConfig,
+    available_destinations: D,
+  ) -> Result<Output, Error>
+  where
+    D: IntoIterator<Item = (DestinationId, &'a str, &'a str)>,
+  {
+    if self.outputs.contains_key(config.name.as_str()) {
+      return Err(Error::OutputAlreadyExists(Box::new(config)));
+    }
+
+    let OutputConfig {
+      name,
+      destinations,
+      filter,
+      transforms,
+    } = config;
+    let state = Arc::new(OutputState {
+      destinations: Mutex::new(destinations),
+      connected: Mutex::new(Arc::new(Vec::new())),
+      sender: self.sender.clone(),
+    });
+
+    let output = Output::new(name.clone(), state.clone()).with_processing(filter, transforms);
+
+    for (destination_id, destination_name, display_name) in available_destinations {
+      if state.connect(destination_id, destination_name, display_name) {
+        Self::queue_range(&mut self.pending_ranges, &state, &output, destination_id);
+        Self::notify(
+          &mut self.connection_handler,
+          OutputConnection::Connected {
+            output: name.clone(),
+            destination: destination_id,
+          },
+        );
+      }
+    }
+
+    self.outputs.insert(name, (state, output.clone()));
+
+    Ok(output)
+  }
+
+  pub fn get(&self, name: &str) -> Result<Output, Error> {
+    self
+      .outputs
+      .get(name)
+      .map(|(_, output)| output.clone())
+      .ok_or_else(|| Error::OutputNotFound(name.to_string()))
+  }
+
+  pub fn set_destinations<'a, D>(
+    &mut self,
+    name: &str,
+    destinations: DestinationMatches,
+    available_destinations: D,
+  ) -> Result<(), Error>
+  where
+    D: IntoIterator<Item = (DestinationId, &'a str, &'a str)>,
+  {
+    let (state, output) = self
+      .outputs
+      .get(name)
+      .ok_or_else(|| Error::OutputNotFound(name.to_string()))?;
+
+    let connected = available_destinations
+      .into_iter()
+      .filter_map(|(destination_id, destination_name, display_name)| {
+        destinations
+          .match_remap(destination_id, destination_name, display_name)
+          .map(|remap| (destination_id, remap))
+      })
+      .collect::<Vec<(DestinationId, DestinationRemap)>>();
+
+    *state.destinations.lock() = destinations;
+    let previous = std::mem::replace(&mut *state.connected.lock(), Arc::new(connected.clone()))
+      .iter()
+      .map(|(destination_id, _)| *destination_id)
+      .collect::<Vec<DestinationId>>();
+    let connected = connected
+      .into_iter()
+      .map(|(destination_id, _)| destination_id)
+      .collect::<Vec<DestinationId>>();
+
+    for destination_id in previous.iter() {
+      if !connected.contains(destination_id) {
+        Self::notify(
+          &mut self.connection_handler,
+          OutputConnection::Disconnected {
+            output: name.to_string(),
+            destination: *destination_id,
+          },
+        );
+      }
+    }
+    for destination_id in connected.iter() {
+      if !previous.contains(destination_id) {
+        Self::queue_range(&mut self.pending_ranges, state, output, *destination_id);
+        Self::notify(
+          &mut self.connection_handler,
+          OutputConnection::Connected {
+            output: name.to_string(),
+            destination: *destination_id,
+          },
+        );
+      }
+    }
+
+    Ok(())
+  }
+
+  pub fn set_broadcast_filter(&mut self, filter: Filter) {
+    self.broadcast_filter = filter;
+  }
+
+  pub fn broadcast_filter(&self) -> &Filter {
+    &self.broadcast_filter
+  }
+
+  /// Sends the message to the destinations if the broadcast filter accepts it,
+  /// whether they are connected to any output or not.
+  pub fn broadcast_message<D>(&self, message: Message, destinations: D)
+  where
+    D: IntoIterator<Item = DestinationId>,
+  {
+    if self.broadcast_filter.message(&message) {
+      self.broadcast(0, encode_message(&message).as_slice(), destinations);
+    }
+  }
+
+  /// Sends the words to the destinations, whether they are connected to any output or not.
+  pub fn broadcast<D>(&self, timestamp: TimestampNanos, ump: &[u32], destinations: D)
+  where
+    D: IntoIterator<Item = DestinationId>,
+  {
+    for destination_id in destinations {
+      self.sender.send(destination_id, timestamp, ump);
+    }
+  }
+
+  /// Sends a SysEx message as SysEx7 packets in group 0 to the destinations,
+  /// whether they are connected to any output or not.
+  pub fn broadcast_sysex<D>(&self, data: &[u8], destinations: D)
+  where
+    D: IntoIterator<Item = DestinationId>,
+  {
+    let destinations = destinations.into_iter().collect::<Vec<DestinationId>>();
+    encode_sysex7(0, data, |ump| {
+      self.broadcast(0, ump.as_slice(), destinations.iter().cloned())
+    });
+  }
+
+  /// Sends the panic messages through all the outputs.
+  pub fn panic_all(&self) {
+    for (_, output) in self.outputs.values() {
+      output.panic();
+    }
+  }
+
+  pub fn connect_destination(
+    &mut self,
+    destination_id: DestinationId,
+    destination_name: &str,
+    display_name: &str,
+  ) {
+    for (name, (state, output)) in self.outputs.iter() {
+      if state.connect(destination_id, destination_name, display_name) {
+        Self::queue_range(&mut self.pending_ranges, state, output, destination_id);
+        Self::notify(
+          &mut self.connection_handler,
+          OutputConnection::Connected {
+            output: name.clone(),
+            destination: destination_id,
+          },
+        );
+      }
+    }
+  }
+
+  pub fn disconnect_destination(&mut self, destination_id: DestinationId) {
+    for (name, (output, _)) in self.outputs.iter() {
+      if output.disconnect(destination_id) {
+        Self::notify(
+          &mut self.connection_handler,
+          OutputConnection::Disconnected {
+            output: name.clone(),
+            destination: destination_id,
+          },
+        );
+      }
+    }
+  }
+
+  /// Takes the pitch bend ranges for the destinations connected since the last time,
+  /// to send them once the locks taken by the sender are released.
+  pub fn take_pending_ranges(&mut self) -> PendingRanges {
+    PendingRanges(std::mem::take(&mut self.pending_ranges))
+  }
+
+  fn queue_range(
+    pending_ranges: &mut Vec<PendingRange>,
+    state: &Arc<OutputState>,
+    output: &Output,
+    destination_id: DestinationId,
+  ) {
+    if output.has_pitch_bend_range() {
+      pending_ranges.push((state.clone(), output.clone(), destination_id));
+    }
+  }
+
+  fn notify(handler: &mut Option<OutputConnectionHandler>, connection: OutputConnection) {
+    if let Some(handler) = handler.as_mut() {
+      handler(connection);
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::filter::Filter;
+  use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+  use crate::protocol::messages::{Message, MessageType};
+  use crate::transform::Transform;
+
+  fn note_on(channel: u8) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::NoteOn {
+          note: 0x3c,
+          velocity: 0x64,
+        },
+      }),
+    }
+  }
+
+  type Sent = Arc<Mutex<Vec<(DestinationId, Vec<u32>)>>>;
+
+  #[derive(Default, Clone)]
+  struct Recorder(Sent);
+
+  impl DestinationSender for Recorder {
+    fn send(&self, destination: DestinationId, _timestamp: TimestampNanos, ump: &[u32]) {
+      self.0.lock().push((destination, ump.to_vec()));
+    }
+  }
+
+  #[test]
+  fn send_to_matching_destinations() {
+    let recorder = Recorder::default();
+    let mut outputs = Outputs::new(recorder.clone());
+    let config = OutputConfig::new("synths").with_destination("Synth");
+
+    let output = outputs
+      .create(config, vec![(1, "Synth", "Synth"), (2, "Drums", "Drums")])
+      .unwrap();
+    output.send_ump(0, &[0x2090_3c64]);
+
+    assert_eq!(output.connected_destinations(), vec![1]);
+    assert_eq!(recorder.0.lock().as_slice(), &[(1, vec![0x2090_3c64])]);
+  }
+
+  #[test]
+  fn destinations_come_and_go() {
+    let recorder = Recorder::default();
+    let mut outputs = Outputs::new(recorder.clone());
+    let output = outputs
+      .create(OutputConfig::new("all").with_all_destinations(), vec![])
+      .unwrap();
+
+    outputs.connect_destination(1, "Synth", "Synth");
+    outputs.connect_destination(1, "Synth", "Synth");
+    assert_eq!(output.connected_destinations(), vec![1]);
+
+    outputs.disconnect_destination(1);
+    output.send_ump(0, &[0x2090_3c64]);
+    assert!(output.connected_destinations().is_empty());
+    assert!(recorder.0.lock().is_empty());
+  }
+
+  #[test]
+  fn set_destinations_reconnects() {
+    let recorder = Recorder::default();
+    let mut outputs = Outputs::new(recorder.clone());
+    let available_destinations = vec![(1, "Synth", "Synth"), (2, "Drums", "Drums")];
+    let output = outputs
+      .create(
+        OutputConfig::new("out").with_destination("Synth"),
+        available_destinations.clone(),
+      )
+      .unwrap();
+
+    outputs
+      .set_destinations(
+        "out",
+        DestinationMatches::default().with_destination("Drums"),
+        available_destinations,
+      )
+      .unwrap();
+    output.send_ump(0, &[0x2090_3c64]);
+
+    assert_eq!(output.connected_destinations(), vec![2]);
+    assert_eq!(recorder.0.lock().as_slice(), &[(2, vec![0x2090_3c64])]);
+
+    let result = outputs.set_destinations("unknown", DestinationMatches::default(), vec![]);
+    assert!(matches!(result, Err(Error::OutputNotFound(name)) if name == "unknown"));
+  }
+
+  #[test]
+  fn filter_and_transform_before_sending() {
+    let recorder = Recorder::default();
+    let mut outputs = Outputs::new(recorder.clone());
+    let config = OutputConfig::new("out")
+      .with_all_destinations()
+      .with_filter(Filter::new().with_channels(1, &[1]))
+      .with_transform(Transform::new().with_channel(1, 2))
+      .with_transform(Transform::new().with_channel(2, 3));
+    let output = outputs.create(config, vec![(1, "Synth", "Synth")]).unwrap();
+
+    output.send(note_on(0));
+    output.send(note_on(1));
+
+    assert_eq!(recorder.0.lock().as_slice(), &[(1, vec![0x2092_3c64])]);
+    assert_eq!(outputs.get("out").unwrap().name(), "out");
+  }
+
+  #[test]
+  fn send_pitch_bend_range_on_connect() {
+    let recorder = Recorder::default();
+    let mut outputs = Outputs::new(recorder.clone());
+    let config = OutputConfig::new("mpe")
+      .with_all_destinations()
+      .with_transform(Transform::new().with_pitch_bend_range(2.0, 48.5));
+    outputs.create(config, vec![(1, "Synth", "Synth")]).unwrap();
+    outputs.take_pending_ranges().send();
+
+    {
+      let sent = recorder.0.lock();
+      assert_eq!(sent.len(), 16 * 16 * 6);
+      assert_eq!(
+        sent[..6].iter().map(|(_, ump)| ump[0]).collect::<Vec<_>>(),
+        vec![
+          0x20b0_6500,
+          0x20b0_6400,
+          0x20b0_0630,
+          0x20b0_2632,
+          0x20b0_657f,
+          0x20b0_647f
+        ]
+      );
+      assert_eq!(sent[6].1, vec![0x20b1_6500]);
+      assert_eq!(sent[16 * 6].1, vec![0x21b0_6500]);
+    }
+
+    recorder.0.lock().clear();
+    outputs.connect_destination(2, "Other", "Other");
+    outputs.take_pending_ranges().send();
+    assert!(recorder
+      .0
+      .lock()
+      .iter()
+      .all(|(destination, _)| *destination == 2));
+    assert_eq!(recorder.0.lock().len(), 16 * 16 * 6);
+  }
+
+  #[test]
+  fn set_pitch_bend_range_while_running() {
+    let recorder = Recorder::default();
+    let mut outputs = Outputs::new(recorder.clone());
+    let config = OutputConfig::new("mpe")
+      .with_all_destinations()
+      .with_transform(Transform::new().with_pitch_bend_range(2.0, 48.0));
+    let output = outputs.create(config, vec![(1, "Synth", "Synth")]).unwrap();
+    outputs.take_pending_ranges().send();
+    recorder.0.lock().clear();
+
+    output.set_pitch_bend_range(2.0, 24.0);
+    {
+      let sent = recorder.0.lock();
+      assert_eq!(sent.len(), 16 * 16 * 6);
+      assert_eq!(sent[2].1, vec![0x20b0_0618]);
+      assert_eq!(sent[16 * 16 * 6 - 4].1, vec![0x2fbf_0618]);
+    }
+
+    recorder.0.lock().clear();
+    output.send(Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message: ChannelVoice1Message::PitchBend { data: 0x3fff },
+      }),
+    });
+    // A full bend of 2 semitones is 1/12 of the range of 24, rather than 1/24 of 48
+    let data = 0x22ab;
+    assert_eq!(
+      recorder.0.lock().as_slice(),
+      &[(1, vec![0x20e0_0000 | (data & 0x7f) << 8 | data >> 7])]
+    );
+
+    recorder.0.lock().clear();
+    outputs.connect_destination(2, "Other", "Other");
+    outputs.take_pending_ranges().send();
+    assert_eq!(recorder.0.lock()[2].1, vec![0x20b0_0618]);
+  }
+
+  /// Takes the lock of the endpoints to send, as the CoreMIDI sender
+  struct LockingSender {
+    endpoints: Arc<Mutex<()>>,
+    recorder: Recorder,
+  }
+
+  impl DestinationSender for LockingSender {
+    fn send(&self, destination: DestinationId, timestamp: TimestampNanos, ump: &[u32]) {
+      let _endpoints = self
+        .endpoints
+        .try_lock()
+        .expect("sending while the driver holds the endpoints");
+      self.recorder.send(destination, timestamp, ump);
+    }
+  }
+
+  #[test]
+  fn send_pitch_bend_range_after_releasing_the_locks() {
+    let endpoints = Arc::new(Mutex::new(()));
+    let recorder = Recorder::default();
+    let mut outputs = Outputs::new(LockingSender {
+      endpoints: endpoints.clone(),
+      recorder: recorder.clone(),
+    });
+    let config = OutputConfig::new("mpe")
+      .with_all_destinations()
+      .with_transform(Transform::new().with_pitch_bend_range(2.0, 48.0));
+
+    let pending = {
+      let _endpoints = endpoints.lock();
+      outputs.create(config, vec![(1, "Synth", "Synth")]).unwrap();
+      outputs.connect_destination(2, "Other", "Other");
+      outputs
+        .set_destinations(
+          "mpe",
+          DestinationMatches::default()
+            .with_destination("Synth")
+            .with_destination("Other")
+            .with_destination("New"),
+          vec![
+            (1, "Synth", "Synth"),
+            (2, "Other", "Other"),
+            (3, "New", "New"),
+          ],
+        )
+        .unwrap();
+      outputs.take_pending_ranges()
+    };
+    assert!(recorder.0.lock().is_empty());
+
+    pending.send();
+    let sent = recorder.0.lock();
+    for destination in 1..=3 {
+      let count = sent.iter().filter(|(id, _)| *id == destination).count();
+      assert_eq!(count, 16 * 16 * 6);
+    }
+  }
+
+  #[test]
+  fn remap_per_destination() {
+    let recorder = Recorder::default();
+    let mut outputs = Outputs::new(recorder.clone());
+    let config = OutputConfig::new("part")
+      .with_remapped_destination("Synth A", DestinationRemap::new().with_channel(1))
+      .with_remapped_destination("Synth B", DestinationRemap::new().with_channel(5));
+    let output = outputs
+      .create(
+        config,
+        vec![(1, "Synth A", "Synth A"), (2, "Synth B", "Synth B")],
+      )
+      .unwrap();
+
+    output.send(note_on(9));
+
+    assert_eq!(
+      recorder.0.lock().as_slice(),
+      &[(1, vec![0x2090_3c64]), (2, vec![0x2094_3c64])]
+    );
+  }
+
+  #[test]
+  fn notify_connection_changes() {
+    let mut outputs = Outputs::new(Recorder::default());
+    let connections = Arc::new(Mutex::new(Vec::new()));
+    let connections_clone = connections.clone();
+    outputs.set_connection_handler(Box::new(move |connection| {
+      connections_clone.lock().push(connection)
+    }));
+
+    outputs
+      .create(
+        OutputConfig::new("out").with_destination("Synth"),
+        vec![(1, "Synth", "Synth")],
+      )
+      .unwrap();
+    outputs.connect_destination(2, "Drums", "Drums");
+    outputs.disconnect_destination(1);
+    outputs
+      .set_destinations(
+        "out",
+        DestinationMatches::default().with_destination("Drums"),
+        vec![(2, "Drums", "Drums")],
+      )
+      .unwrap();
+
+    let connected = |destination| OutputConnection::Connected {
+      output: "out".to_string(),
+      destination,
+    };
+    let disconnected = |destination| OutputConnection::Disconnected {
+      output: "out".to_string(),
+      destination,
+    };
+    assert_eq!(
+      connections.lock().as_slice(),
+      &[connected(1), disconnected(1), connected(2)]
+    );
+  }
+
+  #[test]
+  fn create_existing_output_fails() {
+    let mut outputs = Outputs::new(Recorder::default());
+    outputs.create(OutputConfig::new("out"), vec![]).unwrap();
+
+    let result = outputs.create(OutputConfig::new("out"), vec![]);
+
+    assert!(matches!(result, Err(Error::OutputAlreadyExists(_))));
+  }
+}
@@ -0,0 +1,115 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use parking_lot::{Condvar, Mutex};
+
+use crate::event::{self, TimestampNanos};
+
+const NO_DEADLINE: TimestampNanos = TimestampNanos::MAX;
+
+/// Background thread delivering the control changes kept pending by the rate limiters
+/// once they are due, so the last values are not held back when the sources stop sending.
+///
+/// The `deliver` function receives the current time, in the clock of `event::now()`,
+/// and returns when the next pending values are due, if any. The thread stops once the timer is dropped.
+pub struct PendingTimer {
+  shared: Arc<Shared>,
+}
+
+struct Shared {
+  running: Mutex<bool>,
+  condvar: Condvar,
+  /// Read without the lock, so scheduling a deadline already covered does not lock
+  deadline: AtomicU64,
+}
+
+impl PendingTimer {
+  pub fn spawn<F>(name: &str, deliver: F) -> std::io::Result<Self>
+  where
+    F: FnMut(TimestampNanos) -> Option<TimestampNanos> + Send + 'static,
+  {
+    let shared = Arc::new(Shared {
+      running: Mutex::new(true),
+      condvar: Condvar::new(),
+      deadline: AtomicU64::new(NO_DEADLINE),
+    });
+
+    let thread_shared = shared.clone();
+    std::thread::Builder::new()
+      .name(name.to_string())
+      .spawn(move || Self::run(thread_shared, deliver))?;
+
+    Ok(Self { shared })
+  }
+
+  /// Wakes up the thread at `deadline`, unless it is waiting for an earlier one.
+  pub fn schedule(&self, deadline: TimestampNanos) {
+    if self.shared.deadline.fetch_min(deadline, Ordering::AcqRel) > deadline {
+      let _running = self.shared.running.lock();
+      self.shared.condvar.notify_one();
+    }
+  }
+
+  fn run<F>(shared: Arc<Shared>, mut deliver: F)
+  where
+    F: FnMut(TimestampNanos) -> Option<TimestampNanos>,
+  {
+    loop {
+      let mut running = shared.running.lock();
+      if !*running {
+        break;
+      }
+      let deadline = shared.deadline.load(Ordering::Acquire);
+      let now = event::now();
+      if deadline == NO_DEADLINE {
+        shared.condvar.wait(&mut running);
+      } else if now < deadline {
+        shared
+          .condvar
+          .wait_for(&mut running, Duration::from_nanos(deadline - now));
+      } else {
+        // Without the lock, as the scheduling happens while the pending values are updated
+        drop(running);
+        shared.deadline.store(NO_DEADLINE, Ordering::Release);
+        if let Some(next) = deliver(now) {
+          shared.deadline.fetch_min(next, Ordering::AcqRel);
+        }
+      }
+    }
+  }
+}
+
+impl Drop for PendingTimer {
+  fn drop(&mut self) {
+    *self.shared.running.lock() = false;
+    self.shared.condvar.notify_one();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::mpsc;
+
+  use super::*;
+
+  #[test]
+  fn deliver_at_the_deadlines() {
+    let (sender, receiver) = mpsc::channel();
+    let start = event::now();
+    let mut deadlines = vec![start + 2_000_000];
+    let timer = PendingTimer::spawn("pending-test", move |now| {
+      sender.send(now).unwrap();
+      deadlines.pop()
+    })
+    .unwrap();
+
+    timer.schedule(start + 1_000_000);
+
+    let timeout = Duration::from_secs(5);
+    assert!(receiver.recv_timeout(timeout).unwrap() >= start + 1_000_000);
+    assert!(receiver.recv_timeout(timeout).unwrap() >= start + 2_000_000);
+    drop(timer);
+    assert!(receiver.recv_timeout(Duration::from_millis(50)).is_err());
+  }
+}
@@ -0,0 +1,348 @@
+use std::collections::HashSet;
+use std::io::BufReader;
+use std::net::{Shutdown, SocketAddr, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use crate::drivers;
+use crate::drivers::endpoints;
+use crate::drivers::inputs::Inputs;
+use crate::drivers::proxy::protocol::{EndpointListing, Frame};
+use crate::drivers::Capabilities;
+use crate::endpoints::{DestinationInfo, SourceInfo};
+use crate::input_config::InputConfig;
+use crate::input_handler::InputHandler;
+use crate::input_info::InputInfo;
+use crate::source_match::SourceMatches;
+
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(1);
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(1);
+
+type Endpoints = endpoints::Endpoints<(), ()>;
+
+#[derive(Error, Debug)]
+pub enum ProxyError {
+  #[error("Error resolving the address: {0}")]
+  Address(std::io::Error),
+
+  #[error("Error connecting to the proxy server: {0}")]
+  Connect(std::io::Error),
+
+  #[error("Error listening for connections: {0}")]
+  Bind(std::io::Error),
+
+  #[error("Error creating the proxy thread: {0}")]
+  Thread(std::io::Error),
+}
+
+/// Receives the sources of a `ProxyServer` running in another machine.
+///
+/// The events keep the timestamps from the server, and the connection is
+/// re-established automatically if it is lost.
+pub struct ProxyDriver {
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  running: Arc<AtomicBool>,
+  stream: Arc<Mutex<Option<TcpStream>>>,
+  receiver: Option<JoinHandle<()>>,
+}
+
+impl drivers::DriverSpec for ProxyDriver {
+  fn create_input<H>(&mut self, config: InputConfig, handler: H) -> Result<String, drivers::Error>
+  where
+    H: Into<InputHandler>,
+  {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .create(config, handler.into(), endpoints.connected_source_names())
+  }
+
+  fn sources(&self) -> Vec<SourceInfo> {
+    let endpoints = self.endpoints.lock();
+    self.inputs.lock().source_infos(&endpoints)
+  }
+
+  fn destinations(&self) -> Vec<DestinationInfo> {
+    self.endpoints.lock().destination_infos()
+  }
+
+  fn inputs(&self) -> Vec<InputInfo> {
+    self.inputs.lock().infos()
+  }
+
+  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
+    self.inputs.lock().config(name)
+  }
+
+  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .set_sources(name, sources, endpoints.connected_source_names())
+  }
+
+  fn remove_input(&self, name: &str) -> Result<(), drivers::Error> {
+    self.inputs.lock().remove(name)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      ump_native: true,
+      hotplug: true,
+      ..Capabilities::default()
+    }
+  }
+}
+
+impl ProxyDriver {
+  /// Connects to a `ProxyServer`, failing if it is not reachable at this point.
+  pub fn new<A>(_name: &str, address: A) -> Result<Self, drivers::Error>
+  where
+    A: ToSocketAddrs,
+  {
+    let address = address
+      .to_socket_addrs()
+      .map_err(ProxyError::Address)?
+      .next()
+      .ok_or_else(|| {
+        ProxyError::Address(std::io::Error::new(
+          std::io::ErrorKind::NotFound,
+          "No address found",
+        ))
+      })?;
+
+    let stream =
+      TcpStream::connect_timeout(&address, CONNECT_TIMEOUT).map_err(ProxyError::Connect)?;
+
+    let endpoints = Arc::new(Mutex::new(Endpoints::new()));
+    let inputs = Inputs::shared();
+    let running = Arc::new(AtomicBool::new(true));
+
+    let receiver = Receiver {
+      address,
+      endpoints: endpoints.clone(),
+      inputs: inputs.clone(),
+      running: running.clone(),
+      stream: Arc::new(Mutex::new(None)),
+    };
+    let stream_handle = receiver.stream.clone();
+
+    let receiver = std::thread::Builder::new()
+      .name("proxy-driver".to_string())
+      .spawn(move || receiver.run(stream))
+      .map_err(ProxyError::Thread)?;
+
+    Ok(Self {
+      endpoints,
+      inputs,
+      running,
+      stream: stream_handle,
+      receiver: Some(receiver),
+    })
+  }
+}
+
+impl Drop for ProxyDriver {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::Relaxed);
+    if let Some(stream) = self.stream.lock().as_ref() {
+      stream.shutdown(Shutdown::Both).ok();
+    }
+    if let Some(receiver) = self.receiver.take() {
+      receiver.join().ok();
+    }
+  }
+}
+
+struct Receiver {
+  address: SocketAddr,
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  running: Arc<AtomicBool>,
+  /// A handle to the current connection, so it can be shut down when the driver is dropped
+  stream: Arc<Mutex<Option<TcpStream>>>,
+}
+
+impl Receiver {
+  fn run(self, stream: TcpStream) {
+    let mut stream = Some(stream);
+    while self.running.load(Ordering::Relaxed) {
+      match stream.take() {
+        Some(stream) => {
+          self.receive(stream);
+          self.handle_disconnected();
+        }
+        None => {
+          std::thread::sleep(RECONNECT_INTERVAL);
+          stream = TcpStream::connect_timeout(&self.address, CONNECT_TIMEOUT).ok();
+        }
+      }
+    }
+  }
+
+  /// Reads the frames from the server until the connection fails or is shut down.
+  fn receive(&self, stream: TcpStream) {
+    match stream.try_clone() {
+      Ok(handle) => *self.stream.lock() = Some(handle),
+      Err(_) => return,
+    }
+    // The driver might have been dropped before the handle was available
+    if !self.running.load(Ordering::Relaxed) {
+      return;
+    }
+
+    let mut reader = BufReader::new(stream);
+    while let Ok(frame) = Frame::read(&mut reader) {
+      match frame {
+        Frame::Sources(sources) => self.update_sources(sources),
+        Frame::Destinations(destinations) => self.update_destinations(destinations),
+        Frame::Event {
+          source,
+          timestamp,
+          ump,
+        } => {
+          let mut inputs = self.inputs.lock();
+          inputs.dispatch(source, timestamp, ump.as_slice());
+          inputs.flush();
+        }
+      }
+    }
+
+    self.stream.lock().take();
+  }
+
+  fn update_sources(&self, sources: Vec<EndpointListing>) {
+    let mut endpoints = self.endpoints.lock();
+    let mut inputs = self.inputs.lock();
+
+    let available = sources
+      .iter()
+      .map(|source| source.id)
+      .collect::<HashSet<_>>();
+    let removed = endpoints
+      .connected_sources()
+      .into_iter()
+      .map(|connected_source| connected_source.id)
+      .filter(|id| !available.contains(id))
+      .collect::<Vec<_>>();
+    for id in removed {
+      endpoints.remove_source_by_id(id);
+      inputs.disconnect_source(id);
+    }
+
+    for source in sources {
+      if endpoints.get_source(source.id).is_none() {
+        inputs.connect_source(
+          source.id,
+          source.name.as_str(),
+          source.display_name.as_str(),
+        );
+        endpoints.add_source_with_display_name(source.id, source.name, source.display_name, ());
+      }
+    }
+  }
+
+  fn update_destinations(&self, destinations: Vec<EndpointListing>) {
+    let mut endpoints = self.endpoints.lock();
+
+    let available = destinations
+      .iter()
+      .map(|destination| destination.id)
+      .collect::<HashSet<_>>();
+    let removed = endpoints
+      .connected_destinations()
+      .into_iter()
+      .map(|connected_destination| connected_destination.id)
+      .filter(|id| !available.contains(id))
+      .collect::<Vec<_>>();
+    for id in removed {
+      endpoints.remove_destination_by_id(id);
+    }
+
+    for destination in destinations {
+      endpoints.add_destination_with_display_name(
+        destination.id,
+        destination.name,
+        destination.display_name,
+        (),
+      );
+    }
+  }
+
+  fn handle_disconnected(&self) {
+    self.update_sources(Vec::new());
+    self.update_destinations(Vec::new());
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::mpsc;
+  use std::time::Instant;
+
+  use super::*;
+  use crate::drivers::proxy::ProxyServer;
+  use crate::drivers::{DriverSpec, LoopbackDriver};
+  use crate::event::Event;
+  use crate::filter::Filter;
+
+  fn wait_until<F: Fn() -> bool>(condition: F) {
+    let start = Instant::now();
+    while !condition() {
+      assert!(start.elapsed() < Duration::from_secs(5), "Timed out");
+      std::thread::sleep(Duration::from_millis(10));
+    }
+  }
+
+  #[test]
+  fn connect_to_missing_server_fails() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    drop(listener);
+
+    let result = ProxyDriver::new("test", address);
+
+    assert!(matches!(
+      result,
+      Err(drivers::Error::Proxy(ProxyError::Connect(_)))
+    ));
+  }
+
+  #[test]
+  fn events_and_endpoints_through_the_proxy() {
+    let mut loopback = LoopbackDriver::new("loopback");
+    let port = loopback.add_port("sequencer").unwrap();
+    let sender = loopback.sender(port).unwrap();
+    let server = ProxyServer::new(loopback, "127.0.0.1:0").unwrap();
+
+    let mut driver = ProxyDriver::new("test", server.address()).unwrap();
+    wait_until(|| driver.sources().len() == 1 && driver.destinations().len() == 1);
+    assert_eq!(driver.sources()[0].id, port);
+    assert_eq!(driver.sources()[0].name, "sequencer");
+
+    let (events_tx, events_rx) = mpsc::channel();
+    let config = InputConfig::new("input").with_source("sequencer", Filter::default());
+    driver
+      .create_input(config, move |event: Event| {
+        events_tx.send(event).ok();
+      })
+      .unwrap();
+
+    sender.send(1234, &[0x2090_3c64]);
+
+    let event = events_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(event.timestamp, 1234);
+    assert_eq!(event.endpoint, port);
+
+    drop(server);
+    wait_until(|| driver.sources().is_empty());
+  }
+}
@@ -0,0 +1,6 @@
+mod driver;
+mod protocol;
+mod server;
+
+pub use driver::{ProxyDriver, ProxyError};
+pub use server::ProxyServer;
@@ -0,0 +1,222 @@
+//! Framing for the proxy connections.
+//!
+//! Every frame starts with a byte for its kind and the length of the payload as a big endian u32.
+//! The server sends the full list of sources and destinations every time it changes,
+//! and the events received from its sources as UMP words.
+
+use std::io::{self, Read, Write};
+
+use crate::endpoints::{EndpointId, SourceId};
+use crate::event::TimestampNanos;
+
+const SOURCES: u8 = 0x01;
+const DESTINATIONS: u8 = 0x02;
+const EVENT: u8 = 0x03;
+
+/// Frames larger than this are considered corrupted data
+const MAX_FRAME_LEN: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EndpointListing {
+  pub id: EndpointId,
+  pub name: String,
+  pub display_name: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Frame {
+  Sources(Vec<EndpointListing>),
+  Destinations(Vec<EndpointListing>),
+  Event {
+    source: SourceId,
+    timestamp: TimestampNanos,
+    ump: Vec<u32>,
+  },
+}
+
+impl Frame {
+  pub fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+    let mut payload = Vec::new();
+    let kind = match self {
+      Self::Sources(endpoints) => {
+        write_endpoints(&mut payload, endpoints);
+        SOURCES
+      }
+      Self::Destinations(endpoints) => {
+        write_endpoints(&mut payload, endpoints);
+        DESTINATIONS
+      }
+      Self::Event {
+        source,
+        timestamp,
+        ump,
+      } => {
+        payload.extend_from_slice(&source.to_be_bytes());
+        payload.extend_from_slice(&timestamp.to_be_bytes());
+        for word in ump {
+          payload.extend_from_slice(&word.to_be_bytes());
+        }
+        EVENT
+      }
+    };
+
+    let mut frame = Vec::with_capacity(5 + payload.len());
+    frame.push(kind);
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&payload);
+    writer.write_all(&frame)
+  }
+
+  pub fn read<R: Read>(reader: &mut R) -> io::Result<Self> {
+    let mut header = [0u8; 5];
+    reader.read_exact(&mut header)?;
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > MAX_FRAME_LEN {
+      return Err(invalid_data("Frame too large"));
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload)?;
+
+    let mut payload = Payload(payload.as_slice());
+    match header[0] {
+      SOURCES => read_endpoints(&mut payload).map(Self::Sources),
+      DESTINATIONS => read_endpoints(&mut payload).map(Self::Destinations),
+      EVENT => {
+        let source = payload.u64()?;
+        let timestamp = payload.u64()?;
+        let mut ump = Vec::with_capacity(payload.0.len() / 4);
+        while !payload.0.is_empty() {
+          ump.push(payload.u32()?);
+        }
+        Ok(Self::Event {
+          source,
+          timestamp,
+          ump,
+        })
+      }
+      _ => Err(invalid_data("Unknown frame kind")),
+    }
+  }
+}
+
+fn write_endpoints(payload: &mut Vec<u8>, endpoints: &[EndpointListing]) {
+  payload.extend_from_slice(&(endpoints.len() as u32).to_be_bytes());
+  for endpoint in endpoints {
+    payload.extend_from_slice(&endpoint.id.to_be_bytes());
+    write_string(payload, endpoint.name.as_str());
+    write_string(payload, endpoint.display_name.as_str());
+  }
+}
+
+fn write_string(payload: &mut Vec<u8>, value: &str) {
+  payload.extend_from_slice(&(value.len() as u32).to_be_bytes());
+  payload.extend_from_slice(value.as_bytes());
+}
+
+fn read_endpoints(payload: &mut Payload) -> io::Result<Vec<EndpointListing>> {
+  let len = payload.u32()? as usize;
+  let mut endpoints = Vec::with_capacity(len.min(payload.0.len()));
+  for _ in 0..len {
+    endpoints.push(EndpointListing {
+      id: payload.u64()?,
+      name: payload.string()?,
+      display_name: payload.string()?,
+    });
+  }
+  Ok(endpoints)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+  io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+struct Payload<'a>(&'a [u8]);
+
+impl<'a> Payload<'a> {
+  fn bytes(&mut self, len: usize) -> io::Result<&'a [u8]> {
+    if self.0.len() < len {
+      return Err(invalid_data("Truncated frame"));
+    }
+    let (bytes, rest) = self.0.split_at(len);
+    self.0 = rest;
+    Ok(bytes)
+  }
+
+  fn u32(&mut self) -> io::Result<u32> {
+    let bytes = self.bytes(4)?;
+    Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+  }
+
+  fn u64(&mut self) -> io::Result<u64> {
+    let high = self.u32()? as u64;
+    let low = self.u32()? as u64;
+    Ok(high << 32 | low)
+  }
+
+  fn string(&mut self) -> io::Result<String> {
+    let len = self.u32()? as usize;
+    let bytes = self.bytes(len)?;
+    String::from_utf8(bytes.to_vec()).map_err(|_| invalid_data("Invalid string"))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn round_trip(frame: Frame) {
+    let mut buffer = Vec::new();
+    frame.write(&mut buffer).unwrap();
+    assert_eq!(Frame::read(&mut buffer.as_slice()).unwrap(), frame);
+  }
+
+  #[test]
+  fn endpoints_round_trip() {
+    let endpoints = vec![
+      EndpointListing {
+        id: 0x1234_5678_9abc_def0,
+        name: "Port 1".to_string(),
+        display_name: "Keys Port 1".to_string(),
+      },
+      EndpointListing {
+        id: 2,
+        name: "Pads".to_string(),
+        display_name: "Pads".to_string(),
+      },
+    ];
+    round_trip(Frame::Sources(endpoints.clone()));
+    round_trip(Frame::Destinations(endpoints));
+    round_trip(Frame::Sources(Vec::new()));
+  }
+
+  #[test]
+  fn event_round_trip() {
+    round_trip(Frame::Event {
+      source: 0xfedc_ba98_7654_3210,
+      timestamp: 123_456_789,
+      ump: vec![0x4090_3c00, 0xffff_0000],
+    });
+  }
+
+  #[test]
+  fn read_truncated_frame() {
+    let mut buffer = Vec::new();
+    Frame::Sources(vec![EndpointListing {
+      id: 1,
+      name: "Keys".to_string(),
+      display_name: "Keys".to_string(),
+    }])
+    .write(&mut buffer)
+    .unwrap();
+
+    let result = Frame::read(&mut &buffer[..buffer.len() - 1]);
+    assert!(result.is_err());
+  }
+
+  #[test]
+  fn read_unknown_kind() {
+    let buffer = [0xffu8, 0, 0, 0, 0];
+    let result = Frame::read(&mut &buffer[..]);
+    assert_eq!(result.unwrap_err().kind(), io::ErrorKind::InvalidData);
+  }
+}
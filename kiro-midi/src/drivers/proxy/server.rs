@@ -0,0 +1,192 @@
+use std::io::ErrorKind;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::drivers;
+use crate::drivers::proxy::driver::ProxyError;
+use crate::drivers::proxy::protocol::{EndpointListing, Frame};
+use crate::drivers::DriverSpec;
+use crate::event::Event;
+use crate::filter::Filter;
+use crate::input_config::InputConfig;
+use crate::protocol::encoder::encode_message;
+
+const INPUT_NAME: &str = "proxy";
+const POLL_TIMEOUT: Duration = Duration::from_millis(10);
+const ENDPOINTS_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Exposes the sources of a driver to the `ProxyDriver`s connected through TCP.
+///
+/// The driver is moved into a thread that accepts the connections, forwards the events
+/// received from all its sources, and sends the endpoints every time they change.
+pub struct ProxyServer {
+  address: SocketAddr,
+  running: Arc<AtomicBool>,
+  thread: Option<JoinHandle<()>>,
+}
+
+impl ProxyServer {
+  pub fn new<D, A>(mut driver: D, address: A) -> Result<Self, drivers::Error>
+  where
+    D: DriverSpec + Send + 'static,
+    A: ToSocketAddrs,
+  {
+    let listener = TcpListener::bind(address).map_err(ProxyError::Bind)?;
+    listener.set_nonblocking(true).map_err(ProxyError::Bind)?;
+    let address = listener.local_addr().map_err(ProxyError::Bind)?;
+
+    let (events_tx, events_rx) = mpsc::channel();
+    let config = InputConfig::new(INPUT_NAME).with_all_sources(Filter::default());
+    driver.create_input(config, move |event: Event| {
+      events_tx.send(event).ok();
+    })?;
+
+    let running = Arc::new(AtomicBool::new(true));
+    let server = Server {
+      driver,
+      listener,
+      events: events_rx,
+      clients: Vec::new(),
+      sources: Vec::new(),
+      destinations: Vec::new(),
+      running: running.clone(),
+    };
+
+    let thread = std::thread::Builder::new()
+      .name("proxy-server".to_string())
+      .spawn(move || server.run())
+      .map_err(ProxyError::Thread)?;
+
+    Ok(Self {
+      address,
+      running,
+      thread: Some(thread),
+    })
+  }
+
+  /// The address where the server is listening, useful when binding to port 0.
+  pub fn address(&self) -> SocketAddr {
+    self.address
+  }
+}
+
+impl Drop for ProxyServer {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::Relaxed);
+    if let Some(thread) = self.thread.take() {
+      thread.join().ok();
+    }
+  }
+}
+
+struct Server<D> {
+  driver: D,
+  listener: TcpListener,
+  events: Receiver<Event>,
+  clients: Vec<TcpStream>,
+  sources: Vec<EndpointListing>,
+  destinations: Vec<EndpointListing>,
+  running: Arc<AtomicBool>,
+}
+
+impl<D: DriverSpec> Server<D> {
+  fn run(mut self) {
+    let mut last_endpoints_update = Instant::now();
+    self.update_endpoints();
+
+    while self.running.load(Ordering::Relaxed) {
+      self.accept_clients();
+
+      match self.events.recv_timeout(POLL_TIMEOUT) {
+        Ok(event) => {
+          self.send_event(event);
+          while let Ok(event) = self.events.try_recv() {
+            self.send_event(event);
+          }
+        }
+        Err(RecvTimeoutError::Timeout) => {}
+        Err(RecvTimeoutError::Disconnected) => break,
+      }
+
+      if last_endpoints_update.elapsed() >= ENDPOINTS_INTERVAL {
+        last_endpoints_update = Instant::now();
+        self.update_endpoints();
+      }
+    }
+  }
+
+  fn accept_clients(&mut self) {
+    loop {
+      match self.listener.accept() {
+        Ok((mut stream, _)) => {
+          let ready = stream.set_nonblocking(false).is_ok()
+            && stream.set_nodelay(true).is_ok()
+            && Frame::Sources(self.sources.clone())
+              .write(&mut stream)
+              .is_ok()
+            && Frame::Destinations(self.destinations.clone())
+              .write(&mut stream)
+              .is_ok();
+          if ready {
+            self.clients.push(stream);
+          }
+        }
+        Err(error) if error.kind() == ErrorKind::WouldBlock => break,
+        Err(_) => break,
+      }
+    }
+  }
+
+  fn update_endpoints(&mut self) {
+    let sources = self
+      .driver
+      .sources()
+      .into_iter()
+      .map(|source| EndpointListing {
+        id: source.id,
+        name: source.name,
+        display_name: source.display_name,
+      })
+      .collect::<Vec<EndpointListing>>();
+
+    let destinations = self
+      .driver
+      .destinations()
+      .into_iter()
+      .map(|destination| EndpointListing {
+        id: destination.id,
+        name: destination.name,
+        display_name: destination.display_name,
+      })
+      .collect::<Vec<EndpointListing>>();
+
+    if sources != self.sources {
+      self.sources = sources;
+      self.broadcast(&Frame::Sources(self.sources.clone()));
+    }
+
+    if destinations != self.destinations {
+      self.destinations = destinations;
+      self.broadcast(&Frame::Destinations(self.destinations.clone()));
+    }
+  }
+
+  fn send_event(&mut self, event: Event) {
+    self.broadcast(&Frame::Event {
+      source: event.endpoint,
+      timestamp: event.timestamp,
+      ump: encode_message(&event.message).as_slice().to_vec(),
+    })
+  }
+
+  /// Sends a frame to all the clients, forgetting about the ones that fail
+  fn broadcast(&mut self, frame: &Frame) {
+    self
+      .clients
+      .retain(|mut client| frame.write(&mut client).is_ok());
+  }
+}
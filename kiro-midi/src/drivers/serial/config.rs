@@ -0,0 +1,40 @@
+/// Standard baud rate for the MIDI DIN interface
+pub const MIDI_BAUD_RATE: u32 = 31250;
+
+#[derive(Debug, Clone)]
+pub struct SerialConfig {
+  /// Pairs of device path and baud rate
+  pub devices: Vec<(String, u32)>,
+}
+
+impl SerialConfig {
+  pub fn new() -> Self {
+    Self {
+      devices: Vec::new(),
+    }
+  }
+
+  #[must_use]
+  pub fn with_device<P>(self, path: P) -> Self
+  where
+    P: Into<String>,
+  {
+    self.with_device_and_baud_rate(path, MIDI_BAUD_RATE)
+  }
+
+  /// USB serial bridges (Teensy, Arduino, ...) usually work at standard serial baud rates
+  #[must_use]
+  pub fn with_device_and_baud_rate<P>(mut self, path: P, baud_rate: u32) -> Self
+  where
+    P: Into<String>,
+  {
+    self.devices.push((path.into(), baud_rate));
+    self
+  }
+}
+
+impl Default for SerialConfig {
+  fn default() -> Self {
+    Self::new()
+  }
+}
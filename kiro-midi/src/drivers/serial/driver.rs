@@ -0,0 +1,201 @@
+use std::io::{ErrorKind, Read};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use parking_lot::Mutex;
+use serialport::SerialPort;
+use thiserror::Error;
+
+use crate::drivers;
+use crate::drivers::endpoints;
+use crate::drivers::inputs::Inputs;
+use crate::drivers::serial::config::SerialConfig;
+use crate::drivers::Capabilities;
+use crate::endpoints::{DestinationInfo, SourceId, SourceInfo};
+use crate::event;
+use crate::input_config::InputConfig;
+use crate::input_handler::InputHandler;
+use crate::input_info::InputInfo;
+use crate::protocol::midi1;
+use crate::source_match::SourceMatches;
+
+const READ_TIMEOUT: Duration = Duration::from_millis(100);
+const REOPEN_INTERVAL: Duration = Duration::from_secs(1);
+const BUFFER_SIZE: usize = 256;
+
+type Endpoints = endpoints::Endpoints<String, String>;
+
+#[derive(Error, Debug)]
+pub enum SerialError {
+  #[error("Error creating the reader thread for {0}: {1}")]
+  Thread(String, std::io::Error),
+}
+
+pub struct SerialDriver {
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  running: Arc<AtomicBool>,
+  readers: Vec<JoinHandle<()>>,
+}
+
+impl drivers::DriverSpec for SerialDriver {
+  fn create_input<H>(&mut self, config: InputConfig, handler: H) -> Result<String, drivers::Error>
+  where
+    H: Into<InputHandler>,
+  {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .create(config, handler.into(), endpoints.connected_source_names())
+  }
+
+  fn sources(&self) -> Vec<SourceInfo> {
+    let endpoints = self.endpoints.lock();
+    self.inputs.lock().source_infos(&endpoints)
+  }
+
+  fn destinations(&self) -> Vec<DestinationInfo> {
+    self.endpoints.lock().destination_infos()
+  }
+
+  fn inputs(&self) -> Vec<InputInfo> {
+    self.inputs.lock().infos()
+  }
+
+  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
+    self.inputs.lock().config(name)
+  }
+
+  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .set_sources(name, sources, endpoints.connected_source_names())
+  }
+
+  fn remove_input(&self, name: &str) -> Result<(), drivers::Error> {
+    self.inputs.lock().remove(name)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      hotplug: true,
+      ..Capabilities::default()
+    }
+  }
+}
+
+impl SerialDriver {
+  /// Creates a driver that reads MIDI 1.0 bytes from the configured serial devices.
+  ///
+  /// The devices become available as sources (named by their path) while they can be opened,
+  /// and they are reopened periodically when missing or unplugged.
+  pub fn new(name: &str, config: SerialConfig) -> Result<Self, drivers::Error> {
+    let endpoints = Arc::new(Mutex::new(Endpoints::new()));
+    let inputs = Inputs::shared();
+    let running = Arc::new(AtomicBool::new(true));
+
+    let mut readers = Vec::with_capacity(config.devices.len());
+    for (path, baud_rate) in config.devices {
+      let reader = Reader {
+        path: path.clone(),
+        baud_rate,
+        source_id: endpoints::hashed_id(path.as_str()),
+        endpoints: endpoints.clone(),
+        inputs: inputs.clone(),
+        running: running.clone(),
+      };
+
+      let join_handle = std::thread::Builder::new()
+        .name(format!("{}-serial", name))
+        .spawn(move || reader.run())
+        .map_err(|error| SerialError::Thread(path, error))?;
+
+      readers.push(join_handle);
+    }
+
+    Ok(Self {
+      endpoints,
+      inputs,
+      running,
+      readers,
+    })
+  }
+}
+
+impl Drop for SerialDriver {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::Relaxed);
+    for reader in self.readers.drain(..) {
+      reader.join().ok();
+    }
+  }
+}
+
+struct Reader {
+  path: String,
+  baud_rate: u32,
+  source_id: SourceId,
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  running: Arc<AtomicBool>,
+}
+
+impl Reader {
+  fn run(self) {
+    while self.running.load(Ordering::Relaxed) {
+      match serialport::new(self.path.as_str(), self.baud_rate)
+        .timeout(READ_TIMEOUT)
+        .open()
+      {
+        Ok(port) => {
+          self.handle_device_connected();
+          self.receive(port);
+          self.handle_device_disconnected();
+        }
+        Err(_) => std::thread::sleep(REOPEN_INTERVAL),
+      }
+    }
+  }
+
+  /// Reads from the device until it fails or the driver is dropped.
+  fn receive(&self, mut port: Box<dyn SerialPort>) {
+    let mut parser = midi1::Parser::new(0);
+    let mut buffer = [0u8; BUFFER_SIZE];
+    while self.running.load(Ordering::Relaxed) {
+      match port.read(&mut buffer) {
+        Ok(len) => {
+          let timestamp = event::now();
+          let mut inputs = self.inputs.lock();
+          parser.parse(&buffer[..len], |ump| {
+            inputs.dispatch(self.source_id, timestamp, ump.as_slice())
+          });
+          inputs.flush();
+        }
+        Err(error) if error.kind() == ErrorKind::TimedOut => {}
+        Err(_) => break,
+      }
+    }
+  }
+
+  fn handle_device_connected(&self) {
+    let mut endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .connect_source(self.source_id, self.path.as_str(), self.path.as_str());
+    endpoints.add_source(self.source_id, self.path.clone(), self.path.clone());
+    endpoints.add_destination(self.source_id, self.path.clone(), self.path.clone());
+  }
+
+  fn handle_device_disconnected(&self) {
+    let mut endpoints = self.endpoints.lock();
+    endpoints.remove_source_by_id(self.source_id);
+    endpoints.remove_destination_by_id(self.source_id);
+    self.inputs.lock().disconnect_source(self.source_id);
+  }
+}
@@ -0,0 +1,5 @@
+mod config;
+mod driver;
+
+pub use config::SerialConfig;
+pub use driver::{SerialDriver, SerialError};
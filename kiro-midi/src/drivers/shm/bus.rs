@@ -0,0 +1,461 @@
+use std::fs::OpenOptions;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::atomic::{fence, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+use memmap2::MmapMut;
+
+use crate::endpoints::EndpointId;
+use crate::event::TimestampNanos;
+
+/// "kiroSHM" followed by the version of the layout
+const MAGIC: u64 = 0x6b69_726f_5348_4d01;
+const OPEN_TIMEOUT: Duration = Duration::from_secs(1);
+
+const HEADER_WORDS: usize = 8;
+const MAGIC_WORD: usize = 0;
+const CAPACITY_WORD: usize = 1;
+const WRITE_INDEX_WORD: usize = 2;
+
+pub const MAX_ENDPOINTS: usize = 64;
+pub const MAX_NAME_LEN: usize = 64;
+const NAME_WORDS: usize = MAX_NAME_LEN / 8;
+const ENDPOINT_STATE: usize = 0;
+const ENDPOINT_ID: usize = 1;
+const ENDPOINT_OWNER: usize = 2;
+const ENDPOINT_NAME: usize = 3;
+const ENDPOINT_WORDS: usize = ENDPOINT_NAME + NAME_WORDS;
+
+/// The low byte of the endpoint state is the kind, and the rest is a generation
+/// incremented on every change, so readers can detect the entries modified while reading them.
+const KIND_MASK: u64 = 0xff;
+const GENERATION_UNIT: u64 = 0x100;
+const KIND_FREE: u64 = 0;
+const KIND_WRITING: u64 = 1;
+const KIND_SOURCE: u64 = 2;
+const KIND_DESTINATION: u64 = 3;
+
+pub const MAX_PACKET_WORDS: usize = 4;
+const SLOT_SEQUENCE: usize = 0;
+const SLOT_ENDPOINT: usize = 1;
+const SLOT_TIMESTAMP: usize = 2;
+const SLOT_LEN: usize = 3;
+const SLOT_DATA: usize = 4;
+const SLOT_WORDS: usize = SLOT_DATA + MAX_PACKET_WORDS / 2;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndpointKind {
+  Source,
+  Destination,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BusEndpoint {
+  pub id: EndpointId,
+  pub owner: u64,
+  pub kind: EndpointKind,
+  pub name: String,
+}
+
+/// Memory mapped file shared by all the processes, with a table of endpoints,
+/// and a ring buffer where all the packets are broadcast.
+///
+/// Everything in it is accessed through atomic words, the endpoints and the slots of the
+/// ring buffer being protected by a sequence number like a seqlock, so any process can
+/// write without locks, and the readers just discard what was modified while reading it.
+pub struct Bus {
+  _map: MmapMut,
+  words: *const AtomicU64,
+  len: usize,
+  capacity: usize,
+}
+
+// The mapped memory is only accessed through atomics
+unsafe impl Send for Bus {}
+unsafe impl Sync for Bus {}
+
+impl Bus {
+  /// Opens the bus, creating and initialising the file if it does not exist yet.
+  pub fn open(path: &Path, capacity: usize) -> Result<Self> {
+    let created = OpenOptions::new()
+      .read(true)
+      .write(true)
+      .create_new(true)
+      .open(path);
+
+    match created {
+      Ok(file) => {
+        let capacity = capacity.max(1);
+        file.set_len((Self::num_words(capacity) * 8) as u64)?;
+        let bus = Self::map(unsafe { MmapMut::map_mut(&file)? }, capacity);
+        bus
+          .word(CAPACITY_WORD)
+          .store(capacity as u64, Ordering::Relaxed);
+        bus.word(MAGIC_WORD).store(MAGIC, Ordering::Release);
+        Ok(bus)
+      }
+      Err(error) if error.kind() == ErrorKind::AlreadyExists => {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        // The process creating the file might still be initialising it
+        let start = Instant::now();
+        loop {
+          if file.metadata()?.len() >= (HEADER_WORDS * 8) as u64 {
+            let map = unsafe { MmapMut::map_mut(&file)? };
+            let words = map.as_ptr() as *const AtomicU64;
+            let magic = unsafe { &*words.add(MAGIC_WORD) }.load(Ordering::Acquire);
+            if magic == MAGIC {
+              let capacity = unsafe { &*words.add(CAPACITY_WORD) }.load(Ordering::Relaxed) as usize;
+              if capacity == 0 || map.len() < Self::num_words(capacity) * 8 {
+                return Err(Error::new(ErrorKind::InvalidData, "Invalid bus size"));
+              }
+              return Ok(Self::map(map, capacity));
+            }
+          }
+          if start.elapsed() > OPEN_TIMEOUT {
+            return Err(Error::new(ErrorKind::InvalidData, "Invalid bus header"));
+          }
+          std::thread::sleep(Duration::from_millis(1));
+        }
+      }
+      Err(error) => Err(error),
+    }
+  }
+
+  fn map(mut map: MmapMut, capacity: usize) -> Self {
+    let words = map.as_mut_ptr() as *const AtomicU64;
+    let len = map.len() / 8;
+    Self {
+      _map: map,
+      words,
+      len,
+      capacity,
+    }
+  }
+
+  fn num_words(capacity: usize) -> usize {
+    HEADER_WORDS + MAX_ENDPOINTS * ENDPOINT_WORDS + capacity * SLOT_WORDS
+  }
+
+  fn word(&self, index: usize) -> &AtomicU64 {
+    assert!(index < self.len);
+    // The map is page aligned, and it lives as long as self
+    unsafe { &*self.words.add(index) }
+  }
+
+  fn endpoint_word(&self, index: usize, offset: usize) -> &AtomicU64 {
+    self.word(HEADER_WORDS + index * ENDPOINT_WORDS + offset)
+  }
+
+  fn slot_word(&self, index: u64, offset: usize) -> &AtomicU64 {
+    let slot = (index % self.capacity as u64) as usize;
+    self.word(HEADER_WORDS + MAX_ENDPOINTS * ENDPOINT_WORDS + slot * SLOT_WORDS + offset)
+  }
+
+  /// Adds an endpoint to the table, returning its index there.
+  pub fn register(
+    &self,
+    kind: EndpointKind,
+    id: EndpointId,
+    name: &str,
+    owner: u64,
+  ) -> Result<usize> {
+    if self.endpoints().iter().any(|endpoint| endpoint.id == id) {
+      return Err(Error::new(
+        ErrorKind::AlreadyExists,
+        "Endpoint already exists",
+      ));
+    }
+
+    for index in 0..MAX_ENDPOINTS {
+      let state_word = self.endpoint_word(index, ENDPOINT_STATE);
+      let state = state_word.load(Ordering::Acquire);
+      if state & KIND_MASK != KIND_FREE {
+        continue;
+      }
+      let writing = (state & !KIND_MASK) + GENERATION_UNIT + KIND_WRITING;
+      if state_word
+        .compare_exchange(state, writing, Ordering::AcqRel, Ordering::Relaxed)
+        .is_ok()
+      {
+        self
+          .endpoint_word(index, ENDPOINT_ID)
+          .store(id, Ordering::Relaxed);
+        self
+          .endpoint_word(index, ENDPOINT_OWNER)
+          .store(owner, Ordering::Relaxed);
+        for (offset, word) in encode_name(name).iter().enumerate() {
+          self
+            .endpoint_word(index, ENDPOINT_NAME + offset)
+            .store(*word, Ordering::Relaxed);
+        }
+        let kind = match kind {
+          EndpointKind::Source => KIND_SOURCE,
+          EndpointKind::Destination => KIND_DESTINATION,
+        };
+        state_word.store(
+          (writing & !KIND_MASK) + GENERATION_UNIT + kind,
+          Ordering::Release,
+        );
+        return Ok(index);
+      }
+    }
+
+    Err(Error::new(ErrorKind::Other, "Too many endpoints"))
+  }
+
+  pub fn unregister(&self, index: usize) {
+    let state_word = self.endpoint_word(index, ENDPOINT_STATE);
+    let state = state_word.load(Ordering::Relaxed);
+    state_word.store(
+      (state & !KIND_MASK) + GENERATION_UNIT + KIND_FREE,
+      Ordering::Release,
+    );
+  }
+
+  pub fn endpoints(&self) -> Vec<BusEndpoint> {
+    let mut endpoints = Vec::new();
+    for index in 0..MAX_ENDPOINTS {
+      let state_word = self.endpoint_word(index, ENDPOINT_STATE);
+      let state = state_word.load(Ordering::Acquire);
+      let kind = match state & KIND_MASK {
+        KIND_SOURCE => EndpointKind::Source,
+        KIND_DESTINATION => EndpointKind::Destination,
+        _ => continue,
+      };
+      let id = self
+        .endpoint_word(index, ENDPOINT_ID)
+        .load(Ordering::Relaxed);
+      let owner = self
+        .endpoint_word(index, ENDPOINT_OWNER)
+        .load(Ordering::Relaxed);
+      let mut name = [0u64; NAME_WORDS];
+      for (offset, word) in name.iter_mut().enumerate() {
+        *word = self
+          .endpoint_word(index, ENDPOINT_NAME + offset)
+          .load(Ordering::Relaxed);
+      }
+      fence(Ordering::Acquire);
+      if state_word.load(Ordering::Relaxed) == state {
+        endpoints.push(BusEndpoint {
+          id,
+          owner,
+          kind,
+          name: decode_name(&name),
+        });
+      }
+    }
+    endpoints
+  }
+
+  /// Broadcasts a packet of up to `MAX_PACKET_WORDS` UMP words.
+  pub fn write(&self, endpoint: EndpointId, timestamp: TimestampNanos, ump: &[u32]) {
+    debug_assert!(ump.len() <= MAX_PACKET_WORDS);
+    let len = ump.len().min(MAX_PACKET_WORDS);
+    let index = self.word(WRITE_INDEX_WORD).fetch_add(1, Ordering::AcqRel);
+
+    let sequence = self.slot_word(index, SLOT_SEQUENCE);
+    sequence.store(0, Ordering::Relaxed);
+    fence(Ordering::Release);
+
+    self
+      .slot_word(index, SLOT_ENDPOINT)
+      .store(endpoint, Ordering::Relaxed);
+    self
+      .slot_word(index, SLOT_TIMESTAMP)
+      .store(timestamp, Ordering::Relaxed);
+    self
+      .slot_word(index, SLOT_LEN)
+      .store(len as u64, Ordering::Relaxed);
+    let mut data = [0u32; MAX_PACKET_WORDS];
+    data[..len].copy_from_slice(&ump[..len]);
+    for offset in 0..MAX_PACKET_WORDS / 2 {
+      let word = ((data[offset * 2] as u64) << 32) | data[offset * 2 + 1] as u64;
+      self
+        .slot_word(index, SLOT_DATA + offset)
+        .store(word, Ordering::Relaxed);
+    }
+
+    sequence.store(index + 1, Ordering::Release);
+  }
+
+  /// Creates a reader for the packets written from now on.
+  pub fn reader(&self) -> BusReader {
+    BusReader {
+      next: self.word(WRITE_INDEX_WORD).load(Ordering::Acquire),
+    }
+  }
+
+  /// Calls `f` for every packet written since the last read, returning how many were read.
+  ///
+  /// When the reader falls behind more than the capacity of the ring buffer,
+  /// the overwritten packets are lost.
+  pub fn read<F>(&self, reader: &mut BusReader, mut f: F) -> usize
+  where
+    F: FnMut(EndpointId, TimestampNanos, &[u32]),
+  {
+    let mut count = 0;
+    loop {
+      let write_index = self.word(WRITE_INDEX_WORD).load(Ordering::Acquire);
+      if reader.next >= write_index {
+        break;
+      }
+      let oldest = write_index.saturating_sub(self.capacity as u64);
+      if reader.next < oldest {
+        reader.next = oldest;
+      }
+
+      let index = reader.next;
+      let sequence = self.slot_word(index, SLOT_SEQUENCE);
+      let expected = index + 1;
+      let before = sequence.load(Ordering::Acquire);
+      match before.cmp(&expected) {
+        // Still being written
+        std::cmp::Ordering::Less => break,
+        // Overwritten by a writer in a later lap
+        std::cmp::Ordering::Greater => {
+          reader.next += 1;
+          continue;
+        }
+        std::cmp::Ordering::Equal => {}
+      }
+
+      let endpoint = self.slot_word(index, SLOT_ENDPOINT).load(Ordering::Relaxed);
+      let timestamp = self
+        .slot_word(index, SLOT_TIMESTAMP)
+        .load(Ordering::Relaxed);
+      let len = self.slot_word(index, SLOT_LEN).load(Ordering::Relaxed) as usize;
+      let mut data = [0u32; MAX_PACKET_WORDS];
+      for offset in 0..MAX_PACKET_WORDS / 2 {
+        let word = self
+          .slot_word(index, SLOT_DATA + offset)
+          .load(Ordering::Relaxed);
+        data[offset * 2] = (word >> 32) as u32;
+        data[offset * 2 + 1] = word as u32;
+      }
+      fence(Ordering::Acquire);
+
+      reader.next += 1;
+      if sequence.load(Ordering::Relaxed) == before {
+        f(endpoint, timestamp, &data[..len.min(MAX_PACKET_WORDS)]);
+        count += 1;
+      }
+    }
+    count
+  }
+}
+
+/// Position of a process in the ring buffer
+pub struct BusReader {
+  next: u64,
+}
+
+/// Packs the name in words, truncating it to `MAX_NAME_LEN` bytes
+fn encode_name(name: &str) -> [u64; NAME_WORDS] {
+  let mut len = name.len().min(MAX_NAME_LEN);
+  while !name.is_char_boundary(len) {
+    len -= 1;
+  }
+  let mut bytes = [0u8; MAX_NAME_LEN];
+  bytes[..len].copy_from_slice(&name.as_bytes()[..len]);
+
+  let mut words = [0u64; NAME_WORDS];
+  for (word, chunk) in words.iter_mut().zip(bytes.chunks(8)) {
+    let mut word_bytes = [0u8; 8];
+    word_bytes.copy_from_slice(chunk);
+    *word = u64::from_le_bytes(word_bytes);
+  }
+  words
+}
+
+fn decode_name(words: &[u64; NAME_WORDS]) -> String {
+  let bytes = words
+    .iter()
+    .flat_map(|word| word.to_le_bytes())
+    .take_while(|byte| *byte != 0)
+    .collect::<Vec<u8>>();
+  String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn bus_path(name: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!("kiro-midi-bus-{}-{}", std::process::id(), name));
+    std::fs::remove_file(&path).ok();
+    path
+  }
+
+  #[test]
+  fn names() {
+    assert_eq!(decode_name(&encode_name("Synth")), "Synth");
+    assert_eq!(decode_name(&encode_name(&"ü".repeat(40))), "ü".repeat(32));
+  }
+
+  #[test]
+  fn endpoints_are_shared() {
+    let path = bus_path("endpoints");
+    let bus1 = Bus::open(&path, 16).unwrap();
+    let bus2 = Bus::open(&path, 1024).unwrap();
+
+    let index = bus1.register(EndpointKind::Source, 1, "Keys", 10).unwrap();
+    bus2
+      .register(EndpointKind::Destination, 2, "Synth", 20)
+      .unwrap();
+    assert!(bus2.register(EndpointKind::Source, 1, "Keys", 20).is_err());
+
+    assert_eq!(
+      bus2.endpoints(),
+      vec![
+        BusEndpoint {
+          id: 1,
+          owner: 10,
+          kind: EndpointKind::Source,
+          name: "Keys".to_string()
+        },
+        BusEndpoint {
+          id: 2,
+          owner: 20,
+          kind: EndpointKind::Destination,
+          name: "Synth".to_string()
+        },
+      ]
+    );
+
+    bus1.unregister(index);
+    assert_eq!(bus2.endpoints().len(), 1);
+    assert_eq!(bus2.capacity, 16);
+
+    std::fs::remove_file(&path).ok();
+  }
+
+  #[test]
+  fn slow_readers_lose_the_oldest_packets() {
+    let path = bus_path("overrun");
+    let bus = Bus::open(&path, 4).unwrap();
+    let mut reader = bus.reader();
+
+    for index in 0..6u32 {
+      bus.write(1, index as u64, &[index, 0, 0, 0]);
+    }
+
+    let mut packets = Vec::new();
+    let count = bus.read(&mut reader, |endpoint, timestamp, ump| {
+      packets.push((endpoint, timestamp, ump.to_vec()))
+    });
+
+    assert_eq!(count, 4);
+    assert_eq!(
+      packets
+        .iter()
+        .map(|(_, timestamp, _)| *timestamp)
+        .collect::<Vec<_>>(),
+      vec![2, 3, 4, 5]
+    );
+    assert_eq!(packets[0].2, vec![2, 0, 0, 0]);
+    assert_eq!(bus.read(&mut reader, |_, _, _| {}), 0);
+
+    std::fs::remove_file(&path).ok();
+  }
+}
@@ -0,0 +1,34 @@
+use std::path::PathBuf;
+
+pub const DEFAULT_CAPACITY: usize = 4096;
+
+#[derive(Debug, Clone)]
+pub struct SharedMemoryConfig {
+  /// File backing the shared memory, which should be the same for all the processes.
+  /// A path in a tmpfs (like `/dev/shm` in Linux) avoids any disk activity.
+  pub path: PathBuf,
+  /// Number of packets that the ring buffer can hold before the slow readers lose data.
+  /// It is only used by the process creating the file.
+  pub capacity: usize,
+}
+
+impl SharedMemoryConfig {
+  pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+    Self {
+      path: path.into(),
+      capacity: DEFAULT_CAPACITY,
+    }
+  }
+
+  #[must_use]
+  pub fn with_capacity(mut self, capacity: usize) -> Self {
+    self.capacity = capacity;
+    self
+  }
+}
+
+impl Default for SharedMemoryConfig {
+  fn default() -> Self {
+    Self::new(std::env::temp_dir().join("kiro-midi.shm"))
+  }
+}
@@ -0,0 +1,547 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use parking_lot::Mutex;
+use thiserror::Error;
+
+use crate::destination_match::DestinationMatches;
+use crate::drivers;
+use crate::drivers::endpoints;
+use crate::drivers::inputs::Inputs;
+use crate::drivers::outputs::{DestinationSender, Outputs};
+use crate::drivers::shm::bus::{Bus, BusEndpoint, EndpointKind, MAX_ENDPOINTS, MAX_PACKET_WORDS};
+use crate::drivers::shm::config::SharedMemoryConfig;
+use crate::drivers::thru::Thru;
+use crate::drivers::Capabilities;
+use crate::endpoints::{DestinationId, DestinationInfo, EndpointId, SourceId, SourceInfo};
+use crate::event::TimestampNanos;
+use crate::filter::Filter;
+use crate::input_config::InputConfig;
+use crate::input_handler::InputHandler;
+use crate::input_info::InputInfo;
+use crate::output::Output;
+use crate::output_config::OutputConfig;
+use crate::output_connection::OutputConnectionHandler;
+use crate::protocol::messages::Message;
+use crate::source_match::SourceMatches;
+use crate::transform::Transform;
+
+const POLL_INTERVAL: Duration = Duration::from_micros(500);
+const ENDPOINTS_INTERVAL: Duration = Duration::from_millis(100);
+
+type Endpoints = endpoints::Endpoints<(), ()>;
+
+/// Distinguishes the drivers of the same process
+static NEXT_DRIVER: AtomicU32 = AtomicU32::new(0);
+
+#[derive(Error, Debug)]
+pub enum SharedMemoryError {
+  #[error("Error opening the shared memory at {0}: {1}")]
+  Open(PathBuf, std::io::Error),
+
+  #[error("A shared memory endpoint with this name already exists: {0}")]
+  EndpointAlreadyExists(String),
+
+  #[error("There are already {} shared memory endpoints", MAX_ENDPOINTS)]
+  TooManyEndpoints,
+
+  #[error("Shared memory endpoint not found: {0:016x}")]
+  EndpointNotFound(EndpointId),
+
+  #[error("Error creating the shared memory thread: {0}")]
+  Thread(std::io::Error),
+}
+
+/// Connects processes in the same host through a ring buffer in shared memory.
+///
+/// The virtual sources created by any process show up as sources in all of them,
+/// and the virtual destinations as destinations in the other processes, and as sources
+/// in the process that created them, where the data sent to them is received.
+///
+/// The endpoints are removed when the driver is dropped, so the ones from processes
+/// that crashed stay around until the file is deleted.
+pub struct SharedMemoryDriver {
+  bus: Arc<Bus>,
+  owner: u64,
+  owned: Mutex<HashMap<EndpointId, usize>>,
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  outputs: Arc<Mutex<Outputs>>,
+  running: Arc<AtomicBool>,
+  receiver: Option<JoinHandle<()>>,
+}
+
+impl drivers::DriverSpec for SharedMemoryDriver {
+  fn create_input<H>(&mut self, config: InputConfig, handler: H) -> Result<String, drivers::Error>
+  where
+    H: Into<InputHandler>,
+  {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .create(config, handler.into(), endpoints.connected_source_names())
+  }
+
+  fn sources(&self) -> Vec<SourceInfo> {
+    let endpoints = self.endpoints.lock();
+    self.inputs.lock().source_infos(&endpoints)
+  }
+
+  fn destinations(&self) -> Vec<DestinationInfo> {
+    self.endpoints.lock().destination_infos()
+  }
+
+  fn inputs(&self) -> Vec<InputInfo> {
+    self.inputs.lock().infos()
+  }
+
+  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
+    self.inputs.lock().config(name)
+  }
+
+  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    self
+      .inputs
+      .lock()
+      .set_sources(name, sources, endpoints.connected_source_names())
+  }
+
+  fn remove_input(&self, name: &str) -> Result<(), drivers::Error> {
+    self.inputs.lock().remove(name)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      virtual_endpoints: true,
+      output: true,
+      ump_native: true,
+      hotplug: true,
+      source_merging: true,
+      ..Capabilities::default()
+    }
+  }
+
+  fn create_virtual_source(&mut self, name: &str) -> Result<EndpointId, drivers::Error> {
+    self.register(EndpointKind::Source, name)
+  }
+
+  fn create_virtual_destination(&mut self, name: &str) -> Result<SourceId, drivers::Error> {
+    self.register(EndpointKind::Destination, name)
+  }
+
+  fn create_output(&mut self, config: OutputConfig) -> Result<Output, drivers::Error> {
+    let (output, pending_ranges) = {
+      let endpoints = self.endpoints.lock();
+      let mut outputs = self.outputs.lock();
+      let output = outputs.create(config, endpoints.connected_destination_names())?;
+      (output, outputs.take_pending_ranges())
+    };
+    pending_ranges.send();
+    Ok(output)
+  }
+
+  fn set_output_destinations(
+    &self,
+    name: &str,
+    destinations: DestinationMatches,
+  ) -> Result<(), drivers::Error> {
+    let pending_ranges = {
+      let endpoints = self.endpoints.lock();
+      let mut outputs = self.outputs.lock();
+      outputs.set_destinations(name, destinations, endpoints.connected_destination_names())?;
+      outputs.take_pending_ranges()
+    };
+    pending_ranges.send();
+    Ok(())
+  }
+  fn set_output_connection_handler(&mut self, handler: OutputConnectionHandler) {
+    self.outputs.lock().set_connection_handler(handler);
+  }
+
+  fn set_broadcast_filter(&mut self, filter: Filter) {
+    self.outputs.lock().set_broadcast_filter(filter);
+  }
+
+  fn broadcast(&self, message: Message) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.lock();
+    let destinations = endpoints
+      .connected_destinations()
+      .into_iter()
+      .map(|destination| destination.id);
+    self.outputs.lock().broadcast_message(message, destinations);
+    Ok(())
+  }
+
+  fn panic_all(&self) {
+    self.outputs.lock().panic_all();
+  }
+
+  fn create_thru(
+    &mut self,
+    input: &str,
+    output: &str,
+    transform: Transform,
+  ) -> Result<(), drivers::Error> {
+    let output = self.outputs.lock().get(output)?;
+    self
+      .inputs
+      .lock()
+      .add_thru(input, Thru::new(output, transform))
+  }
+}
+
+impl SharedMemoryDriver {
+  pub fn new(name: &str, config: SharedMemoryConfig) -> Result<Self, drivers::Error> {
+    let bus = Bus::open(&config.path, config.capacity)
+      .map_err(|error| SharedMemoryError::Open(config.path.clone(), error))?;
+    let bus = Arc::new(bus);
+    let owner =
+      ((std::process::id() as u64) << 32) | NEXT_DRIVER.fetch_add(1, Ordering::Relaxed) as u64;
+    let endpoints = Arc::new(Mutex::new(Endpoints::new()));
+    let inputs = Inputs::shared();
+    let outputs = Arc::new(Mutex::new(Outputs::new(BusSender { bus: bus.clone() })));
+    let running = Arc::new(AtomicBool::new(true));
+
+    let receiver = Receiver {
+      bus: bus.clone(),
+      owner,
+      endpoints: endpoints.clone(),
+      inputs: inputs.clone(),
+      outputs: outputs.clone(),
+      running: running.clone(),
+    };
+    receiver.sync_endpoints();
+
+    let receiver = std::thread::Builder::new()
+      .name(format!("{}-shm", name))
+      .spawn(move || receiver.run())
+      .map_err(SharedMemoryError::Thread)?;
+
+    Ok(Self {
+      bus,
+      owner,
+      owned: Mutex::new(HashMap::new()),
+      endpoints,
+      inputs,
+      outputs,
+      running,
+      receiver: Some(receiver),
+    })
+  }
+
+  /// Removes a virtual endpoint created by this driver.
+  pub fn remove_virtual_endpoint(&mut self, id: EndpointId) -> Result<(), drivers::Error> {
+    let index = self
+      .owned
+      .lock()
+      .remove(&id)
+      .ok_or(SharedMemoryError::EndpointNotFound(id))?;
+    self.bus.unregister(index);
+    self.sync_endpoints();
+    Ok(())
+  }
+
+  /// Sends UMP words from a virtual source of this driver, or to a destination of another process.
+  pub fn send(
+    &self,
+    endpoint: EndpointId,
+    timestamp: TimestampNanos,
+    ump: &[u32],
+  ) -> Result<(), drivers::Error> {
+    let is_destination = self.endpoints.lock().get_destination(endpoint).is_some();
+    if !is_destination && !self.owned.lock().contains_key(&endpoint) {
+      return Err(SharedMemoryError::EndpointNotFound(endpoint).into());
+    }
+
+    write_packets(&self.bus, endpoint, timestamp, ump);
+    Ok(())
+  }
+
+  fn register(&mut self, kind: EndpointKind, name: &str) -> Result<EndpointId, drivers::Error> {
+    let id = endpoints::hashed_id(name);
+    let index = self
+      .bus
+      .register(kind, id, name, self.owner)
+      .map_err(|error| match error.kind() {
+        std::io::ErrorKind::AlreadyExists => {
+          SharedMemoryError::EndpointAlreadyExists(name.to_string())
+        }
+        _ => SharedMemoryError::TooManyEndpoints,
+      })?;
+    self.owned.lock().insert(id, index);
+    self.sync_endpoints();
+    Ok(id)
+  }
+
+  fn sync_endpoints(&self) {
+    sync_endpoints(
+      &self.bus,
+      self.owner,
+      &self.endpoints,
+      &self.inputs,
+      &self.outputs,
+    )
+  }
+}
+
+impl Drop for SharedMemoryDriver {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::Relaxed);
+    if let Some(receiver) = self.receiver.take() {
+      receiver.join().ok();
+    }
+    for (_, index) in self.owned.lock().drain() {
+      self.bus.unregister(index);
+    }
+  }
+}
+
+struct Receiver {
+  bus: Arc<Bus>,
+  owner: u64,
+  endpoints: Arc<Mutex<Endpoints>>,
+  inputs: Arc<Mutex<Inputs>>,
+  outputs: Arc<Mutex<Outputs>>,
+  running: Arc<AtomicBool>,
+}
+
+impl Receiver {
+  fn run(self) {
+    let mut reader = self.bus.reader();
+    let mut last_endpoints_update = Instant::now();
+
+    while self.running.load(Ordering::Relaxed) {
+      if last_endpoints_update.elapsed() >= ENDPOINTS_INTERVAL {
+        last_endpoints_update = Instant::now();
+        self.sync_endpoints();
+      }
+
+      let count = {
+        let mut inputs = self.inputs.lock();
+        let count = self.bus.read(&mut reader, |endpoint, timestamp, ump| {
+          inputs.dispatch(endpoint, timestamp, ump)
+        });
+        inputs.flush();
+        count
+      };
+
+      if count == 0 {
+        std::thread::sleep(POLL_INTERVAL);
+      }
+    }
+  }
+
+  fn sync_endpoints(&self) {
+    sync_endpoints(
+      &self.bus,
+      self.owner,
+      &self.endpoints,
+      &self.inputs,
+      &self.outputs,
+    )
+  }
+}
+
+struct BusSender {
+  bus: Arc<Bus>,
+}
+
+impl DestinationSender for BusSender {
+  fn send(&self, destination: DestinationId, timestamp: TimestampNanos, ump: &[u32]) {
+    write_packets(&self.bus, destination, timestamp, ump);
+  }
+}
+
+fn write_packets(bus: &Bus, endpoint: EndpointId, timestamp: TimestampNanos, ump: &[u32]) {
+  for packet in ump.chunks(MAX_PACKET_WORDS) {
+    bus.write(endpoint, timestamp, packet);
+  }
+}
+
+/// Updates the endpoints of a driver from the table in the bus.
+///
+/// The sources are the virtual sources from all the processes, plus the virtual destinations
+/// owned by the driver, while the destinations are the ones owned by others.
+fn sync_endpoints(
+  bus: &Bus,
+  owner: u64,
+  endpoints: &Mutex<Endpoints>,
+  inputs: &Mutex<Inputs>,
+  outputs: &Mutex<Outputs>,
+) {
+  let (sources, destinations): (Vec<BusEndpoint>, Vec<BusEndpoint>) = bus
+    .endpoints()
+    .into_iter()
+    .partition(|endpoint| endpoint.kind == EndpointKind::Source || endpoint.owner == owner);
+
+  let mut endpoints = endpoints.lock();
+  let mut inputs = inputs.lock();
+
+  let available_sources = sources
+    .iter()
+    .map(|source| source.id)
+    .collect::<HashSet<_>>();
+  let removed_sources = endpoints
+    .connected_sources()
+    .into_iter()
+    .map(|connected_source| connected_source.id)
+    .filter(|id| !available_sources.contains(id))
+    .collect::<Vec<_>>();
+  for id in removed_sources {
+    endpoints.remove_source_by_id(id);
+    inputs.disconnect_source(id);
+  }
+  for source in sources {
+    if endpoints.get_source(source.id).is_none() {
+      inputs.connect_source(source.id, source.name.as_str(), source.name.as_str());
+      endpoints.add_source(source.id, source.name, ());
+    }
+  }
+
+  let available_destinations = destinations
+    .iter()
+    .map(|destination| destination.id)
+    .collect::<HashSet<_>>();
+  let removed_destinations = endpoints
+    .connected_destinations()
+    .into_iter()
+    .map(|connected_destination| connected_destination.id)
+    .filter(|id| !available_destinations.contains(id))
+    .collect::<Vec<_>>();
+  let mut outputs = outputs.lock();
+  for id in removed_destinations {
+    endpoints.remove_destination_by_id(id);
+    outputs.disconnect_destination(id);
+  }
+  for destination in destinations {
+    if endpoints.get_destination(destination.id).is_none() {
+      outputs.connect_destination(
+        destination.id,
+        destination.name.as_str(),
+        destination.name.as_str(),
+      );
+      endpoints.add_destination(destination.id, destination.name, ());
+    }
+  }
+
+  let pending_ranges = outputs.take_pending_ranges();
+  drop(outputs);
+  drop(inputs);
+  drop(endpoints);
+  pending_ranges.send();
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::mpsc;
+
+  use super::*;
+  use crate::drivers::DriverSpec;
+  use crate::event::Event;
+  use crate::filter::Filter;
+
+  fn config(name: &str) -> SharedMemoryConfig {
+    let path = std::env::temp_dir().join(format!("kiro-midi-shm-{}-{}", std::process::id(), name));
+    std::fs::remove_file(&path).ok();
+    SharedMemoryConfig::new(path).with_capacity(64)
+  }
+
+  fn receiver(driver: &mut SharedMemoryDriver, source: &str) -> mpsc::Receiver<Event> {
+    let (sender, receiver) = mpsc::channel();
+    let config = InputConfig::new(source).with_source(source, Filter::default());
+    driver
+      .create_input(config, move |event| sender.send(event).unwrap())
+      .unwrap();
+    receiver
+  }
+
+  fn wait_until<F: Fn() -> bool>(condition: F) {
+    let start = Instant::now();
+    while !condition() {
+      assert!(start.elapsed() < Duration::from_secs(5), "Timed out");
+      std::thread::sleep(Duration::from_millis(10));
+    }
+  }
+
+  #[test]
+  fn virtual_sources_are_shared() {
+    let config = config("sources");
+    let mut ui = SharedMemoryDriver::new("ui", config.clone()).unwrap();
+    let mut engine = SharedMemoryDriver::new("engine", config.clone()).unwrap();
+
+    let keys = ui.create_virtual_source("keys").unwrap();
+    wait_until(|| engine.sources().len() == 1);
+    assert_eq!(engine.sources()[0].id, keys);
+    assert!(engine.destinations().is_empty());
+
+    let events = receiver(&mut engine, "keys");
+    ui.send(keys, 10, &[0x2090_3c64]).unwrap();
+
+    let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(event.timestamp, 10);
+    assert_eq!(event.endpoint, keys);
+
+    drop(ui);
+    wait_until(|| engine.sources().is_empty());
+    std::fs::remove_file(&config.path).ok();
+  }
+
+  #[test]
+  fn virtual_destinations_receive_from_other_processes() {
+    let config = config("destinations");
+    let mut ui = SharedMemoryDriver::new("ui", config.clone()).unwrap();
+    let mut engine = SharedMemoryDriver::new("engine", config.clone()).unwrap();
+
+    let synth = engine.create_virtual_destination("synth").unwrap();
+    assert_eq!(engine.sources()[0].id, synth);
+    assert!(engine.destinations().is_empty());
+    wait_until(|| ui.destinations().len() == 1);
+    assert!(ui.sources().is_empty());
+
+    let events = receiver(&mut engine, "synth");
+    ui.send(synth, 20, &[0x4090_3c00, 0x8000_0000]).unwrap();
+
+    let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(event.timestamp, 20);
+    assert_eq!(event.endpoint, synth);
+
+    let output = ui
+      .create_output(OutputConfig::new("out").with_destination("synth"))
+      .unwrap();
+    assert_eq!(output.connected_destinations(), vec![synth]);
+    output.send(event.message);
+    assert_eq!(
+      events.recv_timeout(Duration::from_secs(5)).unwrap().message,
+      event.message
+    );
+    std::fs::remove_file(&config.path).ok();
+  }
+
+  #[test]
+  fn send_to_unknown_endpoint_fails() {
+    let config = config("unknown");
+    let mut driver = SharedMemoryDriver::new("test", config.clone()).unwrap();
+    let keys = driver.create_virtual_source("keys").unwrap();
+
+    assert!(matches!(
+      driver.create_virtual_source("keys"),
+      Err(drivers::Error::SharedMemory(
+        SharedMemoryError::EndpointAlreadyExists(_)
+      ))
+    ));
+    driver.remove_virtual_endpoint(keys).unwrap();
+    assert!(matches!(
+      driver.send(keys, 0, &[0x2090_3c64]),
+      Err(drivers::Error::SharedMemory(
+        SharedMemoryError::EndpointNotFound(_)
+      ))
+    ));
+    std::fs::remove_file(&config.path).ok();
+  }
+}
@@ -0,0 +1,6 @@
+mod bus;
+mod config;
+mod driver;
+
+pub use config::SharedMemoryConfig;
+pub use driver::{SharedMemoryDriver, SharedMemoryError};
@@ -0,0 +1,21 @@
+use crate::event::Event;
+use crate::output::Output;
+use crate::transform::Transform;
+
+/// Routes the events received by an input to an output, from the thread receiving them
+pub struct Thru {
+  output: Output,
+  transform: Transform,
+}
+
+impl Thru {
+  pub fn new(output: Output, transform: Transform) -> Self {
+    Self { output, transform }
+  }
+
+  pub fn send(&self, event: &Event) {
+    for message in self.transform.apply_layered(event.message) {
+      self.output.send(message);
+    }
+  }
+}
@@ -0,0 +1,294 @@
+use std::cell::{Cell, RefCell};
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use thiserror::Error;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+  MidiAccess, MidiConnectionEvent, MidiInput, MidiMessageEvent, MidiOutput, MidiPort,
+  MidiPortDeviceState, MidiPortType,
+};
+
+use crate::drivers;
+use crate::drivers::endpoints;
+use crate::drivers::inputs::Inputs;
+use crate::drivers::Capabilities;
+use crate::endpoints::{DestinationInfo, EndpointId, SourceId, SourceInfo};
+use crate::event::{self, TimestampNanos};
+use crate::input_config::InputConfig;
+use crate::input_handler::InputHandler;
+use crate::input_info::InputInfo;
+use crate::protocol::midi1;
+use crate::source_match::SourceMatches;
+
+type Endpoints = endpoints::Endpoints<MidiInput, MidiOutput>;
+
+type MessageCallback = Closure<dyn FnMut(MidiMessageEvent)>;
+
+#[derive(Error, Debug)]
+pub enum WebMidiError {
+  #[error("The browser window is not available")]
+  WindowNotAvailable,
+
+  #[error("Error requesting access to the MIDI devices: {0}")]
+  AccessRequest(String),
+}
+
+/// Delivers the values kept by the rate limiters once the sources stop sending,
+/// from a timeout of the browser, as there are no threads to wait in.
+#[derive(Clone, Default)]
+struct PendingTimeout(Rc<Cell<Option<(TimestampNanos, i32)>>>);
+
+impl PendingTimeout {
+  fn schedule(&self, inputs: &Rc<RefCell<Inputs>>, deadline: TimestampNanos) {
+    let window = match web_sys::window() {
+      Some(window) => window,
+      None => return,
+    };
+    if let Some((scheduled, handle)) = self.0.get() {
+      if scheduled <= deadline {
+        return;
+      }
+      window.clear_timeout_with_handle(handle);
+    }
+
+    let delay_millis = deadline.saturating_sub(event::now()) / 1_000_000 + 1;
+    let (timeout, inputs) = (self.clone(), inputs.clone());
+    let callback = Closure::once_into_js(move || {
+      timeout.0.set(None);
+      let next_deadline = inputs.borrow_mut().deliver_pending(event::now());
+      if let Some(next_deadline) = next_deadline {
+        timeout.schedule(&inputs, next_deadline);
+      }
+    });
+    if let Ok(handle) = window.set_timeout_with_callback_and_timeout_and_arguments_0(
+      callback.unchecked_ref(),
+      delay_millis as i32,
+    ) {
+      self.0.set(Some((deadline, handle)));
+    }
+  }
+}
+
+pub struct WebMidiDriver {
+  access: MidiAccess,
+  endpoints: Rc<RefCell<Endpoints>>,
+  inputs: Rc<RefCell<Inputs>>,
+  callbacks: Rc<RefCell<HashMap<SourceId, MessageCallback>>>,
+  _state_change_callback: Closure<dyn FnMut(MidiConnectionEvent)>,
+}
+
+impl drivers::DriverSpec for WebMidiDriver {
+  fn create_input<H>(&mut self, config: InputConfig, handler: H) -> Result<String, drivers::Error>
+  where
+    H: Into<InputHandler>,
+  {
+    let endpoints = self.endpoints.borrow();
+    self
+      .inputs
+      .borrow_mut()
+      .create(config, handler.into(), endpoints.connected_source_names())
+  }
+
+  fn sources(&self) -> Vec<SourceInfo> {
+    self.inputs.borrow().source_infos(&self.endpoints.borrow())
+  }
+
+  fn destinations(&self) -> Vec<DestinationInfo> {
+    self.endpoints.borrow().destination_infos()
+  }
+
+  fn inputs(&self) -> Vec<InputInfo> {
+    self.inputs.borrow().infos()
+  }
+
+  fn get_input_config(&self, name: &str) -> Option<InputConfig> {
+    self.inputs.borrow().config(name)
+  }
+
+  fn set_input_sources(&self, name: &str, sources: SourceMatches) -> Result<(), drivers::Error> {
+    let endpoints = self.endpoints.borrow();
+    self
+      .inputs
+      .borrow_mut()
+      .set_sources(name, sources, endpoints.connected_source_names())
+  }
+
+  fn remove_input(&self, name: &str) -> Result<(), drivers::Error> {
+    self.inputs.borrow_mut().remove(name)
+  }
+
+  fn capabilities(&self) -> Capabilities {
+    Capabilities {
+      hotplug: true,
+      ..Capabilities::default()
+    }
+  }
+}
+
+impl WebMidiDriver {
+  /// The name is not used, as the Web MIDI API doesn't have the concept of clients.
+  pub async fn new(_name: &str) -> Result<Self, drivers::Error> {
+    let window = web_sys::window().ok_or(WebMidiError::WindowNotAvailable)?;
+    let promise = window
+      .navigator()
+      .request_midi_access()
+      .map_err(Self::access_request_error)?;
+    let access = JsFuture::from(promise)
+      .await
+      .map_err(Self::access_request_error)?
+      .unchecked_into::<MidiAccess>();
+
+    let endpoints = Rc::new(RefCell::new(Endpoints::new()));
+    let inputs = Rc::new(RefCell::new(Inputs::new()));
+    let callbacks = Rc::new(RefCell::new(HashMap::new()));
+
+    let state_change_callback =
+      Self::state_change_callback(endpoints.clone(), inputs.clone(), callbacks.clone());
+    access.set_onstatechange(Some(state_change_callback.as_ref().unchecked_ref()));
+
+    for input in Self::ports::<MidiInput>(&access.inputs()) {
+      Self::handle_source_connected(&endpoints, &inputs, &callbacks, input);
+    }
+    for output in Self::ports::<MidiOutput>(&access.outputs()) {
+      Self::handle_destination_connected(&endpoints, output);
+    }
+
+    Ok(Self {
+      access,
+      endpoints,
+      inputs,
+      callbacks,
+      _state_change_callback: state_change_callback,
+    })
+  }
+
+  fn access_request_error(error: JsValue) -> WebMidiError {
+    WebMidiError::AccessRequest(format!("{:?}", error))
+  }
+
+  fn state_change_callback(
+    endpoints: Rc<RefCell<Endpoints>>,
+    inputs: Rc<RefCell<Inputs>>,
+    callbacks: Rc<RefCell<HashMap<SourceId, MessageCallback>>>,
+  ) -> Closure<dyn FnMut(MidiConnectionEvent)> {
+    Closure::wrap(Box::new(move |event: MidiConnectionEvent| {
+      if let Some(port) = event.port() {
+        let connected = port.state() == MidiPortDeviceState::Connected;
+        match port.type_() {
+          MidiPortType::Input if connected => {
+            Self::handle_source_connected(&endpoints, &inputs, &callbacks, port.unchecked_into())
+          }
+          MidiPortType::Input => {
+            Self::handle_source_disconnected(&endpoints, &inputs, &callbacks, &port)
+          }
+          MidiPortType::Output if connected => {
+            Self::handle_destination_connected(&endpoints, port.unchecked_into())
+          }
+          MidiPortType::Output => Self::handle_destination_disconnected(&endpoints, &port),
+          _ => {}
+        }
+      }
+    }) as Box<dyn FnMut(MidiConnectionEvent)>)
+  }
+
+  fn message_callback(inputs: Rc<RefCell<Inputs>>, source_id: SourceId) -> MessageCallback {
+    // The events carry a single message, but they can be of any length, like the SysEx ones
+    let mut parser = midi1::Parser::new(0);
+    let timeout = PendingTimeout::default();
+    Closure::wrap(Box::new(move |event: MidiMessageEvent| {
+      if let Ok(data) = event.data() {
+        // The Web MIDI timestamps are milliseconds relative to the navigation start, as `event::now()`
+        let timestamp = (event.time_stamp() * 1_000_000.0) as TimestampNanos;
+        let mut dispatcher = inputs.borrow_mut();
+        parser.parse(data.as_slice(), |ump| {
+          dispatcher.dispatch(source_id, timestamp, ump.as_slice())
+        });
+        dispatcher.flush();
+        let next_deadline = dispatcher.next_deadline();
+        drop(dispatcher);
+        if let Some(next_deadline) = next_deadline {
+          timeout.schedule(&inputs, next_deadline);
+        }
+      }
+    }) as Box<dyn FnMut(MidiMessageEvent)>)
+  }
+
+  fn handle_source_connected(
+    endpoints: &Rc<RefCell<Endpoints>>,
+    inputs: &Rc<RefCell<Inputs>>,
+    callbacks: &Rc<RefCell<HashMap<SourceId, MessageCallback>>>,
+    input: MidiInput,
+  ) {
+    let (source_id, name) = Self::port_info(&input);
+    let mut endpoints = endpoints.borrow_mut();
+    if endpoints.get_source(source_id).is_none() {
+      let callback = Self::message_callback(inputs.clone(), source_id);
+      input.set_onmidimessage(Some(callback.as_ref().unchecked_ref()));
+      callbacks.borrow_mut().insert(source_id, callback);
+      inputs
+        .borrow_mut()
+        .connect_source(source_id, name.as_str(), name.as_str());
+      endpoints.add_source(source_id, name, input);
+    }
+  }
+
+  fn handle_source_disconnected(
+    endpoints: &Rc<RefCell<Endpoints>>,
+    inputs: &Rc<RefCell<Inputs>>,
+    callbacks: &Rc<RefCell<HashMap<SourceId, MessageCallback>>>,
+    port: &MidiPort,
+  ) {
+    let (source_id, _) = Self::port_info(port);
+    if let Some(connected_source) = endpoints.borrow_mut().remove_source_by_id(source_id) {
+      connected_source.source.set_onmidimessage(None);
+      callbacks.borrow_mut().remove(&source_id);
+      inputs.borrow_mut().disconnect_source(source_id);
+    }
+  }
+
+  fn handle_destination_connected(endpoints: &Rc<RefCell<Endpoints>>, output: MidiOutput) {
+    let (destination_id, name) = Self::port_info(&output);
+    endpoints
+      .borrow_mut()
+      .add_destination(destination_id, name, output);
+  }
+
+  fn handle_destination_disconnected(endpoints: &Rc<RefCell<Endpoints>>, port: &MidiPort) {
+    let (destination_id, _) = Self::port_info(port);
+    endpoints
+      .borrow_mut()
+      .remove_destination_by_id(destination_id);
+  }
+
+  fn ports<T: JsCast>(map: &JsValue) -> Vec<T> {
+    js_sys::try_iter(map)
+      .ok()
+      .flatten()
+      .map(|entries| {
+        entries
+          .filter_map(Result::ok)
+          .map(|entry| js_sys::Array::from(&entry).get(1).unchecked_into::<T>())
+          .collect()
+      })
+      .unwrap_or_default()
+  }
+
+  fn port_info(port: &MidiPort) -> (EndpointId, String) {
+    let port_id = port.id();
+    let name = port.name().unwrap_or_else(|| port_id.clone());
+    (endpoints::hashed_id(port_id.as_str()), name)
+  }
+}
+
+impl Drop for WebMidiDriver {
+  fn drop(&mut self) {
+    self.access.set_onstatechange(None);
+    for connected_source in self.endpoints.borrow().connected_sources() {
+      connected_source.source.set_onmidimessage(None);
+    }
+    self.callbacks.borrow_mut().clear();
+  }
+}
@@ -0,0 +1,3 @@
+mod driver;
+
+pub use driver::{WebMidiDriver, WebMidiError};
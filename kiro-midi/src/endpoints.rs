@@ -1,3 +1,5 @@
+use crate::midi_ci::DeviceInfo;
+
 pub type EndpointId = u64;
 pub type SourceId = EndpointId;
 pub type DestinationId = EndpointId;
@@ -6,15 +8,26 @@ pub type DestinationId = EndpointId;
 pub struct SourceInfo {
   pub id: SourceId,
   pub name: String,
+  /// Human readable name, which can be the same as the name for some drivers
+  pub display_name: String,
   pub connected_inputs: Vec<String>,
+  /// Identity of the device behind the source, once it replied to `identify_sources`
+  pub identity: Option<DeviceInfo>,
 }
 
 impl SourceInfo {
-  pub fn new(id: SourceId, name: String, connected_inputs: Vec<String>) -> Self {
+  pub fn new(
+    id: SourceId,
+    name: String,
+    display_name: String,
+    connected_inputs: Vec<String>,
+  ) -> Self {
     Self {
       id,
       name,
+      display_name,
       connected_inputs,
+      identity: None,
     }
   }
 }
@@ -23,10 +36,16 @@ impl SourceInfo {
 pub struct DestinationInfo {
   pub id: DestinationId,
   pub name: String,
+  /// Human readable name, which can be the same as the name for some drivers
+  pub display_name: String,
 }
 
 impl DestinationInfo {
-  pub fn new(id: DestinationId, name: String) -> Self {
-    Self { id, name }
+  pub fn new(id: DestinationId, name: String, display_name: String) -> Self {
+    Self {
+      id,
+      name,
+      display_name,
+    }
   }
 }
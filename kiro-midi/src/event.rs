@@ -4,6 +4,65 @@ use std::fmt::Formatter;
 
 pub type TimestampNanos = u64;
 
+/// Current time in the clock of the event timestamps, in nanoseconds.
+///
+/// Every driver timestamps the events it receives with this clock, so the events from different
+/// drivers (in an `AggregateDriver`, for example) can be compared, and it is the one to use for
+/// the timestamps of `Output::send_at`. It is the host time on MacOS, the time since the navigation
+/// started in the browsers (as `performance.now()`), and the time since the first call elsewhere.
+///
+/// The shm and proxy drivers are the exception, as they keep the timestamps of the process
+/// that sent the events.
+pub fn now() -> TimestampNanos {
+  clock::now()
+}
+
+#[cfg(all(target_os = "macos", feature = "coremidi"))]
+mod clock {
+  use super::TimestampNanos;
+
+  pub fn now() -> TimestampNanos {
+    crate::drivers::host_time_nanos()
+  }
+}
+
+#[cfg(all(target_arch = "wasm32", feature = "webmidi"))]
+mod clock {
+  use super::TimestampNanos;
+
+  pub fn now() -> TimestampNanos {
+    web_sys::window()
+      .and_then(|window| window.performance())
+      .map_or(0, |performance| {
+        (performance.now() * 1_000_000.0) as TimestampNanos
+      })
+  }
+}
+
+#[cfg(not(any(
+  all(target_os = "macos", feature = "coremidi"),
+  all(target_arch = "wasm32", feature = "webmidi")
+)))]
+mod clock {
+  use std::sync::Once;
+  use std::time::Instant;
+
+  use super::TimestampNanos;
+
+  static START: Once = Once::new();
+  static mut EPOCH: Option<Instant> = None;
+
+  pub fn now() -> TimestampNanos {
+    // Safety: EPOCH is only written once, before any read, guarded by START
+    let epoch = unsafe {
+      START.call_once(|| EPOCH = Some(Instant::now()));
+      EPOCH.unwrap_or_else(Instant::now)
+    };
+    epoch.elapsed().as_nanos() as TimestampNanos
+  }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Clone, PartialEq)]
 pub struct Event {
   pub timestamp: TimestampNanos,
@@ -1,19 +1,67 @@
-use std::fmt::{Debug, Formatter};
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::string::{String, ToString};
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::{Debug, Formatter};
+use core::ops::{Not, RangeInclusive};
 
-#[derive(Clone, Copy)]
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::flex_data::FlexDataAddress;
+use crate::protocol::messages::sysex7::{SysEx7, SysExId, SysExStatus};
+use crate::protocol::messages::system::System;
+use crate::protocol::messages::{Message, MessageType};
+use crate::protocol::parameters::scale_up;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone, Copy, PartialEq, Eq)]
 pub struct Filter {
   mtypes: u16,
   groups: u16,
   channels: [u16; 16],
+  /// Lowest and highest notes accepted by every channel of every group
+  notes: [[(u8, u8); 16]; 16],
+  /// Lowest and highest velocities of the note on messages, with 16 bits
+  velocities: (u16, u16),
+  /// Controller numbers accepted in the control changes, from 0 to 63 in the first mask
+  controllers: [u64; 2],
+  /// Program numbers accepted in the program changes, from 0 to 63 in the first mask
+  programs: [u64; 2],
+  /// Whether to accept the clock and transport messages
+  clock: bool,
+  /// SysEx IDs either accepted or blocked, depending on `sysex_ids_allowed`
+  sysex_ids: [Option<SysExId>; MAX_SYSEX_IDS],
+  sysex_ids_allowed: bool,
 }
 
+/// Number of SysEx IDs that a filter can allow or block
+pub const MAX_SYSEX_IDS: usize = 8;
+
 impl Filter {
-  pub fn new() -> Self {
+  pub const fn new() -> Self {
     Self {
       mtypes: 0xffff,
       groups: 0xffff,
       channels: [0xffff; 16],
+      notes: [[(0, 127); 16]; 16],
+      velocities: (0, 0xffff),
+      controllers: [u64::MAX; 2],
+      programs: [u64::MAX; 2],
+      clock: true,
+      sysex_ids: [None; MAX_SYSEX_IDS],
+      sysex_ids_allowed: false,
+    }
+  }
+
+  /// Only accepts some message types, from 0x0 (utility) to 0xf.
+  #[must_use]
+  pub fn with_mtypes(mut self, mtypes: &[u8]) -> Self {
+    self.mtypes = 0;
+    for mtype in mtypes.iter().cloned() {
+      self.mtypes |= 1 << (mtype & 0x0f);
     }
+    self
   }
 
   #[must_use]
@@ -41,6 +89,117 @@ impl Filter {
     self
   }
 
+  /// Only accepts the notes within a range in a channel (such as the notes of the pads of a drum controller).
+  ///
+  /// It applies to the messages addressed to a single note: note on and off, poly pressure,
+  /// and the MIDI 2.0 per-note controllers, management and pitch bend.
+  #[must_use]
+  pub fn with_note_range(mut self, group: u8, channel: u8, notes: RangeInclusive<u8>) -> Self {
+    if group > 0 && group <= 16 && channel > 0 && channel <= 16 {
+      self.notes[(group - 1) as usize][(channel - 1) as usize] = (*notes.start(), *notes.end());
+    }
+    self
+  }
+
+  /// Only accepts the note on messages with a velocity within a range, to drop the accidental light touches
+  /// from the pads of a controller.
+  ///
+  /// The range has the 16 bits of the MIDI 2.0 velocities, and the MIDI 1.0 ones are scaled up to compare them.
+  /// The MIDI 1.0 note on messages with velocity 0 are note offs, so they are always accepted.
+  #[must_use]
+  pub fn with_velocity_range(mut self, velocities: RangeInclusive<u16>) -> Self {
+    self.velocities = (*velocities.start(), *velocities.end());
+    self
+  }
+
+  /// Only accepts the control changes of some controllers, such as the modulation wheel and the expression.
+  #[must_use]
+  pub fn with_controllers<I>(mut self, controllers: I) -> Self
+  where
+    I: IntoIterator<Item = u8>,
+  {
+    self.controllers = [0; 2];
+    for controller in controllers {
+      let controller = controller & 0x7f;
+      self.controllers[(controller / 64) as usize] |= 1 << (controller % 64);
+    }
+    self
+  }
+
+  /// Drops the control changes of some controllers, such as the channel mode messages from 120 to 127.
+  #[must_use]
+  pub fn without_controllers<I>(mut self, controllers: I) -> Self
+  where
+    I: IntoIterator<Item = u8>,
+  {
+    for controller in controllers {
+      let controller = controller & 0x7f;
+      self.controllers[(controller / 64) as usize] &= !(1 << (controller % 64));
+    }
+    self
+  }
+
+  /// Only accepts the program changes of some programs, from 0 to 127.
+  ///
+  /// The bank select control changes sent before them with MIDI 1.0 are not dropped with them,
+  /// unless also dropped with `without_controllers([0, 32])`.
+  #[must_use]
+  pub fn with_programs<I>(mut self, programs: I) -> Self
+  where
+    I: IntoIterator<Item = u8>,
+  {
+    self.programs = [0; 2];
+    for program in programs {
+      let program = program & 0x7f;
+      self.programs[(program / 64) as usize] |= 1 << (program % 64);
+    }
+    self
+  }
+
+  /// Drops the program changes of some programs, from 0 to 127.
+  #[must_use]
+  pub fn without_programs<I>(mut self, programs: I) -> Self
+  where
+    I: IntoIterator<Item = u8>,
+  {
+    for program in programs {
+      let program = program & 0x7f;
+      self.programs[(program / 64) as usize] &= !(1 << (program % 64));
+    }
+    self
+  }
+
+  /// Only accepts the SysEx7 messages with some IDs (up to `MAX_SYSEX_IDS`),
+  /// such as `SysExId::NonRealTime(0x0d)` for MIDI-CI.
+  #[must_use]
+  pub fn with_sysex_ids(mut self, ids: &[SysExId]) -> Self {
+    self.set_sysex_ids(ids);
+    self.sysex_ids_allowed = true;
+    self
+  }
+
+  /// Drops the SysEx7 messages with some IDs (up to `MAX_SYSEX_IDS`).
+  #[must_use]
+  pub fn without_sysex_ids(mut self, ids: &[SysExId]) -> Self {
+    self.set_sysex_ids(ids);
+    self.sysex_ids_allowed = false;
+    self
+  }
+
+  fn set_sysex_ids(&mut self, ids: &[SysExId]) {
+    self.sysex_ids = [None; MAX_SYSEX_IDS];
+    for (slot, id) in self.sysex_ids.iter_mut().zip(ids.iter()) {
+      *slot = Some(*id);
+    }
+  }
+
+  /// Drops the clock and transport messages (timing clock, start, continue, stop and song position).
+  #[must_use]
+  pub fn without_clock(mut self) -> Self {
+    self.clock = false;
+    self
+  }
+
   #[inline]
   pub fn mtype(&self, mtype: u8) -> bool {
     let mtype = mtype & 0x0f;
@@ -62,6 +221,118 @@ impl Filter {
     let mask = 1 << channel;
     (self.channels[group] & mask) != 0
   }
+
+  #[inline]
+  pub fn note(&self, group: u8, channel: u8, note: u8) -> bool {
+    let (low, high) = self.notes[(group & 0x0f) as usize][(channel & 0x0f) as usize];
+    low <= note && note <= high
+  }
+
+  #[inline]
+  pub fn velocity(&self, velocity: u16) -> bool {
+    let (low, high) = self.velocities;
+    low <= velocity && velocity <= high
+  }
+
+  #[inline]
+  pub fn controller(&self, index: u8) -> bool {
+    let index = index & 0x7f;
+    let mask = 1 << (index % 64);
+    (self.controllers[(index / 64) as usize] & mask) != 0
+  }
+
+  #[inline]
+  pub fn program(&self, program: u8) -> bool {
+    let program = program & 0x7f;
+    let mask = 1 << (program % 64);
+    (self.programs[(program / 64) as usize] & mask) != 0
+  }
+
+  #[inline]
+  pub fn system(&self, system: &System) -> bool {
+    self.clock
+      || !matches!(
+        system,
+        System::TimingClock
+          | System::Start
+          | System::Continue
+          | System::Stop
+          | System::SongPositionPointer(_)
+      )
+  }
+
+  /// Messages without a known ID only pass when no IDs are allowed explicitly.
+  #[inline]
+  pub fn sysex_id(&self, id: Option<SysExId>) -> bool {
+    let listed = id.map_or(false, |id| self.sysex_ids.contains(&Some(id)));
+    listed == self.sysex_ids_allowed
+  }
+
+  /// Checks the ID of the first packet of a SysEx7 message. The rest of the packets don't have it,
+  /// so they always pass, and the decoder is the one dropping them after a blocked first packet.
+  pub(crate) fn sysex7(&self, packet: &SysEx7) -> bool {
+    match packet.status {
+      SysExStatus::Complete | SysExStatus::Start => {
+        self.sysex_id(SysExId::from_data(packet.data()))
+      }
+      SysExStatus::Continue | SysExStatus::End => true,
+    }
+  }
+
+  /// Checks the channel, the note, the velocity and the controller of a MIDI 1.0 channel voice message.
+  pub(crate) fn channel_voice1(&self, group: u8, channel_voice: &ChannelVoice1) -> bool {
+    let channel = channel_voice.channel;
+    self.channel(group, channel)
+      && channel_voice
+        .message
+        .note()
+        .map_or(true, |note| self.note(group, channel, note))
+      && match channel_voice.message {
+        ChannelVoice1Message::NoteOn { velocity, .. } if velocity > 0 => {
+          self.velocity(scale_up(velocity as u32, 7, 16) as u16)
+        }
+        ChannelVoice1Message::ControlChange { index, .. } => self.controller(index),
+        ChannelVoice1Message::ProgramChange { program } => self.program(program),
+        _ => true,
+      }
+  }
+
+  /// Checks the channel, the note, the velocity and the controller of a MIDI 2.0 channel voice message.
+  pub(crate) fn channel_voice(&self, group: u8, channel_voice: &ChannelVoice) -> bool {
+    let channel = channel_voice.channel;
+    self.channel(group, channel)
+      && channel_voice
+        .message
+        .note()
+        .map_or(true, |note| self.note(group, channel, note))
+      && match channel_voice.message {
+        ChanelVoiceMessage::NoteOn { velocity, .. } => self.velocity(velocity),
+        ChanelVoiceMessage::ControlChange { index, .. } => self.controller(index),
+        ChanelVoiceMessage::ProgramChange { program, .. } => self.program(program),
+        _ => true,
+      }
+  }
+
+  /// Checks an already decoded message, as the outputs do before sending it.
+  pub(crate) fn message(&self, message: &Message) -> bool {
+    let group = message.group;
+    let (mtype, accepted) = match &message.mtype {
+      MessageType::Utility(_) => (0x00, true),
+      MessageType::System(system) => (0x01, self.system(system)),
+      MessageType::ChannelVoice1(channel_voice) => {
+        (0x02, self.channel_voice1(group, channel_voice))
+      }
+      MessageType::ChannelVoice(channel_voice) => (0x04, self.channel_voice(group, channel_voice)),
+      MessageType::SysEx7(packet) => (0x03, self.sysex7(packet)),
+      MessageType::SysEx8(_) | MessageType::MixedDataSet(_) => (0x05, true),
+      MessageType::FlexData(flex_data) => match flex_data.address {
+        FlexDataAddress::Channel(channel) => (0x0d, self.channel(group, channel)),
+        FlexDataAddress::Group => (0x0d, true),
+      },
+    };
+
+    self.mtype(mtype) && self.group(group) && accepted
+  }
 }
 
 impl Default for Filter {
@@ -70,8 +341,34 @@ impl Default for Filter {
   }
 }
 
+impl Filter {
+  /// Accepts the messages accepted by both filters.
+  pub fn and<F>(self, other: F) -> FilterExpr
+  where
+    F: Into<FilterExpr>,
+  {
+    FilterExpr::from(self).and(other)
+  }
+
+  /// Accepts the messages accepted by any of the filters.
+  pub fn or<F>(self, other: F) -> FilterExpr
+  where
+    F: Into<FilterExpr>,
+  {
+    FilterExpr::from(self).or(other)
+  }
+}
+
+impl Not for Filter {
+  type Output = FilterExpr;
+
+  fn not(self) -> Self::Output {
+    !FilterExpr::from(self)
+  }
+}
+
 impl Debug for Filter {
-  fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+  fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
     writeln!(f, "MidiFilter:")?;
     writeln!(f, "  MT : {:016b}  GR : {:016b}", self.mtypes, self.groups)?;
     for i in 0..8 {
@@ -85,6 +382,323 @@ impl Debug for Filter {
         self.channels[j + 1]
       )?;
     }
+    if self.controllers != [u64::MAX; 2] {
+      let [low, high] = self.controllers;
+      writeln!(f, "  CC : {:016x}{:016x}", high, low)?;
+    }
+    if self.programs != [u64::MAX; 2] {
+      let [low, high] = self.programs;
+      writeln!(f, "  PC : {:016x}{:016x}", high, low)?;
+    }
+    if !self.clock {
+      writeln!(f, "  No clock")?;
+    }
+    if self.sysex_ids_allowed || self.sysex_ids[0].is_some() {
+      if self.sysex_ids_allowed {
+        write!(f, "  SysEx only:")?;
+      } else {
+        write!(f, "  SysEx except:")?;
+      }
+      for id in self.sysex_ids.iter().flatten() {
+        write!(f, " {:?}", id)?;
+      }
+      writeln!(f)?;
+    }
+    if self.velocities != (0, 0xffff) {
+      let (low, high) = self.velocities;
+      writeln!(f, "  VEL: {:#06x}-{:#06x}", low, high)?;
+    }
+    for (group, notes) in self.notes.iter().enumerate() {
+      for (channel, (low, high)) in notes.iter().enumerate() {
+        if (*low, *high) != (0, 127) {
+          writeln!(
+            f,
+            "  G{:02} C{:02}: notes {}-{}",
+            group + 1,
+            channel + 1,
+            low,
+            high
+          )?;
+        }
+      }
+    }
     Ok(())
   }
 }
+
+/// Filters combined with AND, OR and NOT, such as `channel 1 notes OR channel 16 control changes`.
+///
+/// The messages go through the expression after decoding them, evaluating the operands in order
+/// and stopping as soon as the result is known, without allocating.
+/// A plain filter is also applied by the decoder, skipping the messages before decoding them.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FilterExpr {
+  Filter(Box<Filter>),
+  And(Vec<FilterExpr>),
+  Or(Vec<FilterExpr>),
+  Not(Box<FilterExpr>),
+}
+
+impl FilterExpr {
+  #[must_use]
+  pub fn and<F>(self, other: F) -> Self
+  where
+    F: Into<FilterExpr>,
+  {
+    match self {
+      Self::And(mut operands) => {
+        operands.push(other.into());
+        Self::And(operands)
+      }
+      expr => Self::And(vec![expr, other.into()]),
+    }
+  }
+
+  #[must_use]
+  pub fn or<F>(self, other: F) -> Self
+  where
+    F: Into<FilterExpr>,
+  {
+    match self {
+      Self::Or(mut operands) => {
+        operands.push(other.into());
+        Self::Or(operands)
+      }
+      expr => Self::Or(vec![expr, other.into()]),
+    }
+  }
+
+  pub fn message(&self, message: &Message) -> bool {
+    match self {
+      Self::Filter(filter) => filter.message(message),
+      Self::And(operands) => operands.iter().all(|operand| operand.message(message)),
+      Self::Or(operands) => operands.iter().any(|operand| operand.message(message)),
+      Self::Not(operand) => !operand.message(message),
+    }
+  }
+}
+
+impl Default for FilterExpr {
+  fn default() -> Self {
+    Self::Filter(Box::new(Filter::new()))
+  }
+}
+
+impl From<Filter> for FilterExpr {
+  fn from(filter: Filter) -> Self {
+    Self::Filter(Box::new(filter))
+  }
+}
+
+impl Not for FilterExpr {
+  type Output = FilterExpr;
+
+  fn not(self) -> Self::Output {
+    match self {
+      Self::Not(operand) => *operand,
+      expr => Self::Not(Box::new(expr)),
+    }
+  }
+}
+
+const MIDI_CI_SUB_ID: u8 = 0x0d;
+
+pub const PRESET_ALL: &str = "all";
+pub const PRESET_KEYS_ONLY: &str = "keys only";
+pub const PRESET_NO_CLOCK: &str = "no clock";
+pub const PRESET_DRUMS_CH10: &str = "drums ch10";
+pub const PRESET_MIDI_CI: &str = "midi-ci";
+
+/// Filters by name, so the applications can persist them and share them between their inputs and outputs.
+///
+/// The default registry comes with the presets for the usual setups.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterPresets {
+  presets: BTreeMap<String, Filter>,
+}
+
+impl FilterPresets {
+  /// A registry without presets.
+  pub fn empty() -> Self {
+    Self {
+      presets: BTreeMap::new(),
+    }
+  }
+
+  /// A registry with the presets for the usual setups:
+  ///
+  /// - `all`: every message.
+  /// - `keys only`: the MIDI 1.0 and 2.0 channel voice messages, without system, SysEx or utility messages.
+  /// - `no clock`: every message but the clock and transport ones.
+  /// - `drums ch10`: the channel 10 of the group 1, where the General MIDI drums are.
+  /// - `midi-ci`: the MIDI-CI SysEx messages.
+  pub fn new() -> Self {
+    let mut presets = Self::empty();
+    presets.insert(PRESET_ALL, Filter::new());
+    presets.insert(PRESET_KEYS_ONLY, Filter::new().with_mtypes(&[0x2, 0x4]));
+    presets.insert(PRESET_NO_CLOCK, Filter::new().without_clock());
+    presets.insert(
+      PRESET_DRUMS_CH10,
+      Filter::new().with_groups(&[1]).with_channels(1, &[10]),
+    );
+    presets.insert(
+      PRESET_MIDI_CI,
+      Filter::new()
+        .with_mtypes(&[0x3])
+        .with_sysex_ids(&[SysExId::NonRealTime(MIDI_CI_SUB_ID)]),
+    );
+    presets
+  }
+
+  pub fn get(&self, name: &str) -> Option<Filter> {
+    self.presets.get(name).copied()
+  }
+
+  /// Adds a preset, replacing the one with the same name.
+  pub fn insert<N>(&mut self, name: N, filter: Filter)
+  where
+    N: ToString,
+  {
+    self.presets.insert(name.to_string(), filter);
+  }
+
+  pub fn remove(&mut self, name: &str) -> Option<Filter> {
+    self.presets.remove(name)
+  }
+
+  pub fn names(&self) -> impl Iterator<Item = &str> {
+    self.presets.keys().map(String::as_str)
+  }
+}
+
+impl Default for FilterPresets {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn message(group: u8, mtype: MessageType) -> Message {
+    Message { group, mtype }
+  }
+
+  fn note_on(group: u8, channel: u8) -> Message {
+    message(
+      group,
+      MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::NoteOn {
+          note: 60,
+          velocity: 100,
+        },
+      }),
+    )
+  }
+
+  fn control_change(channel: u8) -> Message {
+    message(
+      0,
+      MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::ControlChange {
+          index: 7,
+          data: 100,
+        },
+      }),
+    )
+  }
+
+  #[test]
+  fn combine_filters() {
+    let notes = Filter::new().with_mtypes(&[0x2]).with_controllers([]);
+    let ch1_notes = notes.with_groups(&[1]).with_channels(1, &[1]);
+    let ch16_control_changes = Filter::new().with_groups(&[1]).with_channels(1, &[16]);
+    let expr = ch1_notes.or(ch16_control_changes.and(!notes));
+
+    assert!(expr.message(&note_on(0, 0)));
+    assert!(expr.message(&control_change(15)));
+    assert!(!expr.message(&note_on(0, 15)));
+    assert!(!expr.message(&control_change(0)));
+    assert!(!expr.message(&note_on(1, 0)));
+    assert_eq!(!!expr.clone(), expr);
+    assert_eq!(
+      ch1_notes.or(notes).or(ch16_control_changes),
+      FilterExpr::Or(vec![
+        ch1_notes.into(),
+        notes.into(),
+        ch16_control_changes.into()
+      ])
+    );
+  }
+
+  #[test]
+  fn presets() {
+    let presets = FilterPresets::new();
+    let clock = message(0, MessageType::System(System::TimingClock));
+
+    assert!(presets.get(PRESET_ALL).unwrap().message(&clock));
+    assert!(!presets.get(PRESET_KEYS_ONLY).unwrap().message(&clock));
+    assert!(presets
+      .get(PRESET_KEYS_ONLY)
+      .unwrap()
+      .message(&note_on(3, 0)));
+    assert!(!presets.get(PRESET_NO_CLOCK).unwrap().message(&clock));
+    assert!(presets
+      .get(PRESET_NO_CLOCK)
+      .unwrap()
+      .message(&message(0, MessageType::System(System::TuneRequest))));
+    let drums = presets.get(PRESET_DRUMS_CH10).unwrap();
+    assert!(drums.message(&note_on(0, 9)));
+    assert!(!drums.message(&note_on(0, 0)));
+    assert!(!drums.message(&note_on(1, 9)));
+  }
+
+  #[test]
+  fn sysex_ids() {
+    let sysex = |data: &[u8]| {
+      message(
+        0,
+        MessageType::SysEx7(SysEx7::new(SysExStatus::Start, data)),
+      )
+    };
+    let roland = sysex(&[0x41, 0x10, 0x42, 0x12]);
+    let midi_ci = sysex(&[0x7e, 0x7f, 0x0d, 0x70, 0x02]);
+    let continuation = message(
+      0,
+      MessageType::SysEx7(SysEx7::new(SysExStatus::Continue, &[0x01])),
+    );
+
+    let only_roland = Filter::new().with_sysex_ids(&[SysExId::Manufacturer(0x41)]);
+    assert!(only_roland.message(&roland));
+    assert!(!only_roland.message(&midi_ci));
+    assert!(!only_roland.message(&sysex(&[])));
+    assert!(only_roland.message(&continuation));
+
+    let no_midi_ci = Filter::new().without_sysex_ids(&[SysExId::NonRealTime(0x0d)]);
+    assert!(no_midi_ci.message(&roland));
+    assert!(!no_midi_ci.message(&midi_ci));
+    assert!(no_midi_ci.message(&sysex(&[])));
+
+    let presets = FilterPresets::new();
+    assert!(presets.get(PRESET_MIDI_CI).unwrap().message(&midi_ci));
+    assert!(!presets.get(PRESET_MIDI_CI).unwrap().message(&roland));
+    assert!(!presets.get(PRESET_MIDI_CI).unwrap().message(&note_on(0, 0)));
+  }
+
+  #[test]
+  fn custom_presets() {
+    let mut presets = FilterPresets::empty();
+    let pads = Filter::new().with_note_range(1, 10, 36..=51);
+    presets.insert("pads", pads);
+
+    assert_eq!(presets.names().collect::<Vec<_>>(), vec!["pads"]);
+    assert_eq!(presets.get("pads"), Some(pads));
+    assert_eq!(presets.remove("pads"), Some(pads));
+    assert_eq!(presets.get("pads"), None);
+  }
+}
@@ -0,0 +1,534 @@
+//! Human readable text for the messages, with the names of the notes, controllers and programs.
+//!
+//! Messages are rendered as `ch 3 Note On C#4 vel 98`, with the channels counting from 1 and
+//! the middle C (note 60) as C4. The group is only shown when it is not the first one.
+
+use std::fmt;
+
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::sysex7::SysEx7;
+use crate::protocol::messages::system::System;
+use crate::protocol::messages::{Message, MessageType};
+
+const NOTE_NAMES: [&str; 12] = [
+  "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Names of the MIDI 1.0 control changes, for the ones defined by the specification
+const CONTROLLER_NAMES: [Option<&str>; 128] = [
+  Some("Bank Select"),
+  Some("Modulation Wheel"),
+  Some("Breath Controller"),
+  None,
+  Some("Foot Controller"),
+  Some("Portamento Time"),
+  Some("Data Entry"),
+  Some("Channel Volume"),
+  Some("Balance"),
+  None,
+  Some("Pan"),
+  Some("Expression Controller"),
+  Some("Effect Control 1"),
+  Some("Effect Control 2"),
+  None,
+  None,
+  Some("General Purpose Controller 1"),
+  Some("General Purpose Controller 2"),
+  Some("General Purpose Controller 3"),
+  Some("General Purpose Controller 4"),
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  Some("Bank Select LSB"),
+  Some("Modulation Wheel LSB"),
+  Some("Breath Controller LSB"),
+  None,
+  Some("Foot Controller LSB"),
+  Some("Portamento Time LSB"),
+  Some("Data Entry LSB"),
+  Some("Channel Volume LSB"),
+  Some("Balance LSB"),
+  None,
+  Some("Pan LSB"),
+  Some("Expression Controller LSB"),
+  Some("Effect Control 1 LSB"),
+  Some("Effect Control 2 LSB"),
+  None,
+  None,
+  Some("General Purpose Controller 1 LSB"),
+  Some("General Purpose Controller 2 LSB"),
+  Some("General Purpose Controller 3 LSB"),
+  Some("General Purpose Controller 4 LSB"),
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  Some("Sustain Pedal"),
+  Some("Portamento"),
+  Some("Sostenuto"),
+  Some("Soft Pedal"),
+  Some("Legato Footswitch"),
+  Some("Hold 2"),
+  Some("Sound Variation"),
+  Some("Timbre/Harmonic Intensity"),
+  Some("Release Time"),
+  Some("Attack Time"),
+  Some("Brightness"),
+  Some("Decay Time"),
+  Some("Vibrato Rate"),
+  Some("Vibrato Depth"),
+  Some("Vibrato Delay"),
+  Some("Sound Controller 10"),
+  Some("General Purpose Controller 5"),
+  Some("General Purpose Controller 6"),
+  Some("General Purpose Controller 7"),
+  Some("General Purpose Controller 8"),
+  Some("Portamento Control"),
+  None,
+  None,
+  None,
+  Some("High Resolution Velocity Prefix"),
+  None,
+  None,
+  Some("Reverb Send Level"),
+  Some("Tremolo Depth"),
+  Some("Chorus Send Level"),
+  Some("Celeste Depth"),
+  Some("Phaser Depth"),
+  Some("Data Increment"),
+  Some("Data Decrement"),
+  Some("NRPN LSB"),
+  Some("NRPN MSB"),
+  Some("RPN LSB"),
+  Some("RPN MSB"),
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  None,
+  Some("All Sound Off"),
+  Some("Reset All Controllers"),
+  Some("Local Control"),
+  Some("All Notes Off"),
+  Some("Omni Mode Off"),
+  Some("Omni Mode On"),
+  Some("Mono Mode On"),
+  Some("Poly Mode On"),
+];
+
+/// Names of the General MIDI Level 1 programs
+const PROGRAM_NAMES: [&str; 128] = [
+  "Acoustic Grand Piano",
+  "Bright Acoustic Piano",
+  "Electric Grand Piano",
+  "Honky-tonk Piano",
+  "Electric Piano 1",
+  "Electric Piano 2",
+  "Harpsichord",
+  "Clavi",
+  "Celesta",
+  "Glockenspiel",
+  "Music Box",
+  "Vibraphone",
+  "Marimba",
+  "Xylophone",
+  "Tubular Bells",
+  "Dulcimer",
+  "Drawbar Organ",
+  "Percussive Organ",
+  "Rock Organ",
+  "Church Organ",
+  "Reed Organ",
+  "Accordion",
+  "Harmonica",
+  "Tango Accordion",
+  "Acoustic Guitar (nylon)",
+  "Acoustic Guitar (steel)",
+  "Electric Guitar (jazz)",
+  "Electric Guitar (clean)",
+  "Electric Guitar (muted)",
+  "Overdriven Guitar",
+  "Distortion Guitar",
+  "Guitar Harmonics",
+  "Acoustic Bass",
+  "Electric Bass (finger)",
+  "Electric Bass (pick)",
+  "Fretless Bass",
+  "Slap Bass 1",
+  "Slap Bass 2",
+  "Synth Bass 1",
+  "Synth Bass 2",
+  "Violin",
+  "Viola",
+  "Cello",
+  "Contrabass",
+  "Tremolo Strings",
+  "Pizzicato Strings",
+  "Orchestral Harp",
+  "Timpani",
+  "String Ensemble 1",
+  "String Ensemble 2",
+  "SynthStrings 1",
+  "SynthStrings 2",
+  "Choir Aahs",
+  "Voice Oohs",
+  "Synth Voice",
+  "Orchestra Hit",
+  "Trumpet",
+  "Trombone",
+  "Tuba",
+  "Muted Trumpet",
+  "French Horn",
+  "Brass Section",
+  "SynthBrass 1",
+  "SynthBrass 2",
+  "Soprano Sax",
+  "Alto Sax",
+  "Tenor Sax",
+  "Baritone Sax",
+  "Oboe",
+  "English Horn",
+  "Bassoon",
+  "Clarinet",
+  "Piccolo",
+  "Flute",
+  "Recorder",
+  "Pan Flute",
+  "Blown Bottle",
+  "Shakuhachi",
+  "Whistle",
+  "Ocarina",
+  "Lead 1 (square)",
+  "Lead 2 (sawtooth)",
+  "Lead 3 (calliope)",
+  "Lead 4 (chiff)",
+  "Lead 5 (charang)",
+  "Lead 6 (voice)",
+  "Lead 7 (fifths)",
+  "Lead 8 (bass + lead)",
+  "Pad 1 (new age)",
+  "Pad 2 (warm)",
+  "Pad 3 (polysynth)",
+  "Pad 4 (choir)",
+  "Pad 5 (bowed)",
+  "Pad 6 (metallic)",
+  "Pad 7 (halo)",
+  "Pad 8 (sweep)",
+  "FX 1 (rain)",
+  "FX 2 (soundtrack)",
+  "FX 3 (crystal)",
+  "FX 4 (atmosphere)",
+  "FX 5 (brightness)",
+  "FX 6 (goblins)",
+  "FX 7 (echoes)",
+  "FX 8 (sci-fi)",
+  "Sitar",
+  "Banjo",
+  "Shamisen",
+  "Koto",
+  "Kalimba",
+  "Bag pipe",
+  "Fiddle",
+  "Shanai",
+  "Tinkle Bell",
+  "Agogo",
+  "Steel Drums",
+  "Woodblock",
+  "Taiko Drum",
+  "Melodic Tom",
+  "Synth Drum",
+  "Reverse Cymbal",
+  "Guitar Fret Noise",
+  "Breath Noise",
+  "Seashore",
+  "Bird Tweet",
+  "Telephone Ring",
+  "Helicopter",
+  "Applause",
+  "Gunshot",
+];
+
+/// The name of a note with its octave, such as `C#4` for the note 61.
+pub fn note_name(note: u8) -> String {
+  let note = note & 0x7f;
+  format!(
+    "{}{}",
+    NOTE_NAMES[(note % 12) as usize],
+    (note / 12) as i8 - 1
+  )
+}
+
+/// The name of a MIDI 1.0 control change, if it is defined by the specification.
+pub fn controller_name(index: u8) -> Option<&'static str> {
+  CONTROLLER_NAMES[(index & 0x7f) as usize]
+}
+
+/// The name of a program in General MIDI.
+pub fn program_name(program: u8) -> &'static str {
+  PROGRAM_NAMES[(program & 0x7f) as usize]
+}
+
+impl fmt::Display for Message {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    if self.group != 0 {
+      write!(f, "grp {} ", self.group + 1)?;
+    }
+    match &self.mtype {
+      MessageType::ChannelVoice1(channel_voice) => fmt_channel_voice1(channel_voice, f),
+      MessageType::ChannelVoice(channel_voice) => fmt_channel_voice(channel_voice, f),
+      MessageType::System(system) => fmt_system(system, f),
+      MessageType::SysEx7(sysex) => fmt_sysex7(sysex, f),
+      MessageType::Utility(utility) => write!(f, "{:?}", utility),
+      MessageType::SysEx8(sysex) => write!(f, "{:?}", sysex),
+      MessageType::MixedDataSet(mixed_data_set) => write!(f, "{:?}", mixed_data_set),
+      MessageType::FlexData(flex_data) => write!(f, "{:?}", flex_data),
+    }
+  }
+}
+
+fn fmt_controller(index: u8, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+  match controller_name(index) {
+    Some(name) => write!(f, "CC {} {}", index, name),
+    None => write!(f, "CC {}", index),
+  }
+}
+
+fn fmt_channel_voice1(channel_voice: &ChannelVoice1, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+  write!(f, "ch {} ", channel_voice.channel + 1)?;
+  match channel_voice.message {
+    ChannelVoice1Message::NoteOff { note, velocity } => {
+      write!(f, "Note Off {} vel {}", note_name(note), velocity)
+    }
+    ChannelVoice1Message::NoteOn { note, velocity } => {
+      write!(f, "Note On {} vel {}", note_name(note), velocity)
+    }
+    ChannelVoice1Message::PolyPressure { note, data } => {
+      write!(f, "Poly Pressure {} {}", note_name(note), data)
+    }
+    ChannelVoice1Message::ControlChange { index, data } => {
+      fmt_controller(index, f)?;
+      write!(f, " {}", data)
+    }
+    ChannelVoice1Message::ProgramChange { program } => {
+      write!(f, "Program Change {} {}", program, program_name(program))
+    }
+    ChannelVoice1Message::ChannelPressure { data } => write!(f, "Channel Pressure {}", data),
+    ChannelVoice1Message::PitchBend { data } => {
+      write!(f, "Pitch Bend {}", data as i32 - 0x2000)
+    }
+  }
+}
+
+fn fmt_channel_voice(channel_voice: &ChannelVoice, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+  write!(f, "ch {} ", channel_voice.channel + 1)?;
+  match channel_voice.message {
+    ChanelVoiceMessage::NoteOff { note, velocity, .. } => {
+      write!(f, "Note Off {} vel {}", note_name(note), velocity)
+    }
+    ChanelVoiceMessage::NoteOn { note, velocity, .. } => {
+      write!(f, "Note On {} vel {}", note_name(note), velocity)
+    }
+    ChanelVoiceMessage::PolyPressure { note, data } => {
+      write!(f, "Poly Pressure {} {}", note_name(note), data)
+    }
+    ChanelVoiceMessage::RegisteredPerNoteController { note, index, data } => write!(
+      f,
+      "Registered Per-Note Controller {} {} {}",
+      note_name(note),
+      index,
+      data
+    ),
+    ChanelVoiceMessage::AssignablePerNoteController { note, index, data } => write!(
+      f,
+      "Assignable Per-Note Controller {} {} {}",
+      note_name(note),
+      index,
+      data
+    ),
+    ChanelVoiceMessage::PerNoteManagement {
+      note,
+      detach,
+      reset,
+    } => {
+      write!(f, "Per-Note Management {}", note_name(note))?;
+      if detach {
+        write!(f, " detach")?;
+      }
+      if reset {
+        write!(f, " reset")?;
+      }
+      Ok(())
+    }
+    ChanelVoiceMessage::ControlChange { index, data } => {
+      fmt_controller(index, f)?;
+      write!(f, " {}", data)
+    }
+    ChanelVoiceMessage::RegisteredController { bank, index, data } => {
+      write!(f, "RPN {}:{} {}", bank, index, data)
+    }
+    ChanelVoiceMessage::AssignableController { bank, index, data } => {
+      write!(f, "NRPN {}:{} {}", bank, index, data)
+    }
+    ChanelVoiceMessage::RelativeRegisteredController { bank, index, data } => {
+      write!(f, "Relative RPN {}:{} {:+}", bank, index, data)
+    }
+    ChanelVoiceMessage::RelativeAssignableController { bank, index, data } => {
+      write!(f, "Relative NRPN {}:{} {:+}", bank, index, data)
+    }
+    ChanelVoiceMessage::ProgramChange { program, bank } => {
+      write!(f, "Program Change {} {}", program, program_name(program))?;
+      match bank {
+        Some(bank) => write!(f, " bank {}", bank),
+        None => Ok(()),
+      }
+    }
+    ChanelVoiceMessage::ChannelPressure { data } => write!(f, "Channel Pressure {}", data),
+    ChanelVoiceMessage::PitchBend { data } => {
+      write!(f, "Pitch Bend {}", data as i64 - 0x8000_0000)
+    }
+    ChanelVoiceMessage::PerNotePitchBend { note, data } => write!(
+      f,
+      "Per-Note Pitch Bend {} {}",
+      note_name(note),
+      data as i64 - 0x8000_0000
+    ),
+  }
+}
+
+fn fmt_system(system: &System, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+  match *system {
+    System::TimeCode(data) => write!(f, "Time Code {} {}", data >> 4, data & 0x0f),
+    System::SongPositionPointer(position) => write!(f, "Song Position {}", position),
+    System::SongSelect(song) => write!(f, "Song Select {}", song),
+    System::TuneRequest => write!(f, "Tune Request"),
+    System::TimingClock => write!(f, "Timing Clock"),
+    System::Start => write!(f, "Start"),
+    System::Continue => write!(f, "Continue"),
+    System::Stop => write!(f, "Stop"),
+    System::ActiveSensing => write!(f, "Active Sensing"),
+    System::Reset => write!(f, "Reset"),
+  }
+}
+
+fn fmt_sysex7(sysex: &SysEx7, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+  write!(f, "SysEx {:?}", sysex.status)?;
+  for byte in sysex.data() {
+    write!(f, " {:02x}", byte)?;
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::messages::sysex7::SysExStatus;
+
+  fn channel_voice1(group: u8, channel: u8, message: ChannelVoice1Message) -> String {
+    Message {
+      group,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 { channel, message }),
+    }
+    .to_string()
+  }
+
+  #[test]
+  fn names() {
+    assert_eq!(note_name(60), "C4");
+    assert_eq!(note_name(61), "C#4");
+    assert_eq!(note_name(0), "C-1");
+    assert_eq!(note_name(127), "G9");
+    assert_eq!(controller_name(64), Some("Sustain Pedal"));
+    assert_eq!(controller_name(3), None);
+    assert_eq!(program_name(0), "Acoustic Grand Piano");
+    assert_eq!(program_name(127), "Gunshot");
+  }
+
+  #[test]
+  fn display_messages() {
+    assert_eq!(
+      channel_voice1(
+        0,
+        2,
+        ChannelVoice1Message::NoteOn {
+          note: 61,
+          velocity: 98
+        }
+      ),
+      "ch 3 Note On C#4 vel 98"
+    );
+    assert_eq!(
+      channel_voice1(
+        1,
+        0,
+        ChannelVoice1Message::ControlChange {
+          index: 7,
+          data: 100
+        }
+      ),
+      "grp 2 ch 1 CC 7 Channel Volume 100"
+    );
+    assert_eq!(
+      channel_voice1(0, 9, ChannelVoice1Message::ProgramChange { program: 40 }),
+      "ch 10 Program Change 40 Violin"
+    );
+    assert_eq!(
+      channel_voice1(0, 0, ChannelVoice1Message::PitchBend { data: 0 }),
+      "ch 1 Pitch Bend -8192"
+    );
+
+    let message = Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice(ChannelVoice {
+        channel: 0,
+        message: ChanelVoiceMessage::ProgramChange {
+          program: 0,
+          bank: Some(3),
+        },
+      }),
+    };
+    assert_eq!(
+      message.to_string(),
+      "ch 1 Program Change 0 Acoustic Grand Piano bank 3"
+    );
+
+    let message = Message {
+      group: 0,
+      mtype: MessageType::SysEx7(SysEx7::new(
+        SysExStatus::Complete,
+        &[0x7e, 0x7f, 0x06, 0x01],
+      )),
+    };
+    assert_eq!(message.to_string(), "SysEx Complete 7e 7f 06 01");
+  }
+}
@@ -0,0 +1,238 @@
+//! Humanizers, moving the notes a bit off the grid.
+//!
+//! The `Humanize` is a `Processor` delaying the notes and changing their velocities by random amounts,
+//! up to the limits set. It goes before an `Output` scheduling the events at their timestamps, such as
+//! the outputs from an `OutputQueue`, as the notes can only be delayed. The note offs are delayed as much
+//! as their note ons, so the notes keep their lengths and never end before starting.
+//!
+//! The random numbers come from a seed, so the same events get the same offsets every time.
+
+use std::time::Duration;
+
+use crate::event::{Event, TimestampNanos};
+use crate::processor::Processor;
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::MessageType;
+
+pub const DEFAULT_SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// Changes the timing and the velocity of the notes, see the module docs.
+pub struct Humanize {
+  max_delay: TimestampNanos,
+  max_velocity_offset: u8,
+  seed: u64,
+  random: u64,
+  /// Delay of the last note on of every channel and note, for their note offs
+  delays: Vec<TimestampNanos>,
+}
+
+impl Humanize {
+  /// Leaves the notes as they are, until setting the limits.
+  pub fn new() -> Self {
+    Self {
+      max_delay: 0,
+      max_velocity_offset: 0,
+      seed: DEFAULT_SEED,
+      random: DEFAULT_SEED,
+      delays: vec![0; 16 * 128],
+    }
+  }
+
+  /// Delays every note by up to `max_delay`.
+  #[must_use]
+  pub fn with_timing(mut self, max_delay: Duration) -> Self {
+    self.max_delay = max_delay.as_nanos() as TimestampNanos;
+    self
+  }
+
+  /// Raises or lowers the velocity of every note on by up to `max_offset` (in MIDI 1.0 steps),
+  /// keeping it from 1 to the maximum.
+  #[must_use]
+  pub fn with_velocity(mut self, max_offset: u8) -> Self {
+    self.max_velocity_offset = max_offset.min(127);
+    self
+  }
+
+  /// Sets the seed of the random offsets, which start over with `Processor::reset`.
+  #[must_use]
+  pub fn with_seed(mut self, seed: u64) -> Self {
+    self.seed = seed;
+    self.random = seed;
+    self
+  }
+
+  /// Xorshift, good enough for the offsets
+  fn next_random(&mut self) -> u64 {
+    // Xorshift gets stuck at 0
+    if self.random == 0 {
+      self.random = DEFAULT_SEED;
+    }
+    self.random ^= self.random << 13;
+    self.random ^= self.random >> 7;
+    self.random ^= self.random << 17;
+    self.random
+  }
+
+  fn delay(&mut self) -> TimestampNanos {
+    match self.max_delay {
+      0 => 0,
+      max_delay => self.next_random() % (max_delay + 1),
+    }
+  }
+
+  /// Random offset from `-max_velocity_offset` to `max_velocity_offset`.
+  fn velocity_offset(&mut self) -> i32 {
+    let range = self.max_velocity_offset as u64 * 2 + 1;
+    (self.next_random() % range) as i32 - self.max_velocity_offset as i32
+  }
+
+  fn note_on(&mut self, channel: u8, note: u8) -> TimestampNanos {
+    let delay = self.delay();
+    self.delays[Self::index(channel, note)] = delay;
+    delay
+  }
+
+  fn note_off(&self, channel: u8, note: u8) -> TimestampNanos {
+    self.delays[Self::index(channel, note)]
+  }
+
+  fn index(channel: u8, note: u8) -> usize {
+    (channel & 0x0f) as usize * 128 + (note & 0x7f) as usize
+  }
+}
+
+impl Default for Humanize {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Processor for Humanize {
+  fn process(&mut self, mut event: Event, output: &mut dyn FnMut(Event)) {
+    let delay = match &mut event.message.mtype {
+      MessageType::ChannelVoice1(ChannelVoice1 { channel, message }) => match message {
+        ChannelVoice1Message::NoteOn { note, velocity } if *velocity > 0 => {
+          let offset = self.velocity_offset();
+          *velocity = (*velocity as i32 + offset).clamp(1, 127) as u8;
+          self.note_on(*channel, *note)
+        }
+        ChannelVoice1Message::NoteOn { note, .. } | ChannelVoice1Message::NoteOff { note, .. } => {
+          self.note_off(*channel, *note)
+        }
+        _ => 0,
+      },
+      MessageType::ChannelVoice(ChannelVoice { channel, message }) => match message {
+        ChanelVoiceMessage::NoteOn { note, velocity, .. } => {
+          // In MIDI 1.0 steps, as the velocity is scaled up from 7 to 16 bits
+          let offset = self.velocity_offset() << 9;
+          *velocity = (*velocity as i32 + offset).clamp(1, u16::MAX as i32) as u16;
+          self.note_on(*channel, *note)
+        }
+        ChanelVoiceMessage::NoteOff { note, .. } => self.note_off(*channel, *note),
+        _ => 0,
+      },
+      _ => 0,
+    };
+    event.timestamp += delay;
+    output(event);
+  }
+
+  fn reset(&mut self) {
+    self.random = self.seed;
+    self.delays.iter_mut().for_each(|delay| *delay = 0);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::messages::Message;
+
+  const MS: TimestampNanos = 1_000_000;
+
+  fn note(timestamp: TimestampNanos, note: u8, velocity: u8) -> Event {
+    Event {
+      timestamp,
+      endpoint: 1,
+      message: Message {
+        group: 0,
+        mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+          channel: 0,
+          message: if velocity > 0 {
+            ChannelVoice1Message::NoteOn { note, velocity }
+          } else {
+            ChannelVoice1Message::NoteOff { note, velocity }
+          },
+        }),
+      },
+    }
+  }
+
+  fn run(humanize: &mut Humanize, events: &[Event]) -> Vec<Event> {
+    let mut output = Vec::new();
+    for event in events {
+      humanize.process(event.clone(), &mut |event| output.push(event));
+    }
+    output
+  }
+
+  #[test]
+  fn bounded_offsets() {
+    let mut humanize = Humanize::new()
+      .with_timing(Duration::from_millis(10))
+      .with_velocity(8);
+    let events = (0..100u64)
+      .flat_map(|step| {
+        let note_number = 36 + (step % 48) as u8;
+        [
+          note(step * 100 * MS, note_number, 100),
+          note(step * 100 * MS + 50 * MS, note_number, 0),
+        ]
+      })
+      .collect::<Vec<_>>();
+
+    let humanized = run(&mut humanize, &events);
+
+    assert_eq!(humanized.len(), events.len());
+    for (pair, humanized_pair) in events.chunks(2).zip(humanized.chunks(2)) {
+      let delay = humanized_pair[0].timestamp - pair[0].timestamp;
+      assert!(delay <= 10 * MS);
+      assert_eq!(humanized_pair[1].timestamp - pair[1].timestamp, delay);
+      match humanized_pair[0].message.mtype {
+        MessageType::ChannelVoice1(ChannelVoice1 {
+          message: ChannelVoice1Message::NoteOn { velocity, .. },
+          ..
+        }) => assert!((92..=108).contains(&velocity)),
+        _ => panic!("not a note on"),
+      }
+    }
+    assert!(humanized
+      .iter()
+      .zip(events.iter())
+      .any(|(humanized, event)| humanized != event));
+  }
+
+  #[test]
+  fn reproducible_with_the_seed() {
+    let events = [note(0, 60, 100), note(MS, 62, 100), note(2 * MS, 64, 100)];
+    let mut humanize = Humanize::new()
+      .with_timing(Duration::from_millis(20))
+      .with_velocity(20)
+      .with_seed(42);
+
+    let first = run(&mut humanize, &events);
+    humanize.reset();
+    let second = run(&mut humanize, &events);
+    let other_seed = run(
+      &mut Humanize::new()
+        .with_timing(Duration::from_millis(20))
+        .with_velocity(20)
+        .with_seed(7),
+      &events,
+    );
+
+    assert!(first == second);
+    assert!(first != other_seed);
+  }
+}
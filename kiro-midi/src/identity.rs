@@ -0,0 +1,213 @@
+//! Identity of the devices, and the messages to reset them to a known state.
+//!
+//! The identity request is a Universal Non Real Time SysEx message that every device should answer
+//! with its manufacturer, family, model and firmware version. The resets switch the sound modules
+//! to the General MIDI mode, or to the GS and XG extensions from Roland and Yamaha.
+
+use std::collections::HashMap;
+
+use crate::endpoints::SourceId;
+use crate::filter::Filter;
+use crate::midi_ci::DeviceInfo;
+use crate::protocol::decoder::DecoderProtocol2;
+use crate::protocol::messages::sysex7::SysExAssembler;
+use crate::protocol::messages::MessageType;
+
+const UNIVERSAL_NON_REALTIME: u8 = 0x7e;
+const GENERAL_INFORMATION: u8 = 0x06;
+const IDENTITY_REQUEST: u8 = 0x01;
+const IDENTITY_REPLY: u8 = 0x02;
+
+/// Header, manufacturer, family, model and version
+const IDENTITY_REPLY_LEN: usize = 4 + 1 + 2 + 2 + 4;
+/// The manufacturer ids starting with 0 take three bytes
+const EXTENDED_ID_LEN: usize = 2;
+
+/// Device ID addressing all the devices
+pub const ALL_DEVICES: u8 = 0x7f;
+
+/// The data of the SysEx message asking the devices for their identity.
+pub fn identity_request(device: u8) -> Vec<u8> {
+  vec![
+    UNIVERSAL_NON_REALTIME,
+    device & 0x7f,
+    GENERAL_INFORMATION,
+    IDENTITY_REQUEST,
+  ]
+}
+
+/// The reply of a device to the identity request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IdentityReply {
+  pub device: u8,
+  /// The manufacturer ids of one byte are in the first byte, followed by zeros
+  pub info: DeviceInfo,
+}
+
+impl IdentityReply {
+  /// Parses the data of a SysEx message, returning `None` when it is not an identity reply.
+  pub fn parse(data: &[u8]) -> Option<Self> {
+    let data = data.strip_prefix(&[0xf0]).unwrap_or(data);
+    let data = data.strip_suffix(&[0xf7]).unwrap_or(data);
+    let is_reply = data.len() >= IDENTITY_REPLY_LEN
+      && data[0] == UNIVERSAL_NON_REALTIME
+      && data[2] == GENERAL_INFORMATION
+      && data[3] == IDENTITY_REPLY;
+    if !is_reply {
+      return None;
+    }
+
+    let (manufacturer, body) = match data[4] {
+      0x00 if data.len() >= IDENTITY_REPLY_LEN + EXTENDED_ID_LEN => {
+        ([0x00, data[5], data[6]], &data[7..])
+      }
+      0x00 => return None,
+      id => ([id, 0x00, 0x00], &data[5..]),
+    };
+    Some(Self {
+      device: data[1],
+      info: DeviceInfo {
+        manufacturer,
+        family: read_u14(&body[0..2]),
+        model: read_u14(&body[2..4]),
+        version: [body[4], body[5], body[6], body[7]],
+      },
+    })
+  }
+
+  /// Builds the data of the SysEx message, without the `F0` and `F7` bytes.
+  pub fn to_sysex(&self) -> Vec<u8> {
+    let mut data = vec![
+      UNIVERSAL_NON_REALTIME,
+      self.device & 0x7f,
+      GENERAL_INFORMATION,
+      IDENTITY_REPLY,
+    ];
+    let manufacturer = self.info.manufacturer;
+    if manufacturer[0] == 0x00 {
+      data.extend_from_slice(&manufacturer);
+    } else {
+      data.push(manufacturer[0]);
+    }
+    write_u14(&mut data, self.info.family);
+    write_u14(&mut data, self.info.model);
+    data.extend(self.info.version.iter().map(|byte| byte & 0x7f));
+    data
+  }
+}
+
+fn read_u14(data: &[u8]) -> u16 {
+  (data[0] & 0x7f) as u16 | ((data[1] & 0x7f) as u16) << 7
+}
+
+fn write_u14(data: &mut Vec<u8>, value: u16) {
+  data.extend_from_slice(&[(value & 0x7f) as u8, ((value >> 7) & 0x7f) as u8]);
+}
+
+/// The resets for the sound modules, sent to all the devices
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SystemReset {
+  GeneralMidi,
+  GeneralMidi2,
+  /// Leaves the General MIDI mode, for the devices with a mode of their own
+  GeneralMidiOff,
+  /// Roland GS
+  Gs,
+  /// Yamaha XG
+  Xg,
+}
+
+impl SystemReset {
+  /// The data of the SysEx message, without the `F0` and `F7` bytes.
+  pub fn to_sysex(self) -> &'static [u8] {
+    match self {
+      Self::GeneralMidi => &[0x7e, 0x7f, 0x09, 0x01],
+      Self::GeneralMidi2 => &[0x7e, 0x7f, 0x09, 0x03],
+      Self::GeneralMidiOff => &[0x7e, 0x7f, 0x09, 0x02],
+      Self::Gs => &[0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7f, 0x00, 0x41],
+      Self::Xg => &[0x43, 0x10, 0x4c, 0x00, 0x00, 0x7e, 0x00],
+    }
+  }
+}
+
+/// Keeps track of the identities replied through the sources.
+#[derive(Default)]
+pub struct Identities {
+  receivers: HashMap<SourceId, Receiver>,
+  identities: HashMap<SourceId, DeviceInfo>,
+}
+
+struct Receiver {
+  decoder: DecoderProtocol2,
+  assembler: SysExAssembler,
+}
+
+impl Identities {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The identity of the device behind a source, once it replied.
+  pub fn identity(&self, source_id: SourceId) -> Option<DeviceInfo> {
+    self.identities.get(&source_id).copied()
+  }
+
+  /// Handles the UMP words received from a source, looking for identity replies.
+  pub fn receive(&mut self, source_id: SourceId, ump: &[u32]) {
+    let receiver = self.receivers.entry(source_id).or_insert_with(|| Receiver {
+      decoder: DecoderProtocol2::default(),
+      assembler: SysExAssembler::new(IDENTITY_REPLY_LEN + EXTENDED_ID_LEN),
+    });
+
+    let filter = Filter::new();
+    for word in ump.iter().cloned() {
+      if let Ok(Some(message)) = receiver.decoder.next(word, &filter) {
+        if let MessageType::SysEx7(sysex) = message.mtype {
+          if let Some(reply) = receiver
+            .assembler
+            .push(&sysex)
+            .and_then(IdentityReply::parse)
+          {
+            self.identities.insert(source_id, reply.info);
+          }
+        }
+      }
+    }
+  }
+
+  pub fn remove_source(&mut self, source_id: SourceId) {
+    self.receivers.remove(&source_id);
+    self.identities.remove(&source_id);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn identity_replies() {
+    let data = [
+      0xf0, 0x7e, 0x10, 0x06, 0x02, 0x41, 0x0b, 0x01, 0x03, 0x00, 0x00, 0x00, 0x01, 0x02, 0xf7,
+    ];
+    let reply = IdentityReply::parse(&data).unwrap();
+    assert_eq!(reply.device, 0x10);
+    assert_eq!(reply.info.manufacturer, [0x41, 0x00, 0x00]);
+    assert_eq!(reply.info.family, 0x8b);
+    assert_eq!(reply.info.model, 0x03);
+    assert_eq!(reply.info.version, [0x00, 0x00, 0x01, 0x02]);
+    assert_eq!(reply.to_sysex(), &data[1..data.len() - 1]);
+
+    let extended = IdentityReply {
+      device: ALL_DEVICES,
+      info: DeviceInfo {
+        manufacturer: [0x00, 0x20, 0x29],
+        family: 0x1234,
+        model: 0x0042,
+        version: [1, 2, 3, 4],
+      },
+    };
+    assert_eq!(IdentityReply::parse(&extended.to_sysex()), Some(extended));
+    assert_eq!(IdentityReply::parse(&identity_request(ALL_DEVICES)), None);
+  }
+}
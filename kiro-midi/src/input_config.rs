@@ -1,10 +1,26 @@
-use crate::filter::Filter;
+use std::time::Duration;
+
+use crate::filter::FilterExpr;
 use crate::source_match::{SourceMatch, SourceMatches};
+use crate::transform::Transform;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone)]
 pub struct InputConfig {
   pub name: String,
   pub sources: SourceMatches,
+  /// Whether to correct the timestamps of the events with the JR Timestamps sent by the sources
+  pub jitter_reduction: bool,
+  /// Whether to translate the RPN and NRPN control changes into MIDI 2.0 registered and assignable controllers
+  pub assemble_parameters: bool,
+  /// Whether to combine the MSB and LSB control changes into MIDI 2.0 control changes with 14 bits of resolution
+  pub pair_controllers: bool,
+  /// Maximum number of control changes per second for every controller, dropping the values in between
+  pub controller_rate_limit: Option<u32>,
+  /// Time window to drop the messages identical to one received just before, from any of the sources
+  pub duplicate_window: Option<Duration>,
+  /// Whether to deliver the events of all the sources sorted by timestamp, for every cycle of the driver
+  pub merge_sources: bool,
 }
 
 impl InputConfig {
@@ -15,21 +31,103 @@ impl InputConfig {
     Self {
       name: name.into(),
       sources: SourceMatches::default(),
+      jitter_reduction: false,
+      assemble_parameters: false,
+      pair_controllers: false,
+      controller_rate_limit: None,
+      duplicate_window: None,
+      merge_sources: false,
     }
   }
 
-  pub fn with_source<M>(mut self, source_match: M, filter: Filter) -> Self
+  pub fn with_source<M, F>(mut self, source_match: M, filter: F) -> Self
   where
     M: Into<SourceMatch>,
+    F: Into<FilterExpr>,
   {
     self.sources.add_source(source_match, filter);
     self
   }
 
-  pub fn with_all_sources(mut self, filter: Filter) -> Self {
+  /// Connects to the sources matching, transforming their events after decoding them,
+  /// such as to send everything from a keyboard to the channel 1.
+  pub fn with_source_transform<M, F>(
+    mut self,
+    source_match: M,
+    filter: F,
+    transform: Transform,
+  ) -> Self
+  where
+    M: Into<SourceMatch>,
+    F: Into<FilterExpr>,
+  {
+    self
+      .sources
+      .add_source_with_transform(source_match, filter, transform);
+    self
+  }
+
+  /// Connects to the sources matching, multiplying the velocities of their note ons by `gain` and adding `offset`
+  /// to them, such as to balance a heavy keyboard with a light pad controller. See `Transform::with_velocity_scaling`.
+  pub fn with_source_velocity<M, F>(self, source_match: M, filter: F, gain: f32, offset: i8) -> Self
+  where
+    M: Into<SourceMatch>,
+    F: Into<FilterExpr>,
+  {
+    self.with_source_transform(
+      source_match,
+      filter,
+      Transform::new().with_velocity_scaling(gain, offset),
+    )
+  }
+
+  pub fn with_all_sources<F>(mut self, filter: F) -> Self
+  where
+    F: Into<FilterExpr>,
+  {
     self
       .sources
       .add_source(SourceMatch::regex(".*").expect("regex"), filter);
     self
   }
+
+  pub fn with_jitter_reduction(mut self, enabled: bool) -> Self {
+    self.jitter_reduction = enabled;
+    self
+  }
+
+  pub fn with_parameter_assembly(mut self, enabled: bool) -> Self {
+    self.assemble_parameters = enabled;
+    self
+  }
+
+  pub fn with_controller_pairing(mut self, enabled: bool) -> Self {
+    self.pair_controllers = enabled;
+    self
+  }
+
+  /// Limits the control changes to `max_rate` per second and controller, such as for endless encoders.
+  ///
+  /// The last value dropped is delivered once its interval has passed, even if the source sends nothing else.
+  pub fn with_controller_rate_limit(mut self, max_rate: u32) -> Self {
+    self.controller_rate_limit = Some(max_rate);
+    self
+  }
+
+  /// Drops the messages identical to one received less than `window` before,
+  /// such as when the same device is reachable through two of the sources.
+  pub fn with_duplicate_suppression(mut self, window: Duration) -> Self {
+    self.duplicate_window = Some(window);
+    self
+  }
+
+  /// Delivers the events received from all the sources in the same cycle of the driver sorted by their timestamps,
+  /// instead of source by source, such as when several controllers play the same part.
+  ///
+  /// It only makes a difference for the drivers reading several sources at once, such as the shared memory one,
+  /// as the rest receive the data of every source as it comes. See `Capabilities::source_merging`.
+  pub fn with_source_merging(mut self, enabled: bool) -> Self {
+    self.merge_sources = enabled;
+    self
+  }
 }
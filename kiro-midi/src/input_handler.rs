@@ -3,18 +3,70 @@ use std::fmt::{Debug, Formatter};
 
 use crate::event::Event;
 
+/// Where the events of an input are delivered.
+///
+/// A single input can deliver to several handlers with `InputHandler::fanout`, such as a ring buffer
+/// for the audio engine and a callback for the UI, without connecting to the sources twice.
 pub enum InputHandler {
   Callback(Box<dyn FnMut(Event) + Send + 'static>),
+  /// Drops the events that don't fit
   RingBuffer(Producer<Event>),
+  /// Passes the events that don't fit to the callback, such as to count them or to send them another way
+  RingBufferWithOverflow(Producer<Event>, Box<dyn FnMut(Event) + Send + 'static>),
+  /// Delivers every event to all the handlers, in order, so a full ring buffer doesn't affect the rest
+  Fanout(Vec<InputHandler>),
 }
 
 impl InputHandler {
+  pub fn fanout<I, H>(handlers: I) -> Self
+  where
+    I: IntoIterator<Item = H>,
+    H: Into<InputHandler>,
+  {
+    InputHandler::Fanout(handlers.into_iter().map(Into::into).collect())
+  }
+
+  /// Delivers the events to another handler too, after this one.
+  #[must_use]
+  pub fn with_handler<H>(self, handler: H) -> Self
+  where
+    H: Into<InputHandler>,
+  {
+    match self {
+      InputHandler::Fanout(mut handlers) => {
+        handlers.push(handler.into());
+        InputHandler::Fanout(handlers)
+      }
+      current => InputHandler::Fanout(vec![current, handler.into()]),
+    }
+  }
+
+  pub fn ring_buffer_with_overflow<F>(producer: Producer<Event>, on_overflow: F) -> Self
+  where
+    F: FnMut(Event) + Send + 'static,
+  {
+    InputHandler::RingBufferWithOverflow(producer, Box::new(on_overflow))
+  }
+
   pub(crate) fn call(&mut self, event: Event) {
     match self {
       InputHandler::Callback(ref mut callback) => (callback)(event),
       InputHandler::RingBuffer(ref mut producer) => {
         producer.push(event).ok();
       }
+      InputHandler::RingBufferWithOverflow(ref mut producer, ref mut on_overflow) => {
+        if let Err(event) = producer.push(event) {
+          (on_overflow)(event);
+        }
+      }
+      InputHandler::Fanout(ref mut handlers) => {
+        if let Some((last, rest)) = handlers.split_last_mut() {
+          for handler in rest.iter_mut() {
+            handler.call(event.clone());
+          }
+          last.call(event);
+        }
+      }
     };
   }
 }
@@ -39,6 +91,8 @@ impl Debug for InputHandler {
     match self {
       Self::Callback(_) => write!(f, "Callback"),
       Self::RingBuffer(_) => write!(f, "RingBuffer"),
+      Self::RingBufferWithOverflow(_, _) => write!(f, "RingBufferWithOverflow"),
+      Self::Fanout(handlers) => f.debug_tuple("Fanout").field(handlers).finish(),
     }
   }
 }
@@ -93,4 +147,42 @@ mod tests {
 
     assert_eq!(consumer.pop(), Some(event));
   }
+
+  #[test]
+  fn fanout_with_independent_overflow() {
+    let (full_producer, mut full_consumer) = ringbuf::RingBuffer::new(1).split();
+    let (producer, mut consumer) = ringbuf::RingBuffer::new(2).split();
+    let overflowed = Arc::new(AtomicU8::new(0));
+    let overflowed_clone = overflowed.clone();
+    let last_group = Arc::new(AtomicU8::new(0));
+    let last_group_clone = last_group.clone();
+    let event = |group| Event {
+      timestamp: 0,
+      endpoint: 0,
+      message: Message {
+        group,
+        mtype: MessageType::Utility(Utility::Noop),
+      },
+    };
+
+    let mut handler = InputHandler::fanout([
+      InputHandler::ring_buffer_with_overflow(full_producer, move |_| {
+        overflowed_clone.fetch_add(1, Ordering::Relaxed);
+      }),
+      InputHandler::from(producer),
+    ])
+    .with_handler(move |event: Event| {
+      last_group_clone.store(event.message.group, Ordering::Relaxed)
+    });
+
+    handler.call(event(1));
+    handler.call(event(2));
+
+    assert_eq!(full_consumer.pop(), Some(event(1)));
+    assert_eq!(full_consumer.pop(), None);
+    assert_eq!(overflowed.load(Ordering::Relaxed), 1);
+    assert_eq!(consumer.pop(), Some(event(1)));
+    assert_eq!(consumer.pop(), Some(event(2)));
+    assert_eq!(last_group.load(Ordering::Relaxed), 2);
+  }
 }
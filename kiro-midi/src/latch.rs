@@ -0,0 +1,268 @@
+//! Note latches, holding the notes played until they are played again.
+//!
+//! The `Latch` is a `Processor` turning every note on into a toggle: the notes not sounding start,
+//! and the ones sounding stop, with a note off sent instead of the note on. The note offs received
+//! are dropped, and the rest of messages pass on.
+//!
+//! All the notes sounding can be stopped from any thread through the `LatchControl`, or with a control
+//! change chosen for it, such as a footswitch.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::endpoints::EndpointId;
+use crate::event::{Event, TimestampNanos};
+use crate::processor::Processor;
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::{Message, MessageType};
+
+/// Groups and channels, for each of MIDI 1.0 and 2.0
+const LATCHED_CHANNELS: usize = 2 * 16 * 16;
+
+/// Toggles the notes on every note on, see the module docs.
+pub struct Latch {
+  /// The notes sounding, as a bit set for every protocol, group and channel
+  latched: Vec<u128>,
+  endpoint: EndpointId,
+  clear_controller: Option<u8>,
+  clear: Arc<AtomicBool>,
+}
+
+impl Latch {
+  pub fn new() -> (Self, LatchControl) {
+    let clear = Arc::new(AtomicBool::new(false));
+    let latch = Self {
+      latched: vec![0; LATCHED_CHANNELS],
+      endpoint: 0,
+      clear_controller: None,
+      clear: clear.clone(),
+    };
+    (latch, LatchControl { clear })
+  }
+
+  /// Stops all the notes sounding when receiving the control change `index` with a value of 64 or more.
+  ///
+  /// The control changes for it are dropped, whatever their value.
+  #[must_use]
+  pub fn with_clear_controller(mut self, index: u8) -> Self {
+    self.clear_controller = Some(index & 0x7f);
+    self
+  }
+
+  fn index(midi2: bool, group: u8, channel: u8) -> usize {
+    midi2 as usize * 256 + (group & 0x0f) as usize * 16 + (channel & 0x0f) as usize
+  }
+
+  /// Toggles the note, returning whether it sounds now.
+  fn toggle(&mut self, midi2: bool, group: u8, channel: u8, note: u8) -> bool {
+    let latched = &mut self.latched[Self::index(midi2, group, channel)];
+    *latched ^= 1 << (note & 0x7f);
+    *latched & (1 << (note & 0x7f)) != 0
+  }
+
+  /// Sends the note offs of all the notes sounding.
+  fn clear(&mut self, timestamp: TimestampNanos, output: &mut dyn FnMut(Event)) {
+    for (index, latched) in self.latched.iter_mut().enumerate() {
+      let midi2 = index >= 256;
+      let group = (index / 16 % 16) as u8;
+      let channel = (index % 16) as u8;
+      for note in (0..128u8).filter(|note| *latched & (1 << note) != 0) {
+        let mtype = if midi2 {
+          MessageType::ChannelVoice(ChannelVoice {
+            channel,
+            message: ChanelVoiceMessage::NoteOff {
+              note,
+              velocity: 0,
+              attr_type: 0,
+              attr_data: 0,
+            },
+          })
+        } else {
+          MessageType::ChannelVoice1(ChannelVoice1 {
+            channel,
+            message: ChannelVoice1Message::NoteOff { note, velocity: 0 },
+          })
+        };
+        output(Event {
+          timestamp,
+          endpoint: self.endpoint,
+          message: Message { group, mtype },
+        });
+      }
+      *latched = 0;
+    }
+  }
+
+  fn clear_requested(&self) -> bool {
+    self.clear.swap(false, Ordering::AcqRel)
+  }
+}
+
+impl Processor for Latch {
+  fn process(&mut self, mut event: Event, output: &mut dyn FnMut(Event)) {
+    if self.clear_requested() {
+      self.clear(event.timestamp, output);
+    }
+
+    let group = event.message.group;
+    match &mut event.message.mtype {
+      MessageType::ChannelVoice1(ChannelVoice1 { channel, message }) => match *message {
+        ChannelVoice1Message::NoteOn { note, velocity } if velocity > 0 => {
+          if !self.toggle(false, group, *channel, note) {
+            *message = ChannelVoice1Message::NoteOff { note, velocity: 0 };
+          }
+        }
+        ChannelVoice1Message::NoteOn { .. } | ChannelVoice1Message::NoteOff { .. } => return,
+        ChannelVoice1Message::ControlChange { index, data }
+          if Some(index) == self.clear_controller =>
+        {
+          if data >= 64 {
+            self.clear(event.timestamp, output);
+          }
+          return;
+        }
+        _ => {}
+      },
+      MessageType::ChannelVoice(ChannelVoice { channel, message }) => match *message {
+        ChanelVoiceMessage::NoteOn { note, .. } => {
+          if !self.toggle(true, group, *channel, note) {
+            *message = ChanelVoiceMessage::NoteOff {
+              note,
+              velocity: 0,
+              attr_type: 0,
+              attr_data: 0,
+            };
+          }
+        }
+        ChanelVoiceMessage::NoteOff { .. } => return,
+        ChanelVoiceMessage::ControlChange { index, data }
+          if Some(index) == self.clear_controller =>
+        {
+          if data >= 0x8000_0000 {
+            self.clear(event.timestamp, output);
+          }
+          return;
+        }
+        _ => {}
+      },
+      _ => {}
+    }
+
+    self.endpoint = event.endpoint;
+    output(event);
+  }
+
+  fn advance(&mut self, now: TimestampNanos, output: &mut dyn FnMut(Event)) {
+    if self.clear_requested() {
+      self.clear(now, output);
+    }
+  }
+
+  /// Forgets the notes sounding, without stopping them.
+  fn reset(&mut self) {
+    self.latched.iter_mut().for_each(|latched| *latched = 0);
+  }
+}
+
+/// Stops the notes held by a `Latch` from any thread.
+#[derive(Clone)]
+pub struct LatchControl {
+  clear: Arc<AtomicBool>,
+}
+
+impl LatchControl {
+  /// Stops all the notes sounding, with the next event processed or when the latch is advanced.
+  pub fn clear(&self) {
+    self.clear.store(true, Ordering::Release);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn note(message: ChannelVoice1Message) -> Event {
+    Event {
+      timestamp: 10,
+      endpoint: 1,
+      message: Message {
+        group: 0,
+        mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+          channel: 3,
+          message,
+        }),
+      },
+    }
+  }
+
+  fn run(latch: &mut Latch, messages: &[ChannelVoice1Message]) -> Vec<ChannelVoice1Message> {
+    let mut output = Vec::new();
+    for message in messages {
+      latch.process(note(*message), &mut |event| match event.message.mtype {
+        MessageType::ChannelVoice1(ChannelVoice1 { message, .. }) => output.push(message),
+        _ => panic!("not a MIDI 1.0 channel voice message"),
+      });
+    }
+    output
+  }
+
+  #[test]
+  fn toggle_notes() {
+    let (mut latch, _) = Latch::new();
+    let on = |note| ChannelVoice1Message::NoteOn { note, velocity: 90 };
+    let off = |note| ChannelVoice1Message::NoteOff { note, velocity: 0 };
+
+    assert_eq!(
+      run(
+        &mut latch,
+        &[
+          on(60),
+          off(60),
+          on(64),
+          ChannelVoice1Message::NoteOn {
+            note: 64,
+            velocity: 0
+          },
+          on(60),
+          off(60),
+          ChannelVoice1Message::PitchBend { data: 0x3000 },
+        ]
+      ),
+      vec![
+        on(60),
+        on(64),
+        off(60),
+        ChannelVoice1Message::PitchBend { data: 0x3000 }
+      ]
+    );
+  }
+
+  #[test]
+  fn clear_the_notes() {
+    let (latch, control) = Latch::new();
+    let mut latch = latch.with_clear_controller(64);
+    let on = |note| ChannelVoice1Message::NoteOn { note, velocity: 90 };
+    let off = |note| ChannelVoice1Message::NoteOff { note, velocity: 0 };
+    let pedal = |data| ChannelVoice1Message::ControlChange { index: 64, data };
+
+    assert_eq!(
+      run(&mut latch, &[on(60), on(67), pedal(127), pedal(0), on(60)]),
+      vec![on(60), on(67), off(60), off(67), on(60)]
+    );
+
+    control.clear();
+    let mut cleared = Vec::new();
+    latch.advance(20, &mut |event| cleared.push(event));
+    assert_eq!(cleared.len(), 1);
+    assert!(
+      cleared[0]
+        == Event {
+          timestamp: 20,
+          ..note(off(60))
+        }
+    );
+
+    latch.advance(30, &mut |_| panic!("nothing to clear"));
+  }
+}
@@ -0,0 +1,359 @@
+//! LFOs, generating control changes, pitch bends or channel pressures following a waveform.
+//!
+//! The `Lfo` is a `Processor` adding its messages to the events passing through it, so it can go
+//! in a `Chain` before any `Output`, or join the events of an input in a `Split`. Its cycles last a
+//! number of beats, at the tempo of its own clock or of the MIDI clock received.
+//!
+//! With its own clock, the messages are generated as the node is advanced (see `Processor::advance`),
+//! every update interval at most, and only when the value changes. With the MIDI clock, they are
+//! generated with the clocks received while the transport runs, following the song position.
+
+use std::time::Duration;
+
+use crate::event::{Event, TimestampNanos};
+use crate::processor::Processor;
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::system::System;
+use crate::protocol::messages::{Message, MessageType};
+use crate::transport::{TransportState, TICKS_PER_BEAT};
+
+const NANOS_PER_MINUTE: f64 = 60_000_000_000.0;
+
+pub const DEFAULT_TEMPO: f64 = 120.0;
+
+pub const DEFAULT_UPDATE_INTERVAL: Duration = Duration::from_millis(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfoWaveform {
+  /// Starting from the middle of the range, going up
+  Sine,
+  /// Starting from the bottom of the range, going up
+  Triangle,
+  /// A random value for every cycle
+  SampleAndHold,
+}
+
+/// The messages generated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LfoTarget {
+  Controller(u8),
+  PitchBend,
+  ChannelPressure,
+}
+
+/// Where the cycles of the LFO follow the tempo from
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LfoClock {
+  /// Its own clock, at a tempo in beats per minute
+  Internal(f64),
+  /// The MIDI clock received
+  External,
+}
+
+/// Generates the messages for a target following a waveform, see the module docs.
+pub struct Lfo {
+  target: LfoTarget,
+  waveform: LfoWaveform,
+  group: u8,
+  channel: u8,
+  beats: f64,
+  low: f32,
+  high: f32,
+  clock: LfoClock,
+  update_interval: TimestampNanos,
+  transport: TransportState,
+  /// When the first cycle started and the next update is due, with the internal clock
+  start: Option<TimestampNanos>,
+  next_update: TimestampNanos,
+  last_data: Option<u16>,
+  /// The cycle held by the sample and hold, and its value
+  held: Option<(u64, f32)>,
+  random: u32,
+}
+
+impl Lfo {
+  /// Sends a sine over the whole range of the target to the group 1 and channel 1,
+  /// with a cycle every beat at 120 BPM.
+  pub fn new(target: LfoTarget) -> Self {
+    Self {
+      target,
+      waveform: LfoWaveform::Sine,
+      group: 0,
+      channel: 0,
+      beats: 1.0,
+      low: 0.0,
+      high: 1.0,
+      clock: LfoClock::Internal(DEFAULT_TEMPO),
+      update_interval: DEFAULT_UPDATE_INTERVAL.as_nanos() as TimestampNanos,
+      transport: TransportState::new(),
+      start: None,
+      next_update: 0,
+      last_data: None,
+      held: None,
+      random: 0x2545_f491,
+    }
+  }
+
+  #[must_use]
+  pub fn with_waveform(mut self, waveform: LfoWaveform) -> Self {
+    self.waveform = waveform;
+    self
+  }
+
+  /// Sends the messages to a group from 1 to 16 and a channel from 1 to 16.
+  #[must_use]
+  pub fn with_destination(mut self, group: u8, channel: u8) -> Self {
+    self.group = group.clamp(1, 16) - 1;
+    self.channel = channel.clamp(1, 16) - 1;
+    self
+  }
+
+  /// Sets the length of every cycle, in beats, such as 4 for a bar or 0.25 for a sixteenth.
+  #[must_use]
+  pub fn with_period(mut self, beats: f64) -> Self {
+    self.beats = beats.max(1.0 / TICKS_PER_BEAT as f64);
+    self
+  }
+
+  /// Limits the values to a part of the range of the target, from 0 (its minimum) to 1 (its maximum).
+  #[must_use]
+  pub fn with_range(mut self, low: f32, high: f32) -> Self {
+    self.low = low.clamp(0.0, 1.0);
+    self.high = high.clamp(0.0, 1.0);
+    self
+  }
+
+  #[must_use]
+  pub fn with_clock(mut self, clock: LfoClock) -> Self {
+    self.clock = match clock {
+      LfoClock::Internal(tempo) => LfoClock::Internal(tempo.max(1.0)),
+      LfoClock::External => LfoClock::External,
+    };
+    self
+  }
+
+  /// Sets the shortest time between the messages with the internal clock.
+  #[must_use]
+  pub fn with_update_interval(mut self, interval: Duration) -> Self {
+    self.update_interval = (interval.as_nanos() as TimestampNanos).max(1);
+    self
+  }
+
+  /// The value of the waveform at `phase`, in cycles from the start, from 0 to 1.
+  fn wave(&mut self, phase: f64) -> f32 {
+    let position = phase.fract() as f32;
+    match self.waveform {
+      LfoWaveform::Sine => 0.5 + 0.5 * (position * core::f32::consts::TAU).sin(),
+      LfoWaveform::Triangle if position < 0.5 => 2.0 * position,
+      LfoWaveform::Triangle => 2.0 - 2.0 * position,
+      LfoWaveform::SampleAndHold => {
+        let cycle = phase as u64;
+        match self.held {
+          Some((held_cycle, value)) if held_cycle == cycle => value,
+          _ => {
+            // Xorshift, good enough for the values
+            self.random ^= self.random << 13;
+            self.random ^= self.random >> 17;
+            self.random ^= self.random << 5;
+            let value = self.random as f32 / u32::MAX as f32;
+            self.held = Some((cycle, value));
+            value
+          }
+        }
+      }
+    }
+  }
+
+  /// Passes on the message for the value at `phase`, unless it didn't change.
+  fn update(&mut self, timestamp: TimestampNanos, phase: f64, output: &mut dyn FnMut(Event)) {
+    let value = self.low + (self.high - self.low) * self.wave(phase);
+    let max = match self.target {
+      LfoTarget::PitchBend => 0x3fff,
+      LfoTarget::Controller(_) | LfoTarget::ChannelPressure => 0x7f,
+    };
+    let data = (value * max as f32).round() as u16;
+    if self.last_data == Some(data) {
+      return;
+    }
+    self.last_data = Some(data);
+
+    let message = match self.target {
+      LfoTarget::Controller(index) => ChannelVoice1Message::ControlChange {
+        index: index & 0x7f,
+        data: data as u8,
+      },
+      LfoTarget::PitchBend => ChannelVoice1Message::PitchBend { data },
+      LfoTarget::ChannelPressure => ChannelVoice1Message::ChannelPressure { data: data as u8 },
+    };
+    output(Event {
+      timestamp,
+      endpoint: 0,
+      message: Message {
+        group: self.group,
+        mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+          channel: self.channel,
+          message,
+        }),
+      },
+    });
+  }
+}
+
+impl Processor for Lfo {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    self.advance(event.timestamp, output);
+
+    if self.clock == LfoClock::External {
+      if let MessageType::System(System::TimingClock) = event.message.mtype {
+        if let Some(clocks) = self.transport.tick() {
+          let phase = clocks as f64 / (TICKS_PER_BEAT as f64 * self.beats);
+          self.update(event.timestamp, phase, output);
+        }
+      } else {
+        self.transport.process(&event.message);
+      }
+    }
+
+    output(event);
+  }
+
+  fn advance(&mut self, now: TimestampNanos, output: &mut dyn FnMut(Event)) {
+    let tempo = match self.clock {
+      LfoClock::Internal(tempo) => tempo,
+      LfoClock::External => return,
+    };
+    let start = *self.start.get_or_insert_with(|| {
+      self.next_update = now;
+      now
+    });
+    let cycle_nanos = self.beats * NANOS_PER_MINUTE / tempo;
+    while self.next_update <= now {
+      let phase = (self.next_update - start) as f64 / cycle_nanos;
+      self.update(self.next_update, phase, output);
+      self.next_update += self.update_interval;
+    }
+  }
+
+  /// Starts the cycles over, with the next advance or clock.
+  fn reset(&mut self) {
+    self.transport.reset();
+    self.start = None;
+    self.last_data = None;
+    self.held = None;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MS: TimestampNanos = 1_000_000;
+
+  fn values(events: &[Event]) -> Vec<(TimestampNanos, u16)> {
+    events
+      .iter()
+      .map(|event| match event.message.mtype {
+        MessageType::ChannelVoice1(ChannelVoice1 { message, .. }) => {
+          let data = match message {
+            ChannelVoice1Message::ControlChange { data, .. } => data as u16,
+            ChannelVoice1Message::PitchBend { data } => data,
+            ChannelVoice1Message::ChannelPressure { data } => data as u16,
+            _ => panic!("unexpected message"),
+          };
+          (event.timestamp / MS, data)
+        }
+        _ => panic!("unexpected message"),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn triangle_with_the_internal_clock() {
+    // A cycle every 500 ms, updating every 125 ms
+    let mut lfo = Lfo::new(LfoTarget::Controller(1))
+      .with_waveform(LfoWaveform::Triangle)
+      .with_destination(1, 2)
+      .with_clock(LfoClock::Internal(120.0))
+      .with_update_interval(Duration::from_millis(125));
+    let mut events = Vec::new();
+
+    lfo.advance(1000 * MS, &mut |event| events.push(event));
+    lfo.advance(1500 * MS, &mut |event| events.push(event));
+
+    assert_eq!(
+      values(&events),
+      vec![(1000, 0), (1125, 64), (1250, 127), (1375, 64), (1500, 0)]
+    );
+    assert!(matches!(
+      events[0].message.mtype,
+      MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 1,
+        message: ChannelVoice1Message::ControlChange { index: 1, .. },
+      })
+    ));
+  }
+
+  #[test]
+  fn sine_with_the_external_clock() {
+    let mut lfo = Lfo::new(LfoTarget::PitchBend)
+      .with_clock(LfoClock::External)
+      .with_period(4.0 / 24.0)
+      .with_range(0.0, 0.4);
+    let mut events = Vec::new();
+    let system = |timestamp, system| Event {
+      timestamp,
+      endpoint: 1,
+      message: Message {
+        group: 0,
+        mtype: MessageType::System(system),
+      },
+    };
+
+    lfo.process(system(0, System::TimingClock), &mut |event| {
+      events.push(event)
+    });
+    lfo.process(system(MS, System::Start), &mut |event| events.push(event));
+    for clock in 0..7 {
+      lfo.process(
+        system((clock + 2) * MS, System::TimingClock),
+        &mut |event| events.push(event),
+      );
+    }
+
+    let generated = events
+      .iter()
+      .filter(|event| event.endpoint == 0)
+      .cloned()
+      .collect::<Vec<_>>();
+    assert_eq!(
+      values(&generated),
+      vec![
+        (2, 3277),
+        (3, 6553),
+        (4, 3277),
+        (5, 0),
+        (6, 3277),
+        (7, 6553),
+        (8, 3277)
+      ]
+    );
+    assert_eq!(events.len() - generated.len(), 9);
+  }
+
+  #[test]
+  fn sample_and_hold_every_cycle() {
+    let mut lfo = Lfo::new(LfoTarget::ChannelPressure)
+      .with_waveform(LfoWaveform::SampleAndHold)
+      .with_range(0.5, 1.0);
+    let mut events = Vec::new();
+
+    lfo.advance(0, &mut |event| events.push(event));
+    lfo.advance(2400 * MS, &mut |event| events.push(event));
+
+    let values = values(&events);
+    assert!(values.len() <= 5);
+    assert!(values
+      .iter()
+      .all(|(timestamp, value)| timestamp % 500 == 0 && (63..=127).contains(value)));
+  }
+}
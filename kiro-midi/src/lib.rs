@@ -1,19 +1,118 @@
+//! MIDI 1.0 and 2.0 for kiro, with the drivers to talk to the MIDI devices of every platform.
+//!
+//! The protocol layer (the UMP decoder and encoder, the messages and the filters) only needs
+//! `core` and `alloc`, so it can be used from `no_std` projects with the default features disabled.
+//! Everything else needs the `std` feature.
+
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod arpeggiator;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod delay;
+#[cfg(feature = "std")]
+pub(crate) mod destination_match;
+#[cfg(feature = "std")]
 pub mod drivers;
+#[cfg(feature = "std")]
 pub mod endpoints;
+#[cfg(feature = "std")]
 pub(crate) mod event;
+// Some parts are only used by the drivers
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 pub(crate) mod filter;
+#[cfg(feature = "std")]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod humanize;
+#[cfg(feature = "std")]
+pub mod identity;
+#[cfg(feature = "std")]
 pub(crate) mod input_config;
+#[cfg(feature = "std")]
 pub(crate) mod input_handler;
+#[cfg(feature = "std")]
 pub(crate) mod input_info;
+#[cfg(feature = "std")]
+pub mod latch;
+#[cfg(feature = "std")]
+pub mod lfo;
+#[cfg(feature = "std")]
+pub mod midi_ci;
+#[cfg(feature = "std")]
+pub mod mmc;
+#[cfg(feature = "std")]
+pub mod mpe;
+#[cfg(feature = "std")]
+pub mod msc;
+#[cfg(feature = "std")]
 pub mod note_freq;
+#[cfg(feature = "std")]
+pub(crate) mod output;
+#[cfg(feature = "std")]
+pub(crate) mod output_config;
+#[cfg(feature = "std")]
+pub(crate) mod output_connection;
+#[cfg(feature = "std")]
+pub(crate) mod output_producer;
+#[cfg(feature = "std")]
+pub(crate) mod output_queue;
+#[cfg(feature = "std")]
+pub mod processor;
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
 pub(crate) mod protocol;
+#[cfg(feature = "std")]
+pub mod router;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "std")]
+pub mod sds;
+#[cfg(feature = "std")]
 pub(crate) mod source_match;
+#[cfg(feature = "std")]
+pub mod timecode;
+#[cfg(feature = "std")]
+pub(crate) mod transform;
+#[cfg(feature = "std")]
+pub mod transport;
+#[cfg(feature = "std")]
+pub mod tuning;
+#[cfg(feature = "std")]
+pub mod voices;
 
+#[cfg(feature = "std")]
+pub use destination_match::{DestinationMatch, DestinationMatches, DestinationRemap};
+#[cfg(feature = "std")]
 pub use drivers::{Driver, DriverSpec};
-pub use event::{Event, TimestampNanos};
-pub use filter::Filter;
+#[cfg(feature = "std")]
+pub use event::{now, Event, TimestampNanos};
+pub use filter::{Filter, FilterExpr, FilterPresets};
+#[cfg(feature = "std")]
 pub use input_config::InputConfig;
+#[cfg(feature = "std")]
 pub use input_handler::InputHandler;
+#[cfg(feature = "std")]
 pub use input_info::InputInfo;
+#[cfg(feature = "std")]
+pub use output::Output;
+#[cfg(feature = "std")]
+pub use output_config::OutputConfig;
+#[cfg(feature = "std")]
+pub use output_connection::{OutputConnection, OutputConnectionHandler};
+#[cfg(feature = "std")]
+pub use output_producer::OutputProducer;
+#[cfg(feature = "std")]
+pub use output_queue::OutputQueue;
+#[cfg(any(test, feature = "conformance"))]
+pub use protocol::conformance;
 pub use protocol::messages;
+pub use protocol::midi1;
+pub use protocol::{decoder, encoder};
+#[cfg(feature = "std")]
 pub use source_match::{SourceMatch, SourceMatches};
+#[cfg(feature = "std")]
+pub use transform::{Pressure, Quantize, Scale, Transform, VelocityCurve};
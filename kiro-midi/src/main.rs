@@ -67,11 +67,14 @@ fn print_endpoints(driver: &midi::drivers::Driver) {
         format!(" ({})", source.connected_inputs.join(", "))
       })
       .unwrap_or_default();
-    println!("  [{:08x}] {} {}", source.id, source.name, input_names);
+    println!(
+      "  [{:08x}] {} {}",
+      source.id, source.display_name, input_names
+    );
   }
   println!("Destinations:");
   for destination in driver.destinations() {
-    println!("  [{:08x}] {}", destination.id, destination.name);
+    println!("  [{:08x}] {}", destination.id, destination.display_name);
   }
   println!("===================================================================================");
 }
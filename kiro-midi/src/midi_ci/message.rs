@@ -0,0 +1,646 @@
+use crate::midi_ci::negotiation::MidiProtocol;
+use crate::midi_ci::profile::ProfileId;
+use crate::midi_ci::{CiAddress, CiCategories, DeviceInfo, Muid};
+
+const UNIVERSAL_NON_REALTIME: u8 = 0x7e;
+const MIDI_CI: u8 = 0x0d;
+const CI_VERSION: u8 = 0x02;
+/// MIDI-CI 1.2 deprecated the protocol negotiation, so its messages are sent as of MIDI-CI 1.1
+const PROTOCOL_NEGOTIATION_CI_VERSION: u8 = 0x01;
+/// No function block, used in the replies to discovery from devices without them
+const NO_FUNCTION_BLOCK: u8 = 0x7f;
+
+const INITIATE_PROTOCOL_NEGOTIATION: u8 = 0x10;
+const PROTOCOL_NEGOTIATION_REPLY: u8 = 0x11;
+const SET_NEW_PROTOCOL: u8 = 0x12;
+const TEST_NEW_PROTOCOL: u8 = 0x13;
+const TEST_NEW_PROTOCOL_REPLY: u8 = 0x14;
+const NEW_PROTOCOL_ESTABLISHED: u8 = 0x15;
+const PROFILE_INQUIRY: u8 = 0x20;
+const PROFILE_INQUIRY_REPLY: u8 = 0x21;
+pub(crate) const SET_PROFILE_ON: u8 = 0x22;
+pub(crate) const SET_PROFILE_OFF: u8 = 0x23;
+const PROFILE_ENABLED_REPORT: u8 = 0x24;
+const PROFILE_DISABLED_REPORT: u8 = 0x25;
+const PROFILE_ADDED_REPORT: u8 = 0x26;
+const PROFILE_REMOVED_REPORT: u8 = 0x27;
+pub(crate) const GET_PROPERTY: u8 = 0x34;
+const GET_PROPERTY_REPLY: u8 = 0x35;
+pub(crate) const SET_PROPERTY: u8 = 0x36;
+const SET_PROPERTY_REPLY: u8 = 0x37;
+const DISCOVERY: u8 = 0x70;
+const DISCOVERY_REPLY: u8 = 0x71;
+const INVALIDATE_MUID: u8 = 0x7e;
+const NAK: u8 = 0x7f;
+
+/// Status code of the NAKs for the messages that are not supported
+const NAK_NOT_SUPPORTED: u8 = 0x01;
+
+/// Length of the fields common to all the messages, from the universal SysEx id to the destination MUID
+const HEADER_LEN: usize = 13;
+
+/// Length of the fields of a property exchange message besides its header and data
+pub(crate) const PROPERTY_FIELDS_LEN: usize = HEADER_LEN + 9;
+
+/// A MIDI-CI message, as sent in the data of a SysEx message without the `F0` and `F7` bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct CiMessage {
+  pub address: CiAddress,
+  pub source: Muid,
+  pub destination: Muid,
+  pub body: CiBody,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum CiBody {
+  Discovery {
+    device_info: DeviceInfo,
+    categories: CiCategories,
+    max_sysex_size: u32,
+  },
+  DiscoveryReply {
+    device_info: DeviceInfo,
+    categories: CiCategories,
+    max_sysex_size: u32,
+  },
+  InvalidateMuid {
+    target: Muid,
+  },
+  InitiateProtocolNegotiation {
+    authority: u8,
+    protocols: Vec<MidiProtocol>,
+  },
+  ProtocolNegotiationReply {
+    authority: u8,
+    protocols: Vec<MidiProtocol>,
+  },
+  SetNewProtocol {
+    authority: u8,
+    protocol: MidiProtocol,
+  },
+  TestNewProtocol {
+    authority: u8,
+    data: Vec<u8>,
+  },
+  TestNewProtocolReply {
+    authority: u8,
+    data: Vec<u8>,
+  },
+  NewProtocolEstablished {
+    authority: u8,
+  },
+  ProfileInquiry,
+  ProfileInquiryReply {
+    enabled: Vec<ProfileId>,
+    disabled: Vec<ProfileId>,
+  },
+  SetProfileOn {
+    profile: ProfileId,
+    channels: u16,
+  },
+  SetProfileOff {
+    profile: ProfileId,
+  },
+  ProfileEnabledReport {
+    profile: ProfileId,
+    channels: u16,
+  },
+  ProfileDisabledReport {
+    profile: ProfileId,
+    channels: u16,
+  },
+  ProfileAddedReport {
+    profile: ProfileId,
+  },
+  ProfileRemovedReport {
+    profile: ProfileId,
+  },
+  GetProperty(PropertyChunk),
+  GetPropertyReply(PropertyChunk),
+  SetProperty(PropertyChunk),
+  SetPropertyReply(PropertyChunk),
+  Nak {
+    sub_id2: u8,
+  },
+  /// Messages that can not be handled, with their sub id
+  Unsupported(u8),
+}
+
+/// A chunk of a property exchange message, with the header in the first one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct PropertyChunk {
+  pub request_id: u8,
+  pub header: Vec<u8>,
+  pub chunks: u16,
+  /// Number of this chunk, starting from 1
+  pub chunk: u16,
+  pub data: Vec<u8>,
+}
+
+impl PropertyChunk {
+  fn parse(data: &[u8]) -> Option<Self> {
+    let request_id = *data.get(0)?;
+    let header_len = read_u14(data.get(1..3)?) as usize;
+    let header = data.get(3..3 + header_len)?.to_vec();
+    let data = &data[3 + header_len..];
+    let chunks = read_u14(data.get(0..2)?);
+    let chunk = read_u14(data.get(2..4)?);
+    let data_len = read_u14(data.get(4..6)?) as usize;
+    let data = data.get(6..6 + data_len)?.to_vec();
+    Some(Self {
+      request_id,
+      header,
+      chunks,
+      chunk,
+      data,
+    })
+  }
+
+  fn write(&self, data: &mut Vec<u8>) {
+    data.push(self.request_id & 0x7f);
+    write_u14(data, self.header.len() as u16);
+    data.extend_from_slice(&self.header);
+    write_u14(data, self.chunks);
+    write_u14(data, self.chunk);
+    write_u14(data, self.data.len() as u16);
+    data.extend_from_slice(&self.data);
+  }
+}
+
+impl CiMessage {
+  /// Parses the data of a SysEx message, returning `None` when it is not a valid MIDI-CI message.
+  pub fn parse(data: &[u8]) -> Option<Self> {
+    let data = data.strip_prefix(&[0xf0]).unwrap_or(data);
+    let data = data.strip_suffix(&[0xf7]).unwrap_or(data);
+    if data.len() < HEADER_LEN || data[0] != UNIVERSAL_NON_REALTIME || data[2] != MIDI_CI {
+      return None;
+    }
+
+    let address = CiAddress::from_device_id(data[1]);
+    let sub_id2 = data[3];
+    let source = read_u28(&data[5..9]);
+    let destination = read_u28(&data[9..13]);
+    let body = &data[HEADER_LEN..];
+
+    let body = match sub_id2 {
+      DISCOVERY | DISCOVERY_REPLY => {
+        if body.len() < 16 {
+          return None;
+        }
+        let device_info = DeviceInfo {
+          manufacturer: [body[0], body[1], body[2]],
+          family: read_u14(&body[3..5]),
+          model: read_u14(&body[5..7]),
+          version: [body[7], body[8], body[9], body[10]],
+        };
+        let categories = CiCategories::from_bits(body[11]);
+        let max_sysex_size = read_u28(&body[12..16]);
+        if sub_id2 == DISCOVERY {
+          CiBody::Discovery {
+            device_info,
+            categories,
+            max_sysex_size,
+          }
+        } else {
+          CiBody::DiscoveryReply {
+            device_info,
+            categories,
+            max_sysex_size,
+          }
+        }
+      }
+      INVALIDATE_MUID => CiBody::InvalidateMuid {
+        target: read_u28(body.get(0..4)?),
+      },
+      INITIATE_PROTOCOL_NEGOTIATION => CiBody::InitiateProtocolNegotiation {
+        authority: *body.get(0)?,
+        protocols: read_protocols(body)?,
+      },
+      PROTOCOL_NEGOTIATION_REPLY => CiBody::ProtocolNegotiationReply {
+        authority: *body.get(0)?,
+        protocols: read_protocols(body)?,
+      },
+      SET_NEW_PROTOCOL => CiBody::SetNewProtocol {
+        authority: *body.get(0)?,
+        protocol: MidiProtocol::from_bytes(body.get(1..6)?)?,
+      },
+      TEST_NEW_PROTOCOL => CiBody::TestNewProtocol {
+        authority: *body.get(0)?,
+        data: body.get(1..49)?.to_vec(),
+      },
+      TEST_NEW_PROTOCOL_REPLY => CiBody::TestNewProtocolReply {
+        authority: *body.get(0)?,
+        data: body.get(1..49)?.to_vec(),
+      },
+      NEW_PROTOCOL_ESTABLISHED => CiBody::NewProtocolEstablished {
+        authority: *body.get(0)?,
+      },
+      PROFILE_INQUIRY => CiBody::ProfileInquiry,
+      PROFILE_INQUIRY_REPLY => {
+        let (enabled, body) = read_profiles(body)?;
+        let (disabled, _) = read_profiles(body)?;
+        CiBody::ProfileInquiryReply { enabled, disabled }
+      }
+      SET_PROFILE_ON => CiBody::SetProfileOn {
+        profile: read_profile(body)?,
+        channels: body.get(5..7).map_or(1, read_u14),
+      },
+      SET_PROFILE_OFF => CiBody::SetProfileOff {
+        profile: read_profile(body)?,
+      },
+      PROFILE_ENABLED_REPORT => CiBody::ProfileEnabledReport {
+        profile: read_profile(body)?,
+        channels: body.get(5..7).map_or(1, read_u14),
+      },
+      PROFILE_DISABLED_REPORT => CiBody::ProfileDisabledReport {
+        profile: read_profile(body)?,
+        channels: body.get(5..7).map_or(1, read_u14),
+      },
+      PROFILE_ADDED_REPORT => CiBody::ProfileAddedReport {
+        profile: read_profile(body)?,
+      },
+      PROFILE_REMOVED_REPORT => CiBody::ProfileRemovedReport {
+        profile: read_profile(body)?,
+      },
+      GET_PROPERTY => CiBody::GetProperty(PropertyChunk::parse(body)?),
+      GET_PROPERTY_REPLY => CiBody::GetPropertyReply(PropertyChunk::parse(body)?),
+      SET_PROPERTY => CiBody::SetProperty(PropertyChunk::parse(body)?),
+      SET_PROPERTY_REPLY => CiBody::SetPropertyReply(PropertyChunk::parse(body)?),
+      NAK => CiBody::Nak {
+        sub_id2: body.get(0).cloned().unwrap_or(0),
+      },
+      sub_id2 => CiBody::Unsupported(sub_id2),
+    };
+
+    Some(Self {
+      address,
+      source,
+      destination,
+      body,
+    })
+  }
+
+  /// Builds the data of the SysEx message, without the `F0` and `F7` bytes.
+  pub fn to_sysex(&self) -> Vec<u8> {
+    let sub_id2 = match &self.body {
+      CiBody::Discovery { .. } => DISCOVERY,
+      CiBody::DiscoveryReply { .. } => DISCOVERY_REPLY,
+      CiBody::InvalidateMuid { .. } => INVALIDATE_MUID,
+      CiBody::InitiateProtocolNegotiation { .. } => INITIATE_PROTOCOL_NEGOTIATION,
+      CiBody::ProtocolNegotiationReply { .. } => PROTOCOL_NEGOTIATION_REPLY,
+      CiBody::SetNewProtocol { .. } => SET_NEW_PROTOCOL,
+      CiBody::TestNewProtocol { .. } => TEST_NEW_PROTOCOL,
+      CiBody::TestNewProtocolReply { .. } => TEST_NEW_PROTOCOL_REPLY,
+      CiBody::NewProtocolEstablished { .. } => NEW_PROTOCOL_ESTABLISHED,
+      CiBody::ProfileInquiry => PROFILE_INQUIRY,
+      CiBody::ProfileInquiryReply { .. } => PROFILE_INQUIRY_REPLY,
+      CiBody::SetProfileOn { .. } => SET_PROFILE_ON,
+      CiBody::SetProfileOff { .. } => SET_PROFILE_OFF,
+      CiBody::ProfileEnabledReport { .. } => PROFILE_ENABLED_REPORT,
+      CiBody::ProfileDisabledReport { .. } => PROFILE_DISABLED_REPORT,
+      CiBody::ProfileAddedReport { .. } => PROFILE_ADDED_REPORT,
+      CiBody::ProfileRemovedReport { .. } => PROFILE_REMOVED_REPORT,
+      CiBody::GetProperty(_) => GET_PROPERTY,
+      CiBody::GetPropertyReply(_) => GET_PROPERTY_REPLY,
+      CiBody::SetProperty(_) => SET_PROPERTY,
+      CiBody::SetPropertyReply(_) => SET_PROPERTY_REPLY,
+      CiBody::Nak { .. } => NAK,
+      CiBody::Unsupported(sub_id2) => *sub_id2,
+    };
+
+    let version = match sub_id2 {
+      INITIATE_PROTOCOL_NEGOTIATION..=NEW_PROTOCOL_ESTABLISHED => PROTOCOL_NEGOTIATION_CI_VERSION,
+      _ => CI_VERSION,
+    };
+    let mut data = vec![
+      UNIVERSAL_NON_REALTIME,
+      self.address.device_id(),
+      MIDI_CI,
+      sub_id2,
+      version,
+    ];
+    write_u28(&mut data, self.source);
+    write_u28(&mut data, self.destination);
+
+    match &self.body {
+      CiBody::Discovery {
+        device_info,
+        categories,
+        max_sysex_size,
+      }
+      | CiBody::DiscoveryReply {
+        device_info,
+        categories,
+        max_sysex_size,
+      } => {
+        data.extend_from_slice(&device_info.manufacturer);
+        write_u14(&mut data, device_info.family);
+        write_u14(&mut data, device_info.model);
+        data.extend_from_slice(&device_info.version);
+        data.push(categories.to_bits());
+        write_u28(&mut data, *max_sysex_size);
+        // Output path id
+        data.push(0);
+        if sub_id2 == DISCOVERY_REPLY {
+          data.push(NO_FUNCTION_BLOCK);
+        }
+      }
+      CiBody::InvalidateMuid { target } => write_u28(&mut data, *target),
+      CiBody::InitiateProtocolNegotiation {
+        authority,
+        protocols,
+      }
+      | CiBody::ProtocolNegotiationReply {
+        authority,
+        protocols,
+      } => {
+        data.push(*authority);
+        data.push(protocols.len() as u8);
+        for protocol in protocols {
+          data.extend_from_slice(&protocol.to_bytes());
+        }
+      }
+      CiBody::SetNewProtocol {
+        authority,
+        protocol,
+      } => {
+        data.push(*authority);
+        data.extend_from_slice(&protocol.to_bytes());
+      }
+      CiBody::TestNewProtocol {
+        authority,
+        data: test_data,
+      }
+      | CiBody::TestNewProtocolReply {
+        authority,
+        data: test_data,
+      } => {
+        data.push(*authority);
+        data.extend_from_slice(test_data);
+      }
+      CiBody::NewProtocolEstablished { authority } => data.push(*authority),
+      CiBody::ProfileInquiry => {}
+      CiBody::ProfileInquiryReply { enabled, disabled } => {
+        write_profiles(&mut data, enabled);
+        write_profiles(&mut data, disabled);
+      }
+      CiBody::SetProfileOn { profile, channels }
+      | CiBody::ProfileEnabledReport { profile, channels }
+      | CiBody::ProfileDisabledReport { profile, channels } => {
+        data.extend_from_slice(&profile.0);
+        write_u14(&mut data, *channels);
+      }
+      CiBody::SetProfileOff { profile } => {
+        data.extend_from_slice(&profile.0);
+        // Reserved
+        write_u14(&mut data, 0);
+      }
+      CiBody::ProfileAddedReport { profile } | CiBody::ProfileRemovedReport { profile } => {
+        data.extend_from_slice(&profile.0);
+      }
+      CiBody::GetProperty(chunk)
+      | CiBody::GetPropertyReply(chunk)
+      | CiBody::SetProperty(chunk)
+      | CiBody::SetPropertyReply(chunk) => chunk.write(&mut data),
+      CiBody::Nak { sub_id2 } => {
+        // Status code and data, details, and an empty text
+        data.extend_from_slice(&[*sub_id2, NAK_NOT_SUPPORTED, 0, 0, 0, 0, 0, 0, 0, 0]);
+      }
+      CiBody::Unsupported(_) => {}
+    }
+
+    data
+  }
+}
+
+/// Reads the protocols supported by a device, skipping the unknown ones.
+fn read_protocols(data: &[u8]) -> Option<Vec<MidiProtocol>> {
+  let len = *data.get(1)? as usize;
+  let protocols = data.get(2..2 + len * 5)?;
+  Some(
+    protocols
+      .chunks(5)
+      .filter_map(MidiProtocol::from_bytes)
+      .collect(),
+  )
+}
+
+fn read_profile(data: &[u8]) -> Option<ProfileId> {
+  let bytes = data.get(0..5)?;
+  Some(ProfileId([
+    bytes[0], bytes[1], bytes[2], bytes[3], bytes[4],
+  ]))
+}
+
+/// Reads a list of profiles preceded by its length, returning the data after it.
+fn read_profiles(data: &[u8]) -> Option<(Vec<ProfileId>, &[u8])> {
+  let len = read_u14(data.get(0..2)?) as usize;
+  let profiles = (0..len)
+    .map(|index| data.get(2 + index * 5..).and_then(read_profile))
+    .collect::<Option<Vec<ProfileId>>>()?;
+  Some((profiles, &data[2 + len * 5..]))
+}
+
+fn write_profiles(data: &mut Vec<u8>, profiles: &[ProfileId]) {
+  write_u14(data, profiles.len() as u16);
+  for profile in profiles {
+    data.extend_from_slice(&profile.0);
+  }
+}
+
+fn read_u14(data: &[u8]) -> u16 {
+  (data[0] & 0x7f) as u16 | ((data[1] & 0x7f) as u16) << 7
+}
+
+fn write_u14(data: &mut Vec<u8>, value: u16) {
+  data.extend_from_slice(&[(value & 0x7f) as u8, ((value >> 7) & 0x7f) as u8]);
+}
+
+fn read_u28(data: &[u8]) -> u32 {
+  data
+    .iter()
+    .take(4)
+    .enumerate()
+    .fold(0, |value, (index, byte)| {
+      value | ((byte & 0x7f) as u32) << (index * 7)
+    })
+}
+
+fn write_u28(data: &mut Vec<u8>, value: u32) {
+  for index in 0..4 {
+    data.push(((value >> (index * 7)) & 0x7f) as u8);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::midi_ci::BROADCAST_MUID;
+
+  #[test]
+  fn discovery() {
+    let message = CiMessage {
+      address: CiAddress::FunctionBlock,
+      source: 0x0123_4567,
+      destination: BROADCAST_MUID,
+      body: CiBody::Discovery {
+        device_info: DeviceInfo {
+          manufacturer: [0x00, 0x21, 0x09],
+          family: 0x0102,
+          model: 0x0304,
+          version: [1, 2, 3, 4],
+        },
+        categories: CiCategories {
+          profile_configuration: true,
+          ..CiCategories::default()
+        },
+        max_sysex_size: 512,
+      },
+    };
+
+    let data = message.to_sysex();
+    assert_eq!(
+      data,
+      vec![
+        0x7e, 0x7f, 0x0d, 0x70, 0x02, 0x67, 0x0a, 0x0d, 0x09, 0x7f, 0x7f, 0x7f, 0x7f, 0x00, 0x21,
+        0x09, 0x02, 0x02, 0x04, 0x06, 0x01, 0x02, 0x03, 0x04, 0x04, 0x00, 0x04, 0x00, 0x00, 0x00
+      ]
+    );
+    assert_eq!(CiMessage::parse(&data), Some(message));
+  }
+
+  #[test]
+  fn invalid_messages() {
+    assert_eq!(CiMessage::parse(&[0x7e, 0x7f, 0x0d, 0x70]), None);
+    assert_eq!(CiMessage::parse(&[0x7e, 0x7f, 0x06, 0x01]), None);
+    assert_eq!(
+      CiMessage::parse(&[0x7e, 0x7f, 0x0d, 0x70, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0x00]),
+      None
+    );
+  }
+
+  #[test]
+  fn unsupported_messages() {
+    let data = [
+      0xf0, 0x7e, 0x7f, 0x0d, 0x40, 0x02, 1, 0, 0, 0, 2, 0, 0, 0, 0xf7,
+    ];
+
+    assert_eq!(
+      CiMessage::parse(&data),
+      Some(CiMessage {
+        address: CiAddress::FunctionBlock,
+        source: 1,
+        destination: 2,
+        body: CiBody::Unsupported(0x40),
+      })
+    );
+  }
+
+  #[test]
+  fn profile_inquiry_reply() {
+    let message = CiMessage {
+      address: CiAddress::Channel(2),
+      source: 1,
+      destination: 2,
+      body: CiBody::ProfileInquiryReply {
+        enabled: vec![ProfileId([0x7e, 0x31, 0x00, 0x01, 0x01])],
+        disabled: vec![
+          ProfileId([0x7e, 0x21, 0x00, 0x01, 0x01]),
+          ProfileId([0x00, 0x21, 0x09, 0x05, 0x06]),
+        ],
+      },
+    };
+
+    let data = message.to_sysex();
+    assert_eq!(data[1], 0x02);
+    assert_eq!(data.len(), 13 + 2 + 5 + 2 + 2 * 5);
+    assert_eq!(CiMessage::parse(&data), Some(message));
+    assert_eq!(CiMessage::parse(&data[..data.len() - 1]), None);
+  }
+
+  #[test]
+  fn set_profile() {
+    let profile = ProfileId([0x7e, 0x31, 0x00, 0x01, 0x01]);
+    for body in [
+      CiBody::SetProfileOn {
+        profile,
+        channels: 16,
+      },
+      CiBody::SetProfileOff { profile },
+      CiBody::ProfileEnabledReport {
+        profile,
+        channels: 1,
+      },
+    ] {
+      let message = CiMessage {
+        address: CiAddress::Group,
+        source: 1,
+        destination: 2,
+        body,
+      };
+      assert_eq!(CiMessage::parse(&message.to_sysex()), Some(message));
+    }
+  }
+
+  #[test]
+  fn property_chunks() {
+    let message = CiMessage {
+      address: CiAddress::FunctionBlock,
+      source: 1,
+      destination: 2,
+      body: CiBody::GetPropertyReply(PropertyChunk {
+        request_id: 5,
+        header: b"{\"status\":200}".to_vec(),
+        chunks: 2,
+        chunk: 1,
+        data: b"{\"name\":".to_vec(),
+      }),
+    };
+
+    let data = message.to_sysex();
+    assert_eq!(data.len(), PROPERTY_FIELDS_LEN + 14 + 8);
+    assert_eq!(CiMessage::parse(&data), Some(message));
+    assert_eq!(CiMessage::parse(&data[..data.len() - 1]), None);
+  }
+
+  #[test]
+  fn protocol_negotiation() {
+    for body in [
+      CiBody::InitiateProtocolNegotiation {
+        authority: 0x60,
+        protocols: vec![MidiProtocol::Midi2, MidiProtocol::Midi1],
+      },
+      CiBody::SetNewProtocol {
+        authority: 0x60,
+        protocol: MidiProtocol::Midi2,
+      },
+      CiBody::TestNewProtocolReply {
+        authority: 0x10,
+        data: (0..48).collect(),
+      },
+      CiBody::NewProtocolEstablished { authority: 0x60 },
+    ] {
+      let message = CiMessage {
+        address: CiAddress::FunctionBlock,
+        source: 1,
+        destination: 2,
+        body,
+      };
+      let data = message.to_sysex();
+      assert_eq!(data[4], PROTOCOL_NEGOTIATION_CI_VERSION);
+      assert_eq!(CiMessage::parse(&data), Some(message));
+    }
+
+    let unknown = [
+      0x7e, 0x7f, 0x0d, 0x11, 0x01, 1, 0, 0, 0, 2, 0, 0, 0, 0x10, 2, 0x03, 0, 0, 0, 0, 0x02, 0, 0,
+      0, 0,
+    ];
+    assert_eq!(
+      CiMessage::parse(&unknown).map(|message| message.body),
+      Some(CiBody::ProtocolNegotiationReply {
+        authority: 0x10,
+        protocols: vec![MidiProtocol::Midi2],
+      })
+    );
+  }
+}
@@ -0,0 +1,978 @@
+//! MIDI Capability Inquiry (MIDI-CI), to find out which devices support MIDI 2.0
+//! and what they are capable of.
+//!
+//! The messages are exchanged as SysEx7 through a source and the destination paired with it.
+
+mod message;
+pub mod negotiation;
+pub mod profile;
+pub mod property;
+
+use std::collections::hash_map::RandomState;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Instant;
+
+use crate::endpoints::SourceId;
+use crate::filter::Filter;
+use crate::midi_ci::message::{CiBody, CiMessage, PropertyChunk, PROPERTY_FIELDS_LEN};
+use crate::midi_ci::negotiation::{MidiProtocol, Negotiation};
+use crate::midi_ci::profile::{Profile, ProfileId, ProfileState};
+use crate::midi_ci::property::{
+  PropertyError, PropertyReply, PropertyResponse, PropertyResult, PROPERTY_TIMEOUT,
+};
+use crate::protocol::decoder::DecoderProtocol2;
+use crate::protocol::messages::sysex7::SysExAssembler;
+use crate::protocol::messages::MessageType;
+
+/// Identifier of a MIDI-CI device, 28 bits chosen randomly by each device
+pub type Muid = u32;
+
+/// MUID for the messages addressed to all the devices
+pub const BROADCAST_MUID: Muid = 0x0fff_ffff;
+
+/// MUIDs from here up to the broadcast one are reserved
+const RESERVED_MUIDS: Muid = 0x0fff_ff00;
+
+/// Maximum length of the SysEx messages received, as advertised to the other devices
+const MAX_SYSEX_SIZE: u32 = 4096;
+
+/// The categories supported when talking to other devices
+const CATEGORIES: CiCategories = CiCategories {
+  protocol_negotiation: true,
+  profile_configuration: true,
+  property_exchange: true,
+  process_inquiry: false,
+};
+
+/// Authority level sent in the protocol negotiation, as a host application
+const AUTHORITY_LEVEL: u8 = 0x60;
+
+/// Request ids are 7 bits
+const MAX_REQUESTS: u8 = 0x80;
+
+/// What a MIDI-CI message is addressed to within a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CiAddress {
+  /// One of the channels of the group, from 0 to 15
+  Channel(u8),
+  /// The whole group
+  Group,
+  /// The whole function block, or the whole port for the devices without them
+  FunctionBlock,
+}
+
+impl CiAddress {
+  pub(crate) fn from_device_id(device_id: u8) -> Self {
+    match device_id {
+      0x00..=0x0f => Self::Channel(device_id),
+      0x7e => Self::Group,
+      _ => Self::FunctionBlock,
+    }
+  }
+
+  pub(crate) fn device_id(self) -> u8 {
+    match self {
+      Self::Channel(channel) => channel & 0x0f,
+      Self::Group => 0x7e,
+      Self::FunctionBlock => 0x7f,
+    }
+  }
+}
+
+/// Identity of a device, from the universal SysEx device inquiry
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct DeviceInfo {
+  pub manufacturer: [u8; 3],
+  pub family: u16,
+  pub model: u16,
+  pub version: [u8; 4],
+}
+
+/// The categories of MIDI-CI that a device supports, besides the discovery
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CiCategories {
+  pub protocol_negotiation: bool,
+  pub profile_configuration: bool,
+  pub property_exchange: bool,
+  pub process_inquiry: bool,
+}
+
+impl CiCategories {
+  pub(crate) fn from_bits(bits: u8) -> Self {
+    Self {
+      protocol_negotiation: bits & 0x02 != 0,
+      profile_configuration: bits & 0x04 != 0,
+      property_exchange: bits & 0x08 != 0,
+      process_inquiry: bits & 0x10 != 0,
+    }
+  }
+
+  pub(crate) fn to_bits(self) -> u8 {
+    (self.protocol_negotiation as u8) << 1
+      | (self.profile_configuration as u8) << 2
+      | (self.property_exchange as u8) << 3
+      | (self.process_inquiry as u8) << 4
+  }
+}
+
+/// A device found through MIDI-CI discovery, which means that it supports MIDI 2.0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CiDevice {
+  /// The source the device sends its messages from
+  pub source: SourceId,
+  pub muid: Muid,
+  pub device_info: DeviceInfo,
+  pub categories: CiCategories,
+  /// Maximum length of the SysEx messages that the device can receive
+  pub max_sysex_size: u32,
+  /// The profiles reported by the device, after asking for them with a profile inquiry
+  pub profiles: Vec<Profile>,
+  /// The protocol established with the device, MIDI 1.0 until negotiated otherwise
+  pub protocol: MidiProtocol,
+}
+
+/// Keeps track of the MIDI-CI devices found through the sources, answering to their discoveries.
+///
+/// The data received from every source is fed through `receive`, which calls back with the
+/// messages to send to the destination paired with the source.
+pub struct MidiCi {
+  muid: Muid,
+  device_info: DeviceInfo,
+  receivers: HashMap<SourceId, Receiver>,
+  devices: HashMap<SourceId, CiDevice>,
+  requests: HashMap<u8, PropertyRequest>,
+  next_request_id: u8,
+  negotiations: HashMap<SourceId, Negotiation>,
+}
+
+struct Receiver {
+  decoder: DecoderProtocol2,
+  assembler: SysExAssembler,
+}
+
+/// A property exchange request waiting for the chunks of its reply
+struct PropertyRequest {
+  source: SourceId,
+  header: Vec<u8>,
+  data: Vec<u8>,
+  response: PropertyResponse,
+}
+
+impl MidiCi {
+  pub fn new(device_info: DeviceInfo) -> Self {
+    let muid = RandomState::new().build_hasher().finish() as Muid % RESERVED_MUIDS;
+    Self::with_muid(muid, device_info)
+  }
+
+  pub(crate) fn with_muid(muid: Muid, device_info: DeviceInfo) -> Self {
+    Self {
+      muid,
+      device_info,
+      receivers: HashMap::new(),
+      devices: HashMap::new(),
+      requests: HashMap::new(),
+      next_request_id: 0,
+      negotiations: HashMap::new(),
+    }
+  }
+
+  pub fn muid(&self) -> Muid {
+    self.muid
+  }
+
+  /// The devices found so far, sorted by source.
+  pub fn devices(&self) -> Vec<CiDevice> {
+    let mut devices = self.devices.values().cloned().collect::<Vec<CiDevice>>();
+    devices.sort_unstable_by_key(|device| device.source);
+    devices
+  }
+
+  pub fn device(&self, source_id: SourceId) -> Option<&CiDevice> {
+    self.devices.get(&source_id)
+  }
+
+  /// The data of the SysEx message to send to all the destinations to discover the devices behind them.
+  pub fn discovery(&self) -> Vec<u8> {
+    CiMessage {
+      address: CiAddress::FunctionBlock,
+      source: self.muid,
+      destination: BROADCAST_MUID,
+      body: CiBody::Discovery {
+        device_info: self.device_info,
+        categories: CATEGORIES,
+        max_sysex_size: MAX_SYSEX_SIZE,
+      },
+    }
+    .to_sysex()
+  }
+
+  /// The data of the SysEx message asking the device behind a source for its profiles,
+  /// or `None` when the device is not known.
+  pub fn profile_inquiry(&self, source_id: SourceId, address: CiAddress) -> Option<Vec<u8>> {
+    let device = self.devices.get(&source_id)?;
+    Some(self.request(device, address, CiBody::ProfileInquiry))
+  }
+
+  /// The data of the SysEx message asking the device behind a source to enable or disable a profile,
+  /// or `None` when the device is not known.
+  ///
+  /// The profile waits in `ProfileState::Enabling` or `ProfileState::Disabling` until the device
+  /// reports the change, or goes back to its previous state if the device rejects it.
+  pub fn set_profile(
+    &mut self,
+    source_id: SourceId,
+    address: CiAddress,
+    profile: ProfileId,
+    enabled: bool,
+  ) -> Option<Vec<u8>> {
+    let device = self.devices.get_mut(&source_id)?;
+    let (state, body) = if enabled {
+      let channels = match address {
+        CiAddress::Channel(_) => 1,
+        CiAddress::Group | CiAddress::FunctionBlock => 0,
+      };
+      let body = CiBody::SetProfileOn { profile, channels };
+      (ProfileState::Enabling, body)
+    } else {
+      (ProfileState::Disabling, CiBody::SetProfileOff { profile })
+    };
+    profile::set_state(&mut device.profiles, profile, address, state);
+
+    let device = self.devices.get(&source_id)?;
+    Some(self.request(device, address, body))
+  }
+
+  /// The data of the SysEx message starting the negotiation of a protocol with the device behind a source,
+  /// or `None` when the device is not known.
+  ///
+  /// When the device supports the protocol, it's tested and then established in `CiDevice::protocol`,
+  /// otherwise the negotiation falls back to MIDI 1.0.
+  pub fn negotiate_protocol(
+    &mut self,
+    source_id: SourceId,
+    protocol: MidiProtocol,
+  ) -> Option<Vec<u8>> {
+    let device = self.devices.get(&source_id)?;
+    let body = CiBody::InitiateProtocolNegotiation {
+      authority: AUTHORITY_LEVEL,
+      protocols: negotiation::proposed(protocol),
+    };
+    let data = self.request(device, CiAddress::FunctionBlock, body);
+    self
+      .negotiations
+      .insert(source_id, Negotiation::Initiated(protocol));
+    Some(data)
+  }
+
+  /// The data of the SysEx message asking the device behind a source for a property resource
+  /// (such as `DeviceInfo`, `ChannelList` or `ProgramList`), along with the response to wait for,
+  /// or `None` when the device is not known.
+  pub fn get_property(
+    &mut self,
+    source_id: SourceId,
+    resource: &str,
+  ) -> Option<(Vec<Vec<u8>>, PropertyResponse)> {
+    let header = property::request_header(resource);
+    let (request_id, response) = self.start_request(source_id)?;
+    let device = self.devices.get(&source_id)?;
+    let body = CiBody::GetProperty(PropertyChunk {
+      request_id,
+      header,
+      chunks: 1,
+      chunk: 1,
+      data: Vec::new(),
+    });
+    Some((
+      vec![self.request(device, CiAddress::FunctionBlock, body)],
+      response,
+    ))
+  }
+
+  /// The data of the SysEx messages setting a property resource of the device behind a source,
+  /// split into chunks as long as the device can receive, along with the response to wait for,
+  /// or `None` when the device is not known.
+  pub fn set_property(
+    &mut self,
+    source_id: SourceId,
+    resource: &str,
+    data: &[u8],
+  ) -> Option<(Vec<Vec<u8>>, PropertyResponse)> {
+    let header = property::request_header(resource);
+    let (request_id, response) = self.start_request(source_id)?;
+    let device = self.devices.get(&source_id)?;
+
+    let chunk_len = (device.max_sysex_size as usize)
+      .saturating_sub(PROPERTY_FIELDS_LEN + header.len() + 2)
+      .clamp(1, 0x3fff);
+    let chunks = ((data.len() + chunk_len - 1) / chunk_len).max(1);
+    let messages = (0..chunks)
+      .map(|index| {
+        let start = index * chunk_len;
+        let body = CiBody::SetProperty(PropertyChunk {
+          request_id,
+          header: if index == 0 {
+            header.clone()
+          } else {
+            Vec::new()
+          },
+          chunks: chunks as u16,
+          chunk: index as u16 + 1,
+          data: data[start..(start + chunk_len).min(data.len())].to_vec(),
+        });
+        self.request(device, CiAddress::FunctionBlock, body)
+      })
+      .collect();
+
+    Some((messages, response))
+  }
+
+  /// Completes the property exchange requests that timed out with `PropertyError::Timeout`.
+  pub fn expire_requests(&mut self, now: Instant) {
+    let expired = self
+      .requests
+      .iter()
+      .filter(|(_, request)| request.response.is_expired(now))
+      .map(|(request_id, _)| *request_id)
+      .collect::<Vec<u8>>();
+    for request_id in expired {
+      self.complete_request(request_id, Err(PropertyError::Timeout));
+    }
+  }
+
+  /// Handles the UMP words received from a source, calling `reply` with the data of the SysEx messages
+  /// to send back to it.
+  pub fn receive<F>(&mut self, source_id: SourceId, ump: &[u32], mut reply: F)
+  where
+    F: FnMut(&[u8]),
+  {
+    let receiver = self.receivers.entry(source_id).or_insert_with(|| Receiver {
+      decoder: DecoderProtocol2::default(),
+      assembler: SysExAssembler::new(MAX_SYSEX_SIZE as usize),
+    });
+
+    let mut messages = Vec::new();
+    let filter = Filter::new();
+    for word in ump.iter().cloned() {
+      if let Ok(Some(message)) = receiver.decoder.next(word, &filter) {
+        if let MessageType::SysEx7(sysex) = message.mtype {
+          messages.extend(receiver.assembler.push(&sysex).and_then(CiMessage::parse));
+        }
+      }
+    }
+
+    for message in messages {
+      self.handle(source_id, message, &mut reply);
+    }
+    self.expire_requests(Instant::now());
+  }
+
+  /// Forgets about the device behind a source that is gone, failing its pending requests.
+  pub fn remove_source(&mut self, source_id: SourceId) {
+    self.receivers.remove(&source_id);
+    self.devices.remove(&source_id);
+    self.negotiations.remove(&source_id);
+    self.fail_requests(source_id, PropertyError::Disconnected);
+  }
+
+  /// Allocates the id of a new request to a known device. The ids are reused in a round robin,
+  /// so a request still pending after other 127 ones is considered timed out.
+  fn start_request(&mut self, source_id: SourceId) -> Option<(u8, PropertyResponse)> {
+    self.devices.get(&source_id)?;
+    let request_id = self.next_request_id;
+    self.next_request_id = (request_id + 1) % MAX_REQUESTS;
+    self.complete_request(request_id, Err(PropertyError::Timeout));
+
+    let response = PropertyResponse::new(Instant::now() + PROPERTY_TIMEOUT);
+    self.requests.insert(
+      request_id,
+      PropertyRequest {
+        source: source_id,
+        header: Vec::new(),
+        data: Vec::new(),
+        response: response.clone(),
+      },
+    );
+    Some((request_id, response))
+  }
+
+  fn complete_request(&mut self, request_id: u8, result: PropertyResult) {
+    if let Some(request) = self.requests.remove(&request_id) {
+      request.response.complete(result);
+    }
+  }
+
+  fn fail_requests(&mut self, source_id: SourceId, error: PropertyError) {
+    let failed = self
+      .requests
+      .iter()
+      .filter(|(_, request)| request.source == source_id)
+      .map(|(request_id, _)| *request_id)
+      .collect::<Vec<u8>>();
+    for request_id in failed {
+      self.complete_request(request_id, Err(error.clone()));
+    }
+  }
+
+  /// Collects a chunk of the reply to a request, completing it with the last one.
+  fn receive_reply(&mut self, source_id: SourceId, chunk: PropertyChunk) {
+    let request = match self.requests.get_mut(&chunk.request_id) {
+      Some(request) if request.source == source_id => request,
+      _ => return,
+    };
+    request.header.extend_from_slice(&chunk.header);
+    request.data.extend_from_slice(&chunk.data);
+
+    if chunk.chunk >= chunk.chunks {
+      if let Some(request) = self.requests.remove(&chunk.request_id) {
+        request.response.complete(Ok(PropertyReply {
+          header: String::from_utf8_lossy(&request.header).into_owned(),
+          data: request.data,
+        }));
+      }
+    }
+  }
+
+  fn request(&self, device: &CiDevice, address: CiAddress, body: CiBody) -> Vec<u8> {
+    CiMessage {
+      address,
+      source: self.muid,
+      destination: device.muid,
+      body,
+    }
+    .to_sysex()
+  }
+
+  fn handle<F>(&mut self, source_id: SourceId, message: CiMessage, reply: &mut F)
+  where
+    F: FnMut(&[u8]),
+  {
+    if message.source == self.muid
+      || (message.destination != self.muid && message.destination != BROADCAST_MUID)
+    {
+      return;
+    }
+
+    let (muid, address, remote_muid) = (self.muid, message.address, message.source);
+    let answer = |body| {
+      CiMessage {
+        address,
+        source: muid,
+        destination: remote_muid,
+        body,
+      }
+      .to_sysex()
+    };
+
+    match message.body {
+      CiBody::Discovery {
+        device_info,
+        categories,
+        max_sysex_size,
+      } => {
+        reply(&answer(CiBody::DiscoveryReply {
+          device_info: self.device_info,
+          categories: CATEGORIES,
+          max_sysex_size: MAX_SYSEX_SIZE,
+        }));
+        self.add_device(
+          source_id,
+          message.source,
+          device_info,
+          categories,
+          max_sysex_size,
+        );
+      }
+      CiBody::DiscoveryReply {
+        device_info,
+        categories,
+        max_sysex_size,
+      } => {
+        self.add_device(
+          source_id,
+          message.source,
+          device_info,
+          categories,
+          max_sysex_size,
+        );
+      }
+      CiBody::InvalidateMuid { target } => {
+        self.devices.retain(|_, device| device.muid != target);
+      }
+      CiBody::ProfileInquiry => {
+        // There are no profiles to offer to other devices
+        reply(&answer(CiBody::ProfileInquiryReply {
+          enabled: Vec::new(),
+          disabled: Vec::new(),
+        }));
+      }
+      CiBody::SetProfileOn { .. } => reply(&answer(CiBody::Nak {
+        sub_id2: message::SET_PROFILE_ON,
+      })),
+      CiBody::SetProfileOff { .. } => reply(&answer(CiBody::Nak {
+        sub_id2: message::SET_PROFILE_OFF,
+      })),
+      CiBody::GetProperty(_) => reply(&answer(CiBody::Nak {
+        sub_id2: message::GET_PROPERTY,
+      })),
+      CiBody::SetProperty(_) => reply(&answer(CiBody::Nak {
+        sub_id2: message::SET_PROPERTY,
+      })),
+      CiBody::GetPropertyReply(chunk) | CiBody::SetPropertyReply(chunk) => {
+        self.receive_reply(source_id, chunk)
+      }
+      CiBody::Nak { sub_id2 }
+        if sub_id2 == message::GET_PROPERTY || sub_id2 == message::SET_PROPERTY =>
+      {
+        self.fail_requests(source_id, PropertyError::Rejected)
+      }
+      CiBody::InitiateProtocolNegotiation { .. } => {
+        reply(&answer(CiBody::ProtocolNegotiationReply {
+          authority: AUTHORITY_LEVEL,
+          protocols: negotiation::proposed(MidiProtocol::Midi2),
+        }));
+      }
+      CiBody::ProtocolNegotiationReply { protocols, .. } => {
+        if let Some(Negotiation::Initiated(preferred)) = self.negotiations.get(&source_id).copied()
+        {
+          let protocol = negotiation::proposed(preferred)
+            .into_iter()
+            .find(|protocol| protocols.contains(protocol))
+            .unwrap_or(MidiProtocol::Midi1);
+          reply(&answer(CiBody::SetNewProtocol {
+            authority: AUTHORITY_LEVEL,
+            protocol,
+          }));
+          reply(&answer(CiBody::TestNewProtocol {
+            authority: AUTHORITY_LEVEL,
+            data: negotiation::test_data(),
+          }));
+          self
+            .negotiations
+            .insert(source_id, Negotiation::Testing(protocol));
+        }
+      }
+      CiBody::SetNewProtocol { protocol, .. } => {
+        self
+          .negotiations
+          .insert(source_id, Negotiation::Switching(protocol));
+      }
+      CiBody::TestNewProtocol { data, .. } => {
+        reply(&answer(CiBody::TestNewProtocolReply {
+          authority: AUTHORITY_LEVEL,
+          data,
+        }));
+      }
+      CiBody::TestNewProtocolReply { data, .. } => {
+        if let Some(Negotiation::Testing(protocol)) = self.negotiations.get(&source_id).copied() {
+          self.negotiations.remove(&source_id);
+          if data == negotiation::test_data() {
+            reply(&answer(CiBody::NewProtocolEstablished {
+              authority: AUTHORITY_LEVEL,
+            }));
+            self.set_protocol(source_id, remote_muid, protocol);
+          }
+        }
+      }
+      CiBody::NewProtocolEstablished { .. } => {
+        if let Some(Negotiation::Switching(protocol)) = self.negotiations.remove(&source_id) {
+          self.set_protocol(source_id, remote_muid, protocol);
+        }
+      }
+      CiBody::Nak { sub_id2 } if (0x10..=0x15).contains(&sub_id2) => {
+        self.negotiations.remove(&source_id);
+      }
+      CiBody::Unsupported(sub_id2) => {
+        // Only the messages addressed to this device get a NAK, not the broadcasted ones
+        if message.destination == muid {
+          reply(&answer(CiBody::Nak { sub_id2 }));
+        }
+      }
+      body => {
+        if let Some(device) = self
+          .devices
+          .get_mut(&source_id)
+          .filter(|device| device.muid == remote_muid)
+        {
+          Self::update_device(device, address, body);
+        }
+      }
+    }
+  }
+
+  fn add_device(
+    &mut self,
+    source_id: SourceId,
+    muid: Muid,
+    device_info: DeviceInfo,
+    categories: CiCategories,
+    max_sysex_size: u32,
+  ) {
+    self.devices.insert(
+      source_id,
+      CiDevice {
+        source: source_id,
+        muid,
+        device_info,
+        categories,
+        max_sysex_size,
+        profiles: Vec::new(),
+        protocol: MidiProtocol::default(),
+      },
+    );
+  }
+
+  fn set_protocol(&mut self, source_id: SourceId, muid: Muid, protocol: MidiProtocol) {
+    if let Some(device) = self
+      .devices
+      .get_mut(&source_id)
+      .filter(|device| device.muid == muid)
+    {
+      device.protocol = protocol;
+    }
+  }
+
+  /// Keeps track of the changes reported by a device.
+  fn update_device(device: &mut CiDevice, address: CiAddress, body: CiBody) {
+    let profiles = &mut device.profiles;
+    match body {
+      CiBody::ProfileInquiryReply { enabled, disabled } => {
+        profiles.retain(|profile| profile.address != address);
+        for id in enabled {
+          profile::set_state(profiles, id, address, ProfileState::Enabled);
+        }
+        for id in disabled {
+          profile::set_state(profiles, id, address, ProfileState::Disabled);
+        }
+      }
+      CiBody::ProfileEnabledReport { profile, .. } => {
+        profile::set_state(profiles, profile, address, ProfileState::Enabled)
+      }
+      CiBody::ProfileDisabledReport { profile, .. } | CiBody::ProfileAddedReport { profile } => {
+        profile::set_state(profiles, profile, address, ProfileState::Disabled)
+      }
+      CiBody::ProfileRemovedReport { profile } => {
+        profiles.retain(|known| known.id != profile || known.address != address);
+      }
+      CiBody::Nak { sub_id2 }
+        if sub_id2 == message::SET_PROFILE_ON || sub_id2 == message::SET_PROFILE_OFF =>
+      {
+        profile::revert_pending(profiles)
+      }
+      _ => {}
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::encoder::encode_sysex7;
+
+  fn to_ump(data: &[u8]) -> Vec<u32> {
+    let mut words = Vec::new();
+    encode_sysex7(0, data, |ump| words.extend_from_slice(ump.as_slice()));
+    words
+  }
+
+  fn remote(muid: Muid) -> MidiCi {
+    MidiCi::with_muid(
+      muid,
+      DeviceInfo {
+        manufacturer: [0x00, 0x21, 0x09],
+        ..DeviceInfo::default()
+      },
+    )
+  }
+
+  #[test]
+  fn muid_is_not_reserved() {
+    assert!(MidiCi::new(DeviceInfo::default()).muid() < RESERVED_MUIDS);
+  }
+
+  #[test]
+  fn discover_a_device() {
+    let mut local = MidiCi::with_muid(1, DeviceInfo::default());
+    let mut device = remote(2);
+
+    let mut replies = Vec::new();
+    device.receive(10, &to_ump(&local.discovery()), |reply| {
+      replies.push(reply.to_vec())
+    });
+    assert_eq!(replies.len(), 1);
+    assert_eq!(device.device(10).map(|device| device.muid), Some(1));
+
+    let mut answers = Vec::new();
+    local.receive(20, &to_ump(&replies[0]), |reply| {
+      answers.push(reply.to_vec())
+    });
+    assert!(answers.is_empty());
+    assert_eq!(
+      local.devices(),
+      vec![CiDevice {
+        source: 20,
+        muid: 2,
+        device_info: DeviceInfo {
+          manufacturer: [0x00, 0x21, 0x09],
+          ..DeviceInfo::default()
+        },
+        categories: CATEGORIES,
+        max_sysex_size: MAX_SYSEX_SIZE,
+        profiles: Vec::new(),
+        protocol: MidiProtocol::Midi1,
+      }]
+    );
+  }
+
+  #[test]
+  fn invalidate_muid() {
+    let mut local = MidiCi::with_muid(1, DeviceInfo::default());
+    let device = remote(2);
+    local.receive(20, &to_ump(&device.discovery()), |_| {});
+    assert_eq!(local.devices().len(), 1);
+
+    let invalidate = CiMessage {
+      address: CiAddress::FunctionBlock,
+      source: 3,
+      destination: BROADCAST_MUID,
+      body: CiBody::InvalidateMuid { target: 2 },
+    };
+    local.receive(30, &to_ump(&invalidate.to_sysex()), |_| {});
+    assert!(local.devices().is_empty());
+  }
+
+  #[test]
+  fn nak_unsupported_messages() {
+    let mut local = MidiCi::with_muid(1, DeviceInfo::default());
+
+    let mut replies = Vec::new();
+    for destination in [1, 4, BROADCAST_MUID] {
+      let message = CiMessage {
+        address: CiAddress::FunctionBlock,
+        source: 2,
+        destination,
+        body: CiBody::Unsupported(0x40),
+      };
+      local.receive(20, &to_ump(&message.to_sysex()), |reply| {
+        replies.push(reply.to_vec())
+      });
+    }
+
+    assert_eq!(replies.len(), 1);
+    assert_eq!(
+      CiMessage::parse(&replies[0]).map(|message| message.body),
+      Some(CiBody::Nak { sub_id2: 0x40 })
+    );
+  }
+
+  #[test]
+  fn profile_configuration() {
+    let mut local = MidiCi::with_muid(1, DeviceInfo::default());
+    local.receive(20, &to_ump(&remote(2).discovery()), |_| {});
+    let mut from_device = |body| {
+      let message = CiMessage {
+        address: CiAddress::Channel(0),
+        source: 2,
+        destination: 1,
+        body,
+      };
+      local.receive(20, &to_ump(&message.to_sysex()), |_| {});
+      local.device(20).unwrap().profiles.clone()
+    };
+
+    let mpe = ProfileId([0x7e, 0x31, 0x00, 0x01, 0x01]);
+    let organ = ProfileId([0x7e, 0x21, 0x00, 0x01, 0x01]);
+    let profiles = from_device(CiBody::ProfileInquiryReply {
+      enabled: vec![organ],
+      disabled: vec![mpe],
+    });
+    assert_eq!(
+      profiles,
+      vec![
+        Profile {
+          id: organ,
+          address: CiAddress::Channel(0),
+          state: ProfileState::Enabled,
+        },
+        Profile {
+          id: mpe,
+          address: CiAddress::Channel(0),
+          state: ProfileState::Disabled,
+        },
+      ]
+    );
+    assert!(local.profile_inquiry(30, CiAddress::Group).is_none());
+
+    let request = local
+      .set_profile(20, CiAddress::Channel(0), mpe, true)
+      .unwrap();
+    assert_eq!(
+      CiMessage::parse(&request).map(|message| message.body),
+      Some(CiBody::SetProfileOn {
+        profile: mpe,
+        channels: 1,
+      })
+    );
+    assert_eq!(
+      local.device(20).unwrap().profiles[1].state,
+      ProfileState::Enabling
+    );
+
+    let mut from_device = |body| {
+      let message = CiMessage {
+        address: CiAddress::Channel(0),
+        source: 2,
+        destination: BROADCAST_MUID,
+        body,
+      };
+      local.receive(20, &to_ump(&message.to_sysex()), |_| {});
+      local.device(20).unwrap().profiles[1].state
+    };
+    assert_eq!(
+      from_device(CiBody::ProfileEnabledReport {
+        profile: mpe,
+        channels: 1,
+      }),
+      ProfileState::Enabled
+    );
+
+    local.set_profile(20, CiAddress::Channel(0), mpe, false);
+    let message = CiMessage {
+      address: CiAddress::Channel(0),
+      source: 2,
+      destination: 1,
+      body: CiBody::Nak {
+        sub_id2: message::SET_PROFILE_OFF,
+      },
+    };
+    local.receive(20, &to_ump(&message.to_sysex()), |_| {});
+    assert_eq!(
+      local.device(20).unwrap().profiles[1].state,
+      ProfileState::Enabled
+    );
+  }
+
+  #[test]
+  fn property_exchange() {
+    let mut local = MidiCi::with_muid(1, DeviceInfo::default());
+    local.receive(20, &to_ump(&remote(2).discovery()), |_| {});
+    assert!(local.get_property(30, "DeviceInfo").is_none());
+
+    let (requests, response) = local.get_property(20, "DeviceInfo").unwrap();
+    let request_id = match CiMessage::parse(&requests[0]).map(|message| message.body) {
+      Some(CiBody::GetProperty(chunk)) => {
+        assert_eq!(chunk.header, b"{\"resource\":\"DeviceInfo\"}");
+        chunk.request_id
+      }
+      body => panic!("Unexpected request: {:?}", body),
+    };
+
+    for (chunk, header, data) in [(1, "{\"status\":200}", "{\"name\":"), (2, "", "\"kiro\"}")] {
+      let message = CiMessage {
+        address: CiAddress::FunctionBlock,
+        source: 2,
+        destination: 1,
+        body: CiBody::GetPropertyReply(PropertyChunk {
+          request_id,
+          header: header.as_bytes().to_vec(),
+          chunks: 2,
+          chunk,
+          data: data.as_bytes().to_vec(),
+        }),
+      };
+      assert!(response.try_take().is_none());
+      local.receive(20, &to_ump(&message.to_sysex()), |_| {});
+    }
+
+    let reply = response.try_take().unwrap().unwrap();
+    assert_eq!(reply.status(), Some(200));
+    assert_eq!(reply.data, b"{\"name\":\"kiro\"}");
+  }
+
+  #[test]
+  fn property_exchange_failures() {
+    let mut local = MidiCi::with_muid(1, DeviceInfo::default());
+    local.receive(20, &to_ump(&remote(2).discovery()), |_| {});
+
+    let (_, timed_out) = local.get_property(20, "ChannelList").unwrap();
+    local.expire_requests(Instant::now() + PROPERTY_TIMEOUT);
+    assert_eq!(timed_out.try_take(), Some(Err(PropertyError::Timeout)));
+
+    let (requests, rejected) = local
+      .set_property(20, "ProgramList", &[b'x'; 10000])
+      .unwrap();
+    assert_eq!(requests.len(), 3);
+    let nak = CiMessage {
+      address: CiAddress::FunctionBlock,
+      source: 2,
+      destination: 1,
+      body: CiBody::Nak {
+        sub_id2: message::SET_PROPERTY,
+      },
+    };
+    local.receive(20, &to_ump(&nak.to_sysex()), |_| {});
+    assert_eq!(rejected.try_take(), Some(Err(PropertyError::Rejected)));
+
+    let (_, disconnected) = local.get_property(20, "DeviceInfo").unwrap();
+    local.remove_source(20);
+    assert_eq!(
+      disconnected.try_take(),
+      Some(Err(PropertyError::Disconnected))
+    );
+  }
+
+  #[test]
+  fn protocol_negotiation() {
+    let mut local = MidiCi::with_muid(1, DeviceInfo::default());
+    let mut device = remote(2);
+    let mut replies = Vec::new();
+    device.receive(10, &to_ump(&local.discovery()), |reply| {
+      replies.push(reply.to_vec())
+    });
+    local.receive(20, &to_ump(&replies[0]), |_| {});
+
+    // Messages go back and forth until there is nothing left to answer
+    let mut to_device = vec![local.negotiate_protocol(20, MidiProtocol::Midi2).unwrap()];
+    while !to_device.is_empty() {
+      let mut to_local = Vec::new();
+      for data in to_device.drain(..) {
+        device.receive(10, &to_ump(&data), |reply| to_local.push(reply.to_vec()));
+      }
+      for data in to_local {
+        local.receive(20, &to_ump(&data), |reply| to_device.push(reply.to_vec()));
+      }
+    }
+
+    assert_eq!(local.device(20).unwrap().protocol, MidiProtocol::Midi2);
+    assert_eq!(device.device(10).unwrap().protocol, MidiProtocol::Midi2);
+  }
+
+  #[test]
+  fn protocol_negotiation_rejected() {
+    let mut local = MidiCi::with_muid(1, DeviceInfo::default());
+    local.receive(20, &to_ump(&remote(2).discovery()), |_| {});
+    local.negotiate_protocol(20, MidiProtocol::Midi2).unwrap();
+
+    let nak = CiMessage {
+      address: CiAddress::FunctionBlock,
+      source: 2,
+      destination: 1,
+      body: CiBody::Nak { sub_id2: 0x10 },
+    };
+    local.receive(20, &to_ump(&nak.to_sysex()), |_| {});
+    let reply = CiMessage {
+      body: CiBody::ProtocolNegotiationReply {
+        authority: 0x10,
+        protocols: vec![MidiProtocol::Midi2],
+      },
+      ..nak
+    };
+    let mut replies = Vec::new();
+    local.receive(20, &to_ump(&reply.to_sysex()), |reply| {
+      replies.push(reply.to_vec())
+    });
+
+    assert!(replies.is_empty());
+    assert_eq!(local.device(20).unwrap().protocol, MidiProtocol::Midi1);
+  }
+}
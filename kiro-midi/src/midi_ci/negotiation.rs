@@ -0,0 +1,55 @@
+/// The protocol used by a device to send and receive channel voice messages over UMP
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MidiProtocol {
+  Midi1,
+  Midi2,
+}
+
+impl Default for MidiProtocol {
+  fn default() -> Self {
+    Self::Midi1
+  }
+}
+
+impl MidiProtocol {
+  /// Reads the 5 bytes of a protocol in the negotiation messages, returning `None` for the unknown ones.
+  pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+    match bytes.get(0)? {
+      0x01 => Some(Self::Midi1),
+      0x02 => Some(Self::Midi2),
+      _ => None,
+    }
+  }
+
+  /// Type, version, extensions and two reserved bytes
+  pub(crate) fn to_bytes(self) -> [u8; 5] {
+    match self {
+      Self::Midi1 => [0x01, 0x00, 0x00, 0x00, 0x00],
+      Self::Midi2 => [0x02, 0x00, 0x00, 0x00, 0x00],
+    }
+  }
+}
+
+/// Progress of a protocol negotiation with a device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Negotiation {
+  /// Waiting for the device to reply with the protocols it supports
+  Initiated(MidiProtocol),
+  /// Waiting for the device to reply to the test of the new protocol
+  Testing(MidiProtocol),
+  /// Asked by the device to switch, waiting for its confirmation
+  Switching(MidiProtocol),
+}
+
+/// The data sent to test a new protocol, which the other device must send back
+pub(crate) fn test_data() -> Vec<u8> {
+  (0..48).collect()
+}
+
+/// The protocols proposed to a device, in order of preference.
+pub(crate) fn proposed(preferred: MidiProtocol) -> Vec<MidiProtocol> {
+  match preferred {
+    MidiProtocol::Midi2 => vec![MidiProtocol::Midi2, MidiProtocol::Midi1],
+    MidiProtocol::Midi1 => vec![MidiProtocol::Midi1],
+  }
+}
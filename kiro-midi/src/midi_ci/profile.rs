@@ -0,0 +1,57 @@
+use crate::midi_ci::CiAddress;
+
+/// Identifier of a profile, starting with `0x7E` for the standard ones,
+/// or with the manufacturer id for the rest
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ProfileId(pub [u8; 5]);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProfileState {
+  Disabled,
+  Enabled,
+  /// Asked to be enabled, waiting for the device to confirm it
+  Enabling,
+  /// Asked to be disabled, waiting for the device to confirm it
+  Disabling,
+}
+
+/// A profile supported by a MIDI-CI device, on a channel, the whole group or the function block
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Profile {
+  pub id: ProfileId,
+  pub address: CiAddress,
+  pub state: ProfileState,
+}
+
+impl Profile {
+  pub fn is_enabled(&self) -> bool {
+    matches!(self.state, ProfileState::Enabled | ProfileState::Disabling)
+  }
+}
+
+/// Sets the state of a profile, adding it when it is not known yet.
+pub(crate) fn set_state(
+  profiles: &mut Vec<Profile>,
+  id: ProfileId,
+  address: CiAddress,
+  state: ProfileState,
+) {
+  match profiles
+    .iter_mut()
+    .find(|profile| profile.id == id && profile.address == address)
+  {
+    Some(profile) => profile.state = state,
+    None => profiles.push(Profile { id, address, state }),
+  }
+}
+
+/// Goes back to the previous state of the profiles waiting for a confirmation, after a device rejects it.
+pub(crate) fn revert_pending(profiles: &mut [Profile]) {
+  for profile in profiles.iter_mut() {
+    profile.state = match profile.state {
+      ProfileState::Enabling => ProfileState::Disabled,
+      ProfileState::Disabling => ProfileState::Enabled,
+      state => state,
+    };
+  }
+}
@@ -0,0 +1,199 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+
+use parking_lot::{Condvar, Mutex};
+use thiserror::Error;
+
+/// How long to wait for the last chunk of a reply
+pub(crate) const PROPERTY_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum PropertyError {
+  #[error("The device did not reply in time")]
+  Timeout,
+
+  #[error("The device rejected the request")]
+  Rejected,
+
+  #[error("The device is gone")]
+  Disconnected,
+}
+
+/// Reply from a device to a property exchange request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PropertyReply {
+  /// The header of the reply, in JSON
+  pub header: String,
+  /// The data of the property, usually in JSON but it depends on the resource
+  pub data: Vec<u8>,
+}
+
+impl PropertyReply {
+  /// The status code from the header, following the HTTP ones (200 for success).
+  pub fn status(&self) -> Option<u16> {
+    let start = self.header.find("\"status\"")? + "\"status\"".len();
+    let value = self.header[start..]
+      .trim_start()
+      .strip_prefix(':')?
+      .trim_start();
+    let end = value
+      .find(|c: char| !c.is_ascii_digit())
+      .unwrap_or(value.len());
+    value[..end].parse().ok()
+  }
+}
+
+pub type PropertyResult = Result<PropertyReply, PropertyError>;
+
+/// The reply to a property exchange request, still to be received.
+///
+/// It can be awaited from async code, or waited for from a thread that can block. The result
+/// is available once, so the clones of a response are only useful to wait from different places.
+#[derive(Clone)]
+pub struct PropertyResponse {
+  shared: Arc<Shared>,
+}
+
+struct Shared {
+  state: Mutex<State>,
+  condvar: Condvar,
+  deadline: Instant,
+}
+
+struct State {
+  result: Option<PropertyResult>,
+  waker: Option<Waker>,
+}
+
+impl PropertyResponse {
+  pub(crate) fn new(deadline: Instant) -> Self {
+    Self {
+      shared: Arc::new(Shared {
+        state: Mutex::new(State {
+          result: None,
+          waker: None,
+        }),
+        condvar: Condvar::new(),
+        deadline,
+      }),
+    }
+  }
+
+  /// Takes the result if it has been received already.
+  pub fn try_take(&self) -> Option<PropertyResult> {
+    let mut state = self.shared.state.lock();
+    state
+      .result
+      .take()
+      .or_else(|| (Instant::now() >= self.shared.deadline).then(|| Err(PropertyError::Timeout)))
+  }
+
+  /// Blocks until the result is received, or the request times out.
+  pub fn wait(self) -> PropertyResult {
+    let mut state = self.shared.state.lock();
+    loop {
+      if let Some(result) = state.result.take() {
+        return result;
+      }
+      if Instant::now() >= self.shared.deadline {
+        return Err(PropertyError::Timeout);
+      }
+      self
+        .shared
+        .condvar
+        .wait_until(&mut state, self.shared.deadline);
+    }
+  }
+
+  pub(crate) fn complete(&self, result: PropertyResult) {
+    let mut state = self.shared.state.lock();
+    state.result = Some(result);
+    if let Some(waker) = state.waker.take() {
+      waker.wake();
+    }
+    self.shared.condvar.notify_all();
+  }
+
+  pub(crate) fn is_expired(&self, now: Instant) -> bool {
+    now >= self.shared.deadline
+  }
+}
+
+/// The response is woken up when the result is received, or when the driver finds out that it timed out
+/// while receiving data from any source. Otherwise the timeout is only noticed the next time it is polled.
+impl Future for PropertyResponse {
+  type Output = PropertyResult;
+
+  fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+    let mut state = self.shared.state.lock();
+    match state.result.take() {
+      Some(result) => Poll::Ready(result),
+      None if Instant::now() >= self.shared.deadline => Poll::Ready(Err(PropertyError::Timeout)),
+      None => {
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+      }
+    }
+  }
+}
+
+/// The JSON header of a request for a resource.
+pub(crate) fn request_header(resource: &str) -> Vec<u8> {
+  let resource = resource.replace('\\', "\\\\").replace('"', "\\\"");
+  format!("{{\"resource\":\"{}\"}}", resource).into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn reply_status() {
+    let reply = |header: &str| PropertyReply {
+      header: header.to_string(),
+      data: Vec::new(),
+    };
+
+    assert_eq!(reply("{\"status\":200}").status(), Some(200));
+    assert_eq!(
+      reply("{\"totalCount\":3, \"status\" : 404}").status(),
+      Some(404)
+    );
+    assert_eq!(reply("{}").status(), None);
+  }
+
+  #[test]
+  fn wait_for_the_result() {
+    let response = PropertyResponse::new(Instant::now() + Duration::from_secs(10));
+    let completer = response.clone();
+    let reply = PropertyReply {
+      header: "{}".to_string(),
+      data: b"[]".to_vec(),
+    };
+    let expected = reply.clone();
+
+    std::thread::spawn(move || completer.complete(Ok(reply)));
+
+    assert_eq!(response.wait(), Ok(expected));
+  }
+
+  #[test]
+  fn timeout() {
+    let response = PropertyResponse::new(Instant::now());
+
+    assert_eq!(response.try_take(), Some(Err(PropertyError::Timeout)));
+    assert_eq!(response.wait(), Err(PropertyError::Timeout));
+  }
+
+  #[test]
+  fn header_is_escaped() {
+    assert_eq!(
+      request_header("DeviceInfo"),
+      b"{\"resource\":\"DeviceInfo\"}"
+    );
+    assert_eq!(request_header("a\"b"), b"{\"resource\":\"a\\\"b\"}");
+  }
+}
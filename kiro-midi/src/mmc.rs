@@ -0,0 +1,337 @@
+//! MIDI Machine Control (MMC).
+//!
+//! Recorders and control surfaces send their transport commands as Universal Real Time SysEx
+//! messages, addressed to a device ID or to all the devices. A message can hold several commands,
+//! and the locate command carries the time to move to.
+//!
+//! [`MmcDecoder`] turns the SysEx7 packets received into [`MmcMessage`]s, and
+//! [`MmcMessage::to_sysex`] builds the data to send with `Output::send_sysex`.
+
+use crate::protocol::messages::sysex7::SysExAssembler;
+use crate::protocol::messages::{Message, MessageType};
+use crate::timecode::{FrameRate, Timecode};
+
+const UNIVERSAL_REALTIME: u8 = 0x7f;
+/// Sub-ID#1 of the commands, the responses from the devices use 0x07
+const MMC_COMMAND: u8 = 0x06;
+const HEADER_LEN: usize = 3;
+
+/// Device ID addressing all the devices
+pub const ALL_DEVICES: u8 = 0x7f;
+
+const STOP: u8 = 0x01;
+const PLAY: u8 = 0x02;
+const DEFERRED_PLAY: u8 = 0x03;
+const FAST_FORWARD: u8 = 0x04;
+const REWIND: u8 = 0x05;
+const RECORD_STROBE: u8 = 0x06;
+const RECORD_EXIT: u8 = 0x07;
+const RECORD_PAUSE: u8 = 0x08;
+const PAUSE: u8 = 0x09;
+const EJECT: u8 = 0x0a;
+const CHASE: u8 = 0x0b;
+const RESET: u8 = 0x0d;
+const LOCATE: u8 = 0x44;
+/// Locate sub-command with the time as data, instead of an information field
+const LOCATE_TARGET: u8 = 0x01;
+const LOCATE_TARGET_LEN: u8 = 6;
+
+/// The commands from 0x40 to 0x77 are followed by the count of their data bytes
+const FIRST_COMMAND_WITH_DATA: u8 = 0x40;
+const LAST_COMMAND_WITH_DATA: u8 = 0x77;
+
+/// Longest message received, enough for several commands
+const MAX_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmcCommand {
+  Stop,
+  Play,
+  /// Play once the locate in progress finishes
+  DeferredPlay,
+  FastForward,
+  Rewind,
+  /// Start recording, or stop it if already recording
+  RecordStrobe,
+  RecordExit,
+  RecordPause,
+  Pause,
+  Eject,
+  Chase,
+  Reset,
+  /// Move to a time, with the subframes in hundredths of a frame
+  Locate {
+    timecode: Timecode,
+    subframes: u8,
+  },
+  /// Any other command, without its data
+  Other(u8),
+}
+
+impl MmcCommand {
+  /// Parses a command from the start of `data`, returning it with the number of bytes it took.
+  fn parse(data: &[u8]) -> Option<(Self, usize)> {
+    let command = *data.first()?;
+    let len = if (FIRST_COMMAND_WITH_DATA..=LAST_COMMAND_WITH_DATA).contains(&command) {
+      2 + *data.get(1)? as usize
+    } else {
+      1
+    };
+    let data = data.get(..len)?;
+
+    let command = match command {
+      STOP => Self::Stop,
+      PLAY => Self::Play,
+      DEFERRED_PLAY => Self::DeferredPlay,
+      FAST_FORWARD => Self::FastForward,
+      REWIND => Self::Rewind,
+      RECORD_STROBE => Self::RecordStrobe,
+      RECORD_EXIT => Self::RecordExit,
+      RECORD_PAUSE => Self::RecordPause,
+      PAUSE => Self::Pause,
+      EJECT => Self::Eject,
+      CHASE => Self::Chase,
+      RESET => Self::Reset,
+      LOCATE if data.len() == 2 + LOCATE_TARGET_LEN as usize && data[2] == LOCATE_TARGET => {
+        Self::Locate {
+          timecode: Timecode {
+            hours: data[3] & 0x1f,
+            minutes: data[4] & 0x3f,
+            seconds: data[5] & 0x3f,
+            frames: data[6] & 0x1f,
+            rate: FrameRate::from_code(data[3] >> 5),
+          },
+          subframes: data[7] & 0x7f,
+        }
+      }
+      command => Self::Other(command),
+    };
+    Some((command, len))
+  }
+
+  fn write(&self, data: &mut Vec<u8>) {
+    let command = match self {
+      Self::Stop => STOP,
+      Self::Play => PLAY,
+      Self::DeferredPlay => DEFERRED_PLAY,
+      Self::FastForward => FAST_FORWARD,
+      Self::Rewind => REWIND,
+      Self::RecordStrobe => RECORD_STROBE,
+      Self::RecordExit => RECORD_EXIT,
+      Self::RecordPause => RECORD_PAUSE,
+      Self::Pause => PAUSE,
+      Self::Eject => EJECT,
+      Self::Chase => CHASE,
+      Self::Reset => RESET,
+      Self::Locate {
+        timecode,
+        subframes,
+      } => {
+        data.extend_from_slice(&[
+          LOCATE,
+          LOCATE_TARGET_LEN,
+          LOCATE_TARGET,
+          (timecode.hours & 0x1f) | timecode.rate.code() << 5,
+          timecode.minutes & 0x3f,
+          timecode.seconds & 0x3f,
+          timecode.frames & 0x1f,
+          subframes & 0x7f,
+        ]);
+        return;
+      }
+      Self::Other(command) => *command,
+    };
+    // Other commands with data can't be written without it, a zero count keeps the message valid
+    if (FIRST_COMMAND_WITH_DATA..=LAST_COMMAND_WITH_DATA).contains(&command) {
+      data.extend_from_slice(&[command, 0]);
+    } else {
+      data.push(command & 0x7f);
+    }
+  }
+}
+
+/// The commands of a MMC SysEx message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MmcMessage {
+  /// The device addressed, or `ALL_DEVICES`
+  pub device: u8,
+  pub commands: Vec<MmcCommand>,
+}
+
+impl MmcMessage {
+  pub fn new(device: u8, command: MmcCommand) -> Self {
+    Self {
+      device,
+      commands: vec![command],
+    }
+  }
+
+  /// Parses the data of a SysEx message, returning `None` when it is not a valid MMC command message.
+  pub fn parse(data: &[u8]) -> Option<Self> {
+    let data = data.strip_prefix(&[0xf0]).unwrap_or(data);
+    let data = data.strip_suffix(&[0xf7]).unwrap_or(data);
+    if data.len() < HEADER_LEN || data[0] != UNIVERSAL_REALTIME || data[2] != MMC_COMMAND {
+      return None;
+    }
+
+    let device = data[1];
+    let mut commands = Vec::new();
+    let mut data = &data[HEADER_LEN..];
+    while !data.is_empty() {
+      let (command, len) = MmcCommand::parse(data)?;
+      commands.push(command);
+      data = &data[len..];
+    }
+    (!commands.is_empty()).then(|| Self { device, commands })
+  }
+
+  /// Builds the data of the SysEx message, without the `F0` and `F7` bytes.
+  pub fn to_sysex(&self) -> Vec<u8> {
+    let mut data = vec![UNIVERSAL_REALTIME, self.device & 0x7f, MMC_COMMAND];
+    for command in self.commands.iter() {
+      command.write(&mut data);
+    }
+    data
+  }
+
+  /// Whether the message is addressed to a device.
+  pub fn is_for(&self, device: u8) -> bool {
+    self.device == ALL_DEVICES || self.device == device
+  }
+}
+
+/// Decodes the MMC messages from the SysEx7 packets received from a source.
+pub struct MmcDecoder {
+  device: u8,
+  assembler: SysExAssembler,
+}
+
+impl MmcDecoder {
+  /// Decodes the messages addressed to any device.
+  pub fn new() -> Self {
+    Self::for_device(ALL_DEVICES)
+  }
+
+  /// Decodes only the messages addressed to `device` or to all the devices.
+  pub fn for_device(device: u8) -> Self {
+    Self {
+      device,
+      assembler: SysExAssembler::new(MAX_LEN),
+    }
+  }
+
+  /// Handles a message, returning the MMC message once all its packets arrive.
+  pub fn process(&mut self, message: &Message) -> Option<MmcMessage> {
+    let packet = match &message.mtype {
+      MessageType::SysEx7(packet) => packet,
+      _ => return None,
+    };
+    let mmc = self.assembler.push(packet).and_then(MmcMessage::parse)?;
+    (self.device == ALL_DEVICES || mmc.is_for(self.device)).then(|| mmc)
+  }
+
+  pub fn reset(&mut self) {
+    self.assembler.reset();
+  }
+}
+
+impl Default for MmcDecoder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::messages::sysex7::{SysEx7, SysExStatus};
+
+  fn packets(data: &[u8]) -> Vec<Message> {
+    let chunks = data.chunks(6).collect::<Vec<_>>();
+    chunks
+      .iter()
+      .enumerate()
+      .map(|(index, chunk)| {
+        let status = match index {
+          0 if chunks.len() == 1 => SysExStatus::Complete,
+          0 => SysExStatus::Start,
+          _ if index == chunks.len() - 1 => SysExStatus::End,
+          _ => SysExStatus::Continue,
+        };
+        Message {
+          group: 0,
+          mtype: MessageType::SysEx7(SysEx7::new(status, chunk)),
+        }
+      })
+      .collect()
+  }
+
+  #[test]
+  fn parse_commands() {
+    let message = MmcMessage::parse(&[0xf0, 0x7f, 0x10, 0x06, 0x02, 0x06, 0xf7]).unwrap();
+    assert_eq!(message.device, 0x10);
+    assert_eq!(
+      message.commands,
+      vec![MmcCommand::Play, MmcCommand::RecordStrobe]
+    );
+
+    // Unknown commands with data are skipped over
+    let message = MmcMessage::parse(&[0x7f, 0x7f, 0x06, 0x4c, 0x02, 0x01, 0x02, 0x01]).unwrap();
+    assert_eq!(
+      message.commands,
+      vec![MmcCommand::Other(0x4c), MmcCommand::Stop]
+    );
+
+    assert_eq!(MmcMessage::parse(&[0x7f, 0x7f, 0x07, 0x01]), None);
+    assert_eq!(
+      MmcMessage::parse(&[0x7f, 0x7f, 0x06, 0x44, 0x06, 0x01]),
+      None
+    );
+  }
+
+  #[test]
+  fn locate_round_trip() {
+    let message = MmcMessage::new(
+      ALL_DEVICES,
+      MmcCommand::Locate {
+        timecode: Timecode {
+          hours: 1,
+          minutes: 2,
+          seconds: 3,
+          frames: 4,
+          rate: FrameRate::Fps25,
+        },
+        subframes: 50,
+      },
+    );
+    let data = message.to_sysex();
+    assert_eq!(
+      data,
+      [0x7f, 0x7f, 0x06, 0x44, 0x06, 0x01, 0x21, 0x02, 0x03, 0x04, 50]
+    );
+    assert_eq!(MmcMessage::parse(&data), Some(message));
+  }
+
+  #[test]
+  fn decode_for_device() {
+    let mut decoder = MmcDecoder::for_device(0x05);
+    let decode = |decoder: &mut MmcDecoder, data: &[u8]| {
+      packets(data)
+        .iter()
+        .filter_map(|message| decoder.process(message))
+        .collect::<Vec<_>>()
+    };
+
+    let stop = MmcMessage::new(0x05, MmcCommand::Stop).to_sysex();
+    assert_eq!(decode(&mut decoder, &stop).len(), 1);
+    let other = MmcMessage::new(0x06, MmcCommand::Stop).to_sysex();
+    assert!(decode(&mut decoder, &other).is_empty());
+
+    let mut message = MmcMessage::new(ALL_DEVICES, MmcCommand::Rewind);
+    message.commands.push(MmcCommand::DeferredPlay);
+    assert_eq!(
+      decode(&mut decoder, &message.to_sysex()),
+      vec![message.clone()]
+    );
+  }
+}
@@ -0,0 +1,731 @@
+//! MIDI Polyphonic Expression (MPE).
+//!
+//! MPE sends every note through its own member channel, so the pitch bend, channel pressure and
+//! timbre (CC 74) of the channel apply only to that note. The member channels are grouped in zones
+//! configured with the MPE Configuration Message (RPN 6) sent through the manager channel:
+//! the first channel for the lower zone, and the last one for the upper zone.
+//!
+//! [`Mpe`] keeps track of the zones and the channels, and turns the messages into per-note events
+//! carrying all the dimensions of the note.
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use crate::endpoints::EndpointId;
+use crate::event::Event;
+use crate::input_handler::InputHandler;
+use crate::protocol::messages::channel_voice::ChanelVoiceMessage;
+use crate::protocol::messages::channel_voice1::ChannelVoice1Message;
+use crate::protocol::messages::{Message, MessageType};
+use crate::protocol::parameters::{scale_up_14, ParameterAssembler};
+
+/// Pitch bend sensitivity
+pub(crate) const RPN_PITCH_BEND_RANGE: u8 = 0;
+/// MPE Configuration Message
+const RPN_MPE_CONFIGURATION: u8 = 6;
+
+const CC_TIMBRE: u8 = 74;
+
+const DEFAULT_MEMBER_PITCH_BEND_RANGE: f32 = 48.0;
+const DEFAULT_MANAGER_PITCH_BEND_RANGE: f32 = 2.0;
+
+/// Timbre of the channels before receiving any CC 74, at the center
+const DEFAULT_TIMBRE: f32 = 0.5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ZoneKind {
+  /// Managed through the first channel, with the member channels above it
+  Lower,
+  /// Managed through the last channel, with the member channels below it
+  Upper,
+}
+
+impl ZoneKind {
+  pub fn manager_channel(self) -> u8 {
+    match self {
+      Self::Lower => 0,
+      Self::Upper => 15,
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Zone {
+  pub kind: ZoneKind,
+  /// Number of member channels, from 1 to 15
+  pub members: u8,
+  /// Pitch bend range of the member channels, in semitones
+  pub member_pitch_bend_range: f32,
+  /// Pitch bend range of the manager channel, in semitones
+  pub manager_pitch_bend_range: f32,
+}
+
+impl Zone {
+  pub fn new(kind: ZoneKind, members: u8) -> Self {
+    Self {
+      kind,
+      members: members.clamp(1, 15),
+      member_pitch_bend_range: DEFAULT_MEMBER_PITCH_BEND_RANGE,
+      manager_pitch_bend_range: DEFAULT_MANAGER_PITCH_BEND_RANGE,
+    }
+  }
+
+  pub fn manager_channel(&self) -> u8 {
+    self.kind.manager_channel()
+  }
+
+  pub fn member_channels(&self) -> RangeInclusive<u8> {
+    match self.kind {
+      ZoneKind::Lower => 1..=self.members,
+      ZoneKind::Upper => 15 - self.members..=14,
+    }
+  }
+
+  /// Whether the channel is the manager or one of the members of the zone.
+  pub fn contains(&self, channel: u8) -> bool {
+    channel == self.manager_channel() || self.member_channels().contains(&channel)
+  }
+}
+
+/// A note playing in a zone, with its dimensions
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MpeNote {
+  pub zone: ZoneKind,
+  pub group: u8,
+  pub channel: u8,
+  pub note: u8,
+  /// From 0.0 to 1.0
+  pub velocity: f32,
+  /// In semitones, adding up the pitch bend of the member and the manager channels
+  pub pitch_bend: f32,
+  /// From 0.0 to 1.0
+  pub pressure: f32,
+  /// From 0.0 to 1.0, centered at 0.5
+  pub timbre: f32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MpeEvent {
+  NoteOn(MpeNote),
+  /// Some of the dimensions of a playing note changed
+  NoteChanged(MpeNote),
+  /// A note was released, or ended because its zone changed
+  NoteOff {
+    note: MpeNote,
+    velocity: f32,
+  },
+  /// A zone was configured, or removed when `None`
+  ZoneChanged {
+    group: u8,
+    kind: ZoneKind,
+    zone: Option<Zone>,
+  },
+  /// Any other message, such as the control changes of the manager channels,
+  /// or the messages of the channels outside of the zones
+  Message(Message),
+}
+
+/// The dimensions of a channel, normalized
+#[derive(Debug, Clone, Copy)]
+struct Dimensions {
+  /// From -1.0 to 1.0
+  pitch_bend: f32,
+  pressure: f32,
+  timbre: f32,
+}
+
+impl Default for Dimensions {
+  fn default() -> Self {
+    Self {
+      pitch_bend: 0.0,
+      pressure: 0.0,
+      timbre: DEFAULT_TIMBRE,
+    }
+  }
+}
+
+#[derive(Default)]
+struct GroupState {
+  zones: [Option<Zone>; 2],
+  channels: [Dimensions; 16],
+  notes: Vec<MpeNote>,
+}
+
+impl GroupState {
+  fn zone(&self, channel: u8) -> Option<&Zone> {
+    self
+      .zones
+      .iter()
+      .flatten()
+      .find(|zone| zone.contains(channel))
+  }
+
+  fn zone_mut(&mut self, kind: ZoneKind) -> &mut Option<Zone> {
+    match kind {
+      ZoneKind::Lower => &mut self.zones[0],
+      ZoneKind::Upper => &mut self.zones[1],
+    }
+  }
+
+  fn pitch_bend(&self, zone: &Zone, channel: u8) -> f32 {
+    let manager = self.channels[zone.manager_channel() as usize].pitch_bend;
+    let manager = manager * zone.manager_pitch_bend_range;
+    if channel == zone.manager_channel() {
+      manager
+    } else {
+      manager + self.channels[channel as usize].pitch_bend * zone.member_pitch_bend_range
+    }
+  }
+
+  /// Recalculates the dimensions of the notes affected by a change in a channel.
+  fn update_notes<F>(&mut self, channel: u8, f: &mut F)
+  where
+    F: FnMut(MpeEvent),
+  {
+    let zone = match self.zone(channel) {
+      Some(zone) => *zone,
+      None => return,
+    };
+    let manager = channel == zone.manager_channel();
+    for index in 0..self.notes.len() {
+      let note = self.notes[index];
+      if note.zone == zone.kind && (manager || note.channel == channel) {
+        let dimensions = self.channels[note.channel as usize];
+        let updated = MpeNote {
+          pitch_bend: self.pitch_bend(&zone, note.channel),
+          pressure: dimensions.pressure,
+          timbre: dimensions.timbre,
+          ..note
+        };
+        self.notes[index] = updated;
+        f(MpeEvent::NoteChanged(updated));
+      }
+    }
+  }
+
+  fn end_notes<F>(&mut self, kind: ZoneKind, f: &mut F)
+  where
+    F: FnMut(MpeEvent),
+  {
+    let (ended, notes) = self.notes.drain(..).partition(|note| note.zone == kind);
+    self.notes = notes;
+    for note in ended {
+      f(MpeEvent::NoteOff {
+        note,
+        velocity: 0.0,
+      });
+    }
+  }
+}
+
+/// Tracks the MPE zones and the notes of a source, turning its messages into per-note events.
+pub struct Mpe {
+  groups: Vec<GroupState>,
+  parameters: ParameterAssembler,
+}
+
+impl Mpe {
+  pub fn new() -> Self {
+    Self {
+      groups: (0..16).map(|_| GroupState::default()).collect(),
+      parameters: ParameterAssembler::new(),
+    }
+  }
+
+  /// Configures a zone without waiting for an MPE Configuration Message, for the devices that don't send it.
+  #[must_use]
+  pub fn with_zone(mut self, group: u8, kind: ZoneKind, members: u8) -> Self {
+    self.configure(group, kind, members, &mut |_| {});
+    self
+  }
+
+  pub fn zone(&self, group: u8, kind: ZoneKind) -> Option<&Zone> {
+    let state = &self.groups[(group & 0x0f) as usize];
+    match kind {
+      ZoneKind::Lower => state.zones[0].as_ref(),
+      ZoneKind::Upper => state.zones[1].as_ref(),
+    }
+  }
+
+  /// The notes playing, in the order they started.
+  pub fn notes(&self) -> impl Iterator<Item = &MpeNote> {
+    self.groups.iter().flat_map(|state| state.notes.iter())
+  }
+
+  /// Handles a message, calling `f` with the resulting events.
+  ///
+  /// The control changes setting RPNs and NRPNs are consumed, as they are translated into
+  /// registered and assignable controller messages.
+  pub fn process<F>(&mut self, message: Message, mut f: F)
+  where
+    F: FnMut(MpeEvent),
+  {
+    let message = match self.parameters.process(message) {
+      Some(message) => message,
+      None => return,
+    };
+    let group = message.group & 0x0f;
+
+    let (channel, update) = match message.mtype {
+      MessageType::ChannelVoice1(channel_voice) => {
+        (channel_voice.channel, midi1_update(&channel_voice.message))
+      }
+      MessageType::ChannelVoice(channel_voice) => {
+        (channel_voice.channel, midi2_update(&channel_voice.message))
+      }
+      _ => (0, None),
+    };
+
+    match update {
+      Some(Update::Configuration(members)) if channel == 0 || channel == 15 => {
+        let kind = if channel == 0 {
+          ZoneKind::Lower
+        } else {
+          ZoneKind::Upper
+        };
+        self.configure(group, kind, members, &mut f)
+      }
+      Some(update) => self.update(group, channel, update, message, &mut f),
+      None => f(MpeEvent::Message(message)),
+    }
+  }
+
+  /// Wraps a callback receiving MPE events into a handler for an input,
+  /// keeping apart the state of every source connected to it.
+  ///
+  /// The callback gets the original event along with every MPE event resulting from it.
+  pub fn handler<F>(mut f: F) -> InputHandler
+  where
+    F: FnMut(&Event, MpeEvent) + Send + 'static,
+  {
+    let mut sources = HashMap::<EndpointId, Mpe>::new();
+    InputHandler::from(move |event: Event| {
+      sources
+        .entry(event.endpoint)
+        .or_insert_with(Mpe::new)
+        .process(event.message, |mpe_event| f(&event, mpe_event));
+    })
+  }
+
+  fn configure<F>(&mut self, group: u8, kind: ZoneKind, members: u8, f: &mut F)
+  where
+    F: FnMut(MpeEvent),
+  {
+    let state = &mut self.groups[(group & 0x0f) as usize];
+    state.end_notes(kind, f);
+    let zone = (members > 0).then(|| Zone::new(kind, members));
+    *state.zone_mut(kind) = zone;
+    f(MpeEvent::ZoneChanged { group, kind, zone });
+
+    // The other zone shrinks to make room for the new one
+    let other_kind = match kind {
+      ZoneKind::Lower => ZoneKind::Upper,
+      ZoneKind::Upper => ZoneKind::Lower,
+    };
+    let available = 14u8.saturating_sub(members);
+    if let Some(other) = *state.zone_mut(other_kind) {
+      if other.members > available {
+        state.end_notes(other_kind, f);
+        let other = (available > 0).then(|| Zone {
+          members: available,
+          ..other
+        });
+        *state.zone_mut(other_kind) = other;
+        f(MpeEvent::ZoneChanged {
+          group,
+          kind: other_kind,
+          zone: other,
+        });
+      }
+    }
+  }
+
+  fn update<F>(&mut self, group: u8, channel: u8, update: Update, message: Message, f: &mut F)
+  where
+    F: FnMut(MpeEvent),
+  {
+    let state = &mut self.groups[group as usize];
+    let channel = channel & 0x0f;
+    let zone = match state.zone(channel) {
+      Some(zone) => *zone,
+      None => return f(MpeEvent::Message(message)),
+    };
+
+    match update {
+      Update::NoteOn { note, velocity } => {
+        let dimensions = state.channels[channel as usize];
+        let mpe_note = MpeNote {
+          zone: zone.kind,
+          group,
+          channel,
+          note,
+          velocity,
+          pitch_bend: state.pitch_bend(&zone, channel),
+          pressure: dimensions.pressure,
+          timbre: dimensions.timbre,
+        };
+        state.notes.push(mpe_note);
+        f(MpeEvent::NoteOn(mpe_note));
+      }
+      Update::NoteOff { note, velocity } => {
+        if let Some(index) = state
+          .notes
+          .iter()
+          .position(|playing| playing.channel == channel && playing.note == note)
+        {
+          let note = state.notes.remove(index);
+          f(MpeEvent::NoteOff { note, velocity });
+        }
+      }
+      Update::PitchBend(pitch_bend) => {
+        state.channels[channel as usize].pitch_bend = pitch_bend;
+        state.update_notes(channel, f);
+      }
+      Update::Pressure(pressure) => {
+        state.channels[channel as usize].pressure = pressure;
+        state.update_notes(channel, f);
+      }
+      Update::Timbre(timbre) => {
+        state.channels[channel as usize].timbre = timbre;
+        state.update_notes(channel, f);
+      }
+      Update::PitchBendRange(range) => {
+        if let Some(zone) = state.zone_mut(zone.kind) {
+          if channel == zone.manager_channel() {
+            zone.manager_pitch_bend_range = range;
+          } else {
+            zone.member_pitch_bend_range = range;
+          }
+          f(MpeEvent::ZoneChanged {
+            group,
+            kind: zone.kind,
+            zone: Some(*zone),
+          });
+        }
+      }
+      Update::Configuration(_) => f(MpeEvent::Message(message)),
+    }
+  }
+}
+
+impl Default for Mpe {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// What a channel voice message changes, with the values normalized
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Update {
+  NoteOn {
+    note: u8,
+    velocity: f32,
+  },
+  NoteOff {
+    note: u8,
+    velocity: f32,
+  },
+  PitchBend(f32),
+  Pressure(f32),
+  Timbre(f32),
+  /// In semitones
+  PitchBendRange(f32),
+  /// Number of member channels
+  Configuration(u8),
+}
+
+fn midi1_update(message: &ChannelVoice1Message) -> Option<Update> {
+  let update = match *message {
+    ChannelVoice1Message::NoteOn { note, velocity: 0 } => Update::NoteOff {
+      note,
+      velocity: 0.0,
+    },
+    ChannelVoice1Message::NoteOn { note, velocity } => Update::NoteOn {
+      note,
+      velocity: velocity as f32 / 127.0,
+    },
+    ChannelVoice1Message::NoteOff { note, velocity } => Update::NoteOff {
+      note,
+      velocity: velocity as f32 / 127.0,
+    },
+    ChannelVoice1Message::PitchBend { data } => Update::PitchBend((data as f32 - 8192.0) / 8192.0),
+    ChannelVoice1Message::ChannelPressure { data } => Update::Pressure(data as f32 / 127.0),
+    ChannelVoice1Message::ControlChange {
+      index: CC_TIMBRE,
+      data,
+    } => Update::Timbre(data as f32 / 127.0),
+    _ => return None,
+  };
+  Some(update)
+}
+
+fn midi2_update(message: &ChanelVoiceMessage) -> Option<Update> {
+  let update = match *message {
+    ChanelVoiceMessage::NoteOn { note, velocity, .. } => Update::NoteOn {
+      note,
+      velocity: velocity as f32 / u16::MAX as f32,
+    },
+    ChanelVoiceMessage::NoteOff { note, velocity, .. } => Update::NoteOff {
+      note,
+      velocity: velocity as f32 / u16::MAX as f32,
+    },
+    ChanelVoiceMessage::PitchBend { data } => Update::PitchBend(bipolar(data)),
+    ChanelVoiceMessage::ChannelPressure { data } => Update::Pressure(unipolar(data)),
+    ChanelVoiceMessage::ControlChange {
+      index: CC_TIMBRE,
+      data,
+    } => Update::Timbre(unipolar(data)),
+    ChanelVoiceMessage::RegisteredController {
+      bank: 0,
+      index: RPN_PITCH_BEND_RANGE,
+      data,
+    } => Update::PitchBendRange((data >> 25) as f32 + ((data >> 18) & 0x7f) as f32 / 100.0),
+    ChanelVoiceMessage::RegisteredController {
+      bank: 0,
+      index: RPN_MPE_CONFIGURATION,
+      data,
+    } => Update::Configuration(((data >> 25) as u8).min(15)),
+    _ => return None,
+  };
+  Some(update)
+}
+
+fn unipolar(data: u32) -> f32 {
+  (data as f64 / u32::MAX as f64) as f32
+}
+
+fn bipolar(data: u32) -> f32 {
+  ((data as f64 - 0x8000_0000u32 as f64) / 0x8000_0000u32 as f64) as f32
+}
+
+/// The 32 bits value of a pitch bend range, to be sent as a registered controller.
+pub fn pitch_bend_range_data(semitones: u8, cents: u8) -> u32 {
+  scale_up_14((semitones as u16 & 0x7f) << 7 | (cents as u16 & 0x7f))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::messages::channel_voice1::ChannelVoice1;
+
+  fn midi1(channel: u8, message: ChannelVoice1Message) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 { channel, message }),
+    }
+  }
+
+  fn control_change(channel: u8, index: u8, data: u8) -> Message {
+    midi1(channel, ChannelVoice1Message::ControlChange { index, data })
+  }
+
+  fn process(mpe: &mut Mpe, messages: &[Message]) -> Vec<MpeEvent> {
+    let mut events = Vec::new();
+    for message in messages {
+      mpe.process(*message, |event| events.push(event));
+    }
+    events
+  }
+
+  fn configuration(channel: u8, members: u8) -> Vec<Message> {
+    vec![
+      control_change(channel, 101, 0),
+      control_change(channel, 100, 6),
+      control_change(channel, 6, members),
+    ]
+  }
+
+  #[test]
+  fn configure_zones() {
+    let mut mpe = Mpe::new();
+
+    let events = process(&mut mpe, &configuration(0, 7));
+    assert_eq!(
+      events,
+      vec![MpeEvent::ZoneChanged {
+        group: 0,
+        kind: ZoneKind::Lower,
+        zone: Some(Zone::new(ZoneKind::Lower, 7)),
+      }]
+    );
+    assert_eq!(
+      mpe.zone(0, ZoneKind::Lower).map(Zone::member_channels),
+      Some(1..=7)
+    );
+
+    // The upper zone takes the channels it needs from the lower one
+    process(&mut mpe, &configuration(15, 10));
+    assert_eq!(
+      mpe.zone(0, ZoneKind::Upper).map(Zone::member_channels),
+      Some(5..=14)
+    );
+    assert_eq!(
+      mpe.zone(0, ZoneKind::Lower).map(Zone::member_channels),
+      Some(1..=4)
+    );
+
+    process(&mut mpe, &configuration(0, 0));
+    assert_eq!(mpe.zone(0, ZoneKind::Lower), None);
+  }
+
+  #[test]
+  fn notes_with_dimensions() {
+    let mut mpe = Mpe::new().with_zone(0, ZoneKind::Lower, 15);
+
+    let events = process(
+      &mut mpe,
+      &[
+        midi1(2, ChannelVoice1Message::PitchBend { data: 0x3000 }),
+        midi1(
+          2,
+          ChannelVoice1Message::NoteOn {
+            note: 60,
+            velocity: 127,
+          },
+        ),
+        midi1(3, ChannelVoice1Message::ChannelPressure { data: 127 }),
+        midi1(2, ChannelVoice1Message::ChannelPressure { data: 127 }),
+      ],
+    );
+
+    let note = MpeNote {
+      zone: ZoneKind::Lower,
+      group: 0,
+      channel: 2,
+      note: 60,
+      velocity: 1.0,
+      pitch_bend: 24.0,
+      pressure: 0.0,
+      timbre: 0.5,
+    };
+    assert_eq!(
+      events,
+      vec![
+        MpeEvent::NoteOn(note),
+        MpeEvent::NoteChanged(MpeNote {
+          pressure: 1.0,
+          ..note
+        }),
+      ]
+    );
+  }
+
+  #[test]
+  fn manager_pitch_bend_applies_to_the_zone() {
+    let mut mpe = Mpe::new().with_zone(0, ZoneKind::Lower, 15);
+    process(
+      &mut mpe,
+      &[
+        midi1(
+          1,
+          ChannelVoice1Message::NoteOn {
+            note: 60,
+            velocity: 100,
+          },
+        ),
+        midi1(
+          2,
+          ChannelVoice1Message::NoteOn {
+            note: 64,
+            velocity: 100,
+          },
+        ),
+      ],
+    );
+
+    let events = process(
+      &mut mpe,
+      &[midi1(0, ChannelVoice1Message::PitchBend { data: 0 })],
+    );
+
+    assert_eq!(events.len(), 2);
+    assert!(events
+      .iter()
+      .all(|event| matches!(event, MpeEvent::NoteChanged(note) if note.pitch_bend == -2.0)));
+  }
+
+  #[test]
+  fn note_off_and_other_messages() {
+    let mut mpe = Mpe::new().with_zone(0, ZoneKind::Upper, 3);
+    let volume = control_change(15, 7, 100);
+    let outside = midi1(
+      0,
+      ChannelVoice1Message::NoteOn {
+        note: 60,
+        velocity: 100,
+      },
+    );
+
+    let events = process(
+      &mut mpe,
+      &[
+        volume,
+        outside,
+        midi1(
+          12,
+          ChannelVoice1Message::NoteOn {
+            note: 62,
+            velocity: 100,
+          },
+        ),
+        midi1(
+          12,
+          ChannelVoice1Message::NoteOn {
+            note: 62,
+            velocity: 0,
+          },
+        ),
+      ],
+    );
+
+    assert_eq!(events[0], MpeEvent::Message(volume));
+    assert_eq!(events[1], MpeEvent::Message(outside));
+    assert!(matches!(
+      events[3],
+      MpeEvent::NoteOff { note, velocity } if note.note == 62 && velocity == 0.0
+    ));
+    assert_eq!(mpe.notes().count(), 0);
+  }
+
+  #[test]
+  fn reconfiguration_ends_the_notes() {
+    let mut mpe = Mpe::new().with_zone(0, ZoneKind::Lower, 15);
+    process(
+      &mut mpe,
+      &[midi1(
+        1,
+        ChannelVoice1Message::NoteOn {
+          note: 60,
+          velocity: 100,
+        },
+      )],
+    );
+
+    let events = process(&mut mpe, &configuration(0, 4));
+
+    assert!(matches!(events[0], MpeEvent::NoteOff { note, .. } if note.note == 60));
+    assert_eq!(mpe.notes().count(), 0);
+  }
+
+  #[test]
+  fn pitch_bend_range() {
+    let mut mpe = Mpe::new().with_zone(0, ZoneKind::Lower, 15);
+    process(
+      &mut mpe,
+      &[
+        control_change(1, 101, 0),
+        control_change(1, 100, 0),
+        control_change(1, 6, 12),
+        control_change(1, 38, 50),
+      ],
+    );
+
+    assert_eq!(
+      mpe
+        .zone(0, ZoneKind::Lower)
+        .map(|zone| zone.member_pitch_bend_range),
+      Some(12.5)
+    );
+    assert_eq!(pitch_bend_range_data(12, 50) >> 18, 12 << 7 | 50);
+  }
+}
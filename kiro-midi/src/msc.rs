@@ -0,0 +1,454 @@
+//! MIDI Show Control (MSC).
+//!
+//! Lighting desks, sound playback and other show equipment exchange their cue commands as
+//! Universal Real Time SysEx messages. Every message is addressed to a device ID and a command
+//! format (the kind of equipment), and most commands refer to a cue by its number, optionally
+//! within a cue list and a cue path. The numbers are ASCII strings of digits and dots.
+//!
+//! [`MscDecoder`] turns the SysEx7 packets received into [`MscMessage`]s, and
+//! [`MscMessage::to_sysex`] builds the data to send with `Output::send_sysex`.
+
+use crate::protocol::messages::sysex7::SysExAssembler;
+use crate::protocol::messages::{Message, MessageType};
+use crate::timecode::{FrameRate, Timecode};
+
+const UNIVERSAL_REALTIME: u8 = 0x7f;
+const MSC: u8 = 0x02;
+const HEADER_LEN: usize = 5;
+
+/// Device ID addressing all the devices
+pub const ALL_DEVICES: u8 = 0x7f;
+
+/// Command format for lighting equipment
+pub const FORMAT_LIGHTING: u8 = 0x01;
+/// Command format for sound equipment
+pub const FORMAT_SOUND: u8 = 0x10;
+/// Command format addressing all the kinds of equipment
+pub const FORMAT_ALL_TYPES: u8 = 0x7f;
+
+const GO: u8 = 0x01;
+const STOP: u8 = 0x02;
+const RESUME: u8 = 0x03;
+const TIMED_GO: u8 = 0x04;
+const LOAD: u8 = 0x05;
+const SET: u8 = 0x06;
+const FIRE: u8 = 0x07;
+const ALL_OFF: u8 = 0x08;
+const RESTORE: u8 = 0x09;
+const RESET: u8 = 0x0a;
+const GO_OFF: u8 = 0x0b;
+
+/// Separates the cue number from the cue list, and the cue list from the cue path
+const CUE_SEPARATOR: u8 = 0x00;
+const TIME_LEN: usize = 5;
+
+/// The longest message allowed by the specification
+const MAX_LEN: usize = 128;
+
+/// A cue number, optionally within a cue list and a cue path
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Cue {
+  pub number: String,
+  pub list: Option<String>,
+  pub path: Option<String>,
+}
+
+impl Cue {
+  pub fn new(number: &str) -> Self {
+    Self {
+      number: number.to_string(),
+      list: None,
+      path: None,
+    }
+  }
+
+  #[must_use]
+  pub fn with_list(mut self, list: &str) -> Self {
+    self.list = Some(list.to_string());
+    self
+  }
+
+  #[must_use]
+  pub fn with_path(mut self, path: &str) -> Self {
+    self.path = Some(path.to_string());
+    self
+  }
+
+  /// Parses the cue at the end of a command, returning `None` when there is no cue.
+  fn parse(data: &[u8]) -> Option<Option<Self>> {
+    if data.is_empty() {
+      return Some(None);
+    }
+    if data.iter().any(|byte| *byte >= 0x80) {
+      return None;
+    }
+    let mut fields = data
+      .split(|byte| *byte == CUE_SEPARATOR)
+      .map(|field| field.iter().map(|byte| *byte as char).collect::<String>());
+    let number = fields.next()?;
+    let list = fields.next();
+    let path = fields.next();
+    if fields.next().is_some() {
+      return None;
+    }
+    Some(Some(Self { number, list, path }))
+  }
+
+  fn write(&self, data: &mut Vec<u8>) {
+    data.extend(self.number.bytes().filter(is_cue_byte));
+    // The path can only be given along with the list
+    if self.list.is_some() || self.path.is_some() {
+      let list = self.list.as_deref().unwrap_or_default();
+      data.push(CUE_SEPARATOR);
+      data.extend(list.bytes().filter(is_cue_byte));
+    }
+    if let Some(path) = self.path.as_ref() {
+      data.push(CUE_SEPARATOR);
+      data.extend(path.bytes().filter(is_cue_byte));
+    }
+  }
+}
+
+fn is_cue_byte(byte: &u8) -> bool {
+  *byte > CUE_SEPARATOR && *byte < 0x80
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MscCommand {
+  /// Start a cue, or the next one when not given
+  Go {
+    cue: Option<Cue>,
+  },
+  Stop {
+    cue: Option<Cue>,
+  },
+  Resume {
+    cue: Option<Cue>,
+  },
+  /// Start a cue with a fade time, with the subframes in hundredths of a frame
+  TimedGo {
+    timecode: Timecode,
+    subframes: u8,
+    cue: Option<Cue>,
+  },
+  /// Prepare a cue to start with the next go
+  Load {
+    cue: Cue,
+  },
+  /// Set a generic control to a value, both with 14 bits
+  Set {
+    control: u16,
+    value: u16,
+  },
+  /// Trigger a macro
+  Fire {
+    macro_number: u8,
+  },
+  AllOff,
+  Restore,
+  Reset,
+  /// Stop a cue fading out
+  GoOff {
+    cue: Option<Cue>,
+  },
+  /// Any other command, without its data
+  Other(u8),
+}
+
+impl MscCommand {
+  fn parse(command: u8, data: &[u8]) -> Option<Self> {
+    let command = match command {
+      GO => Self::Go {
+        cue: Cue::parse(data)?,
+      },
+      STOP => Self::Stop {
+        cue: Cue::parse(data)?,
+      },
+      RESUME => Self::Resume {
+        cue: Cue::parse(data)?,
+      },
+      TIMED_GO => {
+        let time = data.get(..TIME_LEN)?;
+        Self::TimedGo {
+          timecode: Timecode {
+            hours: time[0] & 0x1f,
+            minutes: time[1] & 0x3f,
+            seconds: time[2] & 0x3f,
+            frames: time[3] & 0x1f,
+            rate: FrameRate::from_code(time[0] >> 5),
+          },
+          subframes: time[4] & 0x7f,
+          cue: Cue::parse(&data[TIME_LEN..])?,
+        }
+      }
+      LOAD => Self::Load {
+        cue: Cue::parse(data)??,
+      },
+      SET => {
+        // An optional time can follow the value, it is ignored
+        let data = data.get(..4)?;
+        Self::Set {
+          control: read_u14(&data[0..2]),
+          value: read_u14(&data[2..4]),
+        }
+      }
+      FIRE => Self::Fire {
+        macro_number: *data.first()? & 0x7f,
+      },
+      ALL_OFF => Self::AllOff,
+      RESTORE => Self::Restore,
+      RESET => Self::Reset,
+      GO_OFF => Self::GoOff {
+        cue: Cue::parse(data)?,
+      },
+      command => Self::Other(command),
+    };
+    Some(command)
+  }
+
+  fn write(&self, data: &mut Vec<u8>) {
+    let (command, cue) = match self {
+      Self::Go { cue } => (GO, cue.as_ref()),
+      Self::Stop { cue } => (STOP, cue.as_ref()),
+      Self::Resume { cue } => (RESUME, cue.as_ref()),
+      Self::TimedGo { cue, .. } => (TIMED_GO, cue.as_ref()),
+      Self::Load { cue } => (LOAD, Some(cue)),
+      Self::Set { .. } => (SET, None),
+      Self::Fire { .. } => (FIRE, None),
+      Self::AllOff => (ALL_OFF, None),
+      Self::Restore => (RESTORE, None),
+      Self::Reset => (RESET, None),
+      Self::GoOff { cue } => (GO_OFF, cue.as_ref()),
+      Self::Other(command) => (*command & 0x7f, None),
+    };
+    data.push(command);
+
+    match self {
+      Self::TimedGo {
+        timecode,
+        subframes,
+        ..
+      } => data.extend_from_slice(&[
+        (timecode.hours & 0x1f) | timecode.rate.code() << 5,
+        timecode.minutes & 0x3f,
+        timecode.seconds & 0x3f,
+        timecode.frames & 0x1f,
+        subframes & 0x7f,
+      ]),
+      Self::Set { control, value } => {
+        write_u14(data, *control);
+        write_u14(data, *value);
+      }
+      Self::Fire { macro_number } => data.push(macro_number & 0x7f),
+      _ => {}
+    }
+
+    if let Some(cue) = cue {
+      cue.write(data);
+    }
+  }
+}
+
+fn read_u14(data: &[u8]) -> u16 {
+  (data[0] & 0x7f) as u16 | ((data[1] & 0x7f) as u16) << 7
+}
+
+fn write_u14(data: &mut Vec<u8>, value: u16) {
+  data.extend_from_slice(&[(value & 0x7f) as u8, ((value >> 7) & 0x7f) as u8]);
+}
+
+/// A MSC SysEx message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MscMessage {
+  /// The device addressed, or `ALL_DEVICES`
+  pub device: u8,
+  /// The kind of equipment addressed, such as `FORMAT_LIGHTING`
+  pub format: u8,
+  pub command: MscCommand,
+}
+
+impl MscMessage {
+  pub fn new(device: u8, format: u8, command: MscCommand) -> Self {
+    Self {
+      device,
+      format,
+      command,
+    }
+  }
+
+  /// Parses the data of a SysEx message, returning `None` when it is not a valid MSC message.
+  pub fn parse(data: &[u8]) -> Option<Self> {
+    let data = data.strip_prefix(&[0xf0]).unwrap_or(data);
+    let data = data.strip_suffix(&[0xf7]).unwrap_or(data);
+    if data.len() < HEADER_LEN || data[0] != UNIVERSAL_REALTIME || data[2] != MSC {
+      return None;
+    }
+
+    Some(Self {
+      device: data[1],
+      format: data[3],
+      command: MscCommand::parse(data[4], &data[HEADER_LEN..])?,
+    })
+  }
+
+  /// Builds the data of the SysEx message, without the `F0` and `F7` bytes.
+  pub fn to_sysex(&self) -> Vec<u8> {
+    let mut data = vec![
+      UNIVERSAL_REALTIME,
+      self.device & 0x7f,
+      MSC,
+      self.format & 0x7f,
+    ];
+    self.command.write(&mut data);
+    data
+  }
+
+  /// Whether the message is addressed to a device and a kind of equipment.
+  pub fn is_for(&self, device: u8, format: u8) -> bool {
+    (self.device == ALL_DEVICES || self.device == device)
+      && (self.format == FORMAT_ALL_TYPES || self.format == format)
+  }
+}
+
+/// Decodes the MSC messages from the SysEx7 packets received from a source.
+pub struct MscDecoder {
+  device: u8,
+  format: u8,
+  assembler: SysExAssembler,
+}
+
+impl MscDecoder {
+  /// Decodes the messages addressed to any device and kind of equipment.
+  pub fn new() -> Self {
+    Self::for_device(ALL_DEVICES, FORMAT_ALL_TYPES)
+  }
+
+  /// Decodes only the messages addressed to `device` and `format`, or to all of them.
+  pub fn for_device(device: u8, format: u8) -> Self {
+    Self {
+      device,
+      format,
+      assembler: SysExAssembler::new(MAX_LEN),
+    }
+  }
+
+  /// Handles a message, returning the MSC message once all its packets arrive.
+  pub fn process(&mut self, message: &Message) -> Option<MscMessage> {
+    let packet = match &message.mtype {
+      MessageType::SysEx7(packet) => packet,
+      _ => return None,
+    };
+    let msc = self.assembler.push(packet).and_then(MscMessage::parse)?;
+    let device_matches =
+      self.device == ALL_DEVICES || msc.device == ALL_DEVICES || msc.device == self.device;
+    let format_matches = self.format == FORMAT_ALL_TYPES
+      || msc.format == FORMAT_ALL_TYPES
+      || msc.format == self.format;
+    (device_matches && format_matches).then(|| msc)
+  }
+
+  pub fn reset(&mut self) {
+    self.assembler.reset();
+  }
+}
+
+impl Default for MscDecoder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::messages::sysex7::{SysEx7, SysExStatus};
+
+  #[test]
+  fn parse_go_with_cue() {
+    let data = [
+      0xf0, 0x7f, 0x01, 0x02, 0x01, 0x01, b'1', b'2', b'.', b'5', 0x00, b'3', 0xf7,
+    ];
+    let message = MscMessage::parse(&data).unwrap();
+    assert_eq!(message.device, 0x01);
+    assert_eq!(message.format, FORMAT_LIGHTING);
+    assert_eq!(
+      message.command,
+      MscCommand::Go {
+        cue: Some(Cue::new("12.5").with_list("3"))
+      }
+    );
+
+    let message = MscMessage::parse(&[0x7f, 0x7f, 0x02, 0x10, 0x02]).unwrap();
+    assert_eq!(message.command, MscCommand::Stop { cue: None });
+
+    // A load needs a cue
+    assert_eq!(MscMessage::parse(&[0x7f, 0x7f, 0x02, 0x10, 0x05]), None);
+  }
+
+  #[test]
+  fn commands_round_trip() {
+    let commands = [
+      MscCommand::Resume {
+        cue: Some(Cue::new("7").with_list("1").with_path("2")),
+      },
+      MscCommand::TimedGo {
+        timecode: Timecode {
+          hours: 0,
+          minutes: 0,
+          seconds: 5,
+          frames: 0,
+          rate: FrameRate::Fps30,
+        },
+        subframes: 0,
+        cue: Some(Cue::new("4")),
+      },
+      MscCommand::Load { cue: Cue::new("9") },
+      MscCommand::Set {
+        control: 300,
+        value: 0x3fff,
+      },
+      MscCommand::Fire { macro_number: 12 },
+      MscCommand::AllOff,
+      MscCommand::GoOff { cue: None },
+    ];
+    for command in commands {
+      let message = MscMessage::new(0x05, FORMAT_SOUND, command);
+      assert_eq!(MscMessage::parse(&message.to_sysex()), Some(message));
+    }
+  }
+
+  #[test]
+  fn decode_for_device() {
+    let mut decoder = MscDecoder::for_device(0x05, FORMAT_LIGHTING);
+    let mut decode = |message: &MscMessage| {
+      let data = message.to_sysex();
+      let chunks = data.chunks(6).collect::<Vec<_>>();
+      let mut decoded = None;
+      for (index, chunk) in chunks.iter().enumerate() {
+        let status = match index {
+          0 if chunks.len() == 1 => SysExStatus::Complete,
+          0 => SysExStatus::Start,
+          _ if index == chunks.len() - 1 => SysExStatus::End,
+          _ => SysExStatus::Continue,
+        };
+        let message = Message {
+          group: 0,
+          mtype: MessageType::SysEx7(SysEx7::new(status, chunk)),
+        };
+        decoded = decoded.or_else(|| decoder.process(&message));
+      }
+      decoded
+    };
+
+    let go = MscCommand::Go {
+      cue: Some(Cue::new("1")),
+    };
+    let message = MscMessage::new(0x05, FORMAT_ALL_TYPES, go.clone());
+    assert_eq!(decode(&message), Some(message));
+    assert_eq!(
+      decode(&MscMessage::new(0x05, FORMAT_SOUND, go.clone())),
+      None
+    );
+    assert_eq!(decode(&MscMessage::new(0x06, FORMAT_LIGHTING, go)), None);
+  }
+}
@@ -0,0 +1,287 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::endpoints::DestinationId;
+use crate::event::TimestampNanos;
+use crate::filter::Filter;
+use crate::output_producer::OutputProducer;
+use crate::protocol::encoder::{encode_message, encode_sysex7};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::sysex7::SYSEX7_MAX_DATA;
+use crate::protocol::messages::{Message, MessageType};
+use crate::transform::Transform;
+
+/// Where the outputs send the UMP words, implemented by every driver supporting them
+pub(crate) trait OutputSink: Send + Sync {
+  /// A timestamp of 0 means to send as soon as possible
+  fn send(&self, timestamp: TimestampNanos, ump: &[u32]);
+  fn connected_destinations(&self) -> Vec<DestinationId>;
+}
+
+/// No pitch bend range set with `Output::set_pitch_bend_range`
+const NO_PITCH_BEND_RANGE: u64 = u64::MAX;
+
+/// Handle to send messages to the destinations matching an `OutputConfig`.
+///
+/// The destinations are connected and disconnected by the driver as they come and go,
+/// so it can be kept around for the whole life of the driver, and cloned to be used
+/// from other threads.
+#[derive(Clone)]
+pub struct Output {
+  name: String,
+  sink: Arc<dyn OutputSink>,
+  filter: Filter,
+  transforms: Vec<Transform>,
+  /// Input and output semitones set with `set_pitch_bend_range`, as the bits of both `f32`,
+  /// shared by all the clones of the output so they can be changed while sending
+  pitch_bend_range: Arc<AtomicU64>,
+}
+
+impl Output {
+  /// Bandwidth of a MIDI 1.0 DIN connection, to pace the SysEx messages for hardware
+  pub const MIDI1_BYTES_PER_SECOND: u32 = 3125;
+
+  pub(crate) fn new(name: String, sink: Arc<dyn OutputSink>) -> Self {
+    Self {
+      name,
+      sink,
+      filter: Filter::default(),
+      transforms: Vec::new(),
+      pitch_bend_range: Arc::new(AtomicU64::new(NO_PITCH_BEND_RANGE)),
+    }
+  }
+
+  pub(crate) fn with_processing(mut self, filter: Filter, transforms: Vec<Transform>) -> Self {
+    self.filter = filter;
+    self.transforms = transforms;
+    self
+  }
+
+  pub fn name(&self) -> &str {
+    self.name.as_str()
+  }
+
+  pub fn connected_destinations(&self) -> Vec<DestinationId> {
+    self.sink.connected_destinations()
+  }
+
+  /// Sends a message to all the connected destinations.
+  pub fn send(&self, message: Message) {
+    self.send_at(message, 0);
+  }
+
+  /// Schedules a message to be delivered to the connected destinations at a given time.
+  ///
+  /// The timestamp uses the clock of `kiro_midi::now()`, the same as the events received by the inputs.
+  /// Drivers with timestamped APIs pass it to the OS, the rest deliver it right away,
+  /// unless the output comes from an `OutputQueue`.
+  pub fn send_at(&self, message: Message, timestamp: TimestampNanos) {
+    self.process(message, &mut |message| {
+      self
+        .sink
+        .send(timestamp, encode_message(&message).as_slice())
+    });
+  }
+
+  /// Sends All Notes Off, All Sound Off and sustain off to every channel of every group of the connected destinations.
+  ///
+  /// The filter is bypassed, so it reaches all the channels anyway, but the transforms are applied,
+  /// so the destinations get them in the groups and channels that the transforms remap to.
+  pub fn panic(&self) {
+    for group in 0..16 {
+      for channel in 0..16 {
+        for (index, data) in [(123, 0), (120, 0), (64, 0)] {
+          let message = Message {
+            group,
+            mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+              channel,
+              message: ChannelVoice1Message::ControlChange { index, data },
+            }),
+          };
+          self.transform(message, &mut |message| {
+            self.sink.send(0, encode_message(&message).as_slice())
+          });
+        }
+      }
+    }
+  }
+
+  /// Sends the pitch bend range of the last transform rescaling the pitch bends, or the one from
+  /// `set_pitch_bend_range` (as RPN 0 control changes) to every channel of every group of the connected
+  /// destinations, so they bend as far as the transform expects.
+  ///
+  /// The drivers send it as the destinations connect. The filter and the transforms are bypassed.
+  pub fn send_pitch_bend_range(&self) {
+    self.pitch_bend_range(&mut |message| self.sink.send(0, encode_message(&message).as_slice()));
+  }
+
+  /// Changes how the pitch bends are rescaled by this output and all its clones, replacing the range of the
+  /// last transform rescaling them (or rescaling them after the transforms if none does), see
+  /// `Transform::with_pitch_bend_range`, and sends the new range to the connected destinations.
+  pub fn set_pitch_bend_range(&self, input: f32, output: f32) {
+    if input > 0.0 && output > 0.0 {
+      let bits = (input.to_bits() as u64) << 32 | output.to_bits() as u64;
+      self.pitch_bend_range.store(bits, Ordering::Release);
+      self.send_pitch_bend_range();
+    }
+  }
+
+  pub(crate) fn has_pitch_bend_range(&self) -> bool {
+    self.rescaling().is_some()
+  }
+
+  /// The position in the transforms of the one rescaling the pitch bends (which is past them when it comes
+  /// from `set_pitch_bend_range` and none of them does), and the transform to apply there.
+  fn rescaling(&self) -> Option<(usize, Transform)> {
+    let last = self
+      .transforms
+      .iter()
+      .rposition(|transform| transform.pitch_bend_range().is_some());
+    match self.pitch_bend_range.load(Ordering::Acquire) {
+      NO_PITCH_BEND_RANGE => last.map(|index| (index, self.transforms[index])),
+      bits => {
+        let input = f32::from_bits((bits >> 32) as u32);
+        let output = f32::from_bits(bits as u32);
+        let (index, transform) = match last {
+          Some(index) => (index, self.transforms[index]),
+          None => (self.transforms.len(), Transform::new()),
+        };
+        Some((index, transform.with_pitch_bend_range(input, output)))
+      }
+    }
+  }
+
+  /// Passes the control changes setting the pitch bend range to `send`, see `send_pitch_bend_range`.
+  pub(crate) fn pitch_bend_range(&self, send: &mut dyn FnMut(Message)) {
+    let range = match self
+      .rescaling()
+      .and_then(|(_, transform)| transform.pitch_bend_range())
+    {
+      Some(range) => range.min(127.0),
+      None => return,
+    };
+    let semitones = range.trunc();
+    let cents = ((range - semitones) * 100.0).round().min(99.0);
+    for group in 0..16 {
+      for channel in 0..16 {
+        // Selecting the RPN 0, setting it, and deselecting it so the data entries don't change it again
+        for (index, data) in [
+          (101, 0),
+          (100, 0),
+          (6, semitones as u8),
+          (38, cents as u8),
+          (101, 127),
+          (100, 127),
+        ] {
+          send(Message {
+            group,
+            mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+              channel,
+              message: ChannelVoice1Message::ControlChange { index, data },
+            }),
+          });
+        }
+      }
+    }
+  }
+
+  /// Creates a ring buffer to send events from real-time threads, see `OutputProducer`.
+  pub fn producer(&self, capacity: usize) -> std::io::Result<OutputProducer> {
+    OutputProducer::new(self.clone(), capacity)
+  }
+
+  /// Sends a SysEx message to all the connected destinations, split into SysEx7 packets for group 0.
+  pub fn send_sysex(&self, data: &[u8]) {
+    if self.sysex_allowed() {
+      encode_sysex7(0, data, |ump| self.sink.send(0, ump.as_slice()));
+    }
+  }
+
+  /// Sends a SysEx message from a background thread, waiting between the packets so the
+  /// destinations don't receive more than `bytes_per_second`, and calls `on_complete` when done.
+  ///
+  /// Slow hardware can drop the data of big messages sent all at once, using
+  /// `Output::MIDI1_BYTES_PER_SECOND` should be safe for most of it.
+  pub fn send_sysex_paced<F>(
+    &self,
+    data: &[u8],
+    bytes_per_second: u32,
+    on_complete: F,
+  ) -> std::io::Result<()>
+  where
+    F: FnOnce() + Send + 'static,
+  {
+    let mut packets = Vec::new();
+    if self.sysex_allowed() {
+      encode_sysex7(0, data, |ump| packets.push(ump));
+    }
+
+    let packet_duration =
+      Duration::from_secs_f64(SYSEX7_MAX_DATA as f64 / bytes_per_second.max(1) as f64);
+    let sink = self.sink.clone();
+
+    std::thread::Builder::new()
+      .name(format!("{}-sysex", self.name))
+      .spawn(move || {
+        for (index, packet) in packets.iter().enumerate() {
+          if index > 0 {
+            std::thread::sleep(packet_duration);
+          }
+          sink.send(0, packet.as_slice());
+        }
+        on_complete();
+      })
+      .map(|_| ())
+  }
+
+  fn sysex_allowed(&self) -> bool {
+    self.filter.mtype(0x03) && self.filter.group(0)
+  }
+
+  /// Applies the filter and the transforms from the config, in order, passing the messages left
+  /// (with the layers of every transform) to `send`.
+  fn process(&self, message: Message, send: &mut dyn FnMut(Message)) {
+    if self.filter.message(&message) {
+      self.transform(message, send);
+    }
+  }
+
+  fn transform(&self, message: Message, send: &mut dyn FnMut(Message)) {
+    Self::transform_from(&self.transforms, self.rescaling(), 0, message, send);
+  }
+
+  /// Applies the transforms from `index` on, replacing the one at the index of `rescaling` by its transform.
+
+  fn transform_from(
+    transforms: &[Transform],
+    rescaling: Option<(usize, Transform)>,
+    index: usize,
+    message: Message,
+    send: &mut dyn FnMut(Message),
+  ) {
+    let transform = match rescaling {
+      Some((rescaling_index, transform)) if rescaling_index == index => Some(transform),
+      _ => transforms.get(index).copied(),
+    };
+    match transform {
+      Some(transform) => {
+        for message in transform.apply_layered(message) {
+          Self::transform_from(transforms, rescaling, index + 1, message, send);
+        }
+      }
+      None => send(message),
+    }
+  }
+
+  /// Sends the words as they are, without filtering nor transforming them.
+  pub(crate) fn send_ump(&self, timestamp: TimestampNanos, ump: &[u32]) {
+    self.sink.send(timestamp, ump);
+  }
+}
+
+impl std::fmt::Debug for Output {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    write!(f, "Output({})", self.name)
+  }
+}
@@ -0,0 +1,68 @@
+use crate::destination_match::{DestinationMatch, DestinationMatches, DestinationRemap};
+use crate::filter::Filter;
+use crate::transform::Transform;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct OutputConfig {
+  pub name: String,
+  pub destinations: DestinationMatches,
+  pub filter: Filter,
+  pub transforms: Vec<Transform>,
+}
+
+impl OutputConfig {
+  pub fn new<N>(name: N) -> Self
+  where
+    N: Into<String>,
+  {
+    Self {
+      name: name.into(),
+      destinations: DestinationMatches::default(),
+      filter: Filter::default(),
+      transforms: Vec::new(),
+    }
+  }
+
+  pub fn with_destination<M>(mut self, destination_match: M) -> Self
+  where
+    M: Into<DestinationMatch>,
+  {
+    self.destinations.add_destination(destination_match);
+    self
+  }
+
+  /// Rewrites the group and channel of the messages sent to the destinations matching.
+  pub fn with_remapped_destination<M>(
+    mut self,
+    destination_match: M,
+    remap: DestinationRemap,
+  ) -> Self
+  where
+    M: Into<DestinationMatch>,
+  {
+    self
+      .destinations
+      .add_remapped_destination(destination_match, remap);
+    self
+  }
+
+  pub fn with_all_destinations(mut self) -> Self {
+    self
+      .destinations
+      .add_destination(DestinationMatch::regex(".*").expect("regex"));
+    self
+  }
+
+  /// Only the messages accepted by the filter are sent.
+  pub fn with_filter(mut self, filter: Filter) -> Self {
+    self.filter = filter;
+    self
+  }
+
+  /// Adds a transform to apply to the messages before sending them, after the ones added before.
+  pub fn with_transform(mut self, transform: Transform) -> Self {
+    self.transforms.push(transform);
+    self
+  }
+}
@@ -0,0 +1,34 @@
+use crate::endpoints::DestinationId;
+
+/// Change in the destinations connected to an output, as they come and go or get re-patched
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutputConnection {
+  Connected {
+    output: String,
+    destination: DestinationId,
+  },
+  Disconnected {
+    output: String,
+    destination: DestinationId,
+  },
+}
+
+impl OutputConnection {
+  pub fn output(&self) -> &str {
+    match self {
+      Self::Connected { output, .. } | Self::Disconnected { output, .. } => output.as_str(),
+    }
+  }
+
+  pub fn destination(&self) -> DestinationId {
+    match self {
+      Self::Connected { destination, .. } | Self::Disconnected { destination, .. } => *destination,
+    }
+  }
+}
+
+/// Called with every change in the connections of the outputs of a driver.
+///
+/// It is called from the thread that detects the change, while the driver is locked,
+/// so it should not call back into the driver (forwarding the changes into a channel is fine).
+pub type OutputConnectionHandler = Box<dyn FnMut(OutputConnection) + Send + 'static>;
@@ -0,0 +1,128 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::Thread;
+
+use ringbuf::{Consumer, Producer, RingBuffer};
+
+use crate::event::Event;
+use crate::output::Output;
+
+/// Producer side of a ring buffer drained into an `Output` by a background thread.
+///
+/// It is the counterpart of `InputHandler::RingBuffer` for the outputs, so the events can be sent
+/// from real-time threads (an audio callback, for example) without any locking or allocation.
+/// The thread sleeps until it is unparked by `push`, so the events are sent right away.
+/// The events are sent with `Output::send_at` using their timestamp, while the endpoint is ignored.
+/// The thread stops once the producer is dropped, after sending the events still in the buffer.
+pub struct OutputProducer {
+  producer: Producer<Event>,
+  running: Arc<AtomicBool>,
+  drainer: Thread,
+}
+
+impl OutputProducer {
+  pub(crate) fn new(output: Output, capacity: usize) -> std::io::Result<Self> {
+    let (producer, consumer) = RingBuffer::new(capacity).split();
+    let running = Arc::new(AtomicBool::new(true));
+    let drainer = Drainer {
+      output,
+      consumer,
+      running: running.clone(),
+    };
+
+    let drainer = std::thread::Builder::new()
+      .name(format!("{}-producer", drainer.output.name()))
+      .spawn(move || drainer.run())?
+      .thread()
+      .clone();
+
+    Ok(Self {
+      producer,
+      running,
+      drainer,
+    })
+  }
+
+  /// Pushes an event to be sent, giving it back when the buffer is full.
+  pub fn push(&mut self, event: Event) -> Result<(), Event> {
+    self.producer.push(event)?;
+    // Without locking, and kept if the thread is not parked yet, so the wake up is never lost
+    self.drainer.unpark();
+    Ok(())
+  }
+
+  pub fn is_full(&self) -> bool {
+    self.producer.is_full()
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.producer.capacity()
+  }
+}
+
+impl Drop for OutputProducer {
+  fn drop(&mut self) {
+    self.running.store(false, Ordering::Release);
+    self.drainer.unpark();
+  }
+}
+
+struct Drainer {
+  output: Output,
+  consumer: Consumer<Event>,
+  running: Arc<AtomicBool>,
+}
+
+impl Drainer {
+  fn run(mut self) {
+    loop {
+      // Checked before draining, so the last events are not lost
+      let running = self.running.load(Ordering::Acquire);
+      while let Some(event) = self.consumer.pop() {
+        self.output.send_at(event.message, event.timestamp);
+      }
+      if !running {
+        break;
+      }
+      std::thread::park();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::time::{Duration, Instant};
+
+  use super::*;
+  use crate::output_queue::OutputQueue;
+  use crate::protocol::messages::utility::Utility;
+  use crate::protocol::messages::{Message, MessageType};
+
+  fn event(timestamp: u64) -> Event {
+    Event {
+      timestamp,
+      endpoint: 0,
+      message: Message {
+        group: 0,
+        mtype: MessageType::Utility(Utility::Noop),
+      },
+    }
+  }
+
+  #[test]
+  fn events_are_sent_through_the_output() {
+    let queue = OutputQueue::new();
+    let mut producer = queue.output("leds").producer(4).unwrap();
+
+    producer.push(event(10)).unwrap();
+    producer.push(event(20)).unwrap();
+
+    let start = Instant::now();
+    while queue.len() < 2 && start.elapsed() < Duration::from_secs(5) {
+      std::thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(queue.next_timestamp(), Some(10));
+    assert_eq!(queue.len(), 2);
+    assert_eq!(producer.capacity(), 4);
+  }
+}
@@ -0,0 +1,293 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::endpoints::DestinationId;
+use crate::event::TimestampNanos;
+use crate::output::{Output, OutputSink};
+use crate::protocol::encoder::Ump;
+
+/// Events kept by `OutputQueue::new`
+const DEFAULT_CAPACITY: usize = 4096;
+
+/// Queue of timestamped events, for the outputs that need to be drained from an audio
+/// process callback to get sample accurate timing.
+///
+/// The outputs created from it put the events sent with `Output::send_at` in the queue,
+/// sorted by timestamp, and then every cycle the callback drains the ones due before the end of it,
+/// computing the frame offsets from the timestamps. Events with the same timestamp keep the order
+/// in which they were sent, and the ones sent with `Output::send` are due right away.
+///
+/// The memory for the events is allocated upfront, so sending doesn't allocate. The outputs lock the
+/// queue just to push the events, and `drain` never waits for them: when an output is sending at the
+/// same time, the cycle is skipped and the events are drained by the next one, so the callback is
+/// never blocked. Once the queue is full, the events sent are dropped until some are drained.
+#[derive(Clone)]
+pub struct OutputQueue {
+  state: Arc<Mutex<QueueState>>,
+}
+
+struct QueueState {
+  events: BinaryHeap<QueuedEvent>,
+  capacity: usize,
+  next_sequence: u64,
+}
+
+struct QueuedEvent {
+  timestamp: TimestampNanos,
+  sequence: u64,
+  ump: Ump,
+}
+
+impl QueuedEvent {
+  fn key(&self) -> (TimestampNanos, u64) {
+    (self.timestamp, self.sequence)
+  }
+}
+
+impl PartialEq for QueuedEvent {
+  fn eq(&self, other: &Self) -> bool {
+    self.key() == other.key()
+  }
+}
+
+impl Eq for QueuedEvent {}
+
+impl PartialOrd for QueuedEvent {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for QueuedEvent {
+  /// Reversed, so the heap gives the earliest event first
+  fn cmp(&self, other: &Self) -> Ordering {
+    other.key().cmp(&self.key())
+  }
+}
+
+impl OutputSink for Mutex<QueueState> {
+  fn send(&self, timestamp: TimestampNanos, ump: &[u32]) {
+    let mut state = self.lock();
+    if state.events.len() < state.capacity {
+      let sequence = state.next_sequence;
+      state.next_sequence += 1;
+      state.events.push(QueuedEvent {
+        timestamp,
+        sequence,
+        ump: Ump::from_slice(ump),
+      });
+    }
+  }
+
+  fn connected_destinations(&self) -> Vec<DestinationId> {
+    Vec::new()
+  }
+}
+
+impl Default for OutputQueue {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl OutputQueue {
+  pub fn new() -> Self {
+    Self::with_capacity(DEFAULT_CAPACITY)
+  }
+
+  /// Creates a queue for up to `capacity` events pending.
+  pub fn with_capacity(capacity: usize) -> Self {
+    Self {
+      state: Arc::new(Mutex::new(QueueState {
+        events: BinaryHeap::with_capacity(capacity),
+        capacity,
+        next_sequence: 0,
+      })),
+    }
+  }
+
+  /// Creates an output that puts the data sent through it in this queue.
+  pub fn output<N>(&self, name: N) -> Output
+  where
+    N: Into<String>,
+  {
+    Output::new(name.into(), self.state.clone())
+  }
+
+  /// Calls `f` with the events due up to `until` (included), in order, and removes them from the queue.
+  ///
+  /// Nothing is drained while an output is sending, the events are left for the next call.
+  pub fn drain<F>(&self, until: TimestampNanos, mut f: F)
+  where
+    F: FnMut(TimestampNanos, &[u32]),
+  {
+    let mut state = match self.state.try_lock() {
+      Some(state) => state,
+      None => return,
+    };
+    while state
+      .events
+      .peek()
+      .map_or(false, |event| event.timestamp <= until)
+    {
+      if let Some(event) = state.events.pop() {
+        f(event.timestamp, event.ump.as_slice());
+      }
+    }
+  }
+
+  /// Timestamp of the next event due, if any.
+  pub fn next_timestamp(&self) -> Option<TimestampNanos> {
+    self.state.lock().events.peek().map(|event| event.timestamp)
+  }
+
+  pub fn capacity(&self) -> usize {
+    self.state.lock().capacity
+  }
+
+  pub fn len(&self) -> usize {
+    self.state.lock().events.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.state.lock().events.is_empty()
+  }
+
+  /// Drops all the events pending.
+  pub fn clear(&self) {
+    self.state.lock().events.clear();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+  use crate::protocol::messages::{Message, MessageType};
+
+  fn note_on(note: u8) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message: ChannelVoice1Message::NoteOn {
+          note,
+          velocity: 0x64,
+        },
+      }),
+    }
+  }
+
+  fn drain(queue: &OutputQueue, until: TimestampNanos) -> Vec<(TimestampNanos, Vec<u32>)> {
+    let mut events = Vec::new();
+    queue.drain(until, |timestamp, ump| {
+      events.push((timestamp, ump.to_vec()))
+    });
+    events
+  }
+
+  #[test]
+  fn drain_in_timestamp_order() {
+    let queue = OutputQueue::new();
+    let output = queue.output("sequencer");
+
+    output.send_at(note_on(0x40), 2000);
+    output.send_at(note_on(0x3c), 1000);
+    output.send_at(note_on(0x3e), 1000);
+    output.send(note_on(0x30));
+
+    assert_eq!(queue.next_timestamp(), Some(0));
+    assert_eq!(
+      drain(&queue, 1500),
+      vec![
+        (0, vec![0x2090_3064]),
+        (1000, vec![0x2090_3c64]),
+        (1000, vec![0x2090_3e64]),
+      ]
+    );
+    assert_eq!(queue.len(), 1);
+    assert_eq!(queue.next_timestamp(), Some(2000));
+
+    assert_eq!(drain(&queue, 2000), vec![(2000, vec![0x2090_4064])]);
+    assert!(queue.is_empty());
+  }
+
+  #[test]
+  fn sysex_split_into_packets() {
+    let queue = OutputQueue::new();
+    let output = queue.output("sysex");
+
+    output.send_sysex(&[0xf0, 1, 2, 3, 4, 5, 6, 7, 0xf7]);
+
+    assert_eq!(
+      drain(&queue, 0),
+      vec![
+        (0, vec![0x3016_0102, 0x0304_0506]),
+        (0, vec![0x3031_0700, 0x0000_0000]),
+      ]
+    );
+  }
+
+  #[test]
+  fn sysex_paced_calls_on_complete() {
+    let queue = OutputQueue::new();
+    let output = queue.output("sysex");
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    output
+      .send_sysex_paced(&[0; 20], 1_000_000, move || sender.send(()).unwrap())
+      .unwrap();
+
+    receiver
+      .recv_timeout(std::time::Duration::from_secs(5))
+      .unwrap();
+    assert_eq!(queue.len(), 4);
+  }
+
+  #[test]
+  fn drain_skipped_while_sending() {
+    let queue = OutputQueue::new();
+    let output = queue.output("sequencer");
+
+    output.send_at(note_on(0x3c), 1000);
+
+    let state = queue.state.lock();
+    assert!(drain(&queue, 1000).is_empty());
+    drop(state);
+
+    assert_eq!(drain(&queue, 1000), vec![(1000, vec![0x2090_3c64])]);
+  }
+
+  #[test]
+  fn events_beyond_the_capacity_are_dropped() {
+    let queue = OutputQueue::with_capacity(2);
+    let output = queue.output("sequencer");
+
+    output.send_at(note_on(0x3c), 3000);
+    output.send_at(note_on(0x3e), 1000);
+    output.send_at(note_on(0x40), 2000);
+
+    assert_eq!(queue.capacity(), 2);
+    assert_eq!(
+      drain(&queue, u64::MAX),
+      vec![(1000, vec![0x2090_3e64]), (3000, vec![0x2090_3c64])]
+    );
+
+    output.send_at(note_on(0x40), 2000);
+    assert_eq!(queue.len(), 1);
+  }
+
+  #[test]
+  fn clear_drops_pending_events() {
+    let queue = OutputQueue::new();
+    queue.output("sequencer").send_at(note_on(0x3c), 1000);
+
+    queue.clear();
+
+    assert!(drain(&queue, u64::MAX).is_empty());
+    assert_eq!(queue.next_timestamp(), None);
+  }
+}
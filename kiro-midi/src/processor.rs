@@ -0,0 +1,415 @@
+//! Processing graphs for the events received by the inputs, between the drivers decoding them
+//! and the handlers or the outputs receiving them.
+//!
+//! Every node of the graph is a `Processor`, receiving the events one by one and passing on as many
+//! as it wants, such as a filter, a transform, or an arpeggiator keeping the notes held. The nodes are
+//! put one after the other with a `Chain`, and the events sent to several of them with a `Split`.
+//!
+//! The graph runs in the thread receiving the events, which is a real-time one for some drivers,
+//! so nothing is allocated nor waited for while processing (as long as the nodes don't do it either).
+//! A `Swappable` node can be replaced from other threads while the events are flowing, and the node
+//! replaced is dropped by the thread swapping the next one.
+//!
+//! The nodes generating events by themselves, such as the arpeggiators running on their own clock,
+//! pass them on when the graph is advanced, which the handlers from `handler` do with every event
+//! received, so they need to be advanced periodically too when the events don't come often enough.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use parking_lot::Mutex;
+
+use crate::event::{Event, TimestampNanos};
+use crate::filter::{Filter, FilterExpr};
+use crate::input_handler::InputHandler;
+use crate::output::Output;
+use crate::transform::Transform;
+
+/// A node of a processing graph.
+pub trait Processor: Send {
+  /// Processes an event, passing the resulting ones (if any) to `output`.
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event));
+
+  /// Passes on the events generated by the node that are due by `now`, with their own timestamps.
+  fn advance(&mut self, _now: TimestampNanos, _output: &mut dyn FnMut(Event)) {}
+
+  /// Forgets the state kept from the events processed, such as the notes held.
+  fn reset(&mut self) {}
+}
+
+/// Wraps a processor, and the handler receiving the events it passes on, into a handler for an input.
+///
+/// The processor is advanced to the timestamp of every event before processing it.
+pub fn handler<P, H>(mut processor: P, handler: H) -> InputHandler
+where
+  P: Processor + 'static,
+  H: Into<InputHandler>,
+{
+  let mut handler = handler.into();
+  InputHandler::from(move |event: Event| {
+    processor.advance(event.timestamp, &mut |event| handler.call(event));
+    processor.process(event, &mut |event| handler.call(event));
+  })
+}
+
+impl Processor for Box<dyn Processor> {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    self.as_mut().process(event, output)
+  }
+
+  fn advance(&mut self, now: TimestampNanos, output: &mut dyn FnMut(Event)) {
+    self.as_mut().advance(now, output)
+  }
+
+  fn reset(&mut self) {
+    self.as_mut().reset()
+  }
+}
+
+/// Passes on the events allowed by the filter.
+impl Processor for Filter {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    if self.message(&event.message) {
+      output(event);
+    }
+  }
+}
+
+/// Passes on the events allowed by the filter expression.
+impl Processor for FilterExpr {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    if self.message(&event.message) {
+      output(event);
+    }
+  }
+}
+
+/// Passes on the transformed events, followed by their layers.
+impl Processor for Transform {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    for message in self.apply_layered(event.message) {
+      output(Event {
+        message,
+        ..event.clone()
+      });
+    }
+  }
+}
+
+/// Sends the messages of the events through the output, scheduled at their timestamps, and passes on the events.
+///
+/// The events received are delivered right away, as their timestamps are already past, while the ones generated
+/// for later (such as the echoes of a `Delay`) wait for their time in an `OutputQueue` or the OS.
+impl Processor for Output {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    self.send_at(event.message, event.timestamp);
+    output(event);
+  }
+}
+
+/// A node processing the events with a function, which receives the output to pass them on.
+pub struct FnProcessor<F>(pub F);
+
+impl<F> Processor for FnProcessor<F>
+where
+  F: FnMut(Event, &mut dyn FnMut(Event)) + Send,
+{
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    (self.0)(event, output)
+  }
+}
+
+/// Nodes processing the events one after the other, every one of them receiving the events passed on by the previous.
+#[derive(Default)]
+pub struct Chain {
+  nodes: Vec<Box<dyn Processor>>,
+}
+
+impl Chain {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  #[must_use]
+  pub fn with<P>(mut self, node: P) -> Self
+  where
+    P: Processor + 'static,
+  {
+    self.push(node);
+    self
+  }
+
+  pub fn push<P>(&mut self, node: P)
+  where
+    P: Processor + 'static,
+  {
+    self.nodes.push(Box::new(node));
+  }
+
+  fn process_nodes(nodes: &mut [Box<dyn Processor>], event: Event, output: &mut dyn FnMut(Event)) {
+    match nodes.split_first_mut() {
+      Some((node, rest)) => {
+        node.process(event, &mut |event| Self::process_nodes(rest, event, output))
+      }
+      None => output(event),
+    }
+  }
+}
+
+impl Processor for Chain {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    Self::process_nodes(&mut self.nodes, event, output)
+  }
+
+  /// The events generated by every node go through the nodes after it.
+  fn advance(&mut self, now: TimestampNanos, output: &mut dyn FnMut(Event)) {
+    let mut nodes = self.nodes.as_mut_slice();
+    while let Some((node, rest)) = nodes.split_first_mut() {
+      node.advance(now, &mut |event| Self::process_nodes(rest, event, output));
+      nodes = rest;
+    }
+  }
+
+  fn reset(&mut self) {
+    for node in self.nodes.iter_mut() {
+      node.reset();
+    }
+  }
+}
+
+/// Nodes processing every event in parallel, in the order they were added, passing on the events of all of them,
+/// such as a filter for the notes below the split point followed by the ones above.
+#[derive(Default)]
+pub struct Split {
+  branches: Vec<Box<dyn Processor>>,
+}
+
+impl Split {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  #[must_use]
+  pub fn with_branch<P>(mut self, branch: P) -> Self
+  where
+    P: Processor + 'static,
+  {
+    self.push_branch(branch);
+    self
+  }
+
+  pub fn push_branch<P>(&mut self, branch: P)
+  where
+    P: Processor + 'static,
+  {
+    self.branches.push(Box::new(branch));
+  }
+}
+
+impl Processor for Split {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    for branch in self.branches.iter_mut() {
+      branch.process(event.clone(), output);
+    }
+  }
+
+  fn advance(&mut self, now: TimestampNanos, output: &mut dyn FnMut(Event)) {
+    for branch in self.branches.iter_mut() {
+      branch.advance(now, output);
+    }
+  }
+
+  fn reset(&mut self) {
+    for branch in self.branches.iter_mut() {
+      branch.reset();
+    }
+  }
+}
+
+#[derive(Default)]
+struct Slots {
+  /// The node to swap in with the next event
+  incoming: Option<Box<dyn Processor>>,
+  /// The node swapped out, to be dropped outside of the processing thread
+  retired: Option<Box<dyn Processor>>,
+}
+
+struct Exchange {
+  pending: AtomicBool,
+  slots: Mutex<Slots>,
+}
+
+/// A node that can be replaced by another one from other threads, through its `ProcessorSwap`.
+///
+/// The new node is swapped in with the next event processed, unless the swap is still in progress,
+/// in which case it waits for the following one rather than blocking the processing thread.
+pub struct Swappable {
+  current: Box<dyn Processor>,
+  exchange: Arc<Exchange>,
+}
+
+impl Swappable {
+  pub fn new<P>(node: P) -> (Self, ProcessorSwap)
+  where
+    P: Processor + 'static,
+  {
+    let exchange = Arc::new(Exchange {
+      pending: AtomicBool::new(false),
+      slots: Mutex::new(Slots::default()),
+    });
+    let swappable = Self {
+      current: Box::new(node),
+      exchange: exchange.clone(),
+    };
+    (swappable, ProcessorSwap { exchange })
+  }
+
+  fn swap_pending(&mut self) {
+    if self.exchange.pending.load(Ordering::Acquire) {
+      if let Some(mut slots) = self.exchange.slots.try_lock() {
+        if let Some(incoming) = slots.incoming.take() {
+          slots.retired = Some(std::mem::replace(&mut self.current, incoming));
+        }
+        self.exchange.pending.store(false, Ordering::Release);
+      }
+    }
+  }
+}
+
+impl Processor for Swappable {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    self.swap_pending();
+    self.current.process(event, output)
+  }
+
+  fn advance(&mut self, now: TimestampNanos, output: &mut dyn FnMut(Event)) {
+    self.swap_pending();
+    self.current.advance(now, output)
+  }
+
+  fn reset(&mut self) {
+    self.current.reset()
+  }
+}
+
+/// Replaces the node of a `Swappable` from any thread.
+#[derive(Clone)]
+pub struct ProcessorSwap {
+  exchange: Arc<Exchange>,
+}
+
+impl ProcessorSwap {
+  /// Sets the node to process the next events with, dropping the one replaced by the previous swap.
+  pub fn swap<P>(&self, node: P)
+  where
+    P: Processor + 'static,
+  {
+    let node: Box<dyn Processor> = Box::new(node);
+    let retired = {
+      let mut slots = self.exchange.slots.lock();
+      slots.incoming = Some(node);
+      self.exchange.pending.store(true, Ordering::Release);
+      slots.retired.take()
+    };
+    drop(retired);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+  use crate::protocol::messages::{Message, MessageType};
+
+  fn note_on(channel: u8, note: u8) -> Event {
+    Event {
+      timestamp: 10,
+      endpoint: 1,
+      message: Message {
+        group: 0,
+        mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+          channel,
+          message: ChannelVoice1Message::NoteOn {
+            note,
+            velocity: 0x64,
+          },
+        }),
+      },
+    }
+  }
+
+  fn run<P: Processor>(processor: &mut P, event: Event) -> Vec<Event> {
+    let mut events = Vec::new();
+    processor.process(event, &mut |event| events.push(event));
+    events
+  }
+
+  #[test]
+  fn chain_nodes() {
+    let mut chain = Chain::new()
+      .with(Filter::new().with_channels(1, &[1]))
+      .with(Transform::new().with_transpose(12))
+      .with(FnProcessor(
+        |event: Event, output: &mut dyn FnMut(Event)| {
+          output(event.clone());
+          output(event);
+        },
+      ));
+
+    assert_eq!(run(&mut chain, note_on(0, 60)), vec![note_on(0, 72); 2]);
+    assert!(run(&mut chain, note_on(1, 60)).is_empty());
+    assert_eq!(run(&mut Chain::new(), note_on(0, 60)), vec![note_on(0, 60)]);
+  }
+
+  #[test]
+  fn split_into_branches() {
+    let mut split = Split::new()
+      .with_branch(Transform::new().with_note_range(0, 59).with_all_channels(2))
+      .with_branch(Transform::new().with_note_range(60, 127))
+      .with_branch(Transform::new().with_all_channels(3));
+
+    assert_eq!(
+      run(&mut split, note_on(0, 48)),
+      vec![note_on(1, 48), note_on(2, 48)]
+    );
+    assert_eq!(
+      run(&mut split, note_on(0, 64)),
+      vec![note_on(0, 64), note_on(2, 64)]
+    );
+  }
+
+  #[test]
+  fn swap_nodes() {
+    let (mut swappable, swap) = Swappable::new(Transform::new());
+    assert_eq!(run(&mut swappable, note_on(0, 60)), vec![note_on(0, 60)]);
+
+    swap.swap(Transform::new().with_transpose(1));
+    swap.swap(Transform::new().with_transpose(2));
+    assert_eq!(run(&mut swappable, note_on(0, 60)), vec![note_on(0, 62)]);
+
+    swap.swap(Transform::new().with_transpose(3));
+    {
+      // Not waiting for a swap in progress
+      let exchange = swappable.exchange.clone();
+      let _swapping = exchange.slots.lock();
+      assert_eq!(run(&mut swappable, note_on(0, 60)), vec![note_on(0, 62)]);
+    }
+    assert!(swappable.exchange.slots.lock().retired.is_none());
+    assert_eq!(run(&mut swappable, note_on(0, 60)), vec![note_on(0, 63)]);
+    assert!(swappable.exchange.slots.lock().retired.is_some());
+  }
+
+  #[test]
+  fn wrap_handlers() {
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let events_clone = events.clone();
+    let mut handler = handler(
+      Transform::new().with_all_channels(2),
+      move |event: Event| events_clone.lock().push(event),
+    );
+
+    handler.call(note_on(0, 60));
+
+    assert_eq!(events.lock().as_slice(), &[note_on(1, 60)]);
+  }
+}
@@ -0,0 +1,842 @@
+//! Conformance checks for the UMP decoder and encoder, and for the MIDI 1.0 translation.
+//!
+//! The checks take the messages or the raw data to verify, so they can be driven from the unit tests
+//! with the random streams generated here, and from the fuzz targets in `fuzz/` with any input.
+//! They return the first [`Violation`] found instead of panicking, to let the caller report it.
+//!
+//! The invariants checked are:
+//!
+//! - Valid messages come back the same after encoding and decoding them, in any order.
+//! - Decoding any stream of words normalizes the messages (reserved bits, lengths out of range),
+//!   so encoding a decoded message and decoding it again gives the same message.
+//! - The decoder synchronizes again after any garbage, as soon as the packet in progress completes.
+//! - The MIDI 1.0 messages come back the same after encoding and parsing them, with running status.
+//! - The MIDI 1.0 parser only produces packets that the decoder accepts.
+//! - The packets from the [`CORPUS`] decode to a single message that encodes back to the same words.
+
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::filter::Filter;
+use crate::protocol::decoder::DecoderProtocol2;
+use crate::protocol::encoder::{encode_message, Ump, MAX_UMP_WORDS};
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::flex_data::{
+  FlexData, FlexDataAddress, FlexDataMessage, FlexText, FLEX_DATA_MAX_TEXT,
+};
+use crate::protocol::messages::mixed_data_set::{
+  MixedDataSet, MixedDataSetHeader, MixedDataSetPayload, MIXED_DATA_SET_PAYLOAD_DATA,
+};
+use crate::protocol::messages::sysex7::{SysEx7, SysExStatus, SYSEX7_MAX_DATA};
+use crate::protocol::messages::sysex8::{SysEx8, SYSEX8_MAX_DATA};
+use crate::protocol::messages::system::System;
+use crate::protocol::messages::utility::Utility;
+use crate::protocol::messages::{Message, MessageType};
+use crate::protocol::midi1;
+
+/// An invariant broken by the decoder, the encoder or the MIDI 1.0 translation
+#[derive(Debug, Clone, PartialEq)]
+pub enum Violation {
+  /// The message at `index` did not come back the same after encoding and decoding the stream
+  RoundTrip {
+    index: usize,
+    expected: Option<Message>,
+    decoded: Option<Message>,
+  },
+  /// A decoded message gave a different one after encoding and decoding it again
+  Unstable {
+    message: Message,
+    decoded: Option<Message>,
+  },
+  /// The decoder did not synchronize again after completing the packet in progress
+  Desync { decoded: Option<Message> },
+  /// The message at `index` did not come back the same after encoding it into MIDI 1.0 and parsing it
+  Midi1RoundTrip {
+    index: usize,
+    expected: Option<Message>,
+    parsed: Option<Message>,
+  },
+  /// The MIDI 1.0 parser produced a packet that the decoder does not accept
+  InvalidPacket { ump: Ump },
+  /// A packet of the corpus did not decode to a single message encoding back to the same words
+  Corpus { name: &'static str },
+}
+
+impl fmt::Display for Violation {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::RoundTrip {
+        index,
+        expected,
+        decoded,
+      } => write!(
+        f,
+        "Message {} expected as {:?} but decoded as {:?}",
+        index, expected, decoded
+      ),
+      Self::Unstable { message, decoded } => write!(
+        f,
+        "Message {:?} decoded as {:?} after encoding it again",
+        message, decoded
+      ),
+      Self::Desync { decoded } => write!(
+        f,
+        "Decoder out of sync after garbage, decoded {:?}",
+        decoded
+      ),
+      Self::Midi1RoundTrip {
+        index,
+        expected,
+        parsed,
+      } => write!(
+        f,
+        "MIDI 1.0 message {} expected as {:?} but parsed as {:?}",
+        index, expected, parsed
+      ),
+      Self::InvalidPacket { ump } => write!(
+        f,
+        "MIDI 1.0 parser produced the invalid packet {:08x?}",
+        ump.as_slice()
+      ),
+      Self::Corpus { name } => write!(f, "Corpus packet '{}' does not round trip", name),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Violation {}
+
+/// A packet built by hand from the layouts in the UMP specification
+#[derive(Debug, Clone, Copy)]
+pub struct KnownPacket {
+  pub name: &'static str,
+  pub words: &'static [u32],
+}
+
+/// Packets of every message type supported, with the reserved bits cleared as the encoder does.
+pub const CORPUS: &[KnownPacket] = &[
+  KnownPacket {
+    name: "NOOP",
+    words: &[0x0000_0000],
+  },
+  KnownPacket {
+    name: "JR Clock",
+    words: &[0x0010_1234],
+  },
+  KnownPacket {
+    name: "JR Timestamp",
+    words: &[0x0320_abcd],
+  },
+  KnownPacket {
+    name: "MIDI Time Code",
+    words: &[0x10f1_3500],
+  },
+  KnownPacket {
+    name: "Song Position Pointer",
+    words: &[0x12f2_7f7f],
+  },
+  KnownPacket {
+    name: "Song Select",
+    words: &[0x10f3_0500],
+  },
+  KnownPacket {
+    name: "Tune Request",
+    words: &[0x10f6_0000],
+  },
+  KnownPacket {
+    name: "Timing Clock",
+    words: &[0x1ff8_0000],
+  },
+  KnownPacket {
+    name: "Start",
+    words: &[0x10fa_0000],
+  },
+  KnownPacket {
+    name: "Continue",
+    words: &[0x10fb_0000],
+  },
+  KnownPacket {
+    name: "Stop",
+    words: &[0x10fc_0000],
+  },
+  KnownPacket {
+    name: "Active Sensing",
+    words: &[0x10fe_0000],
+  },
+  KnownPacket {
+    name: "Reset",
+    words: &[0x10ff_0000],
+  },
+  KnownPacket {
+    name: "MIDI 1.0 Note Off",
+    words: &[0x2182_3c40],
+  },
+  KnownPacket {
+    name: "MIDI 1.0 Note On",
+    words: &[0x2090_3c64],
+  },
+  KnownPacket {
+    name: "MIDI 1.0 Poly Pressure",
+    words: &[0x20a0_3c7f],
+  },
+  KnownPacket {
+    name: "MIDI 1.0 Control Change",
+    words: &[0x2fbf_0764],
+  },
+  KnownPacket {
+    name: "MIDI 1.0 Program Change",
+    words: &[0x20c0_1200],
+  },
+  KnownPacket {
+    name: "MIDI 1.0 Channel Pressure",
+    words: &[0x20d0_3300],
+  },
+  KnownPacket {
+    name: "MIDI 1.0 Pitch Bend",
+    words: &[0x20e0_0040],
+  },
+  KnownPacket {
+    name: "SysEx7 Complete",
+    words: &[0x3004_7e7f, 0x0601_0000],
+  },
+  KnownPacket {
+    name: "SysEx7 Start",
+    words: &[0x3016_0102, 0x0304_0506],
+  },
+  KnownPacket {
+    name: "SysEx7 End",
+    words: &[0x3032_0d0e, 0x0000_0000],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Note Off",
+    words: &[0x4080_3c00, 0x8000_0000],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Note On with Pitch 7.9",
+    words: &[0x4091_3c03, 0xc000_8a00],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Poly Pressure",
+    words: &[0x40a0_3c00, 0xffff_0000],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Registered Per-Note Controller",
+    words: &[0x4000_3c01, 0x1234_5678],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Assignable Per-Note Controller",
+    words: &[0x4010_3c02, 0x8765_4321],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Per-Note Management",
+    words: &[0x40f0_3c03, 0x0000_0000],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Control Change",
+    words: &[0x40b0_0700, 0x8000_0000],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Registered Controller",
+    words: &[0x4020_0102, 0x0000_ffff],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Assignable Controller",
+    words: &[0x4030_0304, 0xffff_ffff],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Relative Registered Controller",
+    words: &[0x4040_0506, 0x0000_03e8],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Relative Assignable Controller",
+    words: &[0x4050_0708, 0xffff_fc18],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Program Change",
+    words: &[0x40c0_0000, 0x1200_0000],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Program Change with Bank",
+    words: &[0x40c0_0001, 0x1200_0105],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Channel Pressure",
+    words: &[0x40d0_0000, 0x1234_5678],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Pitch Bend",
+    words: &[0x4fef_0000, 0x8000_0000],
+  },
+  KnownPacket {
+    name: "MIDI 2.0 Per-Note Pitch Bend",
+    words: &[0x4060_3c00, 0x9000_0000],
+  },
+  KnownPacket {
+    name: "SysEx8 Complete",
+    words: &[0x5004_0580, 0xff00_0000, 0x0000_0000, 0x0000_0000],
+  },
+  KnownPacket {
+    name: "SysEx8 Continue",
+    words: &[0x502e_0101, 0x0203_0405, 0x0607_0809, 0x0a0b_0c0d],
+  },
+  KnownPacket {
+    name: "Mixed Data Set Header",
+    words: &[0x5082_0020, 0x0002_0001, 0x0041_007f, 0x0001_0002],
+  },
+  KnownPacket {
+    name: "Mixed Data Set Payload",
+    words: &[0x5092_0102, 0x0304_0506, 0x0708_090a, 0x0b0c_0d0e],
+  },
+  KnownPacket {
+    name: "Flex Data Set Tempo",
+    words: &[0xd010_0000, 0x02fa_f080, 0x0000_0000, 0x0000_0000],
+  },
+  KnownPacket {
+    name: "Flex Data Set Time Signature",
+    words: &[0xd010_0001, 0x0402_0800, 0x0000_0000, 0x0000_0000],
+  },
+  KnownPacket {
+    name: "Flex Data Set Metronome",
+    words: &[0xd010_0002, 0x1802_0000, 0x0000_0000, 0x0000_0000],
+  },
+  KnownPacket {
+    name: "Flex Data Set Key Signature",
+    words: &[0xd003_0005, 0xd300_0000, 0x0000_0000, 0x0000_0000],
+  },
+  KnownPacket {
+    name: "Flex Data Project Name",
+    words: &[0xd050_0101, 0x6b69_726f, 0x2073_7475, 0x6469_6f20],
+  },
+  KnownPacket {
+    name: "Flex Data Lyrics",
+    words: &[0xd0d0_0201, 0x6c61_0000, 0x0000_0000, 0x0000_0000],
+  },
+  KnownPacket {
+    name: "Flex Data Chord Name",
+    words: &[0xd000_0006, 0x1200_0000, 0x0000_0000, 0x1100_0000],
+  },
+];
+
+/// Pseudo random generator (SplitMix64) for the streams, which can be reproduced from their seed.
+#[derive(Debug, Clone)]
+pub struct Rng {
+  state: u64,
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Self {
+    Self { state: seed }
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = self.state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+  }
+
+  pub fn next_u32(&mut self) -> u32 {
+    (self.next_u64() >> 32) as u32
+  }
+
+  /// A number from 0 to `n - 1`.
+  pub fn below(&mut self, n: u32) -> u32 {
+    ((self.next_u32() as u64 * n as u64) >> 32) as u32
+  }
+
+  fn u7(&mut self) -> u8 {
+    self.below(0x80) as u8
+  }
+
+  fn u8(&mut self) -> u8 {
+    self.next_u32() as u8
+  }
+
+  fn u16(&mut self) -> u16 {
+    self.next_u32() as u16
+  }
+
+  fn bool(&mut self) -> bool {
+    self.next_u32() & 1 != 0
+  }
+
+  fn status(&mut self) -> SysExStatus {
+    match self.below(4) {
+      0 => SysExStatus::Complete,
+      1 => SysExStatus::Start,
+      2 => SysExStatus::Continue,
+      _ => SysExStatus::End,
+    }
+  }
+
+  /// Fills `data` with random bytes, keeping only the bits in `mask`.
+  fn fill(&mut self, data: &mut [u8], mask: u8) {
+    for byte in data.iter_mut() {
+      *byte = self.u8() & mask;
+    }
+  }
+}
+
+/// A valid message of any type, with every field within its range.
+pub fn random_message(rng: &mut Rng) -> Message {
+  let mtype = match rng.below(8) {
+    0 => MessageType::Utility(random_utility(rng)),
+    1 => MessageType::System(random_system(rng)),
+    2 => MessageType::ChannelVoice1(random_channel_voice1(rng)),
+    3 => MessageType::SysEx7(random_sysex7(rng)),
+    4 => MessageType::ChannelVoice(random_channel_voice(rng)),
+    5 => MessageType::SysEx8(random_sysex8(rng)),
+    6 => MessageType::MixedDataSet(random_mixed_data_set(rng)),
+    _ => MessageType::FlexData(random_flex_data(rng)),
+  };
+  Message {
+    group: rng.below(16) as u8,
+    mtype,
+  }
+}
+
+/// A stream of `len` valid messages of any type.
+pub fn random_stream(rng: &mut Rng, len: usize) -> Vec<Message> {
+  (0..len).map(|_| random_message(rng)).collect()
+}
+
+/// A message that MIDI 1.0 carries unchanged: system and channel voice messages,
+/// or a SysEx message short enough for a single packet.
+pub fn random_midi1_message(rng: &mut Rng, group: u8) -> Message {
+  let mtype = match rng.below(4) {
+    0 => MessageType::System(random_system(rng)),
+    1 => {
+      let mut data = [0u8; SYSEX7_MAX_DATA];
+      let len = rng.below(SYSEX7_MAX_DATA as u32 + 1) as usize;
+      rng.fill(&mut data, 0x7f);
+      MessageType::SysEx7(SysEx7::new(SysExStatus::Complete, &data[..len]))
+    }
+    _ => MessageType::ChannelVoice1(random_channel_voice1(rng)),
+  };
+  Message { group, mtype }
+}
+
+fn random_utility(rng: &mut Rng) -> Utility {
+  match rng.below(3) {
+    0 => Utility::Noop,
+    1 => Utility::JrClock {
+      sender_time: rng.u16(),
+    },
+    _ => Utility::JrTimestamp {
+      sender_time: rng.u16(),
+    },
+  }
+}
+
+fn random_system(rng: &mut Rng) -> System {
+  match rng.below(10) {
+    0 => System::TimeCode(rng.u7()),
+    1 => System::SongPositionPointer(rng.below(0x4000) as u16),
+    2 => System::SongSelect(rng.u7()),
+    3 => System::TuneRequest,
+    4 => System::TimingClock,
+    5 => System::Start,
+    6 => System::Continue,
+    7 => System::Stop,
+    8 => System::ActiveSensing,
+    _ => System::Reset,
+  }
+}
+
+fn random_channel_voice1(rng: &mut Rng) -> ChannelVoice1 {
+  let message = match rng.below(7) {
+    0 => ChannelVoice1Message::NoteOff {
+      note: rng.u7(),
+      velocity: rng.u7(),
+    },
+    1 => ChannelVoice1Message::NoteOn {
+      note: rng.u7(),
+      velocity: rng.u7(),
+    },
+    2 => ChannelVoice1Message::PolyPressure {
+      note: rng.u7(),
+      data: rng.u7(),
+    },
+    3 => ChannelVoice1Message::ControlChange {
+      index: rng.u7(),
+      data: rng.u7(),
+    },
+    4 => ChannelVoice1Message::ProgramChange { program: rng.u7() },
+    5 => ChannelVoice1Message::ChannelPressure { data: rng.u7() },
+    _ => ChannelVoice1Message::PitchBend {
+      data: rng.below(0x4000) as u16,
+    },
+  };
+  ChannelVoice1 {
+    channel: rng.below(16) as u8,
+    message,
+  }
+}
+
+fn random_channel_voice(rng: &mut Rng) -> ChannelVoice {
+  let message = match rng.below(17) {
+    0 => ChanelVoiceMessage::NoteOff {
+      note: rng.u7(),
+      velocity: rng.u16(),
+      attr_type: rng.u8(),
+      attr_data: rng.u16(),
+    },
+    1 => ChanelVoiceMessage::NoteOn {
+      note: rng.u7(),
+      velocity: rng.u16(),
+      attr_type: rng.u8(),
+      attr_data: rng.u16(),
+    },
+    2 => ChanelVoiceMessage::PolyPressure {
+      note: rng.u7(),
+      data: rng.next_u32(),
+    },
+    3 => ChanelVoiceMessage::RegisteredPerNoteController {
+      note: rng.u7(),
+      index: rng.u8(),
+      data: rng.next_u32(),
+    },
+    4 => ChanelVoiceMessage::AssignablePerNoteController {
+      note: rng.u7(),
+      index: rng.u8(),
+      data: rng.next_u32(),
+    },
+    5 => ChanelVoiceMessage::PerNoteManagement {
+      note: rng.u7(),
+      detach: rng.bool(),
+      reset: rng.bool(),
+    },
+    6 => ChanelVoiceMessage::ControlChange {
+      index: rng.u7(),
+      data: rng.next_u32(),
+    },
+    7 => ChanelVoiceMessage::RegisteredController {
+      bank: rng.u7(),
+      index: rng.u7(),
+      data: rng.next_u32(),
+    },
+    8 => ChanelVoiceMessage::AssignableController {
+      bank: rng.u7(),
+      index: rng.u7(),
+      data: rng.next_u32(),
+    },
+    9 => ChanelVoiceMessage::RelativeRegisteredController {
+      bank: rng.u7(),
+      index: rng.u7(),
+      data: rng.next_u32() as i32,
+    },
+    10 => ChanelVoiceMessage::RelativeAssignableController {
+      bank: rng.u7(),
+      index: rng.u7(),
+      data: rng.next_u32() as i32,
+    },
+    11 => ChanelVoiceMessage::ProgramChange {
+      program: rng.u7(),
+      bank: None,
+    },
+    12 => ChanelVoiceMessage::ProgramChange {
+      program: rng.u7(),
+      bank: Some(rng.below(0x4000) as u16),
+    },
+    13 => ChanelVoiceMessage::ChannelPressure {
+      data: rng.next_u32(),
+    },
+    14 => ChanelVoiceMessage::PitchBend {
+      data: rng.next_u32(),
+    },
+    _ => ChanelVoiceMessage::PerNotePitchBend {
+      note: rng.u7(),
+      data: rng.next_u32(),
+    },
+  };
+  ChannelVoice {
+    channel: rng.below(16) as u8,
+    message,
+  }
+}
+
+fn random_sysex7(rng: &mut Rng) -> SysEx7 {
+  let mut data = [0u8; SYSEX7_MAX_DATA];
+  let len = rng.below(SYSEX7_MAX_DATA as u32 + 1) as usize;
+  rng.fill(&mut data, 0x7f);
+  SysEx7::new(rng.status(), &data[..len])
+}
+
+fn random_sysex8(rng: &mut Rng) -> SysEx8 {
+  let mut data = [0u8; SYSEX8_MAX_DATA];
+  let len = rng.below(SYSEX8_MAX_DATA as u32 + 1) as usize;
+  rng.fill(&mut data, 0xff);
+  SysEx8::new(rng.status(), rng.u8(), &data[..len])
+}
+
+fn random_mixed_data_set(rng: &mut Rng) -> MixedDataSet {
+  let mds_id = rng.below(16) as u8;
+  if rng.bool() {
+    MixedDataSet::Header(MixedDataSetHeader {
+      mds_id,
+      valid_bytes: rng.u16(),
+      chunks: rng.u16(),
+      chunk: rng.u16(),
+      manufacturer_id: rng.u16(),
+      device_id: rng.u16(),
+      sub_id1: rng.u16(),
+      sub_id2: rng.u16(),
+    })
+  } else {
+    let mut data = [0u8; MIXED_DATA_SET_PAYLOAD_DATA];
+    rng.fill(&mut data, 0xff);
+    MixedDataSet::Payload(MixedDataSetPayload { mds_id, data })
+  }
+}
+
+fn random_flex_data(rng: &mut Rng) -> FlexData {
+  let message = match rng.below(7) {
+    0 => FlexDataMessage::SetTempo {
+      ten_nanos_per_quarter: rng.next_u32(),
+    },
+    1 => FlexDataMessage::SetTimeSignature {
+      numerator: rng.u8(),
+      denominator: rng.u8(),
+      thirty_second_notes: rng.u8(),
+    },
+    2 => FlexDataMessage::SetMetronome {
+      clocks_per_primary_click: rng.u8(),
+      bar_accents: [rng.u8(), rng.u8(), rng.u8()],
+      subdivision_clicks: [rng.u8(), rng.u8()],
+    },
+    3 => FlexDataMessage::SetKeySignature {
+      sharps_flats: rng.below(16) as i8 - 8,
+      tonic: rng.below(8) as u8,
+    },
+    4 => FlexDataMessage::MetadataText {
+      status: rng.u8(),
+      text: random_text(rng),
+    },
+    5 => FlexDataMessage::PerformanceText {
+      status: rng.u8(),
+      text: random_text(rng),
+    },
+    _ => {
+      // The banks and statuses with a structured representation would be decoded as such
+      let status_bank = match rng.u8() {
+        0x00 | 0x01 | 0x02 => 0x03,
+        status_bank => status_bank,
+      };
+      FlexDataMessage::Other {
+        status_bank,
+        status: rng.u8(),
+        data: [rng.next_u32(), rng.next_u32(), rng.next_u32()],
+      }
+    }
+  };
+  let address = if rng.bool() {
+    FlexDataAddress::Channel(rng.below(16) as u8)
+  } else {
+    FlexDataAddress::Group
+  };
+  FlexData {
+    form: rng.status(),
+    address,
+    message,
+  }
+}
+
+/// A text without zeros, as they pad the end of the text.
+fn random_text(rng: &mut Rng) -> FlexText {
+  let mut text = [0u8; FLEX_DATA_MAX_TEXT];
+  let len = rng.below(FLEX_DATA_MAX_TEXT as u32 + 1) as usize;
+  for byte in text.iter_mut() {
+    *byte = rng.below(0xff) as u8 + 1;
+  }
+  FlexText::new(&text[..len])
+}
+
+/// Decodes a stream of words with all the messages allowed.
+fn decode(decoder: &mut DecoderProtocol2, words: &[u32]) -> Vec<Message> {
+  let filter = Filter::new();
+  words
+    .iter()
+    .filter_map(|word| decoder.next(*word, &filter).ok().flatten())
+    .collect()
+}
+
+fn encode(messages: &[Message]) -> Vec<u32> {
+  let mut words = Vec::new();
+  for message in messages {
+    words.extend_from_slice(encode_message(message).as_slice());
+  }
+  words
+}
+
+/// Encodes the messages into a single stream, checking that decoding it gives them back in the same order.
+pub fn check_round_trip(messages: &[Message]) -> Result<(), Violation> {
+  let decoded = decode(&mut DecoderProtocol2::default(), &encode(messages));
+  compare(messages, &decoded).map_err(|(index, expected, decoded)| Violation::RoundTrip {
+    index,
+    expected,
+    decoded,
+  })
+}
+
+/// Decodes any stream of words, checking that the messages decoded are stable
+/// and that the decoder synchronizes again after the stream.
+pub fn check_decode(words: &[u32]) -> Result<(), Violation> {
+  let mut decoder = DecoderProtocol2::default();
+  for message in decode(&mut decoder, words) {
+    let decoded = decode(
+      &mut DecoderProtocol2::default(),
+      encode_message(&message).as_slice(),
+    );
+    if decoded.as_slice() != [message] {
+      return Err(Violation::Unstable {
+        message,
+        decoded: decoded.first().copied(),
+      });
+    }
+  }
+
+  // The packet in progress takes at most the rest of the words of the longest packet,
+  // and the NOOPs completing it or decoded after it are ignored
+  let probe = Message {
+    group: 0,
+    mtype: MessageType::System(System::Reset),
+  };
+  let mut words = [0u32; MAX_UMP_WORDS];
+  words[MAX_UMP_WORDS - 1] = encode_message(&probe).as_slice()[0];
+  let decoded = decode(&mut decoder, &words).last().copied();
+  if decoded != Some(probe) {
+    return Err(Violation::Desync { decoded });
+  }
+  Ok(())
+}
+
+/// Encodes the messages into a MIDI 1.0 byte stream with running status, checking that parsing it
+/// gives them back in the same order. All the messages should be in the same group and carried
+/// unchanged by MIDI 1.0, as the ones from [`random_midi1_message`].
+pub fn check_midi1_round_trip(group: u8, messages: &[Message]) -> Result<(), Violation> {
+  let mut encoder = midi1::Encoder::new().with_running_status(true);
+  let mut bytes = Vec::new();
+  for message in messages {
+    encoder.encode(&message.mtype, |data| bytes.extend_from_slice(data));
+  }
+
+  let mut parsed = Vec::new();
+  midi1::Parser::new(group).parse_messages(&bytes, |message| parsed.push(message));
+  compare(messages, &parsed).map_err(|(index, expected, parsed)| Violation::Midi1RoundTrip {
+    index,
+    expected,
+    parsed,
+  })
+}
+
+/// Parses any MIDI 1.0 byte stream, checking that every packet produced decodes into a message.
+pub fn check_midi1_parse(bytes: &[u8]) -> Result<(), Violation> {
+  let mut violation = None;
+  midi1::Parser::new(0).parse(bytes, |ump| {
+    let decoded = decode(&mut DecoderProtocol2::default(), ump.as_slice());
+    if decoded.len() != 1 && violation.is_none() {
+      violation = Some(Violation::InvalidPacket { ump });
+    }
+  });
+  violation.map_or(Ok(()), Err)
+}
+
+/// Checks that every packet of the [`CORPUS`] decodes into a single message that encodes back to the same words.
+pub fn check_corpus() -> Result<(), Violation> {
+  for packet in CORPUS {
+    let decoded = decode(&mut DecoderProtocol2::default(), packet.words);
+    let matches = match decoded.as_slice() {
+      [message] => encode_message(message).as_slice() == packet.words,
+      _ => false,
+    };
+    if !matches {
+      return Err(Violation::Corpus { name: packet.name });
+    }
+  }
+  Ok(())
+}
+
+/// The index of the first message that differs, with the expected and the actual ones.
+fn compare(
+  expected: &[Message],
+  actual: &[Message],
+) -> Result<(), (usize, Option<Message>, Option<Message>)> {
+  let len = expected.len().max(actual.len());
+  for index in 0..len {
+    let (expected, actual) = (expected.get(index), actual.get(index));
+    if expected != actual {
+      return Err((index, expected.copied(), actual.copied()));
+    }
+  }
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn corpus() {
+    assert_eq!(check_corpus(), Ok(()));
+  }
+
+  #[test]
+  fn random_streams_round_trip() {
+    for seed in 0..200 {
+      let mut rng = Rng::new(seed);
+      let messages = random_stream(&mut rng, 64);
+      assert_eq!(check_round_trip(&messages), Ok(()), "Seed {}", seed);
+    }
+  }
+
+  #[test]
+  fn random_words_decode() {
+    for seed in 0..200 {
+      let mut rng = Rng::new(seed);
+      let words = (0..64).map(|_| rng.next_u32()).collect::<Vec<_>>();
+      assert_eq!(check_decode(&words), Ok(()), "Seed {}", seed);
+    }
+    assert_eq!(check_decode(&[0x5000_0000, 0xd000_0000]), Ok(()));
+  }
+
+  #[test]
+  fn random_midi1_streams() {
+    for seed in 0..200 {
+      let mut rng = Rng::new(seed);
+      let group = rng.below(16) as u8;
+      let messages = (0..64)
+        .map(|_| random_midi1_message(&mut rng, group))
+        .collect::<Vec<_>>();
+      assert_eq!(
+        check_midi1_round_trip(group, &messages),
+        Ok(()),
+        "Seed {}",
+        seed
+      );
+
+      let bytes = (0..256).map(|_| rng.u8()).collect::<Vec<_>>();
+      assert_eq!(check_midi1_parse(&bytes), Ok(()), "Seed {}", seed);
+    }
+  }
+
+  #[test]
+  fn violations_are_reported() {
+    let message = Message {
+      group: 0,
+      mtype: MessageType::System(System::TimeCode(0x80)),
+    };
+    assert_eq!(
+      check_round_trip(&[message]),
+      Err(Violation::RoundTrip {
+        index: 0,
+        expected: Some(message),
+        decoded: Some(Message {
+          group: 0,
+          mtype: MessageType::System(System::TimeCode(0x00)),
+        }),
+      })
+    );
+  }
+}
@@ -0,0 +1,158 @@
+//! Pairing of the MIDI 1.0 control changes with 14 bits of resolution.
+//!
+//! The controllers from 0 to 31 can be sent with a MSB, followed by an optional LSB sent as the
+//! controller 32 positions above. A new MSB resets the LSB, so every one of them is a new value.
+
+use alloc::boxed::Box;
+use alloc::vec;
+
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::{Message, MessageType};
+use crate::protocol::parameters::scale_up_14;
+
+/// Bank select and data entry have their own pairing with the program changes and the parameters
+const UNPAIRED: [u8; 2] = [0, 6];
+
+/// Offset from the MSB controller to the LSB one
+const LSB_OFFSET: u8 = 32;
+
+const NO_MSB: u8 = 0xff;
+
+/// Whether a controller from 0 to 31 is the MSB of a controller with 14 bits of resolution.
+pub fn is_paired_msb(index: u8) -> bool {
+  index < LSB_OFFSET && !UNPAIRED.contains(&index)
+}
+
+/// Combines the MSB and LSB control changes of the same controller into a single MIDI 2.0
+/// control change, with the 14 bits value scaled up to 32 bits. The state is kept per group and channel.
+///
+/// The rest of the messages, as well as a LSB received without a MSB before it, are returned as they are.
+pub struct ControllerPairing {
+  msbs: Box<[u8]>,
+}
+
+impl ControllerPairing {
+  pub fn new() -> Self {
+    Self {
+      msbs: vec![NO_MSB; 16 * 16 * LSB_OFFSET as usize].into_boxed_slice(),
+    }
+  }
+
+  pub fn process(&mut self, message: Message) -> Message {
+    let (channel, index, data) = match message.mtype {
+      MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::ControlChange { index, data },
+      }) => (channel & 0x0f, index, data),
+      _ => return message,
+    };
+
+    let slot = |msb_index: u8| {
+      (((message.group & 0x0f) as usize * 16 + channel as usize) * LSB_OFFSET as usize)
+        + msb_index as usize
+    };
+    let (msb_index, value) = if is_paired_msb(index) {
+      self.msbs[slot(index)] = data;
+      (index, (data as u16) << 7)
+    } else if index >= LSB_OFFSET && is_paired_msb(index - LSB_OFFSET) {
+      let msb_index = index - LSB_OFFSET;
+      match self.msbs[slot(msb_index)] {
+        NO_MSB => return message,
+        msb => (msb_index, (msb as u16) << 7 | data as u16),
+      }
+    } else {
+      return message;
+    };
+
+    Message {
+      group: message.group,
+      mtype: MessageType::ChannelVoice(ChannelVoice {
+        channel,
+        message: ChanelVoiceMessage::ControlChange {
+          index: msb_index,
+          data: scale_up_14(value),
+        },
+      }),
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.msbs.fill(NO_MSB);
+  }
+}
+
+impl Default for ControllerPairing {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn control_change(group: u8, index: u8, data: u8) -> Message {
+    Message {
+      group,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 2,
+        message: ChannelVoice1Message::ControlChange { index, data },
+      }),
+    }
+  }
+
+  fn control_change_14(group: u8, index: u8, value: u16) -> Message {
+    Message {
+      group,
+      mtype: MessageType::ChannelVoice(ChannelVoice {
+        channel: 2,
+        message: ChanelVoiceMessage::ControlChange {
+          index,
+          data: scale_up_14(value),
+        },
+      }),
+    }
+  }
+
+  #[test]
+  fn msb_and_lsb_are_combined() {
+    let mut pairing = ControllerPairing::new();
+
+    assert_eq!(
+      pairing.process(control_change(0, 7, 0x40)),
+      control_change_14(0, 7, 0x2000)
+    );
+    assert_eq!(
+      pairing.process(control_change(0, 39, 0x12)),
+      control_change_14(0, 7, 0x2012)
+    );
+    assert_eq!(
+      pairing.process(control_change(0, 39, 0x13)),
+      control_change_14(0, 7, 0x2013)
+    );
+  }
+
+  #[test]
+  fn lsb_without_msb_is_kept() {
+    let mut pairing = ControllerPairing::new();
+    pairing.process(control_change(1, 7, 0x40));
+
+    assert_eq!(
+      pairing.process(control_change(0, 39, 0x12)),
+      control_change(0, 39, 0x12)
+    );
+  }
+
+  #[test]
+  fn unpaired_controllers_are_kept() {
+    let mut pairing = ControllerPairing::new();
+
+    for (index, data) in [(0, 1), (32, 2), (6, 3), (38, 4), (64, 127)] {
+      assert_eq!(
+        pairing.process(control_change(0, index, data)),
+        control_change(0, index, data)
+      );
+    }
+  }
+}
@@ -1,33 +1,101 @@
-use thiserror::Error;
+use alloc::boxed::Box;
+use core::fmt;
 
-use crate::filter::Filter;
+use crate::filter::{Filter, FilterExpr};
 use crate::protocol::messages::channel_voice::ChannelVoice;
+use crate::protocol::messages::channel_voice1::ChannelVoice1;
+use crate::protocol::messages::flex_data::{FlexData, FlexDataAddress};
+use crate::protocol::messages::mixed_data_set::MixedDataSet;
+use crate::protocol::messages::sysex7::{SysEx7, SysExStatus, SYSEX7_MAX_DATA};
+use crate::protocol::messages::sysex8::{SysEx8, SYSEX8_MAX_DATA};
+use crate::protocol::messages::system::System;
 use crate::protocol::messages::utility::Utility;
 use crate::protocol::messages::{Message, MessageType};
 use crate::protocol::Decode;
 
-#[derive(Debug, Error)]
+#[derive(Debug)]
 pub enum Error {
-  #[error("Found reserved encoding")]
   Reserved,
 }
 
+impl fmt::Display for Error {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Reserved => write!(f, "Found reserved encoding"),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
+/// A packet that could not be decoded, reported while the decoder carries on with the next ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeError {
+  /// A packet with a reserved message type, skipped whole as its length is known
+  Reserved { mtype: u8 },
+  /// A word that can not start a packet, skipped alone until finding one that can
+  Malformed { word: u32 },
+}
+
+impl fmt::Display for DecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Reserved { mtype } => {
+        write!(f, "Skipped packet with reserved message type {:#x}", mtype)
+      }
+      Self::Malformed { word } => write!(f, "Skipped malformed word {:#010x}", word),
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DecodeError {}
+
+/// Called with the packets skipped by a decoder, as a diagnostic.
+///
+/// It is called from the thread decoding the data, so it should not block.
+pub type DecodeErrorHandler = Box<dyn FnMut(DecodeError) + Send + 'static>;
+
 #[derive(Default)]
 pub struct DecoderProtocol2 {
   ump: [u32; 4],
   index: usize,
   len: usize,
+  /// Groups whose SysEx7 message was blocked by its first packet, to drop the rest of its packets
+  sysex7_blocked: u16,
+  error_handler: Option<DecodeErrorHandler>,
 }
 
 impl DecoderProtocol2 {
+  /// Reports the packets skipped while decoding to `handler`.
+  #[must_use]
+  pub fn with_error_handler(mut self, handler: DecodeErrorHandler) -> Self {
+    self.error_handler = Some(handler);
+    self
+  }
+
+  /// Consumes the next word, returning the message when a packet is completed.
+  ///
+  /// The words that can not start a packet (such as a SysEx with more data than fits in the packet)
+  /// are skipped one by one, so after some garbage the decoder synchronizes again with the first
+  /// plausible packet instead of misframing the rest. The packets with an undefined status are still
+  /// skipped whole, as their length is known from the message type.
   pub fn next(&mut self, data: u32, filter: &Filter) -> Result<Option<Message>, Error> {
     if self.index == 0 {
+      if !Self::is_plausible_start(data) {
+        self.report(DecodeError::Malformed { word: data });
+        return Ok(None);
+      }
       self.init(data);
     }
     self.push(data);
 
     let next_message = if self.is_complete() {
       let (mtype, group) = self.extract_mtype_and_group();
+      if matches!(mtype, 0x06..=0x0c | 0x0e | 0x0f) {
+        self.report(DecodeError::Reserved { mtype });
+      }
       let message = if filter.mtype(mtype) && filter.group(group) {
         self.decode(mtype, group, filter)
       } else {
@@ -42,6 +110,67 @@ impl DecoderProtocol2 {
     Ok(next_message)
   }
 
+  /// Like `next`, but with filters combined in an expression, which are applied to the decoded messages.
+  ///
+  /// As with a single filter, the expression decides on the first packet of the SysEx7 messages,
+  /// and the rest of their packets follow it.
+  pub fn next_matching(
+    &mut self,
+    data: u32,
+    filter: &FilterExpr,
+  ) -> Result<Option<Message>, Error> {
+    const ALL: Filter = Filter::new();
+    match filter {
+      FilterExpr::Filter(filter) => self.next(data, filter),
+      expr => Ok(match self.next(data, &ALL)? {
+        Some(message) if self.matches(expr, &message) => Some(message),
+        _ => None,
+      }),
+    }
+  }
+
+  /// Checks a message decoded with all the messages allowed, blocking the rest of the packets of the
+  /// SysEx7 messages which first packet is not accepted, as `next` drops them then.
+  fn matches(&mut self, expr: &FilterExpr, message: &Message) -> bool {
+    match &message.mtype {
+      MessageType::SysEx7(packet) => match packet.status {
+        SysExStatus::Complete => expr.message(message),
+        SysExStatus::Start => {
+          let accepted = expr.message(message);
+          if !accepted {
+            self.sysex7_blocked |= 1 << (message.group & 0x0f);
+          }
+          accepted
+        }
+        // Their start was accepted, otherwise `next` would have dropped them
+        SysExStatus::Continue | SysExStatus::End => true,
+      },
+      _ => expr.message(message),
+    }
+  }
+
+  /// Whether a word can be the first one of a packet. The data in the rest of the words
+  /// can be anything, so the framing can only be checked with the fields of the first word.
+  fn is_plausible_start(word: u32) -> bool {
+    let mtype = (word >> 28) & 0x0f;
+    let status = ((word >> 20) & 0x0f) as u8;
+    let len = ((word >> 16) & 0x0f) as usize;
+    match mtype {
+      0x03 => !SysEx7::is_valid_status(status) || len <= SYSEX7_MAX_DATA,
+      // The stream id counts as data
+      0x05 => !SysEx8::is_valid_status(status) || (1..=SYSEX8_MAX_DATA + 1).contains(&len),
+      _ => true,
+    }
+  }
+
+  fn report(&mut self, error: DecodeError) {
+    if let Some(handler) = self.error_handler.as_mut() {
+      handler(error);
+    }
+  }
+
+  /// The length of the packet only depends on its message type, including the reserved ones,
+  /// so the packets that can not be decoded are skipped whole.
   fn init(&mut self, data: u32) {
     let mtype = (data >> 28) & 0x0f;
     self.len = match mtype {
@@ -51,7 +180,16 @@ impl DecoderProtocol2 {
       0x03 => 2,
       0x04 => 2,
       0x05 => 4,
-      _ => 1,
+      0x06 => 1,
+      0x07 => 1,
+      0x08 => 2,
+      0x09 => 2,
+      0x0a => 2,
+      0x0b => 3,
+      0x0c => 3,
+      0x0d => 4,
+      0x0e => 4,
+      _ => 4,
     };
   }
 
@@ -72,19 +210,105 @@ impl DecoderProtocol2 {
 
   fn decode(&mut self, mtype: u8, group: u8, filter: &Filter) -> Option<Message> {
     match mtype {
-      0x00 => Some(Message {
-        group,
-        mtype: MessageType::Utility(Utility::decode(&self.ump[0..1])),
-      }),
+      0x00 => {
+        let status = ((self.ump[0] >> 20) & 0x0f) as u8;
+        Utility::is_valid_status(status).then(|| Message {
+          group,
+          mtype: MessageType::Utility(Utility::decode(&self.ump[0..1])),
+        })
+      }
+      0x01 => {
+        let status = ((self.ump[0] >> 16) & 0xff) as u8;
+        System::is_valid_status(status)
+          .then(|| System::decode(&self.ump[0..1]))
+          .filter(|system| filter.system(system))
+          .map(|system| Message {
+            group,
+            mtype: MessageType::System(system),
+          })
+      }
+      0x02 => {
+        let status = ((self.ump[0] >> 20) & 0x0f) as u8;
+        if ChannelVoice1::is_valid_status(status) {
+          let channel_voice = ChannelVoice1::decode(&self.ump[0..1]);
+          filter
+            .channel_voice1(group, &channel_voice)
+            .then(|| Message {
+              group,
+              mtype: MessageType::ChannelVoice1(channel_voice),
+            })
+        } else {
+          None
+        }
+      }
+      0x03 => {
+        let status = ((self.ump[0] >> 20) & 0x0f) as u8;
+        if !SysEx7::is_valid_status(status) {
+          return None;
+        }
+        let packet = SysEx7::decode(&self.ump[0..2]);
+        let mask = 1u16 << group;
+        let accepted = match packet.status {
+          SysExStatus::Complete | SysExStatus::Start => filter.sysex7(&packet),
+          SysExStatus::Continue | SysExStatus::End => self.sysex7_blocked & mask == 0,
+        };
+        match packet.status {
+          SysExStatus::Start if !accepted => self.sysex7_blocked |= mask,
+          SysExStatus::Continue => {}
+          _ => self.sysex7_blocked &= !mask,
+        }
+        accepted.then(|| Message {
+          group,
+          mtype: MessageType::SysEx7(packet),
+        })
+      }
       0x04 => {
+        let status = ((self.ump[0] >> 20) & 0x0f) as u8;
+        if !ChannelVoice::is_valid_status(status) {
+          return None;
+        }
         let channel_voice = ChannelVoice::decode(&self.ump[0..2]);
         filter
-          .channel(group, channel_voice.channel)
+          .channel_voice(group, &channel_voice)
           .then(|| Message {
             group,
             mtype: MessageType::ChannelVoice(channel_voice),
           })
       }
+      0x05 => {
+        let status = ((self.ump[0] >> 20) & 0x0f) as u8;
+        if SysEx8::is_valid_status(status) {
+          Some(Message {
+            group,
+            mtype: MessageType::SysEx8(SysEx8::decode(&self.ump[0..4])),
+          })
+        } else if MixedDataSet::is_valid_status(status) {
+          Some(Message {
+            group,
+            mtype: MessageType::MixedDataSet(MixedDataSet::decode(&self.ump[0..4])),
+          })
+        } else {
+          None
+        }
+      }
+      0x0d => {
+        let address = ((self.ump[0] >> 20) & 0x03) as u8;
+        if FlexData::is_valid_address(address) {
+          let flex_data = FlexData::decode(&self.ump[0..4]);
+          let channel = match flex_data.address {
+            FlexDataAddress::Channel(channel) => Some(channel),
+            FlexDataAddress::Group => None,
+          };
+          channel
+            .map_or(true, |channel| filter.channel(group, channel))
+            .then(|| Message {
+              group,
+              mtype: MessageType::FlexData(flex_data),
+            })
+        } else {
+          None
+        }
+      }
       _ => None,
     }
   }
@@ -97,9 +321,14 @@ impl DecoderProtocol2 {
 
 #[cfg(test)]
 mod tests {
+  use std::sync::{Arc, Mutex};
+
   use super::*;
   use crate::protocol::decoder::DecoderProtocol2;
   use crate::protocol::messages::channel_voice::ChanelVoiceMessage;
+  use crate::protocol::messages::channel_voice1::ChannelVoice1Message;
+  use crate::protocol::messages::mixed_data_set::MixedDataSetHeader;
+  use crate::protocol::messages::sysex7::SysExId;
 
   #[test]
   fn first_word_does_not_emit() {
@@ -171,4 +400,421 @@ mod tests {
       result
     );
   }
+
+  #[test]
+  fn system_message_is_emitted() {
+    let filter = Filter::new();
+    let mut decoder = DecoderProtocol2::default();
+
+    let result = decoder.next(0x12f80000, &filter);
+    assert!(
+      matches!(&result, Ok(Some(message)) if message == &Message {
+        group: 2,
+        mtype: MessageType::System(System::TimingClock),
+      }),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn undefined_system_message_is_ignored() {
+    let filter = Filter::new();
+    let mut decoder = DecoderProtocol2::default();
+
+    let result = decoder.next(0x12f90000, &filter);
+    assert!(
+      matches!(result, Ok(None)),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn undefined_utility_message_is_ignored() {
+    let filter = Filter::new();
+    let mut decoder = DecoderProtocol2::default();
+
+    let result = decoder.next(0x0050_0000, &filter);
+    assert!(
+      matches!(result, Ok(None)),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn sysex7_packet_is_emitted() {
+    let filter = Filter::new();
+    let mut decoder = DecoderProtocol2::default();
+
+    decoder.next(0x3512_0102, &filter).unwrap();
+    let result = decoder.next(0x0000_0000, &filter);
+    assert!(
+      matches!(&result, Ok(Some(message)) if message == &Message {
+        group: 5,
+        mtype: MessageType::SysEx7(SysEx7::new(SysExStatus::Start, &[1, 2])),
+      }),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn midi1_channel_voice_message_is_emitted() {
+    let filter = Filter::new();
+    let mut decoder = DecoderProtocol2::default();
+
+    let result = decoder.next(0x23953c64, &filter);
+    assert!(
+      matches!(&result, Ok(Some(message)) if message == &Message {
+        group: 3,
+        mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+          channel: 5,
+          message: ChannelVoice1Message::NoteOn {
+            note: 0x3c,
+            velocity: 0x64,
+          }
+        })
+      }),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn midi1_channel_voice_message_is_filtered_by_channel() {
+    let filter = Filter::new().with_channels(4, &[1]);
+    let mut decoder = DecoderProtocol2::default();
+
+    let result = decoder.next(0x23953c64, &filter);
+    assert!(
+      matches!(result, Ok(None)),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn sysex_messages_are_filtered_by_id() {
+    let filter = Filter::new().with_sysex_ids(&[SysExId::NonRealTime(0x0d)]);
+    let mut decoder = DecoderProtocol2::default();
+    let mut next = |words: [u32; 2]| {
+      decoder.next(words[0], &filter).unwrap();
+      decoder.next(words[1], &filter).unwrap()
+    };
+
+    // A Roland message in group 1 and a MIDI-CI one in group 2, interleaved
+    assert_eq!(next([0x3016_4110, 0x4212_0000]), None);
+    assert!(next([0x3116_7e7f, 0x0d70_0200]).is_some());
+    assert_eq!(next([0x3026_0102, 0x0304_0506]), None);
+    assert!(next([0x3126_0102, 0x0304_0506]).is_some());
+    assert_eq!(next([0x3031_0700, 0x0000_0000]), None);
+    assert!(next([0x3131_0700, 0x0000_0000]).is_some());
+    assert!(next([0x3003_7e7f, 0x0d00_0000]).is_some());
+  }
+
+  #[test]
+  fn messages_are_filtered_by_expression() {
+    let ch1 = Filter::new().with_channels(1, &[1]);
+    let ch16 = Filter::new().with_channels(1, &[16]);
+    let expr = ch1.or(ch16);
+    let mut decoder = DecoderProtocol2::default();
+
+    assert!(matches!(
+      decoder.next_matching(0x2090_3c64, &expr),
+      Ok(Some(_))
+    ));
+    assert!(matches!(
+      decoder.next_matching(0x209f_3c64, &expr),
+      Ok(Some(_))
+    ));
+    assert!(matches!(
+      decoder.next_matching(0x2095_3c64, &expr),
+      Ok(None)
+    ));
+    assert!(matches!(
+      decoder.next_matching(0x2095_3c64, &(!expr)),
+      Ok(Some(_))
+    ));
+  }
+
+  /// The statuses of the SysEx7 packets that pass the expression, for a Roland message in group 1
+  /// and a MIDI-CI one in group 2 split into three packets and interleaved.
+  fn sysex_packets_matching(expr: &FilterExpr) -> Vec<(u8, SysExStatus)> {
+    let mut decoder = DecoderProtocol2::default();
+    let words = [
+      0x3016_4110,
+      0x4212_0000,
+      0x3116_7e7f,
+      0x0d70_0200,
+      0x3026_0102,
+      0x0304_0506,
+      0x3126_0102,
+      0x0304_0506,
+      0x3031_0700,
+      0x0000_0000,
+      0x3131_0700,
+      0x0000_0000,
+    ];
+    words
+      .iter()
+      .filter_map(|word| decoder.next_matching(*word, expr).unwrap())
+      .map(|message| match message.mtype {
+        MessageType::SysEx7(packet) => (message.group, packet.status),
+        _ => panic!("not a SysEx7 packet"),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn sysex_packets_follow_the_first_one_with_expressions() {
+    let roland = Filter::new().with_sysex_ids(&[SysExId::Manufacturer(0x41)]);
+    let midi_ci = Filter::new().with_sysex_ids(&[SysExId::NonRealTime(0x0d)]);
+    let notes = Filter::new().with_mtypes(&[0x2]);
+    let all_packets = |group| {
+      vec![
+        (group, SysExStatus::Start),
+        (group, SysExStatus::Continue),
+        (group, SysExStatus::End),
+      ]
+    };
+
+    assert_eq!(
+      sysex_packets_matching(&roland.and(Filter::new().with_groups(&[1, 2]))),
+      all_packets(0)
+    );
+    assert_eq!(sysex_packets_matching(&notes.or(midi_ci)), all_packets(1));
+    assert_eq!(sysex_packets_matching(&!midi_ci), all_packets(0));
+    assert_eq!(sysex_packets_matching(&!roland.or(notes)), all_packets(1));
+  }
+
+  #[test]
+  fn notes_are_filtered_by_range() {
+    let filter = Filter::new().with_note_range(1, 10, 36..=51);
+    let mut decoder = DecoderProtocol2::default();
+
+    assert!(matches!(decoder.next(0x2099_2464, &filter), Ok(Some(_))));
+    assert!(matches!(decoder.next(0x2099_3464, &filter), Ok(None)));
+    assert!(matches!(decoder.next(0x2089_3440, &filter), Ok(None)));
+    // Other channels and the messages without a note are not affected
+    assert!(matches!(decoder.next(0x2090_3464, &filter), Ok(Some(_))));
+    assert!(matches!(decoder.next(0x20b9_0764, &filter), Ok(Some(_))));
+
+    decoder.next(0x4069_3400, &filter).unwrap();
+    let result = decoder.next(0x8000_0000, &filter);
+    assert!(
+      matches!(result, Ok(None)),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn note_ons_are_filtered_by_velocity() {
+    let filter = Filter::new().with_velocity_range(0x2000..=0xffff);
+    let mut decoder = DecoderProtocol2::default();
+
+    // 0x10 scales up to 0x2000
+    assert!(matches!(decoder.next(0x2090_3c10, &filter), Ok(Some(_))));
+    assert!(matches!(decoder.next(0x2090_3c0f, &filter), Ok(None)));
+    // Note offs, including the note ons with velocity 0, are not affected
+    assert!(matches!(decoder.next(0x2090_3c00, &filter), Ok(Some(_))));
+    assert!(matches!(decoder.next(0x2080_3c01, &filter), Ok(Some(_))));
+
+    decoder.next(0x4090_3c00, &filter).unwrap();
+    assert!(matches!(decoder.next(0x1fff_0000, &filter), Ok(None)));
+    decoder.next(0x4090_3c00, &filter).unwrap();
+    assert!(matches!(decoder.next(0x2000_0000, &filter), Ok(Some(_))));
+  }
+
+  #[test]
+  fn control_changes_are_filtered_by_controller() {
+    let filter = Filter::new().without_controllers(120..=127);
+    let mut decoder = DecoderProtocol2::default();
+
+    assert!(matches!(decoder.next(0x20b0_0764, &filter), Ok(Some(_))));
+    assert!(matches!(decoder.next(0x20b0_7b00, &filter), Ok(None)));
+
+    let filter = Filter::new().with_controllers([1, 11]);
+    assert!(matches!(decoder.next(0x20b0_0b64, &filter), Ok(Some(_))));
+    assert!(matches!(decoder.next(0x20b0_0764, &filter), Ok(None)));
+    decoder.next(0x40b0_0700, &filter).unwrap();
+    assert!(matches!(decoder.next(0x8000_0000, &filter), Ok(None)));
+    decoder.next(0x40b0_0100, &filter).unwrap();
+    assert!(matches!(decoder.next(0x8000_0000, &filter), Ok(Some(_))));
+  }
+
+  #[test]
+  fn program_changes_are_filtered_by_program() {
+    let filter = Filter::new().with_programs(0..=7);
+    let mut decoder = DecoderProtocol2::default();
+
+    assert!(matches!(decoder.next(0x20c0_0500, &filter), Ok(Some(_))));
+    assert!(matches!(decoder.next(0x20c0_0800, &filter), Ok(None)));
+    decoder.next(0x40c0_0001, &filter).unwrap();
+    assert!(matches!(decoder.next(0x0700_0102, &filter), Ok(Some(_))));
+    decoder.next(0x40c0_0000, &filter).unwrap();
+    assert!(matches!(decoder.next(0x7f00_0000, &filter), Ok(None)));
+  }
+
+  #[test]
+  fn sysex8_packet_is_emitted() {
+    let filter = Filter::new();
+    let mut decoder = DecoderProtocol2::default();
+
+    for word in [0x5114_0380, 0x81ff_0000, 0x0000_0000] {
+      assert!(matches!(decoder.next(word, &filter), Ok(None)));
+    }
+    let result = decoder.next(0x0000_0000, &filter);
+    assert!(
+      matches!(&result, Ok(Some(message)) if message == &Message {
+        group: 1,
+        mtype: MessageType::SysEx8(SysEx8::new(SysExStatus::Start, 3, &[0x80, 0x81, 0xff])),
+      }),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn mixed_data_set_header_is_emitted() {
+    let filter = Filter::new();
+    let mut decoder = DecoderProtocol2::default();
+
+    decoder.next(0x5081_0020, &filter).unwrap();
+    decoder.next(0x0001_0001, &filter).unwrap();
+    decoder.next(0x0021_0000, &filter).unwrap();
+    let result = decoder.next(0x0003_0004, &filter);
+    assert!(
+      matches!(&result, Ok(Some(message)) if message == &Message {
+        group: 0,
+        mtype: MessageType::MixedDataSet(MixedDataSet::Header(MixedDataSetHeader {
+          mds_id: 1,
+          valid_bytes: 0x20,
+          chunks: 1,
+          chunk: 1,
+          manufacturer_id: 0x21,
+          device_id: 0,
+          sub_id1: 3,
+          sub_id2: 4,
+        })),
+      }),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn undefined_data_128_status_is_ignored() {
+    let filter = Filter::new();
+    let mut decoder = DecoderProtocol2::default();
+
+    for word in [0x5040_0000, 0, 0] {
+      decoder.next(word, &filter).unwrap();
+    }
+    let result = decoder.next(0, &filter);
+    assert!(
+      matches!(result, Ok(None)),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn reserved_packets_are_skipped_whole() {
+    let filter = Filter::new();
+    let mut decoder = DecoderProtocol2::default();
+
+    for packet in [
+      vec![0x6090_3c00],
+      vec![0x8090_3c00, 0x2090_3c00],
+      vec![0xb090_3c00, 0x2090_3c00, 0x2090_3c00],
+      vec![0xf090_3c00, 0x2090_3c00, 0x2090_3c00, 0x2090_3c00],
+    ] {
+      for word in packet {
+        let result = decoder.next(word, &filter);
+        assert!(
+          matches!(result, Ok(None)),
+          "Unexpected result: {:?}",
+          result
+        );
+      }
+    }
+
+    let result = decoder.next(0x2090_3c40, &filter);
+    assert!(
+      matches!(&result, Ok(Some(message)) if matches!(message.mtype, MessageType::ChannelVoice1(_))),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn flex_data_is_filtered_by_channel() {
+    let filter = Filter::new().with_channels(1, &[1]);
+    let mut decoder = DecoderProtocol2::default();
+
+    for word in [0xd002_0000, 0x02fa_f080, 0, 0] {
+      assert!(matches!(decoder.next(word, &filter), Ok(None)));
+    }
+
+    for word in [0xd010_0000, 0x02fa_f080, 0] {
+      decoder.next(word, &filter).unwrap();
+    }
+    let result = decoder.next(0, &filter);
+    assert!(
+      matches!(&result, Ok(Some(message)) if matches!(message.mtype, MessageType::FlexData(_))),
+      "Unexpected result: {:?}",
+      result
+    );
+  }
+
+  #[test]
+  fn garbage_is_skipped_until_a_plausible_packet() {
+    let filter = Filter::new();
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let errors_clone = errors.clone();
+    let mut decoder = DecoderProtocol2::default().with_error_handler(Box::new(move |error| {
+      errors_clone.lock().unwrap().push(error)
+    }));
+
+    // A SysEx7 and a SysEx8 with invalid lengths, then an undefined channel voice status
+    let mut messages = Vec::new();
+    for word in [
+      0x3009_0000,
+      0x5010_0000,
+      0x4070_0000,
+      0x0000_0001,
+      0x20903c64,
+      0xf000_0000,
+      1,
+      2,
+      3,
+    ] {
+      messages.extend(decoder.next(word, &filter).unwrap());
+    }
+
+    assert_eq!(
+      messages,
+      vec![Message {
+        group: 0,
+        mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+          channel: 0,
+          message: ChannelVoice1Message::NoteOn {
+            note: 0x3c,
+            velocity: 0x64,
+          },
+        }),
+      }]
+    );
+    assert_eq!(
+      errors.lock().unwrap().as_slice(),
+      &[
+        DecodeError::Malformed { word: 0x3009_0000 },
+        DecodeError::Malformed { word: 0x5010_0000 },
+        DecodeError::Reserved { mtype: 0x0f },
+      ]
+    );
+  }
 }
@@ -0,0 +1,91 @@
+//! Suppression of the duplicated messages, received when the same device is reachable through
+//! more than one source, such as when bridging ALSA and JACK, or through two overlapping source matches.
+
+use crate::event::TimestampNanos;
+use crate::protocol::messages::Message;
+
+/// Messages remembered to look for duplicates, enough for the short bursts of both copies interleaved
+const RECENT_MESSAGES: usize = 16;
+
+/// Drops the messages identical to one of the last ones received within a time window,
+/// whatever their source. The window should be short (a few milliseconds), so the repeated
+/// messages sent on purpose, like the timing clocks or the notes of a fast roll, still pass.
+#[derive(Debug, Clone)]
+pub struct DuplicateSuppression {
+  window: TimestampNanos,
+  recent: [Option<(Message, TimestampNanos)>; RECENT_MESSAGES],
+  next: usize,
+}
+
+impl DuplicateSuppression {
+  pub fn new(window: TimestampNanos) -> Self {
+    Self {
+      window,
+      recent: [None; RECENT_MESSAGES],
+      next: 0,
+    }
+  }
+
+  /// Whether a message is new, rather than a duplicate of one received within the window.
+  pub fn process(&mut self, message: &Message, timestamp: TimestampNanos) -> bool {
+    let window = self.window;
+    let duplicate = self.recent.iter().flatten().any(|(recent, received)| {
+      let elapsed = timestamp.max(*received) - timestamp.min(*received);
+      recent == message && elapsed <= window
+    });
+
+    if !duplicate {
+      self.recent[self.next] = Some((*message, timestamp));
+      self.next = (self.next + 1) % RECENT_MESSAGES;
+    }
+
+    !duplicate
+  }
+
+  pub fn reset(&mut self) {
+    self.recent = [None; RECENT_MESSAGES];
+    self.next = 0;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+  use crate::protocol::messages::MessageType;
+
+  const MS: TimestampNanos = 1_000_000;
+
+  fn note_on(note: u8) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message: ChannelVoice1Message::NoteOn {
+          note,
+          velocity: 100,
+        },
+      }),
+    }
+  }
+
+  #[test]
+  fn drop_duplicates_within_the_window() {
+    let mut duplicates = DuplicateSuppression::new(2 * MS);
+
+    assert!(duplicates.process(&note_on(60), 10 * MS));
+    assert!(duplicates.process(&note_on(64), 10 * MS));
+    assert!(!duplicates.process(&note_on(60), 11 * MS));
+    assert!(!duplicates.process(&note_on(64), 11 * MS));
+    assert!(duplicates.process(&note_on(60), 20 * MS));
+  }
+
+  #[test]
+  fn out_of_order_duplicates() {
+    let mut duplicates = DuplicateSuppression::new(2 * MS);
+
+    assert!(duplicates.process(&note_on(60), 10 * MS));
+    assert!(!duplicates.process(&note_on(60), 9 * MS));
+    assert!(duplicates.process(&note_on(60), 7 * MS));
+  }
+}
@@ -0,0 +1,349 @@
+use crate::protocol::messages::sysex7::{SysEx7, SysExStatus, SYSEX7_MAX_DATA};
+use crate::protocol::messages::{Message, MessageType};
+
+pub const MAX_UMP_WORDS: usize = 4;
+
+pub trait Encode<const N: usize> {
+  fn encode(&self) -> [u32; N];
+}
+
+/// The words of an encoded message, kept in the stack so messages can be encoded from real-time threads
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ump {
+  words: [u32; MAX_UMP_WORDS],
+  len: usize,
+}
+
+impl Ump {
+  fn new<const N: usize>(words: [u32; N]) -> Self {
+    let mut ump = Self {
+      words: [0; MAX_UMP_WORDS],
+      len: N,
+    };
+    ump.words[..N].copy_from_slice(&words);
+    ump
+  }
+
+  /// Copies up to `MAX_UMP_WORDS` words, as long as a single packet.
+  pub(crate) fn from_slice(words: &[u32]) -> Self {
+    let len = words.len().min(MAX_UMP_WORDS);
+    let mut ump = Self {
+      words: [0; MAX_UMP_WORDS],
+      len,
+    };
+    ump.words[..len].copy_from_slice(&words[..len]);
+    ump
+  }
+
+  pub fn as_slice(&self) -> &[u32] {
+    &self.words[..self.len]
+  }
+
+  pub fn len(&self) -> usize {
+    self.len
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.len == 0
+  }
+}
+
+/// Encodes a message type into a 32 bits packet for MIDI 1.0 and system messages,
+/// a 64 bits packet for MIDI 2.0 channel voice and SysEx7 messages, or a 128 bits packet
+/// for SysEx8, Mixed Data Set and Flex Data messages, with the group set to 0.
+pub fn encode_message_type(mtype: &MessageType) -> Ump {
+  match mtype {
+    MessageType::Utility(utility) => Ump::new(utility.encode()),
+    MessageType::System(system) => Ump::new(system.encode()),
+    MessageType::ChannelVoice1(channel_voice) => Ump::new(channel_voice.encode()),
+    MessageType::ChannelVoice(channel_voice) => Ump::new(channel_voice.encode()),
+    MessageType::SysEx7(sysex) => Ump::new(sysex.encode()),
+    MessageType::SysEx8(sysex) => Ump::new(sysex.encode()),
+    MessageType::MixedDataSet(mixed_data_set) => Ump::new(mixed_data_set.encode()),
+    MessageType::FlexData(flex_data) => Ump::new(flex_data.encode()),
+  }
+}
+
+/// Encodes a message, setting its group in the first word.
+pub fn encode_message(message: &Message) -> Ump {
+  let mut ump = encode_message_type(&message.mtype);
+  ump.words[0] |= ((message.group & 0x0f) as u32) << 24;
+  ump
+}
+
+/// Splits the data of a SysEx message into SysEx7 packets (two words each), calling `f` for every one of them.
+///
+/// The data can include the `F0` and `F7` bytes from MIDI 1.0 or not, as they are not part of the packets.
+pub fn encode_sysex7<F>(group: u8, data: &[u8], mut f: F)
+where
+  F: FnMut(Ump),
+{
+  let data = data.strip_prefix(&[0xf0]).unwrap_or(data);
+  let data = data.strip_suffix(&[0xf7]).unwrap_or(data);
+
+  // An empty message still needs a complete packet
+  let packets = ((data.len() + SYSEX7_MAX_DATA - 1) / SYSEX7_MAX_DATA).max(1);
+  for index in 0..packets {
+    let start = index * SYSEX7_MAX_DATA;
+    let chunk = &data[start..(start + SYSEX7_MAX_DATA).min(data.len())];
+    let status = match index {
+      _ if packets == 1 => SysExStatus::Complete,
+      0 => SysExStatus::Start,
+      _ if index == packets - 1 => SysExStatus::End,
+      _ => SysExStatus::Continue,
+    };
+
+    f(encode_message(&Message {
+      group,
+      mtype: MessageType::SysEx7(SysEx7::new(status, chunk)),
+    }));
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::filter::Filter;
+  use crate::protocol::decoder::DecoderProtocol2;
+  use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+  use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+  use crate::protocol::messages::mixed_data_set::{MixedDataSet, MixedDataSetPayload};
+  use crate::protocol::messages::sysex8::SysEx8;
+  use crate::protocol::messages::system::System;
+  use crate::protocol::messages::utility::Utility;
+
+  fn round_trip(message: Message) {
+    let ump = encode_message(&message);
+    let filter = Filter::new();
+    let mut decoder = DecoderProtocol2::default();
+
+    let (last, rest) = ump.as_slice().split_last().unwrap();
+    for word in rest {
+      assert!(matches!(decoder.next(*word, &filter), Ok(None)));
+    }
+    let decoded = decoder.next(*last, &filter).unwrap();
+
+    assert_eq!(decoded, Some(message), "Encoded as {:08x?}", ump.as_slice());
+  }
+
+  fn channel_voice1(channel: u8, message: ChannelVoice1Message) -> Message {
+    Message {
+      group: 5,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 { channel, message }),
+    }
+  }
+
+  fn channel_voice(channel: u8, message: ChanelVoiceMessage) -> Message {
+    Message {
+      group: 9,
+      mtype: MessageType::ChannelVoice(ChannelVoice { channel, message }),
+    }
+  }
+
+  #[test]
+  fn encode_message_with_group() {
+    let message = channel_voice1(
+      1,
+      ChannelVoice1Message::NoteOn {
+        note: 0x3c,
+        velocity: 0x64,
+      },
+    );
+
+    assert_eq!(encode_message(&message).as_slice(), &[0x2591_3c64]);
+  }
+
+  fn sysex7(group: u8, data: &[u8]) -> Vec<Vec<u32>> {
+    let mut packets = Vec::new();
+    encode_sysex7(group, data, |ump| packets.push(ump.as_slice().to_vec()));
+    packets
+  }
+
+  #[test]
+  fn sysex7_in_a_single_packet() {
+    assert_eq!(
+      sysex7(2, &[0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7]),
+      vec![vec![0x3204_7e7f, 0x0601_0000]]
+    );
+    assert_eq!(sysex7(0, &[]), vec![vec![0x3000_0000, 0x0000_0000]]);
+  }
+
+  #[test]
+  fn sysex7_in_several_packets() {
+    let data = (1..=14).collect::<Vec<u8>>();
+
+    assert_eq!(
+      sysex7(0, &data),
+      vec![
+        vec![0x3016_0102, 0x0304_0506],
+        vec![0x3026_0708, 0x090a_0b0c],
+        vec![0x3032_0d0e, 0x0000_0000],
+      ]
+    );
+  }
+
+  #[test]
+  fn packet_sizes() {
+    let utility = encode_message_type(&MessageType::Utility(Utility::Noop));
+    let channel_voice = encode_message_type(&MessageType::ChannelVoice(ChannelVoice {
+      channel: 0,
+      message: ChanelVoiceMessage::ChannelPressure { data: 0 },
+    }));
+
+    assert_eq!(utility.len(), 1);
+    assert_eq!(channel_voice.len(), 2);
+  }
+
+  #[test]
+  fn utility_and_system_round_trip() {
+    round_trip(Message {
+      group: 0,
+      mtype: MessageType::Utility(Utility::Noop),
+    });
+    for system in [
+      System::TimeCode(0x35),
+      System::SongPositionPointer(0x1234),
+      System::SongSelect(0x12),
+      System::TuneRequest,
+      System::TimingClock,
+      System::Start,
+      System::Continue,
+      System::Stop,
+      System::ActiveSensing,
+      System::Reset,
+    ] {
+      round_trip(Message {
+        group: 15,
+        mtype: MessageType::System(system),
+      });
+    }
+  }
+
+  #[test]
+  fn sysex7_round_trip() {
+    round_trip(Message {
+      group: 3,
+      mtype: MessageType::SysEx7(SysEx7::new(SysExStatus::Continue, &[1, 2, 3, 4, 5])),
+    });
+  }
+
+  #[test]
+  fn sysex8_and_mixed_data_set_round_trip() {
+    round_trip(Message {
+      group: 4,
+      mtype: MessageType::SysEx8(SysEx8::new(SysExStatus::End, 9, &[0x80, 0xff, 0x00])),
+    });
+    round_trip(Message {
+      group: 5,
+      mtype: MessageType::MixedDataSet(MixedDataSet::Payload(MixedDataSetPayload {
+        mds_id: 2,
+        data: [0xff; 14],
+      })),
+    });
+  }
+
+  #[test]
+  fn midi1_channel_voice_round_trip() {
+    for message in [
+      ChannelVoice1Message::NoteOff {
+        note: 0x3c,
+        velocity: 0x40,
+      },
+      ChannelVoice1Message::NoteOn {
+        note: 0x7f,
+        velocity: 0x01,
+      },
+      ChannelVoice1Message::PolyPressure {
+        note: 0x3c,
+        data: 0x7f,
+      },
+      ChannelVoice1Message::ControlChange {
+        index: 0x07,
+        data: 0x64,
+      },
+      ChannelVoice1Message::ProgramChange { program: 0x12 },
+      ChannelVoice1Message::ChannelPressure { data: 0x33 },
+      ChannelVoice1Message::PitchBend { data: 0x2000 },
+      ChannelVoice1Message::PitchBend { data: 0x3fff },
+    ] {
+      round_trip(channel_voice1(3, message));
+    }
+  }
+
+  #[test]
+  fn midi2_channel_voice_round_trip() {
+    for message in [
+      ChanelVoiceMessage::NoteOff {
+        note: 0x3c,
+        velocity: 0x8000,
+        attr_type: 0,
+        attr_data: 0,
+      },
+      ChanelVoiceMessage::NoteOn {
+        note: 0x3c,
+        velocity: 0xffff,
+        attr_type: 3,
+        attr_data: 0x1234,
+      },
+      ChanelVoiceMessage::PolyPressure {
+        note: 0x3c,
+        data: 0xffff_0000,
+      },
+      ChanelVoiceMessage::RegisteredPerNoteController {
+        note: 0x3c,
+        index: 0x01,
+        data: 0x1234_5678,
+      },
+      ChanelVoiceMessage::AssignablePerNoteController {
+        note: 0x3c,
+        index: 0x02,
+        data: 0x8765_4321,
+      },
+      ChanelVoiceMessage::PerNoteManagement {
+        note: 0x3c,
+        detach: true,
+        reset: false,
+      },
+      ChanelVoiceMessage::ControlChange {
+        index: 0x07,
+        data: 0x8000_0000,
+      },
+      ChanelVoiceMessage::RegisteredController {
+        bank: 0x01,
+        index: 0x02,
+        data: 0x0000_ffff,
+      },
+      ChanelVoiceMessage::AssignableController {
+        bank: 0x03,
+        index: 0x04,
+        data: 0xffff_ffff,
+      },
+      ChanelVoiceMessage::RelativeRegisteredController {
+        bank: 0x05,
+        index: 0x06,
+        data: -1000,
+      },
+      ChanelVoiceMessage::RelativeAssignableController {
+        bank: 0x07,
+        index: 0x08,
+        data: 1000,
+      },
+      ChanelVoiceMessage::ProgramChange {
+        program: 0x12,
+        bank: None,
+      },
+      ChanelVoiceMessage::ProgramChange {
+        program: 0x12,
+        bank: Some(0x1234),
+      },
+      ChanelVoiceMessage::ChannelPressure { data: 0x1234_5678 },
+      ChanelVoiceMessage::PitchBend { data: 0x8000_0000 },
+      ChanelVoiceMessage::PerNotePitchBend {
+        note: 0x3c,
+        data: 0x9000_0000,
+      },
+    ] {
+      round_trip(channel_voice(15, message));
+    }
+  }
+}
@@ -0,0 +1,174 @@
+//! Jitter reduction for the devices sending JR Clock and JR Timestamp messages.
+//!
+//! The JR Clocks tell the time of the sender, which is compared with the time the messages
+//! are received to estimate the offset between both clocks. The shortest delay is the one with
+//! the least jitter, so the offset follows the lowest estimates, and only drifts slowly upwards
+//! to keep up with the clocks of the sender and the receiver running at slightly different rates.
+//! Then the time of the sender in a JR Timestamp is translated into the time of the receiver
+//! for the message following it.
+
+use crate::event::TimestampNanos;
+use crate::protocol::messages::utility::Utility;
+use crate::protocol::messages::{Message, MessageType};
+
+/// The JR times are in ticks of 1/31250 seconds
+const TICK_NANOS: i64 = 32_000;
+
+/// The JR times wrap around every ~2 seconds, so the sender clock is lost after half of it
+const MAX_CLOCK_GAP_NANOS: TimestampNanos = 1_000_000_000;
+
+/// How much of the difference with a later estimate of the offset is followed
+const DRIFT_DIVISOR: i64 = 64;
+
+#[derive(Debug, Default, Clone)]
+pub struct JitterReduction {
+  clock: Option<SenderClock>,
+  timestamp: Option<u16>,
+}
+
+#[derive(Debug, Clone)]
+struct SenderClock {
+  /// Time of the last JR Clock, without wrapping around
+  ticks: i64,
+  /// When the last JR Clock was received
+  received: TimestampNanos,
+  /// Estimated time of the receiver minus time of the sender
+  offset: i64,
+}
+
+impl SenderClock {
+  fn unwrap(&self, sender_time: u16) -> i64 {
+    let delta = sender_time.wrapping_sub(self.ticks as u16) as i16;
+    self.ticks + delta as i64
+  }
+}
+
+impl JitterReduction {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The timestamp of a message received at `timestamp`, corrected with the JR Timestamp before it.
+  ///
+  /// The JR messages themselves update the state and keep their own timestamp, as do the messages
+  /// received before the first JR Clock.
+  pub fn timestamp(&mut self, message: &Message, timestamp: TimestampNanos) -> TimestampNanos {
+    match message.mtype {
+      MessageType::Utility(Utility::JrClock { sender_time }) => {
+        self.clock(sender_time, timestamp);
+        timestamp
+      }
+      MessageType::Utility(Utility::JrTimestamp { sender_time }) => {
+        self.timestamp = Some(sender_time);
+        timestamp
+      }
+      MessageType::Utility(Utility::Noop) => timestamp,
+      _ => match (self.timestamp.take(), self.clock.as_ref()) {
+        (Some(sender_time), Some(clock)) => {
+          let corrected = clock.unwrap(sender_time) * TICK_NANOS + clock.offset;
+          corrected.clamp(0, timestamp as i64) as TimestampNanos
+        }
+        _ => timestamp,
+      },
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.clock = None;
+    self.timestamp = None;
+  }
+
+  fn clock(&mut self, sender_time: u16, received: TimestampNanos) {
+    match self.clock.as_mut() {
+      Some(clock) if received.saturating_sub(clock.received) < MAX_CLOCK_GAP_NANOS => {
+        clock.ticks = clock.unwrap(sender_time);
+        clock.received = received;
+        let offset = received as i64 - clock.ticks * TICK_NANOS;
+        if offset < clock.offset {
+          clock.offset = offset;
+        } else {
+          clock.offset += (offset - clock.offset) / DRIFT_DIVISOR;
+        }
+      }
+      _ => {
+        let ticks = sender_time as i64;
+        self.clock = Some(SenderClock {
+          ticks,
+          received,
+          offset: received as i64 - ticks * TICK_NANOS,
+        });
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::messages::system::System;
+
+  fn utility(utility: Utility) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::Utility(utility),
+    }
+  }
+
+  fn clock(sender_time: u16) -> Message {
+    utility(Utility::JrClock { sender_time })
+  }
+
+  fn jr_timestamp(sender_time: u16) -> Message {
+    utility(Utility::JrTimestamp { sender_time })
+  }
+
+  fn message() -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::System(System::TimingClock),
+    }
+  }
+
+  const MS: TimestampNanos = 1_000_000;
+
+  #[test]
+  fn without_clock_timestamps_are_kept() {
+    let mut jr = JitterReduction::new();
+    assert_eq!(jr.timestamp(&jr_timestamp(100), 50 * MS), 50 * MS);
+    assert_eq!(jr.timestamp(&message(), 50 * MS), 50 * MS);
+  }
+
+  #[test]
+  fn jitter_is_removed() {
+    let mut jr = JitterReduction::new();
+    // Sender clock at tick 0 received with 5ms of jitter, then at tick 3125 (100ms later) without it
+    jr.timestamp(&clock(0), 15 * MS);
+    jr.timestamp(&clock(3125), 110 * MS);
+
+    // A message sent at tick 1250 (40ms) received with 7ms of jitter
+    jr.timestamp(&jr_timestamp(1250), 57 * MS);
+    assert_eq!(jr.timestamp(&message(), 57 * MS), 50 * MS);
+
+    // The JR Timestamp only applies to the message following it
+    assert_eq!(jr.timestamp(&message(), 60 * MS), 60 * MS);
+  }
+
+  #[test]
+  fn sender_time_wraps_around() {
+    let mut jr = JitterReduction::new();
+    jr.timestamp(&clock(0xffff), 10 * MS);
+    jr.timestamp(&clock(3124), 110 * MS);
+
+    jr.timestamp(&jr_timestamp(1), 120 * MS);
+    assert_eq!(jr.timestamp(&message(), 120 * MS), 10 * MS + 64_000);
+  }
+
+  #[test]
+  fn corrected_timestamps_are_not_later() {
+    let mut jr = JitterReduction::new();
+    jr.timestamp(&clock(0), 10 * MS);
+
+    jr.timestamp(&jr_timestamp(3125), 20 * MS);
+    assert_eq!(jr.timestamp(&message(), 20 * MS), 20 * MS);
+  }
+}
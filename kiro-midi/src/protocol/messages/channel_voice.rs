@@ -1,11 +1,13 @@
 use crate::protocol::{Decode, Encode};
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ChannelVoice {
   pub channel: u8,
   pub message: ChanelVoiceMessage,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum ChanelVoiceMessage {
   NoteOff {
@@ -81,6 +83,50 @@ pub enum ChanelVoiceMessage {
   },
 }
 
+impl ChanelVoiceMessage {
+  /// The note of the messages addressed to a single note.
+  pub fn note(&self) -> Option<u8> {
+    match *self {
+      Self::NoteOff { note, .. }
+      | Self::NoteOn { note, .. }
+      | Self::PolyPressure { note, .. }
+      | Self::RegisteredPerNoteController { note, .. }
+      | Self::AssignablePerNoteController { note, .. }
+      | Self::PerNoteManagement { note, .. }
+      | Self::PerNotePitchBend { note, .. } => Some(note),
+      _ => None,
+    }
+  }
+
+  /// The pitch of a note on or note off, from its pitch 7.9 attribute if it has one, or from the note number.
+  pub fn note_pitch(&self) -> Option<NotePitch> {
+    match *self {
+      Self::NoteOn {
+        note,
+        attr_type,
+        attr_data,
+        ..
+      }
+      | Self::NoteOff {
+        note,
+        attr_type,
+        attr_data,
+        ..
+      } => Some(match AttributeType::from(attr_type) {
+        AttributeType::Pitch7_9 => NotePitch::from_attr(attr_data),
+        _ => NotePitch::from_note(note),
+      }),
+      _ => None,
+    }
+  }
+}
+
+impl ChannelVoice {
+  pub(crate) fn is_valid_status(status: u8) -> bool {
+    status != 0b0111
+  }
+}
+
 impl Decode for ChannelVoice {
   fn decode(ump: &[u32]) -> Self {
     assert_eq!(ump.len(), 2);
@@ -205,7 +251,73 @@ impl Decode for ChannelVoice {
 
 impl Encode<2> for ChannelVoice {
   fn encode(&self) -> [u32; 2] {
-    todo!()
+    let (status, data1, data2, data) = match self.message {
+      ChanelVoiceMessage::NoteOff {
+        note,
+        velocity,
+        attr_type,
+        attr_data,
+      } => (
+        0b1000,
+        note,
+        attr_type,
+        (velocity as u32) << 16 | attr_data as u32,
+      ),
+      ChanelVoiceMessage::NoteOn {
+        note,
+        velocity,
+        attr_type,
+        attr_data,
+      } => (
+        0b1001,
+        note,
+        attr_type,
+        (velocity as u32) << 16 | attr_data as u32,
+      ),
+      ChanelVoiceMessage::PolyPressure { note, data } => (0b1010, note, 0, data),
+      ChanelVoiceMessage::RegisteredPerNoteController { note, index, data } => {
+        (0b0000, note, index, data)
+      }
+      ChanelVoiceMessage::AssignablePerNoteController { note, index, data } => {
+        (0b0001, note, index, data)
+      }
+      ChanelVoiceMessage::PerNoteManagement {
+        note,
+        detach,
+        reset,
+      } => (0b1111, note, (detach as u8) << 1 | reset as u8, 0),
+      ChanelVoiceMessage::ControlChange { index, data } => (0b1011, index, 0, data),
+      ChanelVoiceMessage::RegisteredController { bank, index, data } => (0b0010, bank, index, data),
+      ChanelVoiceMessage::AssignableController { bank, index, data } => (0b0011, bank, index, data),
+      ChanelVoiceMessage::RelativeRegisteredController { bank, index, data } => {
+        (0b0100, bank, index, data as u32)
+      }
+      ChanelVoiceMessage::RelativeAssignableController { bank, index, data } => {
+        (0b0101, bank, index, data as u32)
+      }
+      ChanelVoiceMessage::ProgramChange { program, bank } => {
+        let bank_data = bank
+          .map(|bank| ((bank as u32) << 1) & 0x7f00 | (bank as u32) & 0x7f)
+          .unwrap_or_default();
+        (
+          0b1100,
+          0,
+          bank.is_some() as u8,
+          ((program & 0x7f) as u32) << 24 | bank_data,
+        )
+      }
+      ChanelVoiceMessage::ChannelPressure { data } => (0b1101, 0, 0, data),
+      ChanelVoiceMessage::PitchBend { data } => (0b1110, 0, 0, data),
+      ChanelVoiceMessage::PerNotePitchBend { note, data } => (0b0110, note, 0, data),
+    };
+    [
+      0x40000000
+        | (status as u32) << 20
+        | ((self.channel & 0x0f) as u32) << 16
+        | ((data1 & 0x7f) as u32) << 8
+        | data2 as u32,
+      data,
+    ]
   }
 }
 
@@ -230,10 +342,97 @@ impl From<u8> for AttributeType {
   }
 }
 
+/// A pitch in 7.9 fixed point, as in the data of the note attribute type 0x03
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct NotePitch(u16);
+
+impl NotePitch {
+  pub fn from_attr(attr_data: u16) -> Self {
+    Self(attr_data)
+  }
+
+  pub fn from_note(note: u8) -> Self {
+    Self(((note & 0x7f) as u16) << 9)
+  }
+
+  /// The closest pitch to a number of semitones, as a note number with a fraction.
+  pub fn from_semitones(semitones: f32) -> Self {
+    // Rounded by hand, as `f32::round` needs std
+    Self((semitones.clamp(0.0, 127.0 + 511.0 / 512.0) * 512.0 + 0.5) as u16)
+  }
+
+  pub fn to_attr(self) -> u16 {
+    self.0
+  }
+
+  pub fn note(self) -> u8 {
+    (self.0 >> 9) as u8
+  }
+
+  /// Fraction of semitone above the note, in 1/512 of semitone.
+  pub fn fraction(self) -> u16 {
+    self.0 & 0x01ff
+  }
+
+  pub fn semitones(self) -> f32 {
+    attr_to_semitones(self.0)
+  }
+
+  #[cfg(feature = "std")]
+  pub fn frequency(self) -> f32 {
+    attr_to_frequency(self.0)
+  }
+}
+
+/// The pitch of a pitch 7.9 attribute as a note number with a fraction.
+pub fn attr_to_semitones(attr_data: u16) -> f32 {
+  attr_data as f32 / 512.0
+}
+
+/// The frequency in Hz of a pitch 7.9 attribute, in 12 tone equal temperament with A4 (note 69) at 440 Hz.
+#[cfg(feature = "std")]
+pub fn attr_to_frequency(attr_data: u16) -> f32 {
+  440.0 * ((attr_to_semitones(attr_data) - 69.0) / 12.0).exp2()
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
 
+  #[test]
+  fn note_pitch() {
+    let message = ChanelVoiceMessage::NoteOn {
+      note: 60,
+      velocity: 0xffff,
+      attr_type: 0x03,
+      attr_data: (69 << 9) | 0x100,
+    };
+    let pitch = message.note_pitch().unwrap();
+    assert_eq!(pitch.note(), 69);
+    assert_eq!(pitch.fraction(), 0x100);
+    assert_eq!(pitch.semitones(), 69.5);
+
+    let message = ChanelVoiceMessage::NoteOff {
+      note: 69,
+      velocity: 0,
+      attr_type: 0x00,
+      attr_data: 0x1234,
+    };
+    assert_eq!(message.note_pitch(), Some(NotePitch::from_note(69)));
+    assert_eq!(NotePitch::from_semitones(69.5), pitch);
+    assert_eq!(
+      ChanelVoiceMessage::ChannelPressure { data: 0 }.note_pitch(),
+      None
+    );
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn note_frequency() {
+    assert_eq!(attr_to_frequency(NotePitch::from_note(69).to_attr()), 440.0);
+    assert!((NotePitch::from_semitones(69.5).frequency() - 452.893).abs() < 0.001);
+  }
+
   #[test]
   fn decode_note_off() {
     let channel_voice = ChannelVoice::decode(&[0x4182bc03, 0xabcd1234]);
@@ -505,4 +704,106 @@ mod tests {
       }
     );
   }
+
+  #[test]
+  fn encode_note_on() {
+    let channel_voice = ChannelVoice {
+      channel: 2,
+      message: ChanelVoiceMessage::NoteOn {
+        note: 0x3c,
+        attr_type: 0x03,
+        velocity: 0xabcd,
+        attr_data: 0x1234,
+      },
+    };
+
+    assert_eq!(channel_voice.encode(), [0x40923c03, 0xabcd1234]);
+  }
+
+  #[test]
+  fn encode_program_change() {
+    let channel_voice = ChannelVoice {
+      channel: 2,
+      message: ChanelVoiceMessage::ProgramChange {
+        program: 0x7f,
+        bank: Some(0x27a5),
+      },
+    };
+
+    assert_eq!(channel_voice.encode(), [0x40c20001, 0x7f004f25]);
+    assert_eq!(ChannelVoice::decode(&channel_voice.encode()), channel_voice);
+  }
+
+  #[test]
+  fn encode_decode() {
+    let messages = [
+      ChanelVoiceMessage::NoteOff {
+        note: 0x3c,
+        velocity: 0xabcd,
+        attr_type: 0x03,
+        attr_data: 0x1234,
+      },
+      ChanelVoiceMessage::PolyPressure {
+        note: 0x3c,
+        data: 0x12345678,
+      },
+      ChanelVoiceMessage::RegisteredPerNoteController {
+        note: 0x3c,
+        index: 0xa5,
+        data: 0x12345678,
+      },
+      ChanelVoiceMessage::AssignablePerNoteController {
+        note: 0x3c,
+        index: 0xa5,
+        data: 0x12345678,
+      },
+      ChanelVoiceMessage::PerNoteManagement {
+        note: 0x3c,
+        detach: true,
+        reset: false,
+      },
+      ChanelVoiceMessage::ControlChange {
+        index: 0x7f,
+        data: 0x12345678,
+      },
+      ChanelVoiceMessage::RegisteredController {
+        bank: 0x25,
+        index: 0x7f,
+        data: 0x12345678,
+      },
+      ChanelVoiceMessage::AssignableController {
+        bank: 0x25,
+        index: 0x7f,
+        data: 0x12345678,
+      },
+      ChanelVoiceMessage::RelativeRegisteredController {
+        bank: 0x25,
+        index: 0x7f,
+        data: i32::MIN,
+      },
+      ChanelVoiceMessage::RelativeAssignableController {
+        bank: 0x25,
+        index: 0x7f,
+        data: i32::MAX,
+      },
+      ChanelVoiceMessage::ProgramChange {
+        program: 0x12,
+        bank: None,
+      },
+      ChanelVoiceMessage::ChannelPressure { data: 0x87654321 },
+      ChanelVoiceMessage::PitchBend { data: 0x87654321 },
+      ChanelVoiceMessage::PerNotePitchBend {
+        note: 0x7f,
+        data: 0x87654321,
+      },
+    ];
+
+    for message in messages {
+      let channel_voice = ChannelVoice {
+        channel: 15,
+        message,
+      };
+      assert_eq!(ChannelVoice::decode(&channel_voice.encode()), channel_voice);
+    }
+  }
 }
@@ -0,0 +1,269 @@
+use crate::protocol::{Decode, Encode};
+
+/// MIDI 1.0 Channel Voice messages carried in UMP (message type 0x2)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ChannelVoice1 {
+  pub channel: u8,
+  pub message: ChannelVoice1Message,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChannelVoice1Message {
+  NoteOff {
+    note: u8,
+    velocity: u8,
+  },
+  NoteOn {
+    note: u8,
+    velocity: u8,
+  },
+  PolyPressure {
+    note: u8,
+    data: u8,
+  },
+  ControlChange {
+    index: u8,
+    data: u8,
+  },
+  ProgramChange {
+    program: u8,
+  },
+  ChannelPressure {
+    data: u8,
+  },
+  PitchBend {
+    /// 14 bits unsigned bipolar value centered at 0x2000
+    data: u16,
+  },
+}
+
+impl ChannelVoice1Message {
+  /// The note of the messages addressed to a single note.
+  pub fn note(&self) -> Option<u8> {
+    match *self {
+      Self::NoteOff { note, .. } | Self::NoteOn { note, .. } | Self::PolyPressure { note, .. } => {
+        Some(note)
+      }
+      _ => None,
+    }
+  }
+}
+
+impl ChannelVoice1 {
+  pub(crate) fn is_valid_status(status: u8) -> bool {
+    (0b1000..=0b1110).contains(&status)
+  }
+}
+
+impl Decode for ChannelVoice1 {
+  fn decode(ump: &[u32]) -> Self {
+    assert_eq!(ump.len(), 1);
+    let channel = ((ump[0] >> 16) & 0x0f) as u8;
+    let status = ((ump[0] >> 20) & 0x0f) as u8;
+    let data1 = ((ump[0] >> 8) & 0x7f) as u8;
+    let data2 = (ump[0] & 0x7f) as u8;
+    match status {
+      0b1000 => Self {
+        channel,
+        message: ChannelVoice1Message::NoteOff {
+          note: data1,
+          velocity: data2,
+        },
+      },
+      0b1001 => Self {
+        channel,
+        message: ChannelVoice1Message::NoteOn {
+          note: data1,
+          velocity: data2,
+        },
+      },
+      0b1010 => Self {
+        channel,
+        message: ChannelVoice1Message::PolyPressure {
+          note: data1,
+          data: data2,
+        },
+      },
+      0b1011 => Self {
+        channel,
+        message: ChannelVoice1Message::ControlChange {
+          index: data1,
+          data: data2,
+        },
+      },
+      0b1100 => Self {
+        channel,
+        message: ChannelVoice1Message::ProgramChange { program: data1 },
+      },
+      0b1101 => Self {
+        channel,
+        message: ChannelVoice1Message::ChannelPressure { data: data1 },
+      },
+      0b1110 => Self {
+        channel,
+        message: ChannelVoice1Message::PitchBend {
+          data: ((data2 as u16) << 7) | data1 as u16,
+        },
+      },
+      _ => unreachable!(),
+    }
+  }
+}
+
+impl Encode<1> for ChannelVoice1 {
+  fn encode(&self) -> [u32; 1] {
+    let (status, data1, data2) = match self.message {
+      ChannelVoice1Message::NoteOff { note, velocity } => (0b1000, note, velocity),
+      ChannelVoice1Message::NoteOn { note, velocity } => (0b1001, note, velocity),
+      ChannelVoice1Message::PolyPressure { note, data } => (0b1010, note, data),
+      ChannelVoice1Message::ControlChange { index, data } => (0b1011, index, data),
+      ChannelVoice1Message::ProgramChange { program } => (0b1100, program, 0),
+      ChannelVoice1Message::ChannelPressure { data } => (0b1101, data, 0),
+      ChannelVoice1Message::PitchBend { data } => {
+        (0b1110, (data & 0x7f) as u8, ((data >> 7) & 0x7f) as u8)
+      }
+    };
+    [0x20000000
+      | (status as u32) << 20
+      | ((self.channel & 0x0f) as u32) << 16
+      | ((data1 & 0x7f) as u32) << 8
+      | (data2 & 0x7f) as u32]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decode_note_off() {
+    let channel_voice = ChannelVoice1::decode(&[0x21823c40]);
+
+    assert_eq!(
+      channel_voice,
+      ChannelVoice1 {
+        channel: 2,
+        message: ChannelVoice1Message::NoteOff {
+          note: 0x3c,
+          velocity: 0x40,
+        }
+      }
+    );
+  }
+
+  #[test]
+  fn decode_note_on() {
+    let channel_voice = ChannelVoice1::decode(&[0x21923cff]);
+
+    assert_eq!(
+      channel_voice,
+      ChannelVoice1 {
+        channel: 2,
+        message: ChannelVoice1Message::NoteOn {
+          note: 0x3c,
+          velocity: 0x7f,
+        }
+      }
+    );
+  }
+
+  #[test]
+  fn decode_poly_pressure() {
+    let channel_voice = ChannelVoice1::decode(&[0x21a23c12]);
+
+    assert_eq!(
+      channel_voice,
+      ChannelVoice1 {
+        channel: 2,
+        message: ChannelVoice1Message::PolyPressure {
+          note: 0x3c,
+          data: 0x12,
+        }
+      }
+    );
+  }
+
+  #[test]
+  fn decode_control_change() {
+    let channel_voice = ChannelVoice1::decode(&[0x21b20755]);
+
+    assert_eq!(
+      channel_voice,
+      ChannelVoice1 {
+        channel: 2,
+        message: ChannelVoice1Message::ControlChange {
+          index: 0x07,
+          data: 0x55,
+        }
+      }
+    );
+  }
+
+  #[test]
+  fn decode_program_change() {
+    let channel_voice = ChannelVoice1::decode(&[0x21c22a00]);
+
+    assert_eq!(
+      channel_voice,
+      ChannelVoice1 {
+        channel: 2,
+        message: ChannelVoice1Message::ProgramChange { program: 0x2a }
+      }
+    );
+  }
+
+  #[test]
+  fn decode_channel_pressure() {
+    let channel_voice = ChannelVoice1::decode(&[0x21d26400]);
+
+    assert_eq!(
+      channel_voice,
+      ChannelVoice1 {
+        channel: 2,
+        message: ChannelVoice1Message::ChannelPressure { data: 0x64 }
+      }
+    );
+  }
+
+  #[test]
+  fn decode_pitch_bend() {
+    let channel_voice = ChannelVoice1::decode(&[0x21e2017f]);
+
+    assert_eq!(
+      channel_voice,
+      ChannelVoice1 {
+        channel: 2,
+        message: ChannelVoice1Message::PitchBend { data: 0x3f81 }
+      }
+    );
+  }
+
+  #[test]
+  fn encode_note_on() {
+    let channel_voice = ChannelVoice1 {
+      channel: 2,
+      message: ChannelVoice1Message::NoteOn {
+        note: 0x3c,
+        velocity: 0x7f,
+      },
+    };
+
+    assert_eq!(channel_voice.encode(), [0x20923c7f]);
+  }
+
+  #[test]
+  fn encode_pitch_bend() {
+    let channel_voice = ChannelVoice1 {
+      channel: 2,
+      message: ChannelVoice1Message::PitchBend { data: 0x2345 },
+    };
+
+    assert_eq!(channel_voice.encode(), [0x20e24546]);
+    assert_eq!(
+      ChannelVoice1::decode(&channel_voice.encode()),
+      channel_voice
+    );
+  }
+}
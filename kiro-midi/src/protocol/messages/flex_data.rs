@@ -0,0 +1,421 @@
+use alloc::vec::Vec;
+
+use crate::protocol::messages::sysex7::SysExStatus;
+use crate::protocol::{Decode, Encode};
+
+pub const FLEX_DATA_MAX_TEXT: usize = 12;
+
+/// Flex Data message (message type 0xD), with the tempo, time and key signatures,
+/// as well as the metadata and performance texts from MIDI 2.0 clips and sequences.
+///
+/// Texts longer than 12 bytes are split into several packets, which can be put back together
+/// with a `FlexTextAssembler`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FlexData {
+  pub form: SysExStatus,
+  pub address: FlexDataAddress,
+  pub message: FlexDataMessage,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FlexDataAddress {
+  Channel(u8),
+  Group,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FlexDataMessage {
+  SetTempo {
+    /// Duration of a quarter note in units of 10 nanoseconds
+    ten_nanos_per_quarter: u32,
+  },
+  SetTimeSignature {
+    numerator: u8,
+    /// Negative power of 2 (2 for a quarter note, 3 for an eighth note, ...)
+    denominator: u8,
+    thirty_second_notes: u8,
+  },
+  SetMetronome {
+    clocks_per_primary_click: u8,
+    bar_accents: [u8; 3],
+    subdivision_clicks: [u8; 2],
+  },
+  SetKeySignature {
+    /// Positive for sharps, negative for flats and -8 when unknown
+    sharps_flats: i8,
+    /// From 1 (A) to 7 (G), or 0 when unknown
+    tonic: u8,
+  },
+  /// Text describing the project or the clip, with the kind of text in `status` (project name, copyright notice, ...)
+  MetadataText { status: u8, text: FlexText },
+  /// Text to be shown along with the performance, with the kind of text in `status` (lyrics, ruby, ...)
+  PerformanceText { status: u8, text: FlexText },
+  /// Messages without a structured representation yet, such as the chord names
+  Other {
+    status_bank: u8,
+    status: u8,
+    data: [u32; 3],
+  },
+}
+
+/// Up to 12 bytes of UTF-8 text, which can split a character when the text is longer than a packet.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FlexText {
+  len: u8,
+  data: [u8; FLEX_DATA_MAX_TEXT],
+}
+
+impl FlexText {
+  /// Creates a text with the first 12 bytes at most.
+  pub fn new(text: &[u8]) -> Self {
+    let len = text.len().min(FLEX_DATA_MAX_TEXT);
+    let mut data = [0u8; FLEX_DATA_MAX_TEXT];
+    data[..len].copy_from_slice(&text[..len]);
+    Self {
+      len: len as u8,
+      data,
+    }
+  }
+
+  pub fn as_bytes(&self) -> &[u8] {
+    // The length is only out of range when deserialized from invalid data
+    &self.data[..(self.len as usize).min(FLEX_DATA_MAX_TEXT)]
+  }
+
+  fn decode(words: &[u32]) -> Self {
+    let mut data = [0u8; FLEX_DATA_MAX_TEXT];
+    for (index, word) in words.iter().enumerate() {
+      data[index * 4..index * 4 + 4].copy_from_slice(&word.to_be_bytes());
+    }
+    // The text is padded with zeros at the end of the last packet
+    let len = data
+      .iter()
+      .position(|byte| *byte == 0)
+      .unwrap_or(FLEX_DATA_MAX_TEXT);
+    Self::new(&data[..len])
+  }
+
+  fn encode(&self) -> [u32; 3] {
+    let data = &self.data;
+    [
+      u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+      u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+      u32::from_be_bytes([data[8], data[9], data[10], data[11]]),
+    ]
+  }
+}
+
+impl FlexData {
+  pub(crate) fn is_valid_address(address: u8) -> bool {
+    address <= 0x1
+  }
+}
+
+impl Decode for FlexData {
+  fn decode(ump: &[u32]) -> Self {
+    assert_eq!(ump.len(), 4);
+    let form = match (ump[0] >> 22) & 0x03 {
+      0x0 => SysExStatus::Complete,
+      0x1 => SysExStatus::Start,
+      0x2 => SysExStatus::Continue,
+      _ => SysExStatus::End,
+    };
+    let address = match (ump[0] >> 20) & 0x03 {
+      0x0 => FlexDataAddress::Channel(((ump[0] >> 16) & 0x0f) as u8),
+      0x1 => FlexDataAddress::Group,
+      _ => unreachable!(),
+    };
+    let status_bank = ((ump[0] >> 8) & 0xff) as u8;
+    let status = (ump[0] & 0xff) as u8;
+    let [data1, data2, data3, data4] = ump[1].to_be_bytes();
+    let [data5, data6, _, _] = ump[2].to_be_bytes();
+
+    let message = match (status_bank, status) {
+      (0x00, 0x00) => FlexDataMessage::SetTempo {
+        ten_nanos_per_quarter: ump[1],
+      },
+      (0x00, 0x01) => FlexDataMessage::SetTimeSignature {
+        numerator: data1,
+        denominator: data2,
+        thirty_second_notes: data3,
+      },
+      (0x00, 0x02) => FlexDataMessage::SetMetronome {
+        clocks_per_primary_click: data1,
+        bar_accents: [data2, data3, data4],
+        subdivision_clicks: [data5, data6],
+      },
+      (0x00, 0x05) => FlexDataMessage::SetKeySignature {
+        // Sign extension of the upper 4 bits
+        sharps_flats: (data1 as i8) >> 4,
+        tonic: data1 & 0x0f,
+      },
+      (0x01, status) => FlexDataMessage::MetadataText {
+        status,
+        text: FlexText::decode(&ump[1..4]),
+      },
+      (0x02, status) => FlexDataMessage::PerformanceText {
+        status,
+        text: FlexText::decode(&ump[1..4]),
+      },
+      (status_bank, status) => FlexDataMessage::Other {
+        status_bank,
+        status,
+        data: [ump[1], ump[2], ump[3]],
+      },
+    };
+
+    Self {
+      form,
+      address,
+      message,
+    }
+  }
+}
+
+impl Encode<4> for FlexData {
+  fn encode(&self) -> [u32; 4] {
+    let form = match self.form {
+      SysExStatus::Complete => 0x0,
+      SysExStatus::Start => 0x1,
+      SysExStatus::Continue => 0x2,
+      SysExStatus::End => 0x3,
+    };
+    let (address, channel) = match self.address {
+      FlexDataAddress::Channel(channel) => (0x0, (channel & 0x0f) as u32),
+      FlexDataAddress::Group => (0x1, 0x0),
+    };
+
+    let (status_bank, status, data) = match self.message {
+      FlexDataMessage::SetTempo {
+        ten_nanos_per_quarter,
+      } => (0x00, 0x00, [ten_nanos_per_quarter, 0, 0]),
+      FlexDataMessage::SetTimeSignature {
+        numerator,
+        denominator,
+        thirty_second_notes,
+      } => (
+        0x00,
+        0x01,
+        [
+          u32::from_be_bytes([numerator, denominator, thirty_second_notes, 0]),
+          0,
+          0,
+        ],
+      ),
+      FlexDataMessage::SetMetronome {
+        clocks_per_primary_click,
+        bar_accents,
+        subdivision_clicks,
+      } => (
+        0x00,
+        0x02,
+        [
+          u32::from_be_bytes([
+            clocks_per_primary_click,
+            bar_accents[0],
+            bar_accents[1],
+            bar_accents[2],
+          ]),
+          u32::from_be_bytes([subdivision_clicks[0], subdivision_clicks[1], 0, 0]),
+          0,
+        ],
+      ),
+      FlexDataMessage::SetKeySignature {
+        sharps_flats,
+        tonic,
+      } => (
+        0x00,
+        0x05,
+        [
+          (((sharps_flats as u8) << 4 | (tonic & 0x0f)) as u32) << 24,
+          0,
+          0,
+        ],
+      ),
+      FlexDataMessage::MetadataText { status, text } => (0x01, status, text.encode()),
+      FlexDataMessage::PerformanceText { status, text } => (0x02, status, text.encode()),
+      FlexDataMessage::Other {
+        status_bank,
+        status,
+        data,
+      } => (status_bank, status, data),
+    };
+
+    [
+      0xd0000000
+        | form << 22
+        | address << 20
+        | channel << 16
+        | (status_bank as u32) << 8
+        | status as u32,
+      data[0],
+      data[1],
+      data[2],
+    ]
+  }
+}
+
+/// Puts the texts split into several Flex Data packets back together,
+/// dropping the texts longer than the maximum length.
+///
+/// The packets from different sources can be interleaved, so every source needs its own assembler.
+pub struct FlexTextAssembler {
+  text: Vec<u8>,
+  max_len: usize,
+  receiving: bool,
+}
+
+impl FlexTextAssembler {
+  /// The buffer is allocated upfront, so it can be used from real-time threads.
+  pub fn new(max_len: usize) -> Self {
+    Self {
+      text: Vec::with_capacity(max_len),
+      max_len,
+      receiving: false,
+    }
+  }
+
+  /// Returns the whole text once its last packet arrives, ignoring the messages without text.
+  pub fn push(&mut self, packet: &FlexData) -> Option<&[u8]> {
+    let text = match &packet.message {
+      FlexDataMessage::MetadataText { text, .. }
+      | FlexDataMessage::PerformanceText { text, .. } => text,
+      _ => return None,
+    };
+
+    if matches!(packet.form, SysExStatus::Complete | SysExStatus::Start) {
+      self.text.clear();
+      self.receiving = true;
+    }
+
+    if !self.receiving {
+      return None;
+    }
+
+    if self.text.len() + text.as_bytes().len() > self.max_len {
+      self.receiving = false;
+      return None;
+    }
+    self.text.extend_from_slice(text.as_bytes());
+
+    match packet.form {
+      SysExStatus::Complete | SysExStatus::End => {
+        self.receiving = false;
+        Some(self.text.as_slice())
+      }
+      SysExStatus::Start | SysExStatus::Continue => None,
+    }
+  }
+
+  /// Forgets about the text being received.
+  pub fn reset(&mut self) {
+    self.text.clear();
+    self.receiving = false;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn round_trip(flex_data: FlexData) {
+    assert_eq!(FlexData::decode(&flex_data.encode()), flex_data);
+  }
+
+  #[test]
+  fn decode_setup_messages() {
+    assert_eq!(
+      FlexData::decode(&[0xd010_0000, 0x02fa_f080, 0, 0]),
+      FlexData {
+        form: SysExStatus::Complete,
+        address: FlexDataAddress::Group,
+        message: FlexDataMessage::SetTempo {
+          ten_nanos_per_quarter: 50_000_000,
+        },
+      }
+    );
+    assert_eq!(
+      FlexData::decode(&[0xd003_0005, 0xd300_0000, 0, 0]),
+      FlexData {
+        form: SysExStatus::Complete,
+        address: FlexDataAddress::Channel(3),
+        message: FlexDataMessage::SetKeySignature {
+          sharps_flats: -3,
+          tonic: 3,
+        },
+      }
+    );
+  }
+
+  #[test]
+  fn round_trips() {
+    for message in [
+      FlexDataMessage::SetTempo {
+        ten_nanos_per_quarter: 50_000_000,
+      },
+      FlexDataMessage::SetTimeSignature {
+        numerator: 6,
+        denominator: 3,
+        thirty_second_notes: 8,
+      },
+      FlexDataMessage::SetMetronome {
+        clocks_per_primary_click: 24,
+        bar_accents: [1, 2, 3],
+        subdivision_clicks: [4, 5],
+      },
+      FlexDataMessage::SetKeySignature {
+        sharps_flats: -8,
+        tonic: 0,
+      },
+      FlexDataMessage::MetadataText {
+        status: 0x01,
+        text: FlexText::new(b"kiro"),
+      },
+      FlexDataMessage::PerformanceText {
+        status: 0x01,
+        text: FlexText::new(b"la la la la "),
+      },
+      FlexDataMessage::Other {
+        status_bank: 0x00,
+        status: 0x06,
+        data: [1, 2, 3],
+      },
+    ] {
+      round_trip(FlexData {
+        form: SysExStatus::Complete,
+        address: FlexDataAddress::Channel(15),
+        message,
+      });
+    }
+  }
+
+  #[test]
+  fn assemble_texts() {
+    let text = |form, text: &[u8]| FlexData {
+      form,
+      address: FlexDataAddress::Group,
+      message: FlexDataMessage::PerformanceText {
+        status: 0x01,
+        text: FlexText::new(text),
+      },
+    };
+    let mut assembler = FlexTextAssembler::new(32);
+
+    assert_eq!(
+      assembler.push(&text(SysExStatus::Start, b"Twinkle, twi")),
+      None
+    );
+    assert_eq!(
+      assembler.push(&text(SysExStatus::End, b"nkle")),
+      Some(b"Twinkle, twinkle".as_slice())
+    );
+    assert_eq!(
+      assembler.push(&text(SysExStatus::Continue, b"little star")),
+      None
+    );
+  }
+}
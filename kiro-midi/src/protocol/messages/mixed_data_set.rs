@@ -0,0 +1,261 @@
+use alloc::vec::Vec;
+
+use crate::protocol::{Decode, Encode};
+
+pub const MIXED_DATA_SET_PAYLOAD_DATA: usize = 14;
+
+/// Bytes of a chunk taken by the fields of its header, which are counted in its valid bytes
+const HEADER_BYTES: u16 = 14;
+
+/// Packet of a Mixed Data Set (message type 0x5), with the header or part of the payload of a chunk.
+///
+/// Up to 16 sets can be sent at the same time, identified by their `mds_id`,
+/// and their payloads need to be put back together with a `MixedDataSetAssembler`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MixedDataSet {
+  Header(MixedDataSetHeader),
+  Payload(MixedDataSetPayload),
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MixedDataSetHeader {
+  pub mds_id: u8,
+  /// Bytes in this chunk, including the ones from the header
+  pub valid_bytes: u16,
+  pub chunks: u16,
+  /// Number of this chunk, starting from 1
+  pub chunk: u16,
+  pub manufacturer_id: u16,
+  pub device_id: u16,
+  pub sub_id1: u16,
+  pub sub_id2: u16,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MixedDataSetPayload {
+  pub mds_id: u8,
+  pub data: [u8; MIXED_DATA_SET_PAYLOAD_DATA],
+}
+
+impl MixedDataSet {
+  pub fn mds_id(&self) -> u8 {
+    match self {
+      Self::Header(header) => header.mds_id,
+      Self::Payload(payload) => payload.mds_id,
+    }
+  }
+
+  pub(crate) fn is_valid_status(status: u8) -> bool {
+    status == 0x8 || status == 0x9
+  }
+}
+
+impl Decode for MixedDataSet {
+  fn decode(ump: &[u32]) -> Self {
+    assert_eq!(ump.len(), 4);
+    let mds_id = ((ump[0] >> 16) & 0x0f) as u8;
+    match (ump[0] >> 20) & 0x0f {
+      0x8 => Self::Header(MixedDataSetHeader {
+        mds_id,
+        valid_bytes: ump[0] as u16,
+        chunks: (ump[1] >> 16) as u16,
+        chunk: ump[1] as u16,
+        manufacturer_id: (ump[2] >> 16) as u16,
+        device_id: ump[2] as u16,
+        sub_id1: (ump[3] >> 16) as u16,
+        sub_id2: ump[3] as u16,
+      }),
+      0x9 => {
+        let mut data = [0u8; MIXED_DATA_SET_PAYLOAD_DATA];
+        data[0..2].copy_from_slice(&ump[0].to_be_bytes()[2..4]);
+        for (index, word) in ump[1..4].iter().enumerate() {
+          data[2 + index * 4..6 + index * 4].copy_from_slice(&word.to_be_bytes());
+        }
+        Self::Payload(MixedDataSetPayload { mds_id, data })
+      }
+      _ => unreachable!(),
+    }
+  }
+}
+
+impl Encode<4> for MixedDataSet {
+  fn encode(&self) -> [u32; 4] {
+    match self {
+      Self::Header(header) => [
+        0x50800000 | ((header.mds_id & 0x0f) as u32) << 16 | header.valid_bytes as u32,
+        (header.chunks as u32) << 16 | header.chunk as u32,
+        (header.manufacturer_id as u32) << 16 | header.device_id as u32,
+        (header.sub_id1 as u32) << 16 | header.sub_id2 as u32,
+      ],
+      Self::Payload(payload) => {
+        let data = &payload.data;
+        [
+          0x50900000
+            | ((payload.mds_id & 0x0f) as u32) << 16
+            | u32::from_be_bytes([0, 0, data[0], data[1]]),
+          u32::from_be_bytes([data[2], data[3], data[4], data[5]]),
+          u32::from_be_bytes([data[6], data[7], data[8], data[9]]),
+          u32::from_be_bytes([data[10], data[11], data[12], data[13]]),
+        ]
+      }
+    }
+  }
+}
+
+/// Puts the payloads of the chunks of every Mixed Data Set back together,
+/// dropping the sets longer than the maximum length or with missing chunks.
+///
+/// The packets from different sources can be interleaved, so every source needs its own assembler.
+pub struct MixedDataSetAssembler {
+  sets: Vec<Set>,
+  max_len: usize,
+}
+
+struct Set {
+  header: Option<MixedDataSetHeader>,
+  chunk: u16,
+  remaining: usize,
+  data: Vec<u8>,
+}
+
+impl MixedDataSetAssembler {
+  /// The buffers for the 16 sets are allocated upfront, so it can be used from real-time threads.
+  pub fn new(max_len: usize) -> Self {
+    let sets = (0..16)
+      .map(|_| Set {
+        header: None,
+        chunk: 0,
+        remaining: 0,
+        data: Vec::with_capacity(max_len),
+      })
+      .collect();
+
+    Self { sets, max_len }
+  }
+
+  /// Returns the header of the first chunk and the payload of the whole set once its last byte arrives.
+  pub fn push(&mut self, packet: &MixedDataSet) -> Option<(MixedDataSetHeader, &[u8])> {
+    let max_len = self.max_len;
+    let set = &mut self.sets[(packet.mds_id() & 0x0f) as usize];
+
+    match packet {
+      MixedDataSet::Header(header) => {
+        if header.chunk <= 1 {
+          set.header = Some(*header);
+          set.data.clear();
+        } else if set.header.is_none() || header.chunk != set.chunk + 1 || set.remaining > 0 {
+          set.header = None;
+          return None;
+        }
+        set.chunk = header.chunk;
+        set.remaining = header.valid_bytes.saturating_sub(HEADER_BYTES) as usize;
+        if set.data.len() + set.remaining > max_len {
+          set.header = None;
+          return None;
+        }
+      }
+      MixedDataSet::Payload(payload) => {
+        if set.header.is_none() || set.remaining == 0 {
+          return None;
+        }
+        let len = set.remaining.min(MIXED_DATA_SET_PAYLOAD_DATA);
+        set.data.extend_from_slice(&payload.data[..len]);
+        set.remaining -= len;
+      }
+    }
+
+    let header = set.header?;
+    if set.remaining == 0 && set.chunk >= header.chunks {
+      set.header = None;
+      Some((header, set.data.as_slice()))
+    } else {
+      None
+    }
+  }
+
+  /// Forgets about all the sets being received.
+  pub fn reset(&mut self) {
+    for set in self.sets.iter_mut() {
+      set.header = None;
+      set.data.clear();
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn header(chunks: u16, chunk: u16, payload_bytes: u16) -> MixedDataSet {
+    MixedDataSet::Header(MixedDataSetHeader {
+      mds_id: 3,
+      valid_bytes: HEADER_BYTES + payload_bytes,
+      chunks,
+      chunk,
+      manufacturer_id: 0x0021,
+      device_id: 0x0009,
+      sub_id1: 0x0001,
+      sub_id2: 0x0002,
+    })
+  }
+
+  fn payload(start: u8) -> MixedDataSet {
+    let mut data = [0u8; MIXED_DATA_SET_PAYLOAD_DATA];
+    for (index, byte) in data.iter_mut().enumerate() {
+      *byte = start + index as u8;
+    }
+    MixedDataSet::Payload(MixedDataSetPayload { mds_id: 3, data })
+  }
+
+  #[test]
+  fn decode_and_encode() {
+    let header_ump = [0x5083_0014, 0x0002_0001, 0x0021_0009, 0x0001_0002];
+    assert_eq!(header(2, 1, 6).encode(), header_ump);
+    assert_eq!(MixedDataSet::decode(&header_ump), header(2, 1, 6));
+
+    let payload_ump = [0x5093_0001, 0x0203_0405, 0x0607_0809, 0x0a0b_0c0d];
+    assert_eq!(payload(0).encode(), payload_ump);
+    assert_eq!(MixedDataSet::decode(&payload_ump), payload(0));
+  }
+
+  #[test]
+  fn assemble_chunks() {
+    let mut assembler = MixedDataSetAssembler::new(64);
+
+    assert_eq!(assembler.push(&header(2, 1, 16)), None);
+    assert_eq!(assembler.push(&payload(0)), None);
+    assert_eq!(assembler.push(&payload(14)), None);
+    assert_eq!(assembler.push(&header(2, 2, 3)), None);
+
+    let (first, data) = assembler.push(&payload(16)).unwrap();
+    assert_eq!(
+      first,
+      match header(2, 1, 16) {
+        MixedDataSet::Header(header) => header,
+        MixedDataSet::Payload(_) => unreachable!(),
+      }
+    );
+    assert_eq!(data, (0..19).collect::<Vec<u8>>().as_slice());
+  }
+
+  #[test]
+  fn drop_sets_with_missing_chunks() {
+    let mut assembler = MixedDataSetAssembler::new(64);
+
+    assembler.push(&header(3, 1, 1));
+    assembler.push(&payload(0));
+    assert_eq!(assembler.push(&header(3, 3, 1)), None);
+    assert_eq!(assembler.push(&payload(0)), None);
+  }
+
+  #[test]
+  fn drop_sets_too_long() {
+    let mut assembler = MixedDataSetAssembler::new(8);
+
+    assert_eq!(assembler.push(&header(1, 1, 14)), None);
+    assert_eq!(assembler.push(&payload(0)), None);
+  }
+}
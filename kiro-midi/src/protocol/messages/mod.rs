@@ -1,17 +1,316 @@
 pub mod channel_voice;
+pub mod channel_voice1;
+pub mod flex_data;
+pub mod mixed_data_set;
+pub mod sysex7;
+pub mod sysex8;
+pub mod system;
 pub mod utility;
 
-use crate::protocol::messages::channel_voice::ChannelVoice;
+use core::fmt;
+
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::flex_data::FlexData;
+use crate::protocol::messages::mixed_data_set::MixedDataSet;
+use crate::protocol::messages::sysex7::SysEx7;
+use crate::protocol::messages::sysex8::SysEx8;
+use crate::protocol::messages::system::System;
 use crate::protocol::messages::utility::Utility;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Message {
   pub group: u8,
   pub mtype: MessageType,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum MessageType {
   Utility(Utility),
+  System(System),
+  ChannelVoice1(ChannelVoice1),
   ChannelVoice(ChannelVoice),
+  SysEx7(SysEx7),
+  SysEx8(SysEx8),
+  MixedDataSet(MixedDataSet),
+  FlexData(FlexData),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageError {
+  OutOfRange {
+    field: &'static str,
+    value: u32,
+    max: u32,
+  },
+}
+
+impl fmt::Display for MessageError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::OutOfRange { field, value, max } => {
+        write!(f, "The {} {} is out of range (0..={})", field, value, max)
+      }
+    }
+  }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for MessageError {}
+
+fn check(field: &'static str, value: u32, max: u32) -> Result<u8, MessageError> {
+  if value <= max {
+    Ok(value as u8)
+  } else {
+    Err(MessageError::OutOfRange { field, value, max })
+  }
+}
+
+fn check_group_channel(group: u8, channel: u8) -> Result<(), MessageError> {
+  check("group", group as u32, 0x0f)?;
+  check("channel", channel as u32, 0x0f)?;
+  Ok(())
+}
+
+/// Constructors for the channel voice messages, checking that the values are within their range.
+///
+/// The ones without a suffix create MIDI 2.0 messages, and the ones ending in 1 create MIDI 1.0 messages.
+impl Message {
+  pub fn new(group: u8, mtype: MessageType) -> Self {
+    Self { group, mtype }
+  }
+
+  pub fn note_on(group: u8, channel: u8, note: u8, velocity: u16) -> Result<Self, MessageError> {
+    Self::channel_voice(
+      group,
+      channel,
+      ChanelVoiceMessage::NoteOn {
+        note: check("note", note as u32, 0x7f)?,
+        velocity,
+        attr_type: 0,
+        attr_data: 0,
+      },
+    )
+  }
+
+  pub fn note_off(group: u8, channel: u8, note: u8, velocity: u16) -> Result<Self, MessageError> {
+    Self::channel_voice(
+      group,
+      channel,
+      ChanelVoiceMessage::NoteOff {
+        note: check("note", note as u32, 0x7f)?,
+        velocity,
+        attr_type: 0,
+        attr_data: 0,
+      },
+    )
+  }
+
+  pub fn poly_pressure(group: u8, channel: u8, note: u8, data: u32) -> Result<Self, MessageError> {
+    Self::channel_voice(
+      group,
+      channel,
+      ChanelVoiceMessage::PolyPressure {
+        note: check("note", note as u32, 0x7f)?,
+        data,
+      },
+    )
+  }
+
+  pub fn cc(group: u8, channel: u8, index: u8, data: u32) -> Result<Self, MessageError> {
+    Self::channel_voice(
+      group,
+      channel,
+      ChanelVoiceMessage::ControlChange {
+        index: check("controller", index as u32, 0x7f)?,
+        data,
+      },
+    )
+  }
+
+  pub fn program_change(
+    group: u8,
+    channel: u8,
+    program: u8,
+    bank: Option<u16>,
+  ) -> Result<Self, MessageError> {
+    if let Some(bank) = bank {
+      check("bank", bank as u32, 0x3fff)?;
+    }
+    Self::channel_voice(
+      group,
+      channel,
+      ChanelVoiceMessage::ProgramChange {
+        program: check("program", program as u32, 0x7f)?,
+        bank,
+      },
+    )
+  }
+
+  pub fn channel_pressure(group: u8, channel: u8, data: u32) -> Result<Self, MessageError> {
+    Self::channel_voice(group, channel, ChanelVoiceMessage::ChannelPressure { data })
+  }
+
+  /// With `data` as an unsigned bipolar value centered at 0x80000000.
+  pub fn pitch_bend(group: u8, channel: u8, data: u32) -> Result<Self, MessageError> {
+    Self::channel_voice(group, channel, ChanelVoiceMessage::PitchBend { data })
+  }
+
+  pub fn note_on1(group: u8, channel: u8, note: u8, velocity: u8) -> Result<Self, MessageError> {
+    Self::channel_voice1(
+      group,
+      channel,
+      ChannelVoice1Message::NoteOn {
+        note: check("note", note as u32, 0x7f)?,
+        velocity: check("velocity", velocity as u32, 0x7f)?,
+      },
+    )
+  }
+
+  pub fn note_off1(group: u8, channel: u8, note: u8, velocity: u8) -> Result<Self, MessageError> {
+    Self::channel_voice1(
+      group,
+      channel,
+      ChannelVoice1Message::NoteOff {
+        note: check("note", note as u32, 0x7f)?,
+        velocity: check("velocity", velocity as u32, 0x7f)?,
+      },
+    )
+  }
+
+  pub fn poly_pressure1(group: u8, channel: u8, note: u8, data: u8) -> Result<Self, MessageError> {
+    Self::channel_voice1(
+      group,
+      channel,
+      ChannelVoice1Message::PolyPressure {
+        note: check("note", note as u32, 0x7f)?,
+        data: check("pressure", data as u32, 0x7f)?,
+      },
+    )
+  }
+
+  pub fn cc1(group: u8, channel: u8, index: u8, data: u8) -> Result<Self, MessageError> {
+    Self::channel_voice1(
+      group,
+      channel,
+      ChannelVoice1Message::ControlChange {
+        index: check("controller", index as u32, 0x7f)?,
+        data: check("value", data as u32, 0x7f)?,
+      },
+    )
+  }
+
+  pub fn program_change1(group: u8, channel: u8, program: u8) -> Result<Self, MessageError> {
+    Self::channel_voice1(
+      group,
+      channel,
+      ChannelVoice1Message::ProgramChange {
+        program: check("program", program as u32, 0x7f)?,
+      },
+    )
+  }
+
+  pub fn channel_pressure1(group: u8, channel: u8, data: u8) -> Result<Self, MessageError> {
+    Self::channel_voice1(
+      group,
+      channel,
+      ChannelVoice1Message::ChannelPressure {
+        data: check("pressure", data as u32, 0x7f)?,
+      },
+    )
+  }
+
+  /// With `data` as a 14 bits unsigned bipolar value centered at 0x2000.
+  pub fn pitch_bend1(group: u8, channel: u8, data: u16) -> Result<Self, MessageError> {
+    check("pitch bend", data as u32, 0x3fff)?;
+    Self::channel_voice1(group, channel, ChannelVoice1Message::PitchBend { data })
+  }
+
+  fn channel_voice(
+    group: u8,
+    channel: u8,
+    message: ChanelVoiceMessage,
+  ) -> Result<Self, MessageError> {
+    check_group_channel(group, channel)?;
+    Ok(Self::new(
+      group,
+      MessageType::ChannelVoice(ChannelVoice { channel, message }),
+    ))
+  }
+
+  fn channel_voice1(
+    group: u8,
+    channel: u8,
+    message: ChannelVoice1Message,
+  ) -> Result<Self, MessageError> {
+    check_group_channel(group, channel)?;
+    Ok(Self::new(
+      group,
+      MessageType::ChannelVoice1(ChannelVoice1 { channel, message }),
+    ))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn constructors() {
+    assert_eq!(
+      Message::note_on(1, 2, 60, 0xffff),
+      Ok(Message {
+        group: 1,
+        mtype: MessageType::ChannelVoice(ChannelVoice {
+          channel: 2,
+          message: ChanelVoiceMessage::NoteOn {
+            note: 60,
+            velocity: 0xffff,
+            attr_type: 0,
+            attr_data: 0,
+          },
+        }),
+      })
+    );
+    assert_eq!(
+      Message::cc1(0, 15, 7, 100),
+      Ok(Message {
+        group: 0,
+        mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+          channel: 15,
+          message: ChannelVoice1Message::ControlChange {
+            index: 7,
+            data: 100
+          },
+        }),
+      })
+    );
+    assert!(Message::pitch_bend1(0, 0, 0x3fff).is_ok());
+  }
+
+  #[test]
+  fn constructors_check_the_ranges() {
+    assert_eq!(
+      Message::note_on1(0, 16, 60, 100),
+      Err(MessageError::OutOfRange {
+        field: "channel",
+        value: 16,
+        max: 15
+      })
+    );
+    assert_eq!(
+      Message::cc(16, 0, 7, 0),
+      Err(MessageError::OutOfRange {
+        field: "group",
+        value: 16,
+        max: 15
+      })
+    );
+    assert!(Message::note_off(0, 0, 128, 0).is_err());
+    assert!(Message::cc1(0, 0, 7, 128).is_err());
+    assert!(Message::pitch_bend1(0, 0, 0x4000).is_err());
+    assert!(Message::program_change(0, 0, 1, Some(0x4000)).is_err());
+  }
 }
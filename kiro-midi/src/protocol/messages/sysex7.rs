@@ -0,0 +1,243 @@
+use alloc::vec::Vec;
+
+use crate::protocol::{Decode, Encode};
+
+pub const SYSEX7_MAX_DATA: usize = 6;
+
+/// Position of a SysEx7, SysEx8 or Flex Data packet within its message
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysExStatus {
+  Complete,
+  Start,
+  Continue,
+  End,
+}
+
+/// Packet of a 7 bits System Exclusive message (message type 0x3), carrying up to 6 bytes of data.
+///
+/// Messages longer than that are split into several packets, which can be put back together
+/// with a `SysExAssembler`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SysEx7 {
+  pub status: SysExStatus,
+  len: u8,
+  data: [u8; SYSEX7_MAX_DATA],
+}
+
+impl SysEx7 {
+  /// Creates a packet with the first 6 bytes of data at most.
+  pub fn new(status: SysExStatus, data: &[u8]) -> Self {
+    let len = data.len().min(SYSEX7_MAX_DATA);
+    let mut packet = Self {
+      status,
+      len: len as u8,
+      data: [0; SYSEX7_MAX_DATA],
+    };
+    for (byte, data) in packet.data.iter_mut().zip(data[..len].iter()) {
+      *byte = data & 0x7f;
+    }
+    packet
+  }
+
+  pub fn data(&self) -> &[u8] {
+    // The length is only out of range when deserialized from invalid data
+    &self.data[..(self.len as usize).min(SYSEX7_MAX_DATA)]
+  }
+
+  pub(crate) fn is_valid_status(status: u8) -> bool {
+    status <= 0x3
+  }
+}
+
+impl Decode for SysEx7 {
+  fn decode(ump: &[u32]) -> Self {
+    assert_eq!(ump.len(), 2);
+    let status = match (ump[0] >> 20) & 0x0f {
+      0x0 => SysExStatus::Complete,
+      0x1 => SysExStatus::Start,
+      0x2 => SysExStatus::Continue,
+      0x3 => SysExStatus::End,
+      _ => unreachable!(),
+    };
+    let len = ((ump[0] >> 16) & 0x0f) as usize;
+    let [_, _, data0, data1] = ump[0].to_be_bytes();
+    let [data2, data3, data4, data5] = ump[1].to_be_bytes();
+    let data = [data0, data1, data2, data3, data4, data5];
+    Self::new(status, &data[..len.min(SYSEX7_MAX_DATA)])
+  }
+}
+
+impl Encode<2> for SysEx7 {
+  fn encode(&self) -> [u32; 2] {
+    let status = match self.status {
+      SysExStatus::Complete => 0x0,
+      SysExStatus::Start => 0x1,
+      SysExStatus::Continue => 0x2,
+      SysExStatus::End => 0x3,
+    };
+    let data = &self.data;
+    [
+      0x30000000
+        | status << 20
+        | (self.data().len() as u32) << 16
+        | u32::from_be_bytes([0, 0, data[0], data[1]]),
+      u32::from_be_bytes([data[2], data[3], data[4], data[5]]),
+    ]
+  }
+}
+
+/// What a SysEx message is about, read from its first bytes:
+/// the manufacturer ID, or the sub-ID of the universal messages.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SysExId {
+  /// One byte manufacturer ID, such as 0x41 for Roland
+  Manufacturer(u8),
+  /// Three bytes manufacturer ID, with the two bytes after the 0x00, such as (0x20, 0x29) for Focusrite/Novation
+  ExtendedManufacturer(u8, u8),
+  /// Universal non real time message (0x7e) with its sub-ID #1, such as 0x0d for MIDI-CI
+  NonRealTime(u8),
+  /// Universal real time message (0x7f) with its sub-ID #1, such as 0x06 for MMC
+  RealTime(u8),
+}
+
+impl SysExId {
+  /// Reads the ID from the data of a message, without the `F0` byte.
+  ///
+  /// The universal messages have the device ID before the sub-ID, so they need 3 bytes at least.
+  pub fn from_data(data: &[u8]) -> Option<Self> {
+    match data {
+      [0x00, id1, id2, ..] => Some(Self::ExtendedManufacturer(*id1, *id2)),
+      [0x7e, _, sub_id, ..] => Some(Self::NonRealTime(*sub_id)),
+      [0x7f, _, sub_id, ..] => Some(Self::RealTime(*sub_id)),
+      [0x00 | 0x7e | 0x7f, ..] | [] => None,
+      [id, ..] => Some(Self::Manufacturer(*id)),
+    }
+  }
+}
+
+/// Puts the data of the SysEx7 packets back together, dropping the messages longer than the maximum length.
+///
+/// The packets from different sources can be interleaved, so every source needs its own assembler.
+pub struct SysExAssembler {
+  data: Vec<u8>,
+  max_len: usize,
+  receiving: bool,
+}
+
+impl SysExAssembler {
+  /// The buffer is allocated upfront, so it can be used from real-time threads.
+  pub fn new(max_len: usize) -> Self {
+    Self {
+      data: Vec::with_capacity(max_len),
+      max_len,
+      receiving: false,
+    }
+  }
+
+  /// Returns the data of the message once its last packet arrives, without the `F0` and `F7` bytes.
+  pub fn push(&mut self, packet: &SysEx7) -> Option<&[u8]> {
+    if matches!(packet.status, SysExStatus::Complete | SysExStatus::Start) {
+      self.data.clear();
+      self.receiving = true;
+    }
+
+    if !self.receiving {
+      return None;
+    }
+
+    if self.data.len() + packet.data().len() > self.max_len {
+      self.receiving = false;
+      return None;
+    }
+    self.data.extend_from_slice(packet.data());
+
+    match packet.status {
+      SysExStatus::Complete | SysExStatus::End => {
+        self.receiving = false;
+        Some(self.data.as_slice())
+      }
+      SysExStatus::Start | SysExStatus::Continue => None,
+    }
+  }
+
+  /// Forgets about the message being received.
+  pub fn reset(&mut self) {
+    self.data.clear();
+    self.receiving = false;
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decode_and_encode() {
+    let packet = SysEx7::new(SysExStatus::Start, &[1, 2, 3, 4, 5, 6]);
+
+    assert_eq!(packet.encode(), [0x3016_0102, 0x0304_0506]);
+    assert_eq!(SysEx7::decode(&[0x3016_0102, 0x0304_0506]), packet);
+    assert_eq!(SysEx7::decode(&[0x3032_0708, 0x0000_0000]).data(), &[7, 8]);
+  }
+
+  #[test]
+  fn sysex_ids() {
+    assert_eq!(
+      SysExId::from_data(&[0x41, 0x10, 0x42]),
+      Some(SysExId::Manufacturer(0x41))
+    );
+    assert_eq!(
+      SysExId::from_data(&[0x00, 0x20, 0x29, 0x02]),
+      Some(SysExId::ExtendedManufacturer(0x20, 0x29))
+    );
+    assert_eq!(
+      SysExId::from_data(&[0x7e, 0x7f, 0x0d, 0x70]),
+      Some(SysExId::NonRealTime(0x0d))
+    );
+    assert_eq!(
+      SysExId::from_data(&[0x7f, 0x7f, 0x06, 0x02]),
+      Some(SysExId::RealTime(0x06))
+    );
+    assert_eq!(SysExId::from_data(&[0x7e, 0x7f]), None);
+    assert_eq!(SysExId::from_data(&[]), None);
+  }
+
+  #[test]
+  fn assemble_packets() {
+    let mut assembler = SysExAssembler::new(16);
+
+    assert_eq!(
+      assembler.push(&SysEx7::new(SysExStatus::Start, &[1, 2, 3, 4, 5, 6])),
+      None
+    );
+    assert_eq!(
+      assembler.push(&SysEx7::new(SysExStatus::Continue, &[7, 8, 9, 10, 11, 12])),
+      None
+    );
+    assert_eq!(
+      assembler.push(&SysEx7::new(SysExStatus::End, &[13])),
+      Some((1..=13).collect::<Vec<u8>>().as_slice())
+    );
+    assert_eq!(
+      assembler.push(&SysEx7::new(SysExStatus::Complete, &[0x7e])),
+      Some([0x7e].as_slice())
+    );
+  }
+
+  #[test]
+  fn drop_messages_too_long() {
+    let mut assembler = SysExAssembler::new(8);
+
+    assembler.push(&SysEx7::new(SysExStatus::Start, &[0; 6]));
+    assembler.push(&SysEx7::new(SysExStatus::Continue, &[0; 6]));
+    assert_eq!(assembler.push(&SysEx7::new(SysExStatus::End, &[0])), None);
+
+    assert_eq!(
+      assembler.push(&SysEx7::new(SysExStatus::Complete, &[1])),
+      Some([1].as_slice())
+    );
+  }
+}
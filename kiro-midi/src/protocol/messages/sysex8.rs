@@ -0,0 +1,217 @@
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::protocol::messages::sysex7::SysExStatus;
+use crate::protocol::{Decode, Encode};
+
+pub const SYSEX8_MAX_DATA: usize = 13;
+
+/// Packet of an 8 bits System Exclusive message (message type 0x5), carrying up to 13 bytes of data.
+///
+/// Several messages can be sent at the same time through different streams,
+/// so the packets need to be put back together by stream with a `SysEx8Assembler`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SysEx8 {
+  pub status: SysExStatus,
+  pub stream_id: u8,
+  len: u8,
+  data: [u8; SYSEX8_MAX_DATA],
+}
+
+impl SysEx8 {
+  /// Creates a packet with the first 13 bytes of data at most.
+  pub fn new(status: SysExStatus, stream_id: u8, data: &[u8]) -> Self {
+    let len = data.len().min(SYSEX8_MAX_DATA);
+    let mut packet = Self {
+      status,
+      stream_id,
+      len: len as u8,
+      data: [0; SYSEX8_MAX_DATA],
+    };
+    packet.data[..len].copy_from_slice(&data[..len]);
+    packet
+  }
+
+  pub fn data(&self) -> &[u8] {
+    // The length is only out of range when deserialized from invalid data
+    &self.data[..(self.len as usize).min(SYSEX8_MAX_DATA)]
+  }
+
+  pub(crate) fn is_valid_status(status: u8) -> bool {
+    status <= 0x3
+  }
+}
+
+impl Decode for SysEx8 {
+  fn decode(ump: &[u32]) -> Self {
+    assert_eq!(ump.len(), 4);
+    let status = match (ump[0] >> 20) & 0x0f {
+      0x0 => SysExStatus::Complete,
+      0x1 => SysExStatus::Start,
+      0x2 => SysExStatus::Continue,
+      0x3 => SysExStatus::End,
+      _ => unreachable!(),
+    };
+    // The number of bytes includes the stream id
+    let len = (((ump[0] >> 16) & 0x0f) as usize).saturating_sub(1);
+    let [_, _, stream_id, data0] = ump[0].to_be_bytes();
+    let mut data = [0u8; SYSEX8_MAX_DATA];
+    data[0] = data0;
+    for (index, word) in ump[1..4].iter().enumerate() {
+      data[1 + index * 4..5 + index * 4].copy_from_slice(&word.to_be_bytes());
+    }
+    Self::new(status, stream_id, &data[..len.min(SYSEX8_MAX_DATA)])
+  }
+}
+
+impl Encode<4> for SysEx8 {
+  fn encode(&self) -> [u32; 4] {
+    let status = match self.status {
+      SysExStatus::Complete => 0x0,
+      SysExStatus::Start => 0x1,
+      SysExStatus::Continue => 0x2,
+      SysExStatus::End => 0x3,
+    };
+    let data = &self.data;
+    [
+      0x50000000
+        | status << 20
+        | (self.data().len() as u32 + 1) << 16
+        | u32::from_be_bytes([0, 0, self.stream_id, data[0]]),
+      u32::from_be_bytes([data[1], data[2], data[3], data[4]]),
+      u32::from_be_bytes([data[5], data[6], data[7], data[8]]),
+      u32::from_be_bytes([data[9], data[10], data[11], data[12]]),
+    ]
+  }
+}
+
+/// Puts the data of the SysEx8 packets back together by stream,
+/// dropping the messages longer than the maximum length.
+///
+/// The packets from different sources can be interleaved, so every source needs its own assembler.
+pub struct SysEx8Assembler {
+  streams: BTreeMap<u8, Stream>,
+  max_len: usize,
+}
+
+struct Stream {
+  data: Vec<u8>,
+  receiving: bool,
+}
+
+impl SysEx8Assembler {
+  pub fn new(max_len: usize) -> Self {
+    Self {
+      streams: BTreeMap::new(),
+      max_len,
+    }
+  }
+
+  /// Returns the data of the message once the last packet of its stream arrives.
+  ///
+  /// The buffer of a stream is allocated with its first message, and reused for the following ones.
+  pub fn push(&mut self, packet: &SysEx8) -> Option<&[u8]> {
+    let max_len = self.max_len;
+    let stream = match packet.status {
+      SysExStatus::Complete | SysExStatus::Start => {
+        let stream = self
+          .streams
+          .entry(packet.stream_id)
+          .or_insert_with(|| Stream {
+            data: Vec::with_capacity(max_len),
+            receiving: false,
+          });
+        stream.data.clear();
+        stream.receiving = true;
+        stream
+      }
+      SysExStatus::Continue | SysExStatus::End => self
+        .streams
+        .get_mut(&packet.stream_id)
+        .filter(|stream| stream.receiving)?,
+    };
+
+    if stream.data.len() + packet.data().len() > max_len {
+      stream.receiving = false;
+      return None;
+    }
+    stream.data.extend_from_slice(packet.data());
+
+    match packet.status {
+      SysExStatus::Complete | SysExStatus::End => {
+        stream.receiving = false;
+        Some(stream.data.as_slice())
+      }
+      SysExStatus::Start | SysExStatus::Continue => None,
+    }
+  }
+
+  /// Forgets about all the messages being received.
+  pub fn reset(&mut self) {
+    for stream in self.streams.values_mut() {
+      stream.data.clear();
+      stream.receiving = false;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decode_and_encode() {
+    let packet = SysEx8::new(SysExStatus::Start, 7, &(1..=13).collect::<Vec<u8>>());
+    let ump = [0x501e_0701, 0x0203_0405, 0x0607_0809, 0x0a0b_0c0d];
+
+    assert_eq!(packet.encode(), ump);
+    assert_eq!(SysEx8::decode(&ump), packet);
+    assert_eq!(
+      SysEx8::decode(&[0x5034_0281, 0xff00_0000, 0, 0]).data(),
+      &[0x81, 0xff, 0x00]
+    );
+  }
+
+  #[test]
+  fn assemble_interleaved_streams() {
+    let mut assembler = SysEx8Assembler::new(32);
+
+    assert_eq!(
+      assembler.push(&SysEx8::new(SysExStatus::Start, 1, &[1, 2])),
+      None
+    );
+    assert_eq!(
+      assembler.push(&SysEx8::new(SysExStatus::Start, 2, &[0x80])),
+      None
+    );
+    assert_eq!(
+      assembler.push(&SysEx8::new(SysExStatus::End, 1, &[3])),
+      Some([1, 2, 3].as_slice())
+    );
+    assert_eq!(
+      assembler.push(&SysEx8::new(SysExStatus::Continue, 1, &[4])),
+      None
+    );
+    assert_eq!(
+      assembler.push(&SysEx8::new(SysExStatus::End, 2, &[0xff])),
+      Some([0x80, 0xff].as_slice())
+    );
+  }
+
+  #[test]
+  fn drop_messages_too_long() {
+    let mut assembler = SysEx8Assembler::new(16);
+
+    assembler.push(&SysEx8::new(SysExStatus::Start, 0, &[0; 13]));
+    assert_eq!(
+      assembler.push(&SysEx8::new(SysExStatus::End, 0, &[0; 13])),
+      None
+    );
+
+    assert_eq!(
+      assembler.push(&SysEx8::new(SysExStatus::Complete, 0, &[1])),
+      Some([1].as_slice())
+    );
+  }
+}
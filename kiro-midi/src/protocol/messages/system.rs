@@ -0,0 +1,113 @@
+use crate::protocol::{Decode, Encode};
+
+/// System Common and System Real Time messages (message type 0x1)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum System {
+  TimeCode(u8),
+  SongPositionPointer(u16),
+  SongSelect(u8),
+  TuneRequest,
+  TimingClock,
+  Start,
+  Continue,
+  Stop,
+  ActiveSensing,
+  Reset,
+}
+
+impl System {
+  pub(crate) fn is_valid_status(status: u8) -> bool {
+    matches!(
+      status,
+      0xf1 | 0xf2 | 0xf3 | 0xf6 | 0xf8 | 0xfa | 0xfb | 0xfc | 0xfe | 0xff
+    )
+  }
+}
+
+impl Decode for System {
+  fn decode(ump: &[u32]) -> Self {
+    assert_eq!(ump.len(), 1);
+    let status = ((ump[0] >> 16) & 0xff) as u8;
+    let data1 = ((ump[0] >> 8) & 0x7f) as u8;
+    let data2 = (ump[0] & 0x7f) as u8;
+    match status {
+      0xf1 => Self::TimeCode(data1),
+      0xf2 => Self::SongPositionPointer(((data2 as u16) << 7) | data1 as u16),
+      0xf3 => Self::SongSelect(data1),
+      0xf6 => Self::TuneRequest,
+      0xf8 => Self::TimingClock,
+      0xfa => Self::Start,
+      0xfb => Self::Continue,
+      0xfc => Self::Stop,
+      0xfe => Self::ActiveSensing,
+      0xff => Self::Reset,
+      _ => unreachable!(),
+    }
+  }
+}
+
+impl Encode<1> for System {
+  fn encode(&self) -> [u32; 1] {
+    let (status, data1, data2) = match *self {
+      Self::TimeCode(data) => (0xf1, data, 0),
+      Self::SongPositionPointer(position) => (
+        0xf2,
+        (position & 0x7f) as u8,
+        ((position >> 7) & 0x7f) as u8,
+      ),
+      Self::SongSelect(song) => (0xf3, song, 0),
+      Self::TuneRequest => (0xf6, 0, 0),
+      Self::TimingClock => (0xf8, 0, 0),
+      Self::Start => (0xfa, 0, 0),
+      Self::Continue => (0xfb, 0, 0),
+      Self::Stop => (0xfc, 0, 0),
+      Self::ActiveSensing => (0xfe, 0, 0),
+      Self::Reset => (0xff, 0, 0),
+    };
+    [0x10000000 | (status as u32) << 16 | ((data1 & 0x7f) as u32) << 8 | (data2 & 0x7f) as u32]
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn decode_time_code() {
+    assert_eq!(System::decode(&[0x10f13500]), System::TimeCode(0x35));
+  }
+
+  #[test]
+  fn decode_song_position_pointer() {
+    assert_eq!(
+      System::decode(&[0x10f2017f]),
+      System::SongPositionPointer(0x3f81)
+    );
+  }
+
+  #[test]
+  fn decode_song_select() {
+    assert_eq!(System::decode(&[0x10f30500]), System::SongSelect(5));
+  }
+
+  #[test]
+  fn decode_single_byte_messages() {
+    assert_eq!(System::decode(&[0x10f60000]), System::TuneRequest);
+    assert_eq!(System::decode(&[0x10f80000]), System::TimingClock);
+    assert_eq!(System::decode(&[0x10fa0000]), System::Start);
+    assert_eq!(System::decode(&[0x10fb0000]), System::Continue);
+    assert_eq!(System::decode(&[0x10fc0000]), System::Stop);
+    assert_eq!(System::decode(&[0x10fe0000]), System::ActiveSensing);
+    assert_eq!(System::decode(&[0x10ff0000]), System::Reset);
+  }
+
+  #[test]
+  fn encode() {
+    assert_eq!(System::TimeCode(0x35).encode(), [0x10f13500]);
+    assert_eq!(System::SongPositionPointer(0x1234).encode(), [0x10f23424]);
+    assert_eq!(System::SongSelect(0x05).encode(), [0x10f30500]);
+    assert_eq!(System::TimingClock.encode(), [0x10f80000]);
+    assert_eq!(System::Reset.encode(), [0x10ff0000]);
+  }
+}
@@ -1,18 +1,73 @@
-use crate::protocol::Decode;
+use crate::protocol::{Decode, Encode};
 
+/// Utility messages (message type 0x0)
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Utility {
   Noop,
-  // TODO ...
+  /// The time of the sender when the message was sent, in ticks of 1/31250 seconds
+  JrClock {
+    sender_time: u16,
+  },
+  /// The time of the sender when the message following this one was sent, in ticks of 1/31250 seconds
+  JrTimestamp {
+    sender_time: u16,
+  },
+}
+
+impl Utility {
+  pub(crate) fn is_valid_status(status: u8) -> bool {
+    matches!(status, 0b0000 | 0b0001 | 0b0010)
+  }
 }
 
 impl Decode for Utility {
   fn decode(ump: &[u32]) -> Self {
     assert_eq!(ump.len(), 1);
     let status = ((ump[0] >> 20) & 0x0f) as u8;
+    let sender_time = (ump[0] & 0xffff) as u16;
     match status {
       0b0000 => Self::Noop,
-      _ => unimplemented!(),
+      0b0001 => Self::JrClock { sender_time },
+      0b0010 => Self::JrTimestamp { sender_time },
+      _ => unreachable!(),
+    }
+  }
+}
+
+impl Encode<1> for Utility {
+  fn encode(&self) -> [u32; 1] {
+    match *self {
+      Self::Noop => [0x00000000],
+      Self::JrClock { sender_time } => [0x00100000 | sender_time as u32],
+      Self::JrTimestamp { sender_time } => [0x00200000 | sender_time as u32],
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn jitter_reduction() {
+    for utility in [
+      Utility::Noop,
+      Utility::JrClock {
+        sender_time: 0x1234,
+      },
+      Utility::JrTimestamp {
+        sender_time: 0xfedc,
+      },
+    ] {
+      assert_eq!(Utility::decode(&utility.encode()), utility);
     }
+    assert_eq!(
+      Utility::JrTimestamp {
+        sender_time: 0x0102
+      }
+      .encode(),
+      [0x00200102]
+    );
   }
 }
@@ -0,0 +1,1063 @@
+//! Conversion between the MIDI 1.0 byte stream protocol and UMP packets.
+
+use crate::filter::Filter;
+use crate::protocol::controllers;
+use crate::protocol::decoder::DecoderProtocol2;
+use crate::protocol::encoder::{encode_message, Encode, Ump};
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::sysex7::{SysEx7, SysExStatus, SYSEX7_MAX_DATA};
+use crate::protocol::messages::{Message, MessageType};
+use crate::protocol::parameters::{scale_up, scale_up_14, ParameterAssembler};
+
+/// Parser for MIDI 1.0 byte streams, as received from the serial, BLE or WinMM transports.
+///
+/// It consumes chunks of any length, keeping the incomplete messages for the next ones.
+/// It supports running status and real time messages interleaved within other messages,
+/// and converts SysEx messages into SysEx7 packets as their data arrives, so they can span
+/// any number of chunks. A SysEx message is also ended by any status byte other than the real time ones.
+///
+/// The malformed data (undefined status bytes, ends of SysEx without one started, data bytes without a status,
+/// and incomplete messages interrupted by another status) is discarded, counting the bytes lost.
+pub struct Parser {
+  group: u8,
+  running_status: Option<u8>,
+  buffer: [u8; 3],
+  len: usize,
+  expected_len: usize,
+  sysex: Option<SysExState>,
+  discarded: u64,
+}
+
+/// The SysEx message being received, which keeps the last packet until knowing if it's the final one
+struct SysExState {
+  data: [u8; SYSEX7_MAX_DATA],
+  len: usize,
+  started: bool,
+}
+
+impl Parser {
+  pub fn new(group: u8) -> Self {
+    Self {
+      group,
+      running_status: None,
+      buffer: [0; 3],
+      len: 0,
+      expected_len: 0,
+      sysex: None,
+      discarded: 0,
+    }
+  }
+
+  /// Number of bytes discarded as malformed so far.
+  pub fn discarded(&self) -> u64 {
+    self.discarded
+  }
+
+  /// Consumes the next byte from the stream, calling `f` with the UMP packets completed by it.
+  pub fn push<F>(&mut self, byte: u8, mut f: F)
+  where
+    F: FnMut(Ump),
+  {
+    match byte {
+      0xf8..=0xff => match message_to_ump(self.group, &[byte]) {
+        Some(word) => f(Ump::from_slice(&[word])),
+        None => self.discarded += 1,
+      },
+      0xf0 => {
+        self.end_sysex(&mut f);
+        self.interrupt();
+        self.sysex = Some(SysExState {
+          data: [0; SYSEX7_MAX_DATA],
+          len: 0,
+          started: false,
+        });
+      }
+      0xf7 if self.sysex.is_some() => self.end_sysex(&mut f),
+      0xf7 => self.discarded += 1,
+      0x80..=0xf6 => {
+        self.end_sysex(&mut f);
+        self.interrupt();
+        match data_len(byte) {
+          Some(data_len) => {
+            if byte < 0xf0 {
+              self.running_status = Some(byte);
+            }
+            self.start(byte, data_len, &mut f);
+          }
+          None => self.discarded += 1,
+        }
+      }
+      _ => {
+        if let Some(sysex) = self.sysex.as_mut() {
+          if sysex.len == SYSEX7_MAX_DATA {
+            let status = if sysex.started {
+              SysExStatus::Continue
+            } else {
+              SysExStatus::Start
+            };
+            f(sysex_packet(self.group, status, &sysex.data));
+            sysex.started = true;
+            sysex.len = 0;
+          }
+          sysex.data[sysex.len] = byte;
+          sysex.len += 1;
+          return;
+        }
+
+        if self.len == 0 {
+          match self
+            .running_status
+            .and_then(|status| Some((status, data_len(status)?)))
+          {
+            Some((status, data_len)) => self.start(status, data_len, &mut f),
+            None => {
+              self.discarded += 1;
+              return;
+            }
+          }
+        }
+        self.buffer[self.len] = byte;
+        self.len += 1;
+        self.complete(&mut f);
+      }
+    }
+  }
+
+  /// Consumes a chunk of bytes, calling `f` for every UMP packet completed.
+  pub fn parse<F>(&mut self, bytes: &[u8], mut f: F)
+  where
+    F: FnMut(Ump),
+  {
+    for byte in bytes.iter().cloned() {
+      self.push(byte, &mut f);
+    }
+  }
+
+  /// Consumes a chunk of bytes, calling `f` for every message completed.
+  pub fn parse_messages<F>(&mut self, bytes: &[u8], mut f: F)
+  where
+    F: FnMut(Message),
+  {
+    let filter = Filter::new();
+    let mut decoder = DecoderProtocol2::default();
+    self.parse(bytes, |ump| {
+      for word in ump.as_slice().iter().cloned() {
+        if let Ok(Some(message)) = decoder.next(word, &filter) {
+          f(message);
+        }
+      }
+    });
+  }
+
+  /// Forgets about the incomplete messages, including the SysEx one, without sending them.
+  pub fn reset(&mut self) {
+    self.clear_status();
+    self.sysex = None;
+  }
+
+  fn start<F>(&mut self, status: u8, data_len: usize, f: &mut F)
+  where
+    F: FnMut(Ump),
+  {
+    self.buffer[0] = status;
+    self.len = 1;
+    self.expected_len = data_len + 1;
+    self.complete(f);
+  }
+
+  fn complete<F>(&mut self, f: &mut F)
+  where
+    F: FnMut(Ump),
+  {
+    if self.len == self.expected_len {
+      self.len = 0;
+      if let Some(word) = message_to_ump(self.group, &self.buffer[..self.expected_len]) {
+        f(Ump::from_slice(&[word]));
+      }
+    }
+  }
+
+  fn end_sysex<F>(&mut self, f: &mut F)
+  where
+    F: FnMut(Ump),
+  {
+    if let Some(sysex) = self.sysex.take() {
+      let status = if sysex.started {
+        SysExStatus::End
+      } else {
+        SysExStatus::Complete
+      };
+      f(sysex_packet(self.group, status, &sysex.data[..sysex.len]));
+    }
+  }
+
+  fn clear_status(&mut self) {
+    self.running_status = None;
+    self.len = 0;
+  }
+
+  /// Clears the status for a new one, discarding the incomplete message.
+  fn interrupt(&mut self) {
+    self.discarded += self.len as u64;
+    self.clear_status();
+  }
+}
+
+fn sysex_packet(group: u8, status: SysExStatus, data: &[u8]) -> Ump {
+  encode_message(&Message {
+    group,
+    mtype: MessageType::SysEx7(SysEx7::new(status, data)),
+  })
+}
+
+/// Number of data bytes following a MIDI 1.0 status byte,
+/// or `None` for SysEx and undefined status bytes.
+pub fn data_len(status: u8) -> Option<usize> {
+  match status {
+    0x80..=0xbf | 0xe0..=0xef => Some(2),
+    0xc0..=0xdf => Some(1),
+    0xf1 | 0xf3 => Some(1),
+    0xf2 => Some(2),
+    0xf6 | 0xf8 | 0xfa | 0xfb | 0xfc | 0xfe | 0xff => Some(0),
+    _ => None,
+  }
+}
+
+/// Converts a complete MIDI 1.0 message into a single UMP word
+/// (message type 0x1 for system messages and 0x2 for channel voice messages).
+///
+/// It returns `None` for SysEx, undefined or incomplete messages.
+pub fn message_to_ump(group: u8, bytes: &[u8]) -> Option<u32> {
+  let (status, data) = bytes.split_first()?;
+  let len = data_len(*status)?;
+  if data.len() < len || data[..len].iter().any(|byte| byte & 0x80 != 0) {
+    return None;
+  }
+
+  let mtype = if *status >= 0xf0 { 0x1 } else { 0x2 };
+  let data1 = if len > 0 { data[0] as u32 } else { 0 };
+  let data2 = if len > 1 { data[1] as u32 } else { 0 };
+
+  Some(
+    (mtype << 28) | ((group as u32 & 0x0f) << 24) | ((*status as u32) << 16) | (data1 << 8) | data2,
+  )
+}
+
+/// Encoder of messages into MIDI 1.0 byte streams, for the transports and destinations without UMP support.
+///
+/// MIDI 2.0 channel voice messages are downconverted following the translation rules from the UMP
+/// specification, scaling down the values and expanding the RPNs, NRPNs and bank selects into
+/// control changes. The ones without an equivalent (per-note and relative controllers) are dropped,
+/// as well as the utility, SysEx8, Mixed Data Set and Flex Data messages. SysEx7 packets get the `F0` and `F7` bytes at the start and end of the message.
+pub struct Encoder {
+  running_status: bool,
+  controller_pairs: bool,
+  last_status: Option<u8>,
+}
+
+impl Encoder {
+  pub fn new() -> Self {
+    Self {
+      running_status: false,
+      controller_pairs: false,
+      last_status: None,
+    }
+  }
+
+  /// Omits the status byte of the channel messages with the same status as the previous one.
+  #[must_use]
+  pub fn with_running_status(mut self, running_status: bool) -> Self {
+    self.running_status = running_status;
+    self
+  }
+
+  /// Sends the MIDI 2.0 control changes of the controllers from 1 to 31 with 14 bits of resolution,
+  /// following the MSB with a LSB for the controller 32 positions above.
+  #[must_use]
+  pub fn with_controller_pairs(mut self, controller_pairs: bool) -> Self {
+    self.controller_pairs = controller_pairs;
+    self
+  }
+
+  /// Encodes a message, calling `f` with the bytes of every resulting MIDI 1.0 message.
+  pub fn encode<F>(&mut self, mtype: &MessageType, mut f: F)
+  where
+    F: FnMut(&[u8]),
+  {
+    match mtype {
+      MessageType::Utility(_)
+      | MessageType::SysEx8(_)
+      | MessageType::MixedDataSet(_)
+      | MessageType::FlexData(_) => {}
+      MessageType::SysEx7(sysex) => self.encode_sysex7(sysex, &mut f),
+      MessageType::System(system) => self.encode_word(system.encode()[0], &mut f),
+      MessageType::ChannelVoice1(channel_voice) => {
+        self.encode_word(channel_voice.encode()[0], &mut f)
+      }
+      MessageType::ChannelVoice(channel_voice) => {
+        downconvert(channel_voice, |channel_voice| {
+          self.encode_word(channel_voice.encode()[0], &mut f)
+        });
+        if let ChanelVoiceMessage::ControlChange { index, data } = channel_voice.message {
+          if self.controller_pairs && controllers::is_paired_msb(index) {
+            let lsb = ChannelVoice1 {
+              channel: channel_voice.channel,
+              message: ChannelVoice1Message::ControlChange {
+                index: index + 32,
+                data: ((data >> 18) & 0x7f) as u8,
+              },
+            };
+            self.encode_word(lsb.encode()[0], &mut f);
+          }
+        }
+      }
+    }
+  }
+
+  fn encode_sysex7<F>(&mut self, sysex: &SysEx7, f: &mut F)
+  where
+    F: FnMut(&[u8]),
+  {
+    let mut bytes = [0u8; SYSEX7_MAX_DATA + 2];
+    let mut size = 0;
+    if matches!(sysex.status, SysExStatus::Complete | SysExStatus::Start) {
+      bytes[size] = 0xf0;
+      size += 1;
+    }
+    bytes[size..size + sysex.data().len()].copy_from_slice(sysex.data());
+    size += sysex.data().len();
+    if matches!(sysex.status, SysExStatus::Complete | SysExStatus::End) {
+      bytes[size] = 0xf7;
+      size += 1;
+    }
+
+    self.last_status = None;
+    f(&bytes[..size]);
+  }
+
+  /// Forgets the running status, to be called when the stream is interrupted.
+  pub fn reset(&mut self) {
+    self.last_status = None;
+  }
+
+  fn encode_word<F>(&mut self, word: u32, f: &mut F)
+  where
+    F: FnMut(&[u8]),
+  {
+    let status = ((word >> 16) & 0xff) as u8;
+    let len = match data_len(status) {
+      Some(len) => len,
+      None => return,
+    };
+    let bytes = [status, ((word >> 8) & 0x7f) as u8, (word & 0x7f) as u8];
+
+    match status {
+      // Real time messages can be interleaved without affecting the running status
+      0xf8..=0xff => f(&bytes[..1]),
+      0xf0..=0xf7 => {
+        self.last_status = None;
+        f(&bytes[..len + 1]);
+      }
+      _ if self.running_status && self.last_status == Some(status) => f(&bytes[1..len + 1]),
+      _ => {
+        self.last_status = Some(status);
+        f(&bytes[..len + 1]);
+      }
+    }
+  }
+}
+
+impl Default for Encoder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Translates a MIDI 2.0 channel voice message into MIDI 1.0 ones, calling `f` for each of them.
+pub fn downconvert<F>(channel_voice: &ChannelVoice, mut f: F)
+where
+  F: FnMut(ChannelVoice1),
+{
+  let channel = channel_voice.channel;
+  let mut send = |message: ChannelVoice1Message| f(ChannelVoice1 { channel, message });
+  let control_change = |index: u8, data: u8| ChannelVoice1Message::ControlChange { index, data };
+
+  match channel_voice.message {
+    ChanelVoiceMessage::NoteOff { note, velocity, .. } => send(ChannelVoice1Message::NoteOff {
+      note,
+      velocity: (velocity >> 9) as u8,
+    }),
+    ChanelVoiceMessage::NoteOn { note, velocity, .. } => send(ChannelVoice1Message::NoteOn {
+      note,
+      // A velocity of 0 would turn it into a note off
+      velocity: ((velocity >> 9) as u8).max(1),
+    }),
+    ChanelVoiceMessage::PolyPressure { note, data } => send(ChannelVoice1Message::PolyPressure {
+      note,
+      data: (data >> 25) as u8,
+    }),
+    ChanelVoiceMessage::ControlChange { index, data } => {
+      send(control_change(index, (data >> 25) as u8))
+    }
+    ChanelVoiceMessage::RegisteredController { bank, index, data } => {
+      send(control_change(101, bank & 0x7f));
+      send(control_change(100, index & 0x7f));
+      send(control_change(6, (data >> 25) as u8));
+      send(control_change(38, ((data >> 18) & 0x7f) as u8));
+    }
+    ChanelVoiceMessage::AssignableController { bank, index, data } => {
+      send(control_change(99, bank & 0x7f));
+      send(control_change(98, index & 0x7f));
+      send(control_change(6, (data >> 25) as u8));
+      send(control_change(38, ((data >> 18) & 0x7f) as u8));
+    }
+    ChanelVoiceMessage::ProgramChange { program, bank } => {
+      if let Some(bank) = bank {
+        send(control_change(0, ((bank >> 7) & 0x7f) as u8));
+        send(control_change(32, (bank & 0x7f) as u8));
+      }
+      send(ChannelVoice1Message::ProgramChange { program });
+    }
+    ChanelVoiceMessage::ChannelPressure { data } => send(ChannelVoice1Message::ChannelPressure {
+      data: (data >> 25) as u8,
+    }),
+    ChanelVoiceMessage::PitchBend { data } => send(ChannelVoice1Message::PitchBend {
+      data: (data >> 18) as u16,
+    }),
+    ChanelVoiceMessage::RegisteredPerNoteController { .. }
+    | ChanelVoiceMessage::AssignablePerNoteController { .. }
+    | ChanelVoiceMessage::PerNoteManagement { .. }
+    | ChanelVoiceMessage::RelativeRegisteredController { .. }
+    | ChanelVoiceMessage::RelativeAssignableController { .. }
+    | ChanelVoiceMessage::PerNotePitchBend { .. } => {}
+  }
+}
+
+/// Translates a MIDI 1.0 channel voice message into a MIDI 2.0 one, scaling up the values.
+///
+/// A note on with velocity 0 becomes a note off. The control changes are translated one by one,
+/// use an [`Upconverter`] to fold the RPNs, NRPNs and bank selects into their MIDI 2.0 messages.
+pub fn upconvert(channel_voice: &ChannelVoice1) -> ChannelVoice {
+  let velocity = |velocity: u8| scale_up(velocity as u32, 7, 16) as u16;
+  let data = |data: u8| scale_up(data as u32, 7, 32);
+
+  let message = match channel_voice.message {
+    ChannelVoice1Message::NoteOn { note, velocity: 0 } => ChanelVoiceMessage::NoteOff {
+      note,
+      velocity: 0,
+      attr_type: 0,
+      attr_data: 0,
+    },
+    ChannelVoice1Message::NoteOn { note, velocity: v } => ChanelVoiceMessage::NoteOn {
+      note,
+      velocity: velocity(v),
+      attr_type: 0,
+      attr_data: 0,
+    },
+    ChannelVoice1Message::NoteOff { note, velocity: v } => ChanelVoiceMessage::NoteOff {
+      note,
+      velocity: velocity(v),
+      attr_type: 0,
+      attr_data: 0,
+    },
+    ChannelVoice1Message::PolyPressure { note, data: d } => ChanelVoiceMessage::PolyPressure {
+      note,
+      data: data(d),
+    },
+    ChannelVoice1Message::ControlChange { index, data: d } => ChanelVoiceMessage::ControlChange {
+      index,
+      data: data(d),
+    },
+    ChannelVoice1Message::ProgramChange { program } => ChanelVoiceMessage::ProgramChange {
+      program,
+      bank: None,
+    },
+    ChannelVoice1Message::ChannelPressure { data: d } => {
+      ChanelVoiceMessage::ChannelPressure { data: data(d) }
+    }
+    ChannelVoice1Message::PitchBend { data } => ChanelVoiceMessage::PitchBend {
+      data: scale_up_14(data),
+    },
+  };
+  ChannelVoice {
+    channel: channel_voice.channel,
+    message,
+  }
+}
+
+/// Translates the MIDI 1.0 channel voice messages into MIDI 2.0 ones, keeping the state per group and channel
+/// to fold the control changes setting RPNs and NRPNs into registered and assignable controllers, and the
+/// bank selects into the program changes after them.
+pub struct Upconverter {
+  parameters: ParameterAssembler,
+  /// Bank select MSB and LSB received per group and channel
+  banks: [[(Option<u8>, u8); 16]; 16],
+}
+
+impl Upconverter {
+  pub fn new() -> Self {
+    Self {
+      parameters: ParameterAssembler::new(),
+      banks: [[(None, 0); 16]; 16],
+    }
+  }
+
+  /// Translates a message, returning `None` for the control changes consumed to select parameters and banks.
+  /// The messages other than MIDI 1.0 channel voice are returned as they are.
+  pub fn process(&mut self, message: Message) -> Option<Message> {
+    let message = self.parameters.process(message)?;
+    let channel_voice = match message.mtype {
+      MessageType::ChannelVoice1(channel_voice) => channel_voice,
+      _ => return Some(message),
+    };
+
+    let bank =
+      &mut self.banks[(message.group & 0x0f) as usize][(channel_voice.channel & 0x0f) as usize];
+    let channel_voice = match channel_voice.message {
+      ChannelVoice1Message::ControlChange { index: 0, data } => {
+        *bank = (Some(data), 0);
+        return None;
+      }
+      ChannelVoice1Message::ControlChange { index: 32, data } => {
+        bank.1 = data;
+        return None;
+      }
+      ChannelVoice1Message::ProgramChange { program } => ChannelVoice {
+        channel: channel_voice.channel,
+        message: ChanelVoiceMessage::ProgramChange {
+          program,
+          bank: bank.0.map(|msb| (msb as u16) << 7 | bank.1 as u16),
+        },
+      },
+      _ => upconvert(&channel_voice),
+    };
+    Some(Message {
+      group: message.group,
+      mtype: MessageType::ChannelVoice(channel_voice),
+    })
+  }
+
+  pub fn reset(&mut self) {
+    self.parameters.reset();
+    self.banks = [[(None, 0); 16]; 16];
+  }
+}
+
+impl Default for Upconverter {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Translates a message for a destination using the MIDI 1.0 protocol, calling `f` with the resulting messages.
+///
+/// The MIDI 2.0 channel voice messages are downconverted into MIDI 1.0 channel voice messages for the same group,
+/// and the rest of the messages are passed as they are.
+pub fn downconvert_message<F>(message: &Message, mut f: F)
+where
+  F: FnMut(Message),
+{
+  match message.mtype {
+    MessageType::ChannelVoice(channel_voice) => downconvert(&channel_voice, |channel_voice| {
+      f(Message {
+        group: message.group,
+        mtype: MessageType::ChannelVoice1(channel_voice),
+      })
+    }),
+    _ => f(*message),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::encoder::encode_sysex7;
+  use crate::protocol::messages::system::System;
+  use crate::protocol::parameters::scale_up_14;
+  use crate::protocol::Decode;
+
+  #[test]
+  fn channel_voice_message() {
+    assert_eq!(message_to_ump(0, &[0x93, 0x3c, 0x64]), Some(0x20933c64));
+    assert_eq!(message_to_ump(5, &[0xc1, 0x2a]), Some(0x25c12a00));
+  }
+
+  #[test]
+  fn system_message() {
+    assert_eq!(message_to_ump(0, &[0xf8]), Some(0x10f80000));
+    assert_eq!(message_to_ump(1, &[0xf2, 0x01, 0x7f]), Some(0x11f2017f));
+  }
+
+  #[test]
+  fn extra_bytes_are_ignored() {
+    assert_eq!(message_to_ump(0, &[0xf8, 0x12]), Some(0x10f80000));
+  }
+
+  #[test]
+  fn invalid_messages() {
+    assert_eq!(message_to_ump(0, &[]), None);
+    assert_eq!(message_to_ump(0, &[0x93, 0x3c]), None);
+    assert_eq!(message_to_ump(0, &[0x93, 0x3c, 0x80]), None);
+    assert_eq!(message_to_ump(0, &[0xf0, 0x7e, 0xf7]), None);
+    assert_eq!(message_to_ump(0, &[0xf9]), None);
+    assert_eq!(message_to_ump(0, &[0x3c, 0x64]), None);
+  }
+
+  fn parse(parser: &mut Parser, bytes: &[u8]) -> Vec<u32> {
+    let mut words = Vec::new();
+    parser.parse(bytes, |ump| words.extend_from_slice(ump.as_slice()));
+    words
+  }
+
+  #[test]
+  fn parse_messages() {
+    let mut parser = Parser::new(1);
+
+    assert_eq!(
+      parse(&mut parser, &[0x90, 0x3c, 0x64, 0xc2, 0x05, 0xf8]),
+      vec![0x21903c64, 0x21c20500, 0x11f80000]
+    );
+  }
+
+  #[test]
+  fn parse_messages_split_across_chunks() {
+    let mut parser = Parser::new(0);
+
+    assert_eq!(parse(&mut parser, &[0xb0, 0x07]), vec![]);
+    assert_eq!(parse(&mut parser, &[0x64]), vec![0x20b00764]);
+  }
+
+  #[test]
+  fn parse_running_status() {
+    let mut parser = Parser::new(0);
+
+    assert_eq!(
+      parse(&mut parser, &[0x90, 0x3c, 0x64, 0x3e, 0x64, 0x3c, 0x00]),
+      vec![0x20903c64, 0x20903e64, 0x20903c00]
+    );
+  }
+
+  #[test]
+  fn parse_real_time_within_message() {
+    let mut parser = Parser::new(0);
+
+    assert_eq!(
+      parse(&mut parser, &[0x90, 0x3c, 0xf8, 0x64, 0x3e, 0xfe, 0x64]),
+      vec![0x10f80000, 0x20903c64, 0x10fe0000, 0x20903e64]
+    );
+  }
+
+  #[test]
+  fn system_common_cancels_running_status() {
+    let mut parser = Parser::new(0);
+
+    assert_eq!(
+      parse(&mut parser, &[0x90, 0x3c, 0x64, 0xf3, 0x01, 0x3c, 0x64]),
+      vec![0x20903c64, 0x10f30100]
+    );
+  }
+
+  #[test]
+  fn sysex_is_converted_to_packets() {
+    let mut parser = Parser::new(2);
+
+    assert_eq!(
+      parse(
+        &mut parser,
+        &[0xf0, 0x7e, 0x7f, 0x06, 0x01, 0xf7, 0x90, 0x3c, 0x64]
+      ),
+      vec![0x3204_7e7f, 0x0601_0000, 0x22903c64]
+    );
+  }
+
+  #[test]
+  fn sysex_spanning_chunks() {
+    let mut parser = Parser::new(0);
+    let data = (0..13).collect::<Vec<u8>>();
+    let mut expected = Vec::new();
+    encode_sysex7(0, &data, |ump| expected.extend_from_slice(ump.as_slice()));
+
+    let mut words = parse(&mut parser, &[0xf0, 0, 1, 2, 3]);
+    assert_eq!(parse(&mut parser, &[4, 5, 0xf8]), vec![0x10f80000]);
+    words.extend(parse(&mut parser, &[6, 7, 8, 9, 10, 11]));
+    words.extend(parse(&mut parser, &[12, 0xf7]));
+    assert_eq!(words, expected);
+  }
+
+  #[test]
+  fn sysex_ended_by_status() {
+    let mut parser = Parser::new(0);
+
+    assert_eq!(
+      parse(&mut parser, &[0xf0, 0x7d, 0x01, 0x90, 0x3c, 0x64, 0xf7]),
+      vec![0x3002_7d01, 0x0000_0000, 0x20903c64]
+    );
+  }
+
+  #[test]
+  fn parse_messages_with_sysex() {
+    let mut parser = Parser::new(0);
+    let mut messages = Vec::new();
+    parser.parse_messages(&[0xf0, 0x7d, 0xf7, 0xc0, 0x05], |message| {
+      messages.push(message)
+    });
+
+    assert_eq!(
+      messages,
+      vec![
+        Message {
+          group: 0,
+          mtype: MessageType::SysEx7(SysEx7::new(SysExStatus::Complete, &[0x7d])),
+        },
+        Message {
+          group: 0,
+          mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+            channel: 0,
+            message: ChannelVoice1Message::ProgramChange { program: 5 },
+          }),
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn data_without_status_is_ignored() {
+    let mut parser = Parser::new(0);
+
+    assert_eq!(
+      parse(&mut parser, &[0x3c, 0x64, 0x80, 0x3c, 0x00]),
+      vec![0x20803c00]
+    );
+    assert_eq!(parser.discarded(), 2);
+  }
+
+  #[test]
+  fn malformed_data_is_counted() {
+    let mut parser = Parser::new(0);
+
+    // Undefined status, and a note on interrupted by a clock and then by a program change
+    assert_eq!(
+      parse(&mut parser, &[0xf4, 0x90, 0x3c, 0xf8, 0xc0, 0x05]),
+      vec![0x10f80000, 0x20c00500]
+    );
+    assert_eq!(parser.discarded(), 3);
+  }
+
+  #[test]
+  fn stray_end_of_sysex_is_counted() {
+    let mut parser = Parser::new(0);
+
+    assert_eq!(
+      parse(&mut parser, &[0xf7, 0x90, 0x3c, 0x64, 0xf7, 0x3e, 0x64]),
+      vec![0x20903c64, 0x20903e64]
+    );
+    assert_eq!(parser.discarded(), 2);
+  }
+
+  fn encode(encoder: &mut Encoder, mtype: MessageType) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    encoder.encode(&mtype, |message| bytes.extend_from_slice(message));
+    bytes
+  }
+
+  fn note_on(note: u8) -> MessageType {
+    MessageType::ChannelVoice1(ChannelVoice1 {
+      channel: 1,
+      message: ChannelVoice1Message::NoteOn {
+        note,
+        velocity: 0x64,
+      },
+    })
+  }
+
+  fn channel_voice(message: ChanelVoiceMessage) -> MessageType {
+    MessageType::ChannelVoice(ChannelVoice {
+      channel: 2,
+      message,
+    })
+  }
+
+  #[test]
+  fn encode_parse_round_trip() {
+    let mut encoder = Encoder::new();
+    let mut parser = Parser::new(0);
+    let channel_voice1 = ChannelVoice1 {
+      channel: 9,
+      message: ChannelVoice1Message::PitchBend { data: 0x1234 },
+    };
+    let system = System::SongPositionPointer(0x1234);
+
+    let bytes = [
+      encode(&mut encoder, MessageType::ChannelVoice1(channel_voice1)),
+      encode(&mut encoder, MessageType::System(system)),
+    ]
+    .concat();
+
+    assert_eq!(bytes, vec![0xe9, 0x34, 0x24, 0xf2, 0x34, 0x24]);
+    assert_eq!(
+      parse(&mut parser, &bytes),
+      vec![channel_voice1.encode()[0], system.encode()[0]]
+    );
+  }
+
+  #[test]
+  fn encode_with_running_status() {
+    let mut encoder = Encoder::new().with_running_status(true);
+
+    assert_eq!(encode(&mut encoder, note_on(0x3c)), vec![0x91, 0x3c, 0x64]);
+    assert_eq!(encode(&mut encoder, note_on(0x3e)), vec![0x3e, 0x64]);
+    assert_eq!(
+      encode(&mut encoder, MessageType::System(System::TimingClock)),
+      vec![0xf8]
+    );
+    assert_eq!(encode(&mut encoder, note_on(0x40)), vec![0x40, 0x64]);
+    assert_eq!(
+      encode(&mut encoder, MessageType::System(System::TuneRequest)),
+      vec![0xf6]
+    );
+    assert_eq!(encode(&mut encoder, note_on(0x41)), vec![0x91, 0x41, 0x64]);
+
+    encoder.reset();
+    assert_eq!(encode(&mut encoder, note_on(0x42)), vec![0x91, 0x42, 0x64]);
+  }
+
+  #[test]
+  fn encode_controller_pairs() {
+    let control_change = MessageType::ChannelVoice(ChannelVoice {
+      channel: 1,
+      message: ChanelVoiceMessage::ControlChange {
+        index: 7,
+        data: scale_up_14(0x2012),
+      },
+    });
+    let bank_select = MessageType::ChannelVoice(ChannelVoice {
+      channel: 1,
+      message: ChanelVoiceMessage::ControlChange {
+        index: 0,
+        data: 0x0200_0000,
+      },
+    });
+
+    let mut encoder = Encoder::new().with_controller_pairs(true);
+    assert_eq!(
+      encode(&mut encoder, control_change),
+      vec![0xb1, 0x07, 0x40, 0xb1, 0x27, 0x12]
+    );
+    assert_eq!(encode(&mut encoder, bank_select), vec![0xb1, 0x00, 0x01]);
+    assert_eq!(
+      encode(&mut Encoder::new(), control_change),
+      vec![0xb1, 0x07, 0x40]
+    );
+  }
+
+  #[test]
+  fn encode_without_running_status() {
+    let mut encoder = Encoder::new();
+
+    assert_eq!(encode(&mut encoder, note_on(0x3c)), vec![0x91, 0x3c, 0x64]);
+    assert_eq!(encode(&mut encoder, note_on(0x3e)), vec![0x91, 0x3e, 0x64]);
+  }
+
+  #[test]
+  fn downconvert_notes_and_values() {
+    let mut encoder = Encoder::new();
+
+    let note_on = channel_voice(ChanelVoiceMessage::NoteOn {
+      note: 0x3c,
+      velocity: 0x0100,
+      attr_type: 0,
+      attr_data: 0,
+    });
+    assert_eq!(encode(&mut encoder, note_on), vec![0x92, 0x3c, 0x01]);
+
+    let control_change = channel_voice(ChanelVoiceMessage::ControlChange {
+      index: 7,
+      data: 0xffff_ffff,
+    });
+    assert_eq!(encode(&mut encoder, control_change), vec![0xb2, 0x07, 0x7f]);
+
+    let pitch_bend = channel_voice(ChanelVoiceMessage::PitchBend { data: 0x8000_0000 });
+    assert_eq!(encode(&mut encoder, pitch_bend), vec![0xe2, 0x00, 0x40]);
+  }
+
+  #[test]
+  fn downconvert_controllers_and_banks() {
+    let mut encoder = Encoder::new().with_running_status(true);
+
+    let registered_controller = channel_voice(ChanelVoiceMessage::RegisteredController {
+      bank: 0,
+      index: 1,
+      data: 0x8000_0000,
+    });
+    assert_eq!(
+      encode(&mut encoder, registered_controller),
+      vec![0xb2, 101, 0, 100, 1, 6, 0x40, 38, 0]
+    );
+
+    let program_change = channel_voice(ChanelVoiceMessage::ProgramChange {
+      program: 5,
+      bank: Some(0x0081),
+    });
+    assert_eq!(
+      encode(&mut encoder, program_change),
+      vec![0, 1, 32, 1, 0xc2, 5]
+    );
+
+    let per_note_pitch_bend = channel_voice(ChanelVoiceMessage::PerNotePitchBend {
+      note: 0x3c,
+      data: 0,
+    });
+    assert!(encode(&mut encoder, per_note_pitch_bend).is_empty());
+  }
+
+  #[test]
+  fn downconvert_messages() {
+    let mut messages = Vec::new();
+    let assignable_controller = Message {
+      group: 3,
+      mtype: channel_voice(ChanelVoiceMessage::AssignableController {
+        bank: 0x81,
+        index: 2,
+        data: scale_up_14(0x1fff),
+      }),
+    };
+    downconvert_message(&assignable_controller, |message| messages.push(message));
+    let control_changes = messages
+      .iter()
+      .map(|message| match message.mtype {
+        MessageType::ChannelVoice1(ChannelVoice1 {
+          channel: 2,
+          message: ChannelVoice1Message::ControlChange { index, data },
+        }) if message.group == 3 => (index, data),
+        _ => panic!("unexpected message {:?}", message),
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      control_changes,
+      vec![(99, 1), (98, 2), (6, 0x3f), (38, 0x7f)]
+    );
+
+    let clock = Message {
+      group: 3,
+      mtype: MessageType::System(System::TimingClock),
+    };
+    messages.clear();
+    downconvert_message(&clock, |message| messages.push(message));
+    assert_eq!(messages, vec![clock]);
+  }
+
+  #[test]
+  fn upconvert_notes_and_values() {
+    let channel_voice = |message: ChannelVoice1Message| {
+      upconvert(&ChannelVoice1 {
+        channel: 4,
+        message,
+      })
+      .message
+    };
+
+    assert_eq!(
+      channel_voice(ChannelVoice1Message::NoteOn {
+        note: 60,
+        velocity: 0x7f
+      }),
+      ChanelVoiceMessage::NoteOn {
+        note: 60,
+        velocity: 0xffff,
+        attr_type: 0,
+        attr_data: 0
+      }
+    );
+    assert_eq!(
+      channel_voice(ChannelVoice1Message::NoteOn {
+        note: 60,
+        velocity: 0
+      }),
+      ChanelVoiceMessage::NoteOff {
+        note: 60,
+        velocity: 0,
+        attr_type: 0,
+        attr_data: 0
+      }
+    );
+    assert_eq!(
+      channel_voice(ChannelVoice1Message::ControlChange {
+        index: 7,
+        data: 0x40
+      }),
+      ChanelVoiceMessage::ControlChange {
+        index: 7,
+        data: 0x8000_0000
+      }
+    );
+    assert_eq!(
+      channel_voice(ChannelVoice1Message::PitchBend { data: 0x3fff }),
+      ChanelVoiceMessage::PitchBend { data: 0xffff_ffff }
+    );
+  }
+
+  #[test]
+  fn upconvert_parameters_and_banks() {
+    let mut upconverter = Upconverter::new();
+    let mut parser = Parser::new(2);
+    let mut messages = Vec::new();
+    parser.parse_messages(
+      &[
+        0xb1, 101, 0, 100, 0, 6, 12, 0xb1, 0, 1, 32, 2, 0xc1, 5, 0xc2, 6,
+      ],
+      |message| messages.extend(upconverter.process(message)),
+    );
+
+    let channel_voices = messages
+      .iter()
+      .map(|message| match message.mtype {
+        MessageType::ChannelVoice(channel_voice) if message.group == 2 => channel_voice,
+        _ => panic!("unexpected message {:?}", message),
+      })
+      .collect::<Vec<_>>();
+    assert_eq!(
+      channel_voices,
+      vec![
+        ChannelVoice {
+          channel: 1,
+          message: ChanelVoiceMessage::RegisteredController {
+            bank: 0,
+            index: 0,
+            data: scale_up_14(12 << 7)
+          }
+        },
+        ChannelVoice {
+          channel: 1,
+          message: ChanelVoiceMessage::ProgramChange {
+            program: 5,
+            bank: Some(0x0082)
+          }
+        },
+        ChannelVoice {
+          channel: 2,
+          message: ChanelVoiceMessage::ProgramChange {
+            program: 6,
+            bank: None
+          }
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn encode_sysex7_packets() {
+    let mut encoder = Encoder::new().with_running_status(true);
+    let mut bytes = Vec::new();
+    let data = (1..=8).collect::<Vec<u8>>();
+
+    encode(&mut encoder, note_on(0x3c));
+    encode_sysex7(0, &data, |ump| {
+      let mtype = MessageType::SysEx7(SysEx7::decode(ump.as_slice()));
+      encoder.encode(&mtype, |chunk| bytes.extend_from_slice(chunk))
+    });
+
+    assert_eq!(bytes, vec![0xf0, 1, 2, 3, 4, 5, 6, 7, 8, 0xf7]);
+    assert_eq!(encode(&mut encoder, note_on(0x3c)), vec![0x91, 0x3c, 0x64]);
+  }
+}
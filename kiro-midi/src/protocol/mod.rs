@@ -1,10 +1,20 @@
+#[cfg(any(test, feature = "conformance"))]
+pub mod conformance;
+pub mod controllers;
 pub mod decoder;
+#[cfg(feature = "std")]
+pub mod duplicates;
+pub mod encoder;
+#[cfg(feature = "std")]
+pub mod jitter_reduction;
 pub mod messages;
+pub mod midi1;
+pub mod parameters;
+#[cfg(feature = "std")]
+pub mod rate_limiter;
+
+pub use encoder::Encode;
 
 pub trait Decode {
   fn decode(ump: &[u32]) -> Self;
 }
-
-pub trait Encode<const N: usize> {
-  fn encode(&self) -> [u32; N];
-}
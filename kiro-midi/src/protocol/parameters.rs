@@ -0,0 +1,424 @@
+//! Assembly of the MIDI 1.0 registered and non-registered parameter numbers (RPN and NRPN).
+//!
+//! MIDI 1.0 sets a parameter with a sequence of control changes: the parameter is selected
+//! with CC 101/100 (RPN) or CC 99/98 (NRPN), and then its value is sent with the data entry
+//! CC 6 (MSB) and optionally CC 38 (LSB). MIDI 2.0 sends the same in a single registered
+//! or assignable controller message, with a 32 bits value.
+
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::{Message, MessageType};
+
+const DATA_ENTRY_MSB: u8 = 6;
+const DATA_ENTRY_LSB: u8 = 38;
+const NRPN_LSB: u8 = 98;
+const NRPN_MSB: u8 = 99;
+const RPN_LSB: u8 = 100;
+const RPN_MSB: u8 = 101;
+
+/// Value of both the bank and index of the RPN that deselects the parameter
+const NULL_PARAMETER: u8 = 0x7f;
+
+/// A parameter set through a sequence of MIDI 1.0 control changes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+  RegisteredParameter {
+    channel: u8,
+    bank: u8,
+    index: u8,
+    /// 14 bits value
+    value: u16,
+  },
+  NonRegisteredParameter {
+    channel: u8,
+    bank: u8,
+    index: u8,
+    /// 14 bits value
+    value: u16,
+  },
+}
+
+impl Parameter {
+  /// The equivalent MIDI 2.0 registered or assignable controller message, with the value scaled up to 32 bits.
+  pub fn to_channel_voice(self) -> ChannelVoice {
+    match self {
+      Self::RegisteredParameter {
+        channel,
+        bank,
+        index,
+        value,
+      } => ChannelVoice {
+        channel,
+        message: ChanelVoiceMessage::RegisteredController {
+          bank,
+          index,
+          data: scale_up_14(value),
+        },
+      },
+      Self::NonRegisteredParameter {
+        channel,
+        bank,
+        index,
+        value,
+      } => ChannelVoice {
+        channel,
+        message: ChanelVoiceMessage::AssignableController {
+          bank,
+          index,
+          data: scale_up_14(value),
+        },
+      },
+    }
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Selection {
+  None,
+  Registered { bank: u8, index: u8 },
+  NonRegistered { bank: u8, index: u8 },
+}
+
+/// The parameter selected in a channel, and the last data entry MSB received for it
+#[derive(Debug, Clone, Copy)]
+struct ChannelState {
+  selection: Selection,
+  msb: Option<u8>,
+}
+
+impl Default for ChannelState {
+  fn default() -> Self {
+    Self {
+      selection: Selection::None,
+      msb: None,
+    }
+  }
+}
+
+/// Collects the control changes setting RPNs and NRPNs into parameters, per group and channel.
+///
+/// Every data entry for a selected parameter produces a parameter with the value so far,
+/// so a CC 6 alone sets the 7 most significant bits, and a CC 38 after it sets all the 14 bits.
+pub struct ParameterAssembler {
+  channels: [[ChannelState; 16]; 16],
+}
+
+impl ParameterAssembler {
+  pub fn new() -> Self {
+    Self {
+      channels: [[ChannelState::default(); 16]; 16],
+    }
+  }
+
+  /// Handles a MIDI 1.0 channel voice message, returning the parameter set by it, if any.
+  pub fn push(&mut self, group: u8, channel_voice: &ChannelVoice1) -> Option<Parameter> {
+    let (index, data) = match channel_voice.message {
+      ChannelVoice1Message::ControlChange { index, data } => (index, data),
+      _ => return None,
+    };
+    let channel = channel_voice.channel & 0x0f;
+    let state = &mut self.channels[(group & 0x0f) as usize][channel as usize];
+
+    match index {
+      RPN_MSB | RPN_LSB | NRPN_MSB | NRPN_LSB => {
+        state.selection = select(state.selection, index, data);
+        state.msb = None;
+        None
+      }
+      DATA_ENTRY_MSB => {
+        state.msb = Some(data);
+        parameter(state.selection, channel, (data as u16) << 7)
+      }
+      DATA_ENTRY_LSB => {
+        let msb = state.msb?;
+        parameter(state.selection, channel, (msb as u16) << 7 | data as u16)
+      }
+      _ => None,
+    }
+  }
+
+  /// Handles a message as a stage after the decoder, translating the data entries of the selected
+  /// parameters into MIDI 2.0 registered or assignable controller messages.
+  ///
+  /// The control changes selecting the parameters are consumed, and the rest of the messages
+  /// (including the data entries without a parameter selected) are returned as they are.
+  pub fn process(&mut self, message: Message) -> Option<Message> {
+    match message.mtype {
+      MessageType::ChannelVoice1(channel_voice) if is_parameter_control(&channel_voice) => {
+        let selected = self.channels[(message.group & 0x0f) as usize]
+          [(channel_voice.channel & 0x0f) as usize]
+          .selection
+          != Selection::None;
+        match self.push(message.group, &channel_voice) {
+          Some(parameter) => Some(Message {
+            group: message.group,
+            mtype: MessageType::ChannelVoice(parameter.to_channel_voice()),
+          }),
+          None if selected || !is_data_entry(&channel_voice) => None,
+          None => Some(message),
+        }
+      }
+      _ => Some(message),
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.channels = [[ChannelState::default(); 16]; 16];
+  }
+}
+
+impl Default for ParameterAssembler {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+fn is_parameter_control(channel_voice: &ChannelVoice1) -> bool {
+  matches!(
+    channel_voice.message,
+    ChannelVoice1Message::ControlChange {
+      index: DATA_ENTRY_MSB | DATA_ENTRY_LSB | NRPN_LSB | NRPN_MSB | RPN_LSB | RPN_MSB,
+      ..
+    }
+  )
+}
+
+fn is_data_entry(channel_voice: &ChannelVoice1) -> bool {
+  matches!(
+    channel_voice.message,
+    ChannelVoice1Message::ControlChange {
+      index: DATA_ENTRY_MSB | DATA_ENTRY_LSB,
+      ..
+    }
+  )
+}
+
+/// Updates the selected parameter with a RPN or NRPN control change. Selecting the bank and index
+/// of the other kind of parameter resets the other half to 0, as in the usual sequences both are sent.
+fn select(selection: Selection, control: u8, data: u8) -> Selection {
+  let selection = match (selection, control) {
+    (Selection::Registered { index, .. }, RPN_MSB) => Selection::Registered { bank: data, index },
+    (Selection::Registered { bank, .. }, RPN_LSB) => Selection::Registered { bank, index: data },
+    (_, RPN_MSB) => Selection::Registered {
+      bank: data,
+      index: 0,
+    },
+    (_, RPN_LSB) => Selection::Registered {
+      bank: 0,
+      index: data,
+    },
+    (Selection::NonRegistered { index, .. }, NRPN_MSB) => {
+      Selection::NonRegistered { bank: data, index }
+    }
+    (Selection::NonRegistered { bank, .. }, NRPN_LSB) => {
+      Selection::NonRegistered { bank, index: data }
+    }
+    (_, NRPN_MSB) => Selection::NonRegistered {
+      bank: data,
+      index: 0,
+    },
+    (_, _) => Selection::NonRegistered {
+      bank: 0,
+      index: data,
+    },
+  };
+
+  match selection {
+    Selection::Registered {
+      bank: NULL_PARAMETER,
+      index: NULL_PARAMETER,
+    } => Selection::None,
+    selection => selection,
+  }
+}
+
+fn parameter(selection: Selection, channel: u8, value: u16) -> Option<Parameter> {
+  match selection {
+    Selection::None => None,
+    Selection::Registered { bank, index } => Some(Parameter::RegisteredParameter {
+      channel,
+      bank,
+      index,
+      value,
+    }),
+    Selection::NonRegistered { bank, index } => Some(Parameter::NonRegisteredParameter {
+      channel,
+      bank,
+      index,
+      value,
+    }),
+  }
+}
+
+/// Scales a 14 bits value up to 32 bits following the min-center-max rule from the MIDI 2.0 specification,
+/// so the minimum, center and maximum values map to the minimum, center and maximum 32 bits values.
+pub fn scale_up_14(value: u16) -> u32 {
+  scale_up(value as u32, 14, 32)
+}
+
+/// Scales a value up from `source_bits` to `target_bits` following the min-center-max rule.
+pub fn scale_up(value: u32, source_bits: u32, target_bits: u32) -> u32 {
+  let scale_bits = target_bits - source_bits;
+  let repeat_bits = source_bits - 1;
+
+  let value = value & ((1 << source_bits) - 1);
+  let shifted = value << scale_bits;
+  if value <= 1 << repeat_bits {
+    return shifted;
+  }
+
+  // The bits below the most significant one are repeated to fill the lower bits
+  let repeat = value & ((1 << repeat_bits) - 1);
+  let mut repeat = if scale_bits > repeat_bits {
+    repeat << (scale_bits - repeat_bits)
+  } else {
+    repeat >> (repeat_bits - scale_bits)
+  };
+  let mut scaled = shifted;
+  while repeat != 0 {
+    scaled |= repeat;
+    repeat >>= repeat_bits;
+  }
+  scaled
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn control_change(channel: u8, index: u8, data: u8) -> ChannelVoice1 {
+    ChannelVoice1 {
+      channel,
+      message: ChannelVoice1Message::ControlChange { index, data },
+    }
+  }
+
+  fn push_all(assembler: &mut ParameterAssembler, controls: &[(u8, u8)]) -> Vec<Parameter> {
+    controls
+      .iter()
+      .filter_map(|(index, data)| assembler.push(0, &control_change(1, *index, *data)))
+      .collect()
+  }
+
+  #[test]
+  fn scale_up_min_center_max() {
+    assert_eq!(scale_up_14(0), 0);
+    assert_eq!(scale_up_14(0x2000), 0x8000_0000);
+    assert_eq!(scale_up_14(0x3fff), 0xffff_ffff);
+    assert_eq!(scale_up_14(0x1000), 0x4000_0000);
+    assert_eq!(scale_up_14(0x3000) >> 18, 0x3000);
+    assert_eq!(scale_up(0x7f, 7, 16), 0xffff);
+    assert_eq!(scale_up(0x40, 7, 32), 0x8000_0000);
+    assert_eq!(scale_up(0x7f, 7, 32), 0xffff_ffff);
+    assert_eq!(scale_up(0x60, 7, 16), 0xc104);
+  }
+
+  #[test]
+  fn registered_parameter() {
+    let mut assembler = ParameterAssembler::new();
+
+    // Pitch bend sensitivity of 12 semitones and 50 cents
+    let parameters = push_all(&mut assembler, &[(101, 0), (100, 0), (6, 12), (38, 50)]);
+
+    assert_eq!(
+      parameters,
+      vec![
+        Parameter::RegisteredParameter {
+          channel: 1,
+          bank: 0,
+          index: 0,
+          value: 12 << 7,
+        },
+        Parameter::RegisteredParameter {
+          channel: 1,
+          bank: 0,
+          index: 0,
+          value: 12 << 7 | 50,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn non_registered_parameter_stays_selected() {
+    let mut assembler = ParameterAssembler::new();
+
+    let parameters = push_all(&mut assembler, &[(99, 0x12), (98, 0x34), (6, 1), (6, 2)]);
+
+    assert_eq!(
+      parameters.last(),
+      Some(&Parameter::NonRegisteredParameter {
+        channel: 1,
+        bank: 0x12,
+        index: 0x34,
+        value: 2 << 7,
+      })
+    );
+    assert_eq!(parameters.len(), 2);
+  }
+
+  #[test]
+  fn null_parameter_deselects() {
+    let mut assembler = ParameterAssembler::new();
+
+    let parameters = push_all(
+      &mut assembler,
+      &[
+        (101, 0),
+        (100, 2),
+        (101, 0x7f),
+        (100, 0x7f),
+        (6, 64),
+        (38, 0),
+      ],
+    );
+
+    assert!(parameters.is_empty());
+  }
+
+  #[test]
+  fn lsb_without_msb_is_ignored() {
+    let mut assembler = ParameterAssembler::new();
+
+    assert!(push_all(&mut assembler, &[(101, 0), (100, 1), (38, 10)]).is_empty());
+  }
+
+  #[test]
+  fn process_translates_into_midi2() {
+    let mut assembler = ParameterAssembler::new();
+    let message = |channel_voice| Message {
+      group: 3,
+      mtype: MessageType::ChannelVoice1(channel_voice),
+    };
+
+    // A data entry without a parameter selected goes through
+    let unselected = message(control_change(0, 6, 10));
+    assert_eq!(assembler.process(unselected), Some(unselected));
+
+    assert_eq!(assembler.process(message(control_change(0, 99, 1))), None);
+    assert_eq!(assembler.process(message(control_change(0, 98, 2))), None);
+    assert_eq!(
+      assembler.process(message(control_change(0, 6, 0x40))),
+      Some(Message {
+        group: 3,
+        mtype: MessageType::ChannelVoice(ChannelVoice {
+          channel: 0,
+          message: ChanelVoiceMessage::AssignableController {
+            bank: 1,
+            index: 2,
+            data: 0x8000_0000,
+          },
+        }),
+      })
+    );
+
+    // The rest of the control changes, and other groups, are not affected
+    let volume = message(control_change(0, 7, 100));
+    assert_eq!(assembler.process(volume), Some(volume));
+    let other_group = Message {
+      group: 4,
+      ..unselected
+    };
+    assert_eq!(assembler.process(other_group), Some(other_group));
+  }
+}
@@ -0,0 +1,213 @@
+//! Rate limiting of the control changes, for the endless encoders and ribbon controllers
+//! sending far more values than the handlers need.
+//!
+//! Every controller lets one value through per interval. The values received in between are
+//! dropped, but the last one is kept pending, and delivered once its interval has passed,
+//! so the final position of a controller is not lost. The limiter has no timers of its own, so the
+//! pending values need to be asked for, as the inputs do when they receive more data from the source,
+//! and from a timer at `next_deadline` for when the source stops sending.
+
+use alloc::vec::Vec;
+
+use crate::event::TimestampNanos;
+use crate::protocol::messages::channel_voice::{ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::{Message, MessageType};
+
+/// Controllers followed at the same time. The rest pass through without limits until some are released.
+const MAX_CONTROLLERS: usize = 64;
+
+const NANOS_PER_SECOND: TimestampNanos = 1_000_000_000;
+
+#[derive(Debug, Clone)]
+struct Controller {
+  /// Group, channel and index of the controller
+  key: u16,
+  /// When the last value was delivered
+  sent: TimestampNanos,
+  /// The last value dropped since then
+  pending: Option<Message>,
+}
+
+/// Limits the MIDI 1.0 and 2.0 control changes to a maximum rate per controller, keeping the state
+/// per group, channel and controller index. The rest of the messages are returned as they are.
+///
+/// The memory for the controllers is allocated upfront, so it can be used from real-time threads.
+#[derive(Debug, Clone)]
+pub struct ControllerRateLimiter {
+  interval: TimestampNanos,
+  controllers: Vec<Controller>,
+}
+
+impl ControllerRateLimiter {
+  /// Lets `max_rate` control changes per second through for every controller.
+  pub fn new(max_rate: u32) -> Self {
+    Self {
+      interval: NANOS_PER_SECOND / max_rate.max(1) as TimestampNanos,
+      controllers: Vec::with_capacity(MAX_CONTROLLERS),
+    }
+  }
+
+  /// Returns the message unless it is a control change received too soon after the previous one.
+  pub fn process(&mut self, message: Message, timestamp: TimestampNanos) -> Option<Message> {
+    let key = match Self::key(&message) {
+      Some(key) => key,
+      None => return Some(message),
+    };
+
+    let interval = self.interval;
+    match self
+      .controllers
+      .iter_mut()
+      .find(|controller| controller.key == key)
+    {
+      Some(controller) if timestamp.saturating_sub(controller.sent) < interval => {
+        controller.pending = Some(message);
+        None
+      }
+      Some(controller) => {
+        controller.sent = timestamp;
+        controller.pending = None;
+        Some(message)
+      }
+      None => {
+        let controller = Controller {
+          key,
+          sent: timestamp,
+          pending: None,
+        };
+        if self.controllers.len() < MAX_CONTROLLERS {
+          self.controllers.push(controller);
+        } else if let Some(released) = self
+          .controllers
+          .iter_mut()
+          .filter(|controller| controller.pending.is_none())
+          .min_by_key(|controller| controller.sent)
+        {
+          *released = controller;
+        }
+        Some(message)
+      }
+    }
+  }
+
+  /// Takes the oldest pending value whose interval has passed by `now`,
+  /// with the time it was due at as its timestamp.
+  pub fn next_pending(&mut self, now: TimestampNanos) -> Option<(Message, TimestampNanos)> {
+    let interval = self.interval;
+    self
+      .controllers
+      .iter_mut()
+      .filter(|controller| {
+        controller.pending.is_some() && now.saturating_sub(controller.sent) >= interval
+      })
+      .min_by_key(|controller| controller.sent)
+      .and_then(|controller| {
+        controller.sent += interval;
+        controller
+          .pending
+          .take()
+          .map(|message| (message, controller.sent))
+      })
+  }
+
+  /// When the oldest pending value is due, if any.
+  pub fn next_deadline(&self) -> Option<TimestampNanos> {
+    self
+      .controllers
+      .iter()
+      .filter(|controller| controller.pending.is_some())
+      .map(|controller| controller.sent + self.interval)
+      .min()
+  }
+
+  pub fn reset(&mut self) {
+    self.controllers.clear();
+  }
+
+  fn key(message: &Message) -> Option<u16> {
+    let (channel, index) = match message.mtype {
+      MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::ControlChange { index, .. },
+      })
+      | MessageType::ChannelVoice(ChannelVoice {
+        channel,
+        message: ChanelVoiceMessage::ControlChange { index, .. },
+      }) => (channel, index),
+      _ => return None,
+    };
+    Some(
+      ((message.group & 0x0f) as u16) << 11
+        | ((channel & 0x0f) as u16) << 7
+        | (index & 0x7f) as u16,
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const MS: TimestampNanos = 1_000_000;
+
+  fn control_change(index: u8, data: u8) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message: ChannelVoice1Message::ControlChange { index, data },
+      }),
+    }
+  }
+
+  #[test]
+  fn drop_values_above_the_rate() {
+    let mut limiter = ControllerRateLimiter::new(100);
+
+    assert!(limiter.process(control_change(1, 10), 0).is_some());
+    assert!(limiter.process(control_change(1, 11), 2 * MS).is_none());
+    assert!(limiter.process(control_change(1, 12), 5 * MS).is_none());
+    assert!(limiter.process(control_change(2, 10), 5 * MS).is_some());
+    assert!(limiter.process(control_change(1, 13), 10 * MS).is_some());
+    assert_eq!(limiter.next_pending(20 * MS), None);
+  }
+
+  #[test]
+  fn deliver_the_last_value() {
+    let mut limiter = ControllerRateLimiter::new(100);
+
+    limiter.process(control_change(1, 10), 0);
+    limiter.process(control_change(1, 11), 2 * MS);
+    limiter.process(control_change(1, 12), 5 * MS);
+
+    assert_eq!(limiter.next_pending(9 * MS), None);
+    assert_eq!(limiter.next_deadline(), Some(10 * MS));
+    assert_eq!(
+      limiter.next_pending(30 * MS),
+      Some((control_change(1, 12), 10 * MS))
+    );
+    assert_eq!(limiter.next_pending(30 * MS), None);
+    assert_eq!(limiter.next_deadline(), None);
+    assert!(limiter.process(control_change(1, 13), 15 * MS).is_none());
+    assert_eq!(limiter.next_deadline(), Some(20 * MS));
+  }
+
+  #[test]
+  fn other_messages_pass() {
+    let mut limiter = ControllerRateLimiter::new(1);
+    let note_on = Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message: ChannelVoice1Message::NoteOn {
+          note: 60,
+          velocity: 100,
+        },
+      }),
+    };
+
+    assert!(limiter.process(note_on, 0).is_some());
+    assert!(limiter.process(note_on, 1).is_some());
+  }
+}
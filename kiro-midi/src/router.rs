@@ -0,0 +1,545 @@
+//! Routing matrices, connecting the sources to the destinations through processing graphs.
+//!
+//! Every `Route` goes from the sources of an input, through a chain of nodes, to the destinations
+//! of an output. The `Router` creates the inputs and the outputs for them in a driver, and keeps
+//! track of the routes so they can be changed while the events are flowing:
+//!
+//! - The nodes are swapped without missing any event, as the chains are `Swappable`.
+//! - The sources and the destinations are reconnected as with `DriverSpec::set_input_sources`
+//!   and `DriverSpec::set_output_destinations`.
+//! - Several routes can send to the same output, which keeps the filter and the transforms of
+//!   the first route creating it.
+//!
+//! The drivers can't remove inputs nor outputs, so removing a route disconnects them instead,
+//! and a route added later with the same names reuses them, keeping the options they were created with.
+//!
+//! The `RouterConfig` holds all the routes, so applications can persist them (with the `serde` feature)
+//! and restore them on startup. It also keeps the names of the sources and destinations matched by id,
+//! so the routes are connected to them again even if their ids changed, as long as their names didn't.
+//! The nodes that can't be described by a config are created by factories registered by name.
+
+use std::collections::{BTreeMap, HashMap};
+
+use thiserror::Error;
+
+use crate::destination_match::{DestinationMatch, DestinationMatches};
+use crate::drivers::{DriverSpec, Error as DriverError};
+use crate::endpoints::EndpointId;
+use crate::filter::FilterExpr;
+use crate::input_config::InputConfig;
+use crate::output::Output;
+use crate::output_config::OutputConfig;
+use crate::processor::{self, Chain, Processor, ProcessorSwap, Swappable};
+use crate::source_match::{SourceMatch, SourceMatches};
+use crate::transform::Transform;
+
+#[derive(Error, Debug)]
+pub enum RouterError {
+  #[error("Driver: {0}")]
+  Driver(#[from] DriverError),
+
+  #[error("A route with this name already exists: {0}")]
+  RouteAlreadyExists(String),
+
+  #[error("Route not found: {0}")]
+  RouteNotFound(String),
+
+  #[error("No factory registered for the node: {0}")]
+  UnknownNode(String),
+}
+
+/// A node of the chain of a route
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub enum NodeConfig {
+  Filter(FilterExpr),
+  Transform(Box<Transform>),
+  /// A node created by the factory registered with this name in the `Router`
+  Named(String),
+}
+
+impl From<FilterExpr> for NodeConfig {
+  fn from(filter: FilterExpr) -> Self {
+    Self::Filter(filter)
+  }
+}
+
+impl From<Transform> for NodeConfig {
+  fn from(transform: Transform) -> Self {
+    Self::Transform(Box::new(transform))
+  }
+}
+
+/// The events received by an input, going through a chain of nodes, and sent by an output.
+///
+/// The route is named after its input.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
+pub struct Route {
+  pub input: InputConfig,
+  pub nodes: Vec<NodeConfig>,
+  pub output: OutputConfig,
+}
+
+impl Route {
+  pub fn new(input: InputConfig, output: OutputConfig) -> Self {
+    Self {
+      input,
+      nodes: Vec::new(),
+      output,
+    }
+  }
+
+  /// Adds a node at the end of the chain.
+  #[must_use]
+  pub fn with_node<N>(mut self, node: N) -> Self
+  where
+    N: Into<NodeConfig>,
+  {
+    self.nodes.push(node.into());
+    self
+  }
+
+  pub fn name(&self) -> &str {
+    self.input.name.as_str()
+  }
+}
+
+/// All the routes of a `Router`, to persist them and restore them with `Router::restore`.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default)]
+pub struct RouterConfig {
+  pub routes: Vec<Route>,
+  /// Names of the sources and destinations matched by id, to find them again if their ids change
+  pub endpoint_names: BTreeMap<EndpointId, String>,
+}
+
+type NodeFactory = Box<dyn Fn() -> Box<dyn Processor> + Send + Sync>;
+
+/// Keeps the routes between the sources and the destinations of a driver, see the module docs.
+#[derive(Default)]
+pub struct Router {
+  routes: Vec<Route>,
+  inputs: HashMap<String, ProcessorSwap>,
+  outputs: HashMap<String, Output>,
+  factories: HashMap<String, NodeFactory>,
+}
+
+impl Router {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// Registers a factory for the nodes named `name` in the routes.
+  #[must_use]
+  pub fn with_node<N, F, P>(mut self, name: N, factory: F) -> Self
+  where
+    N: Into<String>,
+    F: Fn() -> P + Send + Sync + 'static,
+    P: Processor + 'static,
+  {
+    let factory: NodeFactory = Box::new(move || Box::new(factory()));
+    self.factories.insert(name.into(), factory);
+    self
+  }
+
+  pub fn routes(&self) -> &[Route] {
+    self.routes.as_slice()
+  }
+
+  pub fn route(&self, name: &str) -> Option<&Route> {
+    self.routes.iter().find(|route| route.name() == name)
+  }
+
+  /// Connects a route, creating its input and its output in the driver unless they already exist.
+  pub fn add_route<D>(&mut self, driver: &mut D, route: Route) -> Result<(), RouterError>
+  where
+    D: DriverSpec,
+  {
+    if self.route(route.name()).is_some() {
+      return Err(RouterError::RouteAlreadyExists(route.name().to_string()));
+    }
+
+    let output = match self.outputs.get(route.output.name.as_str()) {
+      Some(output) => {
+        driver.set_output_destinations(
+          route.output.name.as_str(),
+          route.output.destinations.clone(),
+        )?;
+        output.clone()
+      }
+      None => {
+        let output = driver.create_output(route.output.clone())?;
+        self
+          .outputs
+          .insert(route.output.name.clone(), output.clone());
+        output
+      }
+    };
+    let chain = self.chain(&route.nodes, output)?;
+
+    match self.inputs.get(route.name()) {
+      Some(swap) => {
+        swap.swap(chain);
+        driver.set_input_sources(route.name(), route.input.sources.clone())?;
+      }
+      None => {
+        let (swappable, swap) = Swappable::new(chain);
+        driver.create_input(route.input.clone(), processor::handler(swappable, |_| {}))?;
+        self.inputs.insert(route.name().to_string(), swap);
+      }
+    }
+
+    // The routes sharing the output follow its destinations
+    for other in self.routes.iter_mut() {
+      if other.output.name == route.output.name {
+        other.output.destinations = route.output.destinations.clone();
+      }
+    }
+    self.routes.push(route);
+    Ok(())
+  }
+
+  /// Disconnects a route, returning it.
+  ///
+  /// The output keeps its destinations while other routes send to it.
+  pub fn remove_route<D>(&mut self, driver: &D, name: &str) -> Result<Route, RouterError>
+  where
+    D: DriverSpec,
+  {
+    let index = self.index(name)?;
+    let route = self.routes.remove(index);
+
+    driver.set_input_sources(name, SourceMatches::default())?;
+    if let Some(swap) = self.inputs.get(name) {
+      swap.swap(Chain::new());
+    }
+    let output_name = route.output.name.as_str();
+    if !self
+      .routes
+      .iter()
+      .any(|other| other.output.name == output_name)
+    {
+      driver.set_output_destinations(output_name, DestinationMatches::default())?;
+    }
+    Ok(route)
+  }
+
+  /// Replaces the chain of nodes of a route.
+  pub fn set_nodes(&mut self, name: &str, nodes: Vec<NodeConfig>) -> Result<(), RouterError> {
+    let index = self.index(name)?;
+    let output = self.outputs[self.routes[index].output.name.as_str()].clone();
+    let chain = self.chain(&nodes, output)?;
+    if let Some(swap) = self.inputs.get(name) {
+      swap.swap(chain);
+    }
+    self.routes[index].nodes = nodes;
+    Ok(())
+  }
+
+  /// Replaces the sources of a route.
+  pub fn set_sources<D>(
+    &mut self,
+    driver: &D,
+    name: &str,
+    sources: SourceMatches,
+  ) -> Result<(), RouterError>
+  where
+    D: DriverSpec,
+  {
+    let index = self.index(name)?;
+    driver.set_input_sources(name, sources.clone())?;
+    self.routes[index].input.sources = sources;
+    Ok(())
+  }
+
+  /// Replaces the destinations of the output of a route, for all the routes sending to it.
+  pub fn set_destinations<D>(
+    &mut self,
+    driver: &D,
+    name: &str,
+    destinations: DestinationMatches,
+  ) -> Result<(), RouterError>
+  where
+    D: DriverSpec,
+  {
+    let index = self.index(name)?;
+    let output_name = self.routes[index].output.name.clone();
+    driver.set_output_destinations(output_name.as_str(), destinations.clone())?;
+    for route in self.routes.iter_mut() {
+      if route.output.name == output_name {
+        route.output.destinations = destinations.clone();
+      }
+    }
+    Ok(())
+  }
+
+  /// The routes, with the names of the sources and destinations of the driver matched by id.
+  pub fn config<D>(&self, driver: &D) -> RouterConfig
+  where
+    D: DriverSpec,
+  {
+    let mut names = BTreeMap::new();
+    for source in driver.sources() {
+      names.insert(source.id, source.name);
+    }
+    for destination in driver.destinations() {
+      names.insert(destination.id, destination.name);
+    }
+
+    let mut endpoint_names = BTreeMap::new();
+    for id in self.routes.iter().flat_map(matched_ids) {
+      if let Some(name) = names.get(&id) {
+        endpoint_names.insert(id, name.clone());
+      }
+    }
+
+    RouterConfig {
+      routes: self.routes.clone(),
+      endpoint_names,
+    }
+  }
+
+  /// Adds the routes of a config, matching the sources and destinations whose ids changed by their names.
+  ///
+  /// The ids of the endpoints missing in the driver are kept, in case they come back later.
+  pub fn restore<D>(&mut self, driver: &mut D, config: RouterConfig) -> Result<(), RouterError>
+  where
+    D: DriverSpec,
+  {
+    let sources = driver
+      .sources()
+      .into_iter()
+      .map(|source| (source.id, source.name))
+      .collect::<Vec<_>>();
+    let destinations = driver
+      .destinations()
+      .into_iter()
+      .map(|destination| (destination.id, destination.name))
+      .collect::<Vec<_>>();
+    let resolve = |id: EndpointId, endpoints: &[(EndpointId, String)]| {
+      if endpoints.iter().any(|(endpoint_id, _)| *endpoint_id == id) {
+        return id;
+      }
+      config
+        .endpoint_names
+        .get(&id)
+        .and_then(|name| {
+          endpoints
+            .iter()
+            .find(|(_, endpoint_name)| endpoint_name == name)
+        })
+        .map_or(id, |(endpoint_id, _)| *endpoint_id)
+    };
+
+    for mut route in config.routes.clone() {
+      for (source_match, _, _) in route.input.sources.iter_mut() {
+        if let SourceMatch::Id(id) = source_match {
+          *id = resolve(*id, &sources);
+        }
+      }
+      for (destination_match, _) in route.output.destinations.iter_mut() {
+        if let DestinationMatch::Id(id) = destination_match {
+          *id = resolve(*id, &destinations);
+        }
+      }
+      self.add_route(driver, route)?;
+    }
+    Ok(())
+  }
+
+  fn index(&self, name: &str) -> Result<usize, RouterError> {
+    self
+      .routes
+      .iter()
+      .position(|route| route.name() == name)
+      .ok_or_else(|| RouterError::RouteNotFound(name.to_string()))
+  }
+
+  /// Creates the nodes, followed by the output.
+  fn chain(&self, nodes: &[NodeConfig], output: Output) -> Result<Chain, RouterError> {
+    let mut chain = Chain::new();
+    for node in nodes {
+      match node {
+        NodeConfig::Filter(filter) => chain.push(filter.clone()),
+        NodeConfig::Transform(transform) => chain.push(**transform),
+        NodeConfig::Named(name) => {
+          let factory = self
+            .factories
+            .get(name)
+            .ok_or_else(|| RouterError::UnknownNode(name.clone()))?;
+          chain.push(factory())
+        }
+      }
+    }
+    chain.push(output);
+    Ok(chain)
+  }
+}
+
+/// The ids of the sources and destinations matched by a route.
+fn matched_ids(route: &Route) -> impl Iterator<Item = EndpointId> + '_ {
+  let sources = route
+    .input
+    .sources
+    .iter()
+    .filter_map(|(source_match, _, _)| match source_match {
+      SourceMatch::Id(id) => Some(*id),
+      _ => None,
+    });
+  let destinations = route
+    .output
+    .destinations
+    .iter()
+    .filter_map(|(destination_match, _)| match destination_match {
+      DestinationMatch::Id(id) => Some(*id),
+      _ => None,
+    });
+  sources.chain(destinations)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::drivers::MockDriver;
+  use crate::filter::Filter;
+  use crate::processor::FnProcessor;
+
+  const NOTE_ON: [u8; 3] = [0x90, 0x3c, 0x64];
+
+  fn route(name: &str, source: &str, output: &str, destination: &str) -> Route {
+    Route::new(
+      InputConfig::new(name).with_source(source, Filter::new()),
+      OutputConfig::new(output).with_destination(destination),
+    )
+  }
+
+  #[test]
+  fn route_through_the_nodes() {
+    let mut driver = MockDriver::new("test");
+    let keys = driver.add_source("Keys");
+    let synth = driver.add_destination("Synth");
+    let mut router = Router::new().with_node("octave up", || {
+      FnProcessor(|event, output: &mut dyn FnMut(_)| output(event))
+    });
+
+    router
+      .add_route(
+        &mut driver,
+        route("keys", "Keys", "synth", "Synth")
+          .with_node(Transform::new().with_transpose(12))
+          .with_node(NodeConfig::Named("octave up".to_string())),
+      )
+      .unwrap();
+    driver.push_midi1(keys, 10, &NOTE_ON);
+
+    let sent = driver.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].destination, synth);
+    assert_eq!(sent[0].ump, vec![0x2090_4864]);
+
+    router
+      .set_nodes("keys", vec![Transform::new().with_transpose(-12).into()])
+      .unwrap();
+    driver.push_midi1(keys, 20, &NOTE_ON);
+    assert_eq!(driver.take_sent()[0].ump, vec![0x2090_3064]);
+
+    assert!(matches!(
+      router.set_nodes("keys", vec![NodeConfig::Named("missing".to_string())]),
+      Err(RouterError::UnknownNode(_))
+    ));
+  }
+
+  #[test]
+  fn change_and_remove_routes() {
+    let mut driver = MockDriver::new("test");
+    let keys = driver.add_source("Keys");
+    let pads = driver.add_source("Pads");
+    driver.add_destination("Synth");
+    let drums = driver.add_destination("Drums");
+    let mut router = Router::new();
+
+    router
+      .add_route(&mut driver, route("keys", "Keys", "synth", "Synth"))
+      .unwrap();
+    router
+      .add_route(&mut driver, route("pads", "Pads", "synth", "Synth"))
+      .unwrap();
+    assert!(matches!(
+      router.add_route(&mut driver, route("keys", "Keys", "synth", "Synth")),
+      Err(RouterError::RouteAlreadyExists(_))
+    ));
+
+    router
+      .set_destinations(
+        &driver,
+        "keys",
+        DestinationMatches::default().with_destination("Drums"),
+      )
+      .unwrap();
+    driver.push_midi1(pads, 10, &NOTE_ON);
+    assert_eq!(driver.take_sent()[0].destination, drums);
+
+    router.remove_route(&driver, "keys").unwrap();
+    driver.push_midi1(keys, 20, &NOTE_ON);
+    assert!(driver.take_sent().is_empty());
+    driver.push_midi1(pads, 30, &NOTE_ON);
+    assert_eq!(driver.take_sent().len(), 1);
+
+    router.remove_route(&driver, "pads").unwrap();
+    router
+      .add_route(&mut driver, route("keys", "Keys", "synth", "Synth"))
+      .unwrap();
+    driver.push_midi1(keys, 40, &NOTE_ON);
+    assert_eq!(driver.take_sent().len(), 1);
+    assert_eq!(router.routes().len(), 1);
+  }
+
+  #[test]
+  fn restore_by_endpoint_names() {
+    let mut driver = MockDriver::new("test");
+    let keys = driver.add_source("Keys");
+    let synth = driver.add_destination("Synth");
+    let mut router = Router::new();
+    router
+      .add_route(
+        &mut driver,
+        Route::new(
+          InputConfig::new("keys").with_source(keys, Filter::new()),
+          OutputConfig::new("synth").with_destination(synth),
+        ),
+      )
+      .unwrap();
+
+    let mut config = router.config(&driver);
+    assert_eq!(config.endpoint_names.get(&keys), Some(&"Keys".to_string()));
+    assert_eq!(
+      config.endpoint_names.get(&synth),
+      Some(&"Synth".to_string())
+    );
+
+    // As if the ids were different on the next run
+    let (old_keys, old_synth) = (keys + 1, synth + 1);
+    config.endpoint_names = [
+      (old_keys, "Keys".to_string()),
+      (old_synth, "Synth".to_string()),
+    ]
+    .into_iter()
+    .collect();
+    for route in config.routes.iter_mut() {
+      route.input.sources = SourceMatches::default().with_source(old_keys, Filter::new());
+      route.output.destinations = DestinationMatches::default().with_destination(old_synth);
+    }
+
+    let mut driver = MockDriver::new("test");
+    let keys = driver.add_source("Keys");
+    let synth = driver.add_destination("Synth");
+    let mut router = Router::new();
+    router.restore(&mut driver, config).unwrap();
+    driver.push_midi1(keys, 10, &NOTE_ON);
+
+    let sent = driver.take_sent();
+    assert_eq!(sent.len(), 1);
+    assert_eq!(sent[0].destination, synth);
+  }
+}
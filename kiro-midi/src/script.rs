@@ -0,0 +1,423 @@
+//! Processors running a script for every event, written in [Rhai](https://rhai.rs).
+//!
+//! The script defines a `process(event)` function, which returns the event (changed or not) to pass it on,
+//! an array of events to pass on several of them, or nothing to drop it:
+//!
+//! ```text
+//! fn process(event) {
+//!   if event.kind == "note_on" {
+//!     let fifth = event;
+//!     fifth.note += 7;
+//!     return [event, fifth];
+//!   }
+//!   event
+//! }
+//! ```
+//!
+//! The events are maps with the `timestamp`, the `endpoint`, the `group` (from 1 to 16) and the `kind` of message.
+//! The MIDI 1.0 channel voice messages also have their `channel` (from 1 to 16) and data:
+//!
+//! - `note_on` and `note_off`: the `note` and the `velocity`.
+//! - `poly_pressure`: the `note` and the `pressure`.
+//! - `control_change`: the `index` and the `value`.
+//! - `program_change`: the `program`.
+//! - `channel_pressure`: the `pressure`.
+//! - `pitch_bend`: the `value`, from 0 to 16383 and centered at 8192.
+//!
+//! The rest of messages have the `other` kind, and can be dropped, repeated or moved to another group,
+//! but not changed. The values out of range are clamped, and the events missing any of them are dropped.
+//!
+//! The scripts are compiled with `Script::compile`, which should be called off the threads receiving the events,
+//! and run sandboxed (without access to the file system nor the network), with a limit on the operations and
+//! the time taken by every event. The events pass on unchanged when the script fails or runs out of time.
+//! Unlike the rest of processors, running the scripts allocates.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use rhai::{Array, Dynamic, Engine, Map, Scope, AST, INT};
+use thiserror::Error;
+
+use crate::endpoints::EndpointId;
+use crate::event::{Event, TimestampNanos};
+use crate::processor::Processor;
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::{Message, MessageType};
+
+/// The function called for every event
+const PROCESS: &str = "process";
+
+/// Time that a script can take by default for every event
+pub const DEFAULT_TIME_BUDGET: Duration = Duration::from_millis(1);
+
+/// Operations that a script can run by default for every event
+pub const DEFAULT_MAX_OPERATIONS: u64 = 100_000;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ScriptError {
+  #[error("The script doesn't compile: {0}")]
+  Compile(String),
+
+  #[error("The script doesn't define a process(event) function")]
+  MissingProcess,
+}
+
+/// A script compiled and validated, ready to be run by a `ScriptProcessor`.
+#[derive(Clone)]
+pub struct Script {
+  ast: AST,
+}
+
+impl Script {
+  pub fn compile(source: &str) -> Result<Self, ScriptError> {
+    let ast = sandboxed_engine()
+      .compile(source)
+      .map_err(|error| ScriptError::Compile(error.to_string()))?;
+    let valid = ast
+      .iter_functions()
+      .any(|function| function.name == PROCESS && function.params.len() == 1);
+    if valid {
+      Ok(Self { ast })
+    } else {
+      Err(ScriptError::MissingProcess)
+    }
+  }
+}
+
+/// Time taken by the current event, shared with the engine to stop the scripts running out of time
+struct Budget {
+  base: Instant,
+  /// Since `base`, in nanoseconds
+  started: AtomicU64,
+  limit: AtomicU64,
+}
+
+impl Budget {
+  fn start(&self) {
+    self.started.store(self.now(), Ordering::Relaxed);
+  }
+
+  fn exceeded(&self) -> bool {
+    let elapsed = self
+      .now()
+      .saturating_sub(self.started.load(Ordering::Relaxed));
+    elapsed > self.limit.load(Ordering::Relaxed)
+  }
+
+  fn now(&self) -> u64 {
+    self.base.elapsed().as_nanos() as u64
+  }
+}
+
+/// Runs a script for every event, see the module docs.
+///
+/// It can be swapped for another one while processing with `Swappable`, after compiling the new script
+/// in another thread.
+pub struct ScriptProcessor {
+  engine: Engine,
+  script: Script,
+  budget: Arc<Budget>,
+}
+
+impl ScriptProcessor {
+  pub fn new(script: Script) -> Self {
+    let budget = Arc::new(Budget {
+      base: Instant::now(),
+      started: AtomicU64::new(0),
+      limit: AtomicU64::new(DEFAULT_TIME_BUDGET.as_nanos() as u64),
+    });
+    let mut engine = sandboxed_engine();
+    let progress_budget = budget.clone();
+    engine
+      .set_max_operations(DEFAULT_MAX_OPERATIONS)
+      .on_progress(move |_| progress_budget.exceeded().then(|| Dynamic::UNIT));
+    Self {
+      engine,
+      script,
+      budget,
+    }
+  }
+
+  /// Stops the script when it takes longer than `budget` for an event.
+  #[must_use]
+  pub fn with_time_budget(self, budget: Duration) -> Self {
+    let limit = budget.as_nanos().min(u64::MAX as u128) as u64;
+    self.budget.limit.store(limit, Ordering::Relaxed);
+    self
+  }
+
+  /// Stops the script when it runs more than `max_operations` for an event.
+  #[must_use]
+  pub fn with_max_operations(mut self, max_operations: u64) -> Self {
+    self.engine.set_max_operations(max_operations);
+    self
+  }
+}
+
+impl Processor for ScriptProcessor {
+  fn process(&mut self, event: Event, output: &mut dyn FnMut(Event)) {
+    self.budget.start();
+    let result: Result<Dynamic, _> = self.engine.call_fn(
+      &mut Scope::new(),
+      &self.script.ast,
+      PROCESS,
+      (Dynamic::from(event_to_map(&event)),),
+    );
+    match result {
+      Ok(result) if result.is_unit() => {}
+      Ok(result) if result.is::<Array>() => {
+        for item in result.try_cast::<Array>().unwrap_or_default() {
+          if let Some(event) = item
+            .try_cast::<Map>()
+            .and_then(|map| map_to_event(&map, &event))
+          {
+            output(event);
+          }
+        }
+      }
+      Ok(result) => {
+        if let Some(event) = result
+          .try_cast::<Map>()
+          .and_then(|map| map_to_event(&map, &event))
+        {
+          output(event);
+        }
+      }
+      Err(_) => output(event),
+    }
+  }
+}
+
+/// An engine without access to anything but the events, and limits to the resources used by the scripts.
+fn sandboxed_engine() -> Engine {
+  let mut engine = Engine::new();
+  engine
+    .set_max_call_levels(16)
+    .set_max_expr_depths(64, 32)
+    .set_max_string_size(1024)
+    .set_max_array_size(256)
+    .set_max_map_size(64)
+    .on_print(|_| {})
+    .on_debug(|_, _, _| {});
+  engine
+}
+
+fn event_to_map(event: &Event) -> Map {
+  let mut map = Map::new();
+  let mut insert = |name: &str, value: Dynamic| {
+    map.insert(name.into(), value);
+  };
+  insert("timestamp", Dynamic::from(event.timestamp as INT));
+  insert("endpoint", Dynamic::from(event.endpoint as INT));
+  insert(
+    "group",
+    Dynamic::from((event.message.group & 0x0f) as INT + 1),
+  );
+  // Kept to pass on the other messages
+  insert("message", Dynamic::from(event.message));
+
+  let (kind, fields): (&'static str, [(&str, INT); 2]) = match event.message.mtype {
+    MessageType::ChannelVoice1(ChannelVoice1 { channel, message }) => {
+      insert("channel", Dynamic::from((channel & 0x0f) as INT + 1));
+      match message {
+        ChannelVoice1Message::NoteOn { note, velocity } => (
+          "note_on",
+          [("note", note as INT), ("velocity", velocity as INT)],
+        ),
+        ChannelVoice1Message::NoteOff { note, velocity } => (
+          "note_off",
+          [("note", note as INT), ("velocity", velocity as INT)],
+        ),
+        ChannelVoice1Message::PolyPressure { note, data } => (
+          "poly_pressure",
+          [("note", note as INT), ("pressure", data as INT)],
+        ),
+        ChannelVoice1Message::ControlChange { index, data } => (
+          "control_change",
+          [("index", index as INT), ("value", data as INT)],
+        ),
+        ChannelVoice1Message::ProgramChange { program } => {
+          ("program_change", [("program", program as INT), ("", 0)])
+        }
+        ChannelVoice1Message::ChannelPressure { data } => {
+          ("channel_pressure", [("pressure", data as INT), ("", 0)])
+        }
+        ChannelVoice1Message::PitchBend { data } => {
+          ("pitch_bend", [("value", data as INT), ("", 0)])
+        }
+      }
+    }
+    _ => ("other", [("", 0); 2]),
+  };
+  insert("kind", Dynamic::from(kind));
+  for (name, value) in fields {
+    if !name.is_empty() {
+      insert(name, Dynamic::from(value));
+    }
+  }
+  map
+}
+
+/// The event described by a map returned by a script, taking what is missing from the event received.
+fn map_to_event(map: &Map, received: &Event) -> Option<Event> {
+  let int = |name: &str| map.get(name).and_then(|value| value.as_int().ok());
+  let data = |name: &str| int(name).map(|value| value.clamp(0, 127) as u8);
+
+  let timestamp = int("timestamp").map_or(received.timestamp, |timestamp| {
+    timestamp.max(0) as TimestampNanos
+  });
+  let endpoint = int("endpoint").map_or(received.endpoint, |endpoint| endpoint as EndpointId);
+  let group = int("group").map_or(received.message.group, |group| {
+    (group.clamp(1, 16) - 1) as u8
+  });
+  let kind = map
+    .get("kind")
+    .and_then(|kind| kind.clone().into_immutable_string().ok())?;
+
+  let message = match kind.as_str() {
+    "note_on" => ChannelVoice1Message::NoteOn {
+      note: data("note")?,
+      velocity: data("velocity")?,
+    },
+    "note_off" => ChannelVoice1Message::NoteOff {
+      note: data("note")?,
+      velocity: data("velocity")?,
+    },
+    "poly_pressure" => ChannelVoice1Message::PolyPressure {
+      note: data("note")?,
+      data: data("pressure")?,
+    },
+    "control_change" => ChannelVoice1Message::ControlChange {
+      index: data("index")?,
+      data: data("value")?,
+    },
+    "program_change" => ChannelVoice1Message::ProgramChange {
+      program: data("program")?,
+    },
+    "channel_pressure" => ChannelVoice1Message::ChannelPressure {
+      data: data("pressure")?,
+    },
+    "pitch_bend" => ChannelVoice1Message::PitchBend {
+      data: int("value")?.clamp(0, 0x3fff) as u16,
+    },
+    _ => {
+      let mut message = map
+        .get("message")
+        .and_then(|message| message.clone().try_cast::<Message>())?;
+      // The utility messages are not addressed to any group
+      if !matches!(message.mtype, MessageType::Utility(_)) {
+        message.group = group;
+      }
+      return Some(Event {
+        timestamp,
+        endpoint,
+        message,
+      });
+    }
+  };
+
+  let channel = (int("channel")?.clamp(1, 16) - 1) as u8;
+  Some(Event {
+    timestamp,
+    endpoint,
+    message: Message {
+      group,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 { channel, message }),
+    },
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::messages::system::System;
+
+  fn event(mtype: MessageType) -> Event {
+    Event {
+      timestamp: 10,
+      endpoint: 1,
+      message: Message { group: 0, mtype },
+    }
+  }
+
+  fn note_on(channel: u8, note: u8) -> Event {
+    event(MessageType::ChannelVoice1(ChannelVoice1 {
+      channel,
+      message: ChannelVoice1Message::NoteOn {
+        note,
+        velocity: 0x64,
+      },
+    }))
+  }
+
+  fn run(source: &str, event: Event) -> Vec<Event> {
+    let mut processor = ScriptProcessor::new(Script::compile(source).unwrap());
+    let mut events = Vec::new();
+    processor.process(event, &mut |event| events.push(event));
+    events
+  }
+
+  #[test]
+  fn compile_errors() {
+    assert!(matches!(
+      Script::compile("fn process(event) {"),
+      Err(ScriptError::Compile(_))
+    ));
+    assert!(matches!(
+      Script::compile("fn other(event) { event }"),
+      Err(ScriptError::MissingProcess)
+    ));
+  }
+
+  #[test]
+  fn change_drop_and_emit_events() {
+    assert_eq!(
+      run(
+        "fn process(event) { event.note += 12; event.channel = 2; event }",
+        note_on(0, 60)
+      ),
+      vec![note_on(1, 72)]
+    );
+    assert!(run("fn process(event) { }", note_on(0, 60)).is_empty());
+    assert_eq!(
+      run(
+        r#"fn process(event) {
+          let fifth = event;
+          fifth.note += 7;
+          [event, fifth, #{ kind: "control_change", channel: 1, index: 64, value: 300 }]
+        }"#,
+        note_on(0, 60)
+      ),
+      vec![
+        note_on(0, 60),
+        note_on(0, 67),
+        event(MessageType::ChannelVoice1(ChannelVoice1 {
+          channel: 0,
+          message: ChannelVoice1Message::ControlChange {
+            index: 64,
+            data: 127
+          },
+        }))
+      ]
+    );
+
+    let start = event(MessageType::System(System::Start));
+    assert_eq!(
+      run("fn process(event) { [event, event] }", start.clone()),
+      vec![start.clone(), start]
+    );
+  }
+
+  #[test]
+  fn failing_scripts_pass_events() {
+    assert_eq!(
+      run("fn process(event) { throw \"oops\" }", note_on(0, 60)),
+      vec![note_on(0, 60)]
+    );
+    assert_eq!(
+      run("fn process(event) { loop {} }", note_on(0, 60)),
+      vec![note_on(0, 60)]
+    );
+  }
+}
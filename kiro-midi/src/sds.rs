@@ -0,0 +1,761 @@
+//! MIDI Sample Dump Standard (SDS).
+//!
+//! A sample is transferred as a dump header followed by data packets of 120 bytes, all of them
+//! Universal Non Real Time SysEx messages. The receiver acknowledges every message, asks for the
+//! packets with a wrong checksum again, and can ask the sender to wait or cancel the dump.
+//! Senders that don't hear back from the receiver keep sending after a timeout, so the dump works
+//! through a single cable too.
+//!
+//! [`SdsSender`] and [`SdsReceiver`] implement both sides of the handshake. They don't send or
+//! receive by themselves: the SysEx messages received are parsed with [`SdsMessage::parse`] and
+//! passed to them, and the messages they return are sent with `Output::send_sysex`.
+
+use crate::event::TimestampNanos;
+
+const UNIVERSAL_NON_REALTIME: u8 = 0x7e;
+
+const DUMP_HEADER: u8 = 0x01;
+const DATA_PACKET: u8 = 0x02;
+const DUMP_REQUEST: u8 = 0x03;
+const EOF: u8 = 0x7b;
+const WAIT: u8 = 0x7c;
+const CANCEL: u8 = 0x7d;
+const NAK: u8 = 0x7e;
+const ACK: u8 = 0x7f;
+
+const DUMP_HEADER_LEN: usize = 19;
+const DUMP_REQUEST_LEN: usize = 5;
+const HANDSHAKE_LEN: usize = 4;
+
+/// Data bytes in every packet
+pub const PACKET_DATA_LEN: usize = 120;
+const DATA_PACKET_LEN: usize = 4 + PACKET_DATA_LEN + 1;
+
+/// Packet numbers are 7 bits, and roll over
+const PACKET_NUMBERS: usize = 0x80;
+
+const MIN_BITS: u8 = 8;
+const MAX_BITS: u8 = 28;
+
+/// How long the sender waits for the receiver to acknowledge the header, before sending without handshake
+pub const HEADER_TIMEOUT: TimestampNanos = 2_000_000_000;
+/// How long the sender waits for the receiver to acknowledge a packet, before sending the next one
+pub const PACKET_TIMEOUT: TimestampNanos = 20_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopType {
+  Forward,
+  /// Forward and backward
+  Alternating,
+  Off,
+}
+
+impl LoopType {
+  fn from_byte(byte: u8) -> Self {
+    match byte {
+      0x00 => Self::Forward,
+      0x01 => Self::Alternating,
+      _ => Self::Off,
+    }
+  }
+
+  fn to_byte(self) -> u8 {
+    match self {
+      Self::Forward => 0x00,
+      Self::Alternating => 0x01,
+      Self::Off => 0x7f,
+    }
+  }
+}
+
+/// The format of a sample, sent before its data
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DumpHeader {
+  pub channel: u8,
+  /// Sample number in the sampler, 14 bits
+  pub sample: u16,
+  /// Bits per word, from 8 to 28
+  pub bits: u8,
+  /// Period of the sample rate, in nanoseconds
+  pub period_nanos: u32,
+  /// Length in words
+  pub length: u32,
+  /// First word of the sustain loop
+  pub loop_start: u32,
+  /// Last word of the sustain loop
+  pub loop_end: u32,
+  pub loop_type: LoopType,
+}
+
+impl DumpHeader {
+  /// Bytes per word in the data packets, as every byte holds 7 bits.
+  pub fn bytes_per_word(&self) -> usize {
+    (self.bits as usize + 6) / 7
+  }
+
+  /// Number of data packets to transfer the sample.
+  pub fn packets(&self) -> usize {
+    let bytes = self.length as usize * self.bytes_per_word();
+    (bytes + PACKET_DATA_LEN - 1) / PACKET_DATA_LEN
+  }
+}
+
+/// A data packet, with its number rolling over after 127
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataPacket {
+  pub channel: u8,
+  pub number: u8,
+  pub data: [u8; PACKET_DATA_LEN],
+  pub checksum: u8,
+}
+
+impl DataPacket {
+  /// Builds a packet with the right checksum, padding the data with zeros.
+  pub fn new(channel: u8, number: u8, data: &[u8]) -> Self {
+    let mut packet = Self {
+      channel: channel & 0x7f,
+      number: number & 0x7f,
+      data: [0; PACKET_DATA_LEN],
+      checksum: 0,
+    };
+    let len = data.len().min(PACKET_DATA_LEN);
+    packet.data[..len].copy_from_slice(&data[..len]);
+    packet.checksum = packet.expected_checksum();
+    packet
+  }
+
+  /// Whether the checksum matches the data, or it was corrupted on the way.
+  pub fn is_valid(&self) -> bool {
+    self.checksum == self.expected_checksum()
+  }
+
+  /// The XOR of all the bytes of the message, from the Sub-ID to the data.
+  fn expected_checksum(&self) -> u8 {
+    let header = [
+      UNIVERSAL_NON_REALTIME,
+      self.channel,
+      DATA_PACKET,
+      self.number,
+    ];
+    let checksum = header
+      .iter()
+      .chain(self.data.iter())
+      .fold(0, |checksum, byte| checksum ^ byte);
+    checksum & 0x7f
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SdsMessage {
+  DumpHeader(DumpHeader),
+  DataPacket(Box<DataPacket>),
+  DumpRequest {
+    channel: u8,
+    sample: u16,
+  },
+  Ack {
+    channel: u8,
+    packet: u8,
+  },
+  /// Asks for a packet again
+  Nak {
+    channel: u8,
+    packet: u8,
+  },
+  /// Asks the sender to pause until the next message
+  Wait {
+    channel: u8,
+    packet: u8,
+  },
+  Cancel {
+    channel: u8,
+    packet: u8,
+  },
+  Eof {
+    channel: u8,
+    packet: u8,
+  },
+}
+
+impl SdsMessage {
+  /// Parses the data of a SysEx message, returning `None` when it is not a valid SDS message.
+  ///
+  /// The data packets with a wrong checksum are returned too, so they can be asked again.
+  pub fn parse(data: &[u8]) -> Option<Self> {
+    let data = data.strip_prefix(&[0xf0]).unwrap_or(data);
+    let data = data.strip_suffix(&[0xf7]).unwrap_or(data);
+    if data.len() < HANDSHAKE_LEN || data[0] != UNIVERSAL_NON_REALTIME {
+      return None;
+    }
+
+    let channel = data[1];
+    let packet = data[3] & 0x7f;
+    let message = match (data[2], data.len()) {
+      (DUMP_HEADER, DUMP_HEADER_LEN) => Self::DumpHeader(DumpHeader {
+        channel,
+        sample: read_u14(&data[3..5]),
+        bits: data[5],
+        period_nanos: read_u21(&data[6..9]),
+        length: read_u21(&data[9..12]),
+        loop_start: read_u21(&data[12..15]),
+        loop_end: read_u21(&data[15..18]),
+        loop_type: LoopType::from_byte(data[18]),
+      }),
+      (DATA_PACKET, DATA_PACKET_LEN) => {
+        let mut packet = DataPacket::new(channel, packet, &data[4..4 + PACKET_DATA_LEN]);
+        packet.checksum = data[4 + PACKET_DATA_LEN];
+        Self::DataPacket(Box::new(packet))
+      }
+      (DUMP_REQUEST, DUMP_REQUEST_LEN) => Self::DumpRequest {
+        channel,
+        sample: read_u14(&data[3..5]),
+      },
+      (ACK, HANDSHAKE_LEN) => Self::Ack { channel, packet },
+      (NAK, HANDSHAKE_LEN) => Self::Nak { channel, packet },
+      (WAIT, HANDSHAKE_LEN) => Self::Wait { channel, packet },
+      (CANCEL, HANDSHAKE_LEN) => Self::Cancel { channel, packet },
+      (EOF, HANDSHAKE_LEN) => Self::Eof { channel, packet },
+      _ => return None,
+    };
+    Some(message)
+  }
+
+  /// Builds the data of the SysEx message, without the `F0` and `F7` bytes.
+  pub fn to_sysex(&self) -> Vec<u8> {
+    let (sub_id, channel, body) = match self {
+      Self::DumpHeader(header) => {
+        let mut body = Vec::with_capacity(DUMP_HEADER_LEN);
+        write_u14(&mut body, header.sample);
+        body.push(header.bits & 0x7f);
+        write_u21(&mut body, header.period_nanos);
+        write_u21(&mut body, header.length);
+        write_u21(&mut body, header.loop_start);
+        write_u21(&mut body, header.loop_end);
+        body.push(header.loop_type.to_byte());
+        (DUMP_HEADER, header.channel, body)
+      }
+      Self::DataPacket(packet) => {
+        let mut body = Vec::with_capacity(DATA_PACKET_LEN);
+        body.push(packet.number & 0x7f);
+        body.extend(packet.data.iter().map(|byte| byte & 0x7f));
+        body.push(packet.checksum & 0x7f);
+        (DATA_PACKET, packet.channel, body)
+      }
+      Self::DumpRequest { channel, sample } => {
+        let mut body = Vec::with_capacity(2);
+        write_u14(&mut body, *sample);
+        (DUMP_REQUEST, *channel, body)
+      }
+      Self::Ack { channel, packet } => (ACK, *channel, vec![packet & 0x7f]),
+      Self::Nak { channel, packet } => (NAK, *channel, vec![packet & 0x7f]),
+      Self::Wait { channel, packet } => (WAIT, *channel, vec![packet & 0x7f]),
+      Self::Cancel { channel, packet } => (CANCEL, *channel, vec![packet & 0x7f]),
+      Self::Eof { channel, packet } => (EOF, *channel, vec![packet & 0x7f]),
+    };
+    let mut data = vec![UNIVERSAL_NON_REALTIME, channel & 0x7f, sub_id];
+    data.extend_from_slice(&body);
+    data
+  }
+
+  pub fn channel(&self) -> u8 {
+    match self {
+      Self::DumpHeader(header) => header.channel,
+      Self::DataPacket(packet) => packet.channel,
+      Self::DumpRequest { channel, .. }
+      | Self::Ack { channel, .. }
+      | Self::Nak { channel, .. }
+      | Self::Wait { channel, .. }
+      | Self::Cancel { channel, .. }
+      | Self::Eof { channel, .. } => *channel,
+    }
+  }
+}
+
+fn read_u14(data: &[u8]) -> u16 {
+  (data[0] & 0x7f) as u16 | ((data[1] & 0x7f) as u16) << 7
+}
+
+fn write_u14(data: &mut Vec<u8>, value: u16) {
+  data.extend_from_slice(&[(value & 0x7f) as u8, ((value >> 7) & 0x7f) as u8]);
+}
+
+fn read_u21(data: &[u8]) -> u32 {
+  (data[0] & 0x7f) as u32 | ((data[1] & 0x7f) as u32) << 7 | ((data[2] & 0x7f) as u32) << 14
+}
+
+fn write_u21(data: &mut Vec<u8>, value: u32) {
+  data.extend_from_slice(&[
+    (value & 0x7f) as u8,
+    ((value >> 7) & 0x7f) as u8,
+    ((value >> 14) & 0x7f) as u8,
+  ]);
+}
+
+/// A sample with its format, and its words as signed values
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sample {
+  pub header: DumpHeader,
+  pub words: Vec<i32>,
+}
+
+impl Sample {
+  /// The data of all the packets, with every word in unsigned form, left justified in 7 bit bytes.
+  pub fn encode(&self) -> Vec<u8> {
+    let bits = self.header.bits.clamp(MIN_BITS, MAX_BITS) as u32;
+    let bytes = self.header.bytes_per_word();
+    let offset = 1i64 << (bits - 1);
+    let shift = bytes as u32 * 7 - bits;
+    let mut data = Vec::with_capacity(self.words.len() * bytes);
+    for word in self.words.iter() {
+      let max = (1i64 << bits) - 1;
+      let value = ((*word as i64 + offset).clamp(0, max) as u32) << shift;
+      for byte in (0..bytes).rev() {
+        data.push(((value >> (byte * 7)) & 0x7f) as u8);
+      }
+    }
+    data
+  }
+
+  /// The words of the data of the packets, ignoring the padding at the end.
+  pub fn decode(header: DumpHeader, data: &[u8]) -> Self {
+    let bits = header.bits.clamp(MIN_BITS, MAX_BITS) as u32;
+    let bytes = header.bytes_per_word();
+    let offset = 1i64 << (bits - 1);
+    let shift = bytes as u32 * 7 - bits;
+    let words = data
+      .chunks_exact(bytes)
+      .take(header.length as usize)
+      .map(|word| {
+        let value = word
+          .iter()
+          .fold(0u32, |value, byte| value << 7 | (byte & 0x7f) as u32);
+        ((value >> shift) as i64 - offset) as i32
+      })
+      .collect();
+    Self { header, words }
+  }
+}
+
+/// How far a dump went
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SdsProgress {
+  pub packets: usize,
+  pub total: usize,
+}
+
+pub type SdsProgressHandler = Box<dyn FnMut(SdsProgress) + Send + 'static>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SenderState {
+  /// The header is sent first
+  Idle,
+  /// Waiting for the receiver to acknowledge the last message, or paused by it until its next message
+  Waiting {
+    deadline: Option<TimestampNanos>,
+  },
+  /// Send the next packet right away
+  Ready,
+  Done,
+  Cancelled,
+}
+
+/// The last message sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Sent {
+  Header,
+  Packet,
+}
+
+/// Sends a sample, following the replies of the receiver.
+pub struct SdsSender {
+  header: DumpHeader,
+  data: Vec<u8>,
+  next_packet: usize,
+  state: SenderState,
+  sent: Option<Sent>,
+  progress: Option<SdsProgressHandler>,
+}
+
+impl SdsSender {
+  pub fn new(sample: &Sample) -> Self {
+    let mut header = sample.header;
+    header.length = sample.words.len() as u32;
+    Self {
+      header,
+      data: sample.encode(),
+      next_packet: 0,
+      state: SenderState::Idle,
+      sent: None,
+      progress: None,
+    }
+  }
+
+  #[must_use]
+  pub fn with_progress<F>(mut self, f: F) -> Self
+  where
+    F: FnMut(SdsProgress) + Send + 'static,
+  {
+    self.progress = Some(Box::new(f));
+    self
+  }
+
+  pub fn is_done(&self) -> bool {
+    self.state == SenderState::Done
+  }
+
+  pub fn is_cancelled(&self) -> bool {
+    self.state == SenderState::Cancelled
+  }
+
+  /// Returns the next message to send at `now`, if it is time to send one.
+  ///
+  /// It should be called periodically, at least once every `PACKET_TIMEOUT`,
+  /// so the dump continues when the receiver doesn't reply.
+  pub fn poll(&mut self, now: TimestampNanos) -> Option<SdsMessage> {
+    match self.state {
+      SenderState::Idle => {
+        self.sent = Some(Sent::Header);
+        self.state = SenderState::Waiting {
+          deadline: Some(now + HEADER_TIMEOUT),
+        };
+        return Some(SdsMessage::DumpHeader(self.header));
+      }
+      // Without handshake, the last message is assumed received
+      SenderState::Waiting {
+        deadline: Some(deadline),
+      } if now >= deadline => self.advance(),
+      _ => {}
+    }
+
+    if self.state != SenderState::Ready {
+      return None;
+    }
+    let start = self.next_packet * PACKET_DATA_LEN;
+    let end = (start + PACKET_DATA_LEN).min(self.data.len());
+    let packet = DataPacket::new(
+      self.header.channel,
+      (self.next_packet % PACKET_NUMBERS) as u8,
+      &self.data[start..end],
+    );
+    self.sent = Some(Sent::Packet);
+    self.state = SenderState::Waiting {
+      deadline: Some(now + PACKET_TIMEOUT),
+    };
+    Some(SdsMessage::DataPacket(Box::new(packet)))
+  }
+
+  /// Handles a reply from the receiver.
+  pub fn process(&mut self, message: &SdsMessage) {
+    let waiting = matches!(self.state, SenderState::Waiting { .. });
+    if message.channel() != self.header.channel || !waiting {
+      return;
+    }
+    match message {
+      SdsMessage::Ack { .. } => self.advance(),
+      SdsMessage::Nak { packet, .. } => match self.sent {
+        Some(Sent::Header) => self.state = SenderState::Idle,
+        Some(Sent::Packet) if (self.next_packet % PACKET_NUMBERS) as u8 == *packet => {
+          self.state = SenderState::Ready
+        }
+        _ => {}
+      },
+      SdsMessage::Wait { .. } => self.state = SenderState::Waiting { deadline: None },
+      SdsMessage::Cancel { .. } => self.state = SenderState::Cancelled,
+      _ => {}
+    }
+  }
+
+  /// Moves on after the last message sent was received.
+  fn advance(&mut self) {
+    let total = self.header.packets();
+    if self.sent.take() == Some(Sent::Packet) {
+      self.next_packet += 1;
+      if let Some(progress) = self.progress.as_mut() {
+        progress(SdsProgress {
+          packets: self.next_packet,
+          total,
+        });
+      }
+    }
+    self.state = if self.next_packet >= total {
+      SenderState::Done
+    } else {
+      SenderState::Ready
+    };
+  }
+}
+
+/// Receives a sample, replying to the sender.
+pub struct SdsReceiver {
+  channel: u8,
+  header: Option<DumpHeader>,
+  data: Vec<u8>,
+  next_packet: usize,
+  sample: Option<Sample>,
+  progress: Option<SdsProgressHandler>,
+}
+
+impl SdsReceiver {
+  /// Receives the dumps sent to `channel`, the device ID of the sampler.
+  pub fn new(channel: u8) -> Self {
+    Self {
+      channel,
+      header: None,
+      data: Vec::new(),
+      next_packet: 0,
+      sample: None,
+      progress: None,
+    }
+  }
+
+  #[must_use]
+  pub fn with_progress<F>(mut self, f: F) -> Self
+  where
+    F: FnMut(SdsProgress) + Send + 'static,
+  {
+    self.progress = Some(Box::new(f));
+    self
+  }
+
+  /// The message asking the sampler to dump a sample.
+  pub fn request(&self, sample: u16) -> SdsMessage {
+    SdsMessage::DumpRequest {
+      channel: self.channel,
+      sample,
+    }
+  }
+
+  pub fn is_receiving(&self) -> bool {
+    self.header.is_some()
+  }
+
+  /// Takes the last sample received completely.
+  pub fn take_sample(&mut self) -> Option<Sample> {
+    self.sample.take()
+  }
+
+  /// Handles a message from the sender, returning the reply to send back.
+  pub fn process(&mut self, message: &SdsMessage) -> Option<SdsMessage> {
+    if message.channel() != self.channel {
+      return None;
+    }
+    let channel = self.channel;
+    match message {
+      SdsMessage::DumpHeader(header) => {
+        self.data.clear();
+        self.data.reserve(header.packets() * PACKET_DATA_LEN);
+        self.next_packet = 0;
+        self.header = Some(*header);
+        self.complete_if_done();
+        Some(SdsMessage::Ack { channel, packet: 0 })
+      }
+      SdsMessage::DataPacket(packet) => {
+        let header = self.header?;
+        if !packet.is_valid() {
+          return Some(SdsMessage::Nak {
+            channel,
+            packet: packet.number,
+          });
+        }
+        // Packets sent again after their acknowledgement was lost are only acknowledged
+        if packet.number == (self.next_packet % PACKET_NUMBERS) as u8 {
+          self.data.extend_from_slice(&packet.data);
+          self.next_packet += 1;
+          if let Some(progress) = self.progress.as_mut() {
+            progress(SdsProgress {
+              packets: self.next_packet,
+              total: header.packets(),
+            });
+          }
+          self.complete_if_done();
+        }
+        Some(SdsMessage::Ack {
+          channel,
+          packet: packet.number,
+        })
+      }
+      SdsMessage::Cancel { .. } => {
+        self.reset();
+        None
+      }
+      _ => None,
+    }
+  }
+
+  /// Stops receiving, returning the message to tell the sender.
+  pub fn cancel(&mut self) -> Option<SdsMessage> {
+    let packet = (self.next_packet % PACKET_NUMBERS) as u8;
+    self.header.take().map(|_| {
+      self.reset();
+      SdsMessage::Cancel {
+        channel: self.channel,
+        packet,
+      }
+    })
+  }
+
+  pub fn reset(&mut self) {
+    self.header = None;
+    self.data.clear();
+    self.next_packet = 0;
+  }
+
+  fn complete_if_done(&mut self) {
+    if let Some(header) = self.header {
+      if self.next_packet >= header.packets() {
+        self.sample = Some(Sample::decode(header, &self.data));
+        self.reset();
+      }
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::sync::{Arc, Mutex};
+
+  fn sample(bits: u8, length: usize) -> Sample {
+    let header = DumpHeader {
+      channel: 3,
+      sample: 200,
+      bits,
+      period_nanos: 22_675,
+      length: length as u32,
+      loop_start: 10,
+      loop_end: 20,
+      loop_type: LoopType::Forward,
+    };
+    let max = 1i32 << (bits - 1);
+    let words = (0..length as i32)
+      .map(|index| (index * 997) % (2 * max) - max)
+      .collect();
+    Sample { header, words }
+  }
+
+  #[test]
+  fn messages_round_trip() {
+    let header = sample(16, 100).header;
+    let messages = [
+      SdsMessage::DumpHeader(header),
+      SdsMessage::DataPacket(Box::new(DataPacket::new(3, 5, &[1, 2, 3]))),
+      SdsMessage::DumpRequest {
+        channel: 3,
+        sample: 0x1234,
+      },
+      SdsMessage::Nak {
+        channel: 3,
+        packet: 127,
+      },
+    ];
+    for message in messages {
+      assert_eq!(SdsMessage::parse(&message.to_sysex()), Some(message));
+    }
+
+    let mut data = SdsMessage::DataPacket(Box::new(DataPacket::new(3, 5, &[1, 2, 3]))).to_sysex();
+    data[10] = 0x55;
+    match SdsMessage::parse(&data) {
+      Some(SdsMessage::DataPacket(packet)) => assert!(!packet.is_valid()),
+      message => panic!("{:?}", message),
+    }
+  }
+
+  #[test]
+  fn words_encoding() {
+    for (bits, length) in [(8, 130), (12, 61), (16, 100), (24, 45)] {
+      let sample = sample(bits, length);
+      let data = sample.encode();
+      assert_eq!(data.len(), length * sample.header.bytes_per_word());
+      assert_eq!(Sample::decode(sample.header, &data), sample);
+    }
+
+    // Left justified in unsigned form
+    let mut sample = sample(8, 1);
+    sample.words[0] = 0;
+    assert_eq!(sample.encode(), [0x40, 0x00]);
+  }
+
+  #[test]
+  fn dump_with_handshake() {
+    let sample = sample(16, 300);
+    let progress = Arc::new(Mutex::new(Vec::new()));
+    let sent = progress.clone();
+    let mut sender =
+      SdsSender::new(&sample).with_progress(move |progress| sent.lock().unwrap().push(progress));
+    let mut receiver = SdsReceiver::new(3);
+
+    let mut now = 0;
+    let mut corrupted = false;
+    while !sender.is_done() {
+      let message = match sender.poll(now) {
+        Some(SdsMessage::DataPacket(mut packet)) if packet.number == 2 && !corrupted => {
+          corrupted = true;
+          packet.data[0] ^= 1;
+          SdsMessage::DataPacket(packet)
+        }
+        Some(message) => message,
+        None => panic!("the sender is waiting"),
+      };
+      let received = SdsMessage::parse(&message.to_sysex()).unwrap();
+      let reply = receiver.process(&received).unwrap();
+      sender.process(&reply);
+      now += 1_000_000;
+    }
+
+    assert_eq!(receiver.take_sample(), Some(sample));
+    let progress = progress.lock().unwrap();
+    assert_eq!(progress.len(), 8);
+    assert_eq!(
+      progress[7],
+      SdsProgress {
+        packets: 8,
+        total: 8
+      }
+    );
+  }
+
+  #[test]
+  fn dump_without_handshake() {
+    let sample = sample(8, 240);
+    let mut sender = SdsSender::new(&sample);
+    let mut receiver = SdsReceiver::new(3);
+
+    let mut now = 0;
+    let mut messages = 0;
+    while !sender.is_done() {
+      if let Some(message) = sender.poll(now) {
+        receiver.process(&message);
+        messages += 1;
+      }
+      now += PACKET_TIMEOUT;
+    }
+    assert_eq!(messages, 5);
+    assert_eq!(now, HEADER_TIMEOUT + 5 * PACKET_TIMEOUT);
+    assert_eq!(receiver.take_sample(), Some(sample));
+  }
+
+  #[test]
+  fn wait_and_cancel() {
+    let mut sender = SdsSender::new(&sample(16, 300));
+    sender.poll(0);
+    sender.process(&SdsMessage::Wait {
+      channel: 3,
+      packet: 0,
+    });
+    assert_eq!(sender.poll(HEADER_TIMEOUT * 10), None);
+    sender.process(&SdsMessage::Ack {
+      channel: 3,
+      packet: 0,
+    });
+    assert!(matches!(
+      sender.poll(HEADER_TIMEOUT * 10),
+      Some(SdsMessage::DataPacket(_))
+    ));
+    sender.process(&SdsMessage::Cancel {
+      channel: 3,
+      packet: 0,
+    });
+    assert!(sender.is_cancelled());
+    assert_eq!(sender.poll(HEADER_TIMEOUT * 20), None);
+  }
+}
@@ -1,8 +1,15 @@
 use regex::Regex;
 
 use crate::endpoints::SourceId;
-use crate::filter::Filter;
+use crate::filter::{Filter, FilterExpr};
+use crate::transform::Transform;
 
+/// Regexes are serialized as their pattern, and compiled again when deserialized.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(
+  feature = "serde",
+  serde(try_from = "SerializedSourceMatch", into = "SerializedSourceMatch")
+)]
 #[derive(Debug, Clone)]
 pub enum SourceMatch {
   Id(SourceId),
@@ -15,11 +22,44 @@ impl SourceMatch {
     Regex::new(regex).map(Self::Regex)
   }
 
-  pub(crate) fn matches(&self, source_id: SourceId, source_name: &str) -> bool {
+  /// Names and regexes match either the name or the display name of the source.
+  pub(crate) fn matches(&self, source_id: SourceId, source_name: &str, display_name: &str) -> bool {
     match self {
       Self::Id(id) => source_id == *id,
-      Self::Name(name) => source_name == name.as_str(),
-      Self::Regex(regex) => regex.is_match(source_name),
+      Self::Name(name) => source_name == name.as_str() || display_name == name.as_str(),
+      Self::Regex(regex) => regex.is_match(source_name) || regex.is_match(display_name),
+    }
+  }
+}
+
+#[cfg(feature = "serde")]
+#[derive(serde::Serialize, serde::Deserialize)]
+enum SerializedSourceMatch {
+  Id(SourceId),
+  Name(String),
+  Regex(String),
+}
+
+#[cfg(feature = "serde")]
+impl TryFrom<SerializedSourceMatch> for SourceMatch {
+  type Error = regex::Error;
+
+  fn try_from(source_match: SerializedSourceMatch) -> Result<Self, Self::Error> {
+    match source_match {
+      SerializedSourceMatch::Id(id) => Ok(Self::Id(id)),
+      SerializedSourceMatch::Name(name) => Ok(Self::Name(name)),
+      SerializedSourceMatch::Regex(regex) => Self::regex(&regex),
+    }
+  }
+}
+
+#[cfg(feature = "serde")]
+impl From<SourceMatch> for SerializedSourceMatch {
+  fn from(source_match: SourceMatch) -> Self {
+    match source_match {
+      SourceMatch::Id(id) => Self::Id(id),
+      SourceMatch::Name(name) => Self::Name(name),
+      SourceMatch::Regex(regex) => Self::Regex(regex.as_str().to_string()),
     }
   }
 }
@@ -36,41 +76,113 @@ impl From<&str> for SourceMatch {
   }
 }
 
+/// The sources to connect to, with the filter and the transform for the events received from every one of them
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 #[derive(Debug, Clone, Default)]
-pub struct SourceMatches(Vec<(SourceMatch, Filter)>);
+pub struct SourceMatches(Vec<(SourceMatch, FilterExpr, Transform)>);
 
 impl SourceMatches {
   pub fn new(matches: Vec<(SourceMatch, Filter)>) -> Self {
-    Self(matches)
+    Self(
+      matches
+        .into_iter()
+        .map(|(source_match, filter)| (source_match, filter.into(), Transform::new()))
+        .collect(),
+    )
   }
 
   #[must_use]
-  pub fn with_source<M>(mut self, source_match: M, filter: Filter) -> Self
+  pub fn with_source<M, F>(mut self, source_match: M, filter: F) -> Self
   where
     M: Into<SourceMatch>,
+    F: Into<FilterExpr>,
   {
     self.add_source(source_match.into(), filter);
     self
   }
 
-  pub fn add_source<M>(&mut self, source_match: M, filter: Filter)
+  pub fn add_source<M, F>(&mut self, source_match: M, filter: F)
   where
     M: Into<SourceMatch>,
+    F: Into<FilterExpr>,
+  {
+    self.add_source_with_transform(source_match, filter, Transform::new());
+  }
+
+  /// Adds a source whose events are transformed after decoding them (such as to remap their channels).
+  pub fn add_source_with_transform<M, F>(
+    &mut self,
+    source_match: M,
+    filter: F,
+    transform: Transform,
+  ) where
+    M: Into<SourceMatch>,
+    F: Into<FilterExpr>,
+  {
+    self.0.push((source_match.into(), filter.into(), transform));
+  }
+
+  /// Removes the source match at `index`, moving the ones after it one position back.
+  pub fn remove(&mut self, index: usize) -> Option<(SourceMatch, FilterExpr, Transform)> {
+    (index < self.0.len()).then(|| self.0.remove(index))
+  }
+
+  /// Replaces the filter of the source match at `index`, returning whether there is one.
+  pub fn set_filter<F>(&mut self, index: usize, filter: F) -> bool
+  where
+    F: Into<FilterExpr>,
   {
-    self.0.push((source_match.into(), filter));
+    match self.0.get_mut(index) {
+      Some((_, current, _)) => {
+        *current = filter.into();
+        true
+      }
+      None => false,
+    }
+  }
+
+  pub fn len(&self) -> usize {
+    self.0.len()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.0.is_empty()
+  }
+
+  pub(crate) fn iter(&self) -> impl Iterator<Item = &(SourceMatch, FilterExpr, Transform)> {
+    self.0.iter()
+  }
+
+  pub(crate) fn iter_mut(
+    &mut self,
+  ) -> impl Iterator<Item = &mut (SourceMatch, FilterExpr, Transform)> {
+    self.0.iter_mut()
   }
 
-  pub fn match_filter(&self, id: SourceId, name: &str) -> Option<Filter> {
+  pub fn match_filter(&self, id: SourceId, name: &str, display_name: &str) -> Option<FilterExpr> {
     self
-      .0
-      .iter()
-      .find_map(|(source_match, filter)| source_match.matches(id, name).then(|| *filter))
+      .match_source(id, name, display_name)
+      .map(|(filter, _)| filter)
+  }
+
+  /// The filter and the transform of the first match for a source.
+  pub fn match_source(
+    &self,
+    id: SourceId,
+    name: &str,
+    display_name: &str,
+  ) -> Option<(FilterExpr, Transform)> {
+    self.0.iter().find_map(|(source_match, filter, transform)| {
+      source_match
+        .matches(id, name, display_name)
+        .then(|| (filter.clone(), *transform))
+    })
   }
 
-  pub fn match_index(&self, id: SourceId, name: &str) -> Option<usize> {
+  pub fn match_index(&self, id: SourceId, name: &str, display_name: &str) -> Option<usize> {
     self
       .0
       .iter()
-      .position(|(source_match, _)| source_match.matches(id, name))
+      .position(|(source_match, _, _)| source_match.matches(id, name, display_name))
   }
 }
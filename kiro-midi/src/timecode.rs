@@ -0,0 +1,459 @@
+//! MIDI Time Code (MTC).
+//!
+//! While running, the sender transmits the SMPTE time in eight quarter frame messages, each one
+//! carrying a nibble of it, so a complete time takes two frames to arrive. When locating, it sends
+//! the whole time at once in a full frame SysEx message.
+//!
+//! [`TimecodeFollower`] puts the time back together from both, keeps it running between complete
+//! sequences, and measures how the timecode drifts from the local clock while locked.
+
+use crate::event::TimestampNanos;
+use crate::protocol::messages::sysex7::SysExAssembler;
+use crate::protocol::messages::system::System;
+use crate::protocol::messages::{Message, MessageType};
+
+/// Universal Real Time SysEx, Sub-ID#1 MIDI Time Code, Sub-ID#2 Full Message
+const FULL_FRAME_HEADER: [u8; 4] = [0x7f, 0x7f, 0x01, 0x01];
+const FULL_FRAME_LEN: usize = 8;
+
+/// The longest a quarter frame can take (at 24 fps) with some margin,
+/// after which the sender is considered stopped
+const QUARTER_FRAME_TIMEOUT: TimestampNanos = 25_000_000;
+
+const LAST_PIECE: u8 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FrameRate {
+  Fps24,
+  Fps25,
+  /// 29.97 fps with the drop frame numbering
+  Fps30Drop,
+  Fps30,
+}
+
+impl FrameRate {
+  /// From the rate bits of the hours in the quarter frames and the full frame messages.
+  pub fn from_code(code: u8) -> Self {
+    match code & 0x03 {
+      0 => Self::Fps24,
+      1 => Self::Fps25,
+      2 => Self::Fps30Drop,
+      _ => Self::Fps30,
+    }
+  }
+
+  pub fn code(self) -> u8 {
+    match self {
+      Self::Fps24 => 0,
+      Self::Fps25 => 1,
+      Self::Fps30Drop => 2,
+      Self::Fps30 => 3,
+    }
+  }
+
+  /// Number of frames per second in the time numbering.
+  pub fn frames_per_second(self) -> u8 {
+    match self {
+      Self::Fps24 => 24,
+      Self::Fps25 => 25,
+      Self::Fps30Drop | Self::Fps30 => 30,
+    }
+  }
+
+  /// Duration of a frame.
+  pub fn frame_nanos(self) -> u64 {
+    match self {
+      Self::Fps30Drop => 1_001_000_000 / 30,
+      _ => 1_000_000_000 / self.frames_per_second() as u64,
+    }
+  }
+}
+
+/// A SMPTE time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Timecode {
+  pub hours: u8,
+  pub minutes: u8,
+  pub seconds: u8,
+  pub frames: u8,
+  pub rate: FrameRate,
+}
+
+impl Timecode {
+  /// The time after a number of frames from 00:00:00:00, wrapping around after 24 hours.
+  pub fn from_frames(frames: u64, rate: FrameRate) -> Self {
+    // Drop frame skips the frames 0 and 1 of every minute, except in every tenth minute
+    let frames = match rate {
+      FrameRate::Fps30Drop => {
+        let tens = frames / 17982;
+        let rest = frames % 17982;
+        let dropped = if rest < 2 { 0 } else { 2 * ((rest - 2) / 1798) };
+        frames + 18 * tens + dropped
+      }
+      _ => frames,
+    };
+    let fps = rate.frames_per_second() as u64;
+    Self {
+      hours: ((frames / (fps * 3600)) % 24) as u8,
+      minutes: ((frames / (fps * 60)) % 60) as u8,
+      seconds: ((frames / fps) % 60) as u8,
+      frames: (frames % fps) as u8,
+      rate,
+    }
+  }
+
+  /// Number of frames from 00:00:00:00.
+  pub fn to_frames(&self) -> u64 {
+    let fps = self.rate.frames_per_second() as u64;
+    let minutes = self.hours as u64 * 60 + self.minutes as u64;
+    let frames = (minutes * 60 + self.seconds as u64) * fps + self.frames as u64;
+    match self.rate {
+      FrameRate::Fps30Drop => frames - 2 * (minutes - minutes / 10),
+      _ => frames,
+    }
+  }
+
+  /// The time elapsed from 00:00:00:00.
+  pub fn to_nanos(&self) -> u64 {
+    self.to_frames() * self.rate.frame_nanos()
+  }
+
+  /// The time of the next frame.
+  pub fn next_frame(&self) -> Self {
+    Self::from_frames(self.to_frames() + 1, self.rate)
+  }
+
+  fn from_pieces(pieces: &[u8; 8]) -> Self {
+    Self {
+      hours: pieces[6] | (pieces[7] & 0x01) << 4,
+      minutes: pieces[4] | (pieces[5] & 0x03) << 4,
+      seconds: pieces[2] | (pieces[3] & 0x03) << 4,
+      frames: pieces[0] | (pieces[1] & 0x01) << 4,
+      rate: FrameRate::from_code(pieces[7] >> 1),
+    }
+  }
+
+  /// The time of the data of a full frame SysEx message, without the `F0` and `F7` bytes.
+  fn from_full_frame(data: &[u8]) -> Option<Self> {
+    let is_full_frame = data.len() == FULL_FRAME_LEN
+      && data[0] == FULL_FRAME_HEADER[0]
+      && data[2..4] == FULL_FRAME_HEADER[2..4];
+    is_full_frame.then(|| Self {
+      hours: data[4] & 0x1f,
+      minutes: data[5] & 0x3f,
+      seconds: data[6] & 0x3f,
+      frames: data[7] & 0x1f,
+      rate: FrameRate::from_code(data[4] >> 5),
+    })
+  }
+}
+
+/// Where the local clock was when the lock was acquired, and how many quarter frames arrived since
+#[derive(Debug, Clone, Copy)]
+struct Lock {
+  timestamp: TimestampNanos,
+  quarter_frames: u64,
+  drift: i64,
+}
+
+/// Follows the MIDI Time Code of a source.
+///
+/// The time is assembled from the quarter frames sent while running forward, and then it advances
+/// a frame every four quarter frames. Once a complete sequence of quarter frames matches the time
+/// advanced so far, the follower is locked, until a quarter frame is missed or arrives out of order.
+/// Sequences running backwards are not followed, the senders use full frame messages to locate.
+pub struct TimecodeFollower {
+  pieces: [u8; 8],
+  /// The piece expected next, or `None` after an interruption, waiting for the first piece
+  next_piece: Option<u8>,
+  /// Whether the quarter frames kept arriving in order since the time was last assembled
+  running: bool,
+  timecode: Option<Timecode>,
+  lock: Option<Lock>,
+  last_timestamp: Option<TimestampNanos>,
+  sysex: SysExAssembler,
+}
+
+impl TimecodeFollower {
+  pub fn new() -> Self {
+    Self {
+      pieces: [0; 8],
+      next_piece: None,
+      running: false,
+      timecode: None,
+      lock: None,
+      last_timestamp: None,
+      sysex: SysExAssembler::new(FULL_FRAME_LEN),
+    }
+  }
+
+  /// The last time received, or advanced while running.
+  pub fn timecode(&self) -> Option<Timecode> {
+    self.timecode
+  }
+
+  pub fn is_locked(&self) -> bool {
+    self.lock.is_some()
+  }
+
+  /// How far ahead of the local clock the timecode went since it was locked, in nanoseconds.
+  pub fn drift(&self) -> Option<i64> {
+    self.lock.map(|lock| lock.drift)
+  }
+
+  /// Handles a message received at `timestamp`, returning the time when it changes.
+  pub fn process(&mut self, timestamp: TimestampNanos, message: &Message) -> Option<Timecode> {
+    match message.mtype {
+      MessageType::System(System::TimeCode(data)) => self.quarter_frame(timestamp, data),
+      MessageType::SysEx7(packet) => {
+        let timecode = self
+          .sysex
+          .push(&packet)
+          .and_then(Timecode::from_full_frame)?;
+        self.locate(timecode);
+        Some(timecode)
+      }
+      _ => None,
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.next_piece = None;
+    self.running = false;
+    self.timecode = None;
+    self.lock = None;
+    self.last_timestamp = None;
+    self.sysex.reset();
+  }
+
+  fn locate(&mut self, timecode: Timecode) {
+    self.timecode = Some(timecode);
+    self.next_piece = None;
+    self.running = false;
+    self.lock = None;
+  }
+
+  fn quarter_frame(&mut self, timestamp: TimestampNanos, data: u8) -> Option<Timecode> {
+    let piece = (data >> 4) & 0x07;
+    let timed_out = self.last_timestamp.map_or(false, |last| {
+      timestamp.saturating_sub(last) > QUARTER_FRAME_TIMEOUT
+    });
+    self.last_timestamp = Some(timestamp);
+
+    if timed_out || self.next_piece.map_or(piece != 0, |next| next != piece) {
+      self.running = false;
+      self.lock = None;
+      if piece != 0 {
+        self.next_piece = None;
+        return None;
+      }
+    }
+
+    self.pieces[piece as usize] = data & 0x0f;
+    self.next_piece = Some((piece + 1) & LAST_PIECE);
+
+    if let Some(lock) = self.lock.as_mut() {
+      lock.quarter_frames += 1;
+      let rate = self
+        .timecode
+        .map_or(FrameRate::Fps30, |timecode| timecode.rate);
+      let expected = (lock.quarter_frames * rate.frame_nanos() / 4) as i64;
+      let elapsed = timestamp.saturating_sub(lock.timestamp) as i64;
+      lock.drift = expected - elapsed;
+    }
+
+    // The pieces 0 and 4 start a new frame
+    let mut changed = None;
+    if self.running && (piece == 0 || piece == 4) {
+      changed = self.timecode.as_mut().map(|timecode| {
+        *timecode = timecode.next_frame();
+        *timecode
+      });
+    }
+
+    if piece == LAST_PIECE {
+      // The time assembled is the one of the frame started by the piece 0,
+      // and the piece 4 already started the following one
+      let assembled = Timecode::from_pieces(&self.pieces).next_frame();
+      if self.running && self.timecode == Some(assembled) {
+        self.lock.get_or_insert(Lock {
+          timestamp,
+          quarter_frames: 0,
+          drift: 0,
+        });
+      } else {
+        self.lock = None;
+        self.timecode = Some(assembled);
+        changed = Some(assembled);
+      }
+      self.running = true;
+    }
+
+    changed
+  }
+}
+
+impl Default for TimecodeFollower {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::protocol::messages::sysex7::{SysEx7, SysExStatus};
+
+  const QUARTER_FRAME_25: u64 = 10_000_000;
+
+  fn quarter_frames(timecode: &Timecode) -> [u8; 8] {
+    let hours = timecode.hours | timecode.rate.code() << 5;
+    let values = [timecode.frames, timecode.seconds, timecode.minutes, hours];
+    let mut data = [0; 8];
+    for (piece, byte) in data.iter_mut().enumerate() {
+      let value = values[piece / 2];
+      let nibble = if piece % 2 == 0 { value } else { value >> 4 };
+      *byte = (piece as u8) << 4 | nibble & 0x0f;
+    }
+    data
+  }
+
+  fn message(mtype: MessageType) -> Message {
+    Message { group: 0, mtype }
+  }
+
+  /// Sends the quarter frames for two frames from `timecode`, returning the updates.
+  fn run(
+    follower: &mut TimecodeFollower,
+    timecode: Timecode,
+    timestamp: &mut TimestampNanos,
+    quarter_frame: u64,
+  ) -> Vec<Timecode> {
+    let mut updates = Vec::new();
+    for data in quarter_frames(&timecode) {
+      let message = message(MessageType::System(System::TimeCode(data)));
+      updates.extend(follower.process(*timestamp, &message));
+      *timestamp += quarter_frame;
+    }
+    updates
+  }
+
+  fn timecode(hours: u8, minutes: u8, seconds: u8, frames: u8, rate: FrameRate) -> Timecode {
+    Timecode {
+      hours,
+      minutes,
+      seconds,
+      frames,
+      rate,
+    }
+  }
+
+  #[test]
+  fn drop_frame_numbering() {
+    let rate = FrameRate::Fps30Drop;
+    assert_eq!(
+      Timecode::from_frames(1800, rate),
+      timecode(0, 1, 0, 2, rate)
+    );
+    assert_eq!(
+      Timecode::from_frames(17982, rate),
+      timecode(0, 10, 0, 0, rate)
+    );
+    for frames in [0, 1799, 1800, 17981, 17982, 107_892, 2_589_407] {
+      assert_eq!(Timecode::from_frames(frames, rate).to_frames(), frames);
+    }
+    assert_eq!(
+      timecode(0, 0, 59, 29, rate).next_frame(),
+      timecode(0, 1, 0, 2, rate)
+    );
+  }
+
+  #[test]
+  fn assemble_and_lock() {
+    let mut follower = TimecodeFollower::new();
+    let mut timestamp = 1_000_000;
+    let start = timecode(1, 2, 3, 4, FrameRate::Fps25);
+
+    let updates = run(&mut follower, start, &mut timestamp, QUARTER_FRAME_25);
+    assert_eq!(updates, vec![timecode(1, 2, 3, 5, FrameRate::Fps25)]);
+    assert!(!follower.is_locked());
+
+    let updates = run(
+      &mut follower,
+      timecode(1, 2, 3, 6, FrameRate::Fps25),
+      &mut timestamp,
+      QUARTER_FRAME_25,
+    );
+    assert_eq!(
+      updates,
+      vec![
+        timecode(1, 2, 3, 6, FrameRate::Fps25),
+        timecode(1, 2, 3, 7, FrameRate::Fps25),
+      ]
+    );
+    assert!(follower.is_locked());
+    assert_eq!(follower.drift(), Some(0));
+  }
+
+  #[test]
+  fn drift_while_locked() {
+    let mut follower = TimecodeFollower::new();
+    let mut timestamp = 0;
+    let mut current = timecode(0, 0, 0, 0, FrameRate::Fps25);
+    for _ in 0..4 {
+      // The sender clock runs 1% slow
+      run(
+        &mut follower,
+        current,
+        &mut timestamp,
+        QUARTER_FRAME_25 * 101 / 100,
+      );
+      current = current.next_frame().next_frame();
+    }
+
+    assert!(follower.is_locked());
+    // 16 quarter frames arrived since the lock, late by 0.1 ms each
+    assert_eq!(follower.drift(), Some(-1_600_000));
+  }
+
+  #[test]
+  fn missed_quarter_frame_unlocks() {
+    let mut follower = TimecodeFollower::new();
+    let mut timestamp = 0;
+    let start = timecode(0, 0, 10, 0, FrameRate::Fps24);
+    run(&mut follower, start, &mut timestamp, QUARTER_FRAME_25);
+    run(
+      &mut follower,
+      start.next_frame().next_frame(),
+      &mut timestamp,
+      QUARTER_FRAME_25,
+    );
+    assert!(follower.is_locked());
+
+    let data = 0x30;
+    let message = message(MessageType::System(System::TimeCode(data)));
+    assert_eq!(follower.process(timestamp, &message), None);
+    assert!(!follower.is_locked());
+  }
+
+  #[test]
+  fn full_frame_locates() {
+    let mut follower = TimecodeFollower::new();
+    let data = [0x7f, 0x7f, 0x01, 0x01, 0x40 | 10, 20, 30, 15];
+    let start = message(MessageType::SysEx7(SysEx7::new(
+      SysExStatus::Start,
+      &data[..6],
+    )));
+    let end = message(MessageType::SysEx7(SysEx7::new(
+      SysExStatus::End,
+      &data[6..],
+    )));
+
+    assert_eq!(follower.process(0, &start), None);
+    assert_eq!(
+      follower.process(0, &end),
+      Some(timecode(10, 20, 30, 15, FrameRate::Fps30Drop))
+    );
+    assert!(!follower.is_locked());
+  }
+}
@@ -0,0 +1,1589 @@
+use crate::mpe::{pitch_bend_range_data, RPN_PITCH_BEND_RANGE};
+use crate::protocol::messages::channel_voice::{AttributeType, ChanelVoiceMessage, ChannelVoice};
+use crate::protocol::messages::channel_voice1::{ChannelVoice1, ChannelVoice1Message};
+use crate::protocol::messages::system::System;
+use crate::protocol::messages::{Message, MessageType};
+use crate::protocol::parameters::scale_up;
+
+/// Changes applied to the messages received from a source, or routed from an input to an output.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+  groups: [u8; 16],
+  channels: [u8; 16],
+  notes: (u8, u8),
+  transpose: i8,
+  scale: Option<(Scale, Quantize)>,
+  velocity_curve: VelocityCurve,
+  /// Gain and offset (in MIDI 1.0 steps) of the velocities, after the curve
+  velocity_scaling: (f32, i8),
+  pressure_controller: Option<PressureController>,
+  /// Pitch bend ranges received and sent, in semitones
+  pitch_bend_range: Option<(f32, f32)>,
+  /// Program received, with the program and the bank sent instead
+  programs: [Option<(u8, u8, Option<u16>)>; MAX_PROGRAM_MAPS],
+  zones: [Option<Zone>; MAX_ZONES],
+  layers: [Option<Layer>; MAX_LAYERS],
+  clock: bool,
+}
+
+/// Maximum number of split zones of a transform
+pub const MAX_ZONES: usize = 4;
+
+/// Range of notes sent to their own group and channel, both starting from 0
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Zone {
+  low: u8,
+  high: u8,
+  group: u8,
+  channel: u8,
+}
+
+/// Maximum number of layers of a transform
+pub const MAX_LAYERS: usize = 3;
+
+/// Messages sent by a transform for every message received, with its layers
+const LAYERED: usize = 1 + MAX_LAYERS;
+
+/// Copy of the channel messages sent to another channel (starting from 0) of the same group,
+/// with its own transposition and velocity scale
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Layer {
+  channel: u8,
+  transpose: i8,
+  velocity_scale: f32,
+}
+
+/// Maximum number of programs remapped by a transform
+pub const MAX_PROGRAM_MAPS: usize = 16;
+
+impl Transform {
+  pub fn new() -> Self {
+    let mut identity = [0; 16];
+    for (index, target) in identity.iter_mut().enumerate() {
+      *target = index as u8;
+    }
+    Self {
+      groups: identity,
+      channels: identity,
+      notes: (0, 127),
+      transpose: 0,
+      scale: None,
+      velocity_curve: VelocityCurve::Linear,
+      velocity_scaling: (1.0, 0),
+      pressure_controller: None,
+      pitch_bend_range: None,
+      programs: [None; MAX_PROGRAM_MAPS],
+      zones: [None; MAX_ZONES],
+      layers: [None; MAX_LAYERS],
+      clock: true,
+    }
+  }
+
+  /// Sends the messages from the group `from` to the group `to`, both starting from 1,
+  /// such as to put several MIDI 1.0 devices in their own groups of a single MIDI 2.0 stream.
+  #[must_use]
+  pub fn with_group(mut self, from: u8, to: u8) -> Self {
+    if (1..=16).contains(&from) && (1..=16).contains(&to) {
+      self.groups[(from - 1) as usize] = to - 1;
+    }
+    self
+  }
+
+  /// Sends the messages from all the groups to the group `to`, starting from 1.
+  #[must_use]
+  pub fn with_all_groups(mut self, to: u8) -> Self {
+    if (1..=16).contains(&to) {
+      self.groups = [to - 1; 16];
+    }
+    self
+  }
+
+  /// Sends the messages from the channel `from` to the channel `to`, both starting from 1.
+  #[must_use]
+  pub fn with_channel(mut self, from: u8, to: u8) -> Self {
+    if (1..=16).contains(&from) && (1..=16).contains(&to) {
+      self.channels[(from - 1) as usize] = to - 1;
+    }
+    self
+  }
+
+  /// Sends the messages from all the channels to the channel `to`, starting from 1.
+  #[must_use]
+  pub fn with_all_channels(mut self, to: u8) -> Self {
+    if (1..=16).contains(&to) {
+      self.channels = [to - 1; 16];
+    }
+    self
+  }
+
+  /// Drops the note messages (including the per-note ones) out of the range, both included.
+  ///
+  /// The range applies to the notes received, before transposing them.
+  #[must_use]
+  pub fn with_note_range(mut self, low: u8, high: u8) -> Self {
+    self.notes = (low, high);
+    self
+  }
+
+  /// Transposes the note messages (including the per-note ones and the pitch attribute of the MIDI 2.0 notes)
+  /// by a number of semitones, clamping the notes to the range from 0 to 127.
+  #[must_use]
+  pub fn with_transpose(mut self, semitones: i8) -> Self {
+    self.transpose = semitones;
+    self
+  }
+
+  /// Snaps the notes out of a scale to the notes in it, after transposing them.
+  #[must_use]
+  pub fn with_scale(mut self, scale: Scale, quantize: Quantize) -> Self {
+    self.scale = Some((scale, quantize));
+    self
+  }
+
+  /// Stops snapping the notes to a scale.
+  #[must_use]
+  pub fn without_scale(mut self) -> Self {
+    self.scale = None;
+    self
+  }
+
+  /// Maps the velocities of the note ons through a curve, to even out the response of heavy or light keyboards.
+  #[must_use]
+  pub fn with_velocity_curve(mut self, curve: VelocityCurve) -> Self {
+    self.velocity_curve = curve;
+    self
+  }
+
+  /// Multiplies the velocities of the note ons by `gain` and adds `offset` to them, after the velocity curve,
+  /// to balance the keyboards of a rig quickly. The offset is in MIDI 1.0 steps, and scaled to the MIDI 2.0 velocities.
+  ///
+  /// The velocities are kept above 0, so the MIDI 1.0 note ons don't turn into note offs.
+  #[must_use]
+  pub fn with_velocity_scaling(mut self, gain: f32, offset: i8) -> Self {
+    self.velocity_scaling = (gain.max(0.0), offset);
+    self
+  }
+
+  /// Turns the pressure (aftertouch) messages into control changes of `controller`, for the synths ignoring them.
+  ///
+  /// The pressure is scaled to the controller values from `low` (no pressure) to `high` (full pressure),
+  /// from 0 to 127, so it is inverted when `low` is above `high`.
+  /// The poly pressure of all the notes goes to the same controller.
+  #[must_use]
+  pub fn with_pressure_to_controller(
+    mut self,
+    pressure: Pressure,
+    controller: u8,
+    low: u8,
+    high: u8,
+  ) -> Self {
+    self.pressure_controller = Some(PressureController {
+      pressure,
+      controller: controller & 0x7f,
+      low: low.min(127),
+      high: high.min(127),
+    });
+    self
+  }
+
+  /// Rescales the pitch bends from a range of `input` semitones (up and down) to a range of `output` semitones,
+  /// so they bend the same interval on a synth set to `output`, such as from ±2 on a keyboard to ±48 on an MPE synth.
+  /// The bends beyond the output range are clamped.
+  ///
+  /// The pitch bend ranges (RPN 0) received as MIDI 2.0 registered controllers (as the inputs assembling the
+  /// parameters do) are sent as `output` instead, and the outputs with the transform send `output` to their
+  /// destinations as they connect them, see `Output::send_pitch_bend_range`.
+  #[must_use]
+  pub fn with_pitch_bend_range(mut self, input: f32, output: f32) -> Self {
+    if input > 0.0 && output > 0.0 {
+      self.pitch_bend_range = Some((input, output));
+    }
+    self
+  }
+
+  /// The pitch bend range of the messages sent, in semitones, when the pitch bends are rescaled.
+  pub fn pitch_bend_range(&self) -> Option<f32> {
+    self.pitch_bend_range.map(|(_, output)| output)
+  }
+
+  /// Splits the keyboard, sending the notes from `low` to `high` (both included) to the `group` and `channel`,
+  /// both starting from 1, such as the lower half to the channel 2 and the upper half to the channel 1.
+  ///
+  /// The first zone containing a note wins, and the notes out of every zone keep the channel and group
+  /// given by the rest of the transform, as do the messages not addressed to a note (such as the pedals).
+  /// Up to `MAX_ZONES` zones can be added, the ones beyond are ignored (failing a debug assertion).
+  /// The zones apply to the notes received, before transposing them, and the note offs follow their
+  /// note ons even after moving the split point.
+  #[must_use]
+  pub fn with_zone(mut self, low: u8, high: u8, group: u8, channel: u8) -> Self {
+    if (1..=16).contains(&group) && (1..=16).contains(&channel) {
+      let slot = self.zones.iter_mut().find(|slot| slot.is_none());
+      debug_assert!(slot.is_some(), "more than {} zones", MAX_ZONES);
+      if let Some(slot) = slot {
+        *slot = Some(Zone {
+          low,
+          high,
+          group: group - 1,
+          channel: channel - 1,
+        });
+      }
+    }
+    self
+  }
+
+  #[must_use]
+  pub fn without_zones(mut self) -> Self {
+    self.zones = [None; MAX_ZONES];
+    self
+  }
+
+  /// Layers another part over the messages sent, copying the channel messages to the `channel`, starting from 1,
+  /// of the same group, such as to play a piano and a string pad from one keyboard.
+  ///
+  /// The notes of the copy are transposed by `transpose` semitones more, and the velocities of its note ons
+  /// multiplied by `velocity_scale`. The program changes are not copied, so every part keeps its own patch.
+  /// Up to `MAX_LAYERS` layers can be added, the ones beyond are ignored (failing a debug assertion),
+  /// and only `apply_layered` sends them.
+  #[must_use]
+  pub fn with_layer(mut self, channel: u8, transpose: i8, velocity_scale: f32) -> Self {
+    if (1..=16).contains(&channel) {
+      let slot = self.layers.iter_mut().find(|slot| slot.is_none());
+      debug_assert!(slot.is_some(), "more than {} layers", MAX_LAYERS);
+      if let Some(slot) = slot {
+        *slot = Some(Layer {
+          channel: channel - 1,
+          transpose,
+          velocity_scale: velocity_scale.max(0.0),
+        });
+      }
+    }
+    self
+  }
+
+  #[must_use]
+  pub fn without_layers(mut self) -> Self {
+    self.layers = [None; MAX_LAYERS];
+    self
+  }
+
+  /// Sends the program changes of the program `from` as the `program` of the `bank` (with 14 bits) instead,
+  /// so the preset buttons of a device can select any patch downstream. Up to `MAX_PROGRAM_MAPS` programs are remapped,
+  /// the ones beyond are ignored (failing a debug assertion), and remapping a program again replaces its target.
+  ///
+  /// The MIDI 1.0 program changes are turned into MIDI 2.0 ones to select the bank, and the MIDI 2.0 ones keep
+  /// the bank they had when `bank` is `None`.
+  #[must_use]
+  pub fn with_program(mut self, from: u8, program: u8, bank: Option<u16>) -> Self {
+    let from = from & 0x7f;
+    let target = (from, program & 0x7f, bank.map(|bank| bank & 0x3fff));
+    let slot = self
+      .programs
+      .iter_mut()
+      .find(|slot| slot.map_or(true, |(mapped, _, _)| mapped == from));
+    debug_assert!(slot.is_some(), "more than {} programs", MAX_PROGRAM_MAPS);
+    if let Some(slot) = slot {
+      *slot = Some(target);
+    }
+    self
+  }
+
+  /// Drops the clock and transport messages (timing clock, start, continue, stop and song position).
+  #[must_use]
+  pub fn without_clock(mut self) -> Self {
+    self.clock = false;
+    self
+  }
+
+  /// Returns the transformed message, or `None` when it has to be dropped, without its layers.
+  pub fn apply(&self, message: Message) -> Option<Message> {
+    let transpose = match note_event(&message) {
+      Some((_, _, note, _)) => self.note_transpose(note as u8)?,
+      None => 0,
+    };
+    self.apply_transposed(message, transpose)
+  }
+
+  /// Returns the transformed message followed by its copies for every layer, if it is not dropped.
+  pub fn apply_layered(&self, message: Message) -> impl Iterator<Item = Message> {
+    IntoIterator::into_iter(self.layered(message)).flatten()
+  }
+
+  /// Like `apply_layered`, but the note offs and the per-note messages of the notes held are transposed and sent
+  /// to the groups and channels as their note on was, so changing the transposition, the scale, the channels,
+  /// the zones or the layers while holding a note does not leave it stuck.
+  pub(crate) fn apply_held(
+    &self,
+    message: Message,
+    held: &mut HeldNotes,
+  ) -> impl Iterator<Item = Message> {
+    let (group, channel, note, event) = match note_event(&message) {
+      Some(note_event) => note_event,
+      None => return IntoIterator::into_iter(self.layered(message)).flatten(),
+    };
+    let held_note = match event {
+      NoteEvent::On => NOT_HELD,
+      NoteEvent::Off => held.remove(group, channel, note),
+      NoteEvent::Other => held.get(group, channel, note),
+    };
+    let transpose = if held_note == NOT_HELD {
+      self.note_transpose(note as u8)
+    } else {
+      Some(held_note.transpose)
+    };
+
+    let mut messages = [None; LAYERED];
+    let transposed = transpose.and_then(|transpose| {
+      self
+        .apply_transposed(message, transpose)
+        .map(|message| (message, transpose))
+    });
+    if let Some((mut message, transpose)) = transposed {
+      let layers = if held_note != NOT_HELD {
+        message.group = held_note.group;
+        if let Some(channel) = channel_mut(&mut message) {
+          *channel = held_note.channel;
+        }
+        held_note.layers.map(|(transpose, channel)| {
+          (channel != NO_LAYER).then(|| Layer {
+            channel,
+            transpose,
+            velocity_scale: 1.0,
+          })
+        })
+      } else {
+        self.layers
+      };
+      if event == NoteEvent::On {
+        let held_note = HeldNote {
+          transpose,
+          group: message.group,
+          channel: channel_mut(&mut message).map_or(0, |channel| *channel),
+          layers: layers
+            .map(|layer| layer.map_or(NO_LAYER_HELD, |layer| (layer.transpose, layer.channel))),
+        };
+        held.insert(group, channel, note, held_note);
+      }
+      messages[0] = Some(message);
+      for (layered, layer) in messages[1..].iter_mut().zip(layers.iter()) {
+        *layered = layer.and_then(|layer| layer.apply(message));
+      }
+    }
+    IntoIterator::into_iter(messages).flatten()
+  }
+
+  fn layered(&self, message: Message) -> [Option<Message>; LAYERED] {
+    let mut messages = [None; LAYERED];
+    if let Some(message) = self.apply(message) {
+      messages[0] = Some(message);
+      for (layered, layer) in messages[1..].iter_mut().zip(self.layers.iter()) {
+        *layered = layer.and_then(|layer| layer.apply(message));
+      }
+    }
+    messages
+  }
+
+  fn remap_program(&self, mtype: &mut MessageType) {
+    let (channel, from, bank) = match *mtype {
+      MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::ProgramChange { program },
+      }) => (channel, program, None),
+      MessageType::ChannelVoice(ChannelVoice {
+        channel,
+        message: ChanelVoiceMessage::ProgramChange { program, bank },
+      }) => (channel, program, bank),
+      _ => return,
+    };
+    let (program, target_bank) = match self
+      .programs
+      .iter()
+      .flatten()
+      .find(|(mapped, _, _)| *mapped == from)
+    {
+      Some((_, program, bank)) => (*program, *bank),
+      None => return,
+    };
+    let midi1 = matches!(mtype, MessageType::ChannelVoice1(_));
+    *mtype = match target_bank.or(bank) {
+      None if midi1 => MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::ProgramChange { program },
+      }),
+      bank => MessageType::ChannelVoice(ChannelVoice {
+        channel,
+        message: ChanelVoiceMessage::ProgramChange { program, bank },
+      }),
+    };
+  }
+
+  fn zone(&self, note: u8) -> Option<&Zone> {
+    self
+      .zones
+      .iter()
+      .flatten()
+      .find(|zone| zone.low <= note && note <= zone.high)
+  }
+
+  /// The semitones to move a note by, with the transposition and the scale, or `None` when the scale blocks it.
+  fn note_transpose(&self, note: u8) -> Option<i8> {
+    let transposed = (note as i16 + self.transpose as i16).clamp(0, 127) as u8;
+    let quantized = match self.scale {
+      Some((scale, quantize)) => scale.quantize(transposed, quantize)?,
+      None => transposed,
+    };
+    Some((quantized as i16 - note as i16) as i8)
+  }
+
+  /// Applies the velocity gain and offset to a velocity from 1 to `max`.
+  fn scale_velocity(&self, velocity: u32, max: u32) -> u32 {
+    let (gain, offset) = self.velocity_scaling;
+    if (gain, offset) == (1.0, 0) {
+      return velocity;
+    }
+    let offset = offset as i64 * (max as i64 + 1) / 128;
+    ((velocity as f32 * gain).round() as i64 + offset).clamp(1, max as i64) as u32
+  }
+
+  fn bend_channel_voice1(&self, message: &mut ChannelVoice1Message) {
+    if let (Some((input, output)), ChannelVoice1Message::PitchBend { data }) =
+      (self.pitch_bend_range, message)
+    {
+      *data = rescale_bend(*data as u32, 0x2000, input / output).min(0x3fff) as u16;
+    }
+  }
+
+  fn bend_channel_voice(&self, message: &mut ChanelVoiceMessage) {
+    if let Some((input, output)) = self.pitch_bend_range {
+      match message {
+        ChanelVoiceMessage::PitchBend { data }
+        | ChanelVoiceMessage::PerNotePitchBend { data, .. } => {
+          *data = rescale_bend(*data, 0x8000_0000, input / output);
+        }
+        ChanelVoiceMessage::RegisteredController {
+          bank: 0,
+          index: RPN_PITCH_BEND_RANGE,
+          data,
+        } => {
+          let semitones = output.min(127.0).trunc();
+          let cents = ((output - semitones) * 100.0).round().min(99.0);
+          *data = pitch_bend_range_data(semitones as u8, cents as u8);
+        }
+        _ => {}
+      }
+    }
+  }
+
+  fn apply_transposed(&self, mut message: Message, transpose: i8) -> Option<Message> {
+    self.remap_program(&mut message.mtype);
+    // The utility messages are not addressed to any group
+    if !matches!(message.mtype, MessageType::Utility(_)) {
+      message.group = self.groups[(message.group & 0x0f) as usize];
+    }
+    let note = match &mut message.mtype {
+      MessageType::ChannelVoice1(channel_voice) => {
+        channel_voice.channel = self.channels[(channel_voice.channel & 0x0f) as usize];
+        let note = channel_voice.message.note();
+        transpose_channel_voice1(&mut channel_voice.message, transpose);
+        self.bend_channel_voice1(&mut channel_voice.message);
+        if let Some(pressure_controller) = &self.pressure_controller {
+          pressure_controller.channel_voice1(&mut channel_voice.message);
+        }
+        if let ChannelVoice1Message::NoteOn { velocity, .. } = &mut channel_voice.message {
+          if *velocity > 0 {
+            let mapped = self.velocity_curve.map_velocity(*velocity as u32, 0x7f);
+            *velocity = self.scale_velocity(mapped, 0x7f) as u8;
+          }
+        }
+        note
+      }
+      MessageType::ChannelVoice(channel_voice) => {
+        channel_voice.channel = self.channels[(channel_voice.channel & 0x0f) as usize];
+        let note = channel_voice.message.note();
+        transpose_channel_voice(&mut channel_voice.message, transpose);
+        self.bend_channel_voice(&mut channel_voice.message);
+        if let Some(pressure_controller) = &self.pressure_controller {
+          pressure_controller.channel_voice(&mut channel_voice.message);
+        }
+        if let ChanelVoiceMessage::NoteOn { velocity, .. } = &mut channel_voice.message {
+          let mapped = self.velocity_curve.map_velocity(*velocity as u32, 0xffff);
+          *velocity = self.scale_velocity(mapped, 0xffff) as u16;
+        }
+        note
+      }
+      MessageType::System(
+        System::TimingClock
+        | System::Start
+        | System::Continue
+        | System::Stop
+        | System::SongPositionPointer(_),
+      ) if !self.clock => return None,
+      MessageType::Utility(_)
+      | MessageType::System(_)
+      | MessageType::SysEx7(_)
+      | MessageType::SysEx8(_)
+      | MessageType::MixedDataSet(_)
+      | MessageType::FlexData(_) => None,
+    };
+
+    if let Some(zone) = note.and_then(|note| self.zone(note)) {
+      message.group = zone.group;
+      if let Some(channel) = channel_mut(&mut message) {
+        *channel = zone.channel;
+      }
+    }
+
+    match note {
+      Some(note) if note < self.notes.0 || note > self.notes.1 => None,
+      _ => Some(message),
+    }
+  }
+}
+
+impl Default for Transform {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+/// Maximum number of points of a velocity curve table
+pub const MAX_VELOCITY_POINTS: usize = 8;
+
+/// Curve for the velocities of the note ons, with the velocities going from 0 to 1
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VelocityCurve {
+  Linear,
+  /// The velocity to the power of the exponent, so an exponent above 1 softens a heavy keyboard
+  /// and one below 1 makes a light keyboard louder
+  Exponential(f32),
+  /// Straight lines between the points, starting at (0, 0) and ending at (1, 1)
+  Table(VelocityTable),
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VelocityTable {
+  points: [(f32, f32); MAX_VELOCITY_POINTS],
+  len: usize,
+}
+
+impl VelocityCurve {
+  /// A curve going through the points, as pairs of input and output velocities from 0 to 1.
+  ///
+  /// Only the first `MAX_VELOCITY_POINTS` are kept, and they are sorted by their input velocity.
+  pub fn table(points: &[(f32, f32)]) -> Self {
+    let len = points.len().min(MAX_VELOCITY_POINTS);
+    let mut table = VelocityTable {
+      points: [(0.0, 0.0); MAX_VELOCITY_POINTS],
+      len,
+    };
+    for (point, (input, output)) in table.points.iter_mut().zip(points[..len].iter()) {
+      *point = (input.clamp(0.0, 1.0), output.clamp(0.0, 1.0));
+    }
+    table.points[..len].sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(core::cmp::Ordering::Equal));
+    Self::Table(table)
+  }
+
+  /// Maps a velocity from 0 to 1.
+  pub fn map(&self, velocity: f32) -> f32 {
+    let velocity = velocity.clamp(0.0, 1.0);
+    let mapped = match self {
+      Self::Linear => velocity,
+      Self::Exponential(exponent) => velocity.powf(*exponent),
+      Self::Table(table) => {
+        let points = &table.points[..table.len];
+        let start = points.iter().rev().find(|(input, _)| *input <= velocity);
+        let end = points.iter().find(|(input, _)| *input > velocity);
+        let (x0, y0) = start.copied().unwrap_or((0.0, 0.0));
+        let (x1, y1) = end.copied().unwrap_or((1.0, 1.0));
+        if x1 > x0 {
+          y0 + (y1 - y0) * (velocity - x0) / (x1 - x0)
+        } else {
+          y0
+        }
+      }
+    };
+    mapped.clamp(0.0, 1.0)
+  }
+
+  /// Maps a velocity from 1 to `max`, keeping it above 0 so the MIDI 1.0 note ons don't turn into note offs.
+  fn map_velocity(&self, velocity: u32, max: u32) -> u32 {
+    if *self == Self::Linear {
+      return velocity;
+    }
+    let mapped = self.map(velocity as f32 / max as f32) * max as f32;
+    (mapped.round() as u32).clamp(1, max)
+  }
+}
+
+impl Default for VelocityCurve {
+  fn default() -> Self {
+    Self::Linear
+  }
+}
+
+/// How to snap the notes out of a scale
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantize {
+  /// To the closest note in the scale, the lower one when both are as close
+  Nearest,
+  /// To the next note in the scale above
+  Up,
+  /// To the next note in the scale below
+  Down,
+  /// Drops the notes out of the scale
+  Block,
+}
+
+/// The notes of a scale in every octave, as a key and the pitch classes from it.
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scale {
+  /// Pitch class of the key, from 0 (C) to 11 (B)
+  root: u8,
+  /// Pitch classes in the scale, relative to the key
+  intervals: u16,
+}
+
+impl Scale {
+  pub const MAJOR: [u8; 7] = [0, 2, 4, 5, 7, 9, 11];
+  pub const MINOR: [u8; 7] = [0, 2, 3, 5, 7, 8, 10];
+  pub const HARMONIC_MINOR: [u8; 7] = [0, 2, 3, 5, 7, 8, 11];
+  pub const MAJOR_PENTATONIC: [u8; 5] = [0, 2, 4, 7, 9];
+  pub const MINOR_PENTATONIC: [u8; 5] = [0, 3, 5, 7, 10];
+  pub const BLUES: [u8; 6] = [0, 3, 5, 6, 7, 10];
+
+  /// A scale in the key of `root` (from 0 for C to 11 for B), with the intervals in semitones from it.
+  pub fn new(root: u8, intervals: &[u8]) -> Self {
+    let mut scale = Self {
+      root: root % 12,
+      intervals: 0,
+    };
+    for interval in intervals.iter() {
+      scale.intervals |= 1 << (interval % 12);
+    }
+    scale
+  }
+
+  pub fn major(root: u8) -> Self {
+    Self::new(root, &Self::MAJOR)
+  }
+
+  pub fn minor(root: u8) -> Self {
+    Self::new(root, &Self::MINOR)
+  }
+
+  pub fn contains(&self, note: u8) -> bool {
+    let interval = (note % 12 + 12 - self.root) % 12;
+    self.intervals & (1 << interval) != 0
+  }
+
+  /// Snaps a note to the scale, or returns `None` when it is blocked (or the scale has no notes near it).
+  pub fn quantize(&self, note: u8, quantize: Quantize) -> Option<u8> {
+    if self.contains(note) {
+      return Some(note);
+    }
+    let find = |step: i16| {
+      (1..12)
+        .map(|distance| note as i16 + distance * step)
+        .take_while(|note| (0..=127).contains(note))
+        .find(|note| self.contains(*note as u8))
+    };
+    let (below, above) = (find(-1), find(1));
+    let quantized = match quantize {
+      Quantize::Nearest => match (below, above) {
+        (Some(below), Some(above)) if note as i16 - below <= above - note as i16 => below,
+        (_, Some(above)) => above,
+        (below, None) => below?,
+      },
+      Quantize::Up => above.or(below)?,
+      Quantize::Down => below.or(above)?,
+      Quantize::Block => return None,
+    };
+    Some(quantized as u8)
+  }
+}
+
+/// The pressure messages turned into control changes
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pressure {
+  Channel,
+  Poly,
+  Both,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct PressureController {
+  pressure: Pressure,
+  controller: u8,
+  /// Controller values for no pressure and for the full pressure, with 7 bits
+  low: u8,
+  high: u8,
+}
+
+impl PressureController {
+  fn channel_voice1(&self, message: &mut ChannelVoice1Message) {
+    let data = match *message {
+      ChannelVoice1Message::ChannelPressure { data } if self.pressure != Pressure::Poly => data,
+      ChannelVoice1Message::PolyPressure { data, .. } if self.pressure != Pressure::Channel => data,
+      _ => return,
+    };
+    *message = ChannelVoice1Message::ControlChange {
+      index: self.controller,
+      data: (self.scale(scale_up(data as u32, 7, 32)) >> 25) as u8,
+    };
+  }
+
+  fn channel_voice(&self, message: &mut ChanelVoiceMessage) {
+    let data = match *message {
+      ChanelVoiceMessage::ChannelPressure { data } if self.pressure != Pressure::Poly => data,
+      ChanelVoiceMessage::PolyPressure { data, .. } if self.pressure != Pressure::Channel => data,
+      _ => return,
+    };
+    *message = ChanelVoiceMessage::ControlChange {
+      index: self.controller,
+      data: self.scale(data),
+    };
+  }
+
+  /// Scales a pressure with 32 bits to the range of the controller.
+  fn scale(&self, data: u32) -> u32 {
+    let low = scale_up(self.low as u32, 7, 32) as i128;
+    let high = scale_up(self.high as u32, 7, 32) as i128;
+    (low + (high - low) * data as i128 / u32::MAX as i128) as u32
+  }
+}
+
+impl Layer {
+  /// Copies a channel message to the layer, or returns `None` for the rest.
+  fn apply(&self, mut message: Message) -> Option<Message> {
+    match &mut message.mtype {
+      MessageType::ChannelVoice1(ChannelVoice1 {
+        message: ChannelVoice1Message::ProgramChange { .. },
+        ..
+      })
+      | MessageType::ChannelVoice(ChannelVoice {
+        message: ChanelVoiceMessage::ProgramChange { .. },
+        ..
+      }) => return None,
+      MessageType::ChannelVoice1(channel_voice) => {
+        channel_voice.channel = self.channel;
+        transpose_channel_voice1(&mut channel_voice.message, self.transpose);
+        if let ChannelVoice1Message::NoteOn { velocity, .. } = &mut channel_voice.message {
+          // Keeping the note ons from turning into note offs
+          if *velocity > 0 {
+            *velocity = self.scale_velocity(*velocity as u32, 1, 0x7f) as u8;
+          }
+        }
+      }
+      MessageType::ChannelVoice(channel_voice) => {
+        channel_voice.channel = self.channel;
+        transpose_channel_voice(&mut channel_voice.message, self.transpose);
+        if let ChanelVoiceMessage::NoteOn { velocity, .. } = &mut channel_voice.message {
+          *velocity = self.scale_velocity(*velocity as u32, 0, 0xffff) as u16;
+        }
+      }
+      _ => return None,
+    }
+    Some(message)
+  }
+
+  fn scale_velocity(&self, velocity: u32, min: u32, max: u32) -> u32 {
+    ((velocity as f32 * self.velocity_scale).round() as u32).clamp(min, max)
+  }
+}
+
+/// A note held, with its transposition (including the semitones moved to snap it to the scale),
+/// the group and channel it was sent to, and the transposition and channel of its layers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct HeldNote {
+  transpose: i8,
+  group: u8,
+  channel: u8,
+  layers: [(i8, u8); MAX_LAYERS],
+}
+
+/// Marks the layers not sent
+const NO_LAYER: u8 = u8::MAX;
+
+const NO_LAYER_HELD: (i8, u8) = (0, NO_LAYER);
+
+/// Marks the notes not held, as the transpositions only go from -127 to 127
+const NOT_HELD: HeldNote = HeldNote {
+  transpose: i8::MIN,
+  group: 0,
+  channel: 0,
+  layers: [NO_LAYER_HELD; MAX_LAYERS],
+};
+
+/// The most notes held at once that are tracked, the rest are sent as if they were not held
+const MAX_HELD_NOTES: usize = 256;
+
+/// The notes held in every channel of every group, by the note received.
+///
+/// Everything is allocated when created, so the notes can be tracked from real-time threads.
+pub(crate) struct HeldNotes {
+  /// Whether every note is held, by group and channel
+  held: Box<[u128]>,
+  /// How the notes held were sent, with the group, channel and note received
+  notes: Vec<(u16, HeldNote)>,
+}
+
+impl HeldNotes {
+  pub fn new() -> Self {
+    Self {
+      held: vec![0; 16 * 16].into_boxed_slice(),
+      notes: Vec::with_capacity(MAX_HELD_NOTES),
+    }
+  }
+
+  fn key(group: usize, channel: usize, note: usize) -> u16 {
+    ((group << 11) | (channel << 7) | note) as u16
+  }
+
+  fn is_held(&self, group: usize, channel: usize, note: usize) -> bool {
+    self.held[group * 16 + channel] & (1 << note) != 0
+  }
+
+  fn get(&self, group: usize, channel: usize, note: usize) -> HeldNote {
+    if !self.is_held(group, channel, note) {
+      return NOT_HELD;
+    }
+    let key = Self::key(group, channel, note);
+    self
+      .notes
+      .iter()
+      .find(|(held_key, _)| *held_key == key)
+      .map_or(NOT_HELD, |(_, held_note)| *held_note)
+  }
+
+  fn insert(&mut self, group: usize, channel: usize, note: usize, held_note: HeldNote) {
+    let key = Self::key(group, channel, note);
+    if self.is_held(group, channel, note) {
+      if let Some((_, held)) = self.notes.iter_mut().find(|(held_key, _)| *held_key == key) {
+        *held = held_note;
+      }
+    } else if self.notes.len() < MAX_HELD_NOTES {
+      self.notes.push((key, held_note));
+      self.held[group * 16 + channel] |= 1 << note;
+    }
+  }
+
+  fn remove(&mut self, group: usize, channel: usize, note: usize) -> HeldNote {
+    if !self.is_held(group, channel, note) {
+      return NOT_HELD;
+    }
+    self.held[group * 16 + channel] &= !(1 << note);
+    let key = Self::key(group, channel, note);
+    match self.notes.iter().position(|(held_key, _)| *held_key == key) {
+      Some(index) => self.notes.swap_remove(index).1,
+      None => NOT_HELD,
+    }
+  }
+}
+
+impl Default for HeldNotes {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NoteEvent {
+  On,
+  Off,
+  Other,
+}
+
+/// The group, channel and note of a message addressed to a note, as indexes.
+fn note_event(message: &Message) -> Option<(usize, usize, usize, NoteEvent)> {
+  let (channel, note, event) = match message.mtype {
+    MessageType::ChannelVoice1(channel_voice) => {
+      let event = match channel_voice.message {
+        ChannelVoice1Message::NoteOn { velocity, .. } if velocity > 0 => NoteEvent::On,
+        ChannelVoice1Message::NoteOn { .. } | ChannelVoice1Message::NoteOff { .. } => {
+          NoteEvent::Off
+        }
+        _ => NoteEvent::Other,
+      };
+      (channel_voice.channel, channel_voice.message.note()?, event)
+    }
+    MessageType::ChannelVoice(channel_voice) => {
+      let event = match channel_voice.message {
+        ChanelVoiceMessage::NoteOn { .. } => NoteEvent::On,
+        ChanelVoiceMessage::NoteOff { .. } => NoteEvent::Off,
+        _ => NoteEvent::Other,
+      };
+      (channel_voice.channel, channel_voice.message.note()?, event)
+    }
+    _ => return None,
+  };
+  Some((
+    (message.group & 0x0f) as usize,
+    (channel & 0x0f) as usize,
+    (note & 0x7f) as usize,
+    event,
+  ))
+}
+
+fn channel_mut(message: &mut Message) -> Option<&mut u8> {
+  match &mut message.mtype {
+    MessageType::ChannelVoice1(channel_voice) => Some(&mut channel_voice.channel),
+    MessageType::ChannelVoice(channel_voice) => Some(&mut channel_voice.channel),
+    _ => None,
+  }
+}
+
+/// Scales the distance of a pitch bend to its `center` by `ratio`, clamping it to the bits of `center`.
+fn rescale_bend(data: u32, center: u32, ratio: f32) -> u32 {
+  let center = center as f64;
+  let rescaled = center + (data as f64 - center) * ratio as f64;
+  rescaled.round().clamp(0.0, center * 2.0 - 1.0) as u32
+}
+
+fn transpose_note(note: &mut u8, transpose: i8) {
+  *note = (*note as i16 + transpose as i16).clamp(0, 127) as u8;
+}
+
+fn transpose_channel_voice1(message: &mut ChannelVoice1Message, transpose: i8) {
+  match message {
+    ChannelVoice1Message::NoteOff { note, .. }
+    | ChannelVoice1Message::NoteOn { note, .. }
+    | ChannelVoice1Message::PolyPressure { note, .. } => transpose_note(note, transpose),
+    _ => {}
+  }
+}
+
+fn transpose_channel_voice(message: &mut ChanelVoiceMessage, transpose: i8) {
+  match message {
+    ChanelVoiceMessage::NoteOff {
+      note,
+      attr_type,
+      attr_data,
+      ..
+    }
+    | ChanelVoiceMessage::NoteOn {
+      note,
+      attr_type,
+      attr_data,
+      ..
+    } => {
+      transpose_note(note, transpose);
+      // The pitch 7.9 attribute has the note in its top 7 bits
+      if AttributeType::from(*attr_type) == AttributeType::Pitch7_9 {
+        *attr_data = (*attr_data as i32 + ((transpose as i32) << 9)).clamp(0, 0xffff) as u16;
+      }
+    }
+    ChanelVoiceMessage::PolyPressure { note, .. }
+    | ChanelVoiceMessage::RegisteredPerNoteController { note, .. }
+    | ChanelVoiceMessage::AssignablePerNoteController { note, .. }
+    | ChanelVoiceMessage::PerNoteManagement { note, .. }
+    | ChanelVoiceMessage::PerNotePitchBend { note, .. } => transpose_note(note, transpose),
+    _ => {}
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn note_on(channel: u8, note: u8) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::NoteOn {
+          note,
+          velocity: 0x64,
+        },
+      }),
+    }
+  }
+
+  fn system(system: System) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::System(system),
+    }
+  }
+
+  #[test]
+  fn remap_channels() {
+    let transform = Transform::new().with_channel(1, 10);
+
+    assert_eq!(transform.apply(note_on(0, 0x3c)), Some(note_on(9, 0x3c)));
+    assert_eq!(transform.apply(note_on(1, 0x3c)), Some(note_on(1, 0x3c)));
+    assert_eq!(
+      Transform::default().apply(note_on(5, 0x3c)),
+      Some(note_on(5, 0x3c))
+    );
+  }
+
+  #[test]
+  fn remap_all_channels() {
+    let transform = Transform::new().with_all_channels(2).with_channel(0, 3);
+
+    assert_eq!(transform.apply(note_on(7, 0x3c)), Some(note_on(1, 0x3c)));
+    assert_eq!(
+      transform.apply(system(System::TimingClock)),
+      Some(system(System::TimingClock))
+    );
+  }
+
+  #[test]
+  fn remap_groups() {
+    let in_group = |group: u8, message: Message| Message { group, ..message };
+    let transform = Transform::new().with_group(1, 3).with_channel(1, 2);
+
+    assert_eq!(
+      transform.apply(note_on(0, 0x3c)),
+      Some(in_group(2, note_on(1, 0x3c)))
+    );
+    assert_eq!(
+      transform.apply(in_group(1, note_on(0, 0x3c))),
+      Some(in_group(1, note_on(1, 0x3c)))
+    );
+
+    let transform = Transform::new().with_all_groups(1);
+    assert_eq!(
+      transform.apply(in_group(5, system(System::TimingClock))),
+      Some(system(System::TimingClock))
+    );
+  }
+
+  #[test]
+  fn restrict_note_range() {
+    let transform = Transform::new().with_note_range(0x30, 0x3f);
+
+    assert_eq!(transform.apply(note_on(0, 0x30)), Some(note_on(0, 0x30)));
+    assert_eq!(transform.apply(note_on(0, 0x3f)), Some(note_on(0, 0x3f)));
+    assert_eq!(transform.apply(note_on(0, 0x40)), None);
+    assert_eq!(
+      transform.apply(system(System::Start)),
+      Some(system(System::Start))
+    );
+  }
+
+  fn note_off(channel: u8, note: u8) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::NoteOff {
+          note,
+          velocity: 0x40,
+        },
+      }),
+    }
+  }
+
+  #[test]
+  fn transpose_notes() {
+    let transform = Transform::new().with_transpose(-12);
+
+    assert_eq!(transform.apply(note_on(0, 0x3c)), Some(note_on(0, 0x30)));
+    assert_eq!(transform.apply(note_on(0, 0x05)), Some(note_on(0, 0x00)));
+    assert_eq!(
+      Transform::new().with_transpose(12).apply(note_on(0, 0x7a)),
+      Some(note_on(0, 0x7f))
+    );
+
+    let pitch = Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice(ChannelVoice {
+        channel: 0,
+        message: ChanelVoiceMessage::NoteOn {
+          note: 69,
+          velocity: 0xffff,
+          attr_type: 0x03,
+          attr_data: (69 << 9) | 0x100,
+        },
+      }),
+    };
+    let transposed = transform.apply(pitch).unwrap();
+    assert!(matches!(
+      transposed.mtype,
+      MessageType::ChannelVoice(ChannelVoice {
+        message: ChanelVoiceMessage::NoteOn {
+          note: 57,
+          attr_data,
+          ..
+        },
+        ..
+      }) if attr_data == (57 << 9) | 0x100
+    ));
+  }
+
+  #[test]
+  fn held_notes_in_every_channel() {
+    let mut held = HeldNotes::new();
+
+    for channel in 0..16 {
+      Transform::new()
+        .with_transpose(12)
+        .apply_held(note_on(channel, 60), &mut held)
+        .next();
+    }
+    for channel in 0..16 {
+      assert_eq!(
+        Transform::new()
+          .apply_held(note_off(channel, 60), &mut held)
+          .next(),
+        Some(note_off(channel, 72))
+      );
+    }
+    assert!(held.notes.is_empty());
+    assert!(held.held.iter().all(|notes| *notes == 0));
+  }
+
+  #[test]
+  fn split_zones() {
+    let mut held = HeldNotes::new();
+    let split = |point: u8| {
+      Transform::new()
+        .with_zone(0, point - 1, 1, 2)
+        .with_zone(point, 127, 1, 1)
+    };
+
+    assert_eq!(split(60).apply(note_on(0, 59)), Some(note_on(1, 59)));
+    assert_eq!(split(60).apply(note_on(5, 60)), Some(note_on(0, 60)));
+    assert_eq!(
+      Transform::new()
+        .with_zone(0, 59, 2, 2)
+        .apply(note_on(0, 40))
+        .map(|message| message.group),
+      Some(1)
+    );
+
+    // Moving the split point while holding a note
+    split(60).apply_held(note_on(0, 62), &mut held).next();
+    assert_eq!(
+      split(64).apply_held(note_off(0, 62), &mut held).next(),
+      Some(note_off(0, 62))
+    );
+    assert_eq!(
+      split(64).apply_held(note_on(0, 62), &mut held).next(),
+      Some(note_on(1, 62))
+    );
+  }
+
+  #[test]
+  #[cfg(debug_assertions)]
+  #[should_panic(expected = "more than 4 zones")]
+  fn zones_beyond_the_limit() {
+    let _ = (0..=MAX_ZONES as u8).fold(Transform::new(), |transform, zone| {
+      transform.with_zone(zone * 12, zone * 12 + 11, 1, zone + 1)
+    });
+  }
+
+  #[test]
+  fn layer_channels() {
+    let mut held = HeldNotes::new();
+    let note_on_velocity = |channel: u8, note: u8, velocity: u8| Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel,
+        message: ChannelVoice1Message::NoteOn { note, velocity },
+      }),
+    };
+    let program_change = Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message: ChannelVoice1Message::ProgramChange { program: 5 },
+      }),
+    };
+    let layered = Transform::new()
+      .with_layer(2, 12, 0.5)
+      .with_layer(3, -12, 2.0);
+
+    assert_eq!(
+      layered.apply_layered(note_on(0, 60)).collect::<Vec<_>>(),
+      vec![
+        note_on(0, 60),
+        note_on_velocity(1, 72, 50),
+        note_on_velocity(2, 48, 127)
+      ]
+    );
+    assert_eq!(
+      layered.apply_layered(note_on_velocity(0, 60, 1)).last(),
+      Some(note_on_velocity(2, 48, 2))
+    );
+    assert_eq!(
+      Transform::new()
+        .with_layer(2, 0, 0.0)
+        .apply_layered(note_on(0, 60))
+        .last(),
+      Some(note_on_velocity(1, 60, 1))
+    );
+    assert_eq!(
+      layered.apply_layered(program_change).collect::<Vec<_>>(),
+      vec![program_change]
+    );
+    assert_eq!(
+      layered
+        .apply_layered(system(System::Start))
+        .collect::<Vec<_>>(),
+      vec![system(System::Start)]
+    );
+
+    // Removing the layers while holding a note
+    assert_eq!(layered.apply_held(note_on(0, 60), &mut held).count(), 3);
+    assert_eq!(
+      Transform::new()
+        .apply_held(note_off(0, 60), &mut held)
+        .collect::<Vec<_>>(),
+      vec![note_off(0, 60), note_off(1, 72), note_off(2, 48)]
+    );
+    assert_eq!(
+      Transform::new()
+        .apply_held(note_off(0, 60), &mut held)
+        .collect::<Vec<_>>(),
+      vec![note_off(0, 60)]
+    );
+  }
+
+  #[test]
+  #[cfg(debug_assertions)]
+  #[should_panic(expected = "more than 3 layers")]
+  fn layers_beyond_the_limit() {
+    let _ = (0..=MAX_LAYERS as u8).fold(Transform::new(), |transform, layer| {
+      transform.with_layer(layer + 2, 12, 1.0)
+    });
+  }
+
+  #[test]
+  fn note_offs_follow_their_note_ons() {
+    let mut held = HeldNotes::new();
+    let up = Transform::new().with_transpose(2);
+    let down = Transform::new().with_transpose(-2);
+
+    assert_eq!(
+      up.apply_held(note_on(0, 0x3c), &mut held).next(),
+      Some(note_on(0, 0x3e))
+    );
+    // The transposition changes while the note is held
+    assert_eq!(
+      down.apply_held(note_off(0, 0x3c), &mut held).next(),
+      Some(note_off(0, 0x3e))
+    );
+    // Once released, the note offs without a note on take the current transposition
+    assert_eq!(
+      down.apply_held(note_off(0, 0x3c), &mut held).next(),
+      Some(note_off(0, 0x3a))
+    );
+    // So do the MIDI 1.0 note ons with velocity 0
+    up.apply_held(note_on(1, 0x3c), &mut held).next();
+    let release = Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 1,
+        message: ChannelVoice1Message::NoteOn {
+          note: 0x3c,
+          velocity: 0,
+        },
+      }),
+    };
+    assert!(matches!(
+      down.apply_held(release, &mut held).next().unwrap().mtype,
+      MessageType::ChannelVoice1(ChannelVoice1 {
+        message: ChannelVoice1Message::NoteOn { note: 0x3e, .. },
+        ..
+      })
+    ));
+  }
+
+  #[test]
+  fn quantize_to_scales() {
+    let c_major = Scale::major(0);
+    let a_minor_pentatonic = Scale::new(9, &Scale::MINOR_PENTATONIC);
+
+    assert_eq!(c_major.quantize(60, Quantize::Nearest), Some(60));
+    assert_eq!(c_major.quantize(61, Quantize::Nearest), Some(60));
+    assert_eq!(c_major.quantize(61, Quantize::Up), Some(62));
+    assert_eq!(c_major.quantize(63, Quantize::Down), Some(62));
+    assert_eq!(c_major.quantize(61, Quantize::Block), None);
+    assert_eq!(c_major.quantize(127, Quantize::Up), Some(127));
+    assert_eq!(Scale::major(1).quantize(127, Quantize::Up), Some(126));
+    // C# is as close to C as to D, so it goes down
+    assert_eq!(a_minor_pentatonic.quantize(61, Quantize::Nearest), Some(60));
+    assert_eq!(a_minor_pentatonic.quantize(66, Quantize::Nearest), Some(67));
+    assert_eq!(Scale::new(0, &[]).quantize(60, Quantize::Nearest), None);
+  }
+
+  #[test]
+  fn snap_notes_to_scale() {
+    let mut held = HeldNotes::new();
+    let c_major = Transform::new()
+      .with_transpose(1)
+      .with_scale(Scale::major(0), Quantize::Up);
+    let blocked = Transform::new().with_scale(Scale::major(0), Quantize::Block);
+
+    assert_eq!(c_major.apply(note_on(0, 60)), Some(note_on(0, 62)));
+    assert_eq!(c_major.apply(note_on(0, 63)), Some(note_on(0, 64)));
+    assert_eq!(blocked.apply(note_on(0, 61)), None);
+    assert_eq!(blocked.apply(note_on(0, 62)), Some(note_on(0, 62)));
+
+    // The note offs follow the note ons, even after changing the scale
+    c_major.apply_held(note_on(0, 60), &mut held).next();
+    assert_eq!(
+      blocked.apply_held(note_off(0, 60), &mut held).next(),
+      Some(note_off(0, 62))
+    );
+    assert_eq!(blocked.apply_held(note_on(0, 61), &mut held).next(), None);
+    assert_eq!(blocked.apply_held(note_off(0, 61), &mut held).next(), None);
+  }
+
+  #[test]
+  fn rescale_pitch_bends() {
+    let bend = |data: u16| Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message: ChannelVoice1Message::PitchBend { data },
+      }),
+    };
+    let midi2 = |message: ChanelVoiceMessage| Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice(ChannelVoice {
+        channel: 0,
+        message,
+      }),
+    };
+    let to_mpe = Transform::new().with_pitch_bend_range(2.0, 48.0);
+    let from_mpe = Transform::new().with_pitch_bend_range(48.0, 2.0);
+
+    assert_eq!(to_mpe.pitch_bend_range(), Some(48.0));
+    assert_eq!(Transform::new().pitch_bend_range(), None);
+    assert_eq!(to_mpe.apply(bend(0x2000)), Some(bend(0x2000)));
+    assert_eq!(to_mpe.apply(bend(0x3fff)), Some(bend(8533)));
+    assert_eq!(to_mpe.apply(bend(0)), Some(bend(7851)));
+    assert_eq!(from_mpe.apply(bend(0x2100)), Some(bend(14336)));
+    assert_eq!(from_mpe.apply(bend(0x3000)), Some(bend(0x3fff)));
+    assert_eq!(from_mpe.apply(bend(0x1000)), Some(bend(0)));
+
+    let doubled = Transform::new().with_pitch_bend_range(1.0, 2.0);
+    assert_eq!(
+      doubled.apply(midi2(ChanelVoiceMessage::PitchBend { data: 0x9000_0000 })),
+      Some(midi2(ChanelVoiceMessage::PitchBend { data: 0x8800_0000 }))
+    );
+    assert_eq!(
+      doubled.apply(midi2(ChanelVoiceMessage::PerNotePitchBend {
+        note: 60,
+        data: 0x7000_0000
+      })),
+      Some(midi2(ChanelVoiceMessage::PerNotePitchBend {
+        note: 60,
+        data: 0x7800_0000
+      }))
+    );
+    assert_eq!(
+      to_mpe.apply(midi2(ChanelVoiceMessage::RegisteredController {
+        bank: 0,
+        index: RPN_PITCH_BEND_RANGE,
+        data: pitch_bend_range_data(2, 0)
+      })),
+      Some(midi2(ChanelVoiceMessage::RegisteredController {
+        bank: 0,
+        index: RPN_PITCH_BEND_RANGE,
+        data: pitch_bend_range_data(48, 0)
+      }))
+    );
+  }
+
+  #[test]
+  fn pressure_to_controller() {
+    let channel_voice1 = |message: ChannelVoice1Message| Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message,
+      }),
+    };
+    let channel_pressure = channel_voice1(ChannelVoice1Message::ChannelPressure { data: 0x7f });
+    let poly_pressure = channel_voice1(ChannelVoice1Message::PolyPressure {
+      note: 60,
+      data: 0x40,
+    });
+    let control_change =
+      |data: u8| channel_voice1(ChannelVoice1Message::ControlChange { index: 1, data });
+
+    let full = Transform::new().with_pressure_to_controller(Pressure::Both, 1, 0, 127);
+    assert_eq!(full.apply(channel_pressure), Some(control_change(0x7f)));
+    assert_eq!(full.apply(poly_pressure), Some(control_change(0x40)));
+
+    let channel = Transform::new().with_pressure_to_controller(Pressure::Channel, 1, 20, 40);
+    assert_eq!(channel.apply(channel_pressure), Some(control_change(40)));
+    assert_eq!(channel.apply(poly_pressure), Some(poly_pressure));
+
+    let inverted = Transform::new().with_pressure_to_controller(Pressure::Channel, 1, 127, 0);
+    assert_eq!(inverted.apply(channel_pressure), Some(control_change(0)));
+
+    let midi2 = Transform::new().with_pressure_to_controller(Pressure::Poly, 1, 0, 127);
+    let poly_pressure = Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice(ChannelVoice {
+        channel: 0,
+        message: ChanelVoiceMessage::PolyPressure {
+          note: 60,
+          data: 0x8000_0000,
+        },
+      }),
+    };
+    assert_eq!(
+      midi2.apply(poly_pressure).unwrap().mtype,
+      MessageType::ChannelVoice(ChannelVoice {
+        channel: 0,
+        message: ChanelVoiceMessage::ControlChange {
+          index: 1,
+          data: 0x8000_0000,
+        },
+      })
+    );
+  }
+
+  #[test]
+  fn remap_programs() {
+    let program_change = |program: u8| Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+        channel: 0,
+        message: ChannelVoice1Message::ProgramChange { program },
+      }),
+    };
+    let program_change2 = |program: u8, bank: Option<u16>| Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice(ChannelVoice {
+        channel: 0,
+        message: ChanelVoiceMessage::ProgramChange { program, bank },
+      }),
+    };
+    let transform = Transform::new()
+      .with_program(0, 10, None)
+      .with_program(1, 20, Some(0x0102))
+      .with_program(0, 30, None);
+
+    assert_eq!(transform.apply(program_change(0)), Some(program_change(30)));
+    assert_eq!(
+      transform.apply(program_change(1)),
+      Some(program_change2(20, Some(0x0102)))
+    );
+    assert_eq!(transform.apply(program_change(2)), Some(program_change(2)));
+    assert_eq!(
+      transform.apply(program_change2(0, Some(5))),
+      Some(program_change2(30, Some(5)))
+    );
+  }
+
+  #[test]
+  #[cfg(debug_assertions)]
+  #[should_panic(expected = "more than 16 programs")]
+  fn programs_beyond_the_limit() {
+    let _ = (0..=MAX_PROGRAM_MAPS as u8).fold(Transform::new(), |transform, program| {
+      transform.with_program(program, program + 64, None)
+    });
+  }
+
+  #[test]
+  fn velocity_curves() {
+    let velocity = |transform: Transform, velocity: u8| match transform
+      .apply(Message {
+        group: 0,
+        mtype: MessageType::ChannelVoice1(ChannelVoice1 {
+          channel: 0,
+          message: ChannelVoice1Message::NoteOn {
+            note: 0x3c,
+            velocity,
+          },
+        }),
+      })
+      .map(|message| message.mtype)
+    {
+      Some(MessageType::ChannelVoice1(ChannelVoice1 {
+        message: ChannelVoice1Message::NoteOn { velocity, .. },
+        ..
+      })) => velocity,
+      _ => unreachable!(),
+    };
+
+    let soft = Transform::new().with_velocity_curve(VelocityCurve::Exponential(2.0));
+    assert_eq!(velocity(soft, 127), 127);
+    assert_eq!(velocity(soft, 64), 32);
+    // The note ons are never turned into note offs
+    assert_eq!(velocity(soft, 1), 1);
+    assert_eq!(velocity(soft, 0), 0);
+
+    let table = VelocityCurve::table(&[(0.5, 0.75), (0.25, 0.5)]);
+    assert_eq!(table.map(0.125), 0.25);
+    assert_eq!(table.map(0.375), 0.625);
+    assert_eq!(table.map(0.75), 0.875);
+    assert_eq!(
+      velocity(Transform::new().with_velocity_curve(table), 127),
+      127
+    );
+
+    let pitch = Message {
+      group: 0,
+      mtype: MessageType::ChannelVoice(ChannelVoice {
+        channel: 0,
+        message: ChanelVoiceMessage::NoteOn {
+          note: 0x3c,
+          velocity: 0x8000,
+          attr_type: 0,
+          attr_data: 0,
+        },
+      }),
+    };
+    assert!(matches!(
+      soft.apply(pitch).unwrap().mtype,
+      MessageType::ChannelVoice(ChannelVoice {
+        message: ChanelVoiceMessage::NoteOn {
+          velocity: 0x4000,
+          ..
+        },
+        ..
+      })
+    ));
+
+    let louder = Transform::new().with_velocity_scaling(1.5, 10);
+    assert_eq!(velocity(louder, 20), 40);
+    assert_eq!(velocity(louder, 100), 127);
+    assert_eq!(velocity(louder, 0), 0);
+    let softer = Transform::new().with_velocity_scaling(0.5, -20);
+    assert_eq!(velocity(softer, 100), 30);
+    assert_eq!(velocity(softer, 20), 1);
+    assert_eq!(velocity(soft.with_velocity_scaling(1.0, 10), 64), 42);
+    assert!(matches!(
+      louder.apply(pitch).unwrap().mtype,
+      MessageType::ChannelVoice(ChannelVoice {
+        message: ChanelVoiceMessage::NoteOn {
+          velocity: 0xd400,
+          ..
+        },
+        ..
+      })
+    ));
+  }
+
+  #[test]
+  fn strip_clock() {
+    let transform = Transform::new().without_clock();
+
+    assert_eq!(transform.apply(system(System::TimingClock)), None);
+    assert_eq!(
+      transform.apply(system(System::SongPositionPointer(0))),
+      None
+    );
+    assert_eq!(
+      transform.apply(system(System::TuneRequest)),
+      Some(system(System::TuneRequest))
+    );
+    assert_eq!(transform.apply(note_on(0, 0x3c)), Some(note_on(0, 0x3c)));
+  }
+}
@@ -0,0 +1,174 @@
+//! Transport of a sequencer, as controlled through the System Real Time and Common messages.
+
+use crate::protocol::messages::system::System;
+use crate::protocol::messages::{Message, MessageType};
+
+/// MIDI clocks per beat (a quarter note)
+pub const TICKS_PER_BEAT: u64 = 24;
+/// Every beat of the song position pointer is a sixteenth note
+pub const TICKS_PER_SIXTEENTH: u64 = TICKS_PER_BEAT / 4;
+
+/// The song position pointer holds 14 bits
+const MAX_SONG_POSITION: u64 = 0x3fff;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+  /// Start from the beginning of the song
+  Start,
+  Stop,
+  /// Resume from the current position
+  Continue,
+  /// Move to a position, in sixteenth notes from the beginning of the song
+  SongPosition {
+    sixteenths: u16,
+  },
+}
+
+impl Transport {
+  pub fn from_message(message: &Message) -> Option<Self> {
+    match message.mtype {
+      MessageType::System(System::Start) => Some(Self::Start),
+      MessageType::System(System::Stop) => Some(Self::Stop),
+      MessageType::System(System::Continue) => Some(Self::Continue),
+      MessageType::System(System::SongPositionPointer(sixteenths)) => Some(Self::SongPosition {
+        sixteenths: sixteenths & 0x3fff,
+      }),
+      _ => None,
+    }
+  }
+
+  pub fn to_message(self, group: u8) -> Message {
+    let system = match self {
+      Self::Start => System::Start,
+      Self::Stop => System::Stop,
+      Self::Continue => System::Continue,
+      Self::SongPosition { sixteenths } => System::SongPositionPointer(sixteenths & 0x3fff),
+    };
+    Message {
+      group,
+      mtype: MessageType::System(system),
+    }
+  }
+}
+
+/// Tracks whether the transport is running and its position, counting the MIDI clocks as ticks.
+#[derive(Debug, Clone, Default)]
+pub struct TransportState {
+  running: bool,
+  /// Position of the next clock
+  ticks: u64,
+}
+
+impl TransportState {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  pub fn is_running(&self) -> bool {
+    self.running
+  }
+
+  /// Position in ticks, 24 per beat.
+  pub fn ticks(&self) -> u64 {
+    self.ticks
+  }
+
+  /// The position as a song position pointer, rounded down to the sixteenth.
+  pub fn song_position(&self) -> u16 {
+    (self.ticks / TICKS_PER_SIXTEENTH).min(MAX_SONG_POSITION) as u16
+  }
+
+  pub fn apply(&mut self, transport: Transport) {
+    match transport {
+      Transport::Start => {
+        self.running = true;
+        self.ticks = 0;
+      }
+      Transport::Stop => self.running = false,
+      Transport::Continue => self.running = true,
+      Transport::SongPosition { sixteenths } => {
+        self.ticks = sixteenths as u64 * TICKS_PER_SIXTEENTH;
+      }
+    }
+  }
+
+  /// Advances the position with a clock, returning the position of the clock if the transport is running.
+  pub fn tick(&mut self) -> Option<u64> {
+    self.running.then(|| {
+      self.ticks += 1;
+      self.ticks - 1
+    })
+  }
+
+  /// Handles the transport messages and the clocks, returning the transport message if it was one.
+  pub fn process(&mut self, message: &Message) -> Option<Transport> {
+    if let MessageType::System(System::TimingClock) = message.mtype {
+      self.tick();
+      return None;
+    }
+    let transport = Transport::from_message(message)?;
+    self.apply(transport);
+    Some(transport)
+  }
+
+  pub fn reset(&mut self) {
+    *self = Self::default();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn system(system: System) -> Message {
+    Message {
+      group: 0,
+      mtype: MessageType::System(system),
+    }
+  }
+
+  #[test]
+  fn transport_messages() {
+    for transport in [
+      Transport::Start,
+      Transport::Stop,
+      Transport::Continue,
+      Transport::SongPosition { sixteenths: 0x1234 },
+    ] {
+      assert_eq!(
+        Transport::from_message(&transport.to_message(3)),
+        Some(transport)
+      );
+    }
+    assert_eq!(Transport::from_message(&system(System::TimingClock)), None);
+  }
+
+  #[test]
+  fn running_and_position() {
+    let mut state = TransportState::new();
+    let clock = system(System::TimingClock);
+
+    state.process(&clock);
+    assert_eq!(state.ticks(), 0);
+
+    assert_eq!(
+      state.process(&system(System::Start)),
+      Some(Transport::Start)
+    );
+    for _ in 0..13 {
+      state.process(&clock);
+    }
+    assert!(state.is_running());
+    assert_eq!(state.ticks(), 13);
+    assert_eq!(state.song_position(), 2);
+
+    state.process(&system(System::Stop));
+    state.process(&system(System::SongPositionPointer(8)));
+    state.process(&clock);
+    assert_eq!(state.ticks(), 48);
+
+    state.process(&system(System::Continue));
+    assert_eq!(state.tick(), Some(48));
+    assert_eq!(state.ticks(), 49);
+  }
+}
@@ -0,0 +1,427 @@
+//! MIDI Tuning Standard (MTS).
+//!
+//! A tuning gives every note number its own pitch, with a resolution of 1/16384 of semitone.
+//! A device receives a whole tuning with a bulk dump, addressed to one of its tuning programs,
+//! and can have single notes retuned while playing with the note change messages.
+//!
+//! The pitches convert to the 7.9 pitch attribute of the MIDI 2.0 note on with
+//! [`TuningPitch::to_note_pitch`], to play the tuning on MIDI 2.0 destinations.
+
+use crate::protocol::messages::channel_voice::NotePitch;
+use crate::protocol::messages::sysex7::SysExAssembler;
+use crate::protocol::messages::{Message, MessageType};
+
+const UNIVERSAL_NON_REALTIME: u8 = 0x7e;
+const UNIVERSAL_REALTIME: u8 = 0x7f;
+const MIDI_TUNING: u8 = 0x08;
+
+const BULK_DUMP_REQUEST: u8 = 0x00;
+const BULK_DUMP: u8 = 0x01;
+const NOTE_CHANGE: u8 = 0x02;
+const NOTE_CHANGE_WITH_BANK: u8 = 0x07;
+
+const HEADER_LEN: usize = 4;
+const NAME_LEN: usize = 16;
+const PITCH_LEN: usize = 3;
+const NOTES: usize = 128;
+/// Header, program, name, pitches and checksum
+const BULK_DUMP_LEN: usize = HEADER_LEN + 1 + NAME_LEN + NOTES * PITCH_LEN + 1;
+/// Header, bank, program, count and 127 changes
+const MAX_LEN: usize = HEADER_LEN + 3 + 0x7f * (PITCH_LEN + 1);
+
+/// Device ID addressing all the devices
+pub const ALL_DEVICES: u8 = 0x7f;
+
+/// The pitch data reserved to leave a note unchanged
+const NO_CHANGE: [u8; 3] = [0x7f, 0x7f, 0x7f];
+
+const FRACTION_BITS: u32 = 14;
+const FRACTION_MASK: u32 = (1 << FRACTION_BITS) - 1;
+/// Bits dropped from the fraction to fit it in the 9 bits of a `NotePitch`
+const NOTE_PITCH_SHIFT: u32 = FRACTION_BITS - 9;
+const MAX_PITCH: u32 = (0x7f << FRACTION_BITS) | (FRACTION_MASK - 1);
+
+/// A pitch in semitones from the note 0, with 14 bits of fraction
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct TuningPitch(u32);
+
+impl TuningPitch {
+  pub fn from_note(note: u8) -> Self {
+    Self(((note & 0x7f) as u32) << FRACTION_BITS)
+  }
+
+  /// The closest pitch to a number of semitones.
+  pub fn from_semitones(semitones: f64) -> Self {
+    let max = MAX_PITCH as f64 / (1 << FRACTION_BITS) as f64;
+    Self((semitones.clamp(0.0, max) * (1 << FRACTION_BITS) as f64).round() as u32)
+  }
+
+  /// From the three bytes of the MTS frequency data, returning `None` when they leave the note unchanged.
+  pub fn from_bytes(data: [u8; 3]) -> Option<Self> {
+    (data != NO_CHANGE).then(|| {
+      let fraction = ((data[1] & 0x7f) as u32) << 7 | (data[2] & 0x7f) as u32;
+      Self(((data[0] & 0x7f) as u32) << FRACTION_BITS | fraction)
+    })
+  }
+
+  pub fn to_bytes(self) -> [u8; 3] {
+    let fraction = self.fraction();
+    [
+      self.note(),
+      ((fraction >> 7) & 0x7f) as u8,
+      (fraction & 0x7f) as u8,
+    ]
+  }
+
+  pub fn note(self) -> u8 {
+    (self.0 >> FRACTION_BITS) as u8
+  }
+
+  /// Fraction of semitone above the note, in 1/16384 of semitone.
+  pub fn fraction(self) -> u16 {
+    (self.0 & FRACTION_MASK) as u16
+  }
+
+  pub fn semitones(self) -> f64 {
+    self.0 as f64 / (1 << FRACTION_BITS) as f64
+  }
+
+  pub fn frequency(self) -> f64 {
+    440.0 * ((self.semitones() - 69.0) / 12.0).exp2()
+  }
+
+  /// The closest pitch in 7.9 fixed point.
+  pub fn to_note_pitch(self) -> NotePitch {
+    let attr = (self.0 + (1 << (NOTE_PITCH_SHIFT - 1))) >> NOTE_PITCH_SHIFT;
+    NotePitch::from_attr(attr.min(u16::MAX as u32) as u16)
+  }
+}
+
+impl From<NotePitch> for TuningPitch {
+  fn from(pitch: NotePitch) -> Self {
+    Self((pitch.to_attr() as u32) << NOTE_PITCH_SHIFT)
+  }
+}
+
+/// The pitches of all the note numbers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tuning {
+  /// Up to 16 ASCII characters
+  pub name: String,
+  pub pitches: [TuningPitch; NOTES],
+}
+
+impl Tuning {
+  /// The 12 tone equal temperament, where every note is at its own number.
+  pub fn equal_temperament() -> Self {
+    let mut pitches = [TuningPitch(0); NOTES];
+    for (note, pitch) in pitches.iter_mut().enumerate() {
+      *pitch = TuningPitch::from_note(note as u8);
+    }
+    Self {
+      name: String::new(),
+      pitches,
+    }
+  }
+
+  #[must_use]
+  pub fn with_name(mut self, name: &str) -> Self {
+    self.name = name.to_string();
+    self
+  }
+
+  pub fn pitch(&self, note: u8) -> TuningPitch {
+    self.pitches[(note & 0x7f) as usize]
+  }
+
+  /// The pitch of a note as the 7.9 pitch attribute of a MIDI 2.0 note on.
+  pub fn note_pitch(&self, note: u8) -> NotePitch {
+    self.pitch(note).to_note_pitch()
+  }
+
+  pub fn frequency(&self, note: u8) -> f64 {
+    self.pitch(note).frequency()
+  }
+
+  /// Retunes some notes, as received with a note change message.
+  pub fn apply(&mut self, changes: &[TuningChange]) {
+    for change in changes.iter() {
+      self.pitches[(change.note & 0x7f) as usize] = change.pitch;
+    }
+  }
+}
+
+impl Default for Tuning {
+  fn default() -> Self {
+    Self::equal_temperament()
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TuningChange {
+  pub note: u8,
+  pub pitch: TuningPitch,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MtsMessage {
+  /// Asks a device for the bulk dump of a tuning program
+  BulkDumpRequest { device: u8, program: u8 },
+  /// A whole tuning for a tuning program
+  ///
+  /// The notes left unchanged in the data received keep the pitch of the equal temperament.
+  BulkDump {
+    device: u8,
+    program: u8,
+    tuning: Box<Tuning>,
+  },
+  /// Retunes some notes of a tuning program, in the tuning bank when given
+  NoteChange {
+    device: u8,
+    /// Whether the notes playing are retuned right away, sent as Universal Real Time
+    realtime: bool,
+    bank: Option<u8>,
+    program: u8,
+    changes: Vec<TuningChange>,
+  },
+}
+
+impl MtsMessage {
+  /// Parses the data of a SysEx message, returning `None` when it is not a valid MTS message.
+  ///
+  /// The checksum of the bulk dumps is not verified, as many devices don't compute it right.
+  pub fn parse(data: &[u8]) -> Option<Self> {
+    let data = data.strip_prefix(&[0xf0]).unwrap_or(data);
+    let data = data.strip_suffix(&[0xf7]).unwrap_or(data);
+    if data.len() < HEADER_LEN
+      || !matches!(data[0], UNIVERSAL_NON_REALTIME | UNIVERSAL_REALTIME)
+      || data[2] != MIDI_TUNING
+    {
+      return None;
+    }
+
+    let realtime = data[0] == UNIVERSAL_REALTIME;
+    let device = data[1];
+    let body = &data[HEADER_LEN..];
+    match (realtime, data[3]) {
+      (false, BULK_DUMP_REQUEST) => Some(Self::BulkDumpRequest {
+        device,
+        program: *body.first()? & 0x7f,
+      }),
+      (false, BULK_DUMP) if data.len() == BULK_DUMP_LEN => {
+        let name = &body[1..1 + NAME_LEN];
+        let name = name
+          .iter()
+          .map(|byte| (byte & 0x7f) as char)
+          .collect::<String>();
+        let mut tuning = Tuning::equal_temperament().with_name(name.trim_end());
+        let pitches = body[1 + NAME_LEN..].chunks_exact(PITCH_LEN);
+        for (pitch, data) in tuning.pitches.iter_mut().zip(pitches) {
+          if let Some(changed) = TuningPitch::from_bytes([data[0], data[1], data[2]]) {
+            *pitch = changed;
+          }
+        }
+        Some(Self::BulkDump {
+          device,
+          program: body[0] & 0x7f,
+          tuning: Box::new(tuning),
+        })
+      }
+      (true, NOTE_CHANGE) => Self::parse_note_change(device, realtime, None, body),
+      (_, NOTE_CHANGE_WITH_BANK) => {
+        Self::parse_note_change(device, realtime, Some(*body.first()? & 0x7f), &body[1..])
+      }
+      _ => None,
+    }
+  }
+
+  fn parse_note_change(device: u8, realtime: bool, bank: Option<u8>, body: &[u8]) -> Option<Self> {
+    let program = *body.first()? & 0x7f;
+    let count = *body.get(1)? as usize;
+    let changes = body.get(2..2 + count * 4)?;
+    let changes = changes
+      .chunks_exact(4)
+      .filter_map(|data| {
+        TuningPitch::from_bytes([data[1], data[2], data[3]]).map(|pitch| TuningChange {
+          note: data[0] & 0x7f,
+          pitch,
+        })
+      })
+      .collect();
+    Some(Self::NoteChange {
+      device,
+      realtime,
+      bank,
+      program,
+      changes,
+    })
+  }
+
+  /// Builds the data of the SysEx message, without the `F0` and `F7` bytes.
+  ///
+  /// The note change messages can hold up to 127 changes, the rest are dropped.
+  pub fn to_sysex(&self) -> Vec<u8> {
+    match self {
+      Self::BulkDumpRequest { device, program } => vec![
+        UNIVERSAL_NON_REALTIME,
+        device & 0x7f,
+        MIDI_TUNING,
+        BULK_DUMP_REQUEST,
+        program & 0x7f,
+      ],
+      Self::BulkDump {
+        device,
+        program,
+        tuning,
+      } => {
+        let mut data = Vec::with_capacity(BULK_DUMP_LEN);
+        data.extend_from_slice(&[
+          UNIVERSAL_NON_REALTIME,
+          device & 0x7f,
+          MIDI_TUNING,
+          BULK_DUMP,
+          program & 0x7f,
+        ]);
+        let name = tuning.name.bytes().filter(|byte| *byte < 0x80);
+        data.extend(name.chain(std::iter::repeat(b' ')).take(NAME_LEN));
+        for pitch in tuning.pitches.iter() {
+          data.extend_from_slice(&pitch.to_bytes());
+        }
+        let checksum = data.iter().fold(0, |checksum, byte| checksum ^ byte);
+        data.push(checksum & 0x7f);
+        data
+      }
+      Self::NoteChange {
+        device,
+        realtime,
+        bank,
+        program,
+        changes,
+      } => {
+        let universal = if *realtime {
+          UNIVERSAL_REALTIME
+        } else {
+          UNIVERSAL_NON_REALTIME
+        };
+        let sub_id2 = match bank {
+          Some(_) => NOTE_CHANGE_WITH_BANK,
+          None => NOTE_CHANGE,
+        };
+        let mut data = vec![universal, device & 0x7f, MIDI_TUNING, sub_id2];
+        data.extend(bank.map(|bank| bank & 0x7f));
+        let changes = &changes[..changes.len().min(0x7f)];
+        data.extend_from_slice(&[program & 0x7f, changes.len() as u8]);
+        for change in changes.iter() {
+          data.push(change.note & 0x7f);
+          data.extend_from_slice(&change.pitch.to_bytes());
+        }
+        data
+      }
+    }
+  }
+}
+
+/// Decodes the MTS messages from the SysEx7 packets received from a source.
+pub struct MtsDecoder {
+  assembler: SysExAssembler,
+}
+
+impl MtsDecoder {
+  pub fn new() -> Self {
+    Self {
+      assembler: SysExAssembler::new(MAX_LEN),
+    }
+  }
+
+  /// Handles a message, returning the MTS message once all its packets arrive.
+  pub fn process(&mut self, message: &Message) -> Option<MtsMessage> {
+    match &message.mtype {
+      MessageType::SysEx7(packet) => self.assembler.push(packet).and_then(MtsMessage::parse),
+      _ => None,
+    }
+  }
+
+  pub fn reset(&mut self) {
+    self.assembler.reset();
+  }
+}
+
+impl Default for MtsDecoder {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn pitch_conversions() {
+    let pitch = TuningPitch::from_bytes([60, 0x20, 0x00]).unwrap();
+    assert_eq!(pitch.note(), 60);
+    assert_eq!(pitch.fraction(), 0x1000);
+    assert!((pitch.semitones() - 60.25).abs() < 1e-9);
+    assert_eq!(pitch.to_bytes(), [60, 0x20, 0x00]);
+    assert_eq!(pitch.to_note_pitch(), NotePitch::from_semitones(60.25));
+    assert_eq!(TuningPitch::from(pitch.to_note_pitch()), pitch);
+    assert_eq!(TuningPitch::from_bytes(NO_CHANGE), None);
+
+    assert!((TuningPitch::from_note(69).frequency() - 440.0).abs() < 1e-9);
+    assert_eq!(
+      TuningPitch::from_semitones(200.0).to_bytes(),
+      [0x7f, 0x7f, 0x7e]
+    );
+  }
+
+  #[test]
+  fn bulk_dump_round_trip() {
+    let mut tuning = Tuning::equal_temperament().with_name("Quarter tones");
+    for (note, pitch) in tuning.pitches.iter_mut().enumerate() {
+      *pitch = TuningPitch::from_semitones(60.0 + (note as f64 - 60.0) / 2.0);
+    }
+    let message = MtsMessage::BulkDump {
+      device: 0x7f,
+      program: 3,
+      tuning: Box::new(tuning),
+    };
+
+    let data = message.to_sysex();
+    assert_eq!(data.len(), BULK_DUMP_LEN);
+    assert_eq!(&data[5..21], b"Quarter tones   ");
+    assert_eq!(MtsMessage::parse(&data), Some(message));
+  }
+
+  #[test]
+  fn note_change() {
+    let data = [
+      0xf0, 0x7f, 0x7f, 0x08, 0x02, 0x00, 0x02, 60, 60, 0x40, 0x00, 61, 0x7f, 0x7f, 0x7f, 0xf7,
+    ];
+    let message = MtsMessage::parse(&data).unwrap();
+    let changes = match &message {
+      MtsMessage::NoteChange {
+        realtime: true,
+        bank: None,
+        program: 0,
+        changes,
+        ..
+      } => changes.clone(),
+      _ => panic!("{:?}", message),
+    };
+    assert_eq!(changes.len(), 1);
+
+    let mut tuning = Tuning::default();
+    tuning.apply(&changes);
+    assert_eq!(tuning.note_pitch(60), NotePitch::from_semitones(60.5));
+    assert_eq!(tuning.pitch(61), TuningPitch::from_note(61));
+
+    let with_bank = MtsMessage::NoteChange {
+      device: 1,
+      realtime: false,
+      bank: Some(2),
+      program: 5,
+      changes,
+    };
+    assert_eq!(MtsMessage::parse(&with_bank.to_sysex()), Some(with_bank));
+  }
+}
@@ -0,0 +1,396 @@
+//! Voices of the MIDI 2.0 notes.
+//!
+//! MIDI 2.0 addresses a playing note by its group, channel and note number, to set its per-note
+//! pitch bend, pressure and controllers, and to manage it with the per-note management message.
+//! [`VoiceTracker`] correlates these messages with the note on that started the note,
+//! so every voice is seen with all its controllers.
+
+use crate::protocol::messages::channel_voice::{
+  AttributeType, ChanelVoiceMessage, ChannelVoice, NotePitch,
+};
+use crate::protocol::messages::{Message, MessageType};
+
+/// Center of the bipolar values
+const CENTER: u32 = 0x8000_0000;
+
+/// A note playing, with its per-note controllers
+#[derive(Debug, Clone, PartialEq)]
+pub struct Voice {
+  pub group: u8,
+  pub channel: u8,
+  pub note: u8,
+  pub velocity: u16,
+  pub attr_type: u8,
+  pub attr_data: u16,
+  /// Unsigned bipolar value centered at 0x80000000
+  pub pitch_bend: u32,
+  pub pressure: u32,
+  registered: Vec<(u8, u32)>,
+  assignable: Vec<(u8, u32)>,
+  /// Detached from the per-note messages, which go to the next note on with the same number
+  detached: bool,
+}
+
+impl Voice {
+  fn new(group: u8, channel: u8, note: u8, velocity: u16, attr_type: u8, attr_data: u16) -> Self {
+    Self {
+      group,
+      channel,
+      note,
+      velocity,
+      attr_type,
+      attr_data,
+      pitch_bend: CENTER,
+      pressure: 0,
+      registered: Vec::new(),
+      assignable: Vec::new(),
+      detached: false,
+    }
+  }
+
+  /// The pitch from the pitch 7.9 attribute of the note on, or from the note number.
+  pub fn pitch(&self) -> NotePitch {
+    match AttributeType::from(self.attr_type) {
+      AttributeType::Pitch7_9 => NotePitch::from_attr(self.attr_data),
+      _ => NotePitch::from_note(self.note),
+    }
+  }
+
+  /// The last value of a registered per-note controller, if it was set.
+  pub fn registered_controller(&self, index: u8) -> Option<u32> {
+    find(&self.registered, index)
+  }
+
+  /// The last value of an assignable per-note controller, if it was set.
+  pub fn assignable_controller(&self, index: u8) -> Option<u32> {
+    find(&self.assignable, index)
+  }
+
+  pub fn is_detached(&self) -> bool {
+    self.detached
+  }
+
+  fn reset_controllers(&mut self) {
+    self.pitch_bend = CENTER;
+    self.pressure = 0;
+    self.registered.clear();
+    self.assignable.clear();
+  }
+}
+
+fn find(controllers: &[(u8, u32)], index: u8) -> Option<u32> {
+  controllers
+    .iter()
+    .find(|(controller, _)| *controller == index)
+    .map(|(_, data)| *data)
+}
+
+fn set(controllers: &mut Vec<(u8, u32)>, index: u8, data: u32) {
+  match controllers
+    .iter_mut()
+    .find(|(controller, _)| *controller == index)
+  {
+    Some(controller) => controller.1 = data,
+    None => controllers.push((index, data)),
+  }
+}
+
+/// What changed in a voice
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VoiceChange {
+  PitchBend,
+  Pressure,
+  RegisteredController {
+    index: u8,
+  },
+  AssignableController {
+    index: u8,
+  },
+  /// The per-note controllers went back to their defaults
+  Reset,
+  Detached,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum VoiceEvent<'a> {
+  Started(&'a Voice),
+  Changed {
+    voice: &'a Voice,
+    change: VoiceChange,
+  },
+  Ended {
+    voice: Voice,
+    velocity: u16,
+  },
+  /// Any message not addressed to a voice, or addressed to a note not playing
+  Message(Message),
+}
+
+/// Tracks the voices started by the MIDI 2.0 note on messages, and applies the per-note messages to them.
+#[derive(Default)]
+pub struct VoiceTracker {
+  voices: Vec<Voice>,
+}
+
+impl VoiceTracker {
+  pub fn new() -> Self {
+    Self::default()
+  }
+
+  /// The voices playing, in the order they started.
+  pub fn voices(&self) -> &[Voice] {
+    &self.voices
+  }
+
+  /// The voice receiving the per-note messages for a note.
+  pub fn voice(&self, group: u8, channel: u8, note: u8) -> Option<&Voice> {
+    self
+      .position(group, channel, note)
+      .map(|index| &self.voices[index])
+  }
+
+  /// Handles a message, calling `f` with the resulting event.
+  pub fn process<F>(&mut self, message: Message, mut f: F)
+  where
+    F: FnMut(VoiceEvent),
+  {
+    let (channel, channel_voice) = match message.mtype {
+      MessageType::ChannelVoice(ChannelVoice { channel, message }) => (channel, message),
+      _ => return f(VoiceEvent::Message(message)),
+    };
+    let group = message.group;
+
+    let (note, change) =
+      match channel_voice {
+        ChanelVoiceMessage::NoteOn {
+          note,
+          velocity,
+          attr_type,
+          attr_data,
+        } => {
+          // A note on for a note already playing takes over the per-note messages
+          if let Some(index) = self.position(group, channel, note) {
+            self.voices[index].detached = true;
+          }
+          let voice = Voice::new(group, channel, note, velocity, attr_type, attr_data);
+          self.voices.push(voice);
+          return f(VoiceEvent::Started(&self.voices[self.voices.len() - 1]));
+        }
+        ChanelVoiceMessage::NoteOff { note, velocity, .. } => {
+          // The note off goes to the oldest voice, in case some were detached
+          match self.voices.iter().position(|voice| {
+            voice.group == group && voice.channel == channel && voice.note == note
+          }) {
+            Some(index) => {
+              let voice = self.voices.remove(index);
+              return f(VoiceEvent::Ended { voice, velocity });
+            }
+            None => return f(VoiceEvent::Message(message)),
+          }
+        }
+        ChanelVoiceMessage::PerNotePitchBend { note, data } => (note, Change::PitchBend(data)),
+        ChanelVoiceMessage::PolyPressure { note, data } => (note, Change::Pressure(data)),
+        ChanelVoiceMessage::RegisteredPerNoteController { note, index, data } => {
+          (note, Change::Registered(index, data))
+        }
+        ChanelVoiceMessage::AssignablePerNoteController { note, index, data } => {
+          (note, Change::Assignable(index, data))
+        }
+        ChanelVoiceMessage::PerNoteManagement {
+          note,
+          detach,
+          reset,
+        } => (note, Change::Management { detach, reset }),
+        _ => return f(VoiceEvent::Message(message)),
+      };
+
+    let index = match self.position(group, channel, note) {
+      Some(index) => index,
+      None => return f(VoiceEvent::Message(message)),
+    };
+    let voice = &mut self.voices[index];
+    let change = match change {
+      Change::PitchBend(data) => {
+        voice.pitch_bend = data;
+        VoiceChange::PitchBend
+      }
+      Change::Pressure(data) => {
+        voice.pressure = data;
+        VoiceChange::Pressure
+      }
+      Change::Registered(index, data) => {
+        set(&mut voice.registered, index, data);
+        VoiceChange::RegisteredController { index }
+      }
+      Change::Assignable(index, data) => {
+        set(&mut voice.assignable, index, data);
+        VoiceChange::AssignableController { index }
+      }
+      Change::Management { detach, reset } => {
+        if reset {
+          voice.reset_controllers();
+          f(VoiceEvent::Changed {
+            voice,
+            change: VoiceChange::Reset,
+          });
+        }
+        if !detach {
+          return;
+        }
+        voice.detached = true;
+        VoiceChange::Detached
+      }
+    };
+    f(VoiceEvent::Changed { voice, change });
+  }
+
+  pub fn reset(&mut self) {
+    self.voices.clear();
+  }
+
+  /// The newest voice for a note not detached from the per-note messages.
+  fn position(&self, group: u8, channel: u8, note: u8) -> Option<usize> {
+    self.voices.iter().rposition(|voice| {
+      voice.group == group && voice.channel == channel && voice.note == note && !voice.detached
+    })
+  }
+}
+
+enum Change {
+  PitchBend(u32),
+  Pressure(u32),
+  Registered(u8, u32),
+  Assignable(u8, u32),
+  Management { detach: bool, reset: bool },
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn message(channel: u8, message: ChanelVoiceMessage) -> Message {
+    Message {
+      group: 1,
+      mtype: MessageType::ChannelVoice(ChannelVoice { channel, message }),
+    }
+  }
+
+  fn note_on(channel: u8, note: u8) -> Message {
+    message(
+      channel,
+      ChanelVoiceMessage::NoteOn {
+        note,
+        velocity: 0x8000,
+        attr_type: 0,
+        attr_data: 0,
+      },
+    )
+  }
+
+  fn note_off(channel: u8, note: u8) -> Message {
+    message(
+      channel,
+      ChanelVoiceMessage::NoteOff {
+        note,
+        velocity: 0x1000,
+        attr_type: 0,
+        attr_data: 0,
+      },
+    )
+  }
+
+  fn process(tracker: &mut VoiceTracker, messages: &[Message]) -> Vec<String> {
+    let mut events = Vec::new();
+    for message in messages {
+      tracker.process(*message, |event| events.push(format!("{:?}", event)));
+    }
+    events
+  }
+
+  #[test]
+  fn per_note_controllers() {
+    let mut tracker = VoiceTracker::new();
+    let events = process(
+      &mut tracker,
+      &[
+        note_on(2, 60),
+        note_on(3, 60),
+        message(
+          2,
+          ChanelVoiceMessage::PerNotePitchBend {
+            note: 60,
+            data: 0x9000_0000,
+          },
+        ),
+        message(
+          3,
+          ChanelVoiceMessage::RegisteredPerNoteController {
+            note: 60,
+            index: 74,
+            data: 1234,
+          },
+        ),
+        message(3, ChanelVoiceMessage::PolyPressure { note: 61, data: 1 }),
+      ],
+    );
+    assert_eq!(events.len(), 5);
+    assert!(events[4].starts_with("Message("));
+
+    let voice = tracker.voice(1, 2, 60).unwrap();
+    assert_eq!(voice.pitch_bend, 0x9000_0000);
+    assert_eq!(voice.registered_controller(74), None);
+    let voice = tracker.voice(1, 3, 60).unwrap();
+    assert_eq!(voice.pitch_bend, CENTER);
+    assert_eq!(voice.registered_controller(74), Some(1234));
+
+    let mut ended = None;
+    tracker.process(note_off(2, 60), |event| {
+      if let VoiceEvent::Ended { voice, velocity } = event {
+        ended = Some((voice.pitch_bend, velocity));
+      }
+    });
+    assert_eq!(ended, Some((0x9000_0000, 0x1000)));
+    assert_eq!(tracker.voices().len(), 1);
+  }
+
+  #[test]
+  fn per_note_management() {
+    let mut tracker = VoiceTracker::new();
+    let events = process(
+      &mut tracker,
+      &[
+        note_on(0, 64),
+        message(
+          0,
+          ChanelVoiceMessage::AssignablePerNoteController {
+            note: 64,
+            index: 3,
+            data: 99,
+          },
+        ),
+        message(
+          0,
+          ChanelVoiceMessage::PerNoteManagement {
+            note: 64,
+            detach: true,
+            reset: true,
+          },
+        ),
+        note_on(0, 64),
+        message(0, ChanelVoiceMessage::PolyPressure { note: 64, data: 7 }),
+      ],
+    );
+    assert_eq!(events.len(), 6);
+
+    let voices = tracker.voices();
+    assert!(voices[0].is_detached());
+    assert_eq!(voices[0].assignable_controller(3), None);
+    assert_eq!(voices[0].pressure, 0);
+    assert_eq!(voices[1].pressure, 7);
+
+    // The note off ends the oldest voice
+    process(&mut tracker, &[note_off(0, 64)]);
+    assert_eq!(tracker.voices().len(), 1);
+    assert_eq!(tracker.voice(1, 0, 64).map(|voice| voice.pressure), Some(7));
+  }
+}